@@ -0,0 +1,204 @@
+//! Benchmarks for the host's invocation dispatch path. These run against the same signed echo
+//! and kvcounter actors used by the integration tests, so they must be run from the root of the
+//! repository:
+//!
+//! ```text
+//! cargo bench --bench dispatch
+//! ```
+//!
+//! Scenarios:
+//! * `echo_latency` - a single round trip through the bus and into the actor
+//! * `echo_payload` - round trips carrying 1KB, 64KB, and 1MB request bodies
+//! * `echo_fanout` - batches of concurrent invocations against a single actor
+//! * `provider_latency` - a round trip into the kvcounter actor, which calls a stub key-value
+//!   provider in turn
+
+use actix_rt::{System, SystemRunner};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+use wascap::jwt::{CapabilityProvider, Claims};
+use wascap::prelude::KeyPair;
+use wasmcloud_host::{Actor, CapabilityBuilder, Host, HostBuilder, NativeProvider};
+
+#[allow(dead_code)]
+#[path = "../tests/generated/http.rs"]
+mod http;
+
+const ECHO_ACTOR: &str = "./tests/modules/echo.wasm";
+const KVCOUNTER_ACTOR: &str = "./tests/modules/kvcounter.wasm";
+const OP_HANDLE_REQUEST: &str = "HandleRequest";
+const KEYVALUE_CONTRACT: &str = "wascc:keyvalue";
+const OP_ADD: &str = "Add";
+
+const PAYLOAD_SIZES: [usize; 3] = [1024, 64 * 1024, 1024 * 1024];
+const FANOUT_WIDTHS: [usize; 3] = [1, 10, 100];
+
+fn gen_request(body_size: usize) -> Vec<u8> {
+    http::serialize(&http::Request {
+        method: "POST".to_string(),
+        path: "/bench".to_string(),
+        query_string: "".to_string(),
+        header: Default::default(),
+        body: vec![0xAB; body_size],
+    })
+    .unwrap()
+}
+
+fn start_echo_host(sys: &mut SystemRunner) -> (Host, String) {
+    sys.block_on(async {
+        let h = HostBuilder::new().build();
+        h.start().await.unwrap();
+        let echo = Actor::from_file(ECHO_ACTOR).unwrap();
+        let actor_id = echo.public_key();
+        h.start_actor(echo).await.unwrap();
+        (h, actor_id)
+    })
+}
+
+// A key-value provider that only counts, so the benchmark measures the host rather than a store
+#[derive(Default)]
+struct StubKeyValue {
+    counter: AtomicI32,
+}
+
+impl NativeProvider for StubKeyValue {}
+
+// The response to the key-value contract's Add operation
+#[derive(Serialize)]
+struct AddResponse {
+    value: i32,
+}
+
+fn start_kvcounter_host(sys: &mut SystemRunner) -> (Host, String) {
+    sys.block_on(async {
+        let h = HostBuilder::new().build();
+        h.start().await.unwrap();
+        let kvcounter = Actor::from_file(KVCOUNTER_ACTOR).unwrap();
+        let actor_id = kvcounter.public_key();
+        h.start_actor(kvcounter).await.unwrap();
+
+        let account = KeyPair::new_account();
+        let provider_id = KeyPair::new_service().public_key();
+        let claims = Claims::<CapabilityProvider>::new(
+            "Stub key-value".to_string(),
+            account.public_key(),
+            provider_id.to_string(),
+            KEYVALUE_CONTRACT.to_string(),
+            "Benchmarks".to_string(),
+            None,
+            None,
+            HashMap::new(),
+        );
+        let stub = CapabilityBuilder::new(KEYVALUE_CONTRACT, StubKeyValue::default())
+            .raw_operation(OP_ADD, "Adds to a counter", |p, _ctx, _msg| {
+                let value = p.counter.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(rmp_serde::to_vec_named(&AddResponse { value })?)
+            })
+            .into_native(None, claims)
+            .unwrap();
+        h.start_native_capability(stub).await.unwrap();
+        h.set_link(
+            &actor_id,
+            KEYVALUE_CONTRACT,
+            None,
+            provider_id,
+            HashMap::new(),
+        )
+        .await
+        .unwrap();
+        h.await_link(
+            &actor_id,
+            KEYVALUE_CONTRACT,
+            "default",
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+        (h, actor_id)
+    })
+}
+
+fn echo_latency(c: &mut Criterion) {
+    let mut sys = System::new("bench-latency");
+    let (h, actor_id) = start_echo_host(&mut sys);
+    let req = gen_request(0);
+
+    c.bench_function("echo_latency", |b| {
+        b.iter(|| {
+            sys.block_on(h.call_actor(&actor_id, OP_HANDLE_REQUEST, &req))
+                .unwrap()
+        })
+    });
+
+    sys.block_on(h.stop());
+}
+
+fn echo_payload(c: &mut Criterion) {
+    let mut sys = System::new("bench-payload");
+    let (h, actor_id) = start_echo_host(&mut sys);
+
+    let mut group = c.benchmark_group("echo_payload");
+    for size in PAYLOAD_SIZES.iter() {
+        let req = gen_request(*size);
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &req, |b, req| {
+            b.iter(|| {
+                sys.block_on(h.call_actor(&actor_id, OP_HANDLE_REQUEST, req))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    sys.block_on(h.stop());
+}
+
+fn echo_fanout(c: &mut Criterion) {
+    let mut sys = System::new("bench-fanout");
+    let (h, actor_id) = start_echo_host(&mut sys);
+    let req = gen_request(1024);
+
+    let mut group = c.benchmark_group("echo_fanout");
+    for width in FANOUT_WIDTHS.iter() {
+        group.throughput(Throughput::Elements(*width as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(width), width, |b, width| {
+            b.iter(|| {
+                let calls = (0..*width).map(|_| h.call_actor(&actor_id, OP_HANDLE_REQUEST, &req));
+                for res in sys.block_on(join_all(calls)) {
+                    res.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+
+    sys.block_on(h.stop());
+}
+
+fn provider_latency(c: &mut Criterion) {
+    let mut sys = System::new("bench-provider");
+    let (h, actor_id) = start_kvcounter_host(&mut sys);
+    let req = gen_request(0);
+
+    c.bench_function("provider_latency", |b| {
+        b.iter(|| {
+            sys.block_on(h.call_actor(&actor_id, OP_HANDLE_REQUEST, &req))
+                .unwrap()
+        })
+    });
+
+    sys.block_on(h.stop());
+}
+
+criterion_group!(
+    benches,
+    echo_latency,
+    echo_payload,
+    echo_fanout,
+    provider_latency
+);
+criterion_main!(benches);
@@ -12,6 +12,18 @@ pub fn rpc_prefix(nsprefix: &Option<String>) -> String {
     )
 }
 
+/// Subjects beneath this prefix are reserved for extensions registered by host embedders
+pub fn extension_prefix(nsprefix: &Option<String>) -> String {
+    format!(
+        "wasmbus.ext.{}",
+        nsprefix.as_ref().unwrap_or(&"default".to_string())
+    )
+}
+
+pub fn extension_subject(nsprefix: &Option<String>, extension: &str, subject: &str) -> String {
+    format!("{}.{}.{}", extension_prefix(nsprefix), extension, subject)
+}
+
 pub fn control_event(nsprefix: &Option<String>) -> String {
     format!("{}.events", prefix(nsprefix))
 }
@@ -59,6 +71,33 @@ pub mod commands {
     pub fn update_actor(nsprefix: &Option<String>, host: &str) -> String {
         format!("{}.cmd.{}.upd", prefix(nsprefix), host)
     }
+
+    pub fn set_log_level(nsprefix: &Option<String>, host: &str) -> String {
+        format!("{}.cmd.{}.ll", prefix(nsprefix), host)
+    }
+
+    pub fn prepare_upgrade(nsprefix: &Option<String>, host: &str) -> String {
+        format!("{}.cmd.{}.pu", prefix(nsprefix), host) // pu - prepare upgrade
+    }
+
+    /// Sent to every host in the lattice
+    pub fn set_trace_sampling(nsprefix: &Option<String>) -> String {
+        format!("{}.cmd.sampling", prefix(nsprefix))
+    }
+
+    /// Revocations of API tokens are published to every host
+    pub fn revoke_api_token(nsprefix: &Option<String>) -> String {
+        format!("{}.cmd.revoke", prefix(nsprefix))
+    }
+
+    /// Provider instance commands target an instance ID rather than a host
+    pub fn quiesce_provider(nsprefix: &Option<String>, instance: &str) -> String {
+        format!("{}.cmd.{}.qp", prefix(nsprefix), instance)
+    }
+
+    pub fn resume_provider(nsprefix: &Option<String>, instance: &str) -> String {
+        format!("{}.cmd.{}.rp", prefix(nsprefix), instance)
+    }
 }
 
 pub mod queries {
@@ -76,7 +115,35 @@ pub mod queries {
         format!("{}.get.{}.inv", prefix(nsprefix), host)
     }
 
+    pub fn host_inventory_delta(nsprefix: &Option<String>, host: &str) -> String {
+        format!("{}.get.{}.invd", prefix(nsprefix), host)
+    }
+
+    pub fn host_config(nsprefix: &Option<String>, host: &str) -> String {
+        format!("{}.get.{}.cfg", prefix(nsprefix), host)
+    }
+
+    pub fn host_capabilities(nsprefix: &Option<String>, host: &str) -> String {
+        format!("{}.get.{}.caps", prefix(nsprefix), host)
+    }
+
+    pub fn dependency_graph(nsprefix: &Option<String>, host: &str) -> String {
+        format!("{}.get.{}.deps", prefix(nsprefix), host)
+    }
+
+    pub fn provider_health(nsprefix: &Option<String>, instance: &str) -> String {
+        format!("{}.get.{}.phc", prefix(nsprefix), instance)
+    }
+
+    pub fn provider_config(nsprefix: &Option<String>, instance: &str) -> String {
+        format!("{}.get.{}.pcfg", prefix(nsprefix), instance)
+    }
+
     pub fn hosts(nsprefix: &Option<String>) -> String {
         format!("{}.get.hosts", prefix(nsprefix))
     }
+
+    pub fn revocations(nsprefix: &Option<String>) -> String {
+        format!("{}.get.revocations", prefix(nsprefix))
+    }
 }
@@ -0,0 +1,175 @@
+// Operations that touch many hosts (or many links) are fanned out concurrently rather than
+// awaited one at a time. The caller bounds how many are in flight and how quickly new ones are
+// started, so that a large lattice doesn't get hit with hundreds of simultaneous commands, and
+// can watch each one complete through a progress channel.
+
+use crate::scatter::PartialResults;
+use crate::Result;
+use actix_rt::time::delay_for;
+use futures::channel::mpsc::UnboundedSender;
+use futures::stream::{self, StreamExt};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+const DEFAULT_CONCURRENCY: usize = 16;
+
+/// Controls how an operation is fanned out across the lattice
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanoutOptions {
+    /// The maximum number of requests in flight at once
+    pub concurrency: usize,
+    /// The minimum time between starting consecutive requests, if rate limited
+    pub min_interval: Option<Duration>,
+}
+
+impl Default for FanoutOptions {
+    fn default() -> Self {
+        FanoutOptions {
+            concurrency: DEFAULT_CONCURRENCY,
+            min_interval: None,
+        }
+    }
+}
+
+impl FanoutOptions {
+    pub fn with_concurrency(self, concurrency: usize) -> FanoutOptions {
+        FanoutOptions {
+            concurrency: concurrency.max(1),
+            ..self
+        }
+    }
+
+    /// Limits the rate at which requests are started to the given number per second
+    pub fn with_rate_limit(self, per_second: u32) -> FanoutOptions {
+        FanoutOptions {
+            min_interval: Some(Duration::from_secs(1) / per_second.max(1)),
+            ..self
+        }
+    }
+}
+
+/// Reported as each request in a fan-out completes
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanoutProgress {
+    /// The host (or other target, such as a link) that the request was for
+    pub target: String,
+    /// The number of requests that have completed so far, including this one
+    pub completed: usize,
+    pub total: usize,
+    /// The reason the request failed, if it did
+    pub error: Option<String>,
+}
+
+/// A single request in a fan-out. The host ID is used to attribute failures, and is `None`
+/// for requests that aren't sent to a particular host
+pub struct FanoutTask<F> {
+    pub target: String,
+    pub host_id: Option<String>,
+    pub request: F,
+}
+
+/// Runs the given requests concurrently according to the options, gathering their results.
+/// Failed requests are reported in the failures of the results rather than stopping the others
+pub async fn fan_out<T, F>(
+    tasks: Vec<FanoutTask<F>>,
+    options: &FanoutOptions,
+    progress: Option<UnboundedSender<FanoutProgress>>,
+) -> PartialResults<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let total = tasks.len();
+    let started = Instant::now();
+    let min_interval = options.min_interval;
+    let mut completions = stream::iter(tasks.into_iter().enumerate())
+        .map(|(i, task)| async move {
+            if let Some(interval) = min_interval {
+                if let Some(wait) = (interval * i as u32).checked_sub(started.elapsed()) {
+                    delay_for(wait).await;
+                }
+            }
+            (task.target, task.host_id, task.request.await)
+        })
+        .buffer_unordered(options.concurrency.max(1));
+
+    let mut pr = PartialResults::default();
+    let mut completed = 0;
+    while let Some((target, host_id, res)) = completions.next().await {
+        completed += 1;
+        let error = match res {
+            Ok(r) => {
+                pr.results.push(r);
+                None
+            }
+            Err(e) => {
+                let reason = format!("{}: {}", target, e);
+                pr.errored(host_id, reason.clone());
+                Some(reason)
+            }
+        };
+        if let Some(ref tx) = progress {
+            let _ = tx.unbounded_send(FanoutProgress {
+                target,
+                completed,
+                total,
+                error,
+            });
+        }
+    }
+    pr
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fan_out, FanoutOptions, FanoutTask};
+    use futures::channel::mpsc::unbounded;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn outcome(i: usize) -> crate::Result<usize> {
+        if i == 3 {
+            Err("refused".into())
+        } else {
+            Ok(i)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn fan_out_bounds_concurrency_and_reports_progress() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let tasks = (0..10)
+            .map(|i| {
+                let in_flight = in_flight.clone();
+                let peak = peak.clone();
+                FanoutTask {
+                    target: format!("host{}", i),
+                    host_id: Some(format!("host{}", i)),
+                    request: async move {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        actix_rt::time::delay_for(Duration::from_millis(10)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        outcome(i)
+                    },
+                }
+            })
+            .collect();
+        let (tx, rx) = unbounded();
+        let pr = fan_out(
+            tasks,
+            &FanoutOptions::default().with_concurrency(3),
+            Some(tx),
+        )
+        .await;
+
+        assert_eq!(9, pr.results.len());
+        assert_eq!(Some("host3".to_string()), pr.failures[0].host_id);
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        let progress: Vec<_> = rx.collect().await;
+        assert_eq!(10, progress.len());
+        assert_eq!(10, progress.last().unwrap().completed);
+    }
+}
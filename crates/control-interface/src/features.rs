@@ -0,0 +1,60 @@
+// The hosts of a lattice don't all run the same build or the same configuration: an edge host
+// may have no HTTP ingress and no lattice RPC, while a newer host may have subsystems an older
+// one lacks. Rather than inferring what a host can do from its version, an orchestrator (or
+// anything else holding a control client) can ask it. A host reports its version, its wasm
+// engine, the platform its native providers must be built for, and the names of the optional
+// subsystems it has enabled. Names a client doesn't know are to be ignored, so hosts can report
+// new subsystems without breaking older clients. Hosts that predate the query don't answer it.
+
+use crate::HostCapabilities;
+
+/// Actors and providers can be invoked across hosts of the lattice
+pub const FEATURE_LATTICE_RPC: &str = "lattice-rpc";
+/// The host answers control interface commands and queries, and publishes events
+pub const FEATURE_CONTROL_INTERFACE: &str = "control-interface";
+/// Control requests must carry an API token
+pub const FEATURE_API_TOKENS: &str = "api-tokens";
+/// Actors and providers can be started from OCI registries
+pub const FEATURE_OCI: &str = "oci";
+/// The host keeps invocation metrics, reported as link statistics
+pub const FEATURE_METRICS: &str = "metrics";
+/// The host serves HTTP(S) requests to actors through its built-in router
+pub const FEATURE_HTTP_ROUTER: &str = "http-router";
+/// The host serves gRPC requests to actors
+pub const FEATURE_GRPC_INGRESS: &str = "grpc-ingress";
+/// The host provides a built-in in-memory key-value store
+pub const FEATURE_MEMORY_KEYVALUE: &str = "memory-keyvalue";
+/// Payloads too large for the lattice are sent through a payload store
+pub const FEATURE_PAYLOAD_OFFLOAD: &str = "payload-offload";
+/// Running actors can be replaced without being stopped
+pub const FEATURE_LIVE_UPDATES: &str = "live-updates";
+/// The host can hand over to a newer host through an upgrade journal
+pub const FEATURE_UPGRADE: &str = "upgrade";
+/// The host restarts internal subsystems that stall
+pub const FEATURE_WATCHDOG: &str = "watchdog";
+
+impl HostCapabilities {
+    /// Whether the host reported the given optional subsystem as enabled
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FEATURE_LATTICE_RPC, FEATURE_OCI};
+    use crate::HostCapabilities;
+
+    #[test]
+    fn hosts_report_their_enabled_features() {
+        let caps: HostCapabilities = serde_json::from_str(
+            r#"{
+                "host_id": "Nhost", "version": "0.15.0", "engine": "wasm3",
+                "native_target": "x86_64-linux", "features": ["oci", "teleport"]
+            }"#,
+        )
+        .unwrap();
+        assert!(caps.supports(FEATURE_OCI));
+        assert!(!caps.supports(FEATURE_LATTICE_RPC));
+    }
+}
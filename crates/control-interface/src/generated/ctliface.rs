@@ -131,6 +131,52 @@ pub struct StopProviderAck {
     pub failure: Option<String>,
 }
 
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct SetLogLevelCommand {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "target")]
+    pub target: String,
+    #[serde(rename = "level")]
+    pub level: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct SetLogLevelAck {
+    #[serde(rename = "failure")]
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct PrepareUpgradeCommand {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct PrepareUpgradeAck {
+    #[serde(rename = "failure")]
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct TraceSamplingCommand {
+    #[serde(rename = "default_rate")]
+    pub default_rate: f64,
+    #[serde(rename = "rates")]
+    pub rates: std::collections::HashMap<String, f64>,
+    #[serde(rename = "always_sample_errors")]
+    pub always_sample_errors: bool,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct TraceSamplingAck {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "failure")]
+    pub failure: Option<String>,
+}
+
 #[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
 pub struct LinkDefinitionList {
     #[serde(rename = "links")]
@@ -187,6 +233,80 @@ pub struct HostInventory {
     pub actors: Vec<ActorDescription>,
     #[serde(rename = "providers")]
     pub providers: Vec<ProviderDescription>,
+    #[serde(rename = "link_stats")]
+    pub link_stats: Option<Vec<LinkStatistics>>,
+    #[serde(rename = "subscription_stats")]
+    pub subscription_stats: Option<Vec<SubscriptionStatistics>>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct SubscriptionStatistics {
+    #[serde(rename = "subject")]
+    pub subject: String,
+    #[serde(rename = "target")]
+    pub target: String,
+    #[serde(rename = "delivered")]
+    pub delivered: u64,
+    #[serde(rename = "pending")]
+    pub pending: u64,
+    #[serde(rename = "peak_pending")]
+    pub peak_pending: u64,
+    #[serde(rename = "dropped")]
+    pub dropped: u64,
+    #[serde(rename = "slow")]
+    pub slow: bool,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct LinkStatistics {
+    #[serde(rename = "actor_id")]
+    pub actor_id: String,
+    #[serde(rename = "contract_id")]
+    pub contract_id: String,
+    #[serde(rename = "link_name")]
+    pub link_name: String,
+    #[serde(rename = "invocations")]
+    pub invocations: u64,
+    #[serde(rename = "errors")]
+    pub errors: u64,
+    #[serde(rename = "error_rate")]
+    pub error_rate: f64,
+    #[serde(rename = "p99_latency_ms")]
+    pub p99_latency_ms: u64,
+    #[serde(rename = "responses_too_large")]
+    pub responses_too_large: Option<u64>,
+    #[serde(rename = "labels")]
+    pub labels: Option<std::collections::HashMap<String, String>>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct DependencyGraph {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "actors")]
+    pub actors: Vec<ActorDependencies>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ActorDependencies {
+    #[serde(rename = "actor_id")]
+    pub actor_id: String,
+    #[serde(rename = "calls")]
+    pub calls: Vec<ActorCall>,
+    #[serde(rename = "unused_capabilities")]
+    pub unused_capabilities: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ActorCall {
+    #[serde(rename = "target")]
+    pub target: String,
+    #[serde(rename = "contract_id")]
+    pub contract_id: Option<String>,
+    #[serde(rename = "link_name")]
+    pub link_name: Option<String>,
+    #[serde(rename = "calls")]
+    pub calls: u64,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
@@ -205,6 +325,130 @@ pub struct ProviderDescription {
     pub link_name: String,
     #[serde(rename = "image_ref")]
     pub image_ref: Option<String>,
+    #[serde(rename = "instance_id")]
+    pub instance_id: Option<String>,
+    #[serde(rename = "quiesced")]
+    pub quiesced: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ProviderHealth {
+    #[serde(rename = "instance_id")]
+    pub instance_id: String,
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "provider_id")]
+    pub provider_id: String,
+    #[serde(rename = "link_name")]
+    pub link_name: String,
+    #[serde(rename = "healthy")]
+    pub healthy: bool,
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ProviderInstanceConfig {
+    #[serde(rename = "instance_id")]
+    pub instance_id: String,
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "provider_id")]
+    pub provider_id: String,
+    #[serde(rename = "link_name")]
+    pub link_name: String,
+    #[serde(rename = "image_ref")]
+    pub image_ref: Option<String>,
+    #[serde(rename = "links")]
+    pub links: Vec<LinkDefinition>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ProviderQuiesceAck {
+    #[serde(rename = "instance_id")]
+    pub instance_id: String,
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "failure")]
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct InventoryDeltaRequest {
+    #[serde(rename = "since_revision")]
+    pub since_revision: u64,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct InventoryDelta {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "revision")]
+    pub revision: u64,
+    #[serde(rename = "full")]
+    pub full: bool,
+    #[serde(rename = "labels")]
+    pub labels: Option<std::collections::HashMap<String, String>>,
+    #[serde(rename = "actors_added")]
+    pub actors_added: Vec<ActorDescription>,
+    #[serde(rename = "actors_removed")]
+    pub actors_removed: Vec<String>,
+    #[serde(rename = "providers_added")]
+    pub providers_added: Vec<ProviderDescription>,
+    #[serde(rename = "providers_removed")]
+    pub providers_removed: Vec<ProviderDescription>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct HostConfig {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "version")]
+    pub version: String,
+    #[serde(rename = "namespace")]
+    pub namespace: String,
+    #[serde(rename = "engine")]
+    pub engine: String,
+    #[serde(rename = "labels")]
+    pub labels: std::collections::HashMap<String, String>,
+    #[serde(rename = "rpc_timeout_ms")]
+    pub rpc_timeout_ms: u64,
+    #[serde(rename = "heartbeat_interval_s")]
+    pub heartbeat_interval_s: u64,
+    #[serde(rename = "oci_allow_latest")]
+    pub oci_allow_latest: bool,
+    #[serde(rename = "allow_live_updates")]
+    pub allow_live_updates: bool,
+    #[serde(rename = "lattice_rpc_enabled")]
+    pub lattice_rpc_enabled: bool,
+    #[serde(rename = "max_actors")]
+    pub max_actors: Option<u64>,
+    #[serde(rename = "max_providers")]
+    pub max_providers: Option<u64>,
+    #[serde(rename = "max_guest_memory")]
+    pub max_guest_memory: Option<u64>,
+    #[serde(rename = "provider_cache_path")]
+    pub provider_cache_path: String,
+    #[serde(rename = "oci_cache_path")]
+    pub oci_cache_path: String,
+    #[serde(rename = "oci_registry_user")]
+    pub oci_registry_user: Option<String>,
+    #[serde(rename = "oci_registry_password")]
+    pub oci_registry_password: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct HostCapabilities {
+    #[serde(rename = "host_id")]
+    pub host_id: String,
+    #[serde(rename = "version")]
+    pub version: String,
+    #[serde(rename = "engine")]
+    pub engine: String,
+    #[serde(rename = "native_target")]
+    pub native_target: String,
+    #[serde(rename = "features")]
+    pub features: Vec<String>,
 }
 
 /// The standard function for serializing codec structs into a format that can be
@@ -13,6 +13,7 @@ use crate::Result;
 use data_encoding::HEXUPPER;
 use ring::digest::{Context, Digest, SHA256};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::Read;
 use uuid::Uuid;
 use wascap::jwt::Claims;
@@ -31,6 +32,14 @@ pub struct Invocation {
     pub id: String,
     pub encoded_claims: String,
     pub host_id: String,
+    /// A caller-supplied key identifying this request across retries. A host that has already
+    /// handled a successful invocation with the same key and request returns the same response
+    /// instead of executing the invocation again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Key-value pairs carried by this invocation and by every invocation made while handling it
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub baggage: HashMap<String, String>,
 }
 
 impl Invocation {
@@ -62,8 +71,24 @@ impl Invocation {
             id: subject,
             encoded_claims: claims.encode(&hostkey).unwrap(),
             host_id: issuer.to_string(),
+            idempotency_key: None,
+            baggage: HashMap::new(),
         }
     }
+
+    /// Attaches an idempotency key to the invocation, so that retries of it are only executed
+    /// once. The key is not covered by the invocation's signature
+    pub fn with_idempotency_key(self, key: &str) -> Invocation {
+        Invocation {
+            idempotency_key: Some(key.to_string()),
+            ..self
+        }
+    }
+
+    /// Attaches baggage to the invocation. Baggage is not covered by the invocation's signature
+    pub fn with_baggage(self, baggage: HashMap<String, String>) -> Invocation {
+        Invocation { baggage, ..self }
+    }
 }
 
 /// The response to an invocation
@@ -1,13 +1,20 @@
 pub mod broker;
+mod fanout;
+pub mod features;
 mod generated;
 mod inv;
+mod scatter;
+pub mod tokens;
 
 pub use crate::generated::ctliface::*;
 use actix_rt::time::delay_for;
+pub use fanout::{fan_out, FanoutOptions, FanoutProgress, FanoutTask};
+use futures::channel::mpsc::UnboundedSender;
 use futures::stream::StreamExt;
-use futures::TryStreamExt;
 pub use inv::{Invocation, InvocationResponse};
 use inv::WasccEntity;
+pub use scatter::{FailureKind, HostFailure, PartialResults};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
@@ -20,6 +27,7 @@ pub struct Client {
     nsprefix: Option<String>,
     timeout: Duration,
     key: KeyPair,
+    token: Option<String>,
 }
 
 impl Client {
@@ -29,19 +37,24 @@ impl Client {
             nsprefix,
             timeout,
             key: KeyPair::new_server(),
+            token: None,
         }
     }
 
-    pub async fn get_hosts(&self, timeout: Duration) -> Result<Vec<Host>> {
-        let subject = broker::queries::hosts(&self.nsprefix);
+    /// Sends the given API token (see `tokens::mint_token`) with every control request, for
+    /// hosts that require one
+    pub fn with_token(self, token: &str) -> Self {
+        Client {
+            token: Some(token.to_string()),
+            ..self
+        }
+    }
 
-        self.nc
-            .request_multi(&subject, vec![])
-            .await?
-            .map(|m| deserialize::<Host>(&m.data))
-            .take_until(delay_for(timeout))
-            .try_collect()
-            .await
+    /// Queries the lattice for all responsive hosts, waiting for the full timeout period. Any
+    /// replies that could not be decoded are reported as failures rather than failing the query
+    pub async fn get_hosts(&self, timeout: Duration) -> Result<PartialResults<Host>> {
+        let subject = broker::queries::hosts(&self.nsprefix);
+        self.gather(&subject, vec![], timeout).await
     }
 
     pub async fn perform_actor_auction(
@@ -49,19 +62,13 @@ impl Client {
         actor_ref: &str,
         constraints: HashMap<String, String>,
         timeout: Duration,
-    ) -> Result<Vec<ActorAuctionAck>> {
+    ) -> Result<PartialResults<ActorAuctionAck>> {
         let subject = broker::actor_auction_subject(&self.nsprefix);
         let bytes = serialize(ActorAuctionRequest {
             actor_ref: actor_ref.to_string(),
             constraints,
         })?;
-        self.nc
-            .request_multi(&subject, bytes)
-            .await?
-            .map(|m| deserialize::<ActorAuctionAck>(&m.data))
-            .take_until(delay_for(timeout))
-            .try_collect()
-            .await
+        self.gather(&subject, bytes, timeout).await
     }
 
     pub async fn perform_provider_auction(
@@ -70,42 +77,203 @@ impl Client {
         link_name: &str,
         constraints: HashMap<String, String>,
         timeout: Duration,
-    ) -> Result<Vec<ProviderAuctionAck>> {
+    ) -> Result<PartialResults<ProviderAuctionAck>> {
         let subject = broker::provider_auction_subject(&self.nsprefix);
         let bytes = serialize(ProviderAuctionRequest {
             provider_ref: provider_ref.to_string(),
             link_name: link_name.to_string(),
             constraints,
         })?;
-        self.nc
-            .request_multi(&subject, bytes)
-            .await?
-            .map(|m| deserialize::<ProviderAuctionAck>(&m.data))
-            .take_until(delay_for(timeout))
-            .try_collect()
-            .await
+        self.gather(&subject, bytes, timeout).await
     }
 
     pub async fn get_host_inventory(&self, host_id: &str) -> Result<HostInventory> {
         let subject = broker::queries::host_inventory(&self.nsprefix, host_id);
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, vec![])).await? {
+        match self.request(&subject, vec![]).await? {
             Ok(msg) => {
-                let hi: HostInventory = deserialize(&msg.data)?;
+                let hi: HostInventory = decode(&msg.data)?;
                 Ok(hi)
             }
             Err(e) => Err(format!("Did not receive host inventory from target host: {}", e).into()),
         }
     }
 
+    /// Retrieves the changes to a host's inventory since the given revision, which is the
+    /// `revision` of the last delta received from that host. Pass `0` to get the full inventory.
+    /// When the host no longer remembers the requested revision the delta is marked `full` and
+    /// replaces, rather than updates, the caller's copy when applied. Link statistics aren't
+    /// included, as they change with every invocation
+    pub async fn get_inventory(
+        &self,
+        host_id: &str,
+        since_revision: u64,
+    ) -> Result<InventoryDelta> {
+        let subject = broker::queries::host_inventory_delta(&self.nsprefix, host_id);
+        let bytes = serialize(InventoryDeltaRequest { since_revision })?;
+        match self.request(&subject, bytes).await? {
+            Ok(msg) => {
+                let delta: InventoryDelta = decode(&msg.data)?;
+                Ok(delta)
+            }
+            Err(e) => {
+                Err(format!("Did not receive inventory changes from target host: {}", e).into())
+            }
+        }
+    }
+
+    /// Retrieves the inventories of each of the given hosts concurrently. Hosts that do not
+    /// respond within the client's timeout, or that respond with something other than an
+    /// inventory, are reported in the failures of the returned results
+    pub async fn get_host_inventories(&self, host_ids: &[String]) -> PartialResults<HostInventory> {
+        let requests = host_ids.iter().map(|host_id| async move {
+            let subject = broker::queries::host_inventory(&self.nsprefix, host_id);
+            (host_id.to_string(), self.request(&subject, vec![]).await)
+        });
+        let mut pr = PartialResults::default();
+        for (host_id, res) in futures::future::join_all(requests).await {
+            match res {
+                Ok(Ok(msg)) => match decode::<HostInventory>(&msg.data) {
+                    Ok(hi) => pr.results.push(hi),
+                    Err(e) => pr.errored(Some(host_id), format!("Invalid inventory: {}", e)),
+                },
+                Ok(Err(e)) => pr.errored(Some(host_id), format!("Request failed: {}", e)),
+                Err(_) => pr.timed_out(
+                    Some(host_id),
+                    "Did not receive host inventory from target host".to_string(),
+                ),
+            }
+        }
+        pr
+    }
+
+    /// Discovers the hosts in the lattice and then retrieves each of their inventories. Failures
+    /// from both the discovery and the inventory phases are included in the results
+    pub async fn get_lattice_inventory(
+        &self,
+        timeout: Duration,
+    ) -> Result<PartialResults<HostInventory>> {
+        let hosts = self.get_hosts(timeout).await?;
+        let ids: Vec<String> = hosts.results.into_iter().map(|h| h.id).collect();
+        let mut pr = self.get_host_inventories(&ids).await;
+        pr.failures.extend(hosts.failures);
+        Ok(pr)
+    }
+
+    /// Retrieves the actors and capability providers that each actor on the given host has
+    /// called since it started, along with the capabilities each actor is granted but has not
+    /// used
+    pub async fn get_dependency_graph(&self, host_id: &str) -> Result<DependencyGraph> {
+        let subject = broker::queries::dependency_graph(&self.nsprefix, host_id);
+        match self.request(&subject, vec![]).await? {
+            Ok(msg) => {
+                let graph: DependencyGraph = decode(&msg.data)?;
+                Ok(graph)
+            }
+            Err(e) => {
+                Err(format!("Did not receive dependency graph from target host: {}", e).into())
+            }
+        }
+    }
+
+    /// Retrieves the effective runtime configuration of the given host. Secrets, such as
+    /// registry credentials, are redacted by the host before they are sent over the lattice
+    pub async fn get_host_config(&self, host_id: &str) -> Result<HostConfig> {
+        let subject = broker::queries::host_config(&self.nsprefix, host_id);
+        match self.request(&subject, vec![]).await? {
+            Ok(msg) => {
+                let cfg: HostConfig = decode(&msg.data)?;
+                Ok(cfg)
+            }
+            Err(e) => {
+                Err(format!("Did not receive host configuration from target host: {}", e).into())
+            }
+        }
+    }
+
+    /// Retrieves the version, wasm engine, and enabled optional subsystems of the given host
+    /// (see the `features` module), so callers can adapt to the hosts of a mixed lattice
+    pub async fn get_host_capabilities(&self, host_id: &str) -> Result<HostCapabilities> {
+        let subject = broker::queries::host_capabilities(&self.nsprefix, host_id);
+        match self.request(&subject, vec![]).await? {
+            Ok(msg) => {
+                let caps: HostCapabilities = decode(&msg.data)?;
+                Ok(caps)
+            }
+            Err(e) => {
+                Err(format!("Did not receive host capabilities from target host: {}", e).into())
+            }
+        }
+    }
+
+    /// Probes the health of a single provider instance, identified by the instance ID reported
+    /// for it in its host's inventory, rather than every instance of the provider
+    pub async fn probe_provider_instance(&self, instance_id: &str) -> Result<ProviderHealth> {
+        let subject = broker::queries::provider_health(&self.nsprefix, instance_id);
+        match self.request(&subject, vec![]).await? {
+            Ok(msg) => {
+                let health: ProviderHealth = decode(&msg.data)?;
+                Ok(health)
+            }
+            Err(e) => Err(format!("Did not receive health from provider instance: {}", e).into()),
+        }
+    }
+
+    /// Retrieves the configuration of a single provider instance: where it runs, the image it
+    /// was started from, and the links bound to it
+    pub async fn get_provider_instance_config(
+        &self,
+        instance_id: &str,
+    ) -> Result<ProviderInstanceConfig> {
+        let subject = broker::queries::provider_config(&self.nsprefix, instance_id);
+        match self.request(&subject, vec![]).await? {
+            Ok(msg) => {
+                let cfg: ProviderInstanceConfig = decode(&msg.data)?;
+                Ok(cfg)
+            }
+            Err(e) => Err(format!(
+                "Did not receive configuration from provider instance: {}",
+                e
+            )
+            .into()),
+        }
+    }
+
+    /// Asks a single provider instance to stop accepting new external work, leaving any other
+    /// instances of the same provider untouched
+    pub async fn quiesce_provider_instance(&self, instance_id: &str) -> Result<ProviderQuiesceAck> {
+        let subject = broker::commands::quiesce_provider(&self.nsprefix, instance_id);
+        self.provider_instance_command(&subject).await
+    }
+
+    /// Asks a previously quiesced provider instance to start accepting new work again
+    pub async fn resume_provider_instance(&self, instance_id: &str) -> Result<ProviderQuiesceAck> {
+        let subject = broker::commands::resume_provider(&self.nsprefix, instance_id);
+        self.provider_instance_command(&subject).await
+    }
+
+    async fn provider_instance_command(&self, subject: &str) -> Result<ProviderQuiesceAck> {
+        match self.request(subject, vec![]).await? {
+            Ok(msg) => {
+                let ack: ProviderQuiesceAck = decode(&msg.data)?;
+                Ok(ack)
+            }
+            Err(e) => Err(format!(
+                "Did not receive acknowledgement from provider instance: {}",
+                e
+            )
+            .into()),
+        }
+    }
+
     pub async fn start_actor(&self, host_id: &str, actor_ref: &str) -> Result<StartActorAck> {
         let subject = broker::commands::start_actor(&self.nsprefix, host_id);
         let bytes = serialize(StartActorCommand {
             actor_ref: actor_ref.to_string(),
             host_id: host_id.to_string(),
         })?;
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, &bytes)).await? {
+        match self.request(&subject, bytes).await? {
             Ok(msg) => {
-                let ack: StartActorAck = deserialize(&msg.data)?;
+                let ack: StartActorAck = decode(&msg.data)?;
                 Ok(ack)
             }
             Err(e) => Err(format!("Did not receive start actor acknowledgement: {}", e).into()),
@@ -124,14 +292,45 @@ impl Client {
         operation: &str,
         data: &[u8],
     ) -> Result<InvocationResponse> {
-        let subject = broker::rpc::call_actor(&self.nsprefix, target_id);
-        let bytes = crate::generated::ctliface::serialize(Invocation::new(
+        let inv = Invocation::new(
+            &self.key,
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor(target_id.to_string()),
+            operation,
+            data.to_vec(),
+        );
+        self.send_invocation(target_id, inv).await
+    }
+
+    /// Invokes an actor with an idempotency key. A host that has already handled a successful
+    /// invocation with the same key and payload returns the earlier response rather than
+    /// executing the actor again, so the call can be safely retried. Because retries may be
+    /// routed to a different host, this is a guarantee per host rather than per lattice
+    pub async fn call_actor_idempotent(
+        &self,
+        target_id: &str,
+        operation: &str,
+        data: &[u8],
+        idempotency_key: &str,
+    ) -> Result<InvocationResponse> {
+        let inv = Invocation::new(
             &self.key,
             WasccEntity::Actor("system".to_string()),
             WasccEntity::Actor(target_id.to_string()),
             operation,
             data.to_vec(),
-        ))?;
+        )
+        .with_idempotency_key(idempotency_key);
+        self.send_invocation(target_id, inv).await
+    }
+
+    async fn send_invocation(
+        &self,
+        target_id: &str,
+        inv: Invocation,
+    ) -> Result<InvocationResponse> {
+        let subject = broker::rpc::call_actor(&self.nsprefix, target_id);
+        let bytes = crate::generated::ctliface::serialize(inv)?;
         match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, &bytes)).await? {
             Ok(msg) => {
                 let resp: InvocationResponse = crate::generated::ctliface::deserialize(&msg.data)?;
@@ -166,6 +365,40 @@ impl Client {
         Ok(())
     }
 
+    /// Advertises a set of link definitions to the lattice. Every link is attempted, and
+    /// those that could not be published are reported as failures instead of stopping the
+    /// remaining links from being advertised
+    pub async fn advertise_links(
+        &self,
+        links: Vec<LinkDefinition>,
+    ) -> PartialResults<LinkDefinition> {
+        self.advertise_links_with(links, &FanoutOptions::default(), None)
+            .await
+    }
+
+    /// Advertises a set of link definitions to the lattice, publishing them concurrently
+    /// according to the given options and reporting each one on the progress channel
+    pub async fn advertise_links_with(
+        &self,
+        links: Vec<LinkDefinition>,
+        options: &FanoutOptions,
+        progress: Option<UnboundedSender<FanoutProgress>>,
+    ) -> PartialResults<LinkDefinition> {
+        let subject = broker::rpc::advertise_links(&self.nsprefix);
+        let tasks = links
+            .into_iter()
+            .map(|ld| FanoutTask {
+                target: format!(
+                    "link {} - {} ({})",
+                    ld.actor_id, ld.provider_id, ld.contract_id
+                ),
+                host_id: None,
+                request: self.publish_link(subject.to_string(), ld),
+            })
+            .collect();
+        fan_out(tasks, options, progress).await
+    }
+
     /// Issue a command to a host instructing that it replace an existing actor (indicated by its
     /// public key) with a new actor indicated by an OCI image reference. The host will acknowledge
     /// this request as soon as it verifies that the target actor is running. This acknowledgement
@@ -186,15 +419,34 @@ impl Client {
             actor_id: existing_actor_id.to_string(),
             new_actor_ref: new_actor_ref.to_string(),
         })?;
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, &bytes)).await? {
+        match self.request(&subject, bytes).await? {
             Ok(msg) => {
-                let ack: UpdateActorAck = deserialize(&msg.data)?;
+                let ack: UpdateActorAck = decode(&msg.data)?;
                 Ok(ack)
             }
             Err(e) => Err(format!("Did not receive update actor acknowledgement: {}", e).into()),
         }
     }
 
+    /// Issues update commands to many hosts concurrently, e.g. to roll a new version of an
+    /// actor out across the lattice. Hosts that don't accept the update are reported as failures
+    pub async fn update_actors(
+        &self,
+        updates: Vec<UpdateActorCommand>,
+        options: &FanoutOptions,
+        progress: Option<UnboundedSender<FanoutProgress>>,
+    ) -> PartialResults<UpdateActorAck> {
+        let tasks = updates
+            .into_iter()
+            .map(|cmd| FanoutTask {
+                target: cmd.host_id.to_string(),
+                host_id: Some(cmd.host_id.to_string()),
+                request: self.update_accepted(cmd),
+            })
+            .collect();
+        fan_out(tasks, options, progress).await
+    }
+
     pub async fn start_provider(
         &self,
         host_id: &str,
@@ -207,9 +459,9 @@ impl Client {
             provider_ref: provider_ref.to_string(),
             link_name: link_name.unwrap_or("default".to_string()),
         })?;
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, &bytes)).await? {
+        match self.request(&subject, bytes).await? {
             Ok(msg) => {
-                let ack: StartProviderAck = deserialize(&msg.data)?;
+                let ack: StartProviderAck = decode(&msg.data)?;
                 Ok(ack)
             }
             Err(e) => Err(format!("Did not receive start provider acknowledgement: {}", e).into()),
@@ -230,9 +482,9 @@ impl Client {
             link_name: link_name.to_string(),
             contract_id: contract_id.to_string(),
         })?;
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, &bytes)).await? {
+        match self.request(&subject, bytes).await? {
             Ok(msg) => {
-                let ack: StopProviderAck = deserialize(&msg.data)?;
+                let ack: StopProviderAck = decode(&msg.data)?;
                 Ok(ack)
             }
             Err(e) => Err(format!("Did not receive stop provider acknowledgement: {}", e).into()),
@@ -245,20 +497,91 @@ impl Client {
             host_id: host_id.to_string(),
             actor_ref: actor_ref.to_string(),
         })?;
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, &bytes)).await? {
+        match self.request(&subject, bytes).await? {
             Ok(msg) => {
-                let ack: StopActorAck = deserialize(&msg.data)?;
+                let ack: StopActorAck = decode(&msg.data)?;
                 Ok(ack)
             }
             Err(e) => Err(format!("Did not receive stop actor acknowledgement: {}", e).into()),
         }
     }
 
+    /// Changes the log level of an actor or capability provider (identified by its public key)
+    /// on the given host, without restarting either. The level is one of `off`, `error`, `warn`,
+    /// `info`, `debug`, or `trace`; an empty level puts the target back on the host's level
+    pub async fn set_log_level(
+        &self,
+        host_id: &str,
+        target: &str,
+        level: &str,
+    ) -> Result<SetLogLevelAck> {
+        let subject = broker::commands::set_log_level(&self.nsprefix, host_id);
+        let bytes = serialize(SetLogLevelCommand {
+            host_id: host_id.to_string(),
+            target: target.to_string(),
+            level: level.to_string(),
+        })?;
+        match self.request(&subject, bytes).await? {
+            Ok(msg) => {
+                let ack: SetLogLevelAck = decode(&msg.data)?;
+                Ok(ack)
+            }
+            Err(e) => Err(format!("Did not receive log level acknowledgement: {}", e).into()),
+        }
+    }
+
+    /// Tells a host to prepare to be replaced by a new version of itself: the host drains, writes
+    /// its upgrade journal, and signals its supervisor through its upgrade hooks. The ack is sent
+    /// once all of that is done, or reports why it couldn't be
+    pub async fn prepare_upgrade(&self, host_id: &str) -> Result<PrepareUpgradeAck> {
+        let subject = broker::commands::prepare_upgrade(&self.nsprefix, host_id);
+        let bytes = serialize(PrepareUpgradeCommand {
+            host_id: host_id.to_string(),
+        })?;
+        match self.request(&subject, bytes).await? {
+            Ok(msg) => {
+                let ack: PrepareUpgradeAck = decode(&msg.data)?;
+                Ok(ack)
+            }
+            Err(e) => Err(format!("Did not receive upgrade acknowledgement: {}", e).into()),
+        }
+    }
+
+    /// Sets how the invocations entering the lattice are sampled for tracing on every host that
+    /// replies within the timeout. Rates run from 0 (trace nothing) to 1 (trace everything) and
+    /// are keyed by actor public key or contract ID, with the default rate applying to anything
+    /// without a rate of its own
+    pub async fn set_trace_sampling(
+        &self,
+        default_rate: f64,
+        rates: HashMap<String, f64>,
+        always_sample_errors: bool,
+        timeout: Duration,
+    ) -> Result<PartialResults<TraceSamplingAck>> {
+        let subject = broker::commands::set_trace_sampling(&self.nsprefix);
+        let bytes = serialize(TraceSamplingCommand {
+            default_rate,
+            rates,
+            always_sample_errors,
+        })?;
+        self.gather(&subject, bytes, timeout).await
+    }
+
+    /// Revokes the API token with the given ID on every host in the namespace that trusts the
+    /// given issuer, such as a key shared by a cluster. Hosts started later learn of the
+    /// revocation from the hosts already running
+    pub async fn revoke_api_token(&self, issuer: &KeyPair, token_id: &str) -> Result<()> {
+        let revocation = tokens::mint_revocation(issuer, token_id, &self.nsprefix)?;
+        let subject = broker::commands::revoke_api_token(&self.nsprefix);
+        self.nc.publish(&subject, revocation.as_bytes()).await?;
+        Ok(())
+    }
+
     pub async fn get_claims(&self) -> Result<ClaimsList> {
         let subject = broker::queries::claims(&self.nsprefix);
-        match actix_rt::time::timeout(self.timeout, self.nc.request(&subject, vec![])).await? {
+        match self.request(&subject, vec![]).await? {
             Ok(msg) => {
-                let list: ClaimsList = deserialize(&msg.data)?;
+                let list: ClaimsList = decode(&msg.data)?;
                 Ok(list)
             }
             Err(e) => Err(format!("Did not receive claims from lattice: {}", e).into()),
@@ -266,6 +589,102 @@ impl Client {
     }
 }
 
+impl Client {
+    // Wraps a control request's payload with the client's token, if it has one
+    fn body(&self, payload: Vec<u8>) -> Vec<u8> {
+        match self.token {
+            Some(ref token) => tokens::seal(token, payload),
+            None => payload,
+        }
+    }
+
+    // Sends a control request, with the client's token if it has one, waiting at most the
+    // client's timeout for the reply
+    async fn request(
+        &self,
+        subject: &str,
+        payload: Vec<u8>,
+    ) -> ::std::result::Result<std::io::Result<nats::asynk::Message>, actix_rt::time::Elapsed> {
+        actix_rt::time::timeout(self.timeout, self.nc.request(subject, self.body(payload))).await
+    }
+
+    async fn publish_link(&self, subject: String, ld: LinkDefinition) -> Result<LinkDefinition> {
+        let bytes = crate::generated::ctliface::serialize(&ld)?;
+        self.nc.publish(&subject, &bytes).await?;
+        Ok(ld)
+    }
+
+    async fn update_accepted(&self, cmd: UpdateActorCommand) -> Result<UpdateActorAck> {
+        let ack = self
+            .update_actor(&cmd.host_id, &cmd.actor_id, &cmd.new_actor_ref)
+            .await?;
+        if ack.accepted {
+            Ok(ack)
+        } else {
+            Err(format!("Host did not accept update of actor {}", cmd.actor_id).into())
+        }
+    }
+
+    // Broadcasts a request to every host listening on the subject and collects the replies
+    // until the timeout expires. Replies that can't be decoded are recorded as failures
+    async fn gather<T: DeserializeOwned>(
+        &self,
+        subject: &str,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<PartialResults<T>> {
+        let replies: Vec<nats::asynk::Message> = self
+            .nc
+            .request_multi(subject, self.body(payload))
+            .await?
+            .take_until(delay_for(timeout))
+            .collect()
+            .await;
+        let mut pr = PartialResults::default();
+        for m in replies {
+            match decode::<T>(&m.data) {
+                Ok(r) => pr.results.push(r),
+                Err(e) => pr.errored(None, format!("Invalid reply on {}: {}", subject, e)),
+            }
+        }
+        Ok(pr)
+    }
+}
+
+impl InventoryDelta {
+    /// Brings a copy of a host's inventory up to date with this delta
+    pub fn apply_to(&self, inv: &mut HostInventory) {
+        if self.full {
+            inv.actors.clear();
+            inv.providers.clear();
+        }
+        inv.host_id = self.host_id.to_string();
+        if let Some(ref labels) = self.labels {
+            inv.labels = labels.clone();
+        }
+        inv.actors.retain(|a| {
+            !self.actors_removed.contains(&a.id) && !self.actors_added.iter().any(|n| n.id == a.id)
+        });
+        inv.actors.extend(self.actors_added.iter().cloned());
+        inv.providers.retain(|p| {
+            !self
+                .providers_removed
+                .iter()
+                .chain(self.providers_added.iter())
+                .any(|r| r.id == p.id && r.link_name == p.link_name)
+        });
+        inv.providers.extend(self.providers_added.iter().cloned());
+    }
+}
+
+// Deserializes a reply to a control request, surfacing the host's reason if it refused the request
+fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+    deserialize(data).map_err(|e| match deserialize::<tokens::AccessDenied>(data) {
+        Ok(denied) => format!("Request refused: {}", denied.access_denied).into(),
+        Err(_) => e,
+    })
+}
+
 /// The standard function for serializing codec structs into a format that can be
 /// used for message exchange between actor and host. Use of any other function to
 /// serialize could result in breaking incompatibilities.
@@ -0,0 +1,64 @@
+// Lattice-wide operations are scattered to every host that is listening and the responses are
+// gathered until a deadline. Under a partition (or when a host is simply slow), only some hosts
+// will answer, so rather than failing the entire operation, these types let a caller see what
+// came back and which hosts didn't make it.
+
+/// Indicates why a host did not contribute to the results of a scatter/gather operation
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureKind {
+    /// The host did not respond before the deadline
+    TimedOut,
+    /// The host responded, but with an error or with a response that could not be decoded
+    Errored,
+}
+
+/// A single host's failure to participate in a scatter/gather operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostFailure {
+    /// The host that failed. This will be `None` when the failure can't be attributed to a
+    /// host, e.g. when a reply to a broadcast request could not be decoded
+    pub host_id: Option<String>,
+    pub kind: FailureKind,
+    pub reason: String,
+}
+
+/// The results of an operation that was scattered across multiple hosts in a lattice. The
+/// `results` contain every response that was successfully gathered, while `failures`
+/// describe the hosts that timed out or errored
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialResults<T> {
+    pub results: Vec<T>,
+    pub failures: Vec<HostFailure>,
+}
+
+impl<T> PartialResults<T> {
+    /// Indicates whether every host that was asked responded successfully
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub(crate) fn timed_out(&mut self, host_id: Option<String>, reason: String) {
+        self.failures.push(HostFailure {
+            host_id,
+            kind: FailureKind::TimedOut,
+            reason,
+        });
+    }
+
+    pub(crate) fn errored(&mut self, host_id: Option<String>, reason: String) {
+        self.failures.push(HostFailure {
+            host_id,
+            kind: FailureKind::Errored,
+            reason,
+        });
+    }
+}
+
+impl<T> Default for PartialResults<T> {
+    fn default() -> Self {
+        PartialResults {
+            results: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// Anyone who can reach the lattice's NATS server can send every kind of control request, so
+// giving a dashboard the ability to list hosts also gives it the ability to stop them. Hosts
+// can instead require an API token on control requests. A token is a JWT signed by a host's key
+// (or another key the host trusts, such as one shared by a cluster), bound to one lattice
+// namespace, and carrying a scope:
+//
+// * `read_only` allows queries (inventories, claims, link definitions, health, auctions)
+// * `operator` also allows starting actors and providers, quiescing and resuming provider
+//   instances, and changing log levels
+// * `admin` allows everything, including stopping and updating actors and providers
+//
+// A token may expire, and tokens can be revoked by their ID. A revocation is itself signed by a
+// token issuer, and is published to every host in the namespace; a host honors revocations
+// signed by the issuers it trusts, and asks the hosts already running for the revocations they
+// hold when it starts. The token travels with each request in an envelope around the request's
+// usual payload.
+
+use crate::Result;
+use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
+use ring::digest::{digest, SHA256};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wascap::prelude::KeyPair;
+
+const JWT_HEADER: &str = r#"{"typ":"JWT","alg":"ed25519-nkey"}"#;
+
+/// What the holder of an API token may do. Each scope includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+impl TokenScope {
+    /// Whether this scope covers requests that need the given scope
+    pub fn allows(&self, required: TokenScope) -> bool {
+        *self >= required
+    }
+}
+
+/// The claims carried by an API token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiTokenClaims {
+    /// The token's ID, used to revoke it
+    pub jti: String,
+    /// The public key of the token's issuer
+    pub iss: String,
+    /// A name for the holder of the token, such as the dashboard it was minted for
+    pub sub: String,
+    /// The lattice namespace in which the token is valid
+    pub ns: String,
+    pub scope: TokenScope,
+    pub iat: u64,
+    /// When the token expires, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+}
+
+/// The claims carried by a revocation, which tells hosts to refuse an API token from now on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RevocationClaims {
+    /// The ID (`jti`) of the revoked token
+    pub revoked: String,
+    /// The public key of the revocation's issuer
+    pub iss: String,
+    /// The lattice namespace in which the token is revoked
+    pub ns: String,
+    pub iat: u64,
+}
+
+/// A control request carrying an API token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenEnvelope {
+    pub token: String,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+/// The reply to a control request that was refused for want of a valid token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessDenied {
+    pub access_denied: String,
+}
+
+/// Mints an API token signed by the given key, valid in the given lattice namespace and, if a
+/// time to live is given, only until it runs out
+pub fn mint_token(
+    issuer: &KeyPair,
+    name: &str,
+    namespace: &Option<String>,
+    scope: TokenScope,
+    ttl: Option<Duration>,
+) -> Result<String> {
+    let iat = now();
+    let mut claims = ApiTokenClaims {
+        jti: String::new(),
+        iss: issuer.public_key(),
+        sub: name.to_string(),
+        ns: namespace_of(namespace),
+        scope,
+        iat,
+        exp: ttl.map(|ttl| iat + ttl.as_secs()),
+    };
+    // The token ID is the hash of the claims themselves
+    let hash = digest(&SHA256, serde_json::to_string(&claims)?.as_bytes());
+    claims.jti = BASE32_NOPAD.encode(hash.as_ref());
+    encode_jwt(issuer, &claims)
+}
+
+/// Decodes an API token, verifying its signature against its issuer and that it hasn't expired.
+/// Whether the issuer is trusted, and whether the token has been revoked, is up to the caller
+pub fn decode_token(token: &str) -> Result<ApiTokenClaims> {
+    let claims = decode_jwt::<ApiTokenClaims>(token, "API token", |c| &c.iss)?;
+    if let Some(exp) = claims.exp {
+        if now() >= exp {
+            return Err("API token has expired".into());
+        }
+    }
+    Ok(claims)
+}
+
+/// Mints a revocation of the token with the given ID, signed by the given key, for the given
+/// lattice namespace
+pub fn mint_revocation(
+    issuer: &KeyPair,
+    token_id: &str,
+    namespace: &Option<String>,
+) -> Result<String> {
+    encode_jwt(
+        issuer,
+        &RevocationClaims {
+            revoked: token_id.to_string(),
+            iss: issuer.public_key(),
+            ns: namespace_of(namespace),
+            iat: now(),
+        },
+    )
+}
+
+/// Decodes a revocation, verifying its signature against its issuer. Whether the issuer is
+/// trusted is up to the caller
+pub fn decode_revocation(revocation: &str) -> Result<RevocationClaims> {
+    decode_jwt::<RevocationClaims>(revocation, "Revocation", |c| &c.iss)
+}
+
+fn encode_jwt<T: Serialize>(issuer: &KeyPair, claims: &T) -> Result<String> {
+    let head_and_claims = format!(
+        "{}.{}",
+        BASE64URL_NOPAD.encode(JWT_HEADER.as_bytes()),
+        BASE64URL_NOPAD.encode(serde_json::to_string(claims)?.as_bytes())
+    );
+    let sig = issuer.sign(head_and_claims.as_bytes())?;
+    Ok(format!(
+        "{}.{}",
+        head_and_claims,
+        BASE64URL_NOPAD.encode(&sig)
+    ))
+}
+
+// Decodes a JWT, verifying its signature against the issuer named in its claims
+fn decode_jwt<T: DeserializeOwned>(jwt: &str, kind: &str, issuer: fn(&T) -> &String) -> Result<T> {
+    let parts: Vec<_> = jwt.split('.').collect();
+    if parts.len() != 3 {
+        return Err(format!("{} is not a JWT", kind).into());
+    }
+    let claims: T = serde_json::from_slice(&BASE64URL_NOPAD.decode(parts[1].as_bytes())?)?;
+    let sig = BASE64URL_NOPAD.decode(parts[2].as_bytes())?;
+    KeyPair::from_public_key(issuer(&claims))?
+        .verify(format!("{}.{}", parts[0], parts[1]).as_bytes(), &sig)
+        .map_err(|_| format!("{} signature is invalid", kind))?;
+    Ok(claims)
+}
+
+/// Wraps a control request's payload in an envelope carrying the given token
+pub fn seal(token: &str, body: Vec<u8>) -> Vec<u8> {
+    serde_json::to_vec(&TokenEnvelope {
+        token: token.to_string(),
+        body,
+    })
+    .unwrap()
+}
+
+/// Unwraps a control request's payload from its token envelope, if it has one
+pub fn open(data: &[u8]) -> Option<TokenEnvelope> {
+    serde_json::from_slice(data).ok()
+}
+
+pub(crate) fn namespace_of(namespace: &Option<String>) -> String {
+    namespace
+        .as_ref()
+        .map(|ns| ns.to_string())
+        .unwrap_or_else(|| "default".to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_revocation, decode_token, mint_revocation, mint_token, open, seal, TokenScope,
+    };
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn tokens_round_trip_and_are_verified() {
+        let host = KeyPair::new_server();
+        let token = mint_token(&host, "dashboard", &None, TokenScope::ReadOnly, None).unwrap();
+        let claims = decode_token(&token).unwrap();
+        assert_eq!(host.public_key(), claims.iss);
+        assert_eq!("default", claims.ns);
+        assert!(claims.scope.allows(TokenScope::ReadOnly));
+        assert!(!claims.scope.allows(TokenScope::Operator));
+        assert!(TokenScope::Admin.allows(TokenScope::Operator));
+
+        // Tampering with the claims breaks the signature
+        let parts: Vec<_> = token.split('.').collect();
+        let forged = mint_token(&host, "dashboard", &None, TokenScope::Admin, None).unwrap();
+        let forged_claims = forged.split('.').nth(1).unwrap();
+        assert!(decode_token(&format!("{}.{}.{}", parts[0], forged_claims, parts[2])).is_err());
+
+        let expired = mint_token(
+            &host,
+            "ci",
+            &Some("prod".to_string()),
+            TokenScope::Admin,
+            Some(Duration::from_secs(0)),
+        )
+        .unwrap();
+        assert!(decode_token(&expired).is_err());
+
+        let env = open(&seal(&token, b"{}".to_vec())).unwrap();
+        assert_eq!(token, env.token);
+        assert_eq!(b"{}".to_vec(), env.body);
+        assert!(open(br#"{"actor_ref":"wasmcloud.azurecr.io/echo:0.2.0"}"#).is_none());
+    }
+
+    #[test]
+    fn revocations_are_signed_by_their_issuer() {
+        let cluster = KeyPair::new_account();
+        let token = mint_token(&cluster, "ci", &None, TokenScope::Admin, None).unwrap();
+        let id = decode_token(&token).unwrap().jti;
+        let revocation = mint_revocation(&cluster, &id, &None).unwrap();
+        let claims = decode_revocation(&revocation).unwrap();
+        assert_eq!(id, claims.revoked);
+        assert_eq!(cluster.public_key(), claims.iss);
+        assert_eq!("default", claims.ns);
+
+        // A revocation can't be passed off as a token, or signed for another issuer
+        assert!(decode_token(&revocation).is_err());
+        let other = mint_revocation(&KeyPair::new_account(), &id, &None).unwrap();
+        let parts: Vec<_> = revocation.split('.').collect();
+        let forged = format!(
+            "{}.{}.{}",
+            parts[0],
+            parts[1],
+            other.split('.').nth(2).unwrap()
+        );
+        assert!(decode_revocation(&forged).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+// The `#[capability]` attribute writes the `CapabilityBuilder` calls for an in-process provider.
+// It goes on an impl block of the provider type, and every method in the block marked with
+// `#[operation]` is registered as an operation of the provider's contract:
+//
+// #[capability(contract = "example:echo", name = "Echo", description = "Echoes greetings")]
+// impl Echo {
+//     #[operation(description = "Echoes the greeting")]
+//     fn echo(&self, ctx: &CallContext, msg: String) -> Result<String> { ... }
+//
+//     #[operation(name = "Blob", raw)]
+//     fn blob(&self, ctx: &CallContext, msg: &[u8]) -> Result<Vec<u8>> { ... }
+// }
+//
+// The block is left as it is, less the `#[operation]` attributes, and gains `capability_builder`
+// and `capability` functions that produce the provider's builder and its built capability. An
+// operation is named after its method in upper camel case unless it is given a name, and `raw`
+// operations handle their payload as it is rather than through the standard codec. Operation
+// methods are registered as plain functions, so they are type checked by the builder exactly
+// as hand-registered ones are.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse_macro_input, AttributeArgs, Error, ImplItem, ItemImpl, Lit, Meta, NestedMeta, Result,
+};
+
+#[proc_macro_attribute]
+pub fn capability(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let block = parse_macro_input!(item as ItemImpl);
+    expand(args, block)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+struct Operation {
+    method: syn::Ident,
+    name: String,
+    description: String,
+    raw: bool,
+}
+
+fn expand(args: AttributeArgs, mut block: ItemImpl) -> Result<proc_macro2::TokenStream> {
+    let mut contract = None;
+    let mut name = None;
+    let mut description = String::new();
+    let mut revision = 0u32;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("contract") => {
+                contract = Some(string_value(&nv.lit)?)
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                name = Some(string_value(&nv.lit)?)
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("description") => {
+                description = string_value(&nv.lit)?
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("revision") => {
+                revision = match &nv.lit {
+                    Lit::Int(i) => i.base10_parse()?,
+                    other => return Err(Error::new_spanned(other, "expected an integer")),
+                }
+            }
+            other => {
+                return Err(Error::new_spanned(
+                    other,
+                    "expected contract, name, description or revision",
+                ))
+            }
+        }
+    }
+    let contract = contract.ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            "a capability needs a contract, e.g. #[capability(contract = \"wascc:keyvalue\")]",
+        )
+    })?;
+    let name = name.unwrap_or_else(|| contract.to_string());
+
+    let mut operations = vec![];
+    for item in block.items.iter_mut() {
+        if let ImplItem::Method(method) = item {
+            let mut attrs = vec![];
+            for attr in method.attrs.drain(..) {
+                if attr.path.is_ident("operation") {
+                    operations.push(operation(&method.sig.ident, attr.parse_meta()?)?);
+                } else {
+                    attrs.push(attr);
+                }
+            }
+            method.attrs = attrs;
+        }
+    }
+
+    let registrations = operations.iter().map(|op| {
+        let method = &op.method;
+        let name = &op.name;
+        let description = &op.description;
+        if op.raw {
+            quote! { .raw_operation(#name, #description, Self::#method) }
+        } else {
+            quote! { .operation(#name, #description, Self::#method) }
+        }
+    });
+    let self_ty = &block.self_ty;
+    let (impl_generics, _, where_clause) = block.generics.split_for_impl();
+
+    Ok(quote! {
+        #block
+
+        impl #impl_generics #self_ty #where_clause {
+            /// A builder for the provider's capability, with its description and operations
+            pub fn capability_builder(self) -> ::wasmcloud_host::CapabilityBuilder<Self> {
+                ::wasmcloud_host::CapabilityBuilder::new(#contract, self)
+                    .describe(#name, #description)
+                    .revision(#revision)
+                    #(#registrations)*
+            }
+
+            /// The provider's capability, ready to be started as a native capability
+            pub fn capability(self) -> ::wasmcloud_host::Capability<Self> {
+                self.capability_builder().build()
+            }
+        }
+    })
+}
+
+fn operation(method: &syn::Ident, meta: Meta) -> Result<Operation> {
+    let mut op = Operation {
+        method: method.clone(),
+        name: upper_camel_case(&method.to_string()),
+        description: String::new(),
+        raw: false,
+    };
+    let nested = match meta {
+        Meta::Path(_) => return Ok(op),
+        Meta::List(list) => list.nested,
+        other => {
+            return Err(Error::new_spanned(
+                other,
+                "expected #[operation] or #[operation(...)]",
+            ))
+        }
+    };
+    for arg in nested {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                op.name = string_value(&nv.lit)?
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("description") => {
+                op.description = string_value(&nv.lit)?
+            }
+            NestedMeta::Meta(Meta::Path(p)) if p.is_ident("raw") => op.raw = true,
+            other => {
+                return Err(Error::new_spanned(
+                    other,
+                    "expected name, description or raw",
+                ))
+            }
+        }
+    }
+    Ok(op)
+}
+
+fn string_value(lit: &Lit) -> Result<String> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        other => Err(Error::new_spanned(other, "expected a string")),
+    }
+}
+
+fn upper_camel_case(ident: &str) -> String {
+    ident
+        .split('_')
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
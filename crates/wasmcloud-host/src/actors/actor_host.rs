@@ -1,30 +1,125 @@
 use crate::actors::WasccActor;
+use crate::billing::{self, BillingSink, CostRecord};
 use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::idempotency::{IdempotencyCache, IdempotencyConfig};
 
 use crate::dispatch::{Invocation, InvocationResponse, WasccEntity};
 use crate::hlreg::HostLocalSystemService;
-use crate::messagebus::{AdvertiseClaims, MessageBus, PutClaims, Subscribe};
+use crate::messagebus::ordered::is_ordered;
+use crate::messagebus::{AdvertiseClaims, ClaimOrderedActor, MessageBus, Subscribe};
 use crate::middleware::{run_actor_post_invoke, run_actor_pre_invoke, Middleware};
+use crate::snapshots::{ExecutionSnapshot, SnapshotConfig};
+use crate::symbols::Symbols;
+use crate::wasm_features::{check_actor_features, WasmFeatures};
 use crate::{ControlEvent, Result};
 use actix::prelude::*;
+use actix_web::web;
 use futures::executor::block_on;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use wapc::WapcHost;
 use wascap::jwt::TokenValidation;
 use wascap::prelude::{Claims, KeyPair};
 
+/// An actor with a thread of its own, run by a `SyncArbiter`
 #[derive(Default)]
 pub(crate) struct ActorHost {
     state: Option<State>,
 }
 
+/// An actor pinned to one of the threads of a pool, which it shares with the other actors
+/// pinned there
+#[derive(Default)]
+pub(crate) struct PooledActorHost {
+    host: ActorHost,
+}
+
+/// An actor whose invocations run as tasks on the host's blocking thread pool. The actor itself
+/// lives on the host's own thread, so it must never block: everything it waits on is awaited
+#[derive(Default)]
+pub(crate) struct TaskActorHost {
+    host: ActorHost,
+    module: Option<TaskModule>,
+}
+
 struct State {
-    guest_module: WapcHost,
+    // The guest that runs the actor's module. Task model actors create theirs on the threads
+    // that run their invocations instead
+    guest_module: Option<WapcHost>,
     claims: Claims<wascap::jwt::Actor>,
     mw_chain: Vec<Box<dyn Middleware>>,
     image_ref: Option<String>,
     host_id: String,
     seed: String,
     can_update: bool,
+    snapshots: Option<SnapshotConfig>,
+    wasm_features: WasmFeatures,
+    billing_sinks: Vec<Box<dyn BillingSink>>,
+    idempotency: IdempotencyCache,
+    symbols: Symbols,
+}
+
+// What a task needs to create an instance of an actor on whichever thread runs it. Each module
+// (a start or a live update) gets a new generation, so that instances of a module that has been
+// replaced are never used again
+#[derive(Clone)]
+struct TaskModule {
+    bytes: Arc<Vec<u8>>,
+    seed: String,
+    claims: Claims<wascap::jwt::Actor>,
+    generation: u64,
+}
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+// The generations of the task modules of running actors
+static LIVE_MODULES: Lazy<RwLock<HashSet<u64>>> = Lazy::new(|| RwLock::new(HashSet::new()));
+
+thread_local! {
+    // The instances of task model actors created on this thread, by module generation
+    static TASK_INSTANCES: RefCell<HashMap<u64, WapcHost>> = RefCell::new(HashMap::new());
+}
+
+impl TaskModule {
+    fn new(bytes: Vec<u8>, seed: &str, claims: &Claims<wascap::jwt::Actor>) -> TaskModule {
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::SeqCst);
+        LIVE_MODULES.write().insert(generation);
+        TaskModule {
+            bytes: Arc::new(bytes),
+            seed: seed.to_string(),
+            claims: claims.clone(),
+            generation,
+        }
+    }
+
+    fn retire(&self) {
+        LIVE_MODULES.write().remove(&self.generation);
+    }
+
+    // Runs an invocation on the current thread with this thread's instance of the module,
+    // creating it the first time. Instances of modules that have since been retired are dropped
+    fn run(&self, host_id: &str, inv: &Invocation) -> std::result::Result<Vec<u8>, String> {
+        TASK_INSTANCES.with(|instances| {
+            let mut instances = instances.borrow_mut();
+            {
+                let live = LIVE_MODULES.read();
+                instances.retain(|generation, _| live.contains(generation));
+            }
+            if !instances.contains_key(&self.generation) {
+                let guest =
+                    new_guest(&self.bytes, &self.seed, &self.claims).map_err(|e| e.to_string())?;
+                instances.insert(self.generation, guest);
+            }
+            let guest = &instances[&self.generation];
+            crate::replies::handling(host_id, inv, || guest.call(&inv.operation, &inv.msg))
+                .map_err(|e| e.to_string())
+        })
+    }
 }
 
 #[derive(Message)]
@@ -37,6 +132,10 @@ pub(crate) struct Initialize {
     pub image_ref: Option<String>,
     pub host_id: String,
     pub can_update: bool,
+    pub snapshots: Option<SnapshotConfig>,
+    pub wasm_features: WasmFeatures,
+    pub billing_sinks: Vec<Box<dyn BillingSink>>,
+    pub idempotency: IdempotencyConfig,
 }
 
 #[derive(Message)]
@@ -46,24 +145,119 @@ pub(crate) struct LiveUpdate {
     pub image_ref: String,
 }
 
-impl Handler<LiveUpdate> for ActorHost {
-    type Result = Result<()>;
+/// The address of a running actor, whichever thread model it runs under
+#[derive(Clone)]
+pub(crate) enum ActorAddr {
+    Dedicated(Addr<ActorHost>),
+    Pooled(Addr<PooledActorHost>),
+    Task(Addr<TaskActorHost>),
+}
 
-    fn handle(&mut self, msg: LiveUpdate, ctx: &mut Self::Context) -> Self::Result {
-        if self.state.is_none() {
-            return Err("Attempted to live update an actor with no existing state".into());
+impl ActorAddr {
+    pub async fn initialize(
+        &self,
+        msg: Initialize,
+    ) -> std::result::Result<Result<()>, MailboxError> {
+        match self {
+            ActorAddr::Dedicated(a) => a.send(msg).await,
+            ActorAddr::Pooled(a) => a.send(msg).await,
+            ActorAddr::Task(a) => a.send(msg).await,
+        }
+    }
+
+    pub async fn update(&self, msg: LiveUpdate) -> std::result::Result<Result<()>, MailboxError> {
+        match self {
+            ActorAddr::Dedicated(a) => a.send(msg).await,
+            ActorAddr::Pooled(a) => a.send(msg).await,
+            ActorAddr::Task(a) => a.send(msg).await,
         }
-        if !self.state.as_ref().unwrap().can_update {
+    }
+}
+
+impl ActorHost {
+    // Loads the actor and joins it to the message bus, blocking until it has. Only actors on a
+    // thread of their own or of a pool start this way, never on the host's own thread. `stop` is
+    // called if the actor can't run at all
+    fn load(
+        &mut self,
+        msg: Initialize,
+        recipient: Recipient<Invocation>,
+        stop: impl FnOnce(),
+    ) -> Result<String> {
+        let claims = check_actor(&msg.actor_bytes, &msg.wasm_features)?;
+        let guest = match new_guest(&msg.actor_bytes, &msg.signing_seed, &claims) {
+            Ok(guest) => guest,
+            Err(e) => {
+                stop();
+                return Err(e);
+            }
+        };
+        if let Err(e) = block_on(join_bus(msg.host_id.to_string(), claims.clone(), recipient)) {
+            stop();
+            return Err(e);
+        }
+        Ok(self.install(msg, claims, Some(guest)))
+    }
+
+    // Takes on the state of a started actor, returning its public key
+    fn install(
+        &mut self,
+        msg: Initialize,
+        claims: Claims<wascap::jwt::Actor>,
+        guest: Option<WapcHost>,
+    ) -> String {
+        let symbols = Symbols::from_module(&msg.actor_bytes);
+        self.state = Some(State {
+            guest_module: guest,
+            claims,
+            mw_chain: msg.mw_chain,
+            image_ref: msg.image_ref,
+            host_id: msg.host_id,
+            seed: msg.signing_seed,
+            can_update: msg.can_update,
+            snapshots: msg.snapshots,
+            wasm_features: msg.wasm_features,
+            billing_sinks: msg.billing_sinks,
+            // A live update keeps the remembered results, so retries that straddle it aren't
+            // executed twice
+            idempotency: match self.state.take() {
+                Some(previous) => previous.idempotency,
+                None => IdempotencyCache::new(msg.idempotency),
+            },
+            symbols,
+        });
+        let subject = self.state.as_ref().unwrap().claims.subject.to_string();
+        info!("Actor {} initialized", subject);
+        subject
+    }
+
+    // The event announcing that the actor has started
+    fn started_event(&self, actor: &str, image_ref: Option<String>) -> PublishEvent {
+        let state = self.state.as_ref().unwrap();
+        crate::labels::set_issuer(&state.host_id, actor, &state.claims.issuer);
+        PublishEvent {
+            event: ControlEvent::ActorStarted {
+                actor: actor.to_string(),
+                image_ref,
+            },
+        }
+    }
+
+    // Checks that a live update can replace the running actor and announces that it has begun.
+    // Returns the initialization of the new module, with the old and new revisions
+    fn begin_update(&self, msg: LiveUpdate) -> Result<(Initialize, u32, u32)> {
+        let state = match self.state {
+            Some(ref state) => state,
+            None => return Err("Attempted to live update an actor with no existing state".into()),
+        };
+        if !state.can_update {
             error!(
                 "Rejecting attempt to update actor {} - live updates disabled",
                 msg.image_ref
             );
             return Err("Attempt to live update actor denied. Runtime updates for this actor are not enabled".into());
         }
-        if self
-            .state
-            .as_ref()
-            .unwrap()
+        if state
             .image_ref
             .as_ref()
             .unwrap_or(&"".to_string())
@@ -79,11 +273,8 @@ impl Handler<LiveUpdate> for ActorHost {
         let actor = WasccActor::from_slice(&msg.actor_bytes)?;
         let new_claims = actor.claims();
         // Validate that this update is one that we will allow to take place
-        validate_update(&new_claims, &self.state.as_ref().unwrap().claims)?;
-        let old_revision = self
-            .state
-            .as_ref()
-            .unwrap()
+        validate_update(&new_claims, &state.claims)?;
+        let old_revision = state
             .claims
             .metadata
             .as_ref()
@@ -103,32 +294,187 @@ impl Handler<LiveUpdate> for ActorHost {
                 new_revision,
             },
         };
-        ControlInterface::from_hostlocal_registry(&self.state.as_ref().unwrap().host_id)
-            .do_send(pe);
+        ControlInterface::from_hostlocal_registry(&state.host_id).do_send(pe);
         // Essentially re-starting the actor with a new set of bytes
         let init = Initialize {
             actor_bytes: msg.actor_bytes,
-            mw_chain: self.state.as_ref().unwrap().mw_chain.clone(),
-            signing_seed: self.state.as_ref().unwrap().seed.clone(),
+            mw_chain: state.mw_chain.clone(),
+            signing_seed: state.seed.clone(),
             image_ref: Some(msg.image_ref),
-            host_id: self.state.as_ref().unwrap().host_id.to_string(),
+            host_id: state.host_id.to_string(),
             can_update: true,
+            snapshots: state.snapshots.clone(),
+            wasm_features: state.wasm_features,
+            billing_sinks: state.billing_sinks.clone(),
+            idempotency: state.idempotency.config().clone(),
         };
+        Ok((init, old_revision, new_revision))
+    }
+
+    fn update(
+        &mut self,
+        msg: LiveUpdate,
+        recipient: Recipient<Invocation>,
+        stop: impl FnOnce(),
+    ) -> Result<()> {
+        let (init, old_revision, new_revision) = self.begin_update(msg)?;
         let host_id = init.host_id.to_string();
-        let actor = perform_initialization(self, ctx, init);
-        match actor {
-            Ok(a) => {
-                ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent {
-                    event: ControlEvent::ActorUpdateCompleted {
-                        actor: a,
-                        old_revision,
-                        new_revision,
+        let actor = self.load(init, recipient, stop)?;
+        update_completed(&host_id, actor, old_revision, new_revision);
+        Ok(())
+    }
+
+    // Runs everything that comes before the actor's module handles an invocation: tracing, the
+    // idempotency cache, and the pre-invoke middleware. Returns the response when the module
+    // isn't to handle it
+    fn before_invoke(&mut self, msg: &Invocation) -> Option<InvocationResponse> {
+        let state = self.state.as_mut().unwrap();
+
+        let actor = state.claims.subject.as_str();
+        let tracing = crate::log_levels::enabled(&state.host_id, actor, log::Level::Trace);
+        if tracing && crate::sampling::sampled(msg) {
+            log!(
+                target: actor,
+                log::Level::Trace,
+                "Actor Invocation - From {} to {}: {} (baggage {:?})",
+                msg.origin.url(),
+                msg.target.url(),
+                msg.operation,
+                msg.baggage
+            );
+        }
+
+        if let WasccEntity::Actor(_) = msg.target {
+            if let Some(resp) = state
+                .idempotency
+                .lookup(msg, crate::clock::monotonic(&state.host_id))
+            {
+                return Some(resp);
+            }
+            if run_actor_pre_invoke(msg, &state.mw_chain).is_err() {
+                return Some(InvocationResponse::error(
+                    msg,
+                    "Pre-invoke middleware execution failure on actor",
+                ));
+            }
+            None
+        } else {
+            Some(InvocationResponse::error(
+                msg,
+                "Actor received invocation that should have been delivered to a provider",
+            ))
+        }
+    }
+
+    // Runs everything that comes after the actor's module has handled an invocation: the
+    // post-invoke middleware (or the reporting of a failure), billing, and the idempotency cache
+    fn after_invoke(
+        &mut self,
+        msg: &Invocation,
+        result: std::result::Result<Vec<u8>, String>,
+        elapsed: Duration,
+    ) -> InvocationResponse {
+        let state = self.state.as_mut().unwrap();
+        let actor = state.claims.subject.as_str();
+        let resp = match result.map(|v| crate::limits::cap_output(&state.host_id, msg, v)) {
+            Ok(Ok(v)) => {
+                let resp = InvocationResponse::success(msg, v);
+                match run_actor_post_invoke(resp, &state.mw_chain) {
+                    Ok(r) => r,
+                    Err(e) => InvocationResponse::error(
+                        msg,
+                        &format!("Post-invoke middleware execution failure on actor: {}", e),
+                    ),
+                }
+            }
+            Ok(Err(too_large)) => too_large,
+            Err(e) => {
+                // Symbols uploaded for the actor take the place of its own
+                let error = match crate::symbols::uploaded(&state.host_id, actor) {
+                    Some(symbols) => symbols.symbolicate(&e),
+                    None => state.symbols.symbolicate(&e),
+                };
+                capture_snapshot(state, msg, &error);
+                ControlInterface::from_hostlocal_registry(&state.host_id).do_send(PublishEvent {
+                    event: ControlEvent::ActorInvocationFailed {
+                        actor: actor.to_string(),
+                        operation: msg.operation.to_string(),
+                        error: error.to_string(),
                     },
                 });
-                Ok(())
+                InvocationResponse::error(msg, &format!("Failed to invoke actor: {}", error))
+            }
+        };
+        // Failures are traced even when their request wasn't sampled, if the host is set to
+        if let Some(ref e) = resp.error {
+            let tracing = crate::log_levels::enabled(&state.host_id, actor, log::Level::Trace);
+            if tracing
+                && !crate::sampling::sampled(msg)
+                && crate::sampling::errors_sampled(&state.host_id)
+            {
+                log!(
+                    target: actor,
+                    log::Level::Trace,
+                    "Actor Invocation - From {} to {}: {} failed: {} (baggage {:?})",
+                    msg.origin.url(),
+                    msg.target.url(),
+                    msg.operation,
+                    e,
+                    msg.baggage
+                );
             }
-            Err(e) => Err(e),
         }
+        record_cost(state, msg, elapsed, &resp);
+        let now = crate::clock::monotonic(&state.host_id);
+        state.idempotency.record(msg, &resp, now);
+        resp
+    }
+
+    /// Receives an invocation from any source. This will execute the full pre-exec
+    /// middleware chain, perform the requested operation, and then perform the full
+    /// post-exec middleware chain, assuming no errors indicate a pre-emptive halt
+    fn invoke(&mut self, msg: Invocation) -> InvocationResponse {
+        if let Some(resp) = self.before_invoke(&msg) {
+            return resp;
+        }
+        let state = self.state.as_ref().unwrap();
+        let started = crate::clock::monotonic(&state.host_id);
+        // Invocations the actor makes while handling this one carry its baggage, and any
+        // reply the message expects is owed until the actor sends or defers it
+        let result = match state.guest_module {
+            Some(ref guest) => crate::replies::handling(&state.host_id, &msg, || {
+                guest.call(&msg.operation, &msg.msg)
+            })
+            .map_err(|e| e.to_string()),
+            None => Err("Actor has no module to run".to_string()),
+        };
+        let elapsed = crate::clock::monotonic(&state.host_id)
+            .checked_sub(started)
+            .unwrap_or_default();
+        self.after_invoke(&msg, result, elapsed)
+    }
+}
+
+fn update_completed(host_id: &str, actor: String, old_revision: u32, new_revision: u32) {
+    ControlInterface::from_hostlocal_registry(host_id).do_send(PublishEvent {
+        event: ControlEvent::ActorUpdateCompleted {
+            actor,
+            old_revision,
+            new_revision,
+        },
+    });
+}
+
+impl Actor for ActorHost {
+    type Context = SyncContext<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        info!("Actor started");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        // NOTE: do not attempt to log asynchronously in a stopped function,
+        // resources (including stdout) may not be available
     }
 }
 
@@ -137,34 +483,217 @@ impl Handler<Initialize> for ActorHost {
 
     fn handle(&mut self, msg: Initialize, ctx: &mut Self::Context) -> Self::Result {
         let image_ref = msg.image_ref.clone();
-        let actor = perform_initialization(self, ctx, msg);
-        match actor {
-            Ok(a) => {
-                let pe = PublishEvent {
-                    event: ControlEvent::ActorStarted {
-                        actor: a.to_string(),
-                        image_ref,
-                    },
-                };
-                let host_id = self.state.as_ref().unwrap().host_id.to_string();
-                let _ = block_on(async move {
-                    let cp = ControlInterface::from_hostlocal_registry(&host_id);
-                    let _ = cp.send(pe).await;
-                });
-                Ok(())
-            }
-            Err(e) => Err(e),
+        let host_id = msg.host_id.to_string();
+        let recipient = ctx.address().recipient();
+        let actor = self.load(msg, recipient, || ctx.stop())?;
+        let pe = self.started_event(&actor, image_ref);
+        let _ = block_on(async move {
+            let cp = ControlInterface::from_hostlocal_registry(&host_id);
+            let _ = cp.send(pe).await;
+        });
+        Ok(())
+    }
+}
+
+impl Handler<LiveUpdate> for ActorHost {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: LiveUpdate, ctx: &mut Self::Context) -> Self::Result {
+        let recipient = ctx.address().recipient();
+        self.update(msg, recipient, || ctx.stop())
+    }
+}
+
+impl Handler<Invocation> for ActorHost {
+    type Result = InvocationResponse;
+
+    fn handle(&mut self, msg: Invocation, _ctx: &mut Self::Context) -> Self::Result {
+        self.invoke(msg)
+    }
+}
+
+impl Actor for PooledActorHost {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        info!("Actor started");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(ref state) = self.host.state {
+            crate::threading::unpin(&state.claims.subject);
+        }
+    }
+}
+
+impl Handler<Initialize> for PooledActorHost {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: Initialize, ctx: &mut Self::Context) -> Self::Result {
+        let image_ref = msg.image_ref.clone();
+        let host_id = msg.host_id.to_string();
+        let recipient = ctx.address().recipient();
+        let actor = self.host.load(msg, recipient, || ctx.stop())?;
+        crate::threading::pin(&actor);
+        let pe = self.host.started_event(&actor, image_ref);
+        let _ = block_on(async move {
+            let cp = ControlInterface::from_hostlocal_registry(&host_id);
+            let _ = cp.send(pe).await;
+        });
+        Ok(())
+    }
+}
+
+impl Handler<LiveUpdate> for PooledActorHost {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: LiveUpdate, ctx: &mut Self::Context) -> Self::Result {
+        let recipient = ctx.address().recipient();
+        self.host.update(msg, recipient, || ctx.stop())
+    }
+}
+
+impl Handler<Invocation> for PooledActorHost {
+    type Result = InvocationResponse;
+
+    fn handle(&mut self, msg: Invocation, _ctx: &mut Self::Context) -> Self::Result {
+        self.host.invoke(msg)
+    }
+}
+
+impl TaskActorHost {
+    // Checks the actor's module and creates an instance of it on a thread of the blocking pool,
+    // so that a module that can't run is refused when it starts rather than when it's invoked.
+    // Then joins the actor to the message bus
+    fn load(
+        &mut self,
+        msg: Initialize,
+        recipient: Recipient<Invocation>,
+    ) -> ResponseActFuture<Self, Result<String>> {
+        let claims = match check_actor(&msg.actor_bytes, &msg.wasm_features) {
+            Ok(claims) => claims,
+            Err(e) => return Box::pin(async move { Err(e) }.into_actor(self)),
+        };
+        let module = TaskModule::new(msg.actor_bytes.clone(), &msg.signing_seed, &claims);
+        Box::pin(
+            load_task(module.clone(), msg.host_id.to_string(), claims, recipient)
+                .into_actor(self)
+                .map(move |res, act, ctx| match res {
+                    Ok(claims) => {
+                        if let Some(previous) = act.module.replace(module) {
+                            previous.retire();
+                        }
+                        Ok(act.host.install(msg, claims, None))
+                    }
+                    Err(e) => {
+                        module.retire();
+                        ctx.stop();
+                        Err(e)
+                    }
+                }),
+        )
+    }
+}
+
+impl Actor for TaskActorHost {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        info!("Actor started");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(ref module) = self.module {
+            module.retire();
+        }
+    }
+}
+
+impl Handler<Initialize> for TaskActorHost {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: Initialize, ctx: &mut Self::Context) -> Self::Result {
+        let image_ref = msg.image_ref.clone();
+        let recipient = ctx.address().recipient();
+        Box::pin(self.load(msg, recipient).map(move |res, act, _ctx| {
+            let actor = res?;
+            let pe = act.host.started_event(&actor, image_ref);
+            let host_id = act.host.state.as_ref().unwrap().host_id.to_string();
+            ControlInterface::from_hostlocal_registry(&host_id).do_send(pe);
+            Ok(())
+        }))
+    }
+}
+
+impl Handler<LiveUpdate> for TaskActorHost {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: LiveUpdate, ctx: &mut Self::Context) -> Self::Result {
+        let (init, old_revision, new_revision) = match self.host.begin_update(msg) {
+            Ok(update) => update,
+            Err(e) => return Box::pin(async move { Err(e) }.into_actor(self)),
+        };
+        let host_id = init.host_id.to_string();
+        let recipient = ctx.address().recipient();
+        Box::pin(self.load(init, recipient).map(move |res, _act, _ctx| {
+            update_completed(&host_id, res?, old_revision, new_revision);
+            Ok(())
+        }))
+    }
+}
+
+impl Handler<Invocation> for TaskActorHost {
+    type Result = ResponseActFuture<Self, InvocationResponse>;
+
+    fn handle(&mut self, msg: Invocation, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(resp) = self.host.before_invoke(&msg) {
+            return Box::pin(async move { resp }.into_actor(self));
         }
+        let module = self.module.clone().unwrap();
+        let host_id = self.host.state.as_ref().unwrap().host_id.to_string();
+        let inv = msg.clone();
+        let started = crate::clock::monotonic(&host_id);
+        Box::pin(
+            async move {
+                let res = web::block(move || module.run(&host_id, &inv)).await;
+                res.map_err(|e| e.to_string())
+            }
+            .into_actor(self)
+            .map(move |result, act, _ctx| {
+                let host_id = act.host.state.as_ref().unwrap().host_id.to_string();
+                let elapsed = crate::clock::monotonic(&host_id)
+                    .checked_sub(started)
+                    .unwrap_or_default();
+                act.host.after_invoke(&msg, result, elapsed)
+            }),
+        )
     }
 }
 
-fn perform_initialization(
-    me: &mut ActorHost,
-    ctx: &mut SyncContext<ActorHost>,
-    msg: Initialize,
-) -> Result<String> {
-    let buf = msg.actor_bytes.clone();
-    let actor = WasccActor::from_slice(&buf)?;
+// Creates the first instance of a task model actor's module, then joins the actor to the bus
+async fn load_task(
+    module: TaskModule,
+    host_id: String,
+    claims: Claims<wascap::jwt::Actor>,
+    recipient: Recipient<Invocation>,
+) -> Result<Claims<wascap::jwt::Actor>> {
+    web::block(move || {
+        let guest =
+            new_guest(&module.bytes, &module.seed, &module.claims).map_err(|e| e.to_string())?;
+        TASK_INSTANCES.with(|i| {
+            i.borrow_mut().insert(module.generation, guest);
+        });
+        Ok::<_, String>(())
+    })
+    .await
+    .map_err(|e| format!("Failed to create a WebAssembly host: {}", e))?;
+    join_bus(host_id, claims.clone(), recipient).await?;
+    Ok(claims)
+}
+
+// Checks an actor's claims and the WebAssembly features its module uses, returning its claims
+fn check_actor(bytes: &[u8], wasm_features: &WasmFeatures) -> Result<Claims<wascap::jwt::Actor>> {
+    let actor = WasccActor::from_slice(bytes)?;
     let c = actor.token.claims.clone();
     let jwt = actor.token.jwt.to_string();
 
@@ -172,86 +701,131 @@ fn perform_initialization(
     // has a verified signature, etc.
     let tv = wascap::jwt::validate_token::<wascap::jwt::Actor>(&jwt)?;
     assert_validation_result(&tv)?;
+    check_actor_features(bytes, wasm_features)?;
+    Ok(c)
+}
 
+// Creates a WebAssembly host running an actor's module, whose calls out of the module go to the
+// host on the actor's behalf
+fn new_guest(bytes: &[u8], seed: &str, claims: &Claims<wascap::jwt::Actor>) -> Result<WapcHost> {
     #[cfg(feature = "wasmtime")]
-    let engine = wasmtime_provider::WasmtimeEngineProvider::new(&buf, None);
+    let engine = wasmtime_provider::WasmtimeEngineProvider::new(bytes, None);
     #[cfg(feature = "wasm3")]
-    let engine = wasm3_provider::Wasm3EngineProvider::new(&buf);
-
-    let c2 = c.clone();
-    let c3 = c.clone(); // TODO: I can't believe I have to do this to make the [censored] borrow checker happy
-    let seed = msg.signing_seed.to_string();
+    let engine = wasm3_provider::Wasm3EngineProvider::new(bytes);
 
-    let guest = WapcHost::new(Box::new(engine), move |_id, bd, ns, op, payload| {
+    let seed = seed.to_string();
+    let c = claims.clone();
+    WapcHost::new(Box::new(engine), move |_id, bd, ns, op, payload| {
         crate::dispatch::wapc_host_callback(
             KeyPair::from_seed(&seed).unwrap(),
-            c2.clone(),
+            c.clone(),
             bd,
             ns,
             op,
             payload,
         )
-    });
+    })
+    .map_err(|_e| {
+        error!(
+            "Failed to create a WebAssembly host for actor {}",
+            claims.subject
+        );
+        "Failed to create a raw WebAssembly host".into()
+    })
+}
 
-    match guest {
-        Ok(g) => {
-            let c = c3.clone();
-            let entity = WasccEntity::Actor(c.subject.to_string());
-            let b = MessageBus::from_hostlocal_registry(&msg.host_id);
-            let b2 = b.clone();
-            let recipient = ctx.address().clone().recipient();
-            let _ = block_on(async move {
-                b.send(Subscribe {
-                    interest: entity,
-                    subscriber: recipient,
-                })
-                .await
-            });
-            if !advertise_claims(&c, &b2) {
-                ctx.stop();
-                return Err("Failed to advertise claims to message bus".into());
-            }
-            let hid = msg.host_id.to_string();
-
-            me.state = Some(State {
-                guest_module: g,
-                claims: c.clone(),
-                mw_chain: msg.mw_chain,
-                image_ref: msg.image_ref,
-                host_id: hid,
-                seed: msg.signing_seed,
-                can_update: msg.can_update,
-            });
+// Advertises an actor's claims and subscribes it to the message bus, after claiming the actor
+// for this host if it requires ordered delivery
+async fn join_bus(
+    host_id: String,
+    claims: Claims<wascap::jwt::Actor>,
+    recipient: Recipient<Invocation>,
+) -> Result<()> {
+    let b = MessageBus::from_hostlocal_registry(&host_id);
+    if is_ordered(&claims) {
+        b.send(ClaimOrderedActor {
+            actor: claims.subject.to_string(),
+        })
+        .await??;
+    }
+    // Claims are advertised before subscribing so that the bus knows whether the
+    // actor requires ordered delivery when it creates the lattice subscription
+    let entity = WasccEntity::Actor(claims.subject.to_string());
+    if let Err(e) = b.send(AdvertiseClaims { claims }).await {
+        error!("Actor failed to advertise claims to bus: {}", e);
+        return Err("Failed to advertise claims to message bus".into());
+    }
+    let _ = b
+        .send(Subscribe {
+            interest: entity,
+            subscriber: recipient,
+        })
+        .await;
+    Ok(())
+}
+
+/// Writes a snapshot of a failed invocation to disk, if the host has snapshots enabled, and
+/// publishes an event pointing at it
+fn capture_snapshot(state: &State, inv: &Invocation, error: &str) {
+    let config = match state.snapshots {
+        Some(ref c) => c,
+        None => return,
+    };
+    let snapshot = ExecutionSnapshot {
+        actor: state.claims.subject.to_string(),
+        image_ref: state.image_ref.clone(),
+        origin: inv.origin.url(),
+        operation: inv.operation.to_string(),
+        payload: inv.msg.clone(),
+        error: error.to_string(),
+        timestamp_ms: crate::clock::now_millis(&state.host_id),
+    };
+    match config.capture(snapshot) {
+        Ok((path, digest)) => {
             info!(
-                "Actor {} initialized",
-                &me.state.as_ref().unwrap().claims.subject
-            );
-            Ok(c.subject.to_string())
-        }
-        Err(_e) => {
-            error!(
-                "Failed to create a WebAssembly host for actor {}",
-                actor.token.claims.subject
+                "Captured execution snapshot for actor {} at {}",
+                state.claims.subject,
+                path.display()
             );
-            ctx.stop();
-            Err("Failed to create a raw WebAssembly host".into())
+            ControlInterface::from_hostlocal_registry(&state.host_id).do_send(PublishEvent {
+                event: ControlEvent::ActorSnapshotCaptured {
+                    actor: state.claims.subject.to_string(),
+                    operation: inv.operation.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                    digest,
+                },
+            });
         }
+        Err(e) => error!(
+            "Failed to capture execution snapshot for actor {}: {}",
+            state.claims.subject, e
+        ),
     }
 }
 
-fn advertise_claims(c: &Claims<wascap::jwt::Actor>, bus: &Addr<MessageBus>) -> bool {
-    let pc = AdvertiseClaims { claims: c.clone() };
-    block_on(async move {
-        if let Err(e) = bus.send(pc).await {
-            error!("Actor failed to advertise claims to bus: {}", e);
-            false
-        } else {
-            true
-        }
-    })
+/// Meters an invocation handled by the actor and hands the cost record to the host's billing
+/// sinks. The wapc engines don't meter fuel, so time and payload sizes are what's recorded
+fn record_cost(state: &State, inv: &Invocation, elapsed: Duration, resp: &InvocationResponse) {
+    if state.billing_sinks.is_empty() {
+        return;
+    }
+    let record = CostRecord {
+        host_id: state.host_id.to_string(),
+        actor: state.claims.subject.to_string(),
+        issuer: state.claims.issuer.to_string(),
+        labels: crate::labels::labels_for(&state.host_id, Some(&state.claims.subject)),
+        origin: inv.origin.url(),
+        operation: inv.operation.to_string(),
+        execution_time_us: elapsed.as_micros() as u64,
+        request_bytes: inv.msg.len() as u64,
+        response_bytes: resp.msg.len() as u64,
+        success: resp.error.is_none(),
+        timestamp_ms: crate::clock::now_millis(&state.host_id),
+    };
+    billing::emit(&state.billing_sinks, record);
 }
 
-fn assert_validation_result(tv: &TokenValidation) -> Result<()> {
+pub(crate) fn assert_validation_result(tv: &TokenValidation) -> Result<()> {
     if tv.cannot_use_yet {
         error!(
             "Claims validation failure: Cannot be used {}",
@@ -302,63 +876,3 @@ fn validate_update(
         .rev
         .unwrap_or(0) as u32)
 }
-
-impl Actor for ActorHost {
-    type Context = SyncContext<Self>;
-
-    fn started(&mut self, _ctx: &mut Self::Context) {
-        info!("Actor started");
-    }
-
-    fn stopped(&mut self, _ctx: &mut Self::Context) {
-        // NOTE: do not attempt to log asynchronously in a stopped function,
-        // resources (including stdout) may not be available
-    }
-}
-
-impl Handler<Invocation> for ActorHost {
-    type Result = InvocationResponse;
-
-    /// Receives an invocation from any source. This will execute the full pre-exec
-    /// middleware chain, perform the requested operation, and then perform the full
-    /// post-exec middleware chain, assuming no errors indicate a pre-emptive halt
-    fn handle(&mut self, msg: Invocation, _ctx: &mut Self::Context) -> Self::Result {
-        let state = self.state.as_ref().unwrap();
-
-        trace!(
-            "Actor Invocation - From {} to {}: {}",
-            msg.origin.url(),
-            msg.target.url(),
-            msg.operation
-        );
-
-        if let WasccEntity::Actor(_) = msg.target {
-            if run_actor_pre_invoke(&msg, &state.mw_chain).is_err() {
-                return InvocationResponse::error(
-                    &msg,
-                    "Pre-invoke middleware execution failure on actor",
-                );
-            }
-            match state.guest_module.call(&msg.operation, &msg.msg) {
-                Ok(v) => {
-                    let resp = InvocationResponse::success(&msg, v);
-                    match run_actor_post_invoke(resp, &state.mw_chain) {
-                        Ok(r) => r,
-                        Err(e) => InvocationResponse::error(
-                            &msg,
-                            &format!("Post-invoke middleware execution failure on actor: {}", e),
-                        ),
-                    }
-                }
-                Err(e) => {
-                    InvocationResponse::error(&msg, &format!("Failed to invoke actor: {}", e))
-                }
-            }
-        } else {
-            InvocationResponse::error(
-                &msg,
-                "Actor received invocation that should have been delivered to a provider",
-            )
-        }
-    }
-}
@@ -1,5 +1,8 @@
 mod actor_host;
 mod wascc_actor;
 
-pub(crate) use actor_host::{ActorHost, Initialize, LiveUpdate};
+pub(crate) use actor_host::{
+    assert_validation_result, ActorAddr, ActorHost, Initialize, LiveUpdate, PooledActorHost,
+    TaskActorHost,
+};
 pub(crate) use wascc_actor::WasccActor;
@@ -0,0 +1,292 @@
+// Once an actor or provider is stopped, the host forgets most of what it knew about it, which
+// leaves nothing to answer an auditor asking what ran in the lattice last month. A host can be
+// given an archive store: whenever an actor or provider is stopped through the host (or its
+// control interface), the host writes a record of it to the store, holding the actor's claims,
+// the links (and their configuration) the entity had, and the invocation counts and latency the
+// host measured for it. Records older than the archive's retention are pruned each time a new
+// one is written. Entities still running when the host itself stops are not archived.
+
+use crate::capability::link_cache::{LinkKey, LinkValues};
+use crate::metrics::InvocationMetrics;
+use crate::{Result, WasccEntity};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use wascap::jwt::{Actor, Claims};
+
+/// What a host knew about an actor or provider when it was stopped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedEntity {
+    /// The host on which the entity ran
+    pub host_id: String,
+    /// The public key of the actor or provider
+    pub id: String,
+    /// The contract of a provider, `None` for an actor
+    pub contract_id: Option<String>,
+    /// The link name of a provider, `None` for an actor
+    pub link_name: Option<String>,
+    /// The claims of an actor. The host doesn't keep the claims of providers
+    pub claims: Option<Claims<Actor>>,
+    /// The links of an actor, or the links a provider served
+    pub links: Vec<ArchivedLink>,
+    pub invocations: u64,
+    pub errors: u64,
+    /// The 99th percentile latency of the entity's most recent invocations, in microseconds
+    pub p99_us: u64,
+    /// The time at which the entity was stopped, in milliseconds since the epoch
+    pub stopped_at_ms: u64,
+}
+
+/// A link held by an archived entity, along with its configuration values
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchivedLink {
+    pub actor: String,
+    pub provider_id: String,
+    pub contract_id: String,
+    pub link_name: String,
+    pub values: HashMap<String, String>,
+}
+
+/// A store for the records of stopped entities. Records are written on the host's message bus,
+/// so stores should write them quickly
+pub trait ArchiveStore: CloneArchiveStore + Sync + Send {
+    /// Adds a record to the store
+    fn put(&self, entity: &ArchivedEntity) -> Result<()>;
+    /// Every record in the store
+    fn list(&self) -> Result<Vec<ArchivedEntity>>;
+    /// Removes the records of entities stopped before the given time, in milliseconds since the
+    /// epoch
+    fn prune(&self, before_ms: u64) -> Result<()>;
+}
+
+#[doc(hidden)]
+pub trait CloneArchiveStore {
+    fn clone_store(&self) -> Box<dyn ArchiveStore>;
+}
+
+impl<T> CloneArchiveStore for T
+where
+    T: ArchiveStore + Clone + 'static,
+{
+    fn clone_store(&self) -> Box<dyn ArchiveStore> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ArchiveStore> {
+    fn clone(&self) -> Self {
+        self.clone_store()
+    }
+}
+
+/// Keeps each record as a JSON file in a directory, named for the time the entity was stopped
+#[derive(Clone)]
+pub struct FileArchiveStore {
+    dir: PathBuf,
+}
+
+impl FileArchiveStore {
+    pub fn new(dir: impl AsRef<Path>) -> FileArchiveStore {
+        FileArchiveStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn records(&self) -> Result<Vec<(PathBuf, u64)>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut records = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let stopped = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(stopped_at);
+            if let Some(stopped) = stopped {
+                records.push((path, stopped));
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl ArchiveStore for FileArchiveStore {
+    fn put(&self, entity: &ArchivedEntity) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let name = format!(
+            "{}-{}-{}.json",
+            entity.stopped_at_ms,
+            entity.id,
+            entity.link_name.as_deref().unwrap_or("actor")
+        );
+        std::fs::write(self.dir.join(name), serde_json::to_vec(entity)?)?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<ArchivedEntity>> {
+        self.records()?
+            .into_iter()
+            .map(|(path, _)| Ok(serde_json::from_slice(&std::fs::read(path)?)?))
+            .collect()
+    }
+
+    fn prune(&self, before_ms: u64) -> Result<()> {
+        for (path, stopped) in self.records()? {
+            if stopped < before_ms {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The time at which the entity of an archive file was stopped, from the file's name
+fn stopped_at(file_name: &str) -> Option<u64> {
+    if !file_name.ends_with(".json") {
+        return None;
+    }
+    file_name.split('-').next()?.parse().ok()
+}
+
+/// An archive store and how long it keeps records. See `HostBuilder::with_archive`
+#[derive(Clone)]
+pub(crate) struct Archive {
+    pub store: Box<dyn ArchiveStore>,
+    pub retention: Duration,
+}
+
+impl std::fmt::Debug for Archive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Archive")
+            .field("retention", &self.retention)
+            .finish()
+    }
+}
+
+impl Archive {
+    // The time before which records have outlived the retention
+    fn cutoff(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.retention.as_millis() as u64)
+    }
+
+    /// Writes the record of a stopped entity, pruning records past the retention
+    pub fn keep(&self, entity: ArchivedEntity) {
+        let cutoff = self.cutoff(entity.stopped_at_ms);
+        if let Err(e) = self
+            .store
+            .put(&entity)
+            .and_then(|_| self.store.prune(cutoff))
+        {
+            error!("Failed to archive stopped entity {}: {}", entity.id, e);
+        }
+    }
+
+    /// The records still within the retention, oldest first
+    pub fn entities(&self, now_ms: u64) -> Result<Vec<ArchivedEntity>> {
+        let cutoff = self.cutoff(now_ms);
+        let mut entities: Vec<ArchivedEntity> = self
+            .store
+            .list()?
+            .into_iter()
+            .filter(|e| e.stopped_at_ms >= cutoff)
+            .collect();
+        entities.sort_by_key(|e| e.stopped_at_ms);
+        Ok(entities)
+    }
+}
+
+/// Builds the record of a stopped entity from what the message bus knows about it
+pub(crate) fn record(
+    host_id: &str,
+    entity: &WasccEntity,
+    claims: Option<Claims<Actor>>,
+    links: &HashMap<LinkKey, LinkValues>,
+    metrics: Vec<&InvocationMetrics>,
+    now: Duration,
+    stopped_at_ms: u64,
+) -> ArchivedEntity {
+    let (id, contract_id, link_name) = match entity {
+        WasccEntity::Actor(a) => (a.to_string(), None, None),
+        WasccEntity::Capability {
+            id,
+            contract_id,
+            link_name,
+        } => (
+            id.to_string(),
+            Some(contract_id.to_string()),
+            Some(link_name.to_string()),
+        ),
+    };
+    ArchivedEntity {
+        host_id: host_id.to_string(),
+        links: links
+            .iter()
+            .map(|(k, v)| ArchivedLink {
+                actor: k.actor.to_string(),
+                provider_id: v.provider_id.to_string(),
+                contract_id: k.contract_id.to_string(),
+                link_name: k.link_name.to_string(),
+                values: v.values.clone(),
+            })
+            .collect(),
+        invocations: metrics.iter().map(|m| m.invocations).sum(),
+        errors: metrics.iter().map(|m| m.errors).sum(),
+        p99_us: metrics
+            .iter()
+            .map(|m| m.p99(now).as_micros() as u64)
+            .max()
+            .unwrap_or(0),
+        id,
+        contract_id,
+        link_name,
+        claims,
+        stopped_at_ms,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Archive, ArchiveStore, ArchivedEntity, FileArchiveStore};
+    use std::time::Duration;
+
+    fn entity(id: &str, stopped_at_ms: u64) -> ArchivedEntity {
+        ArchivedEntity {
+            host_id: "NHOST".to_string(),
+            id: id.to_string(),
+            contract_id: None,
+            link_name: None,
+            claims: None,
+            links: vec![],
+            invocations: 3,
+            errors: 1,
+            p99_us: 1200,
+            stopped_at_ms,
+        }
+    }
+
+    #[test]
+    fn archive_keeps_records_within_retention() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let archive = Archive {
+            store: Box::new(FileArchiveStore::new(&dir)),
+            retention: Duration::from_secs(60),
+        };
+        archive.keep(entity("MOLD", 1_000));
+        archive.keep(entity("MNEWER", 50_000));
+        assert_eq!(2, archive.store.list().unwrap().len());
+
+        // Writing a record prunes those past the retention
+        archive.keep(entity("MNEWEST", 70_000));
+        let ids: Vec<String> = archive
+            .entities(70_000)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(vec!["MNEWER", "MNEWEST"], ids);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -16,6 +16,7 @@ pub trait Authorizer: CloneAuthorizer + Sync + Send {
     /// This check will be performed for _every_ invocation that has passed the base capability check,
     /// including the operation that occurs during `bind_actor`. Developers should be aware of this because
     /// if `set_authorizer` is done _after_ actor link, it could potentially allow an unauthorized link.
+    /// The invocation's baggage is available from [current_baggage](crate::current_baggage) during this check.
     fn can_invoke(&self, claims: &Claims<Actor>, target: &WasccEntity, operation: &str) -> bool;
 }
 
@@ -70,6 +71,7 @@ pub(crate) fn authorize_invocation(
     claims_cache: &HashMap<String, Claims<wascap::jwt::Actor>>,
 ) -> Result<()> {
     let _ = inv.validate_antiforgery()?; // Fail authorization if the invocation isn't properly signed
+    crate::baggage::validate(&inv.baggage)?;
 
     if let WasccEntity::Actor(ref actor_key) = &inv.origin {
         if let Some(c) = claims_cache.get(actor_key) {
@@ -80,7 +82,10 @@ pub(crate) fn authorize_invocation(
                     true
                 };
                 if allowed {
-                    if authorizer.can_invoke(&c, &inv.target, &inv.operation) {
+                    let permitted = crate::baggage::with_baggage(inv.baggage.clone(), || {
+                        authorizer.can_invoke(&c, &inv.target, &inv.operation)
+                    });
+                    if permitted {
                         Ok(())
                     } else {
                         Err("Authorization denied - authorizer rejected invocation".into())
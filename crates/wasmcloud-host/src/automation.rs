@@ -0,0 +1,412 @@
+// Operators end up writing the same glue around every host: watch the control events, restart
+// the provider that went unhealthy, page someone if it keeps happening. A host can run that glue
+// itself. It is given automation rules, one per line, each of which names an event, optionally
+// narrows it down by the event's fields, and says what to do when it happens:
+//
+//     on ProviderUnhealthy where contract_id=wascc:keyvalue do restart-provider
+//     on ProviderUnhealthy where contract_id=wascc:keyvalue after 3 within 10m do webhook https://pager.example.com/hook
+//     on ActorInvocationFailed where actor=MB2ZQ... do invoke MCPOLICY...
+//
+// A rule with `after <n>` acts on the n-th time it matches (for the same actor or provider)
+// within its `within` window, or since it last acted if it has no window, and then starts
+// counting again. The actions are:
+//
+// * `restart-provider` stops the provider the event is about and starts it again from the image
+//   reference it was started from. Providers not started from a registry can't be restarted
+// * `stop-actor` stops the actor the event is about
+// * `webhook <url>` posts the event to the URL, in the host's event format
+// * `invoke <actor>` invokes the actor with `HandleLatticeEvent` and the event as JSON. Rules too
+//   involved for this language can be written as a policy actor and handed their events this way
+//
+// Rules are evaluated as the host publishes its events, whether or not its control interface is
+// enabled, and their actions run in the background. Rules are checked when the host starts, and
+// a host with a rule it can't parse fails to start.
+
+use crate::control_interface::cloudevents::EventFormat;
+use crate::dispatch::{Invocation, InvocationResponse, WasccEntity};
+use crate::hlreg::HostLocalSystemService;
+use crate::host_controller::{
+    HostController, QueryHostInventory, StartProvider, StopActor, StopProvider,
+};
+use crate::messagebus::MessageBus;
+use crate::{PublishedEvent, Result, SYSTEM_ACTOR};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use wascap::prelude::KeyPair;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Keyed by host ID
+static ENGINES: Lazy<RwLock<HashMap<String, Engine>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// What a rule does when it fires
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Action {
+    RestartProvider,
+    StopActor,
+    Webhook(String),
+    Invoke(String),
+}
+
+/// A parsed automation rule
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Rule {
+    text: String,
+    event: String,
+    conditions: Vec<(String, String)>,
+    threshold: usize,
+    window: Option<Duration>,
+    action: Action,
+}
+
+impl Rule {
+    fn parse(text: &str) -> Result<Rule> {
+        let bad = |reason: &str| format!("Bad automation rule '{}': {}", text, reason);
+        let mut tokens = text.split_whitespace();
+        if tokens.next() != Some("on") {
+            return Err(bad("rules start with 'on <event>'").into());
+        }
+        let event = tokens.next().ok_or_else(|| bad("no event named"))?;
+        let mut conditions = Vec::new();
+        let mut narrowing = false;
+        let mut threshold = 1;
+        let mut window = None;
+        loop {
+            match tokens.next() {
+                Some("where") => narrowing = true,
+                Some("after") => {
+                    threshold = tokens
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| bad("'after' needs a count above zero"))?;
+                }
+                Some("within") => {
+                    window = Some(
+                        tokens
+                            .next()
+                            .and_then(parse_duration)
+                            .ok_or_else(|| bad("'within' needs a duration such as 30s or 10m"))?,
+                    );
+                }
+                Some("do") => break,
+                Some(condition) => match condition.find('=') {
+                    Some(i) if narrowing && i > 0 => {
+                        conditions
+                            .push((condition[..i].to_string(), condition[i + 1..].to_string()));
+                    }
+                    _ => return Err(bad(&format!("unexpected '{}'", condition)).into()),
+                },
+                None => return Err(bad("no action given with 'do'").into()),
+            }
+        }
+        let action = match (tokens.next(), tokens.next()) {
+            (Some("restart-provider"), None) => Action::RestartProvider,
+            (Some("stop-actor"), None) => Action::StopActor,
+            (Some("webhook"), Some(url)) => Action::Webhook(url.to_string()),
+            (Some("invoke"), Some(actor)) => Action::Invoke(actor.to_string()),
+            _ => return Err(bad("unknown action").into()),
+        };
+        if tokens.next().is_some() {
+            return Err(bad("unexpected text after the action").into());
+        }
+        Ok(Rule {
+            text: text.trim().to_string(),
+            event: event.to_string(),
+            conditions,
+            threshold,
+            window,
+            action,
+        })
+    }
+
+    // Whether the event is the one the rule is about, with the field values it asks for
+    fn matches(&self, event: &PublishedEvent) -> bool {
+        if event.event.name() != self.event {
+            return false;
+        }
+        let fields = match serde_json::to_value(&event.event) {
+            Ok(serde_json::Value::Object(o)) => o.into_iter().next().map(|(_, v)| v),
+            _ => None,
+        };
+        self.conditions.iter().all(
+            |(key, value)| match fields.as_ref().and_then(|f| f.get(key)) {
+                Some(serde_json::Value::String(s)) => s == value,
+                Some(v) => &v.to_string() == value,
+                None => false,
+            },
+        )
+    }
+}
+
+/// Parses the automation rules in the given text, one per line. Blank lines and lines starting
+/// with `#` are ignored
+pub(crate) fn parse_rules(text: &str) -> Result<Vec<Rule>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(Rule::parse)
+        .collect()
+}
+
+// Durations are written as a number followed by `s`, `m` or `h`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let unit = text.chars().last()?;
+    let n: u64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        's' => Some(Duration::from_secs(n)),
+        'm' => Some(Duration::from_secs(n * 60)),
+        'h' => Some(Duration::from_secs(n * 3600)),
+        _ => None,
+    }
+}
+
+// Records a match at the given time, returning whether the rule has now matched often enough
+// within its window to act. Acting clears the count
+fn record_match(
+    times: &mut VecDeque<u64>,
+    now_ms: u64,
+    threshold: usize,
+    window: Option<Duration>,
+) -> bool {
+    if let Some(window) = window {
+        let since = now_ms.saturating_sub(window.as_millis() as u64);
+        while times.front().map_or(false, |t| *t < since) {
+            times.pop_front();
+        }
+    }
+    times.push_back(now_ms);
+    if times.len() >= threshold {
+        times.clear();
+        true
+    } else {
+        false
+    }
+}
+
+struct Engine {
+    rules: Vec<Rule>,
+    // The times each rule matched, by the actor or provider the event was about
+    matches: Vec<HashMap<String, VecDeque<u64>>>,
+    allow_latest: bool,
+    event_format: EventFormat,
+}
+
+/// An action to run because a rule fired
+pub(crate) struct Triggered {
+    rule: String,
+    action: Action,
+    event: PublishedEvent,
+    allow_latest: bool,
+    event_format: EventFormat,
+}
+
+pub(crate) fn register(
+    host_id: &str,
+    rules: Vec<Rule>,
+    allow_latest: bool,
+    event_format: EventFormat,
+) {
+    if rules.is_empty() {
+        return;
+    }
+    ENGINES.write().insert(
+        host_id.to_string(),
+        Engine {
+            matches: vec![HashMap::new(); rules.len()],
+            rules,
+            allow_latest,
+            event_format,
+        },
+    );
+}
+
+pub(crate) fn clear(host_id: &str) {
+    ENGINES.write().remove(host_id);
+}
+
+/// The actions of the host's rules that fire on the event
+pub(crate) fn triggered(host_id: &str, event: &PublishedEvent) -> Vec<Triggered> {
+    let mut engines = ENGINES.write();
+    let engine = match engines.get_mut(host_id) {
+        Some(e) => e,
+        None => return vec![],
+    };
+    let now = crate::clock::now_millis(host_id);
+    let subject = event.event.subject().unwrap_or_default().to_string();
+    let mut fired = Vec::new();
+    for (rule, matches) in engine.rules.iter().zip(engine.matches.iter_mut()) {
+        if !rule.matches(event) {
+            continue;
+        }
+        let times = matches.entry(subject.to_string()).or_default();
+        if record_match(times, now, rule.threshold, rule.window) {
+            fired.push(Triggered {
+                rule: rule.text.to_string(),
+                action: rule.action.clone(),
+                event: event.clone(),
+                allow_latest: engine.allow_latest,
+                event_format: engine.event_format,
+            });
+        }
+    }
+    fired
+}
+
+/// Runs the action of a rule that fired, logging its failure
+pub(crate) async fn run(kp: KeyPair, triggered: Triggered) {
+    info!("Automation rule fired: {}", triggered.rule);
+    if let Err(e) = perform(&kp, &triggered).await {
+        error!("Automation rule '{}' failed: {}", triggered.rule, e);
+    }
+}
+
+async fn perform(kp: &KeyPair, triggered: &Triggered) -> Result<()> {
+    let host_id = kp.public_key();
+    let hc = HostController::from_hostlocal_registry(&host_id);
+    let fields = event_fields(&triggered.event);
+    let field = |name: &str| -> Result<String> {
+        fields
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("The event has no '{}' field", name).into())
+    };
+    match triggered.action {
+        Action::RestartProvider => {
+            let (provider_id, link_name) = (field("provider_id")?, field("link_name")?);
+            let image_ref = hc
+                .send(QueryHostInventory)
+                .await?
+                .providers
+                .into_iter()
+                .find(|p| p.id == provider_id && p.link_name == link_name)
+                .and_then(|p| p.image_ref)
+                .ok_or_else(|| {
+                    format!(
+                        "Provider {} ({}) wasn't started from a registry, so it can't be restarted",
+                        provider_id, link_name
+                    )
+                })?;
+            hc.send(StopProvider {
+                provider_ref: provider_id,
+                link_name: link_name.to_string(),
+                contract_id: field("contract_id")?,
+            })
+            .await?;
+            let provider = crate::oci::fetch_provider(
+                &host_id,
+                &image_ref,
+                triggered.allow_latest,
+                Some(link_name),
+            )
+            .await?;
+            hc.send(StartProvider {
+                provider,
+                image_ref: Some(image_ref),
+            })
+            .await?
+        }
+        Action::StopActor => Ok(hc
+            .send(StopActor {
+                actor_ref: field("actor")?,
+            })
+            .await?),
+        Action::Webhook(ref url) => {
+            let res = reqwest::Client::new()
+                .post(url)
+                .header("Content-Type", triggered.event_format.content_type())
+                .timeout(WEBHOOK_TIMEOUT)
+                .body(triggered.event_format.encode(&triggered.event))
+                .send()
+                .await?;
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("Webhook {} returned {}", url, res.status()).into())
+            }
+        }
+        Action::Invoke(ref actor) => {
+            let inv = Invocation::new(
+                kp,
+                WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                WasccEntity::Actor(actor.to_string()),
+                crate::system_actor::OP_HANDLE_LATTICE_EVENT,
+                serde_json::to_vec(&triggered.event)?,
+            );
+            let ir: InvocationResponse = MessageBus::from_hostlocal_registry(&host_id)
+                .send(inv)
+                .await?;
+            match ir.error {
+                Some(e) => Err(format!("Policy actor {} failed: {}", actor, e).into()),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+// The event's fields with a string value
+fn event_fields(event: &PublishedEvent) -> HashMap<String, String> {
+    match serde_json::to_value(&event.event) {
+        Ok(serde_json::Value::Object(o)) => o
+            .into_iter()
+            .next()
+            .and_then(|(_, v)| match v {
+                serde_json::Value::Object(fields) => Some(
+                    fields
+                        .into_iter()
+                        .filter_map(|(k, v)| v.as_str().map(|s| (k, s.to_string())))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        _ => HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_rules, record_match, Action};
+    use crate::ControlEvent;
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    #[test]
+    fn rules_parse_match_and_count() {
+        let rules = parse_rules(
+            "# restart, then page if it keeps happening
+             on ProviderUnhealthy where contract_id=wascc:keyvalue do restart-provider
+             on ProviderUnhealthy where contract_id=wascc:keyvalue after 3 within 10m do webhook https://pager.example.com",
+        )
+        .unwrap();
+        assert_eq!(2, rules.len());
+        assert_eq!(Action::RestartProvider, rules[0].action);
+        assert_eq!(3, rules[1].threshold);
+        assert_eq!(Some(Duration::from_secs(600)), rules[1].window);
+        assert!(parse_rules("on ProviderUnhealthy do reboot").is_err());
+        assert!(parse_rules("when ProviderUnhealthy do stop-actor").is_err());
+
+        let unhealthy = |contract_id: &str| {
+            ControlEvent::ProviderUnhealthy {
+                contract_id: contract_id.to_string(),
+                link_name: "default".to_string(),
+                provider_id: "Vredis".to_string(),
+                message: "timed out".to_string(),
+            }
+            .into_published("Nhost")
+        };
+        assert!(rules[0].matches(&unhealthy("wascc:keyvalue")));
+        assert!(!rules[0].matches(&unhealthy("wascc:messaging")));
+
+        // The third match within the window acts; one that falls outside it doesn't count
+        let mut times = VecDeque::new();
+        let window = Some(Duration::from_secs(60));
+        assert!(!record_match(&mut times, 0, 3, window));
+        assert!(!record_match(&mut times, 70_000, 3, window));
+        assert!(!record_match(&mut times, 80_000, 3, window));
+        assert!(record_match(&mut times, 90_000, 3, window));
+        assert!(times.is_empty());
+    }
+}
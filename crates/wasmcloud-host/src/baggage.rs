@@ -0,0 +1,113 @@
+// Baggage is a small set of key-value pairs (a tenant ID, a request ID, and so on) carried by an
+// invocation and by every invocation made while handling it, so that values set where a request
+// enters the lattice are visible to every actor, provider, middleware, and authorizer the request
+// passes through. Baggage is independent of any tracing headers an actor or provider may use.
+//
+// Actors and capability providers handle an invocation synchronously on their own thread, so the
+// baggage of the invocation being handled is kept in a thread local. Invocations made from that
+// thread (host calls from an actor, or dispatches from a provider) pick it up from there.
+
+use crate::Result;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The maximum number of baggage items an invocation can carry
+pub const MAX_BAGGAGE_ITEMS: usize = 32;
+/// The maximum combined size, in bytes, of the keys and values of an invocation's baggage
+pub const MAX_BAGGAGE_BYTES: usize = 4096;
+
+thread_local! {
+    static CURRENT: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// The baggage of the invocation being handled on the current thread. Embedded capability
+/// providers and authorizers can use this to read the baggage of the invocation they were given
+pub fn current_baggage() -> HashMap<String, String> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Runs the given function with the given baggage as the current baggage, so that invocations
+/// dispatched by the function carry it. The previous baggage is restored afterward
+pub fn with_baggage<T>(baggage: HashMap<String, String>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<HashMap<String, String>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take().unwrap_or_default();
+            CURRENT.with(|c| *c.borrow_mut() = previous);
+        }
+    }
+    let _restore = Restore(Some(CURRENT.with(|c| c.replace(baggage))));
+    f()
+}
+
+/// Adds items to (or, with an empty value, removes items from) the current baggage. The change
+/// is rejected if the result would exceed the baggage limits
+pub(crate) fn merge_current(items: HashMap<String, String>) -> Result<()> {
+    CURRENT.with(|c| {
+        let mut merged = c.borrow().clone();
+        for (k, v) in items {
+            if v.is_empty() {
+                merged.remove(&k);
+            } else {
+                merged.insert(k, v);
+            }
+        }
+        validate(&merged)?;
+        *c.borrow_mut() = merged;
+        Ok(())
+    })
+}
+
+/// Ensures that baggage is within the limits on item count and size
+pub(crate) fn validate(baggage: &HashMap<String, String>) -> Result<()> {
+    if baggage.len() > MAX_BAGGAGE_ITEMS {
+        return Err(format!(
+            "Invocation baggage has {} items, the limit is {}",
+            baggage.len(),
+            MAX_BAGGAGE_ITEMS
+        )
+        .into());
+    }
+    let size: usize = baggage.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if size > MAX_BAGGAGE_BYTES {
+        return Err(format!(
+            "Invocation baggage is {} bytes, the limit is {}",
+            size, MAX_BAGGAGE_BYTES
+        )
+        .into());
+    }
+    if baggage.keys().any(|k| k.is_empty()) {
+        return Err("Invocation baggage keys cannot be empty".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current_baggage, merge_current, with_baggage, MAX_BAGGAGE_BYTES};
+    use std::collections::HashMap;
+
+    fn items(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn baggage_is_scoped_and_limited() {
+        assert!(current_baggage().is_empty());
+        with_baggage(items(&[("tenant", "acme")]), || {
+            merge_current(items(&[("request_id", "r-1")])).unwrap();
+            assert_eq!(
+                items(&[("tenant", "acme"), ("request_id", "r-1")]),
+                current_baggage()
+            );
+            let big = "x".repeat(MAX_BAGGAGE_BYTES);
+            assert!(merge_current(items(&[("blob", &big)])).is_err());
+            merge_current(items(&[("tenant", "")])).unwrap();
+            assert_eq!(items(&[("request_id", "r-1")]), current_baggage());
+        });
+        assert!(current_baggage().is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+// Every invocation handled by an actor can be metered and the resulting cost record handed to
+// one or more billing sinks. Records are attributed to both the actor and the issuer of its
+// claims, so that a multi-tenant lattice can charge (or show) each tenant for what its actors
+// consumed.
+//
+// Sinks are called on the actor's thread for every invocation, so the sinks here never touch the
+// file system or the network there: each hands its records over a channel to a writer thread of
+// its own, which keeps the file open (or the connection busy) between records.
+
+use crate::Result;
+use crossbeam_channel::Sender;
+use futures::executor::block_on;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The resources consumed by a single actor invocation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CostRecord {
+    /// The host on which the invocation ran
+    pub host_id: String,
+    /// The public key of the actor that handled the invocation
+    pub actor: String,
+    /// The public key of the account that issued the actor's claims
+    pub issuer: String,
+    /// The host's metric labels (see `HostBuilder::with_metric_label`), including the namespace
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// The URL of the entity that sent the invocation
+    pub origin: String,
+    pub operation: String,
+    /// Wall-clock time spent executing the actor, in microseconds
+    pub execution_time_us: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    pub success: bool,
+    /// The time at which the invocation completed, in milliseconds since the epoch
+    pub timestamp_ms: u64,
+}
+
+/// A billing sink receives a cost record for every invocation handled by an actor. Sinks are
+/// called on the actor's thread, so they should hand records off quickly rather than write them
+/// out there
+pub trait BillingSink: CloneBillingSink + Sync + Send {
+    fn record(&self, record: &CostRecord) -> Result<()>;
+}
+
+#[doc(hidden)]
+pub trait CloneBillingSink {
+    fn clone_sink(&self) -> Box<dyn BillingSink>;
+}
+
+impl<T> CloneBillingSink for T
+where
+    T: BillingSink + Clone + 'static,
+{
+    fn clone_sink(&self) -> Box<dyn BillingSink> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn BillingSink> {
+    fn clone(&self) -> Self {
+        self.clone_sink()
+    }
+}
+
+// Hands records to a thread that writes them out one at a time. The thread stops once every
+// clone of the sink that started it has been dropped
+#[derive(Clone)]
+struct Writer {
+    tx: Sender<CostRecord>,
+}
+
+impl Writer {
+    fn spawn(
+        name: &str,
+        mut write: impl FnMut(&CostRecord) -> Result<()> + Send + 'static,
+    ) -> Writer {
+        let (tx, rx) = crossbeam_channel::unbounded::<CostRecord>();
+        let name = name.to_string();
+        std::thread::spawn(move || {
+            for record in rx.iter() {
+                if let Err(e) = write(&record) {
+                    error!(
+                        "Billing sink {} failed to record invocation cost for actor {}: {}",
+                        name, record.actor, e
+                    );
+                }
+            }
+        });
+        Writer { tx }
+    }
+
+    fn send(&self, record: &CostRecord) -> Result<()> {
+        self.tx
+            .send(record.clone())
+            .map_err(|_| "Billing sink writer has stopped".into())
+    }
+}
+
+/// Appends each cost record to a file as a line of JSON. The file is opened once, by the sink's
+/// writer thread, and kept open
+#[derive(Clone)]
+pub struct FileBillingSink {
+    writer: Writer,
+}
+
+impl FileBillingSink {
+    pub fn new(path: impl AsRef<Path>) -> FileBillingSink {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let mut file: Option<File> = None;
+        let writer = Writer::spawn(&path.display().to_string(), move |record| {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            if file.is_none() {
+                file = Some(OpenOptions::new().create(true).append(true).open(&path)?);
+            }
+            // Each record is a single append, so lines from other writers don't interleave
+            file.as_mut().unwrap().write_all(&line)?;
+            Ok(())
+        });
+        FileBillingSink { writer }
+    }
+}
+
+impl BillingSink for FileBillingSink {
+    fn record(&self, record: &CostRecord) -> Result<()> {
+        self.writer.send(record)
+    }
+}
+
+/// Publishes each cost record as JSON on a NATS subject. Records are published by the sink's
+/// writer thread
+#[derive(Clone)]
+pub struct NatsBillingSink {
+    writer: Writer,
+}
+
+impl NatsBillingSink {
+    pub fn new(nc: nats::asynk::Connection, subject: &str) -> NatsBillingSink {
+        let subject = subject.to_string();
+        let name = subject.to_string();
+        let writer = Writer::spawn(&name, move |record| {
+            let payload = serde_json::to_vec(record)?;
+            block_on(nc.publish(&subject, payload))?;
+            Ok(())
+        });
+        NatsBillingSink { writer }
+    }
+}
+
+impl BillingSink for NatsBillingSink {
+    fn record(&self, record: &CostRecord) -> Result<()> {
+        self.writer.send(record)
+    }
+}
+
+pub(crate) fn emit(sinks: &[Box<dyn BillingSink>], record: CostRecord) {
+    for sink in sinks {
+        if let Err(e) = sink.record(&record) {
+            error!(
+                "Failed to record invocation cost for actor {}: {}",
+                record.actor, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BillingSink, CostRecord, FileBillingSink};
+
+    #[test]
+    fn file_sink_appends_json_lines() {
+        let path = std::env::temp_dir().join(format!("billing-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let record = CostRecord {
+            host_id: "NHOST".to_string(),
+            actor: "MACTOR".to_string(),
+            issuer: "ATENANT".to_string(),
+            labels: vec![("namespace".to_string(), "tenant-a".to_string())]
+                .into_iter()
+                .collect(),
+            origin: "wasmbus://wascc/http_server/default/VPROV".to_string(),
+            operation: "HandleRequest".to_string(),
+            execution_time_us: 1500,
+            request_bytes: 12,
+            response_bytes: 40,
+            success: true,
+            timestamp_ms: 0,
+        };
+        let sink = FileBillingSink::new(&path);
+        sink.record(&record).unwrap();
+        sink.record(&record).unwrap();
+
+        // The records are written out by the sink's writer thread
+        let mut contents = String::new();
+        for _ in 0..100 {
+            contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if contents.lines().count() == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let lines: Vec<CostRecord> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(vec![record.clone(), record], lines);
+        let _ = std::fs::remove_file(&path);
+    }
+}
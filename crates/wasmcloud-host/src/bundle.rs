@@ -0,0 +1,309 @@
+// For disconnected and edge deployments, a host can be pointed at an offline bundle instead of
+// OCI registries. A bundle is a directory holding actors and provider archives along with a
+// `bundle.json` index that maps each image reference to its file and SHA-256 digest. The index
+// is signed by whoever built the bundle, and a host only opens bundles signed by a key it trusts,
+// so the digests can't be swapped along with the artifacts. Every artifact is then verified: the
+// digest must match, an actor's claims must be valid and signed, and a provider archive must pass
+// its own signature and hash checks and contain a binary for this host. While a bundle is in use, image references are only ever
+// resolved from it, and a reference that isn't in the bundle is an error rather than a download.
+//
+// The bundle belongs to the host it was given to. Other hosts in the same process keep fetching
+// from registries, unless they were given a bundle of their own.
+
+use crate::capability::par::file_digest;
+use crate::hooks::bytes_digest;
+use crate::preload::verify_artifact;
+use crate::Result;
+use data_encoding::HEXUPPER;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wascap::prelude::KeyPair;
+
+/// The name of a bundle's index file
+pub const BUNDLE_INDEX: &str = "bundle.json";
+
+static BUNDLES: Lazy<RwLock<HashMap<String, Arc<OfflineBundle>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct BundleIndex {
+    artifacts: Vec<BundleEntry>,
+}
+
+// The contents of `bundle.json`: the index, serialized as JSON, and the signature over it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignedIndex {
+    issuer: String,
+    index: String,
+    signature: String,
+}
+
+impl SignedIndex {
+    fn sign(index: &BundleIndex, kp: &KeyPair) -> Result<SignedIndex> {
+        let index = serde_json::to_string(index)?;
+        let signature = HEXUPPER.encode(&kp.sign(index.as_bytes())?);
+        Ok(SignedIndex {
+            issuer: kp.public_key(),
+            index,
+            signature,
+        })
+    }
+
+    fn verify(&self, trusted_issuers: &[String]) -> Result<BundleIndex> {
+        if !trusted_issuers.contains(&self.issuer) {
+            return Err(format!("Bundle issuer {} is not a trusted issuer", self.issuer).into());
+        }
+        let sig = HEXUPPER.decode(self.signature.as_bytes())?;
+        KeyPair::from_public_key(&self.issuer)?
+            .verify(self.index.as_bytes(), &sig)
+            .map_err(|_| "Bundle index signature is invalid")?;
+        Ok(serde_json::from_str(&self.index)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct BundleEntry {
+    image_ref: String,
+    file: String,
+    sha256: String,
+}
+
+/// A verified set of actors and provider archives that a host can use in place of OCI
+/// registries
+#[derive(Debug, Clone)]
+pub struct OfflineBundle {
+    root: PathBuf,
+    artifacts: HashMap<String, BundleEntry>,
+}
+
+impl OfflineBundle {
+    /// Opens the bundle in the given directory, checking that its index was signed by one of the
+    /// trusted issuers (public keys) and verifying every artifact in it. Any artifact that is
+    /// missing, altered, or invalid causes the whole bundle to be rejected
+    pub fn open(root: impl AsRef<Path>, trusted_issuers: &[String]) -> Result<OfflineBundle> {
+        let root = root.as_ref().to_path_buf();
+        let index_path = root.join(BUNDLE_INDEX);
+        let signed: SignedIndex =
+            serde_json::from_slice(&std::fs::read(&index_path).map_err(|e| {
+                format!("Can't read bundle index {}: {}", index_path.display(), e)
+            })?)?;
+        let index = signed.verify(trusted_issuers)?;
+        let bundle = OfflineBundle {
+            root,
+            artifacts: index
+                .artifacts
+                .into_iter()
+                .map(|e| (e.image_ref.to_string(), e))
+                .collect(),
+        };
+        for image_ref in bundle.artifacts.keys() {
+            let bytes = bundle.read(image_ref)?;
+            verify_artifact(&bytes)
+                .map_err(|e| format!("Bundle artifact {} failed verification: {}", image_ref, e))?;
+        }
+        Ok(bundle)
+    }
+
+    /// Writes a bundle into the given directory from the supplied image references and their
+    /// bytes, for example as fetched on a connected machine. The index is signed with the given
+    /// key, whose public key the hosts using the bundle must trust
+    pub fn create(
+        root: impl AsRef<Path>,
+        artifacts: &[(String, Vec<u8>)],
+        signer: &KeyPair,
+    ) -> Result<()> {
+        let root = root.as_ref();
+        std::fs::create_dir_all(root)?;
+        let mut index = BundleIndex::default();
+        for (image_ref, bytes) in artifacts {
+            verify_artifact(bytes)
+                .map_err(|e| format!("Artifact {} failed verification: {}", image_ref, e))?;
+            let sha256 = bytes_digest(bytes);
+            let file = format!("{}.bin", sha256.to_lowercase());
+            std::fs::write(root.join(&file), bytes)?;
+            index.artifacts.push(BundleEntry {
+                image_ref: image_ref.to_string(),
+                file,
+                sha256,
+            });
+        }
+        let signed = SignedIndex::sign(&index, signer)?;
+        std::fs::write(root.join(BUNDLE_INDEX), serde_json::to_vec_pretty(&signed)?)?;
+        Ok(())
+    }
+
+    /// The image references contained in the bundle
+    pub fn image_refs(&self) -> Vec<String> {
+        let mut refs: Vec<_> = self.artifacts.keys().cloned().collect();
+        refs.sort();
+        refs
+    }
+
+    /// Reads an artifact from the bundle, checking its digest
+    fn entry(&self, image_ref: &str) -> Result<&BundleEntry> {
+        Ok(self.artifacts.get(image_ref).ok_or_else(|| {
+            format!(
+                "{} is not in the offline bundle at {}, and this host does not contact registries",
+                image_ref,
+                self.root.display()
+            )
+        })?)
+    }
+
+    pub(crate) fn read(&self, image_ref: &str) -> Result<Vec<u8>> {
+        let entry = self.entry(image_ref)?;
+        let bytes = std::fs::read(self.root.join(&entry.file))
+            .map_err(|e| format!("Can't read bundle artifact {}: {}", entry.file, e))?;
+        if bytes_digest(&bytes) != entry.sha256.to_uppercase() {
+            return Err(format!(
+                "Bundle artifact {} does not match the digest in the bundle index",
+                entry.file
+            )
+            .into());
+        }
+        Ok(bytes)
+    }
+
+    /// The path of an artifact in the bundle, after checking its digest without reading it into
+    /// memory
+    pub(crate) fn path(&self, image_ref: &str) -> Result<PathBuf> {
+        let entry = self.entry(image_ref)?;
+        let path = self.root.join(&entry.file);
+        let digest = file_digest(&path)
+            .map_err(|e| format!("Can't read bundle artifact {}: {}", entry.file, e))?;
+        if digest != entry.sha256.to_uppercase() {
+            return Err(format!(
+                "Bundle artifact {} does not match the digest in the bundle index",
+                entry.file
+            )
+            .into());
+        }
+        Ok(path)
+    }
+}
+
+pub(crate) fn activate(host_id: &str, bundle: OfflineBundle) {
+    info!(
+        "Using offline bundle at {} ({} artifacts), registries will not be contacted",
+        bundle.root.display(),
+        bundle.artifacts.len()
+    );
+    BUNDLES
+        .write()
+        .insert(host_id.to_string(), Arc::new(bundle));
+}
+
+pub(crate) fn deactivate(host_id: &str) {
+    BUNDLES.write().remove(host_id);
+}
+
+/// The offline bundle in use by the host, if any
+pub(crate) fn active_bundle(host_id: &str) -> Option<Arc<OfflineBundle>> {
+    BUNDLES.read().get(host_id).cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BundleEntry, BundleIndex, OfflineBundle, SignedIndex, BUNDLE_INDEX};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn artifacts_outside_the_bundle_or_altered_are_rejected() {
+        let root = std::env::temp_dir().join(format!("bundle-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.bin"), b"tampered").unwrap();
+        let entry = BundleEntry {
+            image_ref: "registry.local/echo:0.1.0".to_string(),
+            file: "a.bin".to_string(),
+            sha256: "00".to_string(),
+        };
+        let index = BundleIndex {
+            artifacts: vec![entry.clone()],
+        };
+        let kp = KeyPair::new_account();
+        let signed = SignedIndex::sign(&index, &kp).unwrap();
+        std::fs::write(
+            root.join(BUNDLE_INDEX),
+            serde_json::to_vec(&signed).unwrap(),
+        )
+        .unwrap();
+        assert!(OfflineBundle::open(&root, &[kp.public_key()]).is_err());
+
+        let mut artifacts = HashMap::new();
+        artifacts.insert(entry.image_ref.to_string(), entry);
+        let bundle = OfflineBundle {
+            root: root.clone(),
+            artifacts,
+        };
+        assert!(bundle
+            .read("registry.local/echo:0.1.0")
+            .unwrap_err()
+            .to_string()
+            .contains("does not match"));
+        assert!(bundle
+            .read("registry.local/other:0.1.0")
+            .unwrap_err()
+            .to_string()
+            .contains("is not in the offline bundle"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[actix_rt::test]
+    async fn other_hosts_keep_fetching_from_registries() {
+        let img = format!("registry.local/bundled-{}:0.1.0", std::process::id());
+        let cached = crate::oci::cached_file(&img);
+        std::fs::write(&cached, b"cached").unwrap();
+        super::activate(
+            "Nbundled",
+            OfflineBundle {
+                root: PathBuf::from("."),
+                artifacts: HashMap::new(),
+            },
+        );
+
+        let bundled = crate::oci::fetch_oci_bytes("Nbundled", &img, false).await;
+        assert!(bundled
+            .unwrap_err()
+            .to_string()
+            .contains("is not in the offline bundle"));
+        let other = crate::oci::fetch_oci_bytes("Nother", &img, false).await;
+        assert_eq!(b"cached".to_vec(), other.unwrap());
+
+        super::deactivate("Nbundled");
+        assert!(super::active_bundle("Nbundled").is_none());
+        let _ = std::fs::remove_file(&cached);
+    }
+
+    #[test]
+    fn indexes_must_be_signed_by_a_trusted_issuer() {
+        let kp = KeyPair::new_account();
+        let trusted = vec![kp.public_key()];
+        let index = BundleIndex {
+            artifacts: vec![BundleEntry {
+                image_ref: "registry.local/echo:0.1.0".to_string(),
+                file: "a.bin".to_string(),
+                sha256: "00".to_string(),
+            }],
+        };
+        let signed = SignedIndex::sign(&index, &kp).unwrap();
+        assert_eq!(index, signed.verify(&trusted).unwrap());
+
+        // A valid signature from a key that isn't trusted is refused
+        assert!(signed
+            .verify(&[KeyPair::new_account().public_key()])
+            .is_err());
+        let other = SignedIndex::sign(&index, &KeyPair::new_account()).unwrap();
+        assert!(other.verify(&trusted).is_err());
+
+        // So is an index whose digests were changed after it was signed
+        let mut altered = signed.clone();
+        altered.index = altered.index.replace("\"00\"", "\"01\"");
+        assert!(altered.verify(&trusted).is_err());
+    }
+}
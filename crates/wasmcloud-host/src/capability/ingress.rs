@@ -0,0 +1,230 @@
+// Options for the HTTP router that would otherwise need a reverse proxy in front of the host:
+// CORS, response compression, and TLS termination. All of them are set per route, in the values
+// of an actor's link to the router, alongside the route itself.
+//
+// TLS is terminated on the router's HTTPS port. Each route with a certificate and key is served
+// with that certificate for its `ROUTE_HOST` (chosen by SNI), and a certificate on a route without
+// a host is used for any other name. Certificates are read when the link is made, so a renewed
+// certificate is picked up by re-linking. Obtaining certificates via ACME is not supported.
+
+use crate::Result;
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{ClientHello, ResolvesServerCert};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+
+/// The link value holding a comma-separated list of the origins allowed to make cross-origin
+/// requests to a route, or `*` for any origin. CORS is disabled for routes without it
+pub const ROUTE_CORS_ORIGINS: &str = "ROUTE_CORS_ORIGINS";
+/// The link value holding the methods allowed in cross-origin requests
+pub const ROUTE_CORS_METHODS: &str = "ROUTE_CORS_METHODS";
+/// The link value holding the request headers allowed in cross-origin requests. Defaults to the
+/// headers the client asks for
+pub const ROUTE_CORS_HEADERS: &str = "ROUTE_CORS_HEADERS";
+/// The link value holding how long, in seconds, clients may cache a preflight response
+pub const ROUTE_CORS_MAX_AGE: &str = "ROUTE_CORS_MAX_AGE";
+/// The link value that, when `true`, compresses responses for clients that accept it
+pub const ROUTE_COMPRESS: &str = "ROUTE_COMPRESS";
+/// The link value holding the path to a PEM certificate chain for the route's host
+pub const ROUTE_TLS_CERT: &str = "ROUTE_TLS_CERT";
+/// The link value holding the path to the PEM private key (PKCS#8 or RSA) for `ROUTE_TLS_CERT`
+pub const ROUTE_TLS_KEY: &str = "ROUTE_TLS_KEY";
+
+const DEFAULT_CORS_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CorsPolicy {
+    origins: Vec<String>,
+    methods: String,
+    headers: Option<String>,
+    max_age: Option<u32>,
+}
+
+impl CorsPolicy {
+    pub(crate) fn from_values(values: &HashMap<String, String>) -> Option<CorsPolicy> {
+        let origins: Vec<String> = values
+            .get(ROUTE_CORS_ORIGINS)?
+            .split(',')
+            .map(|o| o.trim().trim_end_matches('/').to_lowercase())
+            .filter(|o| !o.is_empty())
+            .collect();
+        if origins.is_empty() {
+            return None;
+        }
+        Some(CorsPolicy {
+            origins,
+            methods: values
+                .get(ROUTE_CORS_METHODS)
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_CORS_METHODS.to_string()),
+            headers: values.get(ROUTE_CORS_HEADERS).cloned(),
+            max_age: values
+                .get(ROUTE_CORS_MAX_AGE)
+                .and_then(|a| a.trim().parse().ok()),
+        })
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+        self.origins.iter().any(|o| o == "*" || *o == origin)
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.methods
+            .split(',')
+            .any(|m| m.trim().eq_ignore_ascii_case(method))
+    }
+
+    /// The headers to add to a response for a request from the given origin, if it is allowed
+    pub(crate) fn response_headers(&self, origin: &str) -> Vec<(&'static str, String)> {
+        if !self.allows(origin) {
+            return vec![];
+        }
+        vec![
+            ("access-control-allow-origin", origin.to_string()),
+            ("vary", "Origin".to_string()),
+        ]
+    }
+
+    /// The headers of the response to a preflight request, or `None` if the request isn't allowed
+    pub(crate) fn preflight(
+        &self,
+        origin: &str,
+        method: &str,
+        requested_headers: Option<&str>,
+    ) -> Option<Vec<(&'static str, String)>> {
+        if !self.allows(origin) || !self.allows_method(method) {
+            return None;
+        }
+        let mut headers = self.response_headers(origin);
+        headers.push(("access-control-allow-methods", self.methods.to_string()));
+        if let Some(allowed) = self
+            .headers
+            .as_deref()
+            .or(requested_headers)
+            .filter(|h| !h.is_empty())
+        {
+            headers.push(("access-control-allow-headers", allowed.to_string()));
+        }
+        if let Some(age) = self.max_age {
+            headers.push(("access-control-max-age", age.to_string()));
+        }
+        Some(headers)
+    }
+}
+
+/// The certificates presented by the router's HTTPS listener, chosen by server name
+#[derive(Clone, Default)]
+pub(crate) struct TlsCertificates {
+    // By actor, the host name (if any) of its route and the certificate for it
+    certs: Arc<RwLock<HashMap<String, (Option<String>, CertifiedKey)>>>,
+}
+
+impl TlsCertificates {
+    /// Loads the certificate on an actor's link, if it has one
+    pub(crate) fn bind(
+        &self,
+        actor: &str,
+        host: Option<String>,
+        values: &HashMap<String, String>,
+    ) -> Result<()> {
+        let cert = match (values.get(ROUTE_TLS_CERT), values.get(ROUTE_TLS_KEY)) {
+            (Some(cert), Some(key)) => load_certified_key(cert, key)?,
+            (None, None) => {
+                self.remove(actor);
+                return Ok(());
+            }
+            _ => {
+                return Err(format!(
+                    "Both {} and {} are needed to serve a route over TLS",
+                    ROUTE_TLS_CERT, ROUTE_TLS_KEY
+                )
+                .into())
+            }
+        };
+        self.certs
+            .write()
+            .unwrap()
+            .insert(actor.to_string(), (host, cert));
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, actor: &str) {
+        self.certs.write().unwrap().remove(actor);
+    }
+
+    fn for_server_name(&self, name: Option<&str>) -> Option<CertifiedKey> {
+        let certs = self.certs.read().unwrap();
+        let name = name.map(|n| n.to_lowercase());
+        certs
+            .values()
+            .find(|(host, _)| host.is_some() && *host == name)
+            .or_else(|| certs.values().find(|(host, _)| host.is_none()))
+            .map(|(_, cert)| cert.clone())
+    }
+}
+
+impl ResolvesServerCert for TlsCertificates {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        self.for_server_name(client_hello.server_name().map(|n| n.into()))
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey> {
+    let chain = rustls::internal::pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| format!("Invalid certificate chain in {}", cert_path))?;
+    if chain.is_empty() {
+        return Err(format!("No certificates found in {}", cert_path).into());
+    }
+    let mut keys =
+        rustls::internal::pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .unwrap_or_default();
+    if keys.is_empty() {
+        keys =
+            rustls::internal::pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+                .unwrap_or_default();
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| format!("No private key found in {}", key_path))?;
+    let signing_key =
+        any_supported_type(key).map_err(|_| format!("Unsupported private key in {}", key_path))?;
+    Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CorsPolicy, ROUTE_CORS_MAX_AGE, ROUTE_CORS_METHODS, ROUTE_CORS_ORIGINS};
+    use std::collections::HashMap;
+
+    #[test]
+    fn cors_allows_only_configured_origins_and_methods() {
+        assert!(CorsPolicy::from_values(&HashMap::new()).is_none());
+
+        let mut values = HashMap::new();
+        values.insert(
+            ROUTE_CORS_ORIGINS.to_string(),
+            "https://app.example.com/, https://admin.example.com".to_string(),
+        );
+        values.insert(ROUTE_CORS_METHODS.to_string(), "GET, POST".to_string());
+        values.insert(ROUTE_CORS_MAX_AGE.to_string(), "600".to_string());
+        let cors = CorsPolicy::from_values(&values).unwrap();
+
+        assert_eq!(
+            "https://app.example.com",
+            cors.response_headers("https://app.example.com")[0].1
+        );
+        assert!(cors.response_headers("https://evil.example.com").is_empty());
+
+        let preflight = cors
+            .preflight("https://admin.example.com", "POST", Some("content-type"))
+            .unwrap();
+        assert!(preflight.contains(&("access-control-allow-headers", "content-type".to_string())));
+        assert!(preflight.contains(&("access-control-max-age", "600".to_string())));
+        assert!(cors
+            .preflight("https://admin.example.com", "DELETE", None)
+            .is_none());
+    }
+}
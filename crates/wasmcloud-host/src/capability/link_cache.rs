@@ -1,3 +1,4 @@
+use crate::links::{failover_providers, LINK_VALUE_LEASE_EXPIRES};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -28,12 +29,35 @@ pub(crate) struct LinkValues {
     pub values: HashMap<String, String>,
 }
 
+impl LinkValues {
+    /// The providers that can serve the link: the primary first, followed by any failover
+    /// providers in order of preference
+    pub fn providers(&self) -> Vec<String> {
+        let mut providers = vec![self.provider_id.to_string()];
+        providers.extend(failover_providers(&self.values));
+        providers
+    }
+
+    // Whether binding either link would give its providers the same values
+    fn binds_like(&self, other: &LinkValues) -> bool {
+        let unleased = |values: &HashMap<String, String>| {
+            let mut values = values.clone();
+            values.remove(LINK_VALUE_LEASE_EXPIRES);
+            values
+        };
+        self.provider_id == other.provider_id && unleased(&self.values) == unleased(&other.values)
+    }
+}
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct LinkCache {
     link_config: HashMap<LinkKey, LinkValues>,
 }
 
 impl LinkCache {
+    /// Adds or replaces a link, returning whether its providers need to be told about it. A link
+    /// that only differs from the one it replaces in its lease expiry is a renewal, which leaves
+    /// the providers' bindings as they are
     pub fn add_link(
         &mut self,
         actor: &str,
@@ -41,14 +65,25 @@ impl LinkCache {
         link_name: &str,
         provider_id: &str,
         values: HashMap<String, String>,
-    ) {
-        self.link_config.insert(
-            LinkKey::new(actor, contract_id, link_name),
-            LinkValues {
-                provider_id: provider_id.to_string(),
-                values,
-            },
-        );
+    ) -> bool {
+        let link = LinkValues {
+            provider_id: provider_id.to_string(),
+            values,
+        };
+        let previous = self
+            .link_config
+            .insert(LinkKey::new(actor, contract_id, link_name), link.clone());
+        previous.map_or(true, |p| !p.binds_like(&link))
+    }
+
+    pub fn remove_link(
+        &mut self,
+        actor: &str,
+        contract_id: &str,
+        link_name: &str,
+    ) -> Option<LinkValues> {
+        self.link_config
+            .remove(&LinkKey::new(actor, contract_id, link_name))
     }
 
     pub fn get(&self, key: &LinkKey) -> Option<LinkValues> {
@@ -72,8 +107,8 @@ impl LinkCache {
             .map(|bv| bv.provider_id.to_string())
     }
 
-    /// Retrieves the list of all actor links that pertain to a specific capability provider. Does not return an error,
-    /// will return an empty vector if no links are found. Each item in the returned vector is a tuple consisting of the
+    /// Retrieves the list of all actor links that pertain to a specific capability provider, including those
+    /// for which it is a failover provider. Does not return an error, will return an empty vector if no links are found. Each item in the returned vector is a tuple consisting of the
     /// actor's public key and the config values hash map.
     pub fn find_links(
         &self,
@@ -82,10 +117,56 @@ impl LinkCache {
     ) -> Vec<(String, HashMap<String, String>)> {
         let mut res = Vec::new();
         for (key, val) in &self.link_config {
-            if key.link_name == link_name && val.provider_id == provider_id {
+            if key.link_name == link_name && val.providers().iter().any(|p| p == provider_id) {
                 res.push((key.actor.to_string(), val.values.clone()));
             }
         }
         res
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::LinkCache;
+    use crate::links::LINK_VALUE_LEASE_EXPIRES;
+    use std::collections::HashMap;
+
+    #[test]
+    fn renewals_leave_bindings_alone() {
+        let values = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let mut cache = LinkCache::default();
+        let leased = values(&[("URL", "redis://a"), (LINK_VALUE_LEASE_EXPIRES, "100")]);
+        assert!(cache.add_link(
+            "Mxxx",
+            "wascc:keyvalue",
+            "default",
+            "Vredis",
+            leased.clone()
+        ));
+        assert!(!cache.add_link("Mxxx", "wascc:keyvalue", "default", "Vredis", leased));
+        let renewed = values(&[("URL", "redis://a"), (LINK_VALUE_LEASE_EXPIRES, "200")]);
+        assert!(!cache.add_link(
+            "Mxxx",
+            "wascc:keyvalue",
+            "default",
+            "Vredis",
+            renewed.clone()
+        ));
+
+        let changed = values(&[("URL", "redis://b"), (LINK_VALUE_LEASE_EXPIRES, "200")]);
+        assert!(cache.add_link(
+            "Mxxx",
+            "wascc:keyvalue",
+            "default",
+            "Vredis",
+            changed.clone()
+        ));
+        assert!(cache.add_link("Mxxx", "wascc:keyvalue", "default", "Vother", changed));
+        assert!(cache.add_link("Myyy", "wascc:keyvalue", "default", "Vredis", renewed));
+    }
+}
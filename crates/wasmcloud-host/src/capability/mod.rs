@@ -1,4 +1,13 @@
+pub(crate) mod discovery;
 pub(crate) mod extras;
+pub(crate) mod grpc;
+pub(crate) mod ingress;
+pub(crate) mod keyvalue;
 pub(crate) mod link_cache;
 pub(crate) mod native;
 pub(crate) mod native_host;
+pub(crate) mod par;
+pub(crate) mod router;
+pub(crate) mod sdk;
+pub(crate) mod static_files;
+pub(crate) mod websocket;
@@ -1,22 +1,34 @@
+use crate::capability::par::{extract_target, ExtractedProvider};
 use crate::{Host, Result};
 use provider_archive::ProviderArchive;
+use std::path::Path;
 use wascap::jwt::Claims;
 use wascc_codec::capabilities::CapabilityProvider;
 
 /// Represents a native capability provider compiled as a shared object library.
 /// These plugins are OS- and architecture-specific, so they will be `.so` files on Linux, `.dylib`
 /// files on macOS, etc.
+///
+/// The plugin is loaded into the host's own process and runs with the host's privileges, so
+/// restricting what a provider can do (with seccomp or landlock on Linux, say) means applying the
+/// restrictions to the host process as a whole; they can't be scoped to a single provider. A host
+/// can instead refuse to run providers it doesn't trust (see `HostBuilder::deny_provider`)
 #[derive(Clone)]
 pub struct NativeCapability {
     pub(crate) plugin: Option<Box<dyn CapabilityProvider>>,
     pub(crate) link_name: String,
     pub(crate) claims: Claims<wascap::jwt::CapabilityProvider>,
     pub(crate) native_bytes: Option<Vec<u8>>,
+    pub(crate) native_file: Option<ExtractedProvider>,
+    // The signed token the claims were decoded from, when the host has it to verify
+    pub(crate) jwt: Option<String>,
 }
 
 impl NativeCapability {
     /// Reads a capability provider from an archive file. The right architecture/OS plugin
     /// library will be chosen from the file, or an error will result if it isn't found.
+    /// A loaded archive doesn't keep its signed claims token, so hosts in strict mode refuse
+    /// providers read this way; use `from_archive_file` for them instead
     pub fn from_archive(
         archive: &ProviderArchive,
         link_target_name: Option<String>,
@@ -33,7 +45,9 @@ impl NativeCapability {
                 claims: archive.claims().unwrap(),
                 link_name: link,
                 native_bytes: Some(bytes),
+                native_file: None,
                 plugin: None,
+                jwt: None,
             }),
             None => Err(format!(
                 "No binary found in archive for target {}",
@@ -43,6 +57,24 @@ impl NativeCapability {
         }
     }
 
+    /// Reads a capability provider from an archive file on disk without loading the whole archive
+    /// into memory. Only the plugin library for this host's architecture/OS is extracted, and its
+    /// hash is verified against the provider's claims
+    pub fn from_archive_file(
+        path: impl AsRef<Path>,
+        link_target_name: Option<String>,
+    ) -> Result<Self> {
+        let extracted = extract_target(path.as_ref(), &Host::native_target())?;
+        Ok(NativeCapability {
+            claims: extracted.claims.clone(),
+            link_name: link_target_name.unwrap_or("default".to_string()),
+            native_bytes: None,
+            jwt: Some(extracted.jwt.to_string()),
+            native_file: Some(extracted),
+            plugin: None,
+        })
+    }
+
     /// This function is to be used for _capability embedding_. If you are building a custom
     /// waSCC host and have a fixed set of capabilities that you want to always be available
     /// to actors, then you can declare a dependency on the capability provider, enable
@@ -60,8 +92,24 @@ impl NativeCapability {
         Ok(NativeCapability {
             plugin: Some(b),
             native_bytes: None,
+            native_file: None,
             claims: claims.clone(),
             link_name: link,
+            jwt: None,
+        })
+    }
+
+    /// Like `from_instance`, but takes the provider's claims as a signed JWT, which lets a host
+    /// in strict mode verify them before the provider starts
+    pub fn from_instance_with_token(
+        instance: impl CapabilityProvider + 'static,
+        link_target_name: Option<String>,
+        jwt: &str,
+    ) -> Result<Self> {
+        let claims = Claims::<wascap::jwt::CapabilityProvider>::decode(jwt)?;
+        Ok(NativeCapability {
+            jwt: Some(jwt.to_string()),
+            ..NativeCapability::from_instance(instance, link_target_name, claims)?
         })
     }
 
@@ -12,6 +12,7 @@ use futures::executor::block_on;
 use libloading::{Library, Symbol};
 use std::env::temp_dir;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use wascap::prelude::KeyPair;
 use wascc_codec::capabilities::{
     CapabilityDescriptor, CapabilityProvider, OP_GET_CAPABILITY_DESCRIPTOR,
@@ -24,8 +25,16 @@ pub(crate) struct Initialize {
     pub mw_chain: Vec<Box<dyn Middleware>>,
     pub seed: String,
     pub image_ref: Option<String>,
+    /// Loads and configures the provider without subscribing it to the bus, so that it can be
+    /// readied to replace a running version of itself
+    pub standby: bool,
 }
 
+/// Tells the provider to clean up before its host stops, as the host it runs in stops
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct StopProviderHost;
+
 struct State {
     cap: NativeCapability,
     mw_chain: Vec<Box<dyn Middleware>>,
@@ -54,11 +63,19 @@ impl Actor for NativeCapabilityHost {
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        if self.state.is_none() {
-            //warn!("Stopped a provider host that had no state. Something might be amiss, askew, or perchance awry");
-            return;
-        }
-        let state = self.state.as_mut().unwrap();
+        self.clean_up();
+    }
+}
+
+impl NativeCapabilityHost {
+    fn clean_up(&mut self) {
+        let mut state = match self.state.take() {
+            Some(s) => s,
+            None => {
+                //warn!("Stopped a provider host that had no state. Something might be amiss, askew, or perchance awry");
+                return;
+            }
+        };
 
         state.plugin.stop(); // Tell the provider to clean up, dispose of resources, stop threads, etc
         if let Some(l) = state.library.take() {
@@ -70,6 +87,16 @@ impl Actor for NativeCapabilityHost {
     }
 }
 
+// Cleans the provider up, answering once it is done, and stops its host
+impl Handler<StopProviderHost> for NativeCapabilityHost {
+    type Result = ();
+
+    fn handle(&mut self, _msg: StopProviderHost, ctx: &mut Self::Context) -> Self::Result {
+        self.clean_up();
+        ctx.stop();
+    }
+}
+
 impl Handler<Initialize> for NativeCapabilityHost {
     type Result = Result<WasccEntity>;
 
@@ -123,6 +150,10 @@ impl Handler<Initialize> for NativeCapabilityHost {
             ctx.stop();
             return Err(e);
         }
+        if msg.standby {
+            info!("Native Capability Provider '{}' standing by", entity.url());
+            return Ok(entity);
+        }
         let url = entity.url().to_string();
         let submsg = Subscribe {
             interest: entity.clone(),
@@ -147,6 +178,11 @@ impl Handler<Initialize> for NativeCapabilityHost {
             // are present, perform the bind actor func call
             let _ = b2.send(epl).await;
         });
+        crate::labels::set_issuer(
+            &state.kp.public_key(),
+            &state.cap.claims.subject,
+            &state.cap.claims.issuer,
+        );
         let cp = ControlInterface::from_hostlocal_registry(&state.kp.public_key());
         cp.do_send(PublishEvent {
             event: ControlEvent::ProviderStarted {
@@ -178,11 +214,19 @@ impl Handler<Invocation> for NativeCapabilityHost {
     /// plugin, then runs the provider post-invoke middleware.
     fn handle(&mut self, inv: Invocation, _ctx: &mut Self::Context) -> Self::Result {
         let state = self.state.as_ref().unwrap();
-        trace!(
-            "Provider {} handling invocation operation '{}'",
-            state.cap.claims.subject,
-            inv.operation
-        );
+        let provider = state.cap.claims.subject.as_str();
+        if crate::log_levels::enabled(&state.kp.public_key(), provider, log::Level::Trace)
+            && crate::sampling::sampled(&inv)
+        {
+            log!(
+                target: provider,
+                log::Level::Trace,
+                "Provider {} handling invocation operation '{}' (baggage {:?})",
+                provider,
+                inv.operation,
+                inv.baggage
+            );
+        }
         if let WasccEntity::Actor(ref s) = inv.origin {
             if let WasccEntity::Capability { id, .. } = &inv.target {
                 if id != &state.cap.id() {
@@ -198,8 +242,13 @@ impl Handler<Invocation> for NativeCapabilityHost {
                     );
                 }
 
-                match state.plugin.handle_call(&s, &inv.operation, &inv.msg) {
-                    Ok(msg) => {
+                let res = crate::baggage::with_baggage(inv.baggage.clone(), || {
+                    state.plugin.handle_call(&s, &inv.operation, &inv.msg)
+                });
+                let host_id = state.kp.public_key();
+                match res.map(|msg| crate::limits::cap_output(&host_id, &inv, msg)) {
+                    Ok(Err(too_large)) => too_large,
+                    Ok(Ok(msg)) => {
                         let ir = InvocationResponse::success(&inv, msg);
                         match run_capability_post_invoke(ir, &state.mw_chain) {
                             Ok(r) => r,
@@ -209,7 +258,23 @@ impl Handler<Invocation> for NativeCapabilityHost {
                             ),
                         }
                     }
-                    Err(e) => InvocationResponse::error(&inv, &format!("{}", e)),
+                    Err(e) => {
+                        if !crate::sampling::sampled(&inv)
+                            && crate::sampling::errors_sampled(&host_id)
+                            && crate::log_levels::enabled(&host_id, provider, log::Level::Trace)
+                        {
+                            log!(
+                                target: provider,
+                                log::Level::Trace,
+                                "Provider {} failed invocation operation '{}': {} (baggage {:?})",
+                                provider,
+                                inv.operation,
+                                e,
+                                inv.baggage
+                            );
+                        }
+                        InvocationResponse::error(&inv, &format!("{}", e))
+                    }
                 }
             } else {
                 InvocationResponse::error(&inv, "Invocation sent to the wrong target")
@@ -224,9 +289,10 @@ fn extrude(
     cap: &NativeCapability,
 ) -> Result<(Option<Library>, Box<dyn CapabilityProvider + 'static>)> {
     use std::io::Write;
-    if let Some(ref bytes) = cap.native_bytes {
-        let path = temp_dir();
-        let path = path.join("wasmcloudcache");
+    if let Some(ref extracted) = cap.native_file {
+        load_plugin(&extracted.path)
+    } else if let Some(ref bytes) = cap.native_bytes {
+        let path = provider_cache_dir();
         let path = path.join(&cap.claims.subject);
         let path = path.join(format!(
             "{}",
@@ -241,21 +307,30 @@ fn extrude(
             let mut tf = File::create(&path)?;
             tf.write_all(&bytes)?;
         }
-        type PluginCreate = unsafe fn() -> *mut dyn CapabilityProvider;
-        let library = Library::new(&path)?;
-
-        let plugin = unsafe {
-            let constructor: Symbol<PluginCreate> = library.get(b"__capability_provider_create")?;
-            let boxed_raw = constructor();
-
-            Box::from_raw(boxed_raw)
-        };
-        Ok((Some(library), plugin))
+        load_plugin(&path)
     } else {
         Ok((None, cap.plugin.clone().unwrap()))
     }
 }
 
+fn load_plugin(path: &Path) -> Result<(Option<Library>, Box<dyn CapabilityProvider + 'static>)> {
+    type PluginCreate = unsafe fn() -> *mut dyn CapabilityProvider;
+    let library = Library::new(path)?;
+
+    let plugin = unsafe {
+        let constructor: Symbol<PluginCreate> = library.get(b"__capability_provider_create")?;
+        let boxed_raw = constructor();
+
+        Box::from_raw(boxed_raw)
+    };
+    Ok((Some(library), plugin))
+}
+
+/// The root directory into which provider plugin libraries are extracted prior to loading
+pub(crate) fn provider_cache_dir() -> PathBuf {
+    temp_dir().join("wasmcloudcache")
+}
+
 #[cfg(test)]
 mod test {
     use crate::capability::extras::{ExtrasCapabilityProvider, OP_REQUEST_GUID};
@@ -281,6 +356,7 @@ mod test {
             mw_chain: vec![],
             seed,
             image_ref: None,
+            standby: false,
         };
         let _ = extras.send(init).await.unwrap();
 
@@ -0,0 +1,221 @@
+// Loading a provider archive with `ProviderArchive::try_load` decompresses every target's binary
+// into memory at once, which can be several times the size of the provider that actually runs.
+// Here the archive is read as a stream instead: only the claims and the binary for this host's
+// target are kept, the binary is written straight to disk as it is decompressed, and its digest
+// is computed along the way and checked against the claims before the file is put in place.
+// Binaries are kept by provider and digest, so two builds sharing a revision (or with none) never
+// share a file, and a file already there is hashed again before it's reused.
+
+use crate::capability::native_host::provider_cache_dir;
+use crate::Result;
+use data_encoding::HEXUPPER;
+use flate2::read::GzDecoder;
+use ring::digest::{Context, SHA256};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use wascap::jwt::{CapabilityProvider, Claims};
+
+const CLAIMS_FILE: &str = "claims";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A provider binary extracted from an archive into the provider cache
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExtractedProvider {
+    pub claims: Claims<CapabilityProvider>,
+    pub jwt: String,
+    pub path: PathBuf,
+    pub digest: String,
+}
+
+/// Extracts the claims and the binary for the given target from a provider archive, which can be
+/// compressed (`.par.gz`) or not. The binary ends up where the host loads providers from
+pub(crate) fn extract_target(archive: &Path, target: &str) -> Result<ExtractedProvider> {
+    let mut reader = BufReader::new(File::open(archive)?);
+    let compressed = {
+        use std::io::BufRead;
+        reader.fill_buf()?.starts_with(GZIP_MAGIC)
+    };
+    if compressed {
+        extract_from(GzDecoder::new(reader), target, &provider_cache_dir())
+    } else {
+        extract_from(reader, target, &provider_cache_dir())
+    }
+}
+
+fn extract_from<R: Read>(input: R, target: &str, cache_dir: &Path) -> Result<ExtractedProvider> {
+    std::fs::create_dir_all(cache_dir)?;
+    // The claims might come after the binary in the archive, so the binary goes to a temporary
+    // file until its final location is known
+    let tmp = cache_dir.join(format!("{}.part", uuid::Uuid::new_v4()));
+    let res = extract_entries(input, target, &tmp).and_then(|(claims, digest)| {
+        let (claims, jwt) = claims.ok_or("No claims found in provider archive file")?;
+        let digest =
+            digest.ok_or_else(|| format!("No binary found in archive for target {}", target))?;
+        let expected = claims
+            .metadata
+            .as_ref()
+            .and_then(|m| m.target_hashes.get(target))
+            .ok_or_else(|| format!("Provider claims have no hash for target {}", target))?;
+        if !expected.eq_ignore_ascii_case(&digest) {
+            return Err(format!(
+                "Binary for target {} does not match the hash in the provider's claims",
+                target
+            )
+            .into());
+        }
+        let dir = cache_dir.join(&claims.subject).join(&digest);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(target);
+        // Another host may already have extracted (and loaded) this binary, but a file that has
+        // changed since is replaced
+        let extracted = path.exists() && file_digest(&path)?.eq_ignore_ascii_case(&digest);
+        if extracted {
+            std::fs::remove_file(&tmp)?;
+        } else {
+            std::fs::rename(&tmp, &path)?;
+        }
+        Ok(ExtractedProvider {
+            claims,
+            jwt,
+            path,
+            digest,
+        })
+    });
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res
+}
+
+fn extract_entries<R: Read>(
+    input: R,
+    target: &str,
+    tmp: &Path,
+) -> Result<(Option<(Claims<CapabilityProvider>, String)>, Option<String>)> {
+    let mut claims = None;
+    let mut digest = None;
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let stem = entry
+            .path()?
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        if stem == CLAIMS_FILE {
+            let mut jwt = String::new();
+            entry.read_to_string(&mut jwt)?;
+            let jwt = jwt.trim().to_string();
+            claims = Some((Claims::<CapabilityProvider>::decode(&jwt)?, jwt));
+        } else if stem == target {
+            let mut file = File::create(tmp)?;
+            digest = Some(copy_with_digest(&mut entry, &mut file)?);
+            file.flush()?;
+        }
+        // Binaries for other targets are skipped without being kept
+    }
+    Ok((claims, digest))
+}
+
+/// The SHA-256 digest of a file, in the same form as `bytes_digest`, read a chunk at a time
+pub(crate) fn file_digest(path: &Path) -> Result<String> {
+    copy_with_digest(&mut File::open(path)?, &mut std::io::sink())
+}
+
+fn copy_with_digest<R: Read, W: Write>(input: &mut R, output: &mut W) -> Result<String> {
+    let mut ctx = Context::new(&SHA256);
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.update(&buf[..n]);
+        output.write_all(&buf[..n])?;
+    }
+    Ok(HEXUPPER.encode(ctx.finish().as_ref()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::extract_from;
+    use crate::hooks::bytes_digest;
+    use std::collections::HashMap;
+    use wascap::jwt::{CapabilityProvider, Claims};
+    use wascap::prelude::KeyPair;
+
+    fn archive(subject: &str, target: &str, binary: &[u8], hash: &str) -> Vec<u8> {
+        let account = KeyPair::new_account();
+        let mut hashes = HashMap::new();
+        hashes.insert(target.to_string(), hash.to_string());
+        let claims = Claims::<CapabilityProvider>::new(
+            "Test".to_string(),
+            account.public_key(),
+            subject.to_string(),
+            "wascc:testing".to_string(),
+            "Testing".to_string(),
+            None,
+            None,
+            hashes,
+        );
+        let jwt = claims.encode(&account).unwrap();
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, data) in &[
+            ("other-target.bin", &b"not this one"[..]),
+            (&format!("{}.bin", target)[..], binary),
+            ("claims.jwt", jwt.as_bytes()),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn extracts_only_the_verified_target() {
+        let dir = std::env::temp_dir().join(format!("par-{}", std::process::id()));
+        let binary = b"pretend this is a shared library";
+        let subject = KeyPair::new_service().public_key();
+
+        let par = archive(&subject, "x86_64-linux", binary, &bytes_digest(binary));
+        let extracted = extract_from(&par[..], "x86_64-linux", &dir).unwrap();
+        assert_eq!(binary.to_vec(), std::fs::read(&extracted.path).unwrap());
+        assert_eq!(bytes_digest(binary), extracted.digest);
+
+        // A file altered after extraction isn't reused
+        std::fs::write(&extracted.path, b"altered").unwrap();
+        let again = extract_from(&par[..], "x86_64-linux", &dir).unwrap();
+        assert_eq!(extracted.path, again.path);
+        assert_eq!(binary.to_vec(), std::fs::read(&again.path).unwrap());
+
+        // Nor is another build of the same revision
+        let rebuilt = b"another build of the same revision";
+        let par2 = archive(&subject, "x86_64-linux", rebuilt, &bytes_digest(rebuilt));
+        let other = extract_from(&par2[..], "x86_64-linux", &dir).unwrap();
+        assert_ne!(extracted.path, other.path);
+        assert_eq!(rebuilt.to_vec(), std::fs::read(&other.path).unwrap());
+
+        assert!(extract_from(&par[..], "aarch64-linux", &dir).is_err());
+        let tampered = archive(
+            &subject,
+            "x86_64-linux",
+            binary,
+            &bytes_digest(b"something else"),
+        );
+        assert!(extract_from(&tampered[..], "x86_64-linux", &dir).is_err());
+        // Nothing is left behind by failed extractions
+        assert_eq!(
+            0,
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .filter(|e| e.as_ref().unwrap().path().extension().is_some())
+                .count()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
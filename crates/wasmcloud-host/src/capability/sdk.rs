@@ -0,0 +1,422 @@
+// Writing an in-process provider against `CapabilityProvider` directly means writing the same
+// code every time: holding on to the dispatcher, remembering each actor's link values as it is
+// bound and forgetting them as it is unbound, answering health checks and descriptor requests
+// from the host, and matching operation names to functions that deserialize a request and
+// serialize a response. Every built-in provider carries a copy.
+//
+// The SDK writes that part. A provider implements `NativeProvider` (every method of which has a
+// default) and is handed to a `CapabilityBuilder`, along with its contract ID and a function for
+// each operation. Operation functions take the provider, a `CallContext` holding the calling
+// actor, its link values and a way to call actors back, and the request already decoded; they
+// return the response, which is encoded for them. The builder produces an ordinary
+// `CapabilityProvider`, so the result is started like any other native capability.
+//
+// Providers that would rather not write the builder calls put `#[capability(contract = ...)]` on
+// an impl block and mark its operation methods with `#[operation]`; the attribute (from the
+// wasmcloud-host-macros crate) writes the same builder calls, and generates `capability_builder`
+// and `capability` functions for the provider.
+
+use crate::generated::core::{CapabilityConfiguration, HealthResponse};
+use crate::messagebus::handlers::OP_HEALTH_REQUEST;
+use crate::{NativeCapability, Result, VERSION};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use wascap::jwt::Claims;
+use wascc_codec::capabilities::{
+    CapabilityDescriptor, CapabilityProvider, Dispatcher, NullDispatcher, OperationDirection,
+    OP_GET_CAPABILITY_DESCRIPTOR,
+};
+use wascc_codec::core::{OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+use wascc_codec::{deserialize, serialize, SYSTEM_ACTOR};
+
+type Operation<P> = Arc<dyn Fn(&P, &CallContext, &[u8]) -> Result<Vec<u8>> + Send + Sync>;
+
+/// The lifecycle of an in-process provider built with a `CapabilityBuilder`. Every method has a
+/// default, so a provider only implements those it needs
+pub trait NativeProvider: Send + Sync + 'static {
+    /// Called when an actor is linked to the provider, with the values of the link. The values
+    /// are also available to every call the actor makes, through its `CallContext`
+    fn bind(&self, _actor: &str, _values: &HashMap<String, String>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when an actor's link to the provider is removed
+    fn unbind(&self, _actor: &str) {}
+
+    /// Answers the host's health checks. An error is reported as the reason the provider is
+    /// unhealthy
+    fn health(&self) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when the provider is stopped
+    fn stop(&self) {}
+}
+
+/// What an operation knows about the call it is handling
+pub struct CallContext<'a> {
+    actor: &'a str,
+    values: HashMap<String, String>,
+    dispatcher: &'a RwLock<Box<dyn Dispatcher>>,
+}
+
+impl<'a> CallContext<'a> {
+    /// The public key of the calling actor
+    pub fn actor(&self) -> &str {
+        self.actor
+    }
+
+    /// The values of the calling actor's link to the provider, empty if it isn't linked
+    pub fn link_values(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+
+    /// Invokes an actor linked to the provider, returning its response
+    pub fn call_actor(&self, actor: &str, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
+        self.dispatcher
+            .read()
+            .unwrap()
+            .dispatch(actor, operation, msg)
+    }
+}
+
+/// Builds an in-process capability provider from a `NativeProvider` and its operations
+pub struct CapabilityBuilder<P> {
+    provider: P,
+    contract_id: String,
+    name: String,
+    description: String,
+    revision: u32,
+    operations: Vec<(String, String, Operation<P>)>,
+}
+
+impl<P: NativeProvider> CapabilityBuilder<P> {
+    pub fn new(contract_id: &str, provider: P) -> CapabilityBuilder<P> {
+        CapabilityBuilder {
+            provider,
+            contract_id: contract_id.to_string(),
+            name: contract_id.to_string(),
+            description: String::new(),
+            revision: 0,
+            operations: vec![],
+        }
+    }
+
+    /// Sets the name and description the provider gives in its capability descriptor
+    pub fn describe(self, name: &str, description: &str) -> CapabilityBuilder<P> {
+        CapabilityBuilder {
+            name: name.to_string(),
+            description: description.to_string(),
+            ..self
+        }
+    }
+
+    pub fn revision(self, revision: u32) -> CapabilityBuilder<P> {
+        CapabilityBuilder { revision, ..self }
+    }
+
+    /// Adds an operation whose request and response are encoded with the standard codec
+    pub fn operation<Req, Res, F>(self, name: &str, description: &str, f: F) -> CapabilityBuilder<P>
+    where
+        Req: DeserializeOwned,
+        Res: Serialize,
+        F: Fn(&P, &CallContext, Req) -> Result<Res> + Send + Sync + 'static,
+    {
+        self.raw_operation(name, description, move |p, ctx, msg| {
+            Ok(serialize(f(p, ctx, deserialize(msg)?)?)?)
+        })
+    }
+
+    /// Adds an operation that handles the payload of the call as it is
+    pub fn raw_operation<F>(self, name: &str, description: &str, f: F) -> CapabilityBuilder<P>
+    where
+        F: Fn(&P, &CallContext, &[u8]) -> Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        let mut operations = self.operations;
+        operations.retain(|(n, _, _)| n != name);
+        operations.push((name.to_string(), description.to_string(), Arc::new(f)));
+        CapabilityBuilder { operations, ..self }
+    }
+
+    pub fn build(self) -> Capability<P> {
+        Capability {
+            provider: Arc::new(self.provider),
+            contract_id: self.contract_id,
+            name: self.name,
+            description: self.description,
+            revision: self.revision,
+            operations: Arc::new(
+                self.operations
+                    .into_iter()
+                    .map(|(n, d, f)| (n, (d, f)))
+                    .collect(),
+            ),
+            links: Arc::new(RwLock::new(HashMap::new())),
+            dispatcher: Arc::new(RwLock::new(Box::new(NullDispatcher::new()))),
+        }
+    }
+
+    /// Builds the provider as a native capability with the given claims, which must be for the
+    /// provider's contract
+    pub fn into_native(
+        self,
+        link_name: Option<String>,
+        claims: Claims<wascap::jwt::CapabilityProvider>,
+    ) -> Result<NativeCapability> {
+        let capid = claims
+            .metadata
+            .as_ref()
+            .map(|md| md.capid.to_string())
+            .unwrap_or_default();
+        if capid != self.contract_id {
+            return Err(format!(
+                "Claims are for contract {}, but the provider implements {}",
+                capid, self.contract_id
+            )
+            .into());
+        }
+        NativeCapability::from_instance(self.build(), link_name, claims)
+    }
+}
+
+/// An in-process capability provider built by a `CapabilityBuilder`
+pub struct Capability<P> {
+    provider: Arc<P>,
+    contract_id: String,
+    name: String,
+    description: String,
+    revision: u32,
+    operations: Arc<HashMap<String, (String, Operation<P>)>>,
+    // The values of each linked actor's link, by actor
+    links: Arc<RwLock<HashMap<String, HashMap<String, String>>>>,
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+}
+
+impl<P> Clone for Capability<P> {
+    fn clone(&self) -> Self {
+        Capability {
+            provider: self.provider.clone(),
+            contract_id: self.contract_id.to_string(),
+            name: self.name.to_string(),
+            description: self.description.to_string(),
+            revision: self.revision,
+            operations: self.operations.clone(),
+            links: self.links.clone(),
+            dispatcher: self.dispatcher.clone(),
+        }
+    }
+}
+
+impl<P: NativeProvider> Capability<P> {
+    /// The provider the capability was built from
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    fn bind_actor(&self, config: CapabilityConfiguration) -> Result<Vec<u8>> {
+        self.provider.bind(&config.module, &config.values)?;
+        self.links
+            .write()
+            .unwrap()
+            .insert(config.module, config.values);
+        Ok(vec![])
+    }
+
+    fn remove_actor(&self, config: CapabilityConfiguration) -> Result<Vec<u8>> {
+        if self.links.write().unwrap().remove(&config.module).is_some() {
+            self.provider.unbind(&config.module);
+        }
+        Ok(vec![])
+    }
+
+    fn health(&self) -> Result<Vec<u8>> {
+        let hr = match self.provider.health() {
+            Ok(()) => HealthResponse {
+                healthy: true,
+                message: String::new(),
+            },
+            Err(message) => HealthResponse {
+                healthy: false,
+                message,
+            },
+        };
+        Ok(serialize(hr)?)
+    }
+
+    fn get_descriptor(&self) -> Result<Vec<u8>> {
+        let mut names: Vec<&String> = self.operations.keys().collect();
+        names.sort();
+        let descriptor = names.into_iter().fold(
+            CapabilityDescriptor::builder()
+                .id(&self.contract_id)
+                .name(&self.name)
+                .long_description(&self.description)
+                .version(VERSION)
+                .revision(self.revision),
+            |d, name| {
+                d.with_operation(
+                    name,
+                    OperationDirection::ToProvider,
+                    &self.operations[name].0,
+                )
+            },
+        );
+        Ok(serialize(descriptor.build())?)
+    }
+}
+
+impl<P: NativeProvider> CapabilityProvider for Capability<P> {
+    fn configure_dispatch(
+        &self,
+        dispatcher: Box<dyn Dispatcher>,
+    ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        trace!("Dispatcher received.");
+        *self.dispatcher.write().unwrap() = dispatcher;
+        Ok(())
+    }
+
+    fn handle_call(
+        &self,
+        actor: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Sync + Send>> {
+        trace!("Received host call from {}, operation - {}", actor, op);
+
+        match op {
+            OP_GET_CAPABILITY_DESCRIPTOR if actor == SYSTEM_ACTOR => self.get_descriptor(),
+            OP_BIND_ACTOR if actor == SYSTEM_ACTOR => self.bind_actor(deserialize(msg)?),
+            OP_REMOVE_ACTOR if actor == SYSTEM_ACTOR => self.remove_actor(deserialize(msg)?),
+            OP_HEALTH_REQUEST => self.health(),
+            _ => {
+                let (_, operation) = self
+                    .operations
+                    .get(op)
+                    .ok_or_else(|| format!("{} has no operation {}", self.contract_id, op))?;
+                let ctx = CallContext {
+                    actor,
+                    values: self
+                        .links
+                        .read()
+                        .unwrap()
+                        .get(actor)
+                        .cloned()
+                        .unwrap_or_default(),
+                    dispatcher: &self.dispatcher,
+                };
+                operation(&self.provider, &ctx, msg)
+            }
+        }
+    }
+
+    fn stop(&self) {
+        self.provider.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CallContext, CapabilityBuilder, NativeProvider};
+    use crate::generated::core::{CapabilityConfiguration, HealthResponse};
+    use crate::messagebus::handlers::OP_HEALTH_REQUEST;
+    use crate::{capability, Result};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use wascc_codec::capabilities::{CapabilityProvider, OP_GET_CAPABILITY_DESCRIPTOR};
+    use wascc_codec::core::{OP_BIND_ACTOR, OP_REMOVE_ACTOR};
+    use wascc_codec::{deserialize, serialize, SYSTEM_ACTOR};
+
+    #[derive(Default)]
+    struct Flaky {
+        down: AtomicBool,
+    }
+
+    impl NativeProvider for Flaky {
+        fn unbind(&self, _actor: &str) {
+            self.down.store(true, Ordering::SeqCst);
+        }
+
+        fn health(&self) -> std::result::Result<(), String> {
+            if self.down.load(Ordering::SeqCst) {
+                Err("lost its last link".to_string())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn generated_plumbing_binds_and_reports_health() {
+        let cap = CapabilityBuilder::new("example:echo", Flaky::default())
+            .operation("Echo", "Echoes the greeting", |_p, ctx, msg: String| {
+                Ok(format!("{} from {}", msg, ctx.link_values()["greeting"]))
+            })
+            .build();
+        let mut values = HashMap::new();
+        values.insert("greeting".to_string(), "hello".to_string());
+        let cfg = CapabilityConfiguration {
+            module: "Mxxx".to_string(),
+            values,
+        };
+        cap.handle_call(SYSTEM_ACTOR, OP_BIND_ACTOR, &serialize(&cfg).unwrap())
+            .unwrap();
+        let res = cap
+            .handle_call("Mxxx", "Echo", &serialize("hi").unwrap())
+            .unwrap();
+        assert_eq!("hi from hello", deserialize::<String>(&res).unwrap());
+        assert!(cap.handle_call("Mxxx", "Shout", &[]).is_err());
+
+        cap.handle_call(SYSTEM_ACTOR, OP_REMOVE_ACTOR, &serialize(&cfg).unwrap())
+            .unwrap();
+        let hr: HealthResponse = deserialize(
+            &cap.handle_call(SYSTEM_ACTOR, OP_HEALTH_REQUEST, &[])
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(!hr.healthy);
+        assert_eq!("lost its last link", hr.message);
+    }
+
+    struct Shouter;
+
+    impl NativeProvider for Shouter {}
+
+    #[capability(contract = "example:shout", name = "Shouter", revision = 2)]
+    impl Shouter {
+        #[operation(description = "Shouts the greeting")]
+        fn shout_greeting(&self, ctx: &CallContext, msg: String) -> Result<String> {
+            Ok(format!("{} {}!", msg.to_uppercase(), ctx.actor()))
+        }
+
+        #[operation(name = "Bytes", raw)]
+        fn count(&self, _ctx: &CallContext, msg: &[u8]) -> Result<Vec<u8>> {
+            Ok(vec![msg.len() as u8])
+        }
+    }
+
+    #[test]
+    fn attribute_registers_operations() {
+        let cap = Shouter.capability();
+        let res = cap
+            .handle_call("Mxxx", "ShoutGreeting", &serialize("hi").unwrap())
+            .unwrap();
+        assert_eq!("HI Mxxx!", deserialize::<String>(&res).unwrap());
+        assert_eq!(
+            vec![3],
+            cap.handle_call("Mxxx", "Bytes", &[1, 2, 3]).unwrap()
+        );
+        assert!(cap.handle_call("Mxxx", "Count", &[]).is_err());
+
+        let descriptor: wascc_codec::capabilities::CapabilityDescriptor = deserialize(
+            &cap.handle_call(SYSTEM_ACTOR, OP_GET_CAPABILITY_DESCRIPTOR, &[])
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("example:shout", descriptor.id);
+        assert_eq!("Shouter", descriptor.name);
+        assert_eq!(2, descriptor.revision);
+        assert_eq!(2, descriptor.supported_operations.len());
+    }
+}
@@ -0,0 +1,61 @@
+// Serving large files through an actor means copying them into guest memory, and possibly across
+// the lattice, just to hand them back out again. Instead, an actor answering an HTTP request
+// through the router can respond with a reference to a blob, in the `x-wasmcloud-blob` header as
+// `<container>/<blob id>`, and the router streams the blob to the client itself (with range
+// requests and content types handled as for any static file).
+//
+// Blobs are only ever read from the host's local filesystem. They are resolved against the
+// `ROUTE_BLOB_DIR` of the actor's route, which is the root directory of a filesystem blobstore:
+// containers are its subdirectories and blobs are the files in them. References can't escape the
+// root. The router doesn't go through the blobstore provider an actor is linked to, so an actor
+// whose blobs live in a remote store (S3, for instance) needs to stream them itself, or have
+// them synced to a directory on every host that serves its route.
+
+use crate::Result;
+use std::path::{Component, Path, PathBuf};
+
+/// The response header in which an actor returns a blob reference for the router to serve
+pub const BLOB_HEADER: &str = "x-wasmcloud-blob";
+
+/// The link value holding the local directory that blob references from an actor are served
+/// from. Blobs in a blobstore provider other than the filesystem can't be served this way
+pub const ROUTE_BLOB_DIR: &str = "ROUTE_BLOB_DIR";
+
+/// Resolves a `<container>/<blob id>` reference to a file under the given root
+pub(crate) fn resolve_blob(root: &Path, reference: &str) -> Result<PathBuf> {
+    let relative = Path::new(reference.trim_start_matches('/'));
+    let mut components = relative.components();
+    let valid =
+        components.clone().count() >= 2 && components.all(|c| matches!(c, Component::Normal(_)));
+    if !valid {
+        return Err(format!("Invalid blob reference '{}'", reference).into());
+    }
+    let path = root.join(relative);
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| format!("Blob '{}' is not available: {}", reference, e))?;
+    // Symbolic links inside the root mustn't lead outside of it either
+    if !canonical.starts_with(root.canonicalize()?) {
+        return Err(format!("Blob '{}' is outside of the blob root", reference).into());
+    }
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod test {
+    use super::resolve_blob;
+
+    #[test]
+    fn references_stay_inside_the_root() {
+        let root = std::env::temp_dir().join(format!("blobs-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("images")).unwrap();
+        std::fs::write(root.join("images").join("logo.png"), b"png").unwrap();
+
+        assert!(resolve_blob(&root, "images/logo.png").is_ok());
+        assert!(resolve_blob(&root, "/images/logo.png").is_ok());
+        assert!(resolve_blob(&root, "images/missing.png").is_err());
+        assert!(resolve_blob(&root, "logo.png").is_err());
+        assert!(resolve_blob(&root, "images/../../etc/passwd").is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,287 @@
+// WebSocket connections accepted by the HTTP router. Invocations are request/response, so a
+// connection is carried as a sequence of them: the actor is invoked when the connection is
+// opened (and may refuse it), for each frame the client sends, for each ping, and when the
+// connection closes. Frames for one connection are delivered in order, one at a time. An actor can
+// answer any of these with frames to send back, and can also push frames or close the connection
+// at any time by calling the router with the connection's ID.
+
+use actix::prelude::*;
+use actix_web::web;
+use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use wascc_codec::capabilities::Dispatcher;
+use wascc_codec::{deserialize, serialize};
+
+/// Delivered to an actor when a client opens a WebSocket on one of its routes
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct WebSocketOpen {
+    pub connection_id: String,
+    pub path: String,
+    pub query_string: String,
+    pub header: HashMap<String, String>,
+}
+
+/// A text or binary frame, either received from a client or to be sent to one
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct WebSocketMessage {
+    pub connection_id: String,
+    pub binary: bool,
+    pub data: Vec<u8>,
+}
+
+/// A ping received from a client
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct WebSocketPing {
+    pub connection_id: String,
+    pub data: Vec<u8>,
+}
+
+/// Delivered to an actor once one of its connections has closed
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct WebSocketClose {
+    pub connection_id: String,
+    pub code: u16,
+    pub reason: String,
+}
+
+/// An actor's answer to a WebSocket invocation: whether to accept the connection (when it is
+/// being opened), the frames to send back, and whether to close it
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct WebSocketReply {
+    pub accept: bool,
+    pub messages: Vec<WebSocketMessage>,
+    pub close: bool,
+}
+
+/// Invoked on an actor when a client opens a WebSocket on one of its routes. The actor accepts
+/// the connection by replying with `accept` set
+pub const OP_WEBSOCKET_OPEN: &str = "HandleWebSocketOpen";
+/// Invoked on an actor for each text or binary frame a client sends
+pub const OP_WEBSOCKET_MESSAGE: &str = "HandleWebSocketMessage";
+/// Invoked on an actor for each ping a client sends. The router answers the ping itself
+pub const OP_WEBSOCKET_PING: &str = "HandleWebSocketPing";
+/// Invoked on an actor once a connection has closed, for whatever reason
+pub const OP_WEBSOCKET_CLOSE: &str = "HandleWebSocketClose";
+/// Called by an actor to send a frame on one of its connections
+pub const OP_WEBSOCKET_SEND: &str = "SendWebSocketMessage";
+/// Called by an actor to close one of its connections
+pub const OP_WEBSOCKET_DISCONNECT: &str = "CloseWebSocket";
+
+// The normal closure status code
+const CLOSE_NORMAL: u16 = 1000;
+
+/// The open connections, by connection ID, with the actor that owns each
+pub(crate) type Sockets = Arc<RwLock<HashMap<String, (String, Addr<WsSession>)>>>;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) enum Outbound {
+    Message(WebSocketMessage),
+    Close(WebSocketClose),
+}
+
+pub(crate) struct WsSession {
+    id: String,
+    actor: String,
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+    sockets: Sockets,
+    // Frames from the actor's reply to the open event, sent once the handshake completes
+    pending: Vec<WebSocketMessage>,
+    close: WebSocketClose,
+}
+
+impl WsSession {
+    pub(crate) fn new(
+        id: &str,
+        actor: &str,
+        dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+        sockets: Sockets,
+        pending: Vec<WebSocketMessage>,
+    ) -> WsSession {
+        WsSession {
+            id: id.to_string(),
+            actor: actor.to_string(),
+            dispatcher,
+            sockets,
+            pending,
+            close: WebSocketClose {
+                connection_id: id.to_string(),
+                code: CLOSE_NORMAL,
+                reason: "".to_string(),
+            },
+        }
+    }
+
+    // Waits for the actor to handle the event before taking the next frame, keeping the frames
+    // of a connection in order
+    fn deliver<T: Serialize + Send + 'static>(
+        &self,
+        op: &'static str,
+        msg: T,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let fut = invoke(self.dispatcher.clone(), self.actor.to_string(), op, msg);
+        ctx.wait(fut.into_actor(self).map(|res, act, ctx| match res {
+            Ok(reply) => act.apply(reply, ctx),
+            Err(e) => {
+                error!(
+                    "Actor {} failed to handle WebSocket event on connection {}: {}",
+                    act.actor, act.id, e
+                );
+            }
+        }));
+    }
+
+    fn apply(&mut self, reply: WebSocketReply, ctx: &mut ws::WebsocketContext<Self>) {
+        for m in reply.messages {
+            send_frame(m, ctx);
+        }
+        if reply.close {
+            ctx.close(Some(ws::CloseCode::Normal.into()));
+            ctx.stop();
+        }
+    }
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.sockets
+            .write()
+            .unwrap()
+            .insert(self.id.to_string(), (self.actor.to_string(), ctx.address()));
+        for m in self.pending.drain(..) {
+            send_frame(m, ctx);
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.sockets.write().unwrap().remove(&self.id);
+        let fut = invoke(
+            self.dispatcher.clone(),
+            self.actor.to_string(),
+            OP_WEBSOCKET_CLOSE,
+            self.close.clone(),
+        );
+        actix_rt::spawn(async move {
+            let _ = fut.await;
+        });
+    }
+}
+
+impl Handler<Outbound> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Outbound, ctx: &mut Self::Context) {
+        match msg {
+            Outbound::Message(m) => send_frame(m, ctx),
+            Outbound::Close(c) => {
+                self.close = c.clone();
+                ctx.close(Some(ws::CloseReason {
+                    code: c.code.into(),
+                    description: Some(c.reason).filter(|r| !r.is_empty()),
+                }));
+                ctx.stop();
+            }
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let connection_id = self.id.to_string();
+        match msg {
+            Ok(ws::Message::Text(text)) => self.deliver(
+                OP_WEBSOCKET_MESSAGE,
+                WebSocketMessage {
+                    connection_id,
+                    binary: false,
+                    data: text.into_bytes(),
+                },
+                ctx,
+            ),
+            Ok(ws::Message::Binary(bytes)) => self.deliver(
+                OP_WEBSOCKET_MESSAGE,
+                WebSocketMessage {
+                    connection_id,
+                    binary: true,
+                    data: bytes.to_vec(),
+                },
+                ctx,
+            ),
+            Ok(ws::Message::Ping(data)) => {
+                ctx.pong(&data);
+                self.deliver(
+                    OP_WEBSOCKET_PING,
+                    WebSocketPing {
+                        connection_id,
+                        data: data.to_vec(),
+                    },
+                    ctx,
+                );
+            }
+            Ok(ws::Message::Close(reason)) => {
+                if let Some(ref r) = reason {
+                    self.close.code = r.code.into();
+                    self.close.reason = r.description.clone().unwrap_or_default();
+                }
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("WebSocket connection {} failed: {}", self.id, e);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+fn send_frame(m: WebSocketMessage, ctx: &mut ws::WebsocketContext<WsSession>) {
+    if m.binary {
+        ctx.binary(m.data);
+    } else {
+        ctx.text(String::from_utf8_lossy(&m.data).to_string());
+    }
+}
+
+/// Invokes an actor with a WebSocket event on a blocking thread, returning its reply
+pub(crate) async fn invoke<T: Serialize + Send + 'static>(
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+    actor: String,
+    op: &'static str,
+    msg: T,
+) -> Result<WebSocketReply, String> {
+    let bytes = web::block(move || {
+        let payload = serialize(&msg)?;
+        let lock = dispatcher.read().unwrap();
+        lock.dispatch(&actor, op, &payload)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    deserialize(&bytes).map_err(|e| e.to_string())
+}
+
+/// Delivers a frame or close request from an actor to one of its connections
+pub(crate) fn send_outbound(
+    sockets: &Sockets,
+    actor: &str,
+    connection_id: &str,
+    msg: Outbound,
+) -> crate::Result<()> {
+    let lock = sockets.read().unwrap();
+    match lock.get(connection_id) {
+        Some((owner, addr)) if owner == actor => {
+            addr.do_send(msg);
+            Ok(())
+        }
+        _ => Err(format!(
+            "Actor {} has no WebSocket connection {}",
+            actor, connection_id
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,159 @@
+// The host reads the time from its clock wherever it measures time: lease expiries, lock leases
+// and the windows in which it gathers lock probe answers, key-value expirations, reply deadlines,
+// remembered idempotent results, fan-out timeouts, and the timestamps on its events (heartbeats
+// included), its uptime, cost records, exported lattice state, and the history of its lattice
+// cache. The clock is the system clock unless another is set on the host builder: tests can use
+// a `ManualClock` and advance it to expire leases and deadlines deterministically, and embedded
+// systems without a trustworthy wall clock can supply their own time sources.
+//
+// The clock only tells the time; waiting is still done by the async runtime's timers. Periodic
+// work (the lease sweep, heartbeats) runs on a runtime interval, and waits on the network sleep
+// on a runtime timer, but each wakeup compares against the host's clock, so advancing a manual
+// clock takes effect at the next tick rather than triggering one. As with labels, clocks are
+// kept here by host ID so that any part of a host can read its time.
+
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static CLOCKS: Lazy<RwLock<HashMap<String, Arc<dyn Clock>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// The fixed point the system clock's monotonic readings are measured from
+static PROCESS_START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// A source of time for a host. See `HostBuilder::with_clock`
+pub trait Clock: Send + Sync {
+    /// The current wall-clock time
+    fn now(&self) -> SystemTime;
+
+    /// The time elapsed since an arbitrary fixed point. Unlike the wall-clock time, this never
+    /// goes backwards, so it is used to measure durations
+    fn monotonic(&self) -> Duration;
+}
+
+/// The clock provided by the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        PROCESS_START.elapsed()
+    }
+}
+
+/// A clock that only moves when it is told to. Clones share the same time, so a test can keep a
+/// clone to advance the clock of the host it built
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    state: Arc<Mutex<(SystemTime, Duration)>>,
+}
+
+impl ManualClock {
+    /// A clock stopped at the current system time
+    pub fn new() -> ManualClock {
+        ManualClock::starting_at(SystemTime::now())
+    }
+
+    pub fn starting_at(now: SystemTime) -> ManualClock {
+        ManualClock {
+            state: Arc::new(Mutex::new((now, Duration::default()))),
+        }
+    }
+
+    /// Moves the clock forward by the given time
+    pub fn advance(&self, by: Duration) {
+        let mut state = self.state.lock();
+        state.0 += by;
+        state.1 += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.state.lock().0
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.state.lock().1
+    }
+}
+
+/// Sets the clock a host reads the time from
+pub(crate) fn register(host_id: &str, clock: Arc<dyn Clock>) {
+    CLOCKS.write().insert(host_id.to_string(), clock);
+}
+
+pub(crate) fn unregister(host_id: &str) {
+    CLOCKS.write().remove(host_id);
+}
+
+fn clock(host_id: &str) -> Arc<dyn Clock> {
+    CLOCKS
+        .read()
+        .get(host_id)
+        .cloned()
+        .unwrap_or_else(|| Arc::new(SystemClock))
+}
+
+/// The wall-clock time of the given host
+pub(crate) fn now(host_id: &str) -> SystemTime {
+    clock(host_id).now()
+}
+
+/// A monotonic reading of the given host's clock
+pub(crate) fn monotonic(host_id: &str) -> Duration {
+    clock(host_id).monotonic()
+}
+
+/// The wall-clock time of the given host, as milliseconds since the epoch
+pub(crate) fn now_millis(host_id: &str) -> u64 {
+    now(host_id)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// The wall-clock time of the given host, as seconds since the epoch
+pub(crate) fn now_secs(host_id: &str) -> u64 {
+    now(host_id)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{monotonic, now_secs, register, unregister, ManualClock};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn hosts_read_their_own_clocks() {
+        let clock = ManualClock::starting_at(UNIX_EPOCH + Duration::from_secs(1000));
+        register("Nmanual", Arc::new(clock.clone()));
+        assert_eq!(1000, now_secs("Nmanual"));
+        assert_eq!(Duration::default(), monotonic("Nmanual"));
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(1030, now_secs("Nmanual"));
+        assert_eq!(Duration::from_secs(30), monotonic("Nmanual"));
+
+        // Hosts without a clock of their own use the system clock
+        assert!(now_secs("Nsystem") > 1030);
+        unregister("Nmanual");
+        assert!(now_secs("Nmanual") > 1030);
+    }
+}
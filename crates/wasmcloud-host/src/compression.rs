@@ -0,0 +1,131 @@
+use crate::Result;
+use flate2::read::{DeflateDecoder, DeflateEncoder};
+use flate2::Compression;
+use std::io::Read;
+
+pub(crate) const ENCODING_IDENTITY: &str = "identity";
+pub(crate) const ENCODING_DEFLATE: &str = "deflate";
+
+/// A claims compression scheme is used to compress batches of actor claims before they are
+/// gossiped to the other hosts in a lattice. The name of the scheme travels with each batch, so
+/// every host in a lattice must be configured with a scheme of the same name in order to decode
+/// batches that use it. The built-in `identity` and `deflate` schemes are always understood
+pub trait ClaimsCompression: CloneClaimsCompression + Sync + Send {
+    /// The unique name of this compression scheme
+    fn name(&self) -> &str;
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>>;
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>>;
+}
+
+#[doc(hidden)]
+pub trait CloneClaimsCompression {
+    fn clone_compression(&self) -> Box<dyn ClaimsCompression>;
+}
+
+impl<T> CloneClaimsCompression for T
+where
+    T: ClaimsCompression + Clone + 'static,
+{
+    fn clone_compression(&self) -> Box<dyn ClaimsCompression> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ClaimsCompression> {
+    fn clone(&self) -> Self {
+        self.clone_compression()
+    }
+}
+
+/// Sends claims batches uncompressed
+#[derive(Clone, Default)]
+pub struct NoCompression;
+
+impl ClaimsCompression for NoCompression {
+    fn name(&self) -> &str {
+        ENCODING_IDENTITY
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Compresses claims batches with DEFLATE. This is the default scheme used by hosts
+#[derive(Clone)]
+pub struct DeflateCompression {
+    level: u32,
+}
+
+impl DeflateCompression {
+    /// Creates a DEFLATE scheme with the given compression level, from 0 (none) to 9 (best)
+    pub fn new(level: u32) -> DeflateCompression {
+        DeflateCompression {
+            level: level.min(9),
+        }
+    }
+}
+
+impl Default for DeflateCompression {
+    fn default() -> Self {
+        DeflateCompression::new(6)
+    }
+}
+
+impl ClaimsCompression for DeflateCompression {
+    fn name(&self) -> &str {
+        ENCODING_DEFLATE
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        DeflateEncoder::new(bytes, Compression::new(self.level)).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        DeflateDecoder::new(bytes).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Decompresses a payload using the scheme with the given name, considering the host's
+/// configured scheme as well as the built-in ones
+pub(crate) fn decompress_with(
+    configured: &dyn ClaimsCompression,
+    encoding: &str,
+    bytes: &[u8],
+) -> Result<Vec<u8>> {
+    if encoding == configured.name() {
+        configured.decompress(bytes)
+    } else if encoding == ENCODING_DEFLATE {
+        DeflateCompression::default().decompress(bytes)
+    } else if encoding == ENCODING_IDENTITY {
+        NoCompression.decompress(bytes)
+    } else {
+        Err(format!("Unknown claims compression scheme '{}'", encoding).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decompress_with, ClaimsCompression, DeflateCompression, NoCompression};
+
+    #[test]
+    fn deflate_round_trip() {
+        let payload = "eyJ0eXAiOiJqd3QiLCJhbGciOiJFZDI1NTE5In0".repeat(50);
+        let dc = DeflateCompression::default();
+        let compressed = dc.compress(payload.as_bytes()).unwrap();
+        assert!(compressed.len() < payload.len() / 10);
+
+        // A host configured without compression can still read deflated batches
+        let decompressed = decompress_with(&NoCompression, dc.name(), &compressed).unwrap();
+        assert_eq!(payload.as_bytes(), decompressed.as_slice());
+        assert!(decompress_with(&NoCompression, "brotli", &compressed).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// Typed clients for the common capability contracts, so that an embedder can use a provider
+// administratively (seed a key-value store, publish a message) without assembling the
+// contract's operation names and payloads by hand. A client addresses the provider running in
+// the host for a contract and link name, and its calls come from the host itself: no actor
+// needs to be running, and no actor's identity is borrowed to make them.
+//
+// The payloads are the contract's own types. Those of the key-value and messaging contracts come
+// from wascc-codec, and those of the key-value extensions (expirations, compare-and-swap,
+// bounded increments, and key scans, which spare actors provider-specific escape hatches) are
+// generated from the schema in keyvalue.widl. Providers that don't implement an extension answer
+// it with an error.
+
+use crate::generated::keyvalue::{
+    CompareAndSwapRequest, CompareAndSwapResponse, ExpireRequest, IncrementRequest,
+    IncrementResponse, ScanRequest, ScanResponse, TtlResponse,
+};
+use crate::{Host, Result};
+use std::time::Duration;
+use wascc_codec::keyvalue::{
+    AddRequest, AddResponse, DelRequest, GetRequest, GetResponse, SetRequest, OP_ADD, OP_DEL,
+    OP_GET, OP_SET,
+};
+use wascc_codec::messaging::{
+    BrokerMessage, PublishMessage, RequestMessage, OP_PERFORM_REQUEST, OP_PUBLISH_MESSAGE,
+};
+use wascc_codec::{deserialize, serialize};
+
+pub(crate) const KEYVALUE_CONTRACT: &str = "wascc:keyvalue";
+pub(crate) const MESSAGING_CONTRACT: &str = "wascc:messaging";
+
+/// Sets a key's value only if its current value is the expected one (or, when no value is
+/// expected, only if the key doesn't exist)
+pub const OP_COMPARE_AND_SWAP: &str = "CompareAndSwap";
+/// Atomically adds to a key's numeric value, unless the result would fall outside the given
+/// bounds, in which case the value is left as it is
+pub const OP_INCREMENT: &str = "Increment";
+/// Sets (or, with an expiry of 0, clears) the expiration of an existing key
+pub const OP_EXPIRE: &str = "Expire";
+/// Retrieves the number of seconds until a key expires, 0 meaning it never does
+pub const OP_TTL: &str = "Ttl";
+/// Lists the keys that start with a prefix, a page at a time. Each page returns the cursor to
+/// pass to get the next one, which is empty once there are no more keys
+pub const OP_SCAN: &str = "Scan";
+
+/// A client for a key-value provider running in the host. Obtained from
+/// [keyvalue](crate::Host::keyvalue)
+pub struct KeyValueClient<'a> {
+    host: &'a Host,
+    link_name: String,
+}
+
+impl<'a> KeyValueClient<'a> {
+    pub(crate) fn new(host: &'a Host, link_name: &str) -> KeyValueClient<'a> {
+        KeyValueClient {
+            host,
+            link_name: link_name.to_string(),
+        }
+    }
+
+    /// Retrieves the value of a key, or `None` if the key doesn't exist
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        let req = GetRequest {
+            key: key.to_string(),
+        };
+        let resp: GetResponse = deserialize(&self.send(OP_GET, serialize(req)?).await?)?;
+        Ok(if resp.exists { Some(resp.value) } else { None })
+    }
+
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.set_with_expiry(key, value, None).await
+    }
+
+    /// Sets the value of a key, which the store removes once the expiry has elapsed
+    pub async fn set_with_expiry(
+        &self,
+        key: &str,
+        value: &str,
+        expiry: Option<Duration>,
+    ) -> Result<()> {
+        let req = SetRequest {
+            key: key.to_string(),
+            value: value.to_string(),
+            expires_s: expires_s(expiry),
+        };
+        self.send(OP_SET, serialize(req)?).await?;
+        Ok(())
+    }
+
+    pub async fn del(&self, key: &str) -> Result<()> {
+        let req = DelRequest {
+            key: key.to_string(),
+        };
+        self.send(OP_DEL, serialize(req)?).await?;
+        Ok(())
+    }
+
+    /// Atomically adds to the numeric value of a key, returning the new value
+    pub async fn add(&self, key: &str, value: i32) -> Result<i32> {
+        let req = AddRequest {
+            key: key.to_string(),
+            value,
+        };
+        let resp: AddResponse = deserialize(&self.send(OP_ADD, serialize(req)?).await?)?;
+        Ok(resp.value)
+    }
+
+    /// Sets the value of a key if its current value is `expected` (`None` meaning the key
+    /// doesn't exist). Returns whether the value was set, and the value the key now has
+    pub async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+        expiry: Option<Duration>,
+    ) -> Result<(bool, Option<String>)> {
+        let req = CompareAndSwapRequest {
+            key: key.to_string(),
+            expected: expected.map(|e| e.to_string()),
+            value: value.to_string(),
+            expires_s: expires_s(expiry),
+        };
+        let resp: CompareAndSwapResponse =
+            deserialize(&self.send(OP_COMPARE_AND_SWAP, serialize(req)?).await?)?;
+        Ok((resp.swapped, resp.current))
+    }
+
+    /// Atomically adds to the numeric value of a key, as long as the result stays within the
+    /// (inclusive) bounds. Returns whether the increment was applied, and the key's value
+    pub async fn increment(
+        &self,
+        key: &str,
+        delta: i64,
+        min: Option<i64>,
+        max: Option<i64>,
+    ) -> Result<(bool, i64)> {
+        let req = IncrementRequest {
+            key: key.to_string(),
+            delta,
+            min,
+            max,
+        };
+        let resp: IncrementResponse =
+            deserialize(&self.send(OP_INCREMENT, serialize(req)?).await?)?;
+        Ok((resp.applied, resp.value))
+    }
+
+    /// Sets the expiry of an existing key, or makes it permanent if the expiry is `None`.
+    /// Returns `false` if the key doesn't exist
+    pub async fn expire(&self, key: &str, expiry: Option<Duration>) -> Result<bool> {
+        let req = ExpireRequest {
+            key: key.to_string(),
+            expires_s: expires_s(expiry),
+        };
+        let resp: TtlResponse = deserialize(&self.send(OP_EXPIRE, serialize(req)?).await?)?;
+        Ok(resp.exists)
+    }
+
+    /// Retrieves the time left before a key expires. Returns `None` if the key doesn't exist,
+    /// and a zero duration if it never expires
+    pub async fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        let req = GetRequest {
+            key: key.to_string(),
+        };
+        let resp: TtlResponse = deserialize(&self.send(OP_TTL, serialize(req)?).await?)?;
+        Ok(if resp.exists {
+            Some(Duration::from_secs(resp.expires_s.max(0) as u64))
+        } else {
+            None
+        })
+    }
+
+    /// Retrieves up to `limit` keys starting with the prefix, after the given cursor (empty for
+    /// the first page). Returns the keys and the cursor for the next page, which is empty when
+    /// there are no more keys
+    pub async fn scan(
+        &self,
+        prefix: &str,
+        cursor: &str,
+        limit: u32,
+    ) -> Result<(Vec<String>, String)> {
+        let req = ScanRequest {
+            prefix: prefix.to_string(),
+            cursor: cursor.to_string(),
+            limit,
+        };
+        let resp: ScanResponse = deserialize(&self.send(OP_SCAN, serialize(req)?).await?)?;
+        Ok((resp.keys, resp.cursor))
+    }
+
+    async fn send(&self, op: &str, payload: Vec<u8>) -> Result<Vec<u8>> {
+        self.host
+            .call_provider(KEYVALUE_CONTRACT, &self.link_name, op, payload)
+            .await
+    }
+}
+
+/// A client for a message broker provider running in the host. Obtained from
+/// [messaging](crate::Host::messaging)
+pub struct MessagingClient<'a> {
+    host: &'a Host,
+    link_name: String,
+}
+
+impl<'a> MessagingClient<'a> {
+    pub(crate) fn new(host: &'a Host, link_name: &str) -> MessagingClient<'a> {
+        MessagingClient {
+            host,
+            link_name: link_name.to_string(),
+        }
+    }
+
+    /// Publishes a message on a subject, optionally naming a subject for replies
+    pub async fn publish(&self, subject: &str, reply_to: Option<&str>, body: &[u8]) -> Result<()> {
+        let msg = PublishMessage {
+            message: BrokerMessage {
+                subject: subject.to_string(),
+                reply_to: reply_to.unwrap_or_default().to_string(),
+                body: body.to_vec(),
+            },
+        };
+        self.send(OP_PUBLISH_MESSAGE, serialize(msg)?).await?;
+        Ok(())
+    }
+
+    /// Sends a request on a subject and waits up to the timeout for the body of the reply
+    pub async fn request(&self, subject: &str, body: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        let msg = RequestMessage {
+            subject: subject.to_string(),
+            body: body.to_vec(),
+            timeout_ms: timeout.as_millis() as i64,
+        };
+        let reply: BrokerMessage =
+            deserialize(&self.send(OP_PERFORM_REQUEST, serialize(msg)?).await?)?;
+        Ok(reply.body)
+    }
+
+    async fn send(&self, op: &str, payload: Vec<u8>) -> Result<Vec<u8>> {
+        self.host
+            .call_provider(MESSAGING_CONTRACT, &self.link_name, op, payload)
+            .await
+    }
+}
+
+// The key-value contract uses 0 for "never expires"
+fn expires_s(expiry: Option<Duration>) -> i32 {
+    expiry
+        .map(|e| e.as_secs().max(1).min(i32::MAX as u64) as i32)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::expires_s;
+    use std::time::Duration;
+
+    #[test]
+    fn expiry_is_whole_seconds_and_never_zero() {
+        assert_eq!(0, expires_s(None));
+        assert_eq!(1, expires_s(Some(Duration::from_millis(200))));
+        assert_eq!(90, expires_s(Some(Duration::from_secs(90))));
+    }
+}
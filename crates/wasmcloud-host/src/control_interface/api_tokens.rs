@@ -0,0 +1,192 @@
+// A host that requires API tokens (see `control_interface::tokens`) refuses any control request
+// that doesn't carry a valid token from a trusted issuer, for the host's namespace, with a scope
+// covering the request. The host's own key is always a trusted issuer. Tokens are revoked by ID,
+// with a revocation signed by a trusted issuer: revocations are published to the whole namespace,
+// and a starting host collects those held by the hosts already running, so a revoked token stays
+// refused across the lattice for as long as any of its hosts is up. Tokens revoked with
+// `HostBuilder::with_revoked_api_token` are refused by that host from the moment it starts.
+
+use ::control_interface::tokens::{decode_revocation, decode_token, open, TokenScope};
+use std::collections::HashSet;
+
+/// The issuers a host trusts to mint API tokens, and the IDs of the tokens it refuses
+#[derive(Clone, Debug, Default)]
+pub struct ApiTokenPolicy {
+    pub issuers: Vec<String>,
+    pub revoked: HashSet<String>,
+    // The signed revocations the host has honored, which it hands on to hosts that ask
+    pub(crate) revocations: Vec<String>,
+}
+
+impl ApiTokenPolicy {
+    /// Honors a revocation, if it was signed by a trusted issuer for the given namespace.
+    /// Returns whether the token wasn't already revoked
+    pub(crate) fn revoke(
+        &mut self,
+        namespace: &str,
+        revocation: &str,
+    ) -> std::result::Result<bool, String> {
+        let claims = decode_revocation(revocation).map_err(|e| e.to_string())?;
+        if !self.issuers.contains(&claims.iss) {
+            return Err("Revocation was not issued by a trusted key".to_string());
+        }
+        if claims.ns != namespace {
+            return Err(format!("Revocation is not for namespace {}", namespace));
+        }
+        if !self.revoked.insert(claims.revoked) {
+            return Ok(false);
+        }
+        self.revocations.push(revocation.to_string());
+        Ok(true)
+    }
+
+    /// Checks the token on a control request, returning the request's payload if the token
+    /// allows it
+    pub(crate) fn authorize(
+        &self,
+        namespace: &str,
+        required: TokenScope,
+        data: &[u8],
+    ) -> std::result::Result<Vec<u8>, String> {
+        let envelope = open(data).ok_or("Request carries no API token")?;
+        let claims = decode_token(&envelope.token).map_err(|e| e.to_string())?;
+        if !self.issuers.contains(&claims.iss) {
+            return Err("API token was not issued by a trusted key".to_string());
+        }
+        if self.revoked.contains(&claims.jti) {
+            return Err("API token has been revoked".to_string());
+        }
+        if claims.ns != namespace {
+            return Err(format!("API token is not valid in namespace {}", namespace));
+        }
+        if !claims.scope.allows(required) {
+            return Err(format!(
+                "API token scope {:?} does not allow this request, which needs {:?}",
+                claims.scope, required
+            ));
+        }
+        Ok(envelope.body)
+    }
+}
+
+/// The scope a control request on the given subject needs. Requests on subjects that aren't
+/// known here (such as those of lattice extensions) need the admin scope
+pub(crate) fn required_scope(prefix: &Option<String>, host: &str, subject: &str) -> TokenScope {
+    use super::handlers::InstanceRequest;
+    use ::control_interface::broker::*;
+
+    if let Some((_, request)) = super::ctlactor::instance_request(prefix, subject) {
+        return match request {
+            InstanceRequest::Health | InstanceRequest::Config => TokenScope::ReadOnly,
+            InstanceRequest::Quiesce | InstanceRequest::Resume => TokenScope::Operator,
+        };
+    }
+    let read_only = [
+        queries::host_inventory(prefix, host),
+        queries::host_inventory_delta(prefix, host),
+        queries::dependency_graph(prefix, host),
+        queries::host_config(prefix, host),
+        queries::host_capabilities(prefix, host),
+        queries::linkdefinitions(prefix),
+        queries::claims(prefix),
+        queries::hosts(prefix),
+        provider_auction_subject(prefix),
+        actor_auction_subject(prefix),
+    ];
+    let operator = [
+        commands::start_actor(prefix, host),
+        commands::start_provider(prefix, host),
+        commands::set_log_level(prefix, host),
+        commands::set_trace_sampling(prefix),
+    ];
+    if read_only.iter().any(|s| s == subject) {
+        TokenScope::ReadOnly
+    } else if operator.iter().any(|s| s == subject) {
+        TokenScope::Operator
+    } else {
+        TokenScope::Admin
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{required_scope, ApiTokenPolicy};
+    use ::control_interface::broker::{commands, queries};
+    use ::control_interface::tokens::{mint_revocation, mint_token, seal, TokenScope};
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn tokens_are_checked_against_the_policy() {
+        let host = KeyPair::new_server();
+        let prefix = Some("prod".to_string());
+        let mut policy = ApiTokenPolicy {
+            issuers: vec![host.public_key()],
+            ..Default::default()
+        };
+        let token = mint_token(&host, "dashboard", &prefix, TokenScope::ReadOnly, None).unwrap();
+        let request = seal(&token, b"{}".to_vec());
+
+        let inventory = required_scope(
+            &prefix,
+            &host.public_key(),
+            &queries::host_inventory(&prefix, &host.public_key()),
+        );
+        let stop = required_scope(
+            &prefix,
+            &host.public_key(),
+            &commands::stop_actor(&prefix, &host.public_key()),
+        );
+        assert_eq!(TokenScope::ReadOnly, inventory);
+        assert_eq!(TokenScope::Admin, stop);
+        assert_eq!(
+            b"{}".to_vec(),
+            policy.authorize("prod", inventory, &request).unwrap()
+        );
+        assert!(policy.authorize("prod", stop, &request).is_err());
+        assert!(policy.authorize("dev", inventory, &request).is_err());
+        assert!(policy.authorize("prod", inventory, b"{}").is_err());
+
+        let stranger = KeyPair::new_server();
+        let forged = mint_token(&stranger, "x", &prefix, TokenScope::Admin, None).unwrap();
+        assert!(policy
+            .authorize("prod", stop, &seal(&forged, vec![]))
+            .is_err());
+
+        let id = ::control_interface::tokens::decode_token(&token)
+            .unwrap()
+            .jti;
+        policy.revoked.insert(id);
+        assert!(policy.authorize("prod", inventory, &request).is_err());
+    }
+
+    #[test]
+    fn revocations_need_a_trusted_issuer() {
+        let cluster = KeyPair::new_account();
+        let prefix = Some("prod".to_string());
+        let mut policy = ApiTokenPolicy {
+            issuers: vec![cluster.public_key()],
+            ..Default::default()
+        };
+        let token = mint_token(&cluster, "ci", &prefix, TokenScope::Admin, None).unwrap();
+        let request = seal(&token, vec![]);
+        let id = ::control_interface::tokens::decode_token(&token)
+            .unwrap()
+            .jti;
+
+        let stranger = mint_revocation(&KeyPair::new_account(), &id, &prefix).unwrap();
+        assert!(policy.revoke("prod", &stranger).is_err());
+        let elsewhere = mint_revocation(&cluster, &id, &Some("dev".to_string())).unwrap();
+        assert!(policy.revoke("prod", &elsewhere).is_err());
+        assert!(policy
+            .authorize("prod", TokenScope::Admin, &request)
+            .is_ok());
+
+        let revocation = mint_revocation(&cluster, &id, &prefix).unwrap();
+        assert_eq!(Ok(true), policy.revoke("prod", &revocation));
+        assert_eq!(Ok(false), policy.revoke("prod", &revocation));
+        assert_eq!(vec![revocation], policy.revocations);
+        assert!(policy
+            .authorize("prod", TokenScope::Admin, &request)
+            .is_err());
+    }
+}
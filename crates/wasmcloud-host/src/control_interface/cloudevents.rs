@@ -0,0 +1,211 @@
+// Hosts publish their events as plain JSON by default, in a shape that only wasmCloud tooling
+// knows. They can instead publish them as CloudEvents (https://cloudevents.io), which functions,
+// SIEMs, and other event routers understand without any knowledge of the host. Each kind of
+// event gets a stable type name (`dev.wascc.lattice.actor_started`, and so on), and its payload
+// (the event's fields) is described by a versioned schema, named in the event's `dataschema`.
+// Payload schemas only change in ways that existing consumers can ignore (such as new fields)
+// within a version.
+//
+// Events can be encoded in the CloudEvents JSON format or, for consumers that prefer it, the
+// CloudEvents protobuf format (https://github.com/cloudevents/spec/blob/v1.0.1/protobuf-format.md),
+// which is encoded here by hand since the message is small and fixed.
+
+use super::events::PublishedEvent;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The prefix of the type names of the events published by hosts
+pub const EVENT_TYPE_PREFIX: &str = "dev.wascc.lattice";
+/// The version of the schema of each event's payload
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+const SPEC_VERSION: &str = "1.0";
+const DATA_CONTENT_TYPE: &str = "application/json";
+
+/// The encoding in which a host publishes its events
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventFormat {
+    /// The host's own JSON encoding of `PublishedEvent`
+    Json,
+    /// CloudEvents, in the CloudEvents JSON format
+    CloudEvents,
+    /// CloudEvents, in the CloudEvents protobuf format
+    CloudEventsProtobuf,
+}
+
+impl Default for EventFormat {
+    fn default() -> Self {
+        EventFormat::Json
+    }
+}
+
+impl EventFormat {
+    /// The content type of events in this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            EventFormat::Json => "application/json",
+            EventFormat::CloudEvents => "application/cloudevents+json",
+            EventFormat::CloudEventsProtobuf => "application/cloudevents+protobuf",
+        }
+    }
+
+    /// Encodes an event in this format
+    pub fn encode(&self, event: &PublishedEvent) -> Vec<u8> {
+        match self {
+            EventFormat::Json => serde_json::to_vec(event).unwrap(),
+            EventFormat::CloudEvents => serde_json::to_vec(&CloudEvent::from(event)).unwrap(),
+            EventFormat::CloudEventsProtobuf => CloudEvent::from(event).to_protobuf(),
+        }
+    }
+}
+
+/// A host event as a CloudEvent
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    /// The host that published the event, as `/hosts/{host ID}`
+    pub source: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub datacontenttype: String,
+    pub dataschema: String,
+    /// The actor or provider the event is about, if it is about one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub time: String,
+    /// The event's fields
+    pub data: serde_json::Value,
+}
+
+impl From<&PublishedEvent> for CloudEvent {
+    fn from(published: &PublishedEvent) -> CloudEvent {
+        let name = type_name(&published.event.name());
+        let data = match serde_json::to_value(&published.event) {
+            Ok(serde_json::Value::Object(o)) => o.into_iter().next().map(|(_, v)| v),
+            _ => None,
+        };
+        CloudEvent {
+            specversion: SPEC_VERSION.to_string(),
+            id: uuid::Uuid::new_v4().to_string(),
+            source: format!("/hosts/{}", published.header.host_origin),
+            event_type: format!("{}.{}", EVENT_TYPE_PREFIX, name),
+            datacontenttype: DATA_CONTENT_TYPE.to_string(),
+            dataschema: format!("urn:wascc:lattice:{}:v{}", name, EVENT_SCHEMA_VERSION),
+            subject: published.event.subject().map(|s| s.to_string()),
+            time: Utc
+                .timestamp(published.header.timestamp as i64, 0)
+                .to_rfc3339(),
+            data: data.unwrap_or_else(|| serde_json::json!({})),
+        }
+    }
+}
+
+impl CloudEvent {
+    /// Encodes the event as an `io.cloudevents.v1.CloudEvent` protobuf message, with the
+    /// payload as JSON text
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        put_bytes(&mut buf, 1, self.id.as_bytes());
+        put_bytes(&mut buf, 2, self.source.as_bytes());
+        put_bytes(&mut buf, 3, self.specversion.as_bytes());
+        put_bytes(&mut buf, 4, self.event_type.as_bytes());
+        // Attribute values are a oneof, where 3 is a string, 5 a URI, and 7 a timestamp
+        let mut attributes = vec![
+            (
+                "datacontenttype",
+                3,
+                self.datacontenttype.as_bytes().to_vec(),
+            ),
+            ("dataschema", 5, self.dataschema.as_bytes().to_vec()),
+        ];
+        if let Some(ref subject) = self.subject {
+            attributes.push(("subject", 3, subject.as_bytes().to_vec()));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(&self.time) {
+            let mut timestamp = vec![];
+            put_varint(&mut timestamp, 1 << 3);
+            put_varint(&mut timestamp, time.timestamp() as u64);
+            attributes.push(("time", 7, timestamp));
+        }
+        for (name, kind, value) in attributes {
+            let mut attribute_value = vec![];
+            put_bytes(&mut attribute_value, kind, &value);
+            let mut entry = vec![];
+            put_bytes(&mut entry, 1, name.as_bytes());
+            put_bytes(&mut entry, 2, &attribute_value);
+            put_bytes(&mut buf, 5, &entry);
+        }
+        put_bytes(&mut buf, 7, self.data.to_string().as_bytes());
+        buf
+    }
+}
+
+// ActorStarted becomes actor_started
+fn type_name(variant: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in variant.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+// Writes a length-delimited field
+fn put_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buf, (field << 3) | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{put_varint, CloudEvent, EventFormat};
+    use crate::ControlEvent;
+
+    #[test]
+    fn events_become_cloud_events() {
+        let published = ControlEvent::ActorStarted {
+            actor: "Mxxx".to_string(),
+            image_ref: None,
+        }
+        .into_published("Nhost");
+        let ce = CloudEvent::from(&published);
+        assert_eq!("dev.wascc.lattice.actor_started", ce.event_type);
+        assert_eq!("urn:wascc:lattice:actor_started:v1", ce.dataschema);
+        assert_eq!("/hosts/Nhost", ce.source);
+        assert_eq!(Some("Mxxx".to_string()), ce.subject);
+        assert_eq!("Mxxx", ce.data["actor"]);
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&EventFormat::CloudEvents.encode(&published)).unwrap();
+        assert_eq!("dev.wascc.lattice.actor_started", json["type"]);
+
+        let stopped = ControlEvent::HostStopped.into_published("Nhost");
+        assert_eq!(serde_json::json!({}), CloudEvent::from(&stopped).data);
+
+        // The protobuf encoding starts with the event's ID as field 1
+        let pb = ce.to_protobuf();
+        assert_eq!(0x0a, pb[0]);
+        assert_eq!(ce.id.len(), pb[1] as usize);
+        assert_eq!(ce.id.as_bytes(), &pb[2..2 + ce.id.len()]);
+
+        let mut varint = vec![];
+        put_varint(&mut varint, 300);
+        assert_eq!(vec![0xac, 0x02], varint);
+    }
+}
@@ -1,8 +1,18 @@
-use crate::hlreg::HostLocalSystemService;
+use super::api_tokens::{required_scope, ApiTokenPolicy};
+use super::cloudevents::EventFormat;
+use super::extensions::{handle_extension_message, LatticeExtension, ReplayWindow};
+use crate::generated::core::serialize;
+use crate::hlreg::{HostLocalSystemService, Shutdown};
 use crate::messagebus::{NatsMessage, NatsSubscriber};
+use crate::shutdown::Pending;
+use crate::watchdog::Ping;
 use crate::ControlEvent;
 use actix::prelude::*;
+use futures::StreamExt;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use wascap::prelude::KeyPair;
 
 #[derive(Default)]
@@ -12,6 +22,12 @@ pub struct ControlInterface {
     key: Option<KeyPair>,
     options: ControlOptions,
     subscribers: HashMap<String, Addr<NatsSubscriber>>,
+    extensions: Vec<Box<dyn LatticeExtension>>,
+    // The IDs of the extension messages delivered, kept until they expire
+    replays: ReplayWindow,
+    // Events being published, and those published since the connection was last flushed
+    publishing: Pending,
+    unflushed: Arc<AtomicUsize>,
 }
 
 #[derive(Message)]
@@ -21,14 +37,33 @@ pub struct Initialize {
     pub control_options: ControlOptions,
     pub key: KeyPair,
     pub ns_prefix: String,
+    pub extensions: Vec<Box<dyn LatticeExtension>>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ControlOptions {
     pub oci_allow_latest: bool,
     pub host_labels: HashMap<String, String>,
-    pub max_actors: u16,    // Currently unused
-    pub max_providers: u16, // Currently unused
+    pub allow_live_updates: bool,
+    pub rpc_timeout: Duration,
+    pub heartbeat_interval: Duration,
+    pub lattice_rpc: bool,
+    pub event_format: EventFormat,
+    /// When set, control requests must carry an API token that this policy accepts
+    pub api_tokens: Option<ApiTokenPolicy>,
+    pub(crate) upgrade: crate::self_update::UpgradeOptions,
+    /// The host's limits, reported to clients asking for its configuration
+    pub(crate) limits: crate::limits::HostLimits,
+    /// The host's enabled optional subsystems, reported to clients asking for its capabilities
+    pub features: Vec<String>,
+}
+
+/// Refuses an API token from now on, given a revocation signed by a trusted issuer, and
+/// publishes the revocation to the rest of the namespace
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct RevokeApiToken {
+    pub revocation: String,
 }
 
 #[derive(Message)]
@@ -37,6 +72,14 @@ pub struct PublishEvent {
     pub event: ControlEvent,
 }
 
+/// Waits for the events being published, up to the deadline, and flushes them to the lattice.
+/// Answers with the number of events that couldn't be confirmed as sent
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub(crate) struct FlushEvents {
+    pub deadline: Duration,
+}
+
 impl Supervised for ControlInterface {}
 
 impl SystemService for ControlInterface {
@@ -55,24 +98,57 @@ impl Actor for ControlInterface {
 impl Handler<PublishEvent> for ControlInterface {
     type Result = ResponseActFuture<Self, ()>;
 
-    fn handle(&mut self, msg: PublishEvent, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: PublishEvent, ctx: &mut Context<Self>) -> Self::Result {
+        let key = match self.key {
+            Some(ref k) => k,
+            None => return Box::pin(async move {}.into_actor(self)),
+        };
+        let evt = msg.event.into_published(&key.public_key());
+        // The system actor receives its events whether or not the control interface is enabled
+        if let Some(actor) = crate::system_actor::subscriber(&key.public_key(), &evt) {
+            let kp = KeyPair::from_seed(&key.seed().unwrap()).unwrap();
+            let evt = evt.clone();
+            ctx.spawn(
+                async move {
+                    if let Err(e) = crate::system_actor::notify(
+                        &kp,
+                        &actor,
+                        crate::system_actor::OP_HANDLE_LATTICE_EVENT,
+                        &evt,
+                    )
+                    .await
+                    {
+                        warn!("{}", e);
+                    }
+                }
+                .into_actor(self),
+            );
+        }
+        for triggered in crate::automation::triggered(&key.public_key(), &evt) {
+            let kp = KeyPair::from_seed(&key.seed().unwrap()).unwrap();
+            ctx.spawn(crate::automation::run(kp, triggered).into_actor(self));
+        }
         if self.client.is_none() {
             return Box::pin(async move {}.into_actor(self));
         }
-        let evt = msg
-            .event
-            .into_published(&self.key.as_ref().unwrap().public_key());
         let prefix = Some(self.ns_prefix.to_string());
+        let payload = self.options.event_format.encode(&evt);
         if let Some(ref nc) = self.client {
             let nc = nc.clone();
+            let publishing = self.publishing.track();
+            let unflushed = self.unflushed.clone();
             Box::pin(
                 async move {
-                    let _ = nc
+                    let published = nc
                         .publish(
                             &::control_interface::broker::control_event(&prefix),
-                            serde_json::to_string(&evt).unwrap(),
+                            payload,
                         )
                         .await;
+                    if published.is_ok() {
+                        unflushed.fetch_add(1, Ordering::SeqCst);
+                    }
+                    drop(publishing);
                 }
                 .into_actor(self),
             )
@@ -82,6 +158,91 @@ impl Handler<PublishEvent> for ControlInterface {
     }
 }
 
+impl Handler<FlushEvents> for ControlInterface {
+    type Result = ResponseActFuture<Self, usize>;
+
+    fn handle(&mut self, msg: FlushEvents, _ctx: &mut Context<Self>) -> Self::Result {
+        let nc = match self.client {
+            Some(ref nc) => nc.clone(),
+            None => return Box::pin(async move { 0 }.into_actor(self)),
+        };
+        let publishing = self.publishing.clone();
+        let unflushed = self.unflushed.clone();
+        Box::pin(
+            async move {
+                let unpublished = publishing.settle(msg.deadline).await;
+                let flushed = actix_rt::time::timeout(msg.deadline, flush(&nc, &unflushed)).await;
+                if let Ok(Ok(())) = flushed {
+                    unpublished
+                } else {
+                    unpublished + unflushed.load(Ordering::SeqCst)
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<RevokeApiToken> for ControlInterface {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RevokeApiToken, ctx: &mut Context<Self>) -> Self::Result {
+        self.revoke(&msg.revocation)?;
+        if let Some(ref nc) = self.client {
+            let nc = nc.clone();
+            let subject = ::control_interface::broker::commands::revoke_api_token(&Some(
+                self.ns_prefix.to_string(),
+            ));
+            ctx.spawn(
+                async move {
+                    if nc
+                        .publish(&subject, msg.revocation.as_bytes())
+                        .await
+                        .is_err()
+                    {
+                        warn!("Failed to publish API token revocation to the lattice");
+                    }
+                }
+                .into_actor(self),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ControlInterface {
+    // Honors a revocation, if the host requires API tokens
+    fn revoke(&mut self, revocation: &str) -> Result<(), String> {
+        let policy = match self.options.api_tokens {
+            Some(ref mut policy) => policy,
+            None => return Err("This host does not require API tokens".to_string()),
+        };
+        if policy.revoke(&self.ns_prefix, revocation)? {
+            info!("Revoked API token");
+        }
+        Ok(())
+    }
+}
+
+impl Handler<Ping> for ControlInterface {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Context<Self>) -> Self::Result {}
+}
+
+impl Handler<Shutdown> for ControlInterface {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) -> Self::Result {
+        info!("Control interface shutting down");
+        for (_subject, subscriber) in self.subscribers.drain() {
+            subscriber.do_send(Shutdown);
+        }
+        self.client = None;
+        ctx.stop();
+    }
+}
+
 impl Handler<NatsMessage> for ControlInterface {
     type Result = ResponseActFuture<Self, ()>;
 
@@ -92,14 +253,88 @@ impl Handler<NatsMessage> for ControlInterface {
         let prefix = Some(self.ns_prefix.to_string());
         let host = self.key.as_ref().unwrap().public_key();
 
-        let msg = msg.msg;
+        let mut msg = msg.msg;
         let subject = msg.subject.to_string();
+        // Revocations carry their own signatures, and are passed between hosts without tokens
+        if subject == commands::revoke_api_token(&prefix) {
+            if let Ok(revocation) = std::str::from_utf8(&msg.data) {
+                if let Err(e) = self.revoke(revocation) {
+                    debug!("Ignoring API token revocation: {}", e);
+                }
+            }
+            return Box::pin(async {}.into_actor(self));
+        }
+        if subject == queries::revocations(&prefix) {
+            let revocations = self
+                .options
+                .api_tokens
+                .as_ref()
+                .map(|p| p.revocations.clone())
+                .unwrap_or_default();
+            return Box::pin(
+                async move {
+                    let _ = msg
+                        .respond(&serde_json::to_vec(&revocations).unwrap())
+                        .await;
+                }
+                .into_actor(self),
+            );
+        }
+        if let Some(ref policy) = self.options.api_tokens {
+            let required = required_scope(&prefix, &host, &subject);
+            match policy.authorize(&self.ns_prefix, required, &msg.data) {
+                Ok(body) => msg.data = body,
+                Err(reason) => {
+                    warn!("Refused control request on {}: {}", subject, reason);
+                    let denied = ::control_interface::tokens::AccessDenied {
+                        access_denied: reason,
+                    };
+                    return Box::pin(
+                        async move {
+                            let _ = msg.respond(&serialize(denied).unwrap()).await;
+                        }
+                        .into_actor(self),
+                    );
+                }
+            }
+        }
+        let ext_prefix = format!("{}.", extension_prefix(&prefix));
+        if subject.starts_with(&ext_prefix) {
+            let reply = handle_extension_message(
+                &self.extensions,
+                &mut self.replays,
+                crate::clock::now_secs(&host),
+                &subject[ext_prefix.len()..],
+                &msg.data,
+            );
+            if let Some(ref e) = reply.error {
+                warn!("Lattice extension message on {} failed: {}", subject, e);
+            }
+            return Box::pin(
+                async move {
+                    let _ = msg.respond(&serialize(reply).unwrap()).await;
+                }
+                .into_actor(self),
+            );
+        }
         let allow_latest = self.options.oci_allow_latest;
+        let seed = self.key.as_ref().unwrap().seed().unwrap();
+        let namespace = self.ns_prefix.to_string();
+        let options = self.options.clone();
         let nc = self.client.clone();
+        let unflushed = self.unflushed.clone();
         Box::pin(
             async move {
                 if subject == queries::host_inventory(&prefix, &host) {
                     handle_host_inventory_query(&host, &msg).await
+                } else if subject == queries::host_inventory_delta(&prefix, &host) {
+                    handle_inventory_delta_query(&host, &msg).await
+                } else if subject == queries::dependency_graph(&prefix, &host) {
+                    handle_dependency_graph_query(&host, &msg).await
+                } else if subject == queries::host_config(&prefix, &host) {
+                    handle_host_config_query(&host, &msg, &prefix, &options).await
+                } else if subject == queries::host_capabilities(&prefix, &host) {
+                    handle_host_capabilities_query(&host, &msg, &options).await
                 } else if subject == queries::linkdefinitions(&prefix) {
                     handle_linkdefs_query(&host, &msg).await
                 } else if subject == queries::claims(&prefix) {
@@ -118,10 +353,18 @@ impl Handler<NatsMessage> for ControlInterface {
                     handle_start_provider(&host, &msg, allow_latest).await
                 } else if subject == commands::stop_actor(&prefix, &host) {
                     handle_stop_actor(&host, &msg).await
+                } else if subject == commands::set_log_level(&prefix, &host) {
+                    handle_set_log_level(&host, &msg).await
+                } else if subject == commands::prepare_upgrade(&prefix, &host) {
+                    handle_prepare_upgrade(&seed, &namespace, &msg, &options.upgrade).await
+                } else if subject == commands::set_trace_sampling(&prefix) {
+                    handle_set_trace_sampling(&host, &msg).await
                 } else if subject == queries::hosts(&prefix) {
                     handle_host_probe(&host, &msg).await
+                } else if let Some((instance, request)) = instance_request(&prefix, &subject) {
+                    handle_provider_instance_request(&host, &msg, &instance, request).await
                 }
-                let _ = nc.as_ref().unwrap().flush().await;
+                let _ = flush(nc.as_ref().unwrap(), &unflushed).await;
             }
             .into_actor(self),
         )
@@ -142,61 +385,28 @@ impl Handler<Initialize> for ControlInterface {
         self.client = msg.client;
         self.options = msg.control_options;
         self.ns_prefix = msg.ns_prefix;
-
-        use ::control_interface::broker::*;
+        self.extensions = msg.extensions;
 
         let host_id = self.key.as_ref().unwrap().public_key();
 
         let prefix = Some(self.ns_prefix.to_string());
 
-        self.subscribers.insert(
-            queries::linkdefinitions(&prefix),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            queries::host_inventory(&prefix, &host_id),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers
-            .insert(queries::claims(&prefix), NatsSubscriber::default().start());
-        self.subscribers.insert(
-            queries::linkdefinitions(&prefix),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            provider_auction_subject(&prefix),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            actor_auction_subject(&prefix),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            commands::start_actor(&prefix, &host_id),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            commands::stop_actor(&prefix, &host_id),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            commands::start_provider(&prefix, &host_id),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            commands::stop_provider(&prefix, &host_id),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers.insert(
-            commands::update_actor(&prefix, &host_id),
-            NatsSubscriber::default().start(),
-        );
-        self.subscribers
-            .insert(queries::hosts(&prefix), NatsSubscriber::default().start());
+        for subject in control_subjects(&prefix, &host_id, &self.extensions) {
+            self.subscribers
+                .insert(subject, NatsSubscriber::default().start());
+        }
 
         let nc = self.client.as_ref().unwrap().clone();
         let subscribers = self.subscribers.clone();
         let target = ctx.address().recipient();
+        let sync = self.options.api_tokens.as_ref().map(|_| {
+            collect_revocations(
+                nc.clone(),
+                host_id.to_string(),
+                ::control_interface::broker::queries::revocations(&prefix),
+                self.options.rpc_timeout,
+            )
+        });
         Box::pin(
             async move {
                 for (subject, subscriber) in subscribers.iter() {
@@ -210,7 +420,160 @@ impl Handler<Initialize> for ControlInterface {
                         .await;
                 }
             }
-            .into_actor(self),
+            .into_actor(self)
+            .map(move |_, act, ctx| {
+                // Hosts that require API tokens pick up the revocations made before they started
+                if let Some(sync) = sync {
+                    ctx.spawn(sync.into_actor(act).map(|revocations, act, _ctx| {
+                        for revocation in revocations {
+                            let _ = act.revoke(&revocation);
+                        }
+                    }));
+                }
+            }),
         )
     }
 }
+
+// The subjects the control interface subscribes to for a host
+pub(crate) fn control_subjects(
+    prefix: &Option<String>,
+    host_id: &str,
+    extensions: &[Box<dyn LatticeExtension>],
+) -> Vec<String> {
+    use ::control_interface::broker::*;
+
+    let mut subjects = vec![
+        queries::linkdefinitions(prefix),
+        queries::host_inventory(prefix, host_id),
+        queries::host_inventory_delta(prefix, host_id),
+        queries::host_config(prefix, host_id),
+        queries::host_capabilities(prefix, host_id),
+        queries::dependency_graph(prefix, host_id),
+        queries::claims(prefix),
+        provider_auction_subject(prefix),
+        actor_auction_subject(prefix),
+        commands::start_actor(prefix, host_id),
+        commands::stop_actor(prefix, host_id),
+        commands::start_provider(prefix, host_id),
+        commands::stop_provider(prefix, host_id),
+        commands::update_actor(prefix, host_id),
+        commands::set_log_level(prefix, host_id),
+        commands::prepare_upgrade(prefix, host_id),
+        commands::set_trace_sampling(prefix),
+        commands::revoke_api_token(prefix),
+        queries::hosts(prefix),
+        queries::revocations(prefix),
+        // Provider instances are addressed by ID, so every host hears these and only the host
+        // running the instance replies
+        queries::provider_health(prefix, "*"),
+        queries::provider_config(prefix, "*"),
+        commands::quiesce_provider(prefix, "*"),
+        commands::resume_provider(prefix, "*"),
+    ];
+    for ext in extensions.iter() {
+        for subject in ext.subjects() {
+            subjects.push(extension_subject(prefix, ext.name(), &subject));
+        }
+    }
+    subjects
+}
+
+// Matches a subject against the provider instance subjects, returning the instance ID it
+// addresses and the request it makes
+pub(super) fn instance_request(
+    prefix: &Option<String>,
+    subject: &str,
+) -> Option<(String, super::handlers::InstanceRequest)> {
+    use super::handlers::InstanceRequest;
+    use ::control_interface::broker::*;
+
+    let patterns = vec![
+        (
+            queries::provider_health(prefix, "*"),
+            InstanceRequest::Health,
+        ),
+        (
+            queries::provider_config(prefix, "*"),
+            InstanceRequest::Config,
+        ),
+        (
+            commands::quiesce_provider(prefix, "*"),
+            InstanceRequest::Quiesce,
+        ),
+        (
+            commands::resume_provider(prefix, "*"),
+            InstanceRequest::Resume,
+        ),
+    ];
+    let tokens: Vec<_> = subject.split('.').collect();
+    patterns.into_iter().find_map(|(pattern, request)| {
+        let pattern: Vec<_> = pattern.split('.').collect();
+        if pattern.len() != tokens.len() {
+            return None;
+        }
+        let mut instance = None;
+        for (p, t) in pattern.iter().zip(tokens.iter()) {
+            if *p == "*" {
+                instance = Some(t.to_string());
+            } else if p != t {
+                return None;
+            }
+        }
+        instance.map(|i| (i, request))
+    })
+}
+
+// Asks the hosts already running for the API token revocations they hold, gathering replies
+// until the timeout
+async fn collect_revocations(
+    nc: nats::asynk::Connection,
+    host_id: String,
+    subject: String,
+    timeout: Duration,
+) -> Vec<String> {
+    let replies = match crate::inboxes::request_multi(&nc, &host_id, &subject, &[]).await {
+        Ok(replies) => replies,
+        Err(_) => return Vec::new(),
+    };
+    let replies: Vec<nats::asynk::Message> = replies
+        .take_until(actix_rt::time::delay_for(timeout))
+        .collect()
+        .await;
+    replies
+        .into_iter()
+        .filter_map(|m| serde_json::from_slice::<Vec<String>>(&m.data).ok())
+        .flatten()
+        .collect()
+}
+
+// Flushes the connection, after which the events published before it are known to have reached
+// the lattice
+async fn flush(nc: &nats::asynk::Connection, unflushed: &AtomicUsize) -> std::io::Result<()> {
+    let published = unflushed.load(Ordering::SeqCst);
+    nc.flush().await?;
+    unflushed.fetch_sub(published, Ordering::SeqCst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::instance_request;
+    use crate::control_interface::handlers::InstanceRequest;
+
+    #[test]
+    fn instance_subjects_are_matched() {
+        let prefix = Some("default".to_string());
+        let health = ::control_interface::broker::queries::provider_health(&prefix, "abc123");
+        assert_eq!(
+            Some(("abc123".to_string(), InstanceRequest::Health)),
+            instance_request(&prefix, &health)
+        );
+        let other = Some("other".to_string());
+        assert_eq!(None, instance_request(&other, &health));
+        assert_eq!(
+            None,
+            instance_request(&prefix, "wasmbus.ctl.default.get.hosts")
+        );
+    }
+}
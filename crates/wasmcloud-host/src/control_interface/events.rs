@@ -1,4 +1,3 @@
-use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -6,6 +5,9 @@ use std::collections::HashMap;
 pub struct EventHeader {
     pub host_origin: String,
     pub timestamp: u64,
+    /// The namespace, host, and issuer labels of the event, along with the host's static labels
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -19,6 +21,28 @@ pub struct PublishedEvent {
 pub enum ControlEvent {
     HostStarted,
     HostStopped,
+    /// The host has drained and written its upgrade journal, and is ready to be replaced
+    HostUpgradeReady {
+        journal: String,
+    },
+    /// The host has taken over the state of the host it replaced in an upgrade
+    HostUpgraded {
+        previous_host: String,
+        previous_version: String,
+    },
+    /// A host joined the lattice roster
+    LatticeMemberJoined {
+        host_id: String,
+    },
+    /// A host left the lattice roster, or was dropped from it for missing its announcements
+    LatticeMemberLeft {
+        host_id: String,
+    },
+    /// A host outside the lattice roster tried to join it or sent traffic, and was ignored
+    UnauthorizedHost {
+        host_id: String,
+        reason: String,
+    },
     ActorStarted {
         actor: String,
         image_ref: Option<String>,
@@ -26,6 +50,17 @@ pub enum ControlEvent {
     ActorStopped {
         actor: String,
     },
+    ActorStartRejected {
+        actor: String,
+        image_ref: Option<String>,
+        reason: String,
+    },
+    /// A new version of a running actor was refused, and the running version was left as it was
+    ActorUpdateRejected {
+        actor: String,
+        image_ref: Option<String>,
+        reason: String,
+    },
     ActorUpdateBegan {
         actor: String,
         old_revision: u32,
@@ -36,6 +71,41 @@ pub enum ControlEvent {
         old_revision: u32,
         new_revision: u32,
     },
+    ActorSloBreached {
+        actor: String,
+        reason: String,
+    },
+    ActorSloRecovered {
+        actor: String,
+    },
+    ActorInvocationFailed {
+        actor: String,
+        operation: String,
+        error: String,
+    },
+    /// A shadow actor answered a mirrored invocation differently from the actor it mirrors
+    ActorMirrorMismatch {
+        actor: String,
+        shadow: String,
+        operation: String,
+    },
+    ActorSnapshotCaptured {
+        actor: String,
+        operation: String,
+        path: String,
+        digest: String,
+    },
+    ArtifactPreloaded {
+        image_ref: String,
+        completed: usize,
+        total: usize,
+    },
+    ArtifactPreloadFailed {
+        image_ref: String,
+        reason: String,
+        completed: usize,
+        total: usize,
+    },
     ProviderStarted {
         contract_id: String,
         link_name: String,
@@ -47,6 +117,74 @@ pub enum ControlEvent {
         link_name: String,
         provider_id: String,
     },
+    ProviderQuiesced {
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+    },
+    ProviderResumed {
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+    },
+    SlowConsumer {
+        subject: String,
+        target: String,
+        pending: u64,
+        dropped: u64,
+    },
+    LinkFailover {
+        actor: String,
+        contract_id: String,
+        link_name: String,
+        from_provider: String,
+        to_provider: String,
+    },
+    LinkExpired {
+        actor: String,
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+    },
+    ProviderUpdated {
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+        image_ref: Option<String>,
+    },
+    ProviderStartRejected {
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+        image_ref: Option<String>,
+        reason: String,
+    },
+    /// A provider matched the host's denylist (by contract ID or public key) and was refused
+    ProviderDenied {
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+        image_ref: Option<String>,
+        entry: String,
+    },
+    /// A provider instance failed its health check during a heartbeat
+    ProviderUnhealthy {
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+        message: String,
+    },
+    /// A subsystem of the host stopped making progress. The host restarts the subsystems it can
+    /// restart safely
+    SubsystemStalled {
+        subsystem: String,
+        stalled_for_ms: u64,
+        restarted: bool,
+    },
+    /// A subsystem that had stalled is making progress again
+    SubsystemRecovered {
+        subsystem: String,
+    },
     Heartbeat {
         claims: Vec<wascap::jwt::Claims<wascap::jwt::Actor>>,
         entities: HashMap<String, RunState>,
@@ -66,10 +204,49 @@ pub enum TerminationReason {
 }
 
 impl ControlEvent {
+    /// The actor or provider the event is about, if it is about one
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            ControlEvent::ActorStarted { actor, .. }
+            | ControlEvent::ActorStopped { actor }
+            | ControlEvent::ActorStartRejected { actor, .. }
+            | ControlEvent::ActorUpdateRejected { actor, .. }
+            | ControlEvent::ActorUpdateBegan { actor, .. }
+            | ControlEvent::ActorUpdateCompleted { actor, .. }
+            | ControlEvent::ActorSloBreached { actor, .. }
+            | ControlEvent::ActorSloRecovered { actor }
+            | ControlEvent::ActorInvocationFailed { actor, .. }
+            | ControlEvent::ActorMirrorMismatch { actor, .. }
+            | ControlEvent::ActorSnapshotCaptured { actor, .. }
+            | ControlEvent::LinkFailover { actor, .. }
+            | ControlEvent::LinkExpired { actor, .. } => Some(actor),
+            ControlEvent::ProviderStarted { provider_id, .. }
+            | ControlEvent::ProviderStopped { provider_id, .. }
+            | ControlEvent::ProviderQuiesced { provider_id, .. }
+            | ControlEvent::ProviderResumed { provider_id, .. }
+            | ControlEvent::ProviderUpdated { provider_id, .. }
+            | ControlEvent::ProviderStartRejected { provider_id, .. }
+            | ControlEvent::ProviderDenied { provider_id, .. }
+            | ControlEvent::ProviderUnhealthy { provider_id, .. } => Some(provider_id),
+            ControlEvent::SlowConsumer { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// The name of the event's variant (for example, `ActorStarted`)
+    pub fn name(&self) -> String {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(s)) => s,
+            Ok(serde_json::Value::Object(o)) => o.keys().next().cloned().unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
     pub fn into_published(self, origin: &str) -> PublishedEvent {
         let header = EventHeader {
             host_origin: origin.to_string(),
-            timestamp: Utc::now().timestamp() as u64,
+            timestamp: crate::clock::now_secs(origin),
+            labels: crate::labels::labels_for(origin, self.subject()),
         };
         PublishedEvent {
             header,
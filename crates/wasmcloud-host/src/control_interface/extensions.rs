@@ -0,0 +1,366 @@
+use crate::dispatch::invocation_hash;
+use crate::errors::{self, ErrorKind};
+use crate::generated::core::{deserialize, serialize};
+use crate::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+use wascap::prelude::{Claims, KeyPair};
+
+/// How long an extension message can be delivered after it was signed
+pub const EXTENSION_MESSAGE_TTL: Duration = Duration::from_secs(60);
+
+// How far ahead of the host's clock a sender's clock can be
+const MAX_CLOCK_SKEW_SECS: u64 = 30;
+
+/// A lattice extension allows an embedder to handle its own platform-specific messages over the
+/// host's control plane connection instead of maintaining a second NATS client. Each extension
+/// owns the subjects beneath `wasmbus.ext.{namespace}.{name}`. The host subscribes to them for
+/// the lifetime of the host, verifies the signature of every message, decodes it, and sends the
+/// extension's reply back to the requester
+pub trait LatticeExtension: CloneLatticeExtension + Sync + Send {
+    /// The name of the extension, used as the first subject token beneath the extension prefix
+    fn name(&self) -> &str;
+
+    /// The subjects on which the extension receives messages, relative to its own prefix.
+    /// NATS wildcards are permitted. The default is every subject beneath the prefix
+    fn subjects(&self) -> Vec<String> {
+        vec![">".to_string()]
+    }
+
+    /// Consulted after a message's signature has been verified, allowing the extension to
+    /// restrict which keys may send it messages. The default accepts any valid signature
+    fn authorize(&self, _issuer: &str, _subject: &str) -> bool {
+        true
+    }
+
+    /// Handles a message, returning the payload of the reply
+    fn handle(&self, msg: &ExtensionMessage) -> std::result::Result<Vec<u8>, String>;
+}
+
+#[doc(hidden)]
+pub trait CloneLatticeExtension {
+    fn clone_extension(&self) -> Box<dyn LatticeExtension>;
+}
+
+impl<T> CloneLatticeExtension for T
+where
+    T: LatticeExtension + Clone + 'static,
+{
+    fn clone_extension(&self) -> Box<dyn LatticeExtension> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn LatticeExtension> {
+    fn clone(&self) -> Self {
+        self.clone_extension()
+    }
+}
+
+/// A message sent to a lattice extension. Messages are signed by the sender in the same way as
+/// invocations, so they can't be forged or redirected to a different subject. The signed claims
+/// expire `EXTENSION_MESSAGE_TTL` after the message is created, and a host delivers a message
+/// with a given ID only once, so a captured message can't be replayed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExtensionMessage {
+    pub id: String,
+    /// The subject of the message, relative to the extension's prefix
+    pub subject: String,
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+    /// The public key of the sender
+    pub issuer: String,
+    pub encoded_claims: String,
+}
+
+impl ExtensionMessage {
+    /// Creates a message for the named extension, serializing the payload and signing the
+    /// message with the sender's key
+    pub fn new(
+        kp: &KeyPair,
+        extension: &str,
+        subject: &str,
+        payload: &impl Serialize,
+    ) -> Result<ExtensionMessage> {
+        let id = format!("{}", Uuid::new_v4());
+        let issuer = kp.public_key();
+        let payload = serialize(payload)?;
+        let target_url = target_url(extension, subject);
+        let mut claims = Claims::<wascap::prelude::Invocation>::new(
+            issuer.to_string(),
+            id.to_string(),
+            &target_url,
+            &issuer,
+            &invocation_hash(&target_url, &issuer, &payload),
+        );
+        claims.expires = Some(claims.issued_at + EXTENSION_MESSAGE_TTL.as_secs());
+        Ok(ExtensionMessage {
+            id,
+            subject: subject.to_string(),
+            payload,
+            issuer: issuer.to_string(),
+            encoded_claims: claims.encode(kp)?,
+        })
+    }
+
+    /// Decodes the payload of the message
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T> {
+        deserialize(&self.payload)
+    }
+
+    /// Encodes the message for publication on the lattice
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serialize(self)
+    }
+
+    /// Verifies that the message was signed by its issuer, has not been tampered with, was
+    /// addressed to the given extension, and hasn't expired at `now` (in seconds since the
+    /// epoch). Returns the time at which the message expires
+    pub(crate) fn validate(&self, extension: &str, now: u64) -> Result<u64> {
+        let vr = wascap::jwt::validate_token::<wascap::prelude::Invocation>(&self.encoded_claims)?;
+        let claims = Claims::<wascap::prelude::Invocation>::decode(&self.encoded_claims)?;
+        if vr.cannot_use_yet || !vr.signature_valid {
+            return Err(errors::new(ErrorKind::Authorization(
+                "Extension message claims are invalid".into(),
+            )));
+        }
+        // Expiry is checked against the host's clock rather than the system's, and messages
+        // must expire soon after they were signed so that the host can remember their IDs
+        let expires = match claims.expires {
+            Some(exp)
+                if exp >= claims.issued_at
+                    && exp - claims.issued_at <= EXTENSION_MESSAGE_TTL.as_secs() =>
+            {
+                exp
+            }
+            _ => {
+                return Err(errors::new(ErrorKind::Authorization(
+                    "Extension message claims must expire within the message lifetime".into(),
+                )))
+            }
+        };
+        if expires < now || claims.issued_at > now + MAX_CLOCK_SKEW_SECS {
+            return Err(errors::new(ErrorKind::Authorization(
+                "Extension message has expired or was issued in the future".into(),
+            )));
+        }
+        let target_url = target_url(extension, &self.subject);
+        let inv_claims = claims.metadata.unwrap();
+        if claims.subject != self.id
+            || claims.issuer != self.issuer
+            || inv_claims.target_url != target_url
+            || inv_claims.invocation_hash
+                != invocation_hash(&target_url, &self.issuer, &self.payload)
+        {
+            return Err(errors::new(ErrorKind::Authorization(
+                "Extension message does not match its signed claims".into(),
+            )));
+        }
+        Ok(expires)
+    }
+}
+
+/// The IDs of the extension messages a host has delivered, each kept until the message expires.
+/// A message whose ID is still remembered is a replay
+#[derive(Debug, Default)]
+pub(crate) struct ReplayWindow {
+    seen: HashMap<String, u64>,
+}
+
+impl ReplayWindow {
+    /// Remembers the message with the given ID and expiry, returning false if it has already
+    /// been seen
+    fn admit(&mut self, id: &str, expires: u64, now: u64) -> bool {
+        self.seen.retain(|_, exp| *exp >= now);
+        if self.seen.contains_key(id) {
+            return false;
+        }
+        self.seen.insert(id.to_string(), expires);
+        true
+    }
+}
+
+/// The reply to an extension message
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct ExtensionReply {
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+    pub error: Option<String>,
+}
+
+impl ExtensionReply {
+    /// Decodes a reply received from a host
+    pub fn from_bytes(bytes: &[u8]) -> Result<ExtensionReply> {
+        deserialize(bytes)
+    }
+
+    /// Decodes the payload of the reply, failing if the extension returned an error
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T> {
+        match self.error {
+            Some(ref e) => Err(e.to_string().into()),
+            None => deserialize(&self.payload),
+        }
+    }
+
+    fn error(reason: &str) -> ExtensionReply {
+        ExtensionReply {
+            payload: vec![],
+            error: Some(reason.to_string()),
+        }
+    }
+}
+
+fn target_url(extension: &str, subject: &str) -> String {
+    format!("{}/{}", extension, subject)
+}
+
+/// Routes a message received on the given subject (beneath the extension prefix) to the
+/// extension that owns it. `now` is the time on the host's clock, in seconds since the epoch
+pub(crate) fn handle_extension_message(
+    extensions: &[Box<dyn LatticeExtension>],
+    replays: &mut ReplayWindow,
+    now: u64,
+    relative_subject: &str,
+    bytes: &[u8],
+) -> ExtensionReply {
+    let mut tokens = relative_subject.splitn(2, '.');
+    let name = tokens.next().unwrap_or_default();
+    let subject = tokens.next().unwrap_or_default();
+    let extension = match extensions.iter().find(|e| e.name() == name) {
+        Some(e) => e,
+        None => return ExtensionReply::error("No such extension"),
+    };
+    let msg: ExtensionMessage = match deserialize(bytes) {
+        Ok(m) => m,
+        Err(e) => return ExtensionReply::error(&format!("Bad extension message: {}", e)),
+    };
+    // Prevent a signed message from being replayed onto another subject
+    if msg.subject != subject {
+        return ExtensionReply::error("Extension message subject mismatch");
+    }
+    let expires = match msg.validate(name, now) {
+        Ok(expires) => expires,
+        Err(e) => return ExtensionReply::error(&e.to_string()),
+    };
+    if !extension.authorize(&msg.issuer, subject) {
+        return ExtensionReply::error("Extension message not authorized");
+    }
+    if !replays.admit(&msg.id, expires, now) {
+        return ExtensionReply::error("Extension message has already been delivered");
+    }
+    match extension.handle(&msg) {
+        Ok(payload) => ExtensionReply {
+            payload,
+            error: None,
+        },
+        Err(e) => ExtensionReply::error(&e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        handle_extension_message, ExtensionMessage, LatticeExtension, ReplayWindow,
+        EXTENSION_MESSAGE_TTL,
+    };
+    use wascap::prelude::{Claims, KeyPair};
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl LatticeExtension for Echo {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        fn handle(&self, msg: &ExtensionMessage) -> std::result::Result<Vec<u8>, String> {
+            Ok(msg.payload.clone())
+        }
+    }
+
+    #[test]
+    fn signed_messages_are_routed_and_tampering_is_rejected() {
+        let kp = KeyPair::new_user();
+        let exts: Vec<Box<dyn LatticeExtension>> = vec![Box::new(Echo)];
+        let mut replays = ReplayWindow::default();
+        let now = now();
+        let msg = ExtensionMessage::new(&kp, "echo", "ping", &"hello".to_string()).unwrap();
+
+        let reply = handle_extension_message(
+            &exts,
+            &mut replays,
+            now,
+            "echo.pong",
+            &msg.to_bytes().unwrap(),
+        );
+        assert!(reply.error.is_some());
+
+        let reply = handle_extension_message(
+            &exts,
+            &mut replays,
+            now,
+            "echo.ping",
+            &msg.to_bytes().unwrap(),
+        );
+        assert_eq!("hello", reply.payload::<String>().unwrap());
+
+        let mut forged = ExtensionMessage::new(&kp, "echo", "ping", &"hi".to_string()).unwrap();
+        forged.payload = ExtensionMessage::new(&kp, "echo", "ping", &"bye".to_string())
+            .unwrap()
+            .payload;
+        let reply = handle_extension_message(
+            &exts,
+            &mut replays,
+            now,
+            "echo.ping",
+            &forged.to_bytes().unwrap(),
+        );
+        assert!(reply.error.is_some());
+    }
+
+    #[test]
+    fn messages_expire_and_cant_be_replayed() {
+        let kp = KeyPair::new_user();
+        let exts: Vec<Box<dyn LatticeExtension>> = vec![Box::new(Echo)];
+        let mut replays = ReplayWindow::default();
+        let now = now();
+        let msg = ExtensionMessage::new(&kp, "echo", "ping", &"hello".to_string())
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+
+        let deliver = |replays: &mut ReplayWindow, at: u64| {
+            handle_extension_message(&exts, replays, at, "echo.ping", &msg)
+        };
+        assert!(deliver(&mut replays, now).error.is_none());
+        assert!(deliver(&mut replays, now + 1).error.is_some());
+        // Once the message has expired it isn't accepted even by a host that never saw it
+        let later = now + EXTENSION_MESSAGE_TTL.as_secs() + 1;
+        assert!(deliver(&mut ReplayWindow::default(), later).error.is_some());
+
+        // Messages signed without an expiry are refused
+        let mut unbounded = ExtensionMessage::new(&kp, "echo", "ping", &"hi".to_string()).unwrap();
+        let mut claims =
+            Claims::<wascap::prelude::Invocation>::decode(&unbounded.encoded_claims).unwrap();
+        claims.expires = None;
+        unbounded.encoded_claims = claims.encode(&kp).unwrap();
+        let reply = handle_extension_message(
+            &exts,
+            &mut replays,
+            now,
+            "echo.ping",
+            &unbounded.to_bytes().unwrap(),
+        );
+        assert!(reply.error.is_some());
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
@@ -1,24 +1,35 @@
-use crate::actors::LiveUpdate;
+use crate::capability::native_host::provider_cache_dir;
+use crate::control_interface::ctlactor::ControlOptions;
 
 use crate::hlreg::HostLocalSystemService;
 use crate::host_controller::{
     AuctionActor, AuctionProvider, GetRunningActor, HostController, QueryActorRunning,
-    QueryHostInventory, QueryProviderRunning, QueryUptime, StartActor, StartProvider, StopActor,
-    StopProvider,
+    QueryHostInventory, QueryInventoryChanges, QueryProviderRunning, QueryUptime, StartActor,
+    StartProvider, StopActor, StopProvider, UpdateActor,
 };
-use crate::messagebus::{GetClaims, MessageBus, QueryAllLinks};
-use crate::oci::fetch_oci_bytes;
-use crate::{Actor, NativeCapability};
+use crate::messagebus::{
+    GetClaims, MessageBus, ProbeProvider, QueryAllLinks, QueryDependencyGraph, QueryLinkStatistics,
+    QueryQuiescedProviders, QuerySubscriptionStatistics, QuiesceProvider,
+};
+use crate::oci::{fetch_oci_bytes, oci_cache_dir, OCI_VAR_PASSWORD, OCI_VAR_USER};
+use crate::{Actor, Host};
 
 use control_interface::{
-    deserialize, serialize, ActorAuctionAck, ActorAuctionRequest, ActorDescription, HostInventory,
-    ProviderAuctionAck, ProviderAuctionRequest, ProviderDescription, StopActorAck,
-    StopActorCommand, StopProviderAck, StopProviderCommand, UpdateActorAck, UpdateActorCommand,
+    deserialize, serialize, ActorAuctionAck, ActorAuctionRequest, ActorDescription,
+    HostCapabilities, HostConfig, HostInventory, InventoryDelta, InventoryDeltaRequest,
+    PrepareUpgradeAck, PrepareUpgradeCommand, ProviderAuctionAck, ProviderAuctionRequest,
+    ProviderDescription, ProviderHealth, ProviderInstanceConfig, ProviderQuiesceAck,
+    SetLogLevelAck, SetLogLevelCommand, StopActorAck, StopActorCommand, StopProviderAck,
+    StopProviderCommand, TraceSamplingAck, TraceSamplingCommand, UpdateActorAck,
+    UpdateActorCommand,
 };
 use control_interface::{StartActorAck, StartActorCommand, StartProviderAck, StartProviderCommand};
 
 use std::collections::HashMap;
 use wascap::jwt::Claims;
+use wascap::prelude::KeyPair;
+
+const REDACTED: &str = "*****";
 
 // *** NOTE ***
 // It is extremely important to note that this function will acknowledge the -acceptance-
@@ -37,6 +48,13 @@ pub(crate) async fn handle_update_actor(host: &str, msg: &nats::asynk::Message)
         return;
     }
     let req = req.unwrap();
+    if crate::system_actor::is_reserved(host, &req.actor_id) {
+        error!("The system actor can't be updated while the host is running");
+        let _ = msg
+            .respond(&serialize(UpdateActorAck { accepted: false }).unwrap())
+            .await;
+        return;
+    }
     let actor = hc
         .send(GetRunningActor {
             actor_id: req.actor_id.to_string(),
@@ -45,19 +63,25 @@ pub(crate) async fn handle_update_actor(host: &str, msg: &nats::asynk::Message)
     let mut ack = UpdateActorAck { accepted: false };
     match actor {
         Ok(a) => {
-            if let Some(a) = a {
+            if a.is_some() {
                 ack.accepted = true;
                 let _ = msg.respond(&serialize(ack).unwrap()).await;
-                let bytes = fetch_oci_bytes(&req.new_actor_ref, false).await;
+                let bytes = fetch_oci_bytes(host, &req.new_actor_ref, false).await;
                 match bytes {
                     Ok(v) => {
-                        if let Err(e) = a
-                            .send(LiveUpdate {
-                                actor_bytes: v,
+                        // The new version is admitted by the host controller as a starting
+                        // actor would be, and a rejection is published as an event
+                        let update: crate::Result<()> = async {
+                            let actor = Actor::from_slice(&v)?;
+                            hc.send(UpdateActor {
+                                actor_id: req.actor_id,
+                                actor,
                                 image_ref: req.new_actor_ref,
                             })
-                            .await
-                        {
+                            .await?
+                        }
+                        .await;
+                        if let Err(e) = update {
                             error!("Failed to perform actor update: {}", e);
                         }
                     }
@@ -142,11 +166,18 @@ pub(crate) async fn handle_actor_auction(host: &str, msg: &nats::asynk::Message)
 pub(crate) async fn handle_host_inventory_query(host: &str, msg: &nats::asynk::Message) {
     let hc = HostController::from_hostlocal_registry(host);
     let res = hc.send(QueryHostInventory {}).await;
+    let bus = MessageBus::from_hostlocal_registry(host);
+    let quiesced = bus.send(QueryQuiescedProviders).await.unwrap_or_else(|_| {
+        error!("Mailbox failure querying message bus for quiesced providers");
+        vec![]
+    });
     let mut inv = HostInventory {
         providers: vec![],
         actors: vec![],
         labels: HashMap::new(),
         host_id: host.to_string(),
+        link_stats: None,
+        subscription_stats: None,
     };
     match res {
         Ok(hi) => {
@@ -157,6 +188,12 @@ pub(crate) async fn handle_host_inventory_query(host: &str, msg: &nats::asynk::M
                     id: ps.id.to_string(),
                     link_name: ps.link_name.to_string(),
                     image_ref: ps.image_ref.clone(),
+                    instance_id: Some(ps.instance_id.to_string()),
+                    quiesced: Some(
+                        quiesced
+                            .iter()
+                            .any(|(id, link_name)| *id == ps.id && *link_name == ps.link_name),
+                    ),
                 })
                 .collect();
             inv.actors = hi
@@ -173,9 +210,247 @@ pub(crate) async fn handle_host_inventory_query(host: &str, msg: &nats::asynk::M
             error!("Mailbox failure querying host controller for inventory");
         }
     }
+    match bus.send(QueryLinkStatistics).await {
+        Ok(stats) => inv.link_stats = Some(stats),
+        Err(_) => error!("Mailbox failure querying message bus for link statistics"),
+    }
+    match bus.send(QuerySubscriptionStatistics).await {
+        Ok(stats) => inv.subscription_stats = Some(stats),
+        Err(_) => error!("Mailbox failure querying message bus for subscription statistics"),
+    }
     let _ = msg.respond(&serialize(inv).unwrap()).await;
 }
 
+pub(crate) async fn handle_inventory_delta_query(host: &str, msg: &nats::asynk::Message) {
+    let req = deserialize::<InventoryDeltaRequest>(&msg.data);
+    if req.is_err() {
+        error!("Failed to deserialize inventory delta request");
+        return;
+    }
+    let hc = HostController::from_hostlocal_registry(host);
+    match hc
+        .send(QueryInventoryChanges {
+            since_revision: req.unwrap().since_revision,
+        })
+        .await
+    {
+        Ok(changes) => {
+            let delta = InventoryDelta {
+                host_id: host.to_string(),
+                revision: changes.revision,
+                full: changes.full,
+                labels: changes.labels,
+                actors_added: changes
+                    .actors_added
+                    .into_iter()
+                    .map(|a| ActorDescription {
+                        id: a.id,
+                        image_ref: a.image_ref,
+                    })
+                    .collect(),
+                actors_removed: changes.actors_removed,
+                providers_added: changes
+                    .providers_added
+                    .into_iter()
+                    .map(|p| ProviderDescription {
+                        id: p.id,
+                        link_name: p.link_name,
+                        image_ref: p.image_ref,
+                        instance_id: Some(p.instance_id),
+                        quiesced: None,
+                    })
+                    .collect(),
+                providers_removed: changes
+                    .providers_removed
+                    .into_iter()
+                    .map(|p| ProviderDescription {
+                        id: p.id,
+                        link_name: p.link_name,
+                        image_ref: p.image_ref,
+                        instance_id: Some(p.instance_id),
+                        quiesced: None,
+                    })
+                    .collect(),
+            };
+            let _ = msg.respond(&serialize(delta).unwrap()).await;
+        }
+        Err(_) => error!("Mailbox failure querying host controller for inventory changes"),
+    }
+}
+
+pub(crate) async fn handle_dependency_graph_query(host: &str, msg: &nats::asynk::Message) {
+    let bus = MessageBus::from_hostlocal_registry(host);
+    match bus.send(QueryDependencyGraph).await {
+        Ok(graph) => {
+            let _ = msg.respond(&serialize(graph).unwrap()).await;
+        }
+        Err(_) => error!("Mailbox failure querying message bus for dependency graph"),
+    }
+}
+
+pub(crate) async fn handle_host_config_query(
+    host: &str,
+    msg: &nats::asynk::Message,
+    prefix: &Option<String>,
+    options: &ControlOptions,
+) {
+    // Labels can change after startup (e.g. via manifest), so get the current set
+    let hc = HostController::from_hostlocal_registry(host);
+    let labels = match hc.send(QueryHostInventory {}).await {
+        Ok(hi) => hi.labels,
+        Err(_) => {
+            error!("Mailbox failure querying host controller for labels");
+            options.host_labels.clone()
+        }
+    };
+    let cfg = HostConfig {
+        host_id: host.to_string(),
+        version: crate::VERSION.to_string(),
+        namespace: prefix.clone().unwrap_or("default".to_string()),
+        engine: Host::wasm_engine(),
+        labels,
+        rpc_timeout_ms: options.rpc_timeout.as_millis() as u64,
+        heartbeat_interval_s: options.heartbeat_interval.as_secs(),
+        oci_allow_latest: options.oci_allow_latest,
+        allow_live_updates: options.allow_live_updates,
+        lattice_rpc_enabled: options.lattice_rpc,
+        // Limits the host doesn't have are reported as None
+        max_actors: options.limits.max_actors.map(|n| n as u64),
+        max_providers: options.limits.max_providers.map(|n| n as u64),
+        max_guest_memory: options.limits.max_guest_memory,
+        provider_cache_path: provider_cache_dir().to_string_lossy().to_string(),
+        oci_cache_path: oci_cache_dir().to_string_lossy().to_string(),
+        oci_registry_user: std::env::var(OCI_VAR_USER).ok(),
+        // Never let the registry secret leave the host
+        oci_registry_password: std::env::var(OCI_VAR_PASSWORD)
+            .ok()
+            .map(|_| REDACTED.to_string()),
+    };
+    let _ = msg.respond(&serialize(cfg).unwrap()).await;
+}
+
+pub(crate) async fn handle_host_capabilities_query(
+    host: &str,
+    msg: &nats::asynk::Message,
+    options: &ControlOptions,
+) {
+    let caps = HostCapabilities {
+        host_id: host.to_string(),
+        version: crate::VERSION.to_string(),
+        engine: Host::wasm_engine(),
+        native_target: Host::native_target(),
+        features: options.features.clone(),
+    };
+    let _ = msg.respond(&serialize(caps).unwrap()).await;
+}
+
+/// The diagnostics that can be requested of a single provider instance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InstanceRequest {
+    Health,
+    Config,
+    Quiesce,
+    Resume,
+}
+
+// Requests for provider instances are seen by every host, and only answered by the host that
+// runs the instance
+pub(crate) async fn handle_provider_instance_request(
+    host: &str,
+    msg: &nats::asynk::Message,
+    instance_id: &str,
+    request: InstanceRequest,
+) {
+    let hc = HostController::from_hostlocal_registry(host);
+    let provider = match hc.send(QueryHostInventory {}).await {
+        Ok(inv) => inv
+            .providers
+            .into_iter()
+            .find(|p| p.instance_id == instance_id),
+        Err(_) => {
+            error!("Mailbox failure querying host controller for inventory");
+            return;
+        }
+    };
+    let provider = match provider {
+        Some(p) => p,
+        None => return,
+    };
+    let bus = MessageBus::from_hostlocal_registry(host);
+    let reply = match request {
+        InstanceRequest::Health => {
+            let res: crate::Result<_> = match bus
+                .send(ProbeProvider {
+                    provider_id: provider.id.to_string(),
+                    link_name: provider.link_name.to_string(),
+                })
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => Err(e.into()),
+            };
+            let (healthy, message) = match res {
+                Ok(hr) => (hr.healthy, hr.message),
+                Err(e) => (false, format!("{}", e)),
+            };
+            serialize(ProviderHealth {
+                instance_id: instance_id.to_string(),
+                host_id: host.to_string(),
+                provider_id: provider.id,
+                link_name: provider.link_name,
+                healthy,
+                message,
+            })
+        }
+        InstanceRequest::Config => {
+            let links = match bus.send(QueryAllLinks {}).await {
+                Ok(links) => links.links,
+                Err(_) => {
+                    error!("Messagebus mailbox failure querying link definitions");
+                    return;
+                }
+            };
+            serialize(ProviderInstanceConfig {
+                instance_id: instance_id.to_string(),
+                host_id: host.to_string(),
+                links: links
+                    .into_iter()
+                    .filter(|l| l.provider_id == provider.id && l.link_name == provider.link_name)
+                    .map(|l| ::control_interface::LinkDefinition {
+                        actor_id: l.actor_id,
+                        provider_id: l.provider_id,
+                        link_name: l.link_name,
+                        contract_id: l.contract_id,
+                        values: l.values,
+                    })
+                    .collect(),
+                provider_id: provider.id,
+                link_name: provider.link_name,
+                image_ref: provider.image_ref,
+            })
+        }
+        InstanceRequest::Quiesce | InstanceRequest::Resume => {
+            let res: crate::Result<()> = match bus
+                .send(QuiesceProvider {
+                    provider_id: provider.id,
+                    link_name: provider.link_name,
+                    quiesce: request == InstanceRequest::Quiesce,
+                })
+                .await
+            {
+                Ok(r) => r,
+                Err(e) => Err(e.into()),
+            };
+            serialize(ProviderQuiesceAck {
+                instance_id: instance_id.to_string(),
+                host_id: host.to_string(),
+                failure: res.err().map(|e| format!("{}", e)),
+            })
+        }
+    };
+    let _ = msg.respond(&reply.unwrap()).await;
+}
+
 pub(crate) async fn handle_linkdefs_query(host: &str, msg: &nats::asynk::Message) {
     let mb = MessageBus::from_hostlocal_registry(host);
     match mb.send(QueryAllLinks {}).await {
@@ -277,7 +552,7 @@ pub(crate) async fn handle_start_actor(host: &str, msg: &nats::asynk::Message, a
         }
     }
 
-    let bytes = crate::oci::fetch_oci_bytes(&cmd.actor_ref, allow_latest).await;
+    let bytes = crate::oci::fetch_oci_bytes(host, &cmd.actor_ref, allow_latest).await;
     if let Err(e) = bytes {
         let f = format!("Failed to retrieve actor image from OCI registry: {}", e);
         error!("{}", f);
@@ -428,23 +703,16 @@ pub(crate) async fn handle_start_provider(
         }
     }
 
-    let par = crate::oci::fetch_provider_archive(&cmd.provider_ref, allow_latest).await;
-    if let Err(e) = par {
-        let f = format!(
-            "Failed to retrieve provider archive from OCI registry: {}",
-            e
-        );
-        error!("{}", f);
-        ack.failure = Some(f);
-        let _ = msg.respond(&serialize(ack).unwrap()).await;
-        return;
-    }
-    let par = par.unwrap();
-
-    let cap = NativeCapability::from_archive(&par, Some(cmd.link_name.to_string()));
+    let cap = crate::oci::fetch_provider(
+        host,
+        &cmd.provider_ref,
+        allow_latest,
+        Some(cmd.link_name.to_string()),
+    )
+    .await;
     if let Err(e) = cap {
         let f = format!(
-            "Failed to create provider archive from OCI image bytes: {}",
+            "Failed to retrieve provider archive from OCI registry: {}",
             e
         );
         error!("{}", f);
@@ -475,6 +743,77 @@ pub(crate) async fn handle_start_provider(
     let _ = msg.respond(&serialize(ack).unwrap()).await;
 }
 
+pub(crate) async fn handle_set_log_level(host: &str, msg: &nats::asynk::Message) {
+    let mut ack = SetLogLevelAck::default();
+    let cmd = match deserialize::<SetLogLevelCommand>(&msg.data) {
+        Ok(c) => c,
+        Err(_) => {
+            error!("Failed to deserialize set log level command");
+            ack.failure = Some("Failed to deserialize set log level command".to_string());
+            let _ = msg.respond(&serialize(ack).unwrap()).await;
+            return;
+        }
+    };
+    match crate::log_levels::parse_level(&cmd.level) {
+        Ok(level) => crate::log_levels::set_level(host, &cmd.target, level),
+        Err(e) => {
+            error!("{}", e);
+            ack.failure = Some(format!("{}", e));
+        }
+    }
+    let _ = msg.respond(&serialize(ack).unwrap()).await;
+}
+
+pub(crate) async fn handle_prepare_upgrade(
+    seed: &str,
+    namespace: &str,
+    msg: &nats::asynk::Message,
+    options: &crate::self_update::UpgradeOptions,
+) {
+    let mut ack = PrepareUpgradeAck::default();
+    if deserialize::<PrepareUpgradeCommand>(&msg.data).is_err() {
+        error!("Failed to deserialize prepare upgrade command");
+        ack.failure = Some("Failed to deserialize prepare upgrade command".to_string());
+        let _ = msg.respond(&serialize(ack).unwrap()).await;
+        return;
+    }
+    let kp = KeyPair::from_seed(seed).unwrap();
+    if let Err(e) = crate::self_update::prepare(&kp, namespace, options).await {
+        error!("Failed to prepare host for upgrade: {}", e);
+        ack.failure = Some(format!("{}", e));
+    }
+    let _ = msg.respond(&serialize(ack).unwrap()).await;
+}
+
+pub(crate) async fn handle_set_trace_sampling(host: &str, msg: &nats::asynk::Message) {
+    let mut ack = TraceSamplingAck {
+        host_id: host.to_string(),
+        ..Default::default()
+    };
+    let cmd = match deserialize::<TraceSamplingCommand>(&msg.data) {
+        Ok(c) => c,
+        Err(_) => {
+            error!("Failed to deserialize trace sampling command");
+            ack.failure = Some("Failed to deserialize trace sampling command".to_string());
+            let _ = msg.respond(&serialize(ack).unwrap()).await;
+            return;
+        }
+    };
+    let sampling = crate::TraceSampling {
+        default_rate: cmd.default_rate,
+        rates: cmd.rates,
+        always_sample_errors: cmd.always_sample_errors,
+    };
+    match sampling.validate() {
+        Ok(_) => crate::sampling::set(host, sampling),
+        Err(e) => {
+            error!("{}", e);
+            ack.failure = Some(format!("{}", e));
+        }
+    }
+    let _ = msg.respond(&serialize(ack).unwrap()).await;
+}
+
 pub(crate) async fn handle_stop_actor(host: &str, msg: &nats::asynk::Message) {
     let mut ack = StopActorAck::default();
     let hc = HostController::from_hostlocal_registry(host);
@@ -489,6 +828,14 @@ pub(crate) async fn handle_stop_actor(host: &str, msg: &nats::asynk::Message) {
         }
     };
 
+    if crate::system_actor::is_reserved(host, &cmd.actor_ref) {
+        let f = "The system actor can't be stopped; it stops with the host";
+        error!("{}", f);
+        ack.failure = Some(f.to_string());
+        let _ = msg.respond(&serialize(ack).unwrap()).await;
+        return;
+    }
+
     match hc
         .send(QueryActorRunning {
             actor_ref: cmd.actor_ref.to_string(),
@@ -1,3 +1,6 @@
+pub(crate) mod api_tokens;
+pub mod cloudevents;
 pub(crate) mod ctlactor;
 pub mod events;
+pub(crate) mod extensions;
 mod handlers;
@@ -0,0 +1,62 @@
+// A host can be given a denylist of providers it must never start, by contract ID or by provider
+// public key. This is not a sandbox: providers are loaded as shared libraries into the host's own
+// process, where a seccomp or landlock profile would restrict the host and every other provider
+// along with it, so the host has no way to confine a single provider. Refusing to start a denied
+// provider at all is what it can do instead. Each refusal is published as a `ProviderDenied`
+// security event, followed by the `ProviderStartRejected` event any other rejection produces.
+
+use crate::capability::native::NativeCapability;
+
+/// Finds the denylist entry, a contract ID or a provider public key, that matches a provider
+pub(crate) fn denied_by<'a>(denied: &'a [String], provider: &NativeCapability) -> Option<&'a str> {
+    let contract_id = provider
+        .claims
+        .metadata
+        .as_ref()
+        .map(|md| md.capid.as_str())
+        .unwrap_or_default();
+    let subject = provider.claims.subject.as_str();
+    denied
+        .iter()
+        .find(|id| id.as_str() == subject || id.as_str() == contract_id)
+        .map(|id| id.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::denied_by;
+    use crate::capability::keyvalue::MemoryKeyValueProvider;
+    use crate::NativeCapability;
+    use std::collections::HashMap;
+    use wascap::jwt::{CapabilityProvider, Claims};
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn denied_providers_are_matched() {
+        let claims = Claims::<CapabilityProvider>::new(
+            "Test".to_string(),
+            KeyPair::new_account().public_key(),
+            KeyPair::new_service().public_key(),
+            "wascc:testing".to_string(),
+            "Testing".to_string(),
+            None,
+            None,
+            HashMap::new(),
+        );
+        let subject = claims.subject.to_string();
+        let provider =
+            NativeCapability::from_instance(MemoryKeyValueProvider::new("Ndenied"), None, claims)
+                .unwrap();
+
+        assert_eq!(denied_by(&[], &provider), None);
+        assert_eq!(denied_by(&["wascc:keyvalue".to_string()], &provider), None);
+        assert_eq!(
+            denied_by(&["wascc:testing".to_string()], &provider),
+            Some("wascc:testing")
+        );
+        assert_eq!(
+            denied_by(&[subject.to_string()], &provider),
+            Some(subject.as_str())
+        );
+    }
+}
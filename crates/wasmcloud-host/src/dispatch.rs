@@ -1,6 +1,12 @@
+use crate::baggage::current_baggage;
+use crate::contracts::MESSAGING_CONTRACT;
 use crate::errors::{self, ErrorKind};
 use crate::hlreg::HostLocalSystemService;
-use crate::messagebus::{LookupLink, MessageBus, OP_BIND_ACTOR};
+use crate::host_controller::{HostController, QueryHostInventory};
+use crate::messagebus::push::{
+    Push, PushRequest, PushSubscribe, PushSubscribeRequest, PushUnsubscribe, PushUnsubscribeRequest,
+};
+use crate::messagebus::{LookupLink, MessageBus, QueryNamespace, OP_BIND_ACTOR};
 use crate::{Result, SYSTEM_ACTOR};
 use actix::dev::{MessageResponse, ResponseChannel};
 use actix::prelude::*;
@@ -14,6 +20,25 @@ use std::io::Read;
 use uuid::Uuid;
 use wascap::prelude::{Claims, KeyPair};
 use wascc_codec::capabilities::Dispatcher;
+use wascc_codec::logging::{WriteLogRequest, OP_LOG};
+use wascc_codec::messaging::{OP_PERFORM_REQUEST, OP_PUBLISH_MESSAGE};
+
+/// Describes the host, and the actor that asked for it, to an actor
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct HostMetadata {
+    #[serde(rename = "hostId")]
+    pub host_id: String,
+    pub labels: HashMap<String, String>,
+    #[serde(rename = "latticeNamespace")]
+    pub lattice_namespace: String,
+    pub actor: String,
+    #[serde(rename = "actorName")]
+    pub actor_name: String,
+    #[serde(rename = "actorTags")]
+    pub actor_tags: Vec<String>,
+    #[serde(rename = "actorCapabilities")]
+    pub actor_capabilities: Vec<String>,
+}
 
 pub(crate) const URL_SCHEME: &str = "wasmbus";
 
@@ -23,6 +48,41 @@ pub const CONFIG_WASCC_CLAIMS_NAME: &str = "__wascc_name";
 pub const CONFIG_WASCC_CLAIMS_EXPIRES: &str = "__wascc_expires";
 pub const CONFIG_WASCC_CLAIMS_TAGS: &str = "__wascc_tags";
 
+/// Host calls made by actors on this namespace are answered by the host itself rather than
+/// being routed to a capability provider, so no link is required
+pub const HOST_NAMESPACE: &str = "wasmcloud:host";
+/// Retrieves non-sensitive metadata about the host and the calling actor
+pub const OP_GET_HOST_METADATA: &str = "GetHostMetadata";
+/// Retrieves the baggage of the invocation the calling actor is handling, as a map
+pub const OP_GET_BAGGAGE: &str = "GetBaggage";
+/// Adds the items in a map to the baggage of the invocation the calling actor is handling, so
+/// that they are carried by the invocations it makes from then on. An empty value removes an item
+pub const OP_SET_BAGGAGE: &str = "SetBaggage";
+/// Replies to the message the calling actor is handling (or, given a correlation ID, to a message
+/// whose reply it deferred) over the messaging link the message arrived on
+pub const OP_REPLY: &str = "Reply";
+/// Defers the reply to the message the calling actor is handling, returning a correlation ID
+/// with which the reply can be sent from a later invocation, and the reply's deadline
+pub const OP_DEFER_REPLY: &str = "DeferReply";
+/// Retrieves the calling actor's log level (`off`, `error`, `warn`, `info`, `debug`, or `trace`)
+pub const OP_GET_LOG_LEVEL: &str = "GetLogLevel";
+/// Writes a message to the host's log on behalf of the calling actor, if the actor's log level
+/// allows it. The payload is the same as that of the logging contract's `WriteLog` operation
+pub const OP_WRITE_LOG: &str = "WriteLog";
+/// Sent by a capability provider to the host (dispatching to `HOST_NAMESPACE` in place of an
+/// actor) to push messages to one of its linked actors through a queue kept by the host. The
+/// payload is a `PushSubscribeRequest`
+pub const OP_PUSH_SUBSCRIBE: &str = "PushSubscribe";
+/// Sent by a capability provider to the host to queue a message for an actor it has subscribed
+/// to push to. The payload is a `PushRequest`, answered with a `PushAck` as soon as the message
+/// has been queued (or refused, if the subscription is full)
+pub const OP_PUSH: &str = "Push";
+/// Sent by a capability provider to the host to stop pushing to an actor. The payload is a
+/// `PushUnsubscribeRequest`
+pub const OP_PUSH_UNSUBSCRIBE: &str = "PushUnsubscribe";
+
+const LOGGING_CONTRACT: &str = "wascc:logging";
+
 #[doc(hidden)]
 // Given to a capability provider plugin to give it the means
 // to communicate with the host machinery
@@ -38,6 +98,45 @@ impl ProviderDispatcher {
     }
 }
 
+impl ProviderDispatcher {
+    // Answers the calls a provider makes to the host itself
+    fn handle_host_call(&self, op: &str, msg: &[u8]) -> Result<Vec<u8>> {
+        let bus = MessageBus::from_hostlocal_registry(&self.kp.public_key());
+        match op {
+            OP_PUSH_SUBSCRIBE => {
+                let request: PushSubscribeRequest = crate::generated::core::deserialize(msg)?;
+                block_on(bus.send(PushSubscribe {
+                    provider: self.me.clone(),
+                    request,
+                }))??;
+                Ok(vec![])
+            }
+            OP_PUSH => {
+                let req: PushRequest = crate::generated::core::deserialize(msg)?;
+                let inv = Invocation::new(
+                    &self.kp,
+                    self.me.clone(),
+                    WasccEntity::Actor(req.actor),
+                    &req.operation,
+                    req.msg,
+                )
+                .with_baggage(current_baggage());
+                let ack = block_on(bus.send(Push { invocation: inv }))??;
+                crate::generated::core::serialize(&ack)
+            }
+            OP_PUSH_UNSUBSCRIBE => {
+                let req: PushUnsubscribeRequest = crate::generated::core::deserialize(msg)?;
+                block_on(bus.send(PushUnsubscribe {
+                    provider: self.me.clone(),
+                    actor: req.actor,
+                }))?;
+                Ok(vec![])
+            }
+            _ => Err(format!("Unknown host operation for providers: {}", op).into()),
+        }
+    }
+}
+
 impl Dispatcher for ProviderDispatcher {
     fn dispatch(
         &self,
@@ -51,13 +150,17 @@ impl Dispatcher for ProviderDispatcher {
             actor,
             op
         );
+        if actor == HOST_NAMESPACE {
+            return self.handle_host_call(op, msg);
+        }
         let inv = Invocation::new(
             &self.kp,
             self.me.clone(),
             WasccEntity::Actor(actor.to_string()),
             op,
             msg.to_vec(),
-        );
+        )
+        .with_baggage(current_baggage());
         match block_on(async { self.addr.send(inv).await.map(|ir| ir.msg) }) {
             Ok(v) => Ok(v),
             Err(_e) => {
@@ -79,6 +182,28 @@ pub struct Invocation {
     pub id: String,
     pub encoded_claims: String,
     pub host_id: String,
+    /// A caller-supplied key identifying this request across retries. A host that has already
+    /// handled a successful invocation with the same key and request returns the same response
+    /// instead of executing the invocation again. Results are only remembered in memory by the
+    /// host that handled the invocation, so this is best effort, not exactly-once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Key-value pairs carried by this invocation and by every invocation made while handling it
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub baggage: HashMap<String, String>,
+    /// Set in place of the payload when the payload was too large to send over the lattice and
+    /// was put into the payload store instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_ref: Option<PayloadReference>,
+}
+
+/// A claim check for an invocation payload held in a payload store
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PayloadReference {
+    pub key: String,
+    pub size: u64,
+    /// The SHA-256 digest of the payload, in upper case hex
+    pub digest: String,
 }
 
 impl Invocation {
@@ -110,9 +235,27 @@ impl Invocation {
             id: subject,
             encoded_claims: claims.encode(&hostkey).unwrap(),
             host_id: issuer.to_string(),
+            idempotency_key: None,
+            baggage: HashMap::new(),
+            payload_ref: None,
+        }
+    }
+
+    /// Attaches an idempotency key to the invocation, so that retries of it are only executed
+    /// once. The key is not covered by the invocation's signature
+    pub fn with_idempotency_key(self, key: &str) -> Invocation {
+        Invocation {
+            idempotency_key: Some(key.to_string()),
+            ..self
         }
     }
 
+    /// Attaches baggage to the invocation. Like the idempotency key, baggage is not covered by
+    /// the invocation's signature, and invocations whose baggage exceeds the limits are rejected
+    pub fn with_baggage(self, baggage: HashMap<String, String>) -> Invocation {
+        Invocation { baggage, ..self }
+    }
+
     /// A fully-qualified URL indicating the origin of the invocation
     pub fn origin_url(&self) -> String {
         self.origin.url()
@@ -197,6 +340,14 @@ pub struct InvocationResponse {
     pub msg: Vec<u8>,
     pub error: Option<String>,
     pub invocation_id: String,
+    /// Set when the response was replaced with a `ResponseTooLarge` error for being larger than
+    /// the host allows (see `HostBuilder::with_max_response_size`)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub too_large: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 impl InvocationResponse {
@@ -207,6 +358,7 @@ impl InvocationResponse {
             msg,
             error: None,
             invocation_id: inv.id.to_string(),
+            too_large: false,
         }
     }
 
@@ -216,6 +368,7 @@ impl InvocationResponse {
             msg: Vec::new(),
             error: Some(err.to_string()),
             invocation_id: inv.id.to_string(),
+            too_large: false,
         }
     }
 }
@@ -314,7 +467,61 @@ pub(crate) fn wapc_host_callback(
         namespace,
         operation
     );
+    if namespace == HOST_NAMESPACE {
+        return handle_host_call(&kp, &claims, operation, payload);
+    }
+    // Messages below the actor's log level never reach the logging provider
+    if namespace == LOGGING_CONTRACT && operation == OP_LOG {
+        let req: WriteLogRequest = crate::generated::core::deserialize(payload)?;
+        let level = crate::log_levels::from_contract_level(req.level);
+        if !crate::log_levels::enabled(&kp.public_key(), &claims.subject, level) {
+            return Ok(vec![]);
+        }
+    }
+    // Requests made while working against a deadline can't wait longer than the deadline allows
+    let payload = if namespace == MESSAGING_CONTRACT && operation == OP_PERFORM_REQUEST {
+        crate::replies::clamp_request(&kp.public_key(), payload)?
+    } else {
+        payload.to_vec()
+    };
+    // A traffic shaping rule may send the call over other links, falling back on those after it
+    // when a call fails. The last link attempted answers the actor however the call went
+    let mut links = crate::shaping::route(&kp.public_key(), namespace, link_name, operation);
+    let last = links.pop().unwrap_or_else(|| link_name.to_string());
+    for link in links {
+        let err = match send_link(&kp, &claims, &link, namespace, operation, &payload) {
+            Ok(ir) if ir.error.is_none() => return Ok(ir.msg),
+            Ok(ir) => ir.error.unwrap_or_default(),
+            Err(e) => e.to_string(),
+        };
+        warn!(
+            "Call to {} over link {} failed, trying the next link: {}",
+            namespace, link, err
+        );
+    }
+    invoke_link(&kp, &claims, &last, namespace, operation, &payload)
+}
 
+// Invokes the provider linked to the actor for the given contract and link name
+fn invoke_link(
+    kp: &KeyPair,
+    claims: &Claims<wascap::jwt::Actor>,
+    link_name: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+) -> std::result::Result<Vec<u8>, Box<dyn ::std::error::Error + Sync + Send>> {
+    send_link(kp, claims, link_name, namespace, operation, payload).map(|ir| ir.msg)
+}
+
+fn send_link(
+    kp: &KeyPair,
+    claims: &Claims<wascap::jwt::Actor>,
+    link_name: &str,
+    namespace: &str,
+    operation: &str,
+    payload: &[u8],
+) -> std::result::Result<InvocationResponse, Box<dyn ::std::error::Error + Sync + Send>> {
     // Look up the public key of the provider bound to the origin actor
     // for the given capability contract ID.
     let bus = MessageBus::from_hostlocal_registry(&kp.public_key());
@@ -329,7 +536,7 @@ pub(crate) fn wapc_host_callback(
     });
     if let Some(p) = prov {
         let inv = invocation_from_callback(
-            &kp,
+            kp,
             &claims.subject,
             link_name,
             namespace,
@@ -337,8 +544,19 @@ pub(crate) fn wapc_host_callback(
             &p,
             payload,
         );
-        match block_on(async { bus.send(inv).await.map(|ir| ir.msg) }) {
-            Ok(v) => Ok(v),
+        // An actor pinned to this thread can't run until the caller is done, so the call could
+        // never be answered
+        if let WasccEntity::Actor(ref target) = inv.target {
+            if crate::threading::pinned_here(target) {
+                return Err(format!(
+                    "Actor {} can't call actor {}, as they share a thread of a pool",
+                    claims.subject, target
+                )
+                .into());
+            }
+        }
+        match block_on(async { bus.send(inv).await }) {
+            Ok(ir) => Ok(ir),
             Err(_e) => Err("Mailbox error during host callback".into()),
         }
     } else {
@@ -350,6 +568,78 @@ pub(crate) fn wapc_host_callback(
     }
 }
 
+// Answers host calls that don't involve a capability provider
+fn handle_host_call(
+    kp: &KeyPair,
+    claims: &Claims<wascap::jwt::Actor>,
+    operation: &str,
+    payload: &[u8],
+) -> std::result::Result<Vec<u8>, Box<dyn ::std::error::Error + Sync + Send>> {
+    match operation {
+        OP_GET_HOST_METADATA => {
+            let host_id = kp.public_key();
+            let hc = HostController::from_hostlocal_registry(&host_id);
+            let bus = MessageBus::from_hostlocal_registry(&host_id);
+            let (inv, ns) = block_on(async {
+                (
+                    hc.send(QueryHostInventory {}).await,
+                    bus.send(QueryNamespace {}).await,
+                )
+            });
+            let md = claims.metadata.as_ref();
+            let hm = HostMetadata {
+                host_id,
+                labels: inv.map(|i| i.labels).unwrap_or_default(),
+                lattice_namespace: ns.ok().flatten().unwrap_or_default(),
+                actor: claims.subject.to_string(),
+                actor_name: claims.name(),
+                actor_tags: md.and_then(|m| m.tags.clone()).unwrap_or_default(),
+                actor_capabilities: md.and_then(|m| m.caps.clone()).unwrap_or_default(),
+            };
+            Ok(crate::generated::core::serialize(&hm)?)
+        }
+        OP_GET_BAGGAGE => Ok(crate::generated::core::serialize(&current_baggage())?),
+        OP_REPLY => {
+            let req = crate::generated::core::deserialize(payload)?;
+            let (link_name, msg) =
+                crate::replies::take_reply(&kp.public_key(), &claims.subject, req)?;
+            invoke_link(
+                kp,
+                claims,
+                &link_name,
+                MESSAGING_CONTRACT,
+                OP_PUBLISH_MESSAGE,
+                &msg,
+            )?;
+            Ok(vec![])
+        }
+        OP_DEFER_REPLY => {
+            let deferred = crate::replies::defer(&kp.public_key(), &claims.subject)?;
+            Ok(crate::generated::core::serialize(&deferred)?)
+        }
+        OP_GET_LOG_LEVEL => {
+            let level = crate::log_levels::level_for(&kp.public_key(), &claims.subject);
+            Ok(crate::generated::core::serialize(
+                level.to_string().to_lowercase(),
+            )?)
+        }
+        OP_WRITE_LOG => {
+            let req: WriteLogRequest = crate::generated::core::deserialize(payload)?;
+            let level = crate::log_levels::from_contract_level(req.level);
+            if crate::log_levels::enabled(&kp.public_key(), &claims.subject, level) {
+                log!(target: claims.subject.as_str(), level, "{}", req.body);
+            }
+            Ok(vec![])
+        }
+        OP_SET_BAGGAGE => {
+            let items: HashMap<String, String> = crate::generated::core::deserialize(payload)?;
+            crate::baggage::merge_current(items)?;
+            Ok(vec![])
+        }
+        _ => Err(format!("Unknown host operation: {}", operation).into()),
+    }
+}
+
 fn invocation_from_callback(
     hostkey: &KeyPair,
     origin: &str,
@@ -381,6 +671,7 @@ fn invocation_from_callback(
         op,
         payload.to_vec(),
     )
+    .with_baggage(current_baggage())
 }
 
 pub(crate) fn gen_config_invocation(
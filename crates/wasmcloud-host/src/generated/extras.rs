@@ -27,3 +27,9 @@ pub struct GeneratorRequest {
     #[serde(rename = "max")]
     pub max: u32,
 }
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct TimeResult {
+    #[serde(rename = "timestampMs")]
+    pub timestamp_ms: u64,
+}
@@ -0,0 +1,77 @@
+extern crate rmp_serde as rmps;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct CompareAndSwapRequest {
+    #[serde(rename = "key")]
+    pub key: String,
+    #[serde(rename = "expected")]
+    pub expected: Option<String>,
+    #[serde(rename = "value")]
+    pub value: String,
+    #[serde(rename = "expires_s")]
+    pub expires_s: i32,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct CompareAndSwapResponse {
+    #[serde(rename = "swapped")]
+    pub swapped: bool,
+    #[serde(rename = "current")]
+    pub current: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct IncrementRequest {
+    #[serde(rename = "key")]
+    pub key: String,
+    #[serde(rename = "delta")]
+    pub delta: i64,
+    #[serde(rename = "min")]
+    pub min: Option<i64>,
+    #[serde(rename = "max")]
+    pub max: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct IncrementResponse {
+    #[serde(rename = "applied")]
+    pub applied: bool,
+    #[serde(rename = "value")]
+    pub value: i64,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ExpireRequest {
+    #[serde(rename = "key")]
+    pub key: String,
+    #[serde(rename = "expires_s")]
+    pub expires_s: i32,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct TtlResponse {
+    #[serde(rename = "exists")]
+    pub exists: bool,
+    #[serde(rename = "expires_s")]
+    pub expires_s: i32,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ScanRequest {
+    #[serde(rename = "prefix")]
+    pub prefix: String,
+    #[serde(rename = "cursor")]
+    pub cursor: String,
+    #[serde(rename = "limit")]
+    pub limit: u32,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct ScanResponse {
+    #[serde(rename = "keys")]
+    pub keys: Vec<String>,
+    #[serde(rename = "cursor")]
+    pub cursor: String,
+}
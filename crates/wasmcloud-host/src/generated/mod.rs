@@ -1,2 +1,3 @@
 pub(crate) mod core;
 pub(crate) mod extras;
+pub(crate) mod keyvalue;
@@ -1,4 +1,4 @@
-use actix::{Addr, System, SystemService};
+use actix::{Addr, Message, System, SystemService};
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use std::any::{Any, TypeId};
@@ -16,10 +16,37 @@ type ServiceMap = HashMap<String, AnyMap>;
 
 static SREG: Lazy<Mutex<ServiceMap>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// Sent to host-local services (and the actors they own) when the host that owns
+/// them is shutting down. Recipients are expected to release their resources and stop
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Shutdown;
+
+/// Removes all of the services belonging to the given host from the registry
+pub(crate) fn remove_host(hostid: &str) {
+    SREG.lock().remove(hostid);
+}
+
 /// This trait adds the `from_hostlocal_registry` option to any system service actor
 /// that implements it
 pub(crate) trait HostLocalSystemService: SystemService {
+    /// Obtains the address of this service for the given host only if it has already been
+    /// started. Unlike `from_hostlocal_registry`, this does not require a running system
+    fn existing_from_hostlocal_registry(hostid: &str) -> Option<Addr<Self>> {
+        let sreg = SREG.lock();
+        sreg.get(hostid)
+            .and_then(|reg| reg.get(&TypeId::of::<Self>()))
+            .and_then(|addr| addr.downcast_ref::<Addr<Self>>())
+            .cloned()
+    }
+
+    /// Obtains the address of this service for the given host, starting it on the current
+    /// system if it isn't running yet. A service that's already running can be looked up from
+    /// threads without a system, such as the blocking pool that task model actors run on
     fn from_hostlocal_registry(hostid: &str) -> Addr<Self> {
+        if let Some(addr) = Self::existing_from_hostlocal_registry(hostid) {
+            return addr;
+        }
         System::with_current(|sys| {
             let mut sreg = SREG.lock();
             let reg = sreg
@@ -0,0 +1,226 @@
+use data_encoding::HEXUPPER;
+use ring::digest::{digest, SHA256};
+use std::collections::HashMap;
+use std::path::Path;
+use wascap::jwt::{Actor, CapabilityProvider, Claims};
+
+/// The information available to a pre-start hook when an actor is about to be admitted
+/// into the host
+pub struct ActorAdmission<'a> {
+    /// The claims embedded in the actor's module
+    pub claims: &'a Claims<Actor>,
+    /// The hex-encoded SHA-256 digest of the actor's module bytes
+    pub digest: String,
+    /// The size of the actor's module, in bytes
+    pub size: usize,
+    /// The OCI image reference from which the actor was obtained, if any
+    pub image_ref: Option<&'a str>,
+}
+
+/// The information available to a pre-start hook when a capability provider is about to
+/// be admitted into the host
+pub struct ProviderAdmission<'a> {
+    /// The claims of the capability provider
+    pub claims: &'a Claims<CapabilityProvider>,
+    /// The link name under which the provider will be started
+    pub link_name: &'a str,
+    /// The hex-encoded SHA-256 digest of the provider's native library. This will be `None`
+    /// for providers started from an in-process instance
+    pub digest: Option<String>,
+    /// The OCI image reference from which the provider was obtained, if any
+    pub image_ref: Option<&'a str>,
+}
+
+/// A pre-start hook is consulted before an actor or capability provider is admitted into the
+/// host, after its claims have been validated. Platforms can use hooks to perform checks such
+/// as malware scanning, size limits, or license validation. Returning an error vetoes the start,
+/// and the supplied reason is returned to the caller and published as a control event.
+pub trait PreStartHook: ClonePreStartHook + Sync + Send {
+    /// Invoked before an actor is started
+    fn before_actor_start(&self, _admission: &ActorAdmission) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Invoked before a capability provider is started
+    fn before_provider_start(
+        &self,
+        _admission: &ProviderAdmission,
+    ) -> std::result::Result<(), String> {
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub trait ClonePreStartHook {
+    fn clone_hook(&self) -> Box<dyn PreStartHook>;
+}
+
+impl<T> ClonePreStartHook for T
+where
+    T: PreStartHook + Clone + 'static,
+{
+    fn clone_hook(&self) -> Box<dyn PreStartHook> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn PreStartHook> {
+    fn clone(&self) -> Self {
+        self.clone_hook()
+    }
+}
+
+/// A bootstrap hook runs platform initialization logic as part of the host's start, after the
+/// host's internals are running but before it announces itself on the lattice, and runs the
+/// matching clean-up when the host stops. Returning an error from `on_host_start` fails the
+/// host's start with the supplied reason.
+pub trait BootstrapHook: CloneBootstrapHook + Sync + Send {
+    /// Invoked while the host starts, with the host's ID and labels
+    fn on_host_start(
+        &self,
+        _host_id: &str,
+        _labels: &HashMap<String, String>,
+    ) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Invoked when the host begins to stop
+    fn on_host_stop(&self, _host_id: &str) {}
+}
+
+#[doc(hidden)]
+pub trait CloneBootstrapHook {
+    fn clone_bootstrap_hook(&self) -> Box<dyn BootstrapHook>;
+}
+
+impl<T> CloneBootstrapHook for T
+where
+    T: BootstrapHook + Clone + 'static,
+{
+    fn clone_bootstrap_hook(&self) -> Box<dyn BootstrapHook> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn BootstrapHook> {
+    fn clone(&self) -> Self {
+        self.clone_bootstrap_hook()
+    }
+}
+
+/// An upgrade hook hands a drained host over to whatever supervises its process. It is invoked
+/// once the host has stopped taking on work and has written its upgrade journal, and would
+/// typically signal the supervisor to swap the host binary and restart it. Returning an error
+/// fails the upgrade with the supplied reason, leaving the host drained.
+pub trait UpgradeHook: CloneUpgradeHook + Sync + Send {
+    /// Invoked with the host's ID and the path of the journal the new host will restore
+    fn on_upgrade_ready(&self, host_id: &str, journal: &Path) -> std::result::Result<(), String>;
+}
+
+#[doc(hidden)]
+pub trait CloneUpgradeHook {
+    fn clone_upgrade_hook(&self) -> Box<dyn UpgradeHook>;
+}
+
+impl<T> CloneUpgradeHook for T
+where
+    T: UpgradeHook + Clone + 'static,
+{
+    fn clone_upgrade_hook(&self) -> Box<dyn UpgradeHook> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn UpgradeHook> {
+    fn clone(&self) -> Self {
+        self.clone_upgrade_hook()
+    }
+}
+
+/// Runs each hook in order, returning the reason given by the first hook to veto the start
+pub(crate) fn check_actor_admission(
+    hooks: &[Box<dyn PreStartHook>],
+    admission: &ActorAdmission,
+) -> std::result::Result<(), String> {
+    for hook in hooks {
+        hook.before_actor_start(admission)?;
+    }
+    Ok(())
+}
+
+/// Runs each hook in order, returning the reason given by the first hook to veto the start
+pub(crate) fn check_provider_admission(
+    hooks: &[Box<dyn PreStartHook>],
+    admission: &ProviderAdmission,
+) -> std::result::Result<(), String> {
+    for hook in hooks {
+        hook.before_provider_start(admission)?;
+    }
+    Ok(())
+}
+
+/// Runs each bootstrap hook in order, returning the reason given by the first hook to fail
+pub(crate) fn run_bootstrap_hooks(
+    hooks: &[Box<dyn BootstrapHook>],
+    host_id: &str,
+    labels: &HashMap<String, String>,
+) -> std::result::Result<(), String> {
+    for hook in hooks {
+        hook.on_host_start(host_id, labels)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn bytes_digest(bytes: &[u8]) -> String {
+    HEXUPPER.encode(digest(&SHA256, bytes).as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bytes_digest, check_actor_admission, ActorAdmission, PreStartHook};
+    use wascap::jwt::{Actor, ClaimsBuilder};
+
+    #[derive(Clone)]
+    struct SizeLimit(usize);
+
+    impl PreStartHook for SizeLimit {
+        fn before_actor_start(&self, admission: &ActorAdmission) -> Result<(), String> {
+            if admission.size > self.0 {
+                Err(format!("Actor exceeds {} bytes", self.0))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct Permissive;
+
+    impl PreStartHook for Permissive {}
+
+    #[test]
+    fn first_veto_wins() {
+        let claims = ClaimsBuilder::<Actor>::new().build();
+        let hooks: Vec<Box<dyn PreStartHook>> = vec![
+            Box::new(Permissive),
+            Box::new(SizeLimit(10)),
+            Box::new(SizeLimit(5)),
+        ];
+        let admission = ActorAdmission {
+            claims: &claims,
+            digest: bytes_digest(&[0; 20]),
+            size: 20,
+            image_ref: None,
+        };
+        assert_eq!(
+            check_actor_admission(&hooks, &admission),
+            Err("Actor exceeds 10 bytes".to_string())
+        );
+
+        let small = ActorAdmission {
+            size: 1,
+            ..admission
+        };
+        assert!(check_actor_admission(&hooks, &small).is_ok());
+    }
+}
@@ -6,34 +6,134 @@ use crate::{
 use actix::prelude::*;
 
 use crate::auth::Authorizer;
+use crate::bundle::OfflineBundle;
+use crate::capability::extras::Determinism;
+use crate::capability::link_cache::LinkKey;
+use crate::compression::{ClaimsCompression, DeflateCompression};
+use crate::contracts::{KeyValueClient, MessagingClient};
 
-use crate::control_interface::ctlactor::{ControlInterface, ControlOptions, PublishEvent};
+use crate::control_interface::api_tokens::ApiTokenPolicy;
+use crate::control_interface::cloudevents::EventFormat;
+use crate::control_interface::ctlactor::{
+    ControlInterface, ControlOptions, FlushEvents, PublishEvent, RevokeApiToken,
+};
+use crate::control_interface::extensions::LatticeExtension;
+use ::control_interface::tokens::TokenScope;
 
+use crate::archive::{Archive, ArchiveStore, ArchivedEntity};
+use crate::billing::BillingSink;
+use crate::clock::{Clock, SystemClock};
 use crate::dispatch::Invocation;
-use crate::hlreg::HostLocalSystemService;
+use crate::hlreg::{HostLocalSystemService, Shutdown};
+use crate::hooks::{BootstrapHook, PreStartHook, UpgradeHook};
 use crate::host_controller::{
-    HostController, SetLabels, StartActor, StartProvider, StopActor, StopProvider,
-    RESTRICTED_LABELS,
+    Drain, HostController, SetLabels, StartActor, StartProvider, StopActor, StopAll, StopProvider,
+    UpdateActor, UpgradeProvider, RESTRICTED_LABELS,
+};
+use crate::idempotency::IdempotencyConfig;
+use crate::lattice_state::{ConflictResolution, LatticeSnapshot};
+use crate::limits::HostLimits;
+use crate::locks::{AcquireLock, LatticeLock, LockService};
+use crate::loopback::{resolve_target, HTTP_SERVER_CONTRACT};
+use crate::messagebus::defaults::CapabilityBundle;
+use crate::messagebus::fanout::{FanoutInvocation, FanoutReply, FanoutStrategy, FanoutTarget};
+use crate::messagebus::hb::default_hb_duration;
+use crate::messagebus::history::{CacheChange, CacheHistoryFilter, QueryCacheHistory};
+use crate::messagebus::mirror::{Mirror, MirrorStats, QueryMirrorStats, SetMirror};
+use crate::messagebus::readiness::AwaitLink;
+use crate::messagebus::{
+    DrainBus, FindProvider, QueryActors, QueryAllLinks, QueryDependencyGraph, QueryLinkStatistics,
+    QueryProviders, QueryQuiescedProviders, QueryRpcClient, QuerySubscriptionStatistics,
+    QueryTopology, QuiesceProvider, RemoveLink,
 };
-use crate::messagebus::{QueryActors, QueryProviders};
+use crate::metrics::ActorSlo;
 use crate::oci::fetch_oci_bytes;
-use crate::{ControlEvent, HostManifest, NativeCapability, WasccEntity};
+use crate::offload::{PayloadOffload, PayloadStore};
+use crate::permissions::{NatsPermissions, PermissionScope};
+use crate::plugins::load_plugin;
+use crate::roster::{RosterConfig, RosterMember, RosterService};
+use crate::sampling::TraceSampling;
+use crate::self_update::UpgradeOptions;
+use crate::shaping::ShapingRule;
+use crate::shared_connection::SharedConnection;
+use crate::shutdown::ShutdownReport;
+use crate::snapshots::{SnapshotConfig, SnapshotRedactor};
+use crate::threading::{ThreadConfig, ThreadModel};
+use crate::topology::LatticeTopology;
+use crate::wasm_features::WasmFeatures;
+use crate::watchdog::{Watchdog, WatchdogConfig};
+use crate::{ControlEvent, HostManifest, HttpRequest, HttpResponse, LinkDefinition, WasccEntity};
 use crate::{Result, SYSTEM_ACTOR};
-use provider_archive::ProviderArchive;
+use control_interface::{
+    DependencyGraph, HostCapabilities, LinkStatistics, SubscriptionStatistics,
+};
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use wascap::prelude::KeyPair;
+use wascc_codec::http::OP_HANDLE_REQUEST;
+
+const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(2);
+// How long each phase of stopping the host may take
+const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
 
 pub struct HostBuilder {
     labels: HashMap<String, String>,
     authorizer: Box<dyn Authorizer + 'static>,
+    prestart_hooks: Vec<Box<dyn PreStartHook>>,
+    bootstrap_hooks: Vec<Box<dyn BootstrapHook>>,
+    system_actor: Option<(Vec<u8>, Vec<String>)>,
     namespace: String,
     rpc_timeout: Duration,
+    hb_interval: Duration,
     allow_latest: bool,
     rpc_client: Option<nats::asynk::Connection>,
     cplane_client: Option<nats::asynk::Connection>,
     allow_live_update: bool,
+    services: Option<HashMap<String, String>>,
+    http_router: Option<u16>,
+    https_router: Option<u16>,
+    grpc_ingress: Option<u16>,
+    memory_keyvalue: bool,
+    determinism: Determinism,
+    claims_compression: Box<dyn ClaimsCompression>,
+    legacy_gossip: bool,
+    snapshots: Option<SnapshotConfig>,
+    snapshot_issuers: Vec<String>,
+    extensions: Vec<Box<dyn LatticeExtension>>,
+    slos: HashMap<String, ActorSlo>,
+    alert_webhooks: Vec<String>,
+    lattice_creds: Option<(String, PathBuf)>,
+    wasm_features: WasmFeatures,
+    unused_capability_grace: Option<Duration>,
+    payload_offload: Option<PayloadOffload>,
+    billing_sinks: Vec<Box<dyn BillingSink>>,
+    idempotency: IdempotencyConfig,
+    preload: Vec<String>,
+    offline_bundle: Option<OfflineBundle>,
+    metric_labels: HashMap<String, String>,
+    strict: bool,
+    denied_providers: Vec<String>,
+    bulkheads: HashMap<String, usize>,
+    readiness_gates: HashMap<String, Duration>,
+    event_format: EventFormat,
+    api_tokens: Option<ApiTokenPolicy>,
+    limits: HostLimits,
+    shared_connection: Option<SharedConnection>,
+    clock: Arc<dyn Clock>,
+    trace_sampling: TraceSampling,
+    upgrade: UpgradeOptions,
+    issuer_bundles: HashMap<String, CapabilityBundle>,
+    mirrors: HashMap<String, Mirror>,
+    archive: Option<Archive>,
+    automation_rules: Vec<String>,
+    roster: Option<RosterConfig>,
+    threads: ThreadConfig,
+    traffic_shaping: Vec<ShapingRule>,
+    shutdown_deadline: Duration,
+    watchdog: Option<WatchdogConfig>,
 }
 
 impl HostBuilder {
@@ -41,15 +141,138 @@ impl HostBuilder {
         HostBuilder {
             labels: crate::host_controller::detect_core_host_labels(),
             authorizer: Box::new(crate::auth::DefaultAuthorizer::new()),
+            prestart_hooks: vec![],
+            bootstrap_hooks: vec![],
+            system_actor: None,
             allow_latest: false,
             namespace: "default".to_string(),
-            rpc_timeout: Duration::from_secs(2),
+            rpc_timeout: DEFAULT_RPC_TIMEOUT,
+            hb_interval: default_hb_duration(),
             rpc_client: None,
             cplane_client: None,
             allow_live_update: false,
+            services: None,
+            http_router: None,
+            https_router: None,
+            grpc_ingress: None,
+            memory_keyvalue: false,
+            determinism: Determinism::default(),
+            claims_compression: Box::new(DeflateCompression::default()),
+            legacy_gossip: false,
+            snapshots: None,
+            snapshot_issuers: vec![],
+            extensions: vec![],
+            slos: HashMap::new(),
+            alert_webhooks: vec![],
+            lattice_creds: None,
+            wasm_features: WasmFeatures::default(),
+            unused_capability_grace: None,
+            payload_offload: None,
+            billing_sinks: vec![],
+            idempotency: IdempotencyConfig::default(),
+            preload: vec![],
+            offline_bundle: None,
+            metric_labels: HashMap::new(),
+            strict: false,
+            denied_providers: vec![],
+            bulkheads: HashMap::new(),
+            readiness_gates: HashMap::new(),
+            event_format: EventFormat::default(),
+            api_tokens: None,
+            limits: HostLimits::default(),
+            shared_connection: None,
+            clock: Arc::new(SystemClock),
+            trace_sampling: TraceSampling::default(),
+            upgrade: UpgradeOptions::default(),
+            issuer_bundles: HashMap::new(),
+            mirrors: HashMap::new(),
+            archive: None,
+            automation_rules: vec![],
+            roster: None,
+            threads: ThreadConfig::default(),
+            traffic_shaping: vec![],
+            shutdown_deadline: DEFAULT_SHUTDOWN_DEADLINE,
+            watchdog: None,
         }
     }
 
+    /// A profile for hosts on small devices with slow or intermittent links to the lattice:
+    /// longer RPC timeouts, less frequent heartbeats, the smallest gossip of actor claims, at most
+    /// 16 actors and 8 providers sharing 256MiB of guest memory, responses of at most 256KiB, and
+    /// an idempotency cache of 128 keys per actor kept for a minute. Any of these can be changed
+    /// by the builder's other methods
+    pub fn edge() -> HostBuilder {
+        HostBuilder::new()
+            .with_rpc_timeout(Duration::from_secs(10))
+            .with_heartbeat_interval(Duration::from_secs(120))
+            .with_claims_compression(DeflateCompression::new(9))
+            .with_max_actors(16)
+            .with_max_providers(8)
+            .with_max_guest_memory(256 * 1024 * 1024)
+            .with_max_response_size(256 * 1024)
+            .with_idempotency_window(Duration::from_secs(60), 128)
+    }
+
+    /// A profile for long-running hosts in a data center: at most 1024 actors and 128 providers
+    /// sharing 16GiB of guest memory, responses no larger than the lattice carries by default
+    /// (`DEFAULT_LATTICE_MAX_PAYLOAD`), an idempotency cache of 16384 keys per actor kept for 15
+    /// minutes, and warnings for capabilities that actors are granted but haven't used after an
+    /// hour. Any of these can be changed by the builder's other methods
+    pub fn server() -> HostBuilder {
+        HostBuilder::new()
+            .with_max_actors(1024)
+            .with_max_providers(128)
+            .with_max_guest_memory(16 * 1024 * 1024 * 1024)
+            .with_max_response_size(crate::offload::DEFAULT_LATTICE_MAX_PAYLOAD)
+            .with_idempotency_window(Duration::from_secs(15 * 60), 16 * 1024)
+            .warn_unused_capabilities(Duration::from_secs(60 * 60))
+    }
+
+    /// A profile for production hosts that only run artifacts whose provenance they can verify.
+    /// See `enable_strict_mode`
+    pub fn strict() -> HostBuilder {
+        HostBuilder::new().enable_strict_mode()
+    }
+
+    /// Refuses to start actors and capability providers unless they come with a signed claims
+    /// token that this host can verify and that is currently valid. Provider archives must carry
+    /// signed, unexpired claims, and providers embedded with `NativeCapability::from_instance`
+    /// must be created with `from_instance_with_token` instead. Refused artifacts are reported
+    /// the same way as those rejected by a pre-start hook
+    pub fn enable_strict_mode(self) -> HostBuilder {
+        HostBuilder {
+            strict: true,
+            ..self
+        }
+    }
+
+    /// Denies the providers for a capability contract, or the provider with a given public key,
+    /// so the host refuses to start them. This is a denylist, not a sandbox: providers run in the
+    /// host's own process and can't be confined individually. A refused provider is reported with
+    /// a `ProviderDenied` event, followed by the same rejection event as for a pre-start hook
+    pub fn deny_provider(self, id: &str) -> HostBuilder {
+        let mut denied_providers = self.denied_providers;
+        denied_providers.push(id.to_string());
+        HostBuilder {
+            denied_providers,
+            ..self
+        }
+    }
+
+    /// A profile for hosts in tests: random numbers and the clock seen by actors are fixed so
+    /// runs are reproducible, failed invocations are captured as execution snapshots, RPC fails
+    /// fast, heartbeats are frequent, and images tagged `latest` are allowed. Any of these can be
+    /// changed by the builder's other methods
+    pub fn test() -> HostBuilder {
+        HostBuilder::new()
+            .with_random_seed(0)
+            .with_fixed_clock(UNIX_EPOCH)
+            .enable_execution_snapshots()
+            .with_rpc_timeout(Duration::from_millis(500))
+            .with_heartbeat_interval(Duration::from_secs(1))
+            .oci_allow_latest()
+    }
+
     pub fn enable_live_updates(self) -> HostBuilder {
         HostBuilder {
             allow_live_update: true,
@@ -70,6 +293,47 @@ impl HostBuilder {
             ..self
         }
     }
+
+    /// Uses a connection shared with other hosts in this process for both RPC and the control
+    /// interface. Each host sharing a connection must have its own namespace, and the host
+    /// won't start if another host is already using its namespace on the connection. The
+    /// connection is closed once the last host using it stops
+    pub fn with_shared_connection(self, shared: &SharedConnection) -> HostBuilder {
+        HostBuilder {
+            rpc_client: Some(shared.connection().clone()),
+            cplane_client: Some(shared.connection().clone()),
+            shared_connection: Some(shared.clone()),
+            ..self
+        }
+    }
+
+    /// Connects the host to the lattice at the given NATS URL using a credentials file, such
+    /// as one issued by `LatticeCredentials::issue_user`. The connection is made when the host
+    /// starts and is used for both RPC and the control interface, unless either client has
+    /// been supplied explicitly
+    pub fn with_lattice_credentials(self, url: &str, creds: impl AsRef<Path>) -> HostBuilder {
+        HostBuilder {
+            lattice_creds: Some((url.to_string(), creds.as_ref().to_path_buf())),
+            ..self
+        }
+    }
+
+    /// Makes the host keep an explicit roster of its lattice's members, rather than trusting
+    /// every host that can reach the lattice's subjects. The host joins the roster with notices
+    /// signed by the given admission key (an account seed, for instance), and only admits hosts
+    /// whose notices are signed by that key or one of the trusted issuers (public keys). It
+    /// ignores invocations, claims, and links from hosts outside the roster, and reports each
+    /// of them with an `UnauthorizedHost` event. See `Host::lattice_members`
+    pub fn with_roster(self, admission_seed: &str, trusted_issuers: Vec<String>) -> HostBuilder {
+        HostBuilder {
+            roster: Some(RosterConfig {
+                admission_seed: admission_seed.to_string(),
+                trusted_issuers: trusted_issuers.into_iter().collect(),
+            }),
+            ..self
+        }
+    }
+
     pub fn with_authorizer(self, authorizer: impl Authorizer + 'static) -> HostBuilder {
         HostBuilder {
             authorizer: Box::new(authorizer),
@@ -77,6 +341,511 @@ impl HostBuilder {
         }
     }
 
+    /// Loads a host plugin from a dynamic library (`.so`, `.dylib`, or `.dll`) and adds the
+    /// authorizer, hooks, billing sinks, and lattice extensions it provides to the host. The
+    /// plugin must have been built with the same compiler and version of this crate as the host
+    pub fn with_plugin(self, path: impl AsRef<Path>) -> Result<HostBuilder> {
+        let plugin = load_plugin(path.as_ref())?;
+        let mut builder = match plugin.authorizer() {
+            Some(authorizer) => HostBuilder { authorizer, ..self },
+            None => self,
+        };
+        builder.prestart_hooks.extend(plugin.prestart_hooks());
+        builder.billing_sinks.extend(plugin.billing_sinks());
+        builder.extensions.extend(plugin.lattice_extensions());
+        Ok(builder)
+    }
+
+    /// Adds an actor or provider reference to fetch, verify, and cache in the background when the
+    /// host starts, without starting it, so that starting it later doesn't wait on the registry.
+    /// Progress is published as `ArtifactPreloaded` and `ArtifactPreloadFailed` events
+    pub fn with_preload(self, image_ref: &str) -> HostBuilder {
+        let mut preload = self.preload.clone();
+        preload.push(image_ref.to_string());
+        HostBuilder { preload, ..self }
+    }
+
+    /// Puts the host in air-gapped mode, resolving every actor and provider reference from the
+    /// offline bundle in the given directory instead of from OCI registries. The bundle's index
+    /// must be signed by one of the trusted issuers (public keys). The bundle is verified in full
+    /// here, and requesting an artifact that isn't in it fails
+    pub fn with_offline_bundle(
+        self,
+        path: impl AsRef<Path>,
+        trusted_issuers: &[String],
+    ) -> Result<HostBuilder> {
+        Ok(HostBuilder {
+            offline_bundle: Some(OfflineBundle::open(path, trusted_issuers)?),
+            ..self
+        })
+    }
+
+    /// Adds a hook that is consulted before any actor or capability provider is started in
+    /// this host. Hooks are run in the order in which they were added, and the first hook to
+    /// reject an actor or provider prevents it from starting
+    pub fn with_prestart_hook(self, hook: impl PreStartHook + 'static) -> HostBuilder {
+        let mut hooks = self.prestart_hooks.clone();
+        hooks.push(Box::new(hook));
+        HostBuilder {
+            prestart_hooks: hooks,
+            ..self
+        }
+    }
+
+    /// Adds a hook that runs while the host starts, before the host announces itself on the
+    /// lattice, and again when the host stops. Hooks are run in the order in which they were
+    /// added, and the first hook to fail causes the host's start to fail
+    pub fn with_bootstrap_hook(self, hook: impl BootstrapHook + 'static) -> HostBuilder {
+        let mut hooks = self.bootstrap_hooks.clone();
+        hooks.push(Box::new(hook));
+        HostBuilder {
+            bootstrap_hooks: hooks,
+            ..self
+        }
+    }
+
+    /// Gives every actor signed by the given issuer (an account public key) the links in the
+    /// bundle, such as links to the organization's logging and metrics providers. When this host
+    /// starts one of the issuer's actors, it advertises each bundle link for a capability the
+    /// actor's claims grant, unless the actor already has a link with the same contract ID and
+    /// link name. Bundles added for the same issuer are combined
+    pub fn with_issuer_bundle(self, issuer: &str, bundle: CapabilityBundle) -> HostBuilder {
+        let mut issuer_bundles = self.issuer_bundles.clone();
+        let merged = match issuer_bundles.remove(issuer) {
+            Some(existing) => existing.merge(bundle),
+            None => bundle,
+        };
+        issuer_bundles.insert(issuer.to_string(), merged);
+        HostBuilder {
+            issuer_bundles,
+            ..self
+        }
+    }
+
+    /// Sets where the host writes its upgrade journal when preparing to be replaced by a new
+    /// version of itself, and where it looks for the journal left by the host it replaces when
+    /// it starts. A host that finds a journal restores the state of the host that wrote it,
+    /// and fails to start if that host's version or namespace is incompatible with its own
+    pub fn with_upgrade_journal(self, path: impl AsRef<Path>) -> HostBuilder {
+        HostBuilder {
+            upgrade: UpgradeOptions {
+                journal: Some(path.as_ref().to_path_buf()),
+                ..self.upgrade
+            },
+            ..self
+        }
+    }
+
+    /// Adds a hook that runs once the host has drained and written its upgrade journal, to
+    /// signal whatever supervises the host's process to swap the host binary. Hooks are run in
+    /// the order in which they were added, and the first hook to fail fails the upgrade
+    pub fn with_upgrade_hook(self, hook: impl UpgradeHook + 'static) -> HostBuilder {
+        let mut hooks = self.upgrade.hooks.clone();
+        hooks.push(Box::new(hook));
+        HostBuilder {
+            upgrade: UpgradeOptions {
+                hooks,
+                ..self.upgrade
+            },
+            ..self
+        }
+    }
+
+    /// Designates an actor as this host's system actor. The host starts it before announcing
+    /// itself on the lattice and invokes it with `HostStarted` once it is up (an error fails
+    /// the host's start), with `HostStopping` when it begins to stop, and with
+    /// `HandleLatticeEvent` for each of the named control events (such as `ActorStarted`) that
+    /// the host publishes. The system actor can't be stopped or updated on its own
+    pub fn with_system_actor(self, actor: crate::Actor, events: &[&str]) -> HostBuilder {
+        HostBuilder {
+            system_actor: Some((actor.bytes, events.iter().map(|e| e.to_string()).collect())),
+            ..self
+        }
+    }
+
+    /// Sets the scheme used to compress the batches of actor claims that this host gossips to
+    /// the rest of the lattice. The default is DEFLATE. All hosts in a lattice can decode the
+    /// built-in schemes, but a custom scheme must be configured on every host
+    pub fn with_claims_compression(
+        self,
+        compression: impl ClaimsCompression + 'static,
+    ) -> HostBuilder {
+        HostBuilder {
+            claims_compression: Box::new(compression),
+            ..self
+        }
+    }
+
+    /// Publishes actor claims and link definitions in the format used before claims were
+    /// batched and signed, one unsigned message each, so that hosts from before then can read
+    /// them. Meant for rolling a lattice forward; this host still reads both formats, but hosts
+    /// with a roster refuse unsigned updates
+    pub fn with_legacy_gossip(self) -> HostBuilder {
+        HostBuilder {
+            legacy_gossip: true,
+            ..self
+        }
+    }
+
+    /// Sets the WebAssembly proposals that actors in this host may use. Actors that require a
+    /// disabled proposal fail to start with an error naming it
+    pub fn with_wasm_features(self, wasm_features: WasmFeatures) -> HostBuilder {
+        HostBuilder {
+            wasm_features,
+            ..self
+        }
+    }
+
+    /// Allows actors to use 64-bit linear memories (the WebAssembly memory64 proposal). Actors
+    /// that use them are rejected at start time unless this is enabled, and also if the host's
+    /// engine can't run them. Neither engine the host can be built with runs them yet, so for
+    /// now enabling this only changes the error such actors are rejected with
+    pub fn enable_memory64(self) -> HostBuilder {
+        HostBuilder {
+            wasm_features: WasmFeatures {
+                memory64: true,
+                ..self.wasm_features
+            },
+            ..self
+        }
+    }
+
+    /// Allows actors to declare more than one linear memory (the WebAssembly multi-memory
+    /// proposal). Actors that do so are rejected at start time unless this is enabled, and also
+    /// if the host's engine can't run them. Neither engine the host can be built with runs them
+    /// yet, so for now enabling this only changes the error such actors are rejected with
+    pub fn enable_multi_memory(self) -> HostBuilder {
+        HostBuilder {
+            wasm_features: WasmFeatures {
+                multi_memory: true,
+                ..self.wasm_features
+            },
+            ..self
+        }
+    }
+
+    /// Adds a sink that receives a cost record (execution time and payload sizes, attributed to
+    /// the actor and the issuer of its claims) for every invocation handled by an actor
+    pub fn with_billing_sink(self, sink: impl BillingSink + 'static) -> HostBuilder {
+        let mut billing_sinks = self.billing_sinks.clone();
+        billing_sinks.push(Box::new(sink));
+        HostBuilder {
+            billing_sinks,
+            ..self
+        }
+    }
+
+    /// Sets how long, and for how many keys per actor, the results of invocations carrying an
+    /// idempotency key are remembered. Retries with the same key within this window receive the
+    /// remembered result instead of executing the actor again. Defaults to 5 minutes and 1024
+    /// keys. Results are remembered in memory by the actor's instance on this host, so this is
+    /// best effort: they're lost when the actor stops or the host restarts
+    pub fn with_idempotency_window(self, ttl: Duration, capacity: usize) -> HostBuilder {
+        HostBuilder {
+            idempotency: IdempotencyConfig {
+                ttl,
+                capacity: capacity.max(1),
+            },
+            ..self
+        }
+    }
+
+    /// Enables execution snapshots. When an actor fails to handle an invocation, the host
+    /// writes the operation and payload into a bundle in the host's cache directory and
+    /// publishes an `ActorSnapshotCaptured` event with the path and digest of the bundle. Bundles
+    /// are stamped with the host's clock, and only the newest are kept (see `with_max_snapshots`).
+    /// The actor's linear memory is not captured, because the waPC engines don't expose it
+    pub fn enable_execution_snapshots(self) -> HostBuilder {
+        HostBuilder {
+            snapshots: Some(self.snapshots.unwrap_or_default()),
+            ..self
+        }
+    }
+
+    /// Sets how many execution snapshots the host keeps on disk (`DEFAULT_MAX_SNAPSHOTS` unless
+    /// set). Once a new snapshot goes over the limit, the oldest are deleted. This implicitly
+    /// enables execution snapshots
+    pub fn with_max_snapshots(self, max_snapshots: usize) -> HostBuilder {
+        let mut snapshots = self.snapshots.unwrap_or_default();
+        snapshots.max_snapshots = max_snapshots.max(1);
+        HostBuilder {
+            snapshots: Some(snapshots),
+            ..self
+        }
+    }
+
+    /// Adds a redactor that can scrub execution snapshots before they are written to disk.
+    /// This implicitly enables execution snapshots
+    pub fn with_snapshot_redactor(self, redactor: impl SnapshotRedactor + 'static) -> HostBuilder {
+        let mut snapshots = self.snapshots.unwrap_or_default();
+        snapshots.redactors.push(Box::new(redactor));
+        HostBuilder {
+            snapshots: Some(snapshots),
+            ..self
+        }
+    }
+
+    /// Trusts lattice state snapshots signed by the host or key with the given public key. A host
+    /// always trusts snapshots it signed itself, and refuses to import any other snapshot unless
+    /// its issuer was added here
+    pub fn with_snapshot_issuer(self, issuer: &str) -> HostBuilder {
+        let mut snapshot_issuers = self.snapshot_issuers.clone();
+        snapshot_issuers.push(issuer.to_string());
+        HostBuilder {
+            snapshot_issuers,
+            ..self
+        }
+    }
+
+    /// Registers a lattice extension, which receives messages on its own subjects beneath
+    /// `wasmbus.ext.{namespace}.{name}` over the host's control plane connection. Extensions are
+    /// only active when the host has a control client
+    pub fn with_lattice_extension(self, extension: impl LatticeExtension + 'static) -> HostBuilder {
+        let mut extensions = self.extensions.clone();
+        extensions.push(Box::new(extension));
+        HostBuilder { extensions, ..self }
+    }
+
+    /// Sets the service level objective for an actor. The host evaluates the actor's recent
+    /// invocations against the objective and publishes `ActorSloBreached` and
+    /// `ActorSloRecovered` events as the actor moves in and out of compliance
+    pub fn with_actor_slo(self, actor: &str, slo: ActorSlo) -> HostBuilder {
+        let mut slos = self.slos.clone();
+        slos.insert(actor.to_string(), slo);
+        HostBuilder { slos, ..self }
+    }
+
+    /// Sets the encoding of the events this host publishes on the control interface and posts to
+    /// alert webhooks. The default is the host's own JSON encoding; the CloudEvents formats give
+    /// each kind of event a stable type name and a versioned payload schema, so that any consumer
+    /// of CloudEvents can process them
+    pub fn with_event_format(self, event_format: EventFormat) -> HostBuilder {
+        HostBuilder {
+            event_format,
+            ..self
+        }
+    }
+
+    /// Requires an API token on every control interface request. Tokens minted by this host (see
+    /// `Host::mint_api_token`) or by any issuer added with `with_api_token_issuer` are accepted,
+    /// as long as they are for the host's namespace and their scope covers the request
+    pub fn require_api_tokens(self) -> HostBuilder {
+        HostBuilder {
+            api_tokens: Some(self.api_tokens.unwrap_or_default()),
+            ..self
+        }
+    }
+
+    /// Trusts API tokens minted by the key with the given public key, such as a key shared by
+    /// every host in a cluster, and requires an API token on every control interface request
+    pub fn with_api_token_issuer(self, issuer: &str) -> HostBuilder {
+        let mut policy = self.api_tokens.unwrap_or_default();
+        policy.issuers.push(issuer.to_string());
+        HostBuilder {
+            api_tokens: Some(policy),
+            ..self
+        }
+    }
+
+    /// Refuses the API token with the given ID (its `jti` claim)
+    pub fn with_revoked_api_token(self, token_id: &str) -> HostBuilder {
+        let mut policy = self.api_tokens.unwrap_or_default();
+        policy.revoked.insert(token_id.to_string());
+        HostBuilder {
+            api_tokens: Some(policy),
+            ..self
+        }
+    }
+
+    /// Adds a URL to which the host will POST each objective breach and recovery event, and
+    /// each slow consumer event, in the host's event format, in addition to publishing it on the
+    /// control interface
+    pub fn with_alert_webhook(self, url: &str) -> HostBuilder {
+        let mut alert_webhooks = self.alert_webhooks.clone();
+        alert_webhooks.push(url.to_string());
+        HostBuilder {
+            alert_webhooks,
+            ..self
+        }
+    }
+
+    /// Logs a warning for each capability that an actor is granted in its claims but hasn't
+    /// used within the given period after it started, to help tighten actor claims. The
+    /// capabilities actors have used so far are always available from `get_dependency_graph`
+    pub fn warn_unused_capabilities(self, grace: Duration) -> HostBuilder {
+        HostBuilder {
+            unused_capability_grace: Some(grace),
+            ..self
+        }
+    }
+
+    /// Copies the given percentage (0 to 100) of the invocations made to an actor to a shadow
+    /// actor, such as a rewrite of it, once the actor has answered them. Callers only ever get
+    /// the actor's responses: the shadow's are compared with them and discarded, and the host
+    /// counts matches and publishes an `ActorMirrorMismatch` event for each response that
+    /// differs (see `Host::mirror_stats`). While handling a copy the shadow can call other
+    /// actors, but its calls to capability providers are refused, so it has no side effects
+    pub fn with_mirror(self, actor: &str, shadow: &str, percent: f64) -> HostBuilder {
+        let mut mirrors = self.mirrors.clone();
+        mirrors.insert(actor.to_string(), Mirror::new(shadow, percent));
+        HostBuilder { mirrors, ..self }
+    }
+
+    /// Adds automation rules, one per line, that the host runs as it publishes its events. A
+    /// rule names an event, optionally the values some of its fields must have, how many times
+    /// it must happen (and within how long) before the rule acts, and the action to take:
+    /// `restart-provider`, `stop-actor`, `webhook <url>`, or `invoke <actor>` to hand the event
+    /// to a policy actor. For example,
+    /// `on ProviderUnhealthy where contract_id=wascc:keyvalue after 3 within 10m do webhook https://pager.example.com`.
+    /// Rules are checked when the host starts
+    pub fn with_automation_rules(self, rules: &str) -> HostBuilder {
+        let mut automation_rules = self.automation_rules.clone();
+        automation_rules.push(rules.to_string());
+        HostBuilder {
+            automation_rules,
+            ..self
+        }
+    }
+
+    /// Writes a record of each actor and provider stopped through this host to the given store:
+    /// an actor's claims, the links and link configuration the entity had, and the invocation
+    /// metrics the host measured for it. Records are kept for the given retention, and can be
+    /// read back with `Host::archived_entities`
+    pub fn with_archive(
+        self,
+        store: impl ArchiveStore + 'static,
+        retention: Duration,
+    ) -> HostBuilder {
+        HostBuilder {
+            archive: Some(Archive {
+                store: Box::new(store),
+                retention,
+            }),
+            ..self
+        }
+    }
+
+    /// Sets how the given actor is given the thread it runs on: a thread of its own (the
+    /// default), a share of a pool's threads, or none, running each invocation as a task on the
+    /// host's blocking thread pool. This takes precedence over any thread model the actor's
+    /// claims ask for with a `wasmcloud:threads=` tag. Actors that call each other must not share
+    /// a pool thread, and such calls fail
+    pub fn with_thread_model(self, actor: &str, model: ThreadModel) -> HostBuilder {
+        let mut threads = self.threads.clone();
+        threads.models.insert(actor.to_string(), model);
+        HostBuilder { threads, ..self }
+    }
+
+    /// Sets the number of threads of the named pool, which otherwise has 4. The pool's threads
+    /// start when the first actor placed in it does
+    pub fn with_thread_pool(self, name: &str, threads: usize) -> HostBuilder {
+        let mut config = self.threads.clone();
+        config.pool_sizes.insert(name.to_string(), threads);
+        HostBuilder {
+            threads: config,
+            ..self
+        }
+    }
+
+    /// Bounds the number of invocations on the given contract (for example, `wascc:keyvalue`)
+    /// that can be in flight in this host at once, counting both calls from actors to the
+    /// contract's providers and calls from those providers to actors. Once the bound is reached,
+    /// further invocations on the contract fail immediately instead of waiting, so a slow
+    /// contract can't tie up the actors needed to serve the others. Contracts without a bulkhead
+    /// are unbounded
+    pub fn with_bulkhead(self, contract_id: &str, max_concurrent: usize) -> HostBuilder {
+        let mut bulkheads = self.bulkheads.clone();
+        bulkheads.insert(contract_id.to_string(), max_concurrent);
+        HostBuilder { bulkheads, ..self }
+    }
+
+    /// Holds calls from the given contract's providers (for example, HTTP requests coming in
+    /// through `wascc:http_server`) to an actor until the actor has a link for every other
+    /// capability it is granted, so that requests arriving while an actor's links are still
+    /// being set up don't fail. Calls still waiting after `max_wait` are delivered anyway
+    pub fn with_readiness_gate(self, contract_id: &str, max_wait: Duration) -> HostBuilder {
+        let mut readiness_gates = self.readiness_gates.clone();
+        readiness_gates.insert(contract_id.to_string(), max_wait);
+        HostBuilder {
+            readiness_gates,
+            ..self
+        }
+    }
+
+    /// Caps the number of actors this host runs. Once the cap is reached, the host stops
+    /// answering actor auctions and refuses to start more actors until some are stopped
+    pub fn with_max_actors(self, max_actors: usize) -> HostBuilder {
+        HostBuilder {
+            limits: HostLimits {
+                max_actors: Some(max_actors),
+                ..self.limits
+            },
+            ..self
+        }
+    }
+
+    /// Caps the number of capability providers this host runs, not counting those it starts for
+    /// itself (such as `wascc:extras`). Once the cap is reached, the host stops answering provider
+    /// auctions and refuses to start more providers until some are stopped
+    pub fn with_max_providers(self, max_providers: usize) -> HostBuilder {
+        HostBuilder {
+            limits: HostLimits {
+                max_providers: Some(max_providers),
+                ..self.limits
+            },
+            ..self
+        }
+    }
+
+    /// Caps the total linear memory, in bytes, of the actors this host runs, counting each actor
+    /// as the initial size of the memory its module declares. Actors that would take the host
+    /// past the cap are refused, and the host stops answering actor auctions once it is reached
+    pub fn with_max_guest_memory(self, max_bytes: u64) -> HostBuilder {
+        HostBuilder {
+            limits: HostLimits {
+                max_guest_memory: Some(max_bytes),
+                ..self.limits
+            },
+            ..self
+        }
+    }
+
+    /// Caps the size, in bytes, of the responses the actors and providers in this host give,
+    /// whether to calls made within the host or to calls coming in over the lattice. A larger
+    /// response fails the call with a `ResponseTooLarge` error (see `RESPONSE_TOO_LARGE`) and is
+    /// counted in the link statistics. A cap no larger than the lattice's maximum message size
+    /// (`DEFAULT_LATTICE_MAX_PAYLOAD` unless the NATS servers are configured otherwise) keeps
+    /// such responses from being refused by the lattice
+    pub fn with_max_response_size(self, max_bytes: usize) -> HostBuilder {
+        HostBuilder {
+            limits: HostLimits {
+                max_response_size: Some(max_bytes),
+                ..self.limits
+            },
+            ..self
+        }
+    }
+
+    /// Puts the payloads of invocations that would be larger than the lattice's maximum message
+    /// size (the `max_payload` of the NATS servers, `DEFAULT_LATTICE_MAX_PAYLOAD` unless they are
+    /// configured otherwise) into the given store, sending a reference to the payload over the
+    /// lattice instead. Hosts that receive such invocations fetch and verify the payload from
+    /// their own store, so every host in the lattice should be given a store that reaches the
+    /// same objects
+    pub fn with_payload_store(
+        self,
+        store: impl PayloadStore + 'static,
+        max_payload: usize,
+    ) -> HostBuilder {
+        HostBuilder {
+            payload_offload: Some(PayloadOffload {
+                max_payload,
+                store: Box::new(store),
+            }),
+            ..self
+        }
+    }
+
     pub fn with_namespace(self, namespace: &str) -> HostBuilder {
         HostBuilder {
             namespace: namespace.to_string(),
@@ -84,6 +853,8 @@ impl HostBuilder {
         }
     }
 
+    /// The amount of time to wait for a reply to an RPC call made over the lattice. The
+    /// default is 2 seconds
     pub fn with_rpc_timeout(self, rpc_timeout: Duration) -> HostBuilder {
         HostBuilder {
             rpc_timeout,
@@ -91,6 +862,15 @@ impl HostBuilder {
         }
     }
 
+    /// The interval at which the host emits heartbeat events on the control interface. The
+    /// default is 30 seconds unless overridden by the `HEARTBEAT_INTERVAL_S` environment variable
+    pub fn with_heartbeat_interval(self, hb_interval: Duration) -> HostBuilder {
+        HostBuilder {
+            hb_interval,
+            ..self
+        }
+    }
+
     /// Consulted when a host runtime needs to download an image from an OCI registry,
     /// this option enables the use of images tagged 'latest'. The default is `false` to prevent
     /// accidental mutation of images, close potential attack vectors, and prevent against
@@ -104,6 +884,112 @@ impl HostBuilder {
         }
     }
 
+    /// Starts the built-in `wascc:discovery` capability provider in this host, allowing linked
+    /// actors to resolve logical service names into connection strings. Names are resolved
+    /// against the values on the actor's link to the provider. To also supply host-wide
+    /// defaults, use `with_service`
+    pub fn enable_service_discovery(self) -> HostBuilder {
+        HostBuilder {
+            services: Some(self.services.unwrap_or_default()),
+            ..self
+        }
+    }
+
+    /// Registers a host-wide connection string for a logical service name, e.g. `redis` to
+    /// `redis://127.0.0.1:6379`. This implicitly enables service discovery. A value for the same
+    /// name on an actor's link to the discovery provider takes precedence over this one
+    pub fn with_service(self, name: &str, connection: &str) -> HostBuilder {
+        let mut services = self.services.unwrap_or_default();
+        services.insert(name.to_string(), connection.to_string());
+        HostBuilder {
+            services: Some(services),
+            ..self
+        }
+    }
+
+    /// Starts the built-in HTTP router in this host, listening on the given port. Actors linked to
+    /// the router (`ROUTER_PUBLIC_KEY`, contract `wascc:http_server`) receive the requests that
+    /// match the `ROUTE_PATH_PREFIX` and `ROUTE_HOST` values on their links, so one listener can
+    /// serve many actors. The port can only be used by one host per machine
+    pub fn with_http_router(self, port: u16) -> HostBuilder {
+        HostBuilder {
+            http_router: Some(port),
+            ..self
+        }
+    }
+
+    /// Starts the built-in HTTP router with a listener that terminates TLS on the given port, in
+    /// addition to (or instead of) the plain HTTP port. Routes are served with the certificate
+    /// and key named by the `ROUTE_TLS_CERT` and `ROUTE_TLS_KEY` values on their links, picked by
+    /// the server name the client asks for
+    pub fn with_https_router(self, port: u16) -> HostBuilder {
+        HostBuilder {
+            https_router: Some(port),
+            ..self
+        }
+    }
+
+    /// Starts the built-in gRPC ingress provider in this host, listening for cleartext HTTP/2 on
+    /// the given port. Calls are delivered to the actor linked to the provider (`GRPC_PUBLIC_KEY`,
+    /// contract `wasmcloud:grpc_server`) whose `GRPC_SERVICES` link value names the call's service
+    pub fn with_grpc_ingress(self, port: u16) -> HostBuilder {
+        HostBuilder {
+            grpc_ingress: Some(port),
+            ..self
+        }
+    }
+
+    /// Starts the built-in in-memory key-value provider (`KEYVALUE_PUBLIC_KEY`, contract
+    /// `wascc:keyvalue`) in this host. Each actor linked to it gets its own key space, which lasts
+    /// until the host stops. It supports the key-value contract extensions (`OP_COMPARE_AND_SWAP`,
+    /// `OP_INCREMENT`, `OP_EXPIRE`, `OP_TTL`, and `OP_SCAN`)
+    pub fn with_memory_keyvalue(self) -> HostBuilder {
+        HostBuilder {
+            memory_keyvalue: true,
+            ..self
+        }
+    }
+
+    /// Seeds the random numbers and GUIDs that actors obtain from the built-in `wascc:extras`
+    /// provider, making them reproducible between runs. Each actor receives its own sequence
+    /// derived from this seed. An actor can override this with a `wasmcloud:seed=<n>` claims tag
+    pub fn with_random_seed(self, seed: u64) -> HostBuilder {
+        HostBuilder {
+            determinism: Determinism {
+                seed: Some(seed),
+                ..self.determinism
+            },
+            ..self
+        }
+    }
+
+    /// Sets the clock the host reads the time from for lease expiries, event timestamps, its
+    /// uptime, and cost records, in place of the system clock. Tests can pass a clone of a
+    /// `ManualClock` and advance it to move the host's time forward
+    pub fn with_clock(self, clock: impl Clock + 'static) -> HostBuilder {
+        HostBuilder {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Pins the time that actors obtain from the built-in `wascc:extras` provider to the given
+    /// instant rather than the real clock. An actor can override this with a
+    /// `wasmcloud:clock=<ms since epoch>` claims tag
+    pub fn with_fixed_clock(self, time: SystemTime) -> HostBuilder {
+        let clock_ms = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        HostBuilder {
+            determinism: Determinism {
+                clock_ms: Some(clock_ms),
+                ..self.determinism
+            },
+            ..self
+        }
+    }
+
     pub fn with_label(self, key: &str, value: &str) -> HostBuilder {
         let mut hm = self.labels.clone();
         if !hm.contains_key(key) {
@@ -112,18 +998,124 @@ impl HostBuilder {
         HostBuilder { labels: hm, ..self }
     }
 
+    /// Sets how the host samples the invocations entering the lattice for tracing. By default every
+    /// invocation is traced. The sampling can later be changed across the lattice through the
+    /// control interface
+    pub fn with_trace_sampling(self, sampling: TraceSampling) -> HostBuilder {
+        HostBuilder {
+            trace_sampling: sampling,
+            ..self
+        }
+    }
+
+    /// Sets how long each phase of stopping the host may take: waiting for invocations in flight
+    /// to be answered, waiting for providers to clean up, and flushing events to the lattice. What
+    /// is left undone at a deadline is listed in the report returned by `Host::stop`
+    pub fn with_shutdown_deadline(self, deadline: Duration) -> HostBuilder {
+        HostBuilder {
+            shutdown_deadline: deadline,
+            ..self
+        }
+    }
+
+    /// Starts a watchdog that checks the host's dispatcher, cache sync, heartbeat, and event
+    /// publisher are making progress. A subsystem that stalls for longer than the configured
+    /// threshold is reported with a `SubsystemStalled` event, and restarted where that is safe.
+    /// Restarting the whole host requires an upgrade journal (see `with_upgrade_journal`)
+    pub fn with_watchdog(self, config: WatchdogConfig) -> HostBuilder {
+        HostBuilder {
+            watchdog: Some(config),
+            ..self
+        }
+    }
+
+    /// Adds a traffic shaping rule, spreading the calls actors make on one link name of a
+    /// contract across other link names of that contract. Rules are checked in the order they
+    /// are added. They can be replaced while the host runs with `Host::set_traffic_shaping`
+    pub fn with_traffic_shaping(self, rule: ShapingRule) -> HostBuilder {
+        let mut traffic_shaping = self.traffic_shaping.clone();
+        traffic_shaping.push(rule);
+        HostBuilder {
+            traffic_shaping,
+            ..self
+        }
+    }
+
+    /// Adds a static label to every metric and event emitted by this host, alongside the
+    /// `namespace`, `host_id`, and `issuer` labels the host always sets. Those three names can't be
+    /// used for custom labels
+    pub fn with_metric_label(self, key: &str, value: &str) -> HostBuilder {
+        if crate::labels::is_reserved(key) {
+            warn!("Ignoring metric label '{}', which is set by the host", key);
+            return self;
+        }
+        let mut metric_labels = self.metric_labels.clone();
+        metric_labels.insert(key.to_string(), value.to_string());
+        HostBuilder {
+            metric_labels,
+            ..self
+        }
+    }
+
     pub fn build(self) -> Host {
         Host {
             labels: self.labels,
             authorizer: self.authorizer,
+            prestart_hooks: self.prestart_hooks,
+            bootstrap_hooks: self.bootstrap_hooks,
+            system_actor: self.system_actor,
             id: RefCell::new("".to_string()),
             allow_latest: self.allow_latest,
             kp: RefCell::new(None),
             rpc_timeout: self.rpc_timeout,
+            hb_interval: self.hb_interval,
             namespace: self.namespace,
             rpc_client: self.rpc_client,
             cplane_client: self.cplane_client,
             allow_live_updates: self.allow_live_update,
+            services: self.services,
+            http_router: self.http_router,
+            https_router: self.https_router,
+            grpc_ingress: self.grpc_ingress,
+            memory_keyvalue: self.memory_keyvalue,
+            determinism: self.determinism,
+            claims_compression: self.claims_compression,
+            legacy_gossip: self.legacy_gossip,
+            snapshots: self.snapshots,
+            snapshot_issuers: self.snapshot_issuers,
+            extensions: self.extensions,
+            slos: self.slos,
+            alert_webhooks: self.alert_webhooks,
+            lattice_creds: self.lattice_creds,
+            wasm_features: self.wasm_features,
+            unused_capability_grace: self.unused_capability_grace,
+            payload_offload: self.payload_offload,
+            billing_sinks: self.billing_sinks,
+            idempotency: self.idempotency,
+            preload: self.preload,
+            offline_bundle: self.offline_bundle,
+            metric_labels: self.metric_labels,
+            strict: self.strict,
+            denied_providers: self.denied_providers,
+            bulkheads: self.bulkheads,
+            readiness_gates: self.readiness_gates,
+            event_format: self.event_format,
+            api_tokens: self.api_tokens,
+            limits: self.limits,
+            shared_connection: self.shared_connection,
+            clock: self.clock,
+            trace_sampling: self.trace_sampling,
+            upgrade: self.upgrade,
+            issuer_bundles: self.issuer_bundles,
+            mirrors: self.mirrors,
+            archive: self.archive,
+            automation_rules: self.automation_rules,
+            roster: self.roster,
+            threads: self.threads,
+            traffic_shaping: self.traffic_shaping,
+            shutdown_deadline: self.shutdown_deadline,
+            watchdog: self.watchdog,
+            watchdog_thread: RefCell::new(None),
         }
     }
 }
@@ -131,53 +1123,248 @@ impl HostBuilder {
 pub struct Host {
     labels: HashMap<String, String>,
     authorizer: Box<dyn Authorizer + 'static>,
+    prestart_hooks: Vec<Box<dyn PreStartHook>>,
+    bootstrap_hooks: Vec<Box<dyn BootstrapHook>>,
+    system_actor: Option<(Vec<u8>, Vec<String>)>,
     id: RefCell<String>,
     allow_latest: bool,
     kp: RefCell<Option<KeyPair>>,
     namespace: String,
     rpc_timeout: Duration,
+    hb_interval: Duration,
     cplane_client: Option<nats::asynk::Connection>,
     rpc_client: Option<nats::asynk::Connection>,
     allow_live_updates: bool,
+    services: Option<HashMap<String, String>>,
+    http_router: Option<u16>,
+    https_router: Option<u16>,
+    grpc_ingress: Option<u16>,
+    memory_keyvalue: bool,
+    determinism: Determinism,
+    claims_compression: Box<dyn ClaimsCompression>,
+    legacy_gossip: bool,
+    snapshots: Option<SnapshotConfig>,
+    snapshot_issuers: Vec<String>,
+    extensions: Vec<Box<dyn LatticeExtension>>,
+    slos: HashMap<String, ActorSlo>,
+    alert_webhooks: Vec<String>,
+    lattice_creds: Option<(String, PathBuf)>,
+    wasm_features: WasmFeatures,
+    unused_capability_grace: Option<Duration>,
+    payload_offload: Option<PayloadOffload>,
+    billing_sinks: Vec<Box<dyn BillingSink>>,
+    idempotency: IdempotencyConfig,
+    preload: Vec<String>,
+    offline_bundle: Option<OfflineBundle>,
+    metric_labels: HashMap<String, String>,
+    strict: bool,
+    denied_providers: Vec<String>,
+    bulkheads: HashMap<String, usize>,
+    readiness_gates: HashMap<String, Duration>,
+    event_format: EventFormat,
+    api_tokens: Option<ApiTokenPolicy>,
+    limits: HostLimits,
+    shared_connection: Option<SharedConnection>,
+    clock: Arc<dyn Clock>,
+    trace_sampling: TraceSampling,
+    upgrade: UpgradeOptions,
+    issuer_bundles: HashMap<String, CapabilityBundle>,
+    mirrors: HashMap<String, Mirror>,
+    archive: Option<Archive>,
+    automation_rules: Vec<String>,
+    roster: Option<RosterConfig>,
+    threads: ThreadConfig,
+    traffic_shaping: Vec<ShapingRule>,
+    shutdown_deadline: Duration,
+    watchdog: Option<WatchdogConfig>,
+    watchdog_thread: RefCell<Option<(Arbiter, Addr<Watchdog>)>>,
 }
 
 impl Host {
     /// Starts the host's actor system. This call is non-blocking, so it is up to the consumer
     /// to provide some form of parking or waiting (e.g. wait for a Ctrl-C signal).
     pub async fn start(&self) -> Result<()> {
+        self.trace_sampling.validate()?;
+        for mirror in self.mirrors.values() {
+            mirror.validate()?;
+        }
+        self.threads.validate()?;
+        let unsupported = crate::wasm_features::unsupported_by_engine(&self.wasm_features);
+        if !unsupported.is_empty() {
+            warn!(
+                "The {} engine can't run actors that use {}, so they will be rejected",
+                Host::wasm_engine(),
+                unsupported.join(" or ")
+            );
+        }
+        for rule in self.traffic_shaping.iter() {
+            rule.validate()?;
+        }
+        if let Some(ref watchdog) = self.watchdog {
+            if watchdog.threshold.as_millis() == 0 {
+                return Err("Watchdog threshold must be greater than zero".into());
+            }
+            if watchdog.restart_host && self.upgrade.journal.is_none() {
+                return Err("Watchdog host restarts require an upgrade journal".into());
+            }
+        }
+        let rules = crate::automation::parse_rules(&self.automation_rules.join("\n"))?;
+        if let Some(ref roster) = self.roster {
+            KeyPair::from_seed(&roster.admission_seed)
+                .map_err(|e| format!("Invalid roster admission key: {}", e))?;
+        }
+        let journal = match self.upgrade.journal {
+            Some(ref path) => crate::self_update::read_journal(path, &self.namespace)?,
+            None => None,
+        };
         let kp = KeyPair::new_server();
+        let mut guard = StartGuard {
+            host: self,
+            host_id: kp.public_key(),
+            armed: true,
+        };
+        if let Some(ref bundle) = self.offline_bundle {
+            crate::bundle::activate(&kp.public_key(), bundle.clone());
+        }
+        if let Some(ref shared) = self.shared_connection {
+            shared.attach(&self.namespace, &kp.public_key())?;
+        }
+        crate::labels::register(&kp.public_key(), &self.namespace, &self.metric_labels);
+        crate::clock::register(&kp.public_key(), self.clock.clone());
+        crate::sampling::set(&kp.public_key(), self.trace_sampling.clone());
+        crate::shaping::set(&kp.public_key(), self.traffic_shaping.clone());
+        crate::limits::set_max_response_size(&kp.public_key(), self.limits.max_response_size);
+        crate::automation::register(
+            &kp.public_key(),
+            rules,
+            self.allow_latest,
+            self.event_format,
+        );
+
+        let (rpc_client, cplane_client) = match self.lattice_creds {
+            Some((ref url, ref creds))
+                if self.rpc_client.is_none() || self.cplane_client.is_none() =>
+            {
+                // Replies to the host's requests arrive beneath its user's own inbox prefix
+                crate::inboxes::register(
+                    &kp.public_key(),
+                    &crate::lattice_auth::creds_inbox_prefix(creds)?,
+                );
+                let nc = nats::Options::with_credentials(creds)
+                    .with_name(&format!("wasmcloud-host-{}", kp.public_key()))
+                    .connect_async(url)
+                    .await?;
+                (
+                    self.rpc_client.clone().or_else(|| Some(nc.clone())),
+                    self.cplane_client.clone().or(Some(nc)),
+                )
+            }
+            _ => (self.rpc_client.clone(), self.cplane_client.clone()),
+        };
 
         let mb = MessageBus::from_hostlocal_registry(&kp.public_key());
         let init = crate::messagebus::Initialize {
-            nc: self.rpc_client.clone(),
+            nc: rpc_client.clone(),
             namespace: Some(self.namespace.to_string()),
             key: KeyPair::from_seed(&kp.seed()?)?,
             auth: self.authorizer.clone(),
             rpc_timeout: self.rpc_timeout.clone(),
+            hb_interval: self.hb_interval,
+            claims_compression: self.claims_compression.clone(),
+            legacy_gossip: self.legacy_gossip,
+            zone: crate::host_controller::locality(&self.labels),
+            slos: self.slos.clone(),
+            alert_webhooks: self.alert_webhooks.clone(),
+            unused_capability_grace: self.unused_capability_grace,
+            payload_offload: self.payload_offload.clone(),
+            bulkheads: self.bulkheads.clone(),
+            readiness_gates: self.readiness_gates.clone(),
+            issuer_bundles: self.issuer_bundles.clone(),
+            mirrors: self.mirrors.clone(),
+            archive: self.archive.clone(),
+            event_format: self.event_format,
+            max_response_size: self.limits.max_response_size,
         };
         mb.send(init).await?;
 
+        let ls = LockService::from_hostlocal_registry(&kp.public_key());
+        ls.send(crate::locks::Initialize {
+            nc: rpc_client.clone(),
+            namespace: Some(self.namespace.to_string()),
+            host_id: kp.public_key(),
+        })
+        .await?;
+
+        let roster = RosterService::from_hostlocal_registry(&kp.public_key());
+        roster
+            .send(crate::roster::Initialize {
+                nc: rpc_client.clone(),
+                namespace: Some(self.namespace.to_string()),
+                host_id: kp.public_key(),
+                config: self.roster.clone(),
+                labels: self.labels.clone(),
+                announce_interval: self.hb_interval,
+            })
+            .await?;
+
         let hc = HostController::from_hostlocal_registry(&kp.public_key());
         hc.send(crate::host_controller::Initialize {
             labels: self.labels.clone(),
             auth: self.authorizer.clone(),
+            prestart_hooks: self.prestart_hooks.clone(),
             kp: KeyPair::from_seed(&kp.seed()?)?,
             allow_live_updates: self.allow_live_updates,
+            services: self.services.clone(),
+            http_router: self.http_router,
+            https_router: self.https_router,
+            grpc_ingress: self.grpc_ingress,
+            memory_keyvalue: self.memory_keyvalue,
+            determinism: self.determinism.clone(),
+            snapshots: self.snapshots.clone(),
+            wasm_features: self.wasm_features,
+            billing_sinks: self.billing_sinks.clone(),
+            idempotency: self.idempotency.clone(),
+            strict: self.strict,
+            denied_providers: self.denied_providers.clone(),
+            limits: self.limits,
+            threads: self.threads.clone(),
         })
         .await?;
         *self.id.borrow_mut() = kp.public_key();
 
+        if let Err(e) = self.bootstrap(&kp).await {
+            error!("Host failed to bootstrap: {}", e);
+            *self.kp.borrow_mut() = Some(kp);
+            guard.armed = false;
+            self.stop().await;
+            return Err(e);
+        }
+
         // Start control plane
         let cp = ControlInterface::from_hostlocal_registry(&kp.public_key());
         cp.send(crate::control_interface::ctlactor::Initialize {
-            client: self.cplane_client.clone(),
+            client: cplane_client,
             control_options: ControlOptions {
                 host_labels: self.labels.clone(),
                 oci_allow_latest: self.allow_latest,
+                allow_live_updates: self.allow_live_updates,
+                rpc_timeout: self.rpc_timeout,
+                heartbeat_interval: self.hb_interval,
+                lattice_rpc: rpc_client.is_some(),
+                event_format: self.event_format,
+                // The host can always mint tokens for itself
+                api_tokens: self.api_tokens.clone().map(|mut policy| {
+                    policy.issuers.push(kp.public_key());
+                    policy
+                }),
+                upgrade: self.upgrade.clone(),
+                limits: self.limits,
+                features: self.features(),
                 ..Default::default()
             },
             key: KeyPair::from_seed(&kp.seed()?)?,
             ns_prefix: self.namespace.to_string(),
+            extensions: self.extensions.clone(),
         })
         .await?;
 
@@ -187,19 +1374,171 @@ impl Host {
             })
             .await;
 
+        if !self.preload.is_empty() {
+            actix::spawn(crate::preload::preload(
+                kp.public_key(),
+                self.preload.clone(),
+                self.allow_latest,
+            ));
+        }
+
+        if let Some(ref config) = self.watchdog {
+            let rpc = mb.send(QueryRpcClient).await?;
+            let arbiter = Arbiter::new();
+            let kp = KeyPair::from_seed(&kp.seed()?)?;
+            let namespace = self.namespace.to_string();
+            let config = config.clone();
+            let hb_interval = self.hb_interval;
+            let upgrade = self.upgrade.clone();
+            let watchdog = Watchdog::start_in_arbiter(&arbiter, move |_| {
+                Watchdog::new(kp, &namespace, config, hb_interval, upgrade, rpc)
+            });
+            *self.watchdog_thread.borrow_mut() = Some((arbiter, watchdog));
+        }
+
         *self.kp.borrow_mut() = Some(kp);
+        // From here on, `stop` releases everything the host registered
+        guard.armed = false;
+
+        if let (Some(journal), Some(path)) = (journal, self.upgrade.journal.as_ref()) {
+            let id = self.id();
+            if let Err(e) = crate::self_update::resume(&id, self.allow_latest, &journal, path).await
+            {
+                error!(
+                    "Host failed to take over from host {}: {}",
+                    journal.host_id, e
+                );
+                self.stop().await;
+                return Err(e);
+            }
+        }
 
         Ok(())
     }
 
-    pub async fn stop(&self) {
-        let cp = ControlInterface::from_hostlocal_registry(&self.id.borrow());
+    /// Stops the host, releasing all of its actors, capability providers, and lattice
+    /// subscriptions. Other hosts running within the same actix system are unaffected. The
+    /// report lists what was stopped and any work that was lost, such as invocations still in
+    /// flight or providers that didn't clean up in time (see `HostBuilder::with_shutdown_deadline`)
+    pub async fn stop(&self) -> ShutdownReport {
+        let id = self.id();
+        if id.is_empty() || self.kp.borrow().is_none() {
+            return ShutdownReport::default(); // never started or already stopped
+        }
+        let started = Instant::now();
+        let deadline = self.shutdown_deadline;
+        // Stop the watchdog first, so it doesn't take the host stopping for a stall
+        let watchdog = self.watchdog_thread.borrow_mut().take();
+        if let Some((arbiter, watchdog)) = watchdog {
+            let _ = watchdog.send(Shutdown).await;
+            arbiter.stop();
+        }
+        if let Some(actor) = crate::system_actor::system_actor(&id) {
+            let kp = self.kp.borrow().as_ref().map(|k| k.seed());
+            if let Some(Ok(seed)) = kp {
+                let event = ControlEvent::HostStopped.into_published(&id);
+                if let Err(e) = crate::system_actor::notify(
+                    &KeyPair::from_seed(&seed).unwrap(),
+                    &actor,
+                    crate::system_actor::OP_HOST_STOPPING,
+                    &event,
+                )
+                .await
+                {
+                    warn!("{}", e);
+                }
+            }
+        }
+        for hook in self.bootstrap_hooks.iter() {
+            hook.on_host_stop(&id);
+        }
+        let cp = ControlInterface::from_hostlocal_registry(&id);
         let _ = cp
             .send(PublishEvent {
                 event: ControlEvent::HostStopped,
             })
             .await;
-        System::current().stop();
+        let hc = HostController::from_hostlocal_registry(&id);
+        let mb = MessageBus::from_hostlocal_registry(&id);
+        let roster = RosterService::from_hostlocal_registry(&id);
+        // Leave the roster first, so the other members stop routing to this host
+        let _ = roster.send(Shutdown).await;
+        let _ = hc.send(Drain).await;
+        let mut report = ShutdownReport {
+            host_id: id.to_string(),
+            ..Default::default()
+        };
+        if let Ok(drained) = mb.send(DrainBus { deadline }).await {
+            report.invocations_drained = drained.drained;
+            report.invocations_aborted = drained.aborted;
+            report.claims_flush = drained.claims_flush;
+        }
+        if let Ok(stopped) = hc.send(StopAll { deadline }).await {
+            report.actors_stopped = stopped.actors;
+            report.providers_stopped = stopped.providers;
+            report.providers_timed_out = stopped.timed_out;
+        }
+        report.unacked_events = cp.send(FlushEvents { deadline }).await.unwrap_or_default();
+        crate::hlreg::remove_host(&id);
+        let _ = hc.send(Shutdown).await;
+        let _ = mb.send(Shutdown).await;
+        let _ = cp.send(Shutdown).await;
+        let _ = LockService::from_hostlocal_registry(&id)
+            .send(Shutdown)
+            .await;
+        clear_host_globals(&id);
+        if let Some(ref shared) = self.shared_connection {
+            shared.detach(&id).await;
+        }
+        *self.kp.borrow_mut() = None;
+        report.duration_ms = started.elapsed().as_millis() as u64;
+        if !report.is_clean() {
+            warn!("Host {} did not stop cleanly: {:?}", id, report);
+        }
+        report
+    }
+
+    // Runs the bootstrap hooks, then starts the system actor and tells it that the host has
+    // started. This happens before the control interface is up, so the rest of the lattice
+    // never sees a host that hasn't finished bootstrapping
+    async fn bootstrap(&self, kp: &KeyPair) -> Result<()> {
+        let host_id = kp.public_key();
+        crate::hooks::run_bootstrap_hooks(&self.bootstrap_hooks, &host_id, &self.labels)?;
+        let (bytes, events) = match self.system_actor {
+            Some(ref sa) => sa,
+            None => return Ok(()),
+        };
+        let actor = crate::Actor::from_slice(bytes)?;
+        let pk = actor.public_key();
+        HostController::from_hostlocal_registry(&host_id)
+            .send(StartActor {
+                actor,
+                image_ref: None,
+            })
+            .await??;
+        crate::system_actor::register(&host_id, &pk, events);
+        let event = ControlEvent::HostStarted.into_published(&host_id);
+        crate::system_actor::notify(kp, &pk, crate::system_actor::OP_HOST_STARTED, &event).await
+    }
+
+    /// Starts the host, runs the supplied closure against it, and then stops the host
+    /// regardless of whether the closure succeeded. This ties everything the host spawned
+    /// to the scope of the closure
+    /// ```ignore
+    /// host.run_scoped(|h| async move {
+    ///     h.start_actor(actor).await?;
+    ///     Ok(())
+    /// }).await?;
+    /// ```
+    pub async fn run_scoped<'a, F, Fut, T>(&'a self, f: F) -> Result<T>
+    where
+        F: FnOnce(&'a Host) -> Fut,
+        Fut: std::future::Future<Output = Result<T>> + 'a,
+    {
+        self.start().await?;
+        let res = f(self).await;
+        self.stop().await;
+        res
     }
 
     pub fn id(&self) -> String {
@@ -218,15 +1557,56 @@ impl Host {
         Ok(())
     }
 
+    /// Replaces a running capability provider with a new version of it (for example, from a new
+    /// archive), without dropping the links bound to it. The new version is started alongside the
+    /// old one, given each of the old one's links, and must report itself healthy before
+    /// invocations are routed to it and the old one is stopped. If any of that fails, the old
+    /// version keeps running and an error is returned
+    pub async fn update_native_capability(
+        &self,
+        provider_id: &str,
+        new_archive: crate::NativeCapability,
+    ) -> Result<()> {
+        if new_archive.id() != provider_id {
+            return Err(format!(
+                "Archive contains provider {}, not {}",
+                new_archive.id(),
+                provider_id
+            )
+            .into());
+        }
+        let hc = HostController::from_hostlocal_registry(&self.id.borrow());
+        hc.send(UpgradeProvider {
+            provider: new_archive,
+            image_ref: None,
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Live updates a running actor, started from the given image reference, with a new version
+    /// of its module. The new version is held to the same checks as a starting actor (strict
+    /// mode, the host's limits and the pre-start hooks), and the running version is left as it
+    /// was if it's rejected. Live updates must be enabled on the host
+    pub async fn update_actor(&self, image_ref: &str, new_actor: crate::Actor) -> Result<()> {
+        let hc = HostController::from_hostlocal_registry(&self.id.borrow());
+        hc.send(UpdateActor {
+            actor_id: new_actor.public_key(),
+            actor: new_actor,
+            image_ref: image_ref.to_string(),
+        })
+        .await??;
+        Ok(())
+    }
+
     pub async fn start_capability_from_registry(
         &self,
         cap_ref: &str,
         link_name: Option<String>,
     ) -> Result<()> {
         let hc = HostController::from_hostlocal_registry(&self.id.borrow());
-        let bytes = fetch_oci_bytes(cap_ref, self.allow_latest).await?;
-        let par = ProviderArchive::try_load(&bytes)?;
-        let nc = NativeCapability::from_archive(&par, link_name)?;
+        let nc =
+            crate::oci::fetch_provider(&self.id(), cap_ref, self.allow_latest, link_name).await?;
         hc.send(StartProvider {
             provider: nc,
             image_ref: Some(cap_ref.to_string()),
@@ -248,7 +1628,7 @@ impl Host {
 
     pub async fn start_actor_from_registry(&self, actor_ref: &str) -> Result<()> {
         let hc = HostController::from_hostlocal_registry(&self.id.borrow());
-        let bytes = fetch_oci_bytes(actor_ref, self.allow_latest).await?;
+        let bytes = fetch_oci_bytes(&self.id(), actor_ref, self.allow_latest).await?;
         let actor = crate::Actor::from_slice(&bytes)?;
         hc.send(StartActor {
             actor,
@@ -258,7 +1638,57 @@ impl Host {
         Ok(())
     }
 
+    /// Resolves the function names in the backtraces of an actor's traps using the name section
+    /// of the given module, an unstripped build of the running actor. Until this is called, only
+    /// the names in the running module itself (if it has any) are used
+    pub fn upload_actor_symbols(&self, actor: &str, debug_module: &[u8]) -> Result<()> {
+        crate::symbols::upload(&self.id(), actor, debug_module)
+    }
+
+    /// Mints an API token for this host's namespace, signed with the host's key, for use with a
+    /// control interface client (`Client::with_token`). A token with a time to live expires once
+    /// it runs out
+    pub fn mint_api_token(
+        &self,
+        name: &str,
+        scope: TokenScope,
+        ttl: Option<Duration>,
+    ) -> Result<String> {
+        let kp = self.kp.borrow();
+        let kp = kp.as_ref().ok_or("Host is not running")?;
+        ::control_interface::tokens::mint_token(
+            kp,
+            name,
+            &Some(self.namespace.to_string()),
+            scope,
+            ttl,
+        )
+    }
+
+    /// Refuses the API token with the given ID (its `jti` claim) from now on. The revocation is
+    /// signed with the host's key and published to the namespace, so every host that trusts this
+    /// host as an issuer refuses the token too, including hosts that start later. Tokens minted
+    /// by a key shared across a cluster are revoked with that key, using the control interface
+    /// client's `revoke_api_token`
+    pub async fn revoke_api_token(&self, token_id: &str) -> Result<()> {
+        let revocation = {
+            let kp = self.kp.borrow();
+            let kp = kp.as_ref().ok_or("Host is not running")?;
+            ::control_interface::tokens::mint_revocation(
+                kp,
+                token_id,
+                &Some(self.namespace.to_string()),
+            )?
+        };
+        let ctl = ControlInterface::from_hostlocal_registry(&self.id.borrow());
+        ctl.send(RevokeApiToken { revocation }).await??;
+        Ok(())
+    }
+
     pub async fn stop_actor(&self, actor_ref: &str) -> Result<()> {
+        if crate::system_actor::is_reserved(&self.id(), actor_ref) {
+            return Err("The system actor can't be stopped; it stops with the host".into());
+        }
         let hc = HostController::from_hostlocal_registry(&self.id.borrow());
         hc.send(StopActor {
             actor_ref: actor_ref.to_string(),
@@ -285,6 +1715,40 @@ impl Host {
         Ok(())
     }
 
+    /// Asks a running provider to stop accepting new external work (for example, an HTTP
+    /// server provider might close its listener) while it finishes requests already in flight.
+    /// Unlike stopping the provider or removing links, the provider's links are left in place,
+    /// so it can be brought back with `resume_provider` once maintenance is over. Providers that
+    /// don't support quiescing return an error. The built-in HTTP router and gRPC server support
+    /// it, answering new requests with 503 Service Unavailable and UNAVAILABLE respectively
+    pub async fn quiesce_provider(&self, provider_id: &str, link_name: &str) -> Result<()> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        bus.send(QuiesceProvider {
+            provider_id: provider_id.to_string(),
+            link_name: link_name.to_string(),
+            quiesce: true,
+        })
+        .await?
+    }
+
+    /// Asks a previously quiesced provider to start accepting new work again
+    pub async fn resume_provider(&self, provider_id: &str, link_name: &str) -> Result<()> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        bus.send(QuiesceProvider {
+            provider_id: provider_id.to_string(),
+            link_name: link_name.to_string(),
+            quiesce: false,
+        })
+        .await?
+    }
+
+    /// Returns the ID and link name of each provider that has been quiesced and not yet resumed.
+    /// The same state is reported in the `quiesced` field of the host's inventory
+    pub async fn get_quiesced_providers(&self) -> Result<Vec<(String, String)>> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(bus.send(QueryQuiescedProviders).await?)
+    }
+
     pub async fn get_actors(&self) -> Result<Vec<String>> {
         let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
         Ok(b.send(QueryActors {}).await?.results)
@@ -295,17 +1759,303 @@ impl Host {
         Ok(b.send(QueryProviders {}).await?.results)
     }
 
+    /// The links known to this host, whether set through it or learned from the lattice
+    pub async fn get_links(&self) -> Result<Vec<LinkDefinition>> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(b.send(QueryAllLinks {})
+            .await?
+            .links
+            .into_iter()
+            .map(LinkDefinition::from)
+            .collect())
+    }
+
+    /// The hosts in this host's lattice roster, including this one. Empty if the host was built
+    /// without a roster, in which case every host on the lattice is trusted
+    pub fn lattice_members(&self) -> Vec<RosterMember> {
+        crate::roster::members(&self.id())
+    }
+
+    /// Tries once to take the named lock, which is shared by every host in this host's lattice
+    /// namespace, for the given time to live. Returns `None` if another host (or another caller
+    /// in this host) holds the lock or takes it first. Hold on to the lock by calling `renew`
+    /// before the time to live passes
+    pub async fn acquire_lock(&self, name: &str, ttl: Duration) -> Result<Option<LatticeLock>> {
+        let ls = LockService::from_hostlocal_registry(&self.id.borrow());
+        ls.send(AcquireLock {
+            name: name.to_string(),
+            ttl,
+        })
+        .await?
+    }
+
+    /// Waits until this host is the leader for the given role in its lattice namespace, which
+    /// is at most one host at a time. Leadership is a lock, so the leader must renew it within
+    /// the time to live to remain leader, and other hosts keep campaigning until it lapses
+    pub async fn elect_leader(&self, role: &str, ttl: Duration) -> Result<LatticeLock> {
+        let name = format!("leader.{}", role);
+        loop {
+            if let Some(lock) = self.acquire_lock(&name, ttl).await? {
+                info!("Host elected leader for '{}'", role);
+                return Ok(lock);
+            }
+            actix_rt::time::delay_for(ttl / 2).await;
+        }
+    }
+
+    /// Changes the log level of an actor or capability provider (identified by its public key)
+    /// in this host while it runs. Passing `None` puts the target back on the host's level.
+    /// Raising a target above the host's level raises the maximum level of the `log` crate, but
+    /// the messages still have to get past the filter of whatever logger the host process uses
+    pub fn set_log_level(&self, target: &str, level: Option<log::LevelFilter>) -> Result<()> {
+        let id = self.id();
+        if id.is_empty() {
+            return Err("Host has not been started".into());
+        }
+        crate::log_levels::set_level(&id, target, level);
+        Ok(())
+    }
+
+    /// Changes how this host samples the invocations entering the lattice for tracing. Use the
+    /// control interface to change it for every host in the lattice
+    pub fn set_trace_sampling(&self, sampling: TraceSampling) -> Result<()> {
+        let id = self.id();
+        if id.is_empty() {
+            return Err("Host has not been started".into());
+        }
+        sampling.validate()?;
+        crate::sampling::set(&id, sampling);
+        Ok(())
+    }
+
+    /// Replaces this host's traffic shaping rules, such as to shift a larger share of calls to
+    /// the new provider of a migration
+    pub fn set_traffic_shaping(&self, rules: Vec<ShapingRule>) -> Result<()> {
+        let id = self.id();
+        if id.is_empty() {
+            return Err("Host has not been started".into());
+        }
+        for rule in rules.iter() {
+            rule.validate()?;
+        }
+        crate::shaping::set(&id, rules);
+        Ok(())
+    }
+
+    /// Retrieves call counts, error rates, and p99 latencies for each link (actor, contract ID,
+    /// and link name) over which actors in this host have invoked capability providers. Counts
+    /// cover the life of the host, while error rates and latencies cover the last five minutes
+    pub async fn get_link_statistics(&self) -> Result<Vec<LinkStatistics>> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(b.send(QueryLinkStatistics).await?)
+    }
+
+    /// Exports the same statistics as `get_link_statistics` in the Prometheus text exposition
+    /// format, labelled with the host, actor, contract ID, and link name, for an embedder to
+    /// serve to a metrics scraper
+    pub async fn export_link_metrics(&self) -> Result<String> {
+        let stats = self.get_link_statistics().await?;
+        Ok(crate::metrics::link_metrics_text(&self.id.borrow(), &stats))
+    }
+
+    /// Retrieves the backlog and dropped message counts of each lattice subscription in this
+    /// host, identifying the subscriptions whose actors or providers can't keep up with their
+    /// invocations
+    pub async fn get_subscription_statistics(&self) -> Result<Vec<SubscriptionStatistics>> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(b.send(QuerySubscriptionStatistics).await?)
+    }
+
+    /// Retrieves the actors and capability providers that each actor in this host has called
+    /// since it started, along with the capabilities it is granted but has not yet used
+    pub async fn get_dependency_graph(&self) -> Result<DependencyGraph> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(b.send(QueryDependencyGraph).await?)
+    }
+
+    /// Takes the topology of the lattice as this host sees it: the hosts of its roster, the actors
+    /// and providers running in this host along with their health, and the links between them
+    /// annotated with their traffic. The nodes and links are taken at a single moment. Render it
+    /// with `LatticeTopology::to_dot` or `LatticeTopology::to_json`
+    pub async fn lattice_topology(&self) -> Result<LatticeTopology> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        b.send(QueryTopology).await?
+    }
+
     pub async fn call_actor(&self, actor: &str, operation: &str, msg: &[u8]) -> Result<Vec<u8>> {
-        let inv = Invocation::new(
-            self.kp.borrow().as_ref().unwrap(),
-            WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
-            WasccEntity::Actor(actor.to_string()),
-            operation,
-            msg.to_vec(),
-        );
+        self.invoke_actor(actor, operation, msg, None, HashMap::new())
+            .await
+    }
+
+    /// Calls an actor with baggage: key-value pairs such as a tenant or request ID that are
+    /// carried by this call and by every call the actor (and any provider or actor it calls) makes
+    /// while handling it. Baggage is limited to `MAX_BAGGAGE_ITEMS` items and `MAX_BAGGAGE_BYTES`
+    pub async fn call_actor_with_baggage(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        baggage: HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        crate::baggage::validate(&baggage)?;
+        self.invoke_actor(actor, operation, msg, None, baggage)
+            .await
+    }
+
+    /// Calls an actor with an idempotency key. If this host has already handled a successful
+    /// call with the same key and payload within the host's idempotency window, the earlier
+    /// response is returned and the actor isn't executed again. This is best effort: retries
+    /// that are routed to a different host in the lattice are executed again, as are retries
+    /// after the actor was restarted
+    pub async fn call_actor_idempotent(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        idempotency_key: &str,
+    ) -> Result<Vec<u8>> {
+        self.invoke_actor(actor, operation, msg, Some(idempotency_key), HashMap::new())
+            .await
+    }
+
+    /// Calls every instance of an actor, on this host and every other host in the lattice, or
+    /// every instance of every actor carrying a given tag, and gathers their responses with the
+    /// given strategy. This suits broadcasts such as cache invalidations and configuration
+    /// pushes. Responses are gathered for at most the host's RPC timeout
+    pub async fn call_actor_fanout(
+        &self,
+        target: FanoutTarget,
+        operation: &str,
+        msg: &[u8],
+        strategy: FanoutStrategy,
+    ) -> Result<Vec<FanoutReply>> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        b.send(FanoutInvocation {
+            target,
+            operation: operation.to_string(),
+            msg: msg.to_vec(),
+            strategy,
+            timeout: self.rpc_timeout,
+        })
+        .await?
+    }
+
+    async fn invoke_actor(
+        &self,
+        actor: &str,
+        operation: &str,
+        msg: &[u8],
+        idempotency_key: Option<&str>,
+        baggage: HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let inv = {
+            let kp = self.kp.borrow();
+            let kp = kp.as_ref().ok_or("Host is not running")?;
+            let inv = Invocation::new(
+                kp,
+                WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                WasccEntity::Actor(actor.to_string()),
+                operation,
+                msg.to_vec(),
+            )
+            .with_baggage(baggage);
+            match idempotency_key {
+                Some(key) => inv.with_idempotency_key(key),
+                None => inv,
+            }
+        };
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        let ir: InvocationResponse = b.send(inv).await?;
+
+        if let Some(e) = ir.error {
+            Err(format!("Invocation failure: {}", e).into())
+        } else {
+            Ok(ir.msg)
+        }
+    }
+
+    /// Delivers an HTTP request to an actor as if it had come from an HTTP server provider, without
+    /// opening a socket. The target is either an actor's public key or the name of an HTTP server
+    /// link held by exactly one actor. This is mostly useful for exercising an actor's HTTP
+    /// handler in tests without binding ports
+    pub async fn http_request(&self, target: &str, request: HttpRequest) -> Result<HttpResponse> {
         let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        let links = b.send(QueryAllLinks {}).await?.links;
+        let target = resolve_target(&links, target)?;
+        let inv = {
+            let kp = self.kp.borrow();
+            let kp = kp.as_ref().ok_or("Host is not running")?;
+            Invocation::new(
+                kp,
+                WasccEntity::Capability {
+                    id: target.provider_id,
+                    contract_id: HTTP_SERVER_CONTRACT.to_string(),
+                    link_name: target.link_name,
+                },
+                WasccEntity::Actor(target.actor),
+                OP_HANDLE_REQUEST,
+                wascc_codec::serialize(&request)?,
+            )
+        };
         let ir: InvocationResponse = b.send(inv).await?;
+        if let Some(e) = ir.error {
+            Err(format!("Invocation failure: {}", e).into())
+        } else {
+            Ok(wascc_codec::deserialize(&ir.msg)?)
+        }
+    }
 
+    /// A typed client for the key-value provider running in this host with the given link
+    /// name. Calls are made by the host itself rather than on behalf of any actor, so providers
+    /// that keep a separate store for each actor they serve give the host a store of its own
+    pub fn keyvalue(&self, link_name: &str) -> KeyValueClient {
+        KeyValueClient::new(self, link_name)
+    }
+
+    /// A typed client for the message broker provider running in this host with the given link
+    /// name. As with `keyvalue`, calls are made by the host itself
+    pub fn messaging(&self, link_name: &str) -> MessagingClient {
+        MessagingClient::new(self, link_name)
+    }
+
+    /// Invokes the provider running in this host for the contract and link name, from the
+    /// host's system actor
+    pub(crate) async fn call_provider(
+        &self,
+        contract_id: &str,
+        link_name: &str,
+        operation: &str,
+        msg: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let b = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        let provider_id = b
+            .send(FindProvider {
+                contract_id: contract_id.to_string(),
+                link_name: link_name.to_string(),
+            })
+            .await?
+            .ok_or_else(|| {
+                format!(
+                    "No {} provider with link name '{}' is running in this host",
+                    contract_id, link_name
+                )
+            })?;
+        let inv = {
+            let kp = self.kp.borrow();
+            let kp = kp.as_ref().ok_or("Host is not running")?;
+            Invocation::new(
+                kp,
+                WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                WasccEntity::Capability {
+                    id: provider_id,
+                    contract_id: contract_id.to_string(),
+                    link_name: link_name.to_string(),
+                },
+                operation,
+                msg,
+            )
+        };
+        let ir: InvocationResponse = b.send(inv).await?;
         if let Some(e) = ir.error {
             Err(format!("Invocation failure: {}", e).into())
         } else {
@@ -332,6 +2082,157 @@ impl Host {
         .await?
     }
 
+    /// Waits until a link has been established: it is in this host's lattice cache, and has
+    /// been bound to its providers that run in this host. Use this after `set_link` rather than
+    /// sleeping before sending traffic that depends on the link
+    pub async fn await_link(
+        &self,
+        actor: &str,
+        contract_id: &str,
+        link_name: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        let wait = bus.send(AwaitLink {
+            key: LinkKey {
+                actor: actor.to_string(),
+                contract_id: contract_id.to_string(),
+                link_name: link_name.to_string(),
+            },
+        });
+        match actix_rt::time::timeout(timeout, wait).await {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(format!(
+                "Link {} -> {} ({}) was not established within {:?}",
+                actor, contract_id, link_name, timeout
+            )
+            .into()),
+        }
+    }
+
+    /// Removes a link from this host. If the link's provider is running in this host, it is
+    /// told to release the actor and any clients it holds in a [LinkPool](crate::LinkPool) for
+    /// the link are evicted. This does not remove the link from other hosts in the lattice
+    pub async fn remove_link(&self, actor: &str, contract_id: &str, link_name: &str) -> Result<()> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        bus.send(RemoveLink {
+            contract_id: contract_id.to_string(),
+            actor: actor.to_string(),
+            link_name: link_name.to_string(),
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Sets a link from a link definition that has already been validated by a
+    /// [LinkDefinitionBuilder](crate::LinkDefinitionBuilder)
+    pub async fn set_link_definition(&self, ld: LinkDefinition) -> Result<()> {
+        let ld: control_interface::LinkDefinition = ld.into();
+        self.set_link(
+            &ld.actor_id,
+            &ld.contract_id,
+            Some(ld.link_name),
+            ld.provider_id,
+            ld.values,
+        )
+        .await
+    }
+
+    /// Renews the lease on a link so that it expires the given time from now, advertising the
+    /// renewed link to the lattice. Only the lease changes, so providers keep their binding of the
+    /// link rather than being bound again. A link that wasn't leased becomes leased (see
+    /// [LinkDefinitionBuilder::lease](crate::LinkDefinitionBuilder::lease))
+    pub async fn renew_link(
+        &self,
+        actor: &str,
+        contract_id: &str,
+        link_name: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        let link = bus
+            .send(QueryAllLinks {})
+            .await?
+            .links
+            .into_iter()
+            .find(|l| {
+                l.actor_id == actor && l.contract_id == contract_id && l.link_name == link_name
+            })
+            .ok_or_else(|| format!("No link {} -> {} ({})", actor, contract_id, link_name))?;
+        let mut values = link.values;
+        values.insert(
+            crate::links::LINK_VALUE_LEASE_EXPIRES.to_string(),
+            crate::links::lease_expiry_after(crate::clock::now_secs(&self.id.borrow()), ttl)
+                .to_string(),
+        );
+        self.set_link(
+            actor,
+            contract_id,
+            Some(link_name.to_string()),
+            link.provider_id,
+            values,
+        )
+        .await
+    }
+
+    /// Starts copying the given percentage of the invocations made to an actor to a shadow
+    /// actor, replacing any mirror the actor already has. See `HostBuilder::with_mirror`
+    pub async fn set_mirror(&self, actor: &str, shadow: &str, percent: f64) -> Result<()> {
+        let mirror = Mirror::new(shadow, percent);
+        mirror.validate()?;
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        bus.send(SetMirror {
+            actor: actor.to_string(),
+            mirror: Some(mirror),
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Stops copying an actor's invocations to its shadow
+    pub async fn clear_mirror(&self, actor: &str) -> Result<()> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        bus.send(SetMirror {
+            actor: actor.to_string(),
+            mirror: None,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// How the responses of an actor's shadow have compared with the actor's own, if the actor
+    /// is mirrored
+    pub async fn mirror_stats(&self, actor: &str) -> Result<Option<MirrorStats>> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(bus
+            .send(QueryMirrorStats {
+                actor: actor.to_string(),
+            })
+            .await?)
+    }
+
+    /// The records of the actors and providers stopped through this host that are still within
+    /// the archive's retention, oldest first. See `HostBuilder::with_archive`
+    pub fn archived_entities(&self) -> Result<Vec<ArchivedEntity>> {
+        let archive = self
+            .archive
+            .as_ref()
+            .ok_or("Host has no archive configured")?;
+        archive.entities(crate::clock::now_millis(&self.id()))
+    }
+
+    /// The changes made to this host's lattice cache (link definitions and actor claims) at or
+    /// after the given time, oldest first, along with the host that issued each of them. The
+    /// host only remembers its most recent changes
+    pub async fn cache_history(
+        &self,
+        filter: CacheHistoryFilter,
+        since: SystemTime,
+    ) -> Result<Vec<CacheChange>> {
+        let bus = MessageBus::from_hostlocal_registry(&self.id.borrow());
+        Ok(bus.send(QueryCacheHistory { filter, since }).await?)
+    }
+
     pub async fn apply_manifest(&self, manifest: HostManifest) -> Result<()> {
         let host_id = self.kp.borrow().as_ref().unwrap().public_key();
         let hc = HostController::from_hostlocal_registry(&host_id);
@@ -346,12 +2247,17 @@ impl Host {
         }
 
         for msg in
-            crate::manifest::generate_actor_start_messages(&manifest, self.allow_latest).await
+            crate::manifest::generate_actor_start_messages(&host_id, &manifest, self.allow_latest)
+                .await
         {
             let _ = hc.send(msg).await?;
         }
-        for msg in
-            crate::manifest::generate_provider_start_messages(&manifest, self.allow_latest).await
+        for msg in crate::manifest::generate_provider_start_messages(
+            &host_id,
+            &manifest,
+            self.allow_latest,
+        )
+        .await
         {
             let _ = hc.send(msg).await?;
         }
@@ -362,7 +2268,257 @@ impl Host {
         Ok(())
     }
 
+    /// Produces the exact set of NATS subjects this host will publish and subscribe to, given
+    /// its configuration, so that its NATS user can be granted least-privilege permissions.
+    /// Before the host has started (and so has no ID), host-specific control subjects use a
+    /// wildcard in place of the host ID
+    pub fn required_permissions(&self) -> NatsPermissions {
+        let id = self.id();
+        crate::permissions::required_permissions(&PermissionScope {
+            namespace: &self.namespace,
+            host_id: if id.is_empty() { None } else { Some(id) },
+            zone: crate::host_controller::locality(&self.labels),
+            lattice_rpc: self.rpc_client.is_some() || self.lattice_creds.is_some(),
+            control: self.cplane_client.is_some() || self.lattice_creds.is_some(),
+            extensions: &self.extensions,
+        })
+    }
+
+    /// Exports the lattice state known to this host (actor claims, link definitions, and the
+    /// OCI references of its actors and providers) as a snapshot signed by the host's key
+    pub async fn export_lattice_state(&self) -> Result<LatticeSnapshot> {
+        let kp = match self.kp.borrow().as_ref() {
+            Some(kp) => KeyPair::from_seed(&kp.seed()?)?,
+            None => return Err("Host is not running".into()),
+        };
+        crate::lattice_state::export(&kp.public_key(), &self.namespace, &kp).await
+    }
+
+    /// Prepares this host to be replaced by a new version of itself: the host stops taking on
+    /// actors and providers, quiesces its providers, writes its upgrade journal (see
+    /// [with_upgrade_journal](HostBuilder::with_upgrade_journal)), and runs its upgrade hooks.
+    /// The host keeps serving the work it already has until its process is stopped
+    pub async fn prepare_upgrade(&self) -> Result<()> {
+        let kp = match self.kp.borrow().as_ref() {
+            Some(kp) => KeyPair::from_seed(&kp.seed()?)?,
+            None => return Err("Host is not running".into()),
+        };
+        crate::self_update::prepare(&kp, &self.namespace, &self.upgrade).await
+    }
+
+    /// Imports a lattice state snapshot into this host, starting any actors and providers it
+    /// references that aren't already running and advertising its claims and links to the
+    /// lattice. This can be used to seed a new lattice or to restore one after a disaster.
+    /// Conflicts with the existing state are handled according to the given resolution. Only
+    /// snapshots of this host's namespace, signed by this host or by an issuer added with
+    /// [with_snapshot_issuer](HostBuilder::with_snapshot_issuer), are imported
+    pub async fn import_lattice_state(
+        &self,
+        snapshot: &LatticeSnapshot,
+        resolution: ConflictResolution,
+    ) -> Result<()> {
+        if self.kp.borrow().is_none() {
+            return Err("Host is not running".into());
+        }
+        let id = self.id();
+        let mut trusted = self.snapshot_issuers.clone();
+        trusted.push(id.to_string());
+        let state = snapshot.verify(&trusted)?;
+        crate::lattice_state::import(
+            &id,
+            &self.namespace,
+            self.allow_latest,
+            &snapshot.issuer,
+            state,
+            resolution,
+        )
+        .await
+    }
+
+    /// Reports the version of this host, its wasm engine, the platform its native providers must
+    /// be built for, and which of its optional subsystems are enabled. The same report is given
+    /// to control clients that ask for the host's capabilities
+    pub fn capabilities(&self) -> HostCapabilities {
+        HostCapabilities {
+            host_id: self.id(),
+            version: crate::VERSION.to_string(),
+            engine: Host::wasm_engine(),
+            native_target: Host::native_target(),
+            features: self.features(),
+        }
+    }
+
+    // The names of the host's enabled optional subsystems (see `control_interface::features`)
+    fn features(&self) -> Vec<String> {
+        use ::control_interface::features::*;
+        let lattice = self.lattice_creds.is_some();
+        let enabled = [
+            (FEATURE_LATTICE_RPC, lattice || self.rpc_client.is_some()),
+            (
+                FEATURE_CONTROL_INTERFACE,
+                lattice || self.cplane_client.is_some(),
+            ),
+            (FEATURE_API_TOKENS, self.api_tokens.is_some()),
+            (FEATURE_OCI, true),
+            (FEATURE_METRICS, true),
+            (
+                FEATURE_HTTP_ROUTER,
+                self.http_router.is_some() || self.https_router.is_some(),
+            ),
+            (FEATURE_GRPC_INGRESS, self.grpc_ingress.is_some()),
+            (FEATURE_MEMORY_KEYVALUE, self.memory_keyvalue),
+            (FEATURE_PAYLOAD_OFFLOAD, self.payload_offload.is_some()),
+            (FEATURE_LIVE_UPDATES, self.allow_live_updates),
+            (FEATURE_UPGRADE, self.upgrade.journal.is_some()),
+            (FEATURE_WATCHDOG, self.watchdog.is_some()),
+        ];
+        enabled
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(f, _)| f.to_string())
+            .collect()
+    }
+
     pub(crate) fn native_target() -> String {
         format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
     }
+
+    pub(crate) fn wasm_engine() -> String {
+        #[cfg(feature = "wasmtime")]
+        let engine = "wasmtime";
+        #[cfg(feature = "wasm3")]
+        let engine = "wasm3";
+
+        engine.to_string()
+    }
+}
+
+impl Drop for Host {
+    // Best-effort cleanup for hosts that were dropped without calling `stop`. This can't
+    // wait for the services to wind down, but it does make sure nothing keeps running on
+    // behalf of a host that no longer exists
+    fn drop(&mut self) {
+        let id = self.id();
+        if id.is_empty() || self.kp.borrow().is_none() {
+            return;
+        }
+        if let Some((arbiter, watchdog)) = self.watchdog_thread.borrow_mut().take() {
+            watchdog.do_send(Shutdown);
+            arbiter.stop();
+        }
+        shutdown_services(&id);
+        clear_host_globals(&id);
+        if let Some(ref shared) = self.shared_connection {
+            shared.release(&id);
+        }
+    }
+}
+
+// Undoes what `Host::start` registered on behalf of a host that failed to start: the host-local
+// services, the process-wide registrations keyed by the host's ID, and its place on a shared
+// connection. Disarmed once the host is running, after which `Host::stop` takes over
+struct StartGuard<'a> {
+    host: &'a Host,
+    host_id: String,
+    armed: bool,
+}
+
+impl Drop for StartGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        shutdown_services(&self.host_id);
+        clear_host_globals(&self.host_id);
+        if let Some(ref shared) = self.host.shared_connection {
+            shared.release(&self.host_id);
+        }
+        *self.host.id.borrow_mut() = "".to_string();
+    }
+}
+
+// Tells the host-local services that were started for a host to stop, without waiting for them
+fn shutdown_services(id: &str) {
+    if let Some(hc) = HostController::existing_from_hostlocal_registry(id) {
+        hc.do_send(Shutdown);
+    }
+    if let Some(mb) = MessageBus::existing_from_hostlocal_registry(id) {
+        mb.do_send(Shutdown);
+    }
+    if let Some(cp) = ControlInterface::existing_from_hostlocal_registry(id) {
+        cp.do_send(Shutdown);
+    }
+    if let Some(ls) = LockService::existing_from_hostlocal_registry(id) {
+        ls.do_send(Shutdown);
+    }
+    if let Some(roster) = RosterService::existing_from_hostlocal_registry(id) {
+        roster.do_send(Shutdown);
+    }
+}
+
+// Removes the process-wide registrations kept for a host, including its host-local services
+fn clear_host_globals(id: &str) {
+    crate::hlreg::remove_host(id);
+    crate::labels::unregister(id);
+    crate::clock::unregister(id);
+    crate::inboxes::unregister(id);
+    crate::bundle::deactivate(id);
+    crate::sampling::clear(id);
+    crate::shaping::clear(id);
+    crate::watchdog::clear(id);
+    crate::roster::clear(id);
+    crate::log_levels::clear(id);
+    crate::symbols::clear(id);
+    crate::system_actor::unregister(id);
+    crate::automation::clear(id);
+    crate::pool::clear(id);
+    crate::limits::clear(id);
+}
+
+#[cfg(test)]
+mod test {
+    use super::HostBuilder;
+    use crate::limits::HostLimits;
+    use std::time::Duration;
+
+    #[test]
+    fn profiles_set_limits_and_cache_sizes() {
+        let edge = HostBuilder::edge();
+        assert_eq!(
+            HostLimits {
+                max_actors: Some(16),
+                max_providers: Some(8),
+                max_guest_memory: Some(256 * 1024 * 1024),
+                max_response_size: Some(256 * 1024),
+            },
+            edge.limits
+        );
+        assert_eq!(Duration::from_secs(10), edge.rpc_timeout);
+        assert_eq!(Duration::from_secs(120), edge.hb_interval);
+        assert_eq!(Duration::from_secs(60), edge.idempotency.ttl);
+        assert_eq!(128, edge.idempotency.capacity);
+
+        let server = HostBuilder::server();
+        assert_eq!(
+            HostLimits {
+                max_actors: Some(1024),
+                max_providers: Some(128),
+                max_guest_memory: Some(16 * 1024 * 1024 * 1024),
+                max_response_size: Some(crate::DEFAULT_LATTICE_MAX_PAYLOAD),
+            },
+            server.limits
+        );
+        assert_eq!(16 * 1024, server.idempotency.capacity);
+        assert_eq!(Duration::from_secs(15 * 60), server.idempotency.ttl);
+        assert_eq!(
+            Some(Duration::from_secs(60 * 60)),
+            server.unused_capability_grace
+        );
+
+        // The builder's other methods still override a profile
+        let edge = HostBuilder::edge().with_max_actors(32);
+        assert_eq!(Some(32), edge.limits.max_actors);
+        assert_eq!(Some(8), edge.limits.max_providers);
+        assert_eq!(HostLimits::default(), HostBuilder::new().limits);
+    }
 }
@@ -1,16 +1,28 @@
+use super::inventory::InventoryLog;
 use super::*;
-use crate::actors::ActorHost;
+use crate::actors::{ActorAddr, ActorHost, PooledActorHost, TaskActorHost};
 use crate::auth::Authorizer;
 use crate::capability::extras::ExtrasCapabilityProvider;
-use crate::capability::native_host::NativeCapabilityHost;
+use crate::capability::grpc::GrpcIngressProvider;
+use crate::capability::keyvalue::MemoryKeyValueProvider;
+use crate::capability::native_host::{NativeCapabilityHost, StopProviderHost};
+use crate::capability::router::HttpRouterProvider;
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
 use crate::dispatch::Invocation;
-use crate::hlreg::HostLocalSystemService;
+use crate::hlreg::{HostLocalSystemService, Shutdown};
+use crate::hooks::{
+    bytes_digest, check_actor_admission, check_provider_admission, ActorAdmission,
+    ProviderAdmission,
+};
+use crate::messagebus::upgrade::{RedirectSubscriber, WarmProvider};
 use crate::messagebus::{CanInvoke, GetClaims, MessageBus, Unsubscribe, OP_BIND_ACTOR};
 use crate::middleware::Middleware;
-use crate::{NativeCapability, Result, WasccEntity, SYSTEM_ACTOR};
-use std::collections::HashMap;
+use crate::shutdown::StoppedProvider;
+use crate::threading::{Placement, ThreadPools};
+use crate::{ControlEvent, NativeCapability, Result, WasccEntity, SYSTEM_ACTOR};
+use std::collections::{HashMap, HashSet};
 
-use std::time::Instant;
+use std::time::Duration;
 
 use wascap::prelude::KeyPair;
 
@@ -33,12 +45,34 @@ pub struct HostController {
     host_labels: HashMap<String, String>,
     mw_chain: Vec<Box<dyn Middleware>>,
     kp: Option<KeyPair>,
-    actors: HashMap<String, Addr<ActorHost>>,
+    actors: HashMap<String, ActorAddr>,
     providers: HashMap<ProviderKey, Addr<NativeCapabilityHost>>,
     authorizer: Option<Box<dyn Authorizer>>,
+    prestart_hooks: Vec<Box<dyn PreStartHook>>,
     image_refs: HashMap<String, String>,
-    started: Instant,
+    // A monotonic reading of the host's clock taken when it started
+    started: Duration,
     allow_live_updates: bool,
+    snapshots: Option<SnapshotConfig>,
+    wasm_features: WasmFeatures,
+    billing_sinks: Vec<Box<dyn BillingSink>>,
+    idempotency: IdempotencyConfig,
+    inventory_log: InventoryLog,
+    strict: bool,
+    denied_providers: Vec<String>,
+    limits: HostLimits,
+    // The linear memory of each running actor, by public key. An actor's memory is reserved here
+    // as soon as it is admitted, before it has finished starting
+    actor_memory: HashMap<String, u64>,
+    // The actors that have been admitted but haven't finished starting
+    starting: HashSet<String>,
+    // The number of providers the host started for itself
+    builtin_providers: usize,
+    // Set once the host is preparing to be replaced by an upgrade
+    draining: bool,
+    threads: ThreadPools,
+    // The thread each running actor was placed on, by public key
+    placements: HashMap<String, Placement>,
 }
 
 impl Default for HostController {
@@ -50,9 +84,24 @@ impl Default for HostController {
             actors: HashMap::new(),
             providers: HashMap::new(),
             authorizer: None,
+            prestart_hooks: vec![],
             image_refs: HashMap::new(),
-            started: Instant::now(),
+            started: Duration::default(),
             allow_live_updates: false,
+            snapshots: None,
+            wasm_features: WasmFeatures::default(),
+            billing_sinks: vec![],
+            idempotency: IdempotencyConfig::default(),
+            inventory_log: InventoryLog::default(),
+            strict: false,
+            denied_providers: vec![],
+            limits: HostLimits::default(),
+            actor_memory: HashMap::new(),
+            starting: HashSet::new(),
+            builtin_providers: 0,
+            draining: false,
+            threads: ThreadPools::default(),
+            placements: HashMap::new(),
         }
     }
 }
@@ -82,6 +131,9 @@ impl Handler<AuctionActor> for HostController {
         {
             return false; // don't respond to auctions where the actor in question is running already
         }
+        if self.draining || !self.limits.has_room_for_actor(&self.actor_memory) {
+            return false;
+        }
 
         satisfies_constraints(&self.host_labels, &msg.constraints)
     }
@@ -102,6 +154,14 @@ impl Handler<AuctionProvider> for HostController {
         }) {
             return false;
         }
+        if self.draining
+            || self
+                .limits
+                .admit_provider(self.started_providers())
+                .is_err()
+        {
+            return false;
+        }
 
         satisfies_constraints(&self.host_labels, &msg.constraints)
     }
@@ -136,18 +196,30 @@ impl Handler<QueryActorRunning> for HostController {
 // This returns the messaging address of the actor host that corresponds to a -public key-
 // this handler does NOT examine image refs
 impl Handler<GetRunningActor> for HostController {
-    type Result = Option<Addr<ActorHost>>;
+    type Result = Option<ActorAddr>;
 
     fn handle(&mut self, msg: GetRunningActor, _ctx: &mut Context<Self>) -> Self::Result {
         self.actors.get(&msg.actor_id).cloned()
     }
 }
 
+impl Handler<Drain> for HostController {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Drain, _ctx: &mut Context<Self>) {
+        self.draining = true;
+    }
+}
+
 impl Handler<QueryUptime> for HostController {
     type Result = u64;
 
     fn handle(&mut self, _msg: QueryUptime, _ctx: &mut Context<Self>) -> Self::Result {
-        self.started.elapsed().as_secs()
+        let host_id = self.kp.as_ref().unwrap().public_key();
+        crate::clock::monotonic(&host_id)
+            .checked_sub(self.started)
+            .unwrap_or_default()
+            .as_secs()
     }
 }
 
@@ -223,6 +295,66 @@ impl Handler<CheckLink> for HostController {
     }
 }
 
+impl Handler<Shutdown> for HostController {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) -> Self::Result {
+        info!("Host controller shutting down");
+        // Actor and provider hosts stop once the last reference to them is gone
+        self.actors.clear();
+        self.providers.clear();
+        self.image_refs.clear();
+        for (_, placement) in self.placements.drain() {
+            self.threads.release(placement);
+        }
+        self.threads.stop();
+        ctx.stop();
+    }
+}
+
+impl Handler<StopAll> for HostController {
+    type Result = ResponseActFuture<Self, StoppedEntities>;
+
+    fn handle(&mut self, msg: StopAll, _ctx: &mut Context<Self>) -> Self::Result {
+        let mut actors: Vec<String> = self.actors.drain().map(|(pk, _)| pk).collect();
+        actors.sort();
+        self.image_refs.clear();
+        let providers: Vec<_> = self
+            .providers
+            .drain()
+            .map(|(key, addr)| {
+                let provider = StoppedProvider {
+                    provider_id: key.id,
+                    link_name: key.link_name,
+                };
+                let stopped = addr.send(StopProviderHost).timeout(msg.deadline);
+                async move { (provider, stopped.await.is_ok()) }
+            })
+            .collect();
+        Box::pin(
+            async move {
+                let mut stopped = StoppedEntities {
+                    actors,
+                    providers: vec![],
+                    timed_out: vec![],
+                };
+                for (provider, cleaned_up) in futures::future::join_all(providers).await {
+                    if !cleaned_up {
+                        warn!(
+                            "Provider {} ({}) did not clean up before the host stopped",
+                            provider.provider_id, provider.link_name
+                        );
+                        stopped.timed_out.push(provider.clone());
+                    }
+                    stopped.providers.push(provider);
+                }
+                stopped
+            }
+            .into_actor(self),
+        )
+    }
+}
+
 impl Handler<SetLabels> for HostController {
     type Result = ();
 
@@ -244,6 +376,11 @@ impl Handler<StopActor> for HostController {
 
     fn handle(&mut self, msg: StopActor, _ctx: &mut Context<Self>) -> Self::Result {
         trace!("Stopping actor {} per request.", msg.actor_ref);
+        let host_id = self.kp.as_ref().unwrap().public_key();
+        if crate::system_actor::is_reserved(&host_id, &msg.actor_ref) {
+            warn!("Ignoring request to stop the system actor, which stops with the host");
+            return Box::pin(async {}.into_actor(self));
+        }
         // We should be able to make the actor stop itself by removing the last reference to it
         let pk = if let Some(pk) = self.image_refs.remove(&msg.actor_ref) {
             let _ = self.actors.remove(&pk);
@@ -252,9 +389,11 @@ impl Handler<StopActor> for HostController {
             let _ = self.actors.remove(&msg.actor_ref);
             msg.actor_ref.to_string()
         };
+        self.actor_memory.remove(&pk);
+        let placement = self.placements.remove(&pk);
 
         // Ensure that this actor's interest is removed from the bus
-        let b = MessageBus::from_hostlocal_registry(&self.kp.as_ref().unwrap().public_key());
+        let b = MessageBus::from_hostlocal_registry(&host_id);
         Box::pin(
             async move {
                 let _ = b
@@ -263,7 +402,13 @@ impl Handler<StopActor> for HostController {
                     })
                     .await;
             }
-            .into_actor(self),
+            .into_actor(self)
+            // The actor's thread is given back once nothing is left to invoke it
+            .map(move |_, act, _ctx| {
+                if let Some(placement) = placement {
+                    act.threads.release(placement);
+                }
+            }),
         )
     }
 }
@@ -310,24 +455,118 @@ impl Handler<Initialize> for HostController {
     fn handle(&mut self, msg: Initialize, _ctx: &mut Context<Self>) {
         self.host_labels = msg.labels;
         self.authorizer = Some(msg.auth);
+        self.prestart_hooks = msg.prestart_hooks;
+        self.snapshots = msg.snapshots;
+        self.wasm_features = msg.wasm_features;
+        self.billing_sinks = msg.billing_sinks;
+        self.idempotency = msg.idempotency;
+        self.strict = msg.strict;
+        self.denied_providers = msg.denied_providers;
+        self.limits = msg.limits;
+        self.threads = ThreadPools::new(msg.threads);
         let host_id = msg.kp.public_key();
+        self.started = crate::clock::monotonic(&host_id);
 
         let claims = crate::capability::extras::get_claims();
         let pk = claims.subject.to_string();
         // Start wascc:extras
         let extras = SyncArbiter::start(1, move || NativeCapabilityHost::new());
         let claims = crate::capability::extras::get_claims();
-        let ex = ExtrasCapabilityProvider::default();
+        let ex = ExtrasCapabilityProvider::new(msg.determinism);
         let cap = NativeCapability::from_instance(ex, Some("default".to_string()), claims).unwrap();
         let init = crate::capability::native_host::Initialize {
             cap,
             mw_chain: vec![],
             seed: msg.kp.seed().unwrap(),
             image_ref: None,
+            standby: false,
         };
         extras.do_send(init);
         let key = ProviderKey::new(&pk, "default");
         self.providers.insert(key, extras); // can't let this provider go out of scope, or the actix actor will stop
+
+        // Start wascc:discovery, if enabled
+        if let Some(services) = msg.services {
+            let discovery = SyncArbiter::start(1, move || NativeCapabilityHost::new());
+            let claims = crate::capability::discovery::get_claims();
+            let pk = claims.subject.to_string();
+            let dp = crate::capability::discovery::provider(services);
+            let cap =
+                NativeCapability::from_instance(dp, Some("default".to_string()), claims).unwrap();
+            let init = crate::capability::native_host::Initialize {
+                cap,
+                mw_chain: vec![],
+                seed: msg.kp.seed().unwrap(),
+                image_ref: None,
+                standby: false,
+            };
+            discovery.do_send(init);
+            self.providers
+                .insert(ProviderKey::new(&pk, "default"), discovery);
+        }
+
+        // Start the HTTP router, if enabled on either port
+        if msg.http_router.is_some() || msg.https_router.is_some() {
+            let router = SyncArbiter::start(1, move || NativeCapabilityHost::new());
+            let claims = crate::capability::router::get_claims();
+            let pk = claims.subject.to_string();
+            let hr = HttpRouterProvider::new(msg.http_router, msg.https_router);
+            let cap =
+                NativeCapability::from_instance(hr, Some("default".to_string()), claims).unwrap();
+            let init = crate::capability::native_host::Initialize {
+                cap,
+                mw_chain: vec![],
+                seed: msg.kp.seed().unwrap(),
+                image_ref: None,
+                standby: false,
+            };
+            router.do_send(init);
+            self.providers
+                .insert(ProviderKey::new(&pk, "default"), router);
+        }
+
+        // Start gRPC ingress, if enabled
+        if let Some(port) = msg.grpc_ingress {
+            let grpc = SyncArbiter::start(1, move || NativeCapabilityHost::new());
+            let claims = crate::capability::grpc::get_claims();
+            let pk = claims.subject.to_string();
+            let gp = GrpcIngressProvider::new(port);
+            let cap =
+                NativeCapability::from_instance(gp, Some("default".to_string()), claims).unwrap();
+            let init = crate::capability::native_host::Initialize {
+                cap,
+                mw_chain: vec![],
+                seed: msg.kp.seed().unwrap(),
+                image_ref: None,
+                standby: false,
+            };
+            grpc.do_send(init);
+            self.providers
+                .insert(ProviderKey::new(&pk, "default"), grpc);
+        }
+
+        // Start the in-memory wascc:keyvalue provider, if enabled
+        if msg.memory_keyvalue {
+            let kv = SyncArbiter::start(1, move || NativeCapabilityHost::new());
+            let claims = crate::capability::keyvalue::get_claims();
+            let pk = claims.subject.to_string();
+            let cap = NativeCapability::from_instance(
+                MemoryKeyValueProvider::new(&msg.kp.public_key()),
+                Some("default".to_string()),
+                claims,
+            )
+            .unwrap();
+            let init = crate::capability::native_host::Initialize {
+                cap,
+                mw_chain: vec![],
+                seed: msg.kp.seed().unwrap(),
+                image_ref: None,
+                standby: false,
+            };
+            kv.do_send(init);
+            self.providers.insert(ProviderKey::new(&pk, "default"), kv);
+        }
+        self.builtin_providers = self.providers.len();
         self.kp = Some(msg.kp);
         self.allow_live_updates = msg.allow_live_updates;
         info!(
@@ -347,7 +586,7 @@ impl Handler<StartActor> for HostController {
         let claims = msg.actor.claims();
         info!("Starting actor {}", sub);
 
-        if self.actors.contains_key(&sub) {
+        if self.actors.contains_key(&sub) || self.starting.contains(&sub) {
             error!("Aborting attempt to start already running actor {}", sub);
             return Box::pin(
                 async move { Err(format!("Cannot start already running actor {}", sub).into()) }
@@ -360,6 +599,22 @@ impl Handler<StartActor> for HostController {
                 async move { Err("Permission denied starting actor.".into()) }.into_actor(self),
             );
         }
+
+        let memory = match self.admit_actor(&msg.actor, &msg.image_ref, false) {
+            Ok(memory) => memory,
+            Err(reason) => {
+                return Box::pin(
+                    async move { Err(format!("Actor start rejected: {}", reason).into()) }
+                        .into_actor(self),
+                );
+            }
+        };
+        // Hold the actor's place until it has started, so that starts in flight at the same time
+        // can't together exceed the host's limits
+        self.starting.insert(sub.to_string());
+        if let Some(memory) = memory {
+            self.actor_memory.insert(sub.to_string(), memory);
+        }
         let init = crate::actors::Initialize {
             actor_bytes: msg.actor.bytes.clone(),
             mw_chain: self.mw_chain.clone(),
@@ -367,29 +622,128 @@ impl Handler<StartActor> for HostController {
             image_ref: msg.image_ref.clone(),
             host_id: self.kp.as_ref().unwrap().public_key(),
             can_update: self.allow_live_updates,
+            snapshots: self.snapshots.clone(),
+            wasm_features: self.wasm_features,
+            billing_sinks: self.billing_sinks.clone(),
+            idempotency: self.idempotency.clone(),
         };
 
-        let new_actor = SyncArbiter::start(1, move || ActorHost::default());
+        let placement = self.threads.place(&claims);
+        let new_actor = match placement {
+            Placement::Dedicated => ActorAddr::Dedicated(SyncArbiter::start(1, ActorHost::default)),
+            Placement::Pooled { ref arbiter, .. } => {
+                ActorAddr::Pooled(PooledActorHost::start_in_arbiter(arbiter, |_| {
+                    PooledActorHost::default()
+                }))
+            }
+            Placement::Task => ActorAddr::Task(TaskActorHost::default().start()),
+        };
         let na = new_actor.clone();
 
         Box::pin(
-            async move { new_actor.send(init).await }
+            async move { new_actor.initialize(init).await }
                 .into_actor(self)
-                .map(move |res, act, _ctx| match res {
-                    Ok(r) => match r {
-                        Ok(_) => {
+                .map(move |res, act, _ctx| {
+                    act.starting.remove(&sub);
+                    let res: Result<()> = match res {
+                        Ok(Ok(_)) => {
                             if let Some(imageref) = msg.image_ref {
                                 act.image_refs.insert(imageref, msg.actor.public_key());
                             }
                             act.actors.insert(msg.actor.public_key(), na);
-                            Ok(())
+                            act.placements.insert(msg.actor.public_key(), placement);
+                            return Ok(());
+                        }
+                        Ok(Err(e)) => Err(format!("Failed to initialize actor: {}", e).into()),
+                        Err(_e) => {
+                            error!("Failed to initialize actor - mailbox error");
+                            Err("Failed to initialize actor - mailbox error".into())
+                        }
+                    };
+                    act.threads.release(placement);
+                    act.actor_memory.remove(&sub);
+                    res
+                }),
+        )
+    }
+}
+
+impl Handler<UpdateActor> for HostController {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: UpdateActor, _ctx: &mut Context<Self>) -> Self::Result {
+        let sub = msg.actor_id;
+        let actor = match self.actors.get(&sub) {
+            Some(actor) => actor.clone(),
+            None => {
+                let err = format!("Actor {} is not running in this host", sub);
+                return Box::pin(async move { Err(err.into()) }.into_actor(self));
+            }
+        };
+        let image_ref = Some(msg.image_ref.to_string());
+        let new_sub = msg.actor.public_key();
+        if new_sub != sub {
+            let reason = format!("New version has a different public key, {}", new_sub);
+            error!("Rejected update of actor {}: {}", sub, reason);
+            self.publish_event(ControlEvent::ActorUpdateRejected {
+                actor: sub,
+                image_ref,
+                reason: reason.to_string(),
+            });
+            return Box::pin(
+                async move { Err(format!("Actor update rejected: {}", reason).into()) }
+                    .into_actor(self),
+            );
+        }
+        if !self
+            .authorizer
+            .as_ref()
+            .unwrap()
+            .can_load(&msg.actor.claims())
+        {
+            return Box::pin(
+                async move { Err("Permission denied updating actor.".into()) }.into_actor(self),
+            );
+        }
+        let memory = match self.admit_actor(&msg.actor, &image_ref, true) {
+            Ok(memory) => memory,
+            Err(reason) => {
+                return Box::pin(
+                    async move { Err(format!("Actor update rejected: {}", reason).into()) }
+                        .into_actor(self),
+                );
+            }
+        };
+        // The new version's memory is held from now on, and the old version's given back if the
+        // update fails
+        let previous = match memory {
+            Some(memory) => self.actor_memory.insert(sub.to_string(), memory),
+            None => None,
+        };
+        let update = crate::actors::LiveUpdate {
+            actor_bytes: msg.actor.bytes,
+            image_ref: msg.image_ref,
+        };
+        Box::pin(
+            async move { actor.update(update).await? }
+                .into_actor(self)
+                .map(move |res, act, _ctx| {
+                    if let Err(ref e) = res {
+                        // The running version refused the new one, for instance because its
+                        // revision isn't newer
+                        act.publish_event(ControlEvent::ActorUpdateRejected {
+                            actor: sub.to_string(),
+                            image_ref,
+                            reason: e.to_string(),
+                        });
+                        if memory.is_some() {
+                            match previous {
+                                Some(previous) => act.actor_memory.insert(sub, previous),
+                                None => act.actor_memory.remove(&sub),
+                            };
                         }
-                        Err(e) => Err(format!("Failed to initialize actor: {}", e).into()),
-                    },
-                    Err(_e) => {
-                        error!("Failed to initialize actor - mailbox error");
-                        Err("Failed to initialize actor - mailbox error".into())
                     }
+                    res
                 }),
         )
     }
@@ -399,6 +753,22 @@ impl Handler<QueryHostInventory> for HostController {
     type Result = HostInventory;
 
     fn handle(&mut self, _msg: QueryHostInventory, _ctx: &mut Context<Self>) -> Self::Result {
+        self.inventory()
+    }
+}
+
+impl Handler<QueryInventoryChanges> for HostController {
+    type Result = InventoryChanges;
+
+    fn handle(&mut self, msg: QueryInventoryChanges, _ctx: &mut Context<Self>) -> Self::Result {
+        let current = self.inventory();
+        self.inventory_log
+            .changes_since(current, msg.since_revision)
+    }
+}
+
+impl HostController {
+    fn inventory(&self) -> HostInventory {
         HostInventory {
             actors: self
                 .actors
@@ -416,11 +786,176 @@ impl Handler<QueryHostInventory> for HostController {
                     image_ref: find_imageref(&k.id, &self.image_refs),
                     id: k.id.to_string(),
                     link_name: k.link_name.to_string(),
+                    instance_id: provider_instance_id(
+                        &self.kp.as_ref().unwrap().public_key(),
+                        &k.id,
+                        &k.link_name,
+                    ),
                 })
                 .collect(),
             labels: self.host_labels.clone(),
         }
     }
+
+    // Runs strict mode's checks, the host's limits and the pre-start hooks against an actor that
+    // is starting (or, when `updating`, replacing a running version of itself), reporting a
+    // rejection. Returns the guest memory to hold for the actor, if it is held to the host's limits
+    fn admit_actor(
+        &self,
+        actor: &WasccActor,
+        image_ref: &Option<String>,
+        updating: bool,
+    ) -> std::result::Result<Option<u64>, String> {
+        let claims = actor.claims();
+        let sub = claims.subject.to_string();
+        let admission = ActorAdmission {
+            claims: &claims,
+            digest: bytes_digest(&actor.bytes),
+            size: actor.bytes.len(),
+            image_ref: image_ref.as_deref(),
+        };
+        let verified = if self.strict {
+            crate::strict::verify_actor(actor)
+        } else {
+            Ok(())
+        };
+        let host_id = self.kp.as_ref().unwrap().public_key();
+        // The system actor starts with the host, and isn't held to its limits
+        let memory = if crate::system_actor::is_reserved(&host_id, &sub) {
+            None
+        } else {
+            Some(crate::limits::guest_memory(&actor.bytes))
+        };
+        // A running version of the actor gives up its memory to the one replacing it
+        let mut actor_memory = self.actor_memory.clone();
+        actor_memory.remove(&sub);
+        let denied = crate::denylist::denied_by(&self.denied_providers, provider);
+        if let Some(entry) = denied {
+            self.publish_event(ControlEvent::ProviderDenied {
+                contract_id: contract_id.to_string(),
+                link_name: provider.link_name.to_string(),
+                provider_id: sub.to_string(),
+                image_ref: image_ref.clone(),
+                entry: entry.to_string(),
+            });
+        }
+        let within_limits = self.accepting_work().and_then(|_| match memory {
+            Some(memory) => self.limits.admit_actor(&actor_memory, memory),
+            None => Ok(()),
+        });
+        if let Err(reason) = within_limits
+            .and(verified)
+            .and_then(|_| check_actor_admission(&self.prestart_hooks, &admission))
+        {
+            if updating {
+                error!("Rejected update of actor {}: {}", sub, reason);
+                self.publish_event(ControlEvent::ActorUpdateRejected {
+                    actor: sub,
+                    image_ref: image_ref.clone(),
+                    reason: reason.to_string(),
+                });
+            } else {
+                error!("Rejected actor {}: {}", sub, reason);
+                self.publish_event(ControlEvent::ActorStartRejected {
+                    actor: sub,
+                    image_ref: image_ref.clone(),
+                    reason: reason.to_string(),
+                });
+            }
+            return Err(reason);
+        }
+        Ok(memory)
+    }
+
+    // Runs strict mode's checks, the provider denylist and the pre-start hooks against a
+    // provider (and, for a provider that would add to those running, the host's limits),
+    // reporting a rejection
+    fn admit_provider(
+        &self,
+        provider: &NativeCapability,
+        image_ref: &Option<String>,
+        adds_provider: bool,
+    ) -> std::result::Result<(), String> {
+        let sub = provider.claims.subject.to_string();
+        let contract_id = provider
+            .claims
+            .metadata
+            .as_ref()
+            .map(|md| md.capid.as_str())
+            .unwrap_or_default();
+        let admission = ProviderAdmission {
+            claims: &provider.claims,
+            link_name: &provider.link_name,
+            digest: match provider.native_file {
+                Some(ref extracted) => Some(extracted.digest.to_string()),
+                None => provider.native_bytes.as_ref().map(|b| bytes_digest(b)),
+            },
+            image_ref: image_ref.as_deref(),
+        };
+        let verified = if self.strict {
+            crate::strict::verify_provider(provider)
+        } else {
+            Ok(())
+        };
+        let denied = crate::denylist::denied_by(&self.denied_providers, provider);
+        if let Some(entry) = denied {
+            self.publish_event(ControlEvent::ProviderDenied {
+                contract_id: contract_id.to_string(),
+                link_name: provider.link_name.to_string(),
+                provider_id: sub.to_string(),
+                image_ref: image_ref.clone(),
+                entry: entry.to_string(),
+            });
+        }
+        let within_limits = self.accepting_work().and_then(|_| {
+            if adds_provider {
+                self.limits.admit_provider(self.started_providers())
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(reason) = within_limits
+            .and(verified)
+            .and_then(|_| match denied {
+                Some(entry) => Err(format!(
+                    "Provider {} is denied by this host ({})",
+                    sub, entry
+                )),
+                None => Ok(()),
+            })
+            .and_then(|_| check_provider_admission(&self.prestart_hooks, &admission))
+        {
+            error!("Rejected provider {}: {}", sub, reason);
+            self.publish_event(ControlEvent::ProviderStartRejected {
+                contract_id: contract_id.to_string(),
+                link_name: provider.link_name.to_string(),
+                provider_id: sub,
+                image_ref: image_ref.clone(),
+                reason: reason.to_string(),
+            });
+            return Err(reason);
+        }
+        Ok(())
+    }
+
+    // The number of running providers that count toward the host's limit
+    // Refuses new work while the host drains for an upgrade
+    fn accepting_work(&self) -> std::result::Result<(), String> {
+        if self.draining {
+            Err("Host is draining for an upgrade".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn started_providers(&self) -> usize {
+        self.providers.len().saturating_sub(self.builtin_providers)
+    }
+
+    fn publish_event(&self, event: ControlEvent) {
+        let cp = ControlInterface::from_hostlocal_registry(&self.kp.as_ref().unwrap().public_key());
+        cp.do_send(PublishEvent { event });
+    }
 }
 
 fn find_imageref(target: &str, image_refs: &HashMap<String, String>) -> Option<String> {
@@ -444,6 +979,13 @@ impl Handler<StartProvider> for HostController {
             );
         }
 
+        if let Err(reason) = self.admit_provider(&msg.provider, &msg.image_ref, true) {
+            return Box::pin(
+                async move { Err(format!("Provider start rejected: {}", reason).into()) }
+                    .into_actor(self),
+            );
+        }
+
         info!("Starting provider {}", msg.provider.claims.subject);
 
         let seed = self.kp.as_ref().unwrap().seed().unwrap();
@@ -485,6 +1027,85 @@ impl Handler<StartProvider> for HostController {
     }
 }
 
+impl Handler<UpgradeProvider> for HostController {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: UpgradeProvider, _ctx: &mut Context<Self>) -> Self::Result {
+        let sub = msg.provider.claims.subject.to_string();
+        let key = ProviderKey::new(&sub, &msg.provider.link_name);
+        if !self.providers.contains_key(&key) {
+            let err = format!(
+                "Provider {} with link name {} is not running in this host",
+                sub, msg.provider.link_name
+            );
+            return Box::pin(async move { Err(err.into()) }.into_actor(self));
+        }
+        if let Err(reason) = self.admit_provider(&msg.provider, &msg.image_ref, false) {
+            return Box::pin(
+                async move { Err(format!("Provider update rejected: {}", reason).into()) }
+                    .into_actor(self),
+            );
+        }
+
+        info!("Updating provider {}", sub);
+        let contract_id = msg
+            .provider
+            .claims
+            .metadata
+            .as_ref()
+            .map(|md| md.capid.to_string())
+            .unwrap_or_default();
+        let link_name = msg.provider.link_name.to_string();
+        let init = crate::capability::native_host::Initialize {
+            cap: msg.provider,
+            mw_chain: self.mw_chain.clone(),
+            seed: self.kp.as_ref().unwrap().seed().unwrap(),
+            image_ref: msg.image_ref.clone(),
+            standby: true,
+        };
+        let b = MessageBus::from_hostlocal_registry(&self.kp.as_ref().unwrap().public_key());
+        Box::pin(
+            async move {
+                let new_provider = SyncArbiter::start(1, || NativeCapabilityHost::new());
+                let entity = new_provider.send(init).await??;
+                // If any of this fails, dropping the new provider stops it, and the old one
+                // carries on untouched
+                b.send(WarmProvider {
+                    entity: entity.clone(),
+                    recipient: new_provider.clone().recipient(),
+                })
+                .await??;
+                b.send(RedirectSubscriber {
+                    interest: entity,
+                    subscriber: new_provider.clone().recipient(),
+                })
+                .await??;
+                Ok(new_provider)
+            }
+            .into_actor(self)
+            .map(move |res: Result<Addr<NativeCapabilityHost>>, act, _| {
+                let new_provider = res?;
+                act.image_refs.retain(|_, pk| pk != &sub);
+                if let Some(ref imageref) = msg.image_ref {
+                    act.image_refs.insert(imageref.to_string(), sub.to_string());
+                }
+                // The old provider finishes the invocations already in its mailbox, then its
+                // plugin is told to stop, as it would be if the host stopped
+                if let Some(old_provider) = act.providers.insert(key, new_provider) {
+                    old_provider.do_send(StopProviderHost);
+                }
+                act.publish_event(ControlEvent::ProviderUpdated {
+                    contract_id,
+                    link_name,
+                    provider_id: sub,
+                    image_ref: msg.image_ref,
+                });
+                Ok(())
+            }),
+        )
+    }
+}
+
 async fn initialize_provider(
     provider: NativeCapability,
     mw: Vec<Box<dyn Middleware>>,
@@ -501,6 +1122,7 @@ async fn initialize_provider(
         mw_chain: mw.clone(),
         seed: seed.to_string(),
         image_ref: image_ref.clone(),
+        standby: false,
     };
     let entity = new_provider.send(im).await??;
     let _capid = match entity {
@@ -0,0 +1,189 @@
+// Monitoring agents that poll large hosts frequently don't need the whole inventory every time.
+// The host gives each distinct state of its inventory a revision and keeps a bounded log of the
+// changes between revisions, so that it can answer "what changed since revision N" with only the
+// actors, providers, and labels that differ. A revision is only assigned when an agent asks, by
+// comparing the current inventory with the one last reported, so an idle host does no work.
+
+use super::{ActorSummary, HostInventory, ProviderSummary};
+use std::collections::{HashMap, VecDeque};
+
+// The number of changes remembered. Agents further behind than this get the full inventory
+const MAX_CHANGES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Change {
+    ActorAdded(ActorSummary),
+    ActorRemoved(String),
+    ProviderAdded(ProviderSummary),
+    ProviderRemoved(ProviderSummary),
+    LabelsChanged,
+}
+
+/// The changes to a host's inventory since a given revision. When `full` is set, the added
+/// actors and providers are the entire inventory
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct InventoryChanges {
+    pub revision: u64,
+    pub full: bool,
+    pub labels: Option<HashMap<String, String>>,
+    pub actors_added: Vec<ActorSummary>,
+    pub actors_removed: Vec<String>,
+    pub providers_added: Vec<ProviderSummary>,
+    pub providers_removed: Vec<ProviderSummary>,
+}
+
+#[derive(Default)]
+pub(crate) struct InventoryLog {
+    revision: u64,
+    // Every change after this revision is still in the log
+    floor: u64,
+    last: HostInventory,
+    changes: VecDeque<(u64, Change)>,
+}
+
+impl InventoryLog {
+    /// Records the current inventory, assigning it a new revision if it differs from the last
+    /// one recorded, and returns the changes since the given revision
+    pub fn changes_since(
+        &mut self,
+        current: HostInventory,
+        since_revision: u64,
+    ) -> InventoryChanges {
+        self.record(current);
+        if since_revision == 0 || since_revision < self.floor || since_revision > self.revision {
+            return InventoryChanges {
+                revision: self.revision,
+                full: true,
+                labels: Some(self.last.labels.clone()),
+                actors_added: self.last.actors.clone(),
+                actors_removed: vec![],
+                providers_added: self.last.providers.clone(),
+                providers_removed: vec![],
+            };
+        }
+
+        // Collapse the changes, keeping only the latest for each actor and provider
+        let mut actors: HashMap<&str, &Change> = HashMap::new();
+        let mut providers: HashMap<(&str, &str), &Change> = HashMap::new();
+        let mut labels_changed = false;
+        for (_, change) in self.changes.iter().filter(|(r, _)| *r > since_revision) {
+            match change {
+                Change::ActorAdded(a) => {
+                    actors.insert(&a.id, change);
+                }
+                Change::ActorRemoved(id) => {
+                    actors.insert(id, change);
+                }
+                Change::ProviderAdded(p) | Change::ProviderRemoved(p) => {
+                    providers.insert((&p.id, &p.link_name), change);
+                }
+                Change::LabelsChanged => labels_changed = true,
+            }
+        }
+        let mut delta = InventoryChanges {
+            revision: self.revision,
+            labels: if labels_changed {
+                Some(self.last.labels.clone())
+            } else {
+                None
+            },
+            ..Default::default()
+        };
+        for change in actors.values().chain(providers.values()) {
+            match change {
+                Change::ActorAdded(a) => delta.actors_added.push(a.clone()),
+                Change::ActorRemoved(id) => delta.actors_removed.push(id.to_string()),
+                Change::ProviderAdded(p) => delta.providers_added.push(p.clone()),
+                Change::ProviderRemoved(p) => delta.providers_removed.push(p.clone()),
+                Change::LabelsChanged => {}
+            }
+        }
+        delta
+    }
+
+    fn record(&mut self, current: HostInventory) {
+        let mut changes = vec![];
+        for a in current
+            .actors
+            .iter()
+            .filter(|a| !self.last.actors.contains(a))
+        {
+            changes.push(Change::ActorAdded(a.clone()));
+        }
+        for a in self.last.actors.iter() {
+            if !current.actors.iter().any(|c| c.id == a.id) {
+                changes.push(Change::ActorRemoved(a.id.to_string()));
+            }
+        }
+        for p in current
+            .providers
+            .iter()
+            .filter(|p| !self.last.providers.contains(p))
+        {
+            changes.push(Change::ProviderAdded(p.clone()));
+        }
+        for p in self.last.providers.iter() {
+            if !current
+                .providers
+                .iter()
+                .any(|c| c.id == p.id && c.link_name == p.link_name)
+            {
+                changes.push(Change::ProviderRemoved(p.clone()));
+            }
+        }
+        if current.labels != self.last.labels {
+            changes.push(Change::LabelsChanged);
+        }
+        self.last = current;
+        if changes.is_empty() {
+            return;
+        }
+        self.revision += 1;
+        for change in changes {
+            self.changes.push_back((self.revision, change));
+        }
+        while self.changes.len() > MAX_CHANGES {
+            if let Some((r, _)) = self.changes.pop_front() {
+                self.floor = r;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InventoryLog;
+    use crate::host_controller::{ActorSummary, HostInventory};
+
+    fn actor(id: &str) -> ActorSummary {
+        ActorSummary {
+            id: id.to_string(),
+            image_ref: None,
+        }
+    }
+
+    #[test]
+    fn only_changes_since_the_revision_are_reported() {
+        let mut log = InventoryLog::default();
+        let mut inv = HostInventory {
+            actors: vec![actor("Ma"), actor("Mb")],
+            ..Default::default()
+        };
+        let first = log.changes_since(inv.clone(), 0);
+        assert!(first.full);
+        assert_eq!(2, first.actors_added.len());
+
+        let unchanged = log.changes_since(inv.clone(), first.revision);
+        assert_eq!(first.revision, unchanged.revision);
+        assert!(unchanged.actors_added.is_empty() && unchanged.actors_removed.is_empty());
+
+        inv.actors = vec![actor("Mb"), actor("Mc")];
+        let delta = log.changes_since(inv.clone(), first.revision);
+        assert!(!delta.full);
+        assert_eq!(vec![actor("Mc")], delta.actors_added);
+        assert_eq!(vec!["Ma".to_string()], delta.actors_removed);
+        assert!(delta.labels.is_none());
+
+        assert!(log.changes_since(inv, delta.revision + 10).full);
+    }
+}
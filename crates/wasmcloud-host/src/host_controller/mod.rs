@@ -1,5 +1,14 @@
-use crate::actors::{ActorHost, WasccActor};
+use crate::actors::{ActorAddr, WasccActor};
 use crate::auth::Authorizer;
+use crate::billing::BillingSink;
+use crate::capability::extras::Determinism;
+use crate::hooks::PreStartHook;
+use crate::idempotency::IdempotencyConfig;
+use crate::limits::HostLimits;
+use crate::shutdown::StoppedProvider;
+use crate::snapshots::SnapshotConfig;
+use crate::threading::ThreadConfig;
+use crate::wasm_features::WasmFeatures;
 
 use crate::{NativeCapability, Result};
 use actix::prelude::*;
@@ -9,15 +18,22 @@ use std::collections::HashMap;
 use wascap::prelude::KeyPair;
 
 mod hc_actor;
+mod inventory;
 
 pub(crate) const CORELABEL_ARCH: &str = "hostcore.arch";
 pub(crate) const CORELABEL_OS: &str = "hostcore.os";
 pub(crate) const CORELABEL_OSFAMILY: &str = "hostcore.osfamily";
+/// Hosts labeled with a zone prefer to route lattice invocations to instances in the same zone
+pub const LABEL_ZONE: &str = "zone";
+/// Hosts without a zone label fall back to using their region label for locality-preferring routing
+pub const LABEL_REGION: &str = "region";
+
 pub(crate) const RESTRICTED_LABELS: [&str; 3] = [CORELABEL_OSFAMILY, CORELABEL_ARCH, CORELABEL_OS];
 
 use actix::dev::{MessageResponse, ResponseChannel};
 pub(crate) use hc_actor::detect_core_host_labels;
 pub(crate) use hc_actor::HostController;
+pub(crate) use inventory::InventoryChanges;
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -30,8 +46,23 @@ pub(crate) struct CheckLink {
 pub(crate) struct Initialize {
     pub labels: HashMap<String, String>,
     pub auth: Box<dyn Authorizer>,
+    pub prestart_hooks: Vec<Box<dyn PreStartHook>>,
     pub kp: KeyPair,
     pub allow_live_updates: bool,
+    pub services: Option<HashMap<String, String>>,
+    pub http_router: Option<u16>,
+    pub https_router: Option<u16>,
+    pub grpc_ingress: Option<u16>,
+    pub memory_keyvalue: bool,
+    pub determinism: Determinism,
+    pub snapshots: Option<SnapshotConfig>,
+    pub wasm_features: WasmFeatures,
+    pub billing_sinks: Vec<Box<dyn BillingSink>>,
+    pub idempotency: IdempotencyConfig,
+    pub strict: bool,
+    pub denied_providers: Vec<String>,
+    pub limits: HostLimits,
+    pub threads: ThreadConfig,
 }
 
 #[derive(Message)]
@@ -47,6 +78,16 @@ pub(crate) struct StartActor {
     pub image_ref: Option<String>,
 }
 
+/// Live updates a running actor with a new version of its module, once the new version has been
+/// admitted as a starting actor would be. The new version must have the running actor's public key
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub(crate) struct UpdateActor {
+    pub actor_id: String,
+    pub actor: WasccActor,
+    pub image_ref: String,
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<()>")]
 pub(crate) struct StartProvider {
@@ -54,6 +95,14 @@ pub(crate) struct StartProvider {
     pub image_ref: Option<String>,
 }
 
+/// Replaces a running provider with another version of it, without unbinding its links
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub(crate) struct UpgradeProvider {
+    pub provider: NativeCapability,
+    pub image_ref: Option<String>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub(crate) struct StopActor {
@@ -82,7 +131,7 @@ pub(crate) struct QueryProviderRunning {
 }
 
 #[derive(Message)]
-#[rtype(result = "Option<Addr<ActorHost>>")]
+#[rtype(result = "Option<ActorAddr>")]
 pub(crate) struct GetRunningActor {
     pub actor_id: String,
 }
@@ -95,10 +144,36 @@ pub(crate) struct GetHostID;
 #[rtype(result = "u64")]
 pub(crate) struct QueryUptime;
 
+/// Stops the host from taking on any more actors or providers, ahead of an upgrade
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Drain;
+
+/// Stops every actor and provider as the host stops, giving each provider until the deadline to
+/// clean up
+#[derive(Message)]
+#[rtype(result = "StoppedEntities")]
+pub(crate) struct StopAll {
+    pub deadline: std::time::Duration,
+}
+
+pub(crate) struct StoppedEntities {
+    pub actors: Vec<String>,
+    pub providers: Vec<StoppedProvider>,
+    /// The providers that hadn't finished cleaning up by the deadline
+    pub timed_out: Vec<StoppedProvider>,
+}
+
 #[derive(Message)]
 #[rtype(result = "HostInventory")]
 pub(crate) struct QueryHostInventory;
 
+#[derive(Message)]
+#[rtype(result = "InventoryChanges")]
+pub(crate) struct QueryInventoryChanges {
+    pub since_revision: u64,
+}
+
 #[derive(Message)]
 #[rtype(result = "bool")]
 pub(crate) struct AuctionProvider {
@@ -133,6 +208,7 @@ pub(crate) struct ProviderSummary {
     pub id: String,
     pub image_ref: Option<String>,
     pub link_name: String,
+    pub instance_id: String,
 }
 
 impl<A, M> MessageResponse<A, M> for HostInventory
@@ -146,3 +222,55 @@ where
         }
     }
 }
+
+impl<A, M> MessageResponse<A, M> for InventoryChanges
+where
+    A: Actor,
+    M: Message<Result = InventoryChanges>,
+{
+    fn handle<R: ResponseChannel<M>>(self, _: &mut A::Context, tx: Option<R>) {
+        if let Some(tx) = tx {
+            tx.send(self);
+        }
+    }
+}
+
+/// The ID of a provider instance, derived from the host it runs in, its public key, and its link
+/// name. It stays the same for as long as the instance runs, and tells apart instances of the same
+/// provider running in different hosts
+pub(crate) fn provider_instance_id(host_id: &str, provider_id: &str, link_name: &str) -> String {
+    crate::hooks::bytes_digest(format!("{}/{}/{}", host_id, provider_id, link_name).as_bytes())
+        [..20]
+        .to_string()
+}
+
+/// Determines the locality (zone, or region if no zone is set) advertised by a host with the
+/// given labels. The value is sanitized so that it can be used as a single subject token
+pub(crate) fn locality(labels: &HashMap<String, String>) -> Option<String> {
+    labels
+        .get(LABEL_ZONE)
+        .or_else(|| labels.get(LABEL_REGION))
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            l.trim().replace(
+                |c: char| c == '.' || c == '*' || c == '>' || c.is_whitespace(),
+                "_",
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{locality, LABEL_REGION, LABEL_ZONE};
+    use std::collections::HashMap;
+
+    #[test]
+    fn locality_prefers_zone_and_is_one_subject_token() {
+        let mut labels = HashMap::new();
+        assert_eq!(None, locality(&labels));
+        labels.insert(LABEL_REGION.to_string(), "us-east".to_string());
+        assert_eq!(Some("us-east".to_string()), locality(&labels));
+        labels.insert(LABEL_ZONE.to_string(), " us-east.1a *> b ".to_string());
+        assert_eq!(Some("us-east_1a____b".to_string()), locality(&labels));
+    }
+}
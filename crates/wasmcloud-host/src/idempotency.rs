@@ -0,0 +1,173 @@
+// Callers that retry side-effecting invocations can attach an idempotency key. The host that
+// executes the invocation remembers the result of each keyed invocation for a while, and if the
+// same request arrives again with the same key, returns the remembered result instead of running
+// the actor a second time. Only successful results are remembered, so a retry after a failure
+// is executed again.
+//
+// This is best effort rather than exactly-once. The results are kept in memory by the running
+// instance of the actor that executed the invocation; they are kept across a live update, but
+// are lost when the actor stops or its host restarts. Nothing is shared across the lattice, so a
+// retry that's routed to an instance of the actor on another host is executed again. Callers
+// that need stronger guarantees should make the operation itself idempotent.
+
+use crate::{Invocation, InvocationResponse};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+pub(crate) const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(300);
+pub(crate) const DEFAULT_IDEMPOTENCY_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct IdempotencyConfig {
+    pub ttl: Duration,
+    pub capacity: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            ttl: DEFAULT_IDEMPOTENCY_TTL,
+            capacity: DEFAULT_IDEMPOTENCY_CAPACITY,
+        }
+    }
+}
+
+struct Entry {
+    // When the result was recorded, as a monotonic reading of the host's clock
+    recorded: Duration,
+    // The hash of the original request, so that a key can't be reused for a different request
+    request_hash: String,
+    msg: Vec<u8>,
+}
+
+/// The results of recent keyed invocations handled by one running instance of an actor
+pub(crate) struct IdempotencyCache {
+    config: IdempotencyConfig,
+    entries: HashMap<String, Entry>,
+    order: VecDeque<String>,
+}
+
+impl IdempotencyCache {
+    pub fn new(config: IdempotencyConfig) -> IdempotencyCache {
+        IdempotencyCache {
+            config,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &IdempotencyConfig {
+        &self.config
+    }
+
+    /// Returns the remembered response for a retried invocation, if there is one. A key that
+    /// was used for a different request results in an error response. `now` is a monotonic
+    /// reading of the host's clock
+    pub fn lookup(&mut self, inv: &Invocation, now: Duration) -> Option<InvocationResponse> {
+        let key = inv.idempotency_key.as_ref()?;
+        self.evict_expired(now);
+        let entry = self.entries.get(key)?;
+        // Retries are new invocations with new IDs, so they are matched on origin, target,
+        // operation, and payload
+        if entry.request_hash != inv.hash() {
+            return Some(InvocationResponse::error(
+                inv,
+                "Idempotency key was already used for a different request",
+            ));
+        }
+        trace!("Returning remembered result for idempotency key {}", key);
+        Some(InvocationResponse::success(inv, entry.msg.clone()))
+    }
+
+    /// Remembers the response to a keyed invocation, if it succeeded
+    pub fn record(&mut self, inv: &Invocation, resp: &InvocationResponse, now: Duration) {
+        let key = match inv.idempotency_key {
+            Some(ref k) if resp.error.is_none() => k.to_string(),
+            _ => return,
+        };
+        if self.order.len() >= self.config.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.to_string());
+        self.entries.insert(
+            key,
+            Entry {
+                recorded: now,
+                request_hash: inv.hash(),
+                msg: resp.msg.clone(),
+            },
+        );
+    }
+
+    fn evict_expired(&mut self, now: Duration) {
+        while let Some(oldest) = self.order.front() {
+            match self.entries.get(oldest) {
+                Some(e) if now < e.recorded + self.config.ttl => break,
+                _ => {
+                    let oldest = self.order.pop_front().unwrap();
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IdempotencyCache, IdempotencyConfig};
+    use crate::{Invocation, InvocationResponse, WasccEntity};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    const NOW: Duration = Duration::from_secs(1000);
+
+    fn invocation(kp: &KeyPair, payload: &[u8]) -> Invocation {
+        Invocation::new(
+            kp,
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("MACTOR".to_string()),
+            "Charge",
+            payload.to_vec(),
+        )
+        .with_idempotency_key("order-42")
+    }
+
+    #[test]
+    fn retries_return_the_remembered_result() {
+        let kp = KeyPair::new_server();
+        let mut cache = IdempotencyCache::new(IdempotencyConfig::default());
+        let first = invocation(&kp, b"100");
+        assert!(cache.lookup(&first, NOW).is_none());
+        cache.record(
+            &first,
+            &InvocationResponse::success(&first, b"ok".to_vec()),
+            NOW,
+        );
+
+        let retry = invocation(&kp, b"100");
+        let resp = cache.lookup(&retry, NOW).unwrap();
+        assert_eq!(b"ok".to_vec(), resp.msg);
+        assert_eq!(retry.id, resp.invocation_id);
+
+        let different = invocation(&kp, b"200");
+        assert!(cache.lookup(&different, NOW).unwrap().error.is_some());
+    }
+
+    #[test]
+    fn failures_and_expired_results_are_not_remembered() {
+        let kp = KeyPair::new_server();
+        let mut cache = IdempotencyCache::new(IdempotencyConfig {
+            ttl: Duration::from_secs(60),
+            capacity: 10,
+        });
+        let inv = invocation(&kp, b"100");
+        cache.record(&inv, &InvocationResponse::error(&inv, "declined"), NOW);
+        assert!(cache.lookup(&inv, NOW).is_none());
+
+        cache.record(&inv, &InvocationResponse::success(&inv, vec![]), NOW);
+        assert!(cache.lookup(&inv, NOW + Duration::from_secs(59)).is_some());
+        assert!(cache.lookup(&inv, NOW + Duration::from_secs(60)).is_none());
+    }
+}
@@ -0,0 +1,81 @@
+// Replies to a host's lattice requests arrive on inbox subjects. A host that connects with
+// credentials issued by `LatticeCredentials` may only subscribe beneath an inbox prefix of its
+// own user, so that no other member of the lattice can read the replies meant for it. Every
+// request a host sends therefore goes through here rather than `Connection::request`, which always
+// uses the shared `_INBOX` prefix. Hosts connected any other way keep that shared prefix.
+//
+// As with labels and clocks, the prefixes are kept here by host ID so that any part of a host
+// that sends a request can find its inbox.
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+pub(crate) const DEFAULT_INBOX_PREFIX: &str = "_INBOX";
+
+static PREFIXES: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Sets the inbox prefix for a host's requests
+pub(crate) fn register(host_id: &str, prefix: &str) {
+    PREFIXES
+        .write()
+        .insert(host_id.to_string(), prefix.to_string());
+}
+
+pub(crate) fn unregister(host_id: &str) {
+    PREFIXES.write().remove(host_id);
+}
+
+/// A new, unique inbox subject beneath the host's inbox prefix
+pub(crate) fn new_inbox(host_id: &str) -> String {
+    let prefixes = PREFIXES.read();
+    let prefix = prefixes
+        .get(host_id)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_INBOX_PREFIX);
+    format!("{}.{}", prefix, uuid::Uuid::new_v4())
+}
+
+/// Sends a request from the host and waits for the first reply
+pub(crate) async fn request(
+    nc: &nats::asynk::Connection,
+    host_id: &str,
+    subject: &str,
+    payload: &[u8],
+) -> std::io::Result<nats::asynk::Message> {
+    let sub = request_multi(nc, host_id, subject, payload).await?;
+    let reply = sub.next().await;
+    let _ = sub.unsubscribe().await;
+    reply
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotConnected, "No reply to request"))
+}
+
+/// Sends a request from the host, returning the subscription its replies arrive on
+pub(crate) async fn request_multi(
+    nc: &nats::asynk::Connection,
+    host_id: &str,
+    subject: &str,
+    payload: &[u8],
+) -> std::io::Result<nats::asynk::Subscription> {
+    let inbox = new_inbox(host_id);
+    let sub = nc.subscribe(&inbox).await?;
+    nc.publish_request(subject, &inbox, payload).await?;
+    Ok(sub)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{new_inbox, register, unregister};
+
+    #[test]
+    fn inboxes_are_beneath_the_host_prefix() {
+        assert!(new_inbox("Ninboxes").starts_with("_INBOX."));
+        register("Ninboxes", "_INBOX.UABC");
+        let a = new_inbox("Ninboxes");
+        let b = new_inbox("Ninboxes");
+        assert!(a.starts_with("_INBOX.UABC."));
+        assert_ne!(a, b);
+        unregister("Ninboxes");
+        assert!(!new_inbox("Ninboxes").starts_with("_INBOX.UABC."));
+    }
+}
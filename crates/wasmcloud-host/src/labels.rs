@@ -0,0 +1,108 @@
+// Everything a host emits for monitoring -- control events, link statistics, and cost records --
+// carries the same set of labels: the lattice namespace, the host ID, the issuer of the entity
+// the data is about (when there is one), and any static labels set on the host builder. A
+// collector scraping several lattices can then tell tenants apart without relabeling.
+//
+// The labels are kept here by host ID rather than threaded through every actor, so that any part
+// of a host that emits something can label it the same way.
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+/// The label holding the lattice namespace of the host that emitted a metric or event
+pub const LABEL_NAMESPACE: &str = "namespace";
+/// The label holding the public key of the account that issued the actor or provider a metric or
+/// event is about
+pub const LABEL_ISSUER: &str = "issuer";
+/// The label holding the public key of the host that emitted a metric or event
+pub const LABEL_HOST_ID: &str = "host_id";
+
+#[derive(Debug, Default)]
+struct HostLabels {
+    labels: HashMap<String, String>,
+    // The issuer of each entity that has run on the host. These are kept after an entity stops,
+    // so that the events about it stopping are labeled too
+    issuers: HashMap<String, String>,
+}
+
+static HOST_LABELS: Lazy<RwLock<HashMap<String, HostLabels>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub(crate) fn is_reserved(label: &str) -> bool {
+    label == LABEL_NAMESPACE || label == LABEL_ISSUER || label == LABEL_HOST_ID
+}
+
+/// Sets the labels for everything emitted by a host
+pub(crate) fn register(host_id: &str, namespace: &str, custom: &HashMap<String, String>) {
+    let mut labels: HashMap<String, String> = custom
+        .iter()
+        .filter(|(k, _)| !is_reserved(k))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    labels.insert(LABEL_NAMESPACE.to_string(), namespace.to_string());
+    labels.insert(LABEL_HOST_ID.to_string(), host_id.to_string());
+    HOST_LABELS.write().insert(
+        host_id.to_string(),
+        HostLabels {
+            labels,
+            issuers: HashMap::new(),
+        },
+    );
+}
+
+pub(crate) fn unregister(host_id: &str) {
+    HOST_LABELS.write().remove(host_id);
+}
+
+/// Records the issuer of an actor or provider running on a host
+pub(crate) fn set_issuer(host_id: &str, entity: &str, issuer: &str) {
+    if let Some(host) = HOST_LABELS.write().get_mut(host_id) {
+        host.issuers.insert(entity.to_string(), issuer.to_string());
+    }
+}
+
+/// The labels for something emitted by a host about the given actor or provider (if any)
+pub(crate) fn labels_for(host_id: &str, entity: Option<&str>) -> HashMap<String, String> {
+    let lock = HOST_LABELS.read();
+    let host = match lock.get(host_id) {
+        Some(host) => host,
+        None => {
+            let mut labels = HashMap::new();
+            labels.insert(LABEL_HOST_ID.to_string(), host_id.to_string());
+            return labels;
+        }
+    };
+    let mut labels = host.labels.clone();
+    if let Some(issuer) = entity.and_then(|e| host.issuers.get(e)) {
+        labels.insert(LABEL_ISSUER.to_string(), issuer.to_string());
+    }
+    labels
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        labels_for, register, set_issuer, unregister, LABEL_HOST_ID, LABEL_ISSUER, LABEL_NAMESPACE,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn standard_labels_cannot_be_overridden() {
+        let mut custom = HashMap::new();
+        custom.insert("region".to_string(), "eu-west".to_string());
+        custom.insert(LABEL_NAMESPACE.to_string(), "spoofed".to_string());
+        register("Nlabels", "tenant-a", &custom);
+        set_issuer("Nlabels", "Mactor", "Aissuer");
+
+        let labels = labels_for("Nlabels", Some("Mactor"));
+        assert_eq!("tenant-a", labels[LABEL_NAMESPACE]);
+        assert_eq!("Nlabels", labels[LABEL_HOST_ID]);
+        assert_eq!("Aissuer", labels[LABEL_ISSUER]);
+        assert_eq!("eu-west", labels["region"]);
+        assert!(!labels_for("Nlabels", None).contains_key(LABEL_ISSUER));
+
+        unregister("Nlabels");
+        assert_eq!(1, labels_for("Nlabels", Some("Mactor")).len());
+    }
+}
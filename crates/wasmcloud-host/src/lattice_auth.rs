@@ -0,0 +1,338 @@
+// Helpers for locking a lattice down with NATS 2.x decentralized authentication. A lattice gets
+// its own operator and account, and each host connects as a user of that account whose
+// permissions only cover the lattice's own subjects. Each user may only subscribe to inboxes
+// beneath a prefix of its own public key, so one member of the lattice can't read the replies
+// meant for another; hosts send their requests from that prefix (see `inboxes`). The operator and
+// account seeds can be saved and passed back to `from_seeds` to keep issuing users of the same
+// lattice.
+
+use crate::permissions::{NatsPermissions, SubjectPermissions};
+use crate::Result;
+use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
+use ring::digest::{digest, SHA512_256};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wascap::prelude::KeyPair;
+
+const JWT_HEADER: &str = r#"{"typ":"JWT","alg":"ed25519-nkey"}"#;
+const NATS_JWT_VERSION: u8 = 2;
+const ALL_INBOXES: &str = "_INBOX.>";
+
+/// The operator, account, and signing keys for a lattice secured with NATS decentralized
+/// authentication. The operator and account JWTs are used to configure the NATS server (e.g.
+/// with a memory resolver), while `issue_user` creates a credentials file for each host
+pub struct LatticeCredentials {
+    pub namespace: String,
+    pub operator_jwt: String,
+    pub account_jwt: String,
+    /// The public key of the account, used to register it with the NATS server
+    pub account_id: String,
+    operator: KeyPair,
+    account: KeyPair,
+}
+
+impl LatticeCredentials {
+    /// Generates a new operator and account for the lattice with the given namespace
+    pub fn generate(namespace: &str) -> Result<LatticeCredentials> {
+        LatticeCredentials::from_keys(namespace, KeyPair::new_operator(), KeyPair::new_account())
+    }
+
+    /// Restores the credentials of a lattice from the seeds returned by `operator_seed` and
+    /// `account_seed`. The operator and account JWTs are signed afresh, but name the same keys,
+    /// so users issued before and after are members of the same account
+    pub fn from_seeds(
+        namespace: &str,
+        operator_seed: &str,
+        account_seed: &str,
+    ) -> Result<LatticeCredentials> {
+        let operator = KeyPair::from_seed(operator_seed)?;
+        let account = KeyPair::from_seed(account_seed)?;
+        if !operator.public_key().starts_with('O') || !account.public_key().starts_with('A') {
+            return Err("Lattice credentials need an operator seed and an account seed".into());
+        }
+        LatticeCredentials::from_keys(namespace, operator, account)
+    }
+
+    fn from_keys(
+        namespace: &str,
+        operator: KeyPair,
+        account: KeyPair,
+    ) -> Result<LatticeCredentials> {
+        let operator_jwt = encode(
+            &operator,
+            &operator.public_key(),
+            &format!("wasmcloud-{}", namespace),
+            json!({
+                "type": "operator",
+                "version": NATS_JWT_VERSION,
+            }),
+        )?;
+        let account_jwt = encode(
+            &operator,
+            &account.public_key(),
+            &format!("lattice-{}", namespace),
+            json!({
+                "type": "account",
+                "version": NATS_JWT_VERSION,
+                "limits": {
+                    "subs": -1, "data": -1, "payload": -1, "imports": -1, "exports": -1,
+                    "wildcards": true, "conn": -1, "leaf": -1,
+                },
+            }),
+        )?;
+        Ok(LatticeCredentials {
+            namespace: namespace.to_string(),
+            operator_jwt,
+            account_jwt,
+            account_id: account.public_key(),
+            operator,
+            account,
+        })
+    }
+
+    /// The seed of the operator key. Keep this offline; it is only needed to re-issue the account
+    pub fn operator_seed(&self) -> Result<String> {
+        Ok(self.operator.seed()?)
+    }
+
+    /// The seed of the account key, which signs every user of the lattice. Together with the
+    /// operator seed it restores these credentials through `from_seeds`
+    pub fn account_seed(&self) -> Result<String> {
+        Ok(self.account.seed()?)
+    }
+
+    /// Issues a new user of the lattice account, returning the contents of a NATS credentials
+    /// file. The user may only publish and subscribe on the lattice's own subjects
+    pub fn issue_user(&self, name: &str) -> Result<String> {
+        let subjects = SubjectPermissions {
+            allow: lattice_subjects(&self.namespace),
+        };
+        self.issue_user_with_permissions(
+            name,
+            &NatsPermissions {
+                publish: subjects.clone(),
+                subscribe: subjects,
+            },
+        )
+    }
+
+    /// Issues a new user of the lattice account restricted to the given permissions, such as
+    /// those produced by `Host::required_permissions`. A subscription to every inbox is narrowed
+    /// to the inboxes beneath the user's own prefix
+    pub fn issue_user_with_permissions(
+        &self,
+        name: &str,
+        permissions: &NatsPermissions,
+    ) -> Result<String> {
+        let user = KeyPair::new_user();
+        let own_inboxes = format!("{}.>", inbox_prefix(&user.public_key()));
+        let subscribe: Vec<String> = permissions
+            .subscribe
+            .allow
+            .iter()
+            .map(|s| {
+                if s == ALL_INBOXES {
+                    own_inboxes.to_string()
+                } else {
+                    s.to_string()
+                }
+            })
+            .collect();
+        let jwt = encode(
+            &self.account,
+            &user.public_key(),
+            name,
+            json!({
+                "type": "user",
+                "version": NATS_JWT_VERSION,
+                "pub": { "allow": permissions.publish.allow },
+                "sub": { "allow": subscribe },
+                "subs": -1, "data": -1, "payload": -1,
+            }),
+        )?;
+        Ok(format_creds(&jwt, &user.seed()?))
+    }
+
+    /// Issues a new user and writes its credentials file to the given path, ready to be passed
+    /// to `HostBuilder::with_lattice_credentials`
+    pub fn write_user_creds(&self, name: &str, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.issue_user(name)?)?;
+        Ok(())
+    }
+}
+
+/// The subjects a host needs in order to take part in the lattice with the given namespace
+pub fn lattice_subjects(namespace: &str) -> Vec<String> {
+    let ns = Some(namespace.to_string());
+    vec![
+        format!("{}.>", ::control_interface::broker::rpc_prefix(&ns)),
+        format!("{}.>", ::control_interface::broker::prefix(&ns)),
+        format!("{}.>", ::control_interface::broker::extension_prefix(&ns)),
+        ALL_INBOXES.to_string(),
+    ]
+}
+
+/// The prefix of the inboxes a user issued by `LatticeCredentials` may subscribe to
+pub fn inbox_prefix(user: &str) -> String {
+    format!("{}.{}", crate::inboxes::DEFAULT_INBOX_PREFIX, user)
+}
+
+/// The inbox prefix of the user in a NATS credentials file
+pub(crate) fn creds_inbox_prefix(path: impl AsRef<Path>) -> Result<String> {
+    let creds = std::fs::read_to_string(path)?;
+    let jwt = creds
+        .lines()
+        .skip_while(|l| !l.starts_with("-----BEGIN NATS USER JWT"))
+        .nth(1)
+        .ok_or("No user JWT in credentials file")?;
+    let claims = jwt.split('.').nth(1).ok_or("Malformed user JWT")?;
+    let claims: Value = serde_json::from_slice(&BASE64URL_NOPAD.decode(claims.trim().as_bytes())?)?;
+    let user = claims["sub"].as_str().ok_or("User JWT has no subject")?;
+    Ok(inbox_prefix(user))
+}
+
+fn encode(issuer: &KeyPair, subject: &str, name: &str, nats: Value) -> Result<String> {
+    let iat = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut claims = json!({
+        "jti": "",
+        "iat": iat,
+        "iss": issuer.public_key(),
+        "name": name,
+        "sub": subject,
+        "nats": nats,
+    });
+    // The claims ID is the SHA-512/256 hash of the claims themselves, as NATS computes it
+    let hash = digest(&SHA512_256, serde_json::to_string(&claims)?.as_bytes());
+    claims["jti"] = Value::String(BASE32_NOPAD.encode(hash.as_ref()));
+
+    let head_and_claims = format!(
+        "{}.{}",
+        BASE64URL_NOPAD.encode(JWT_HEADER.as_bytes()),
+        BASE64URL_NOPAD.encode(serde_json::to_string(&claims)?.as_bytes())
+    );
+    let sig = issuer.sign(head_and_claims.as_bytes())?;
+    Ok(format!(
+        "{}.{}",
+        head_and_claims,
+        BASE64URL_NOPAD.encode(&sig)
+    ))
+}
+
+fn format_creds(jwt: &str, seed: &str) -> String {
+    format!(
+        r#"-----BEGIN NATS USER JWT-----
+{}
+------END NATS USER JWT------
+
+************************* IMPORTANT *************************
+NKEY Seed printed below can be used to sign and prove identity.
+NKEYs are sensitive and should be treated as secrets.
+
+-----BEGIN USER NKEY SEED-----
+{}
+------END USER NKEY SEED------
+
+*************************************************************
+"#,
+        jwt, seed
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{creds_inbox_prefix, LatticeCredentials};
+    use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
+    use ring::digest::{digest, SHA512_256};
+    use serde_json::Value;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn users_are_signed_by_the_account_and_scoped_to_the_lattice() {
+        let creds = LatticeCredentials::generate("prod").unwrap();
+        let file = creds.issue_user("host1").unwrap();
+        let jwt = file.lines().nth(1).unwrap();
+        let parts: Vec<_> = jwt.split('.').collect();
+        assert_eq!(3, parts.len());
+
+        let claims: Value =
+            serde_json::from_slice(&BASE64URL_NOPAD.decode(parts[1].as_bytes()).unwrap()).unwrap();
+        assert_eq!(creds.account_id, claims["iss"].as_str().unwrap());
+        let allowed = claims["nats"]["pub"]["allow"].as_array().unwrap();
+        assert!(allowed.contains(&Value::String("wasmbus.rpc.prod.>".to_string())));
+        assert!(!allowed.contains(&Value::String(">".to_string())));
+
+        let sig = BASE64URL_NOPAD.decode(parts[2].as_bytes()).unwrap();
+        let account = KeyPair::from_public_key(&creds.account_id).unwrap();
+        assert!(account
+            .verify(format!("{}.{}", parts[0], parts[1]).as_bytes(), &sig)
+            .is_ok());
+        assert!(file.contains("-----BEGIN USER NKEY SEED-----\nSU"));
+    }
+
+    #[test]
+    fn users_only_subscribe_to_their_own_inboxes() {
+        let creds = LatticeCredentials::generate("prod").unwrap();
+        let file = creds.issue_user("host1").unwrap();
+        let claims = claims(&file);
+        let user = claims["sub"].as_str().unwrap();
+        let subscribe = claims["nats"]["sub"]["allow"].as_array().unwrap();
+        assert!(subscribe.contains(&Value::String(format!("_INBOX.{}.>", user))));
+        assert!(!subscribe.contains(&Value::String("_INBOX.>".to_string())));
+        // Replies are still published to whoever sent the request
+        let publish = claims["nats"]["pub"]["allow"].as_array().unwrap();
+        assert!(publish.contains(&Value::String("_INBOX.>".to_string())));
+
+        let path = std::env::temp_dir().join(format!("{}.creds", user));
+        std::fs::write(&path, &file).unwrap();
+        assert_eq!(
+            format!("_INBOX.{}", user),
+            creds_inbox_prefix(&path).unwrap()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn credentials_are_restored_from_seeds() {
+        let creds = LatticeCredentials::generate("prod").unwrap();
+        let restored = LatticeCredentials::from_seeds(
+            "prod",
+            &creds.operator_seed().unwrap(),
+            &creds.account_seed().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(creds.account_id, restored.account_id);
+        let file = restored.issue_user("host2").unwrap();
+        assert_eq!(creds.account_id, claims(&file)["iss"].as_str().unwrap());
+
+        // The seeds can't be swapped
+        assert!(LatticeCredentials::from_seeds(
+            "prod",
+            &creds.account_seed().unwrap(),
+            &creds.operator_seed().unwrap(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn claims_ids_are_sha512_256_hashes() {
+        let creds = LatticeCredentials::generate("prod").unwrap();
+        let mut claims = claims(&creds.issue_user("host1").unwrap());
+        let jti = claims["jti"].as_str().unwrap().to_string();
+        claims["jti"] = Value::String("".to_string());
+        let hash = digest(
+            &SHA512_256,
+            serde_json::to_string(&claims).unwrap().as_bytes(),
+        );
+        assert_eq!(BASE32_NOPAD.encode(hash.as_ref()), jti);
+    }
+
+    fn claims(creds_file: &str) -> Value {
+        let jwt = creds_file.lines().nth(1).unwrap();
+        let claims = jwt.split('.').nth(1).unwrap();
+        serde_json::from_slice(&BASE64URL_NOPAD.decode(claims.as_bytes()).unwrap()).unwrap()
+    }
+}
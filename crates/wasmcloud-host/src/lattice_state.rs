@@ -0,0 +1,349 @@
+// A lattice state snapshot captures everything a host knows about its lattice (actor claims,
+// link definitions, and the OCI references of what it is running) so that the same state can
+// be used to seed a fresh lattice or rebuild one after a disaster.
+
+use crate::hlreg::HostLocalSystemService;
+use crate::host_controller::{HostController, QueryHostInventory};
+use crate::manifest::{Capability, HostManifest, LinkEntry};
+use crate::messagebus::{AdvertiseClaims, GetClaims, MessageBus, QueryAllLinks};
+use crate::Result;
+use data_encoding::HEXUPPER;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wascap::jwt::{Actor, Claims};
+use wascap::prelude::KeyPair;
+
+/// The state of a lattice as seen by a single host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatticeState {
+    /// The lattice namespace from which the state was exported
+    pub namespace: String,
+    /// The time of the export, in seconds since the epoch
+    pub exported_at: u64,
+    /// The claims of every actor known to the host
+    pub claims: Vec<Claims<Actor>>,
+    /// The public keys of the actors and providers running in the host, by OCI image reference
+    pub image_refs: HashMap<String, String>,
+    /// A manifest that recreates the host's actors, providers, and every known link
+    pub manifest: HostManifest,
+}
+
+/// A lattice state signed by the host that exported it. The state can't be read without
+/// verifying the signature against a set of trusted issuers, so a snapshot that has been
+/// tampered with, or that was signed by an unknown key, will not be imported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatticeSnapshot {
+    /// The public key of the host that signed the snapshot
+    pub issuer: String,
+    /// The serialized (JSON) lattice state
+    pub state: String,
+    /// The hex-encoded signature of the serialized state
+    pub signature: String,
+}
+
+impl LatticeSnapshot {
+    fn sign(state: &LatticeState, kp: &KeyPair) -> Result<LatticeSnapshot> {
+        let state = serde_json::to_string(state)?;
+        let signature = HEXUPPER.encode(&kp.sign(state.as_bytes())?);
+        Ok(LatticeSnapshot {
+            issuer: kp.public_key(),
+            state,
+            signature,
+        })
+    }
+
+    /// Verifies that the snapshot was signed by one of the trusted issuers (public keys) and
+    /// returns the lattice state it contains
+    pub fn verify(&self, trusted_issuers: &[String]) -> Result<LatticeState> {
+        if !trusted_issuers.contains(&self.issuer) {
+            return Err(format!(
+                "Lattice snapshot issuer {} is not a trusted issuer",
+                self.issuer
+            )
+            .into());
+        }
+        let sig = HEXUPPER.decode(self.signature.as_bytes())?;
+        KeyPair::from_public_key(&self.issuer)?
+            .verify(self.state.as_bytes(), &sig)
+            .map_err(|_| "Lattice snapshot signature is invalid")?;
+        Ok(serde_json::from_str(&self.state)?)
+    }
+}
+
+/// Determines what happens when imported state disagrees with the state already held by the
+/// host, such as a link that points at a different provider or claims from a different token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictResolution {
+    /// Keep the existing state and ignore the imported entry
+    KeepExisting,
+    /// Replace the existing state with the imported entry
+    Overwrite,
+    /// Abort the import without applying any of it
+    Fail,
+}
+
+pub(crate) async fn export(
+    host_id: &str,
+    namespace: &str,
+    kp: &KeyPair,
+) -> Result<LatticeSnapshot> {
+    let hc = HostController::from_hostlocal_registry(host_id);
+    let bus = MessageBus::from_hostlocal_registry(host_id);
+    let inv = hc.send(QueryHostInventory).await?;
+    let claims = bus.send(GetClaims).await?.claims;
+    let links = bus.send(QueryAllLinks).await?.links;
+
+    let mut image_refs = HashMap::new();
+    for a in inv.actors.iter() {
+        if let Some(ref r) = a.image_ref {
+            image_refs.insert(r.to_string(), a.id.to_string());
+        }
+    }
+    for p in inv.providers.iter() {
+        if let Some(ref r) = p.image_ref {
+            image_refs.insert(r.to_string(), p.id.to_string());
+        }
+    }
+    let manifest = HostManifest {
+        labels: HashMap::new(),
+        actors: inv
+            .actors
+            .iter()
+            .filter_map(|a| a.image_ref.clone())
+            .collect(),
+        capabilities: inv
+            .providers
+            .iter()
+            .filter_map(|p| {
+                p.image_ref.as_ref().map(|r| Capability {
+                    image_ref: r.to_string(),
+                    link_name: Some(p.link_name.to_string()),
+                })
+            })
+            .collect(),
+        links: links
+            .into_iter()
+            .map(|ld| LinkEntry {
+                actor: ld.actor_id,
+                contract_id: ld.contract_id,
+                provider_id: ld.provider_id,
+                link_name: Some(ld.link_name),
+                values: Some(ld.values),
+            })
+            .collect(),
+    };
+    let state = LatticeState {
+        namespace: namespace.to_string(),
+        exported_at: crate::clock::now_secs(host_id),
+        claims: claims.into_iter().map(|(_k, v)| v).collect(),
+        image_refs,
+        manifest,
+    };
+    LatticeSnapshot::sign(&state, kp)
+}
+
+/// Imports a verified lattice state into a host, refusing state from a different namespace
+pub(crate) async fn import(
+    host_id: &str,
+    namespace: &str,
+    allow_latest: bool,
+    issuer: &str,
+    state: LatticeState,
+    resolution: ConflictResolution,
+) -> Result<()> {
+    if state.namespace != namespace {
+        return Err(format!(
+            "Lattice snapshot is for namespace '{}', not '{}'",
+            state.namespace, namespace
+        )
+        .into());
+    }
+    let hc = HostController::from_hostlocal_registry(host_id);
+    let bus = MessageBus::from_hostlocal_registry(host_id);
+
+    let existing_claims = bus.send(GetClaims).await?.claims;
+    let existing_links: HashMap<_, _> = bus
+        .send(QueryAllLinks)
+        .await?
+        .links
+        .into_iter()
+        .map(|ld| {
+            (
+                (ld.actor_id, ld.contract_id, ld.link_name),
+                (ld.provider_id, ld.values),
+            )
+        })
+        .collect();
+    let inv = hc.send(QueryHostInventory).await?;
+
+    // Work out everything that will be applied before applying any of it, so that a conflict
+    // under `Fail` leaves the host untouched
+    let claims = resolve(
+        state.claims,
+        |c| {
+            existing_claims
+                .get(&c.subject)
+                .map(|e| e.id != c.id)
+                .unwrap_or(false)
+        },
+        resolution,
+        |c| format!("claims for actor {}", c.subject),
+    )?;
+    let links = resolve(
+        state.manifest.links.clone(),
+        |l| {
+            let key = (
+                l.actor.to_string(),
+                l.contract_id.to_string(),
+                l.link_name.clone().unwrap_or_else(|| "default".to_string()),
+            );
+            existing_links
+                .get(&key)
+                .map(|(pid, values)| pid != &l.provider_id || Some(values) != l.values.as_ref())
+                .unwrap_or(false)
+        },
+        resolution,
+        |l| format!("link {} -> {}", l.actor, l.contract_id),
+    )?;
+
+    // Anything already running under the same image reference is left alone
+    let running: Vec<String> = inv
+        .actors
+        .iter()
+        .filter_map(|a| a.image_ref.clone())
+        .chain(inv.providers.iter().filter_map(|p| p.image_ref.clone()))
+        .collect();
+    let manifest = HostManifest {
+        labels: HashMap::new(),
+        actors: state
+            .manifest
+            .actors
+            .into_iter()
+            .filter(|a| !running.contains(a))
+            .collect(),
+        capabilities: state
+            .manifest
+            .capabilities
+            .into_iter()
+            .filter(|c| !running.contains(&c.image_ref))
+            .collect(),
+        links,
+    };
+
+    for claims in claims {
+        bus.send(AdvertiseClaims { claims }).await??;
+    }
+    for msg in
+        crate::manifest::generate_actor_start_messages(host_id, &manifest, allow_latest).await
+    {
+        hc.send(msg).await??;
+    }
+    for msg in
+        crate::manifest::generate_provider_start_messages(host_id, &manifest, allow_latest).await
+    {
+        hc.send(msg).await??;
+    }
+    for msg in crate::manifest::generate_adv_link_messages(&manifest).await {
+        bus.send(msg).await??;
+    }
+    info!(
+        "Imported lattice state from {} (namespace {})",
+        issuer, namespace
+    );
+    Ok(())
+}
+
+/// Filters the incoming entries according to the resolution, failing on the first conflict
+/// when the resolution is `Fail`
+fn resolve<T>(
+    incoming: Vec<T>,
+    conflicts: impl Fn(&T) -> bool,
+    resolution: ConflictResolution,
+    describe: impl Fn(&T) -> String,
+) -> Result<Vec<T>> {
+    let mut v = Vec::new();
+    for item in incoming {
+        if !conflicts(&item) {
+            v.push(item);
+            continue;
+        }
+        match resolution {
+            ConflictResolution::KeepExisting => {
+                info!(
+                    "Keeping existing {} instead of imported state",
+                    describe(&item)
+                )
+            }
+            ConflictResolution::Overwrite => v.push(item),
+            ConflictResolution::Fail => {
+                return Err(
+                    format!("Imported {} conflicts with existing state", describe(&item)).into(),
+                )
+            }
+        }
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve, ConflictResolution, LatticeSnapshot, LatticeState};
+    use crate::HostManifest;
+    use std::collections::HashMap;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn snapshots_are_verified() {
+        let kp = KeyPair::new_server();
+        let state = LatticeState {
+            namespace: "default".to_string(),
+            exported_at: 0,
+            claims: vec![],
+            image_refs: HashMap::new(),
+            manifest: HostManifest {
+                labels: HashMap::new(),
+                actors: vec!["wasmcloud.azurecr.io/echo:0.2.0".to_string()],
+                capabilities: vec![],
+                links: vec![],
+            },
+        };
+        let trusted = vec![kp.public_key()];
+        let mut snapshot = LatticeSnapshot::sign(&state, &kp).unwrap();
+        assert_eq!(
+            state.manifest.actors,
+            snapshot.verify(&trusted).unwrap().manifest.actors
+        );
+        // A valid signature from a key that isn't trusted is refused
+        assert!(snapshot
+            .verify(&[KeyPair::new_server().public_key()])
+            .is_err());
+        let other = LatticeSnapshot::sign(&state, &KeyPair::new_server()).unwrap();
+        assert!(other.verify(&trusted).is_err());
+
+        snapshot.state = snapshot.state.replace("echo", "evil");
+        assert!(snapshot.verify(&trusted).is_err());
+    }
+
+    #[test]
+    fn conflicts_are_resolved() {
+        let incoming = || vec![1, 2, 3];
+        let conflicts = |i: &i32| *i == 2;
+        let describe = |i: &i32| i.to_string();
+
+        let kept = resolve(
+            incoming(),
+            conflicts,
+            ConflictResolution::KeepExisting,
+            describe,
+        );
+        assert_eq!(vec![1, 3], kept.unwrap());
+        let over = resolve(
+            incoming(),
+            conflicts,
+            ConflictResolution::Overwrite,
+            describe,
+        );
+        assert_eq!(vec![1, 2, 3], over.unwrap());
+        assert!(resolve(incoming(), conflicts, ConflictResolution::Fail, describe).is_err());
+    }
+}
@@ -1,26 +1,157 @@
 mod actors;
+mod archive;
 mod auth;
+mod automation;
+mod baggage;
+mod billing;
+mod bundle;
 mod capability;
+mod clock;
+mod compression;
+mod contracts;
 mod control_interface;
+mod denylist;
 mod dispatch;
 mod errors;
 mod generated;
 mod hlreg;
+mod hooks;
 mod host;
 mod host_controller;
+mod idempotency;
+mod inboxes;
+mod labels;
+mod lattice_auth;
+mod lattice_state;
+mod limits;
+mod links;
+mod locks;
+mod log_levels;
+mod loopback;
 mod manifest;
 mod messagebus;
+mod metrics;
 mod middleware;
 mod oci;
+mod offload;
+mod partitions;
+mod permissions;
+mod plugins;
+mod pool;
+mod preload;
+mod replies;
+mod roster;
+mod sampling;
+mod self_update;
+mod shaping;
+mod shared_connection;
+mod shutdown;
+mod snapshots;
+mod strict;
+mod symbols;
+mod system_actor;
+mod threading;
+mod topology;
+mod wasm_features;
+mod watchdog;
 
 #[macro_use]
 extern crate log;
 
+// Lets code generated by the provider macros name this crate from inside it
+extern crate self as wasmcloud_host;
+
+pub use crate::control_interface::cloudevents::{
+    CloudEvent, EventFormat, EVENT_SCHEMA_VERSION, EVENT_TYPE_PREFIX,
+};
 pub use crate::control_interface::events::{ControlEvent, EventHeader, PublishedEvent};
+pub use crate::control_interface::extensions::{
+    ExtensionMessage, ExtensionReply, LatticeExtension, EXTENSION_MESSAGE_TTL,
+};
+pub use ::control_interface::tokens::TokenScope;
+pub use ::control_interface::{
+    ActorCall, ActorDependencies, DependencyGraph, HostCapabilities, LinkStatistics,
+    SubscriptionStatistics,
+};
+pub use archive::{ArchiveStore, ArchivedEntity, ArchivedLink, FileArchiveStore};
+pub use baggage::{current_baggage, with_baggage, MAX_BAGGAGE_BYTES, MAX_BAGGAGE_ITEMS};
+pub use billing::{BillingSink, CostRecord, FileBillingSink, NatsBillingSink};
+pub use bundle::{OfflineBundle, BUNDLE_INDEX};
+pub use capability::discovery::DISCOVERY_PUBLIC_KEY;
+pub use capability::extras::{TAG_PREFIX_CLOCK, TAG_PREFIX_SEED};
+pub use capability::grpc::{
+    GrpcRequest, GrpcResponse, GRPC_PUBLIC_KEY, GRPC_SERVICES, OP_HANDLE_GRPC_REQUEST,
+};
+pub use capability::ingress::{
+    ROUTE_COMPRESS, ROUTE_CORS_HEADERS, ROUTE_CORS_MAX_AGE, ROUTE_CORS_METHODS, ROUTE_CORS_ORIGINS,
+    ROUTE_TLS_CERT, ROUTE_TLS_KEY,
+};
+pub use capability::keyvalue::KEYVALUE_PUBLIC_KEY;
 pub use capability::native::NativeCapability;
-pub use dispatch::{Invocation, InvocationResponse, WasccEntity};
+pub use capability::router::{
+    ROUTER_PUBLIC_KEY, ROUTE_HOST, ROUTE_PATH_PREFIX, ROUTE_STRIP_PREFIX, ROUTE_WEBSOCKET,
+};
+pub use capability::sdk::{CallContext, Capability, CapabilityBuilder, NativeProvider};
+pub use capability::static_files::{BLOB_HEADER, ROUTE_BLOB_DIR};
+pub use capability::websocket::{
+    WebSocketClose, WebSocketMessage, WebSocketOpen, WebSocketPing, WebSocketReply,
+    OP_WEBSOCKET_CLOSE, OP_WEBSOCKET_DISCONNECT, OP_WEBSOCKET_MESSAGE, OP_WEBSOCKET_OPEN,
+    OP_WEBSOCKET_PING, OP_WEBSOCKET_SEND,
+};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use compression::{ClaimsCompression, DeflateCompression, NoCompression};
+pub use contracts::{
+    KeyValueClient, MessagingClient, OP_COMPARE_AND_SWAP, OP_EXPIRE, OP_INCREMENT, OP_SCAN, OP_TTL,
+};
+pub use dispatch::{
+    Invocation, InvocationResponse, PayloadReference, WasccEntity, HOST_NAMESPACE, OP_DEFER_REPLY,
+    OP_GET_BAGGAGE, OP_GET_HOST_METADATA, OP_GET_LOG_LEVEL, OP_PUSH, OP_PUSH_SUBSCRIBE,
+    OP_PUSH_UNSUBSCRIBE, OP_REPLY, OP_SET_BAGGAGE, OP_WRITE_LOG,
+};
+pub use hooks::{ActorAdmission, BootstrapHook, PreStartHook, ProviderAdmission, UpgradeHook};
 pub use host::{Host, HostBuilder};
+pub use host_controller::{LABEL_REGION, LABEL_ZONE};
+pub use labels::{LABEL_HOST_ID, LABEL_ISSUER, LABEL_NAMESPACE};
+pub use lattice_auth::{inbox_prefix, lattice_subjects, LatticeCredentials};
+pub use lattice_state::{ConflictResolution, LatticeSnapshot, LatticeState};
+pub use limits::RESPONSE_TOO_LARGE;
+pub use links::{
+    LinkDefinition, LinkDefinitionBuilder, LINK_VALUE_FAILOVER, LINK_VALUE_FAILOVER_IDEMPOTENT,
+    LINK_VALUE_LEASE_EXPIRES, LINK_VALUE_PORT, LINK_VALUE_URL,
+};
+pub use locks::LatticeLock;
 pub use manifest::HostManifest;
+pub use messagebus::defaults::CapabilityBundle;
+pub use messagebus::fanout::{FanoutReply, FanoutStrategy, FanoutTarget};
+pub use messagebus::history::{CacheChange, CacheChangeKind, CacheHistoryFilter};
+pub use messagebus::mirror::{MirrorStats, BAGGAGE_MIRRORED};
+pub use messagebus::ordered::TAG_ORDERED;
+pub use messagebus::push::{PushAck, PushRequest, PushSubscribeRequest, PushUnsubscribeRequest};
+pub use messagebus::{OP_QUIESCE, OP_RESUME};
+pub use metrics::ActorSlo;
+pub use offload::{
+    FilesystemPayloadStore, HttpPayloadStore, PayloadStore, DEFAULT_LATTICE_MAX_PAYLOAD,
+};
+pub use partitions::Partition;
+pub use permissions::{NatsPermissions, SubjectPermissions};
+#[doc(hidden)]
+pub use plugins::{plugin_build_info, PluginBuildInfo};
+pub use plugins::{HostPlugin, HOST_PLUGIN_API_VERSION};
+pub use pool::{LinkPool, PoolStats};
+pub use replies::BAGGAGE_DEADLINE;
+pub use roster::RosterMember;
+pub use sampling::{TraceSampling, BAGGAGE_SAMPLED};
+pub use shaping::ShapingRule;
+pub use shared_connection::SharedConnection;
+pub use shutdown::{FlushStatus, ShutdownReport, StoppedProvider};
+pub use snapshots::{load_snapshot, ExecutionSnapshot, SnapshotRedactor, DEFAULT_MAX_SNAPSHOTS};
+pub use system_actor::{OP_HANDLE_LATTICE_EVENT, OP_HOST_STARTED, OP_HOST_STOPPING};
+pub use threading::{ThreadModel, TAG_PREFIX_THREADS};
+pub use topology::{LatticeTopology, NodeKind, TopologyEdge, TopologyHost, TopologyNode};
+pub use wascc_codec::http::{Request as HttpRequest, Response as HttpResponse};
+pub use wasm_features::WasmFeatures;
+pub use wasmcloud_host_macros::capability;
+pub use watchdog::{Subsystem, WatchdogConfig};
 
 pub type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error + Send + Sync>>;
 pub type Actor = actors::WasccActor;
@@ -0,0 +1,275 @@
+// A scheduler that places work across the lattice by auction will happily keep placing it on a
+// host that answers every auction, which is more than a small edge device can take. A host can
+// be given caps on the number of actors and capability providers it runs, and on the total
+// linear memory of its actors. Once a cap is reached, the host stops answering the matching
+// auctions (so the scheduler places the work elsewhere) and refuses start commands, reporting
+// them the same way as starts rejected by a pre-start hook. An actor's memory is counted as the
+// initial size of the linear memories its module declares or imports, since that is what it
+// takes as soon as it starts. Providers the host starts for itself don't count toward its cap.
+//
+// A host can also cap the size of the responses its actors and providers give. A buggy actor
+// returning a huge body would otherwise have the host hold and serialize all of it, only for the
+// reply to be refused by the lattice for being larger than its maximum message size. Output over
+// the cap is failed as soon as it has been copied out of the actor's memory or returned by the
+// provider, so that it isn't carried any further, and responses are checked again before they're
+// handed to the caller, both for calls made within the host and for calls coming in over the
+// lattice. Responses over the cap are replaced with a `ResponseTooLarge` error, flagged as too
+// large, and counted in the statistics of the link they were given on.
+
+use crate::{Invocation, InvocationResponse, WasccEntity};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use wasmparser::{ImportSectionEntryType, MemoryType, Parser, Payload};
+
+// The response size cap of each host that has one, by host ID
+static MAX_RESPONSE_SIZES: Lazy<RwLock<HashMap<String, usize>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// The start of the error given in place of a response larger than the host allows (see
+/// `HostBuilder::with_max_response_size`)
+pub const RESPONSE_TOO_LARGE: &str = "ResponseTooLarge";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct HostLimits {
+    pub max_actors: Option<usize>,
+    pub max_providers: Option<usize>,
+    /// In bytes
+    pub max_guest_memory: Option<u64>,
+    /// In bytes
+    pub max_response_size: Option<usize>,
+}
+
+impl HostLimits {
+    /// Whether an actor of unknown size could still be started, given the memory of the actors
+    /// already running
+    pub fn has_room_for_actor(&self, actor_memory: &HashMap<String, u64>) -> bool {
+        self.max_actors.map_or(true, |max| actor_memory.len() < max)
+            && self
+                .max_guest_memory
+                .map_or(true, |max| actor_memory.values().sum::<u64>() < max)
+    }
+
+    /// Checks that an actor taking the given memory can be started alongside those already
+    /// running
+    pub fn admit_actor(
+        &self,
+        actor_memory: &HashMap<String, u64>,
+        memory: u64,
+    ) -> std::result::Result<(), String> {
+        if let Some(max) = self.max_actors {
+            if actor_memory.len() >= max {
+                return Err(format!(
+                    "Host is already running its maximum of {} actors",
+                    max
+                ));
+            }
+        }
+        if let Some(max) = self.max_guest_memory {
+            let in_use: u64 = actor_memory.values().sum();
+            if in_use + memory > max {
+                return Err(format!(
+                    "Actor needs {} bytes of memory, but only {} of the host's {} remain",
+                    memory,
+                    max.saturating_sub(in_use),
+                    max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that another provider can be started alongside the given number already running
+    pub fn admit_provider(&self, running: usize) -> std::result::Result<(), String> {
+        match self.max_providers {
+            Some(max) if running >= max => Err(format!(
+                "Host is already running its maximum of {} providers",
+                max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn set_max_response_size(host_id: &str, max: Option<usize>) {
+    match max {
+        Some(max) => MAX_RESPONSE_SIZES.write().insert(host_id.to_string(), max),
+        None => MAX_RESPONSE_SIZES.write().remove(host_id),
+    };
+}
+
+pub(crate) fn clear(host_id: &str) {
+    MAX_RESPONSE_SIZES.write().remove(host_id);
+}
+
+/// Fails the output of an actor or provider, as soon as the host has it, if it is larger than
+/// the host's cap
+pub(crate) fn cap_output(
+    host_id: &str,
+    inv: &Invocation,
+    output: Vec<u8>,
+) -> std::result::Result<Vec<u8>, InvocationResponse> {
+    let max = MAX_RESPONSE_SIZES.read().get(host_id).cloned();
+    match max {
+        Some(max) if output.len() > max => Err(oversized(
+            max,
+            &inv.target,
+            inv.id.to_string(),
+            output.len(),
+        )),
+        _ => Ok(output),
+    }
+}
+
+/// Fails a response from the target that is larger than the given cap
+pub(crate) fn cap_response(
+    max: Option<usize>,
+    target: &WasccEntity,
+    ir: InvocationResponse,
+) -> InvocationResponse {
+    match max {
+        Some(max) if ir.msg.len() > max => oversized(max, target, ir.invocation_id, ir.msg.len()),
+        _ => ir,
+    }
+}
+
+fn oversized(
+    max: usize,
+    target: &WasccEntity,
+    invocation_id: String,
+    len: usize,
+) -> InvocationResponse {
+    warn!(
+        "Response of {} bytes from {} to invocation {} exceeds the maximum of {} bytes",
+        len,
+        target.url(),
+        invocation_id,
+        max
+    );
+    InvocationResponse {
+        error: Some(format!(
+            "{}: response of {} bytes is larger than the maximum of {} bytes",
+            RESPONSE_TOO_LARGE, len, max
+        )),
+        msg: Vec::new(),
+        invocation_id,
+        too_large: true,
+    }
+}
+
+/// The initial size, in bytes, of the linear memories that a module declares or imports
+pub(crate) fn guest_memory(bytes: &[u8]) -> u64 {
+    let mut pages = 0;
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload {
+            Ok(Payload::MemorySection(reader)) => {
+                for mem in reader.into_iter().flatten() {
+                    pages += initial_pages(&mem);
+                }
+            }
+            Ok(Payload::ImportSection(reader)) => {
+                for import in reader.into_iter().flatten() {
+                    if let ImportSectionEntryType::Memory(mem) = import.ty {
+                        pages += initial_pages(&mem);
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    pages * WASM_PAGE_SIZE
+}
+
+fn initial_pages(mem: &MemoryType) -> u64 {
+    match mem {
+        MemoryType::M32 { limits, .. } => limits.initial as u64,
+        MemoryType::M64 { limits, .. } => limits.initial,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cap_output, cap_response, clear, guest_memory, set_max_response_size, HostLimits};
+    use crate::{Invocation, InvocationResponse, WasccEntity};
+    use std::collections::HashMap;
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn caps_are_enforced() {
+        // (module (memory 2))
+        let module = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x05, 0x03, 0x01, 0x00, 0x02,
+        ];
+        assert_eq!(2 * 64 * 1024, guest_memory(&module));
+
+        let limits = HostLimits {
+            max_actors: Some(2),
+            max_providers: Some(1),
+            max_guest_memory: Some(4 * 64 * 1024),
+            max_response_size: None,
+        };
+        let mut running = HashMap::new();
+        assert!(limits.admit_actor(&running, guest_memory(&module)).is_ok());
+        running.insert("Ma".to_string(), 3 * 64 * 1024);
+        assert!(limits.has_room_for_actor(&running));
+        assert!(limits.admit_actor(&running, guest_memory(&module)).is_err());
+        running.insert("Mb".to_string(), 0);
+        assert!(!limits.has_room_for_actor(&running));
+        assert!(limits.admit_actor(&running, 0).is_err());
+
+        assert!(limits.admit_provider(0).is_ok());
+        assert!(limits.admit_provider(1).is_err());
+        assert!(HostLimits::default().admit_provider(100).is_ok());
+    }
+
+    #[test]
+    fn oversized_responses_are_failed() {
+        let target = WasccEntity::Actor("Mactor".to_string());
+        let ir = |size| InvocationResponse {
+            msg: vec![0; size],
+            error: None,
+            invocation_id: "inv".to_string(),
+            too_large: false,
+        };
+        assert_eq!(ir(10), cap_response(None, &target, ir(10)));
+        assert_eq!(ir(10), cap_response(Some(10), &target, ir(10)));
+        let failed = cap_response(Some(10), &target, ir(11));
+        assert!(failed.too_large);
+        assert!(failed.msg.is_empty());
+        assert_eq!("inv", failed.invocation_id);
+        // An actor's own error that happens to mention the cap isn't taken for it
+        let mut lookalike = ir(0);
+        lookalike.error = Some(super::RESPONSE_TOO_LARGE.to_string());
+        assert!(!cap_response(Some(10), &target, lookalike).too_large);
+    }
+
+    #[test]
+    fn oversized_output_is_failed_where_it_is_produced() {
+        let kp = KeyPair::new_server();
+        let inv = Invocation::new(
+            &kp,
+            WasccEntity::Actor("system".to_string()),
+            WasccEntity::Actor("Mactor".to_string()),
+            "HandleRequest",
+            vec![],
+        );
+        let host_id = kp.public_key();
+        assert_eq!(
+            vec![0; 11],
+            cap_output(&host_id, &inv, vec![0; 11]).unwrap()
+        );
+        set_max_response_size(&host_id, Some(10));
+        assert_eq!(
+            vec![0; 10],
+            cap_output(&host_id, &inv, vec![0; 10]).unwrap()
+        );
+        let failed = cap_output(&host_id, &inv, vec![0; 11]).unwrap_err();
+        assert!(failed.too_large);
+        assert_eq!(inv.id, failed.invocation_id);
+        clear(&host_id);
+        assert!(cap_output(&host_id, &inv, vec![0; 11]).is_ok());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::Result;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LINK_VALUE_PORT: &str = "PORT";
+pub const LINK_VALUE_URL: &str = "URL";
+/// A comma-separated list of the public keys of providers that take over a link, in order of
+/// preference, when its primary provider can't be reached
+pub const LINK_VALUE_FAILOVER: &str = "FAILOVER";
+/// A comma-separated list of the operations on a link that are safe to run twice. When a call
+/// to one of these times out, it is retried on the link's failover providers; other calls are
+/// only retried when they are known not to have reached the provider
+pub const LINK_VALUE_FAILOVER_IDEMPOTENT: &str = "FAILOVER_IDEMPOTENT";
+/// When a leased link expires, in seconds since the epoch. Every host removes the link once its
+/// lease runs out, unless the link's creator renews it first by advertising it again with a
+/// later expiry
+pub const LINK_VALUE_LEASE_EXPIRES: &str = "LEASE_EXPIRES";
+
+const DEFAULT_LINK_NAME: &str = "default";
+const PUBLIC_KEY_LENGTH: usize = 56;
+
+/// A validated link definition, suitable for submission to a host via
+/// [set_link_definition](crate::Host::set_link_definition). Instances of this struct
+/// can only be created through a [LinkDefinitionBuilder]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkDefinition {
+    actor: String,
+    contract_id: String,
+    link_name: String,
+    provider_id: String,
+    values: HashMap<String, String>,
+}
+
+impl LinkDefinition {
+    /// The public key of the actor in this link
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    /// The capability contract ID (e.g. `wascc:keyvalue`) of this link
+    pub fn contract_id(&self) -> &str {
+        &self.contract_id
+    }
+
+    /// The link name, which will be `default` unless otherwise specified
+    pub fn link_name(&self) -> &str {
+        &self.link_name
+    }
+
+    /// The public key of the capability provider in this link
+    pub fn provider_id(&self) -> &str {
+        &self.provider_id
+    }
+
+    /// The configuration values that will be delivered to the provider
+    pub fn values(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+}
+
+impl From<LinkDefinition> for control_interface::LinkDefinition {
+    fn from(ld: LinkDefinition) -> control_interface::LinkDefinition {
+        control_interface::LinkDefinition {
+            actor_id: ld.actor,
+            provider_id: ld.provider_id,
+            link_name: ld.link_name,
+            contract_id: ld.contract_id,
+            values: ld.values,
+        }
+    }
+}
+
+impl From<crate::messagebus::LinkDefinition> for LinkDefinition {
+    fn from(ld: crate::messagebus::LinkDefinition) -> LinkDefinition {
+        LinkDefinition {
+            actor: ld.actor_id,
+            contract_id: ld.contract_id,
+            link_name: ld.link_name,
+            provider_id: ld.provider_id,
+            values: ld.values,
+        }
+    }
+}
+
+/// A builder for link definitions that validates the link before it is submitted, rather
+/// than relying on callers to assemble the right keys and configuration values by hand
+#[derive(Debug, Clone, Default)]
+pub struct LinkDefinitionBuilder {
+    actor: Option<String>,
+    contract_id: Option<String>,
+    link_name: Option<String>,
+    provider_id: Option<String>,
+    values: HashMap<String, String>,
+}
+
+impl LinkDefinitionBuilder {
+    pub fn new() -> LinkDefinitionBuilder {
+        LinkDefinitionBuilder::default()
+    }
+
+    /// The public key of the actor to link
+    pub fn actor(self, actor: &str) -> LinkDefinitionBuilder {
+        LinkDefinitionBuilder {
+            actor: Some(actor.to_string()),
+            ..self
+        }
+    }
+
+    /// Selects the actor to link from an actor instance rather than its public key
+    pub fn actor_from(self, actor: &crate::Actor) -> LinkDefinitionBuilder {
+        self.actor(&actor.public_key())
+    }
+
+    pub fn contract_id(self, contract_id: &str) -> LinkDefinitionBuilder {
+        LinkDefinitionBuilder {
+            contract_id: Some(contract_id.to_string()),
+            ..self
+        }
+    }
+
+    pub fn link_name(self, link_name: &str) -> LinkDefinitionBuilder {
+        LinkDefinitionBuilder {
+            link_name: Some(link_name.to_string()),
+            ..self
+        }
+    }
+
+    /// The public key of the capability provider to link
+    pub fn provider_id(self, provider_id: &str) -> LinkDefinitionBuilder {
+        LinkDefinitionBuilder {
+            provider_id: Some(provider_id.to_string()),
+            ..self
+        }
+    }
+
+    /// Adds a single, arbitrary configuration value to the link
+    pub fn value(self, key: &str, value: &str) -> LinkDefinitionBuilder {
+        let mut values = self.values.clone();
+        values.insert(key.to_string(), value.to_string());
+        LinkDefinitionBuilder { values, ..self }
+    }
+
+    /// Adds all of the given configuration values to the link
+    pub fn values(self, values: HashMap<String, String>) -> LinkDefinitionBuilder {
+        let mut vals = self.values.clone();
+        vals.extend(values);
+        LinkDefinitionBuilder {
+            values: vals,
+            ..self
+        }
+    }
+
+    /// Sets the `PORT` configuration value, used by providers such as the HTTP server
+    pub fn port(self, port: u16) -> LinkDefinitionBuilder {
+        self.value(LINK_VALUE_PORT, &port.to_string())
+    }
+
+    /// Sets the `URL` configuration value, used by providers such as the key-value store
+    pub fn url(self, url: &str) -> LinkDefinitionBuilder {
+        self.value(LINK_VALUE_URL, url)
+    }
+
+    /// Adds a provider that invocations on this link fail over to when the primary provider
+    /// (and any failover providers added before this one) can't be reached. The failover
+    /// provider must implement the same contract
+    pub fn failover_provider(self, provider_id: &str) -> LinkDefinitionBuilder {
+        let failover = match self.values.get(LINK_VALUE_FAILOVER) {
+            Some(f) if !f.is_empty() => format!("{},{}", f, provider_id),
+            _ => provider_id.to_string(),
+        };
+        self.value(LINK_VALUE_FAILOVER, &failover)
+    }
+
+    /// Marks an operation on this link as safe to run twice, so that a call to it that times
+    /// out is retried on the failover providers (see `LINK_VALUE_FAILOVER_IDEMPOTENT`)
+    pub fn idempotent_operation(self, operation: &str) -> LinkDefinitionBuilder {
+        let ops = match self.values.get(LINK_VALUE_FAILOVER_IDEMPOTENT) {
+            Some(o) if !o.is_empty() => format!("{},{}", o, operation),
+            _ => operation.to_string(),
+        };
+        self.value(LINK_VALUE_FAILOVER_IDEMPOTENT, &ops)
+    }
+
+    /// Leases the link for the given time, after which it is removed from every host unless
+    /// renewed (see [renew_link](crate::Host::renew_link))
+    pub fn lease(self, ttl: Duration) -> LinkDefinitionBuilder {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.value(
+            LINK_VALUE_LEASE_EXPIRES,
+            &lease_expiry_after(now, ttl).to_string(),
+        )
+    }
+
+    /// Validates the link definition, returning an error describing the first problem found
+    pub fn build(self) -> Result<LinkDefinition> {
+        let actor = self.actor.ok_or("A link definition requires an actor")?;
+        if !is_public_key(&actor, 'M') {
+            return Err(format!("'{}' is not a valid actor public key", actor).into());
+        }
+        let provider_id = self
+            .provider_id
+            .ok_or("A link definition requires a provider ID")?;
+        if !is_public_key(&provider_id, 'V') {
+            return Err(format!("'{}' is not a valid provider public key", provider_id).into());
+        }
+        let contract_id = self
+            .contract_id
+            .ok_or("A link definition requires a contract ID")?;
+        if contract_id.trim().is_empty() {
+            return Err("A link definition's contract ID cannot be empty".into());
+        }
+        let link_name = self.link_name.unwrap_or(DEFAULT_LINK_NAME.to_string());
+        if link_name.trim().is_empty() {
+            return Err("A link definition's link name cannot be empty".into());
+        }
+        if let Some(k) = self.values.keys().find(|k| k.trim().is_empty()) {
+            return Err(format!("Invalid configuration value key '{}'", k).into());
+        }
+        if self.values.contains_key(LINK_VALUE_LEASE_EXPIRES)
+            && lease_expiry(&self.values).is_none()
+        {
+            return Err("A link's lease expiry must be a number of seconds since the epoch".into());
+        }
+        for failover in failover_providers(&self.values) {
+            if !is_public_key(&failover, 'V') {
+                return Err(
+                    format!("'{}' is not a valid failover provider public key", failover).into(),
+                );
+            }
+            if failover == provider_id {
+                return Err("A link's primary provider can't also be its failover provider".into());
+            }
+        }
+
+        Ok(LinkDefinition {
+            actor,
+            contract_id,
+            link_name,
+            provider_id,
+            values: self.values,
+        })
+    }
+}
+
+/// The failover providers of a link, in order of preference
+pub(crate) fn failover_providers(values: &HashMap<String, String>) -> Vec<String> {
+    values
+        .get(LINK_VALUE_FAILOVER)
+        .map(|f| {
+            f.split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether the link's values mark the operation as safe to run twice
+pub(crate) fn is_idempotent(values: &HashMap<String, String>, operation: &str) -> bool {
+    values
+        .get(LINK_VALUE_FAILOVER_IDEMPOTENT)
+        .map_or(false, |ops| ops.split(',').any(|o| o.trim() == operation))
+}
+
+/// When a leased link expires, in seconds since the epoch, or `None` if the link isn't leased
+pub(crate) fn lease_expiry(values: &HashMap<String, String>) -> Option<u64> {
+    values
+        .get(LINK_VALUE_LEASE_EXPIRES)
+        .and_then(|e| e.trim().parse().ok())
+}
+
+/// The lease expiry of a link leased at the given time (in seconds since the epoch) for the given
+/// time
+pub(crate) fn lease_expiry_after(now: u64, ttl: Duration) -> u64 {
+    now + ttl.as_secs()
+}
+
+fn is_public_key(key: &str, prefix: char) -> bool {
+    key.len() == PUBLIC_KEY_LENGTH
+        && key.starts_with(prefix)
+        && key.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{failover_providers, is_idempotent, lease_expiry, LinkDefinitionBuilder};
+    use std::time::Duration;
+
+    const ACTOR: &str = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    const PROVIDER: &str = "VDHPKGFKDI34Y4RN4PWWZHRYZ6373HYRSNNEM4UTDLLOGO5B37TSVREP";
+    const FAILOVER: &str = "VAHNM37G3ARWKPMMIRSNC3JAFIY6PAZBNGWAPNVACSDFGNZLBWHEDPSE";
+
+    #[test]
+    fn builds_valid_link() {
+        let ld = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .contract_id("wascc:http_server")
+            .port(8080)
+            .build()
+            .unwrap();
+        assert_eq!(ld.link_name(), "default");
+        assert_eq!(ld.values()["PORT"], "8080");
+
+        let wire: control_interface::LinkDefinition = ld.into();
+        assert_eq!(wire.actor_id, ACTOR);
+        assert_eq!(wire.provider_id, PROVIDER);
+    }
+
+    #[test]
+    fn rejects_swapped_keys() {
+        let res = LinkDefinitionBuilder::new()
+            .actor(PROVIDER)
+            .provider_id(ACTOR)
+            .contract_id("wascc:keyvalue")
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn failover_providers_are_listed_in_order() {
+        let ld = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .contract_id("wascc:keyvalue")
+            .failover_provider(FAILOVER)
+            .build()
+            .unwrap();
+        assert_eq!(vec![FAILOVER.to_string()], failover_providers(ld.values()));
+        assert!(!is_idempotent(ld.values(), "Get"));
+
+        let ld = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .contract_id("wascc:keyvalue")
+            .failover_provider(FAILOVER)
+            .idempotent_operation("Get")
+            .idempotent_operation("Contains")
+            .build()
+            .unwrap();
+        assert!(is_idempotent(ld.values(), "Contains"));
+        assert!(!is_idempotent(ld.values(), "Set"));
+
+        let res = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .contract_id("wascc:keyvalue")
+            .failover_provider(PROVIDER)
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn leased_links_carry_their_expiry() {
+        let ld = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .contract_id("wascc:keyvalue")
+            .lease(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        assert!(lease_expiry(ld.values()).is_some());
+
+        let res = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .contract_id("wascc:keyvalue")
+            .value("LEASE_EXPIRES", "tomorrow")
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn rejects_missing_contract() {
+        let res = LinkDefinitionBuilder::new()
+            .actor(ACTOR)
+            .provider_id(PROVIDER)
+            .url("redis://127.0.0.1:6379")
+            .build();
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "A link definition requires a contract ID"
+        );
+    }
+}
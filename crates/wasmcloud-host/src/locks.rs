@@ -0,0 +1,632 @@
+// Named locks shared by every host in a lattice namespace, so that things like an autoscaler or a
+// reconciler can run as a singleton without an external coordination service. Locks are leases:
+// a holder keeps a lock for its time to live and must renew it to keep it any longer.
+//
+// There is no central store. To acquire a lock, a host announces a ticket (the time of its
+// attempt, on the host's clock) for it, then probes the lattice. The host holding the lock answers
+// probes with its ticket, and so does every host with an attempt in progress, so a contender that
+// missed an announcement still hears about it. The host takes the lock unless a holder answered or
+// it heard an earlier (or, on a tie, lower) ticket from another contender. Once it has the lock it
+// probes again, and gives the lock back if another host claims to hold it with an earlier ticket.
+// Hosts without a lattice connection only coordinate with themselves.
+//
+// This is not a consensus protocol: hosts that can't reach each other (e.g. during a partition)
+// can both take the same lock. Holders should treat a lock as advisory, and should stop acting on
+// it once `is_held` turns false.
+
+use crate::hlreg::{HostLocalSystemService, Shutdown};
+use crate::Result;
+use actix::prelude::*;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// How long to gather answers to a probe. This is also how long a host listens for competing
+// tickets before taking a lock
+const LOCK_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Ticket {
+    host: String,
+    issued_ms: u64,
+}
+
+impl Ticket {
+    fn precedes(&self, other: &Ticket) -> bool {
+        (self.issued_ms, &self.host) < (other.issued_ms, &other.host)
+    }
+}
+
+/// The answer to a probe from a host that holds a lock or is trying to take it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum LockAnswer {
+    Held { ticket: Ticket, remaining_ms: u64 },
+    Pending { ticket: Ticket },
+}
+
+struct Lease {
+    token: String,
+    ticket: Ticket,
+    // When the lease runs out, as a monotonic reading of the host's clock
+    expires: Duration,
+}
+
+/// The locks held by one host, and the attempts it has in progress
+#[derive(Default)]
+struct LockTable {
+    held: HashMap<String, Lease>,
+    // For each lock this host is trying to take, its own ticket and whether it heard an earlier
+    // ticket from another host
+    pending: HashMap<String, (Ticket, bool)>,
+}
+
+impl LockTable {
+    fn holder(&self, name: &str, now: Duration) -> Option<&Lease> {
+        self.held.get(name).filter(|l| l.expires > now)
+    }
+
+    /// Starts an attempt to take a lock, unless this host holds it or is already trying to
+    fn begin(&mut self, name: &str, ticket: Ticket, now: Duration) -> bool {
+        if self.holder(name, now).is_some() || self.pending.contains_key(name) {
+            return false;
+        }
+        self.pending.insert(name.to_string(), (ticket, false));
+        true
+    }
+
+    /// What this host answers to a probe for a lock, if it holds it or is trying to take it
+    fn answer(&self, name: &str, now: Duration) -> Option<LockAnswer> {
+        if let Some(lease) = self.holder(name, now) {
+            return Some(LockAnswer::Held {
+                ticket: lease.ticket.clone(),
+                remaining_ms: (lease.expires - now).as_millis() as u64,
+            });
+        }
+        self.pending
+            .get(name)
+            .map(|(ticket, _)| LockAnswer::Pending {
+                ticket: ticket.clone(),
+            })
+    }
+
+    /// Records a ticket announced by a host (possibly this one) for a lock
+    fn contend(&mut self, name: &str, ticket: &Ticket) {
+        if let Some((mine, beaten)) = self.pending.get_mut(name) {
+            if ticket.host != mine.host && ticket.precedes(mine) {
+                *beaten = true;
+            }
+        }
+    }
+
+    /// Completes an attempt, taking the lock unless another host got in first
+    fn finish(
+        &mut self,
+        name: &str,
+        ttl: Duration,
+        held_elsewhere: bool,
+        now: Duration,
+    ) -> Option<String> {
+        let (ticket, beaten) = self.pending.remove(name)?;
+        if beaten || held_elsewhere {
+            return None;
+        }
+        let token = uuid::Uuid::new_v4().to_string();
+        self.held.insert(
+            name.to_string(),
+            Lease {
+                token: token.to_string(),
+                ticket,
+                expires: now + ttl,
+            },
+        );
+        Some(token)
+    }
+
+    /// Checks a lock this host just took against what other hosts answered to a probe, giving it
+    /// back if another host holds it with an earlier ticket. Two hosts can only both take a lock
+    /// when they missed each other's tickets, and then the later one backs off here
+    fn confirm(&mut self, name: &str, token: &str, answers: &[LockAnswer]) -> bool {
+        let mine = match self.held.get(name) {
+            Some(lease) if lease.token == token => lease.ticket.clone(),
+            _ => return false,
+        };
+        let preempted = answers.iter().any(|a| match a {
+            LockAnswer::Held { ticket, .. } => ticket.host != mine.host && ticket.precedes(&mine),
+            LockAnswer::Pending { .. } => false,
+        });
+        if preempted {
+            self.held.remove(name);
+        }
+        !preempted
+    }
+
+    fn renew(&mut self, name: &str, token: &str, ttl: Duration, now: Duration) -> bool {
+        match self.held.get_mut(name) {
+            Some(lease) if lease.token == token && lease.expires > now => {
+                lease.expires = now + ttl;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn release(&mut self, name: &str, token: &str) {
+        if self.held.get(name).map_or(false, |l| l.token == token) {
+            self.held.remove(name);
+        }
+    }
+}
+
+fn probe_subject(ns_prefix: &Option<String>, name: &str) -> String {
+    format!(
+        "{}.locks.probe.{}",
+        crate::messagebus::rpc_subscription::subject_prefix(ns_prefix),
+        name
+    )
+}
+
+fn ticket_subject(ns_prefix: &Option<String>, name: &str) -> String {
+    format!(
+        "{}.locks.ticket.{}",
+        crate::messagebus::rpc_subscription::subject_prefix(ns_prefix),
+        name
+    )
+}
+
+// Lock names become part of a subject, so they can't contain wildcards or whitespace
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.split('.').all(|t| !t.is_empty())
+        && !name.contains(|c: char| c == '*' || c == '>' || c.is_whitespace());
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid lock name '{}'", name).into())
+    }
+}
+
+// Drops the answers from hosts that a simulated partition keeps from reaching this one
+fn reachable(host_id: &str, answers: Vec<LockAnswer>) -> Vec<LockAnswer> {
+    answers
+        .into_iter()
+        .filter(|answer| {
+            let ticket = match answer {
+                LockAnswer::Held { ticket, .. } | LockAnswer::Pending { ticket } => ticket,
+            };
+            crate::partitions::reachable(host_id, Some(&ticket.host))
+        })
+        .collect()
+}
+
+// Probes for a lock, gathering every answer that arrives within the probe timeout. The window is
+// measured on the host's clock, and also closes once no answer has arrived for its full length
+async fn probe(nc: &nats::asynk::Connection, host_id: &str, subject: &str) -> Vec<LockAnswer> {
+    let sub = match crate::inboxes::request_multi(nc, host_id, subject, &[]).await {
+        Ok(sub) => sub,
+        Err(_) => return Vec::new(),
+    };
+    let mut answers = Vec::new();
+    let deadline = crate::clock::monotonic(host_id) + LOCK_PROBE_TIMEOUT;
+    while let Some(remaining) = deadline.checked_sub(crate::clock::monotonic(host_id)) {
+        match actix_rt::time::timeout(remaining, sub.next()).await {
+            Ok(Some(m)) => {
+                if let Ok(answer) = serde_json::from_slice(&m.data) {
+                    answers.push(answer);
+                }
+            }
+            _ => break,
+        }
+    }
+    answers
+}
+
+/// A lock held by this host. The lock is lost once its time to live passes without a renewal
+pub struct LatticeLock {
+    host_id: String,
+    name: String,
+    token: String,
+    expires: Duration,
+}
+
+impl LatticeLock {
+    /// The name of the lock
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the lock's lease is still current
+    pub fn is_held(&self) -> bool {
+        self.expires > crate::clock::monotonic(&self.host_id)
+    }
+
+    /// Extends the lock's lease to the given time to live from now. Fails if the lease already
+    /// expired, since another host may have taken the lock since
+    pub async fn renew(&mut self, ttl: Duration) -> Result<()> {
+        let ls = LockService::from_hostlocal_registry(&self.host_id);
+        let renewed = ls
+            .send(RenewLock {
+                name: self.name.to_string(),
+                token: self.token.to_string(),
+                ttl,
+            })
+            .await?;
+        if renewed {
+            self.expires = crate::clock::monotonic(&self.host_id) + ttl;
+            Ok(())
+        } else {
+            Err(format!("Lock '{}' has expired", self.name).into())
+        }
+    }
+
+    /// Gives up the lock, letting other hosts take it straight away
+    pub async fn release(self) {
+        let ls = LockService::from_hostlocal_registry(&self.host_id);
+        let _ = ls
+            .send(ReleaseLock {
+                name: self.name.to_string(),
+                token: self.token.to_string(),
+            })
+            .await;
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Initialize {
+    pub nc: Option<nats::asynk::Connection>,
+    pub namespace: Option<String>,
+    pub host_id: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Option<LatticeLock>>")]
+pub(crate) struct AcquireLock {
+    pub name: String,
+    pub ttl: Duration,
+}
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+struct RenewLock {
+    name: String,
+    token: String,
+    ttl: Duration,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ReleaseLock {
+    name: String,
+    token: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct LockProbe {
+    name: String,
+    reply: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct LockTicket {
+    name: String,
+    ticket: Option<Ticket>,
+}
+
+#[derive(Default)]
+pub(crate) struct LockService {
+    nc: Option<nats::asynk::Connection>,
+    namespace: Option<String>,
+    host_id: String,
+    table: LockTable,
+}
+
+impl Actor for LockService {
+    type Context = Context<Self>;
+}
+
+impl Supervised for LockService {}
+
+impl SystemService for LockService {}
+
+impl HostLocalSystemService for LockService {}
+
+impl Handler<Initialize> for LockService {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: Initialize, _ctx: &mut Context<Self>) -> Self::Result {
+        self.nc = msg.nc;
+        self.namespace = msg.namespace;
+        self.host_id = msg.host_id;
+        let nc = match self.nc.clone() {
+            Some(nc) => nc,
+            None => return Box::pin(async {}.into_actor(self)),
+        };
+        let probes = probe_subject(&self.namespace, "");
+        let tickets = ticket_subject(&self.namespace, "");
+        Box::pin(
+            async move {
+                (
+                    nc.subscribe(&format!("{}>", probes)).await,
+                    nc.subscribe(&format!("{}>", tickets)).await,
+                )
+            }
+            .into_actor(self)
+            .map(move |(psub, tsub), _act, ctx| {
+                if let Ok(psub) = psub {
+                    ctx.add_message_stream(psub.map(move |m| LockProbe {
+                        name: m.subject.trim_start_matches(&probes).to_string(),
+                        reply: m.reply.clone(),
+                    }));
+                }
+                if let Ok(tsub) = tsub {
+                    ctx.add_message_stream(tsub.map(move |m| LockTicket {
+                        name: m.subject.trim_start_matches(&tickets).to_string(),
+                        ticket: serde_json::from_slice(&m.data).ok(),
+                    }));
+                }
+            }),
+        )
+    }
+}
+
+impl Handler<AcquireLock> for LockService {
+    type Result = ResponseActFuture<Self, Result<Option<LatticeLock>>>;
+
+    fn handle(&mut self, msg: AcquireLock, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Err(e) = validate_name(&msg.name) {
+            return Box::pin(async move { Err(e) }.into_actor(self));
+        }
+        let ticket = Ticket {
+            host: self.host_id.to_string(),
+            issued_ms: crate::clock::now_millis(&self.host_id),
+        };
+        let now = crate::clock::monotonic(&self.host_id);
+        if !self.table.begin(&msg.name, ticket.clone(), now) {
+            return Box::pin(async { Ok(None) }.into_actor(self));
+        }
+        let nc = self.nc.clone();
+        let probe_nc = self.nc.clone();
+        let host_id = self.host_id.to_string();
+        let recheck_host = host_id.to_string();
+        let subject = probe_subject(&self.namespace, &msg.name);
+        let recheck = subject.to_string();
+        let announce = ticket_subject(&self.namespace, &msg.name);
+        let name = msg.name;
+        let ttl = msg.ttl;
+        Box::pin(
+            async move {
+                let nc = match probe_nc {
+                    Some(nc) => nc,
+                    None => return Vec::new(),
+                };
+                let _ = nc
+                    .publish(&announce, serde_json::to_vec(&ticket).unwrap())
+                    .await;
+                probe(&nc, &host_id, &subject).await
+            }
+            .into_actor(self)
+            .map({
+                let name = name.to_string();
+                move |answers, act, _ctx| {
+                    let mut held_elsewhere = false;
+                    for answer in reachable(&act.host_id, answers) {
+                        match answer {
+                            LockAnswer::Held {
+                                ticket,
+                                remaining_ms,
+                            } if ticket.host != act.host_id => {
+                                debug!(
+                                    "Lock '{}' is held by host {} for another {}ms",
+                                    name, ticket.host, remaining_ms
+                                );
+                                held_elsewhere = true;
+                            }
+                            LockAnswer::Pending { ticket } => act.table.contend(&name, &ticket),
+                            _ => {}
+                        }
+                    }
+                    let now = crate::clock::monotonic(&act.host_id);
+                    act.table.finish(&name, ttl, held_elsewhere, now)
+                }
+            })
+            .then(move |token, act, _ctx| {
+                let nc = token.as_ref().and(nc);
+                async move {
+                    let answers = match nc {
+                        Some(nc) => probe(&nc, &recheck_host, &recheck).await,
+                        None => Vec::new(),
+                    };
+                    (token, answers)
+                }
+                .into_actor(act)
+            })
+            .map(move |(token, answers), act, _ctx| {
+                let token = match token {
+                    Some(token) => token,
+                    None => return Ok(None),
+                };
+                let answers = reachable(&act.host_id, answers);
+                if !act.table.confirm(&name, &token, &answers) {
+                    debug!("Gave lock '{}' back to a host with an earlier ticket", name);
+                    return Ok(None);
+                }
+                Ok(Some(LatticeLock {
+                    host_id: act.host_id.to_string(),
+                    name,
+                    token,
+                    expires: crate::clock::monotonic(&act.host_id) + ttl,
+                }))
+            }),
+        )
+    }
+}
+
+impl Handler<RenewLock> for LockService {
+    type Result = bool;
+
+    fn handle(&mut self, msg: RenewLock, _ctx: &mut Context<Self>) -> Self::Result {
+        let now = crate::clock::monotonic(&self.host_id);
+        self.table.renew(&msg.name, &msg.token, msg.ttl, now)
+    }
+}
+
+impl Handler<ReleaseLock> for LockService {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReleaseLock, _ctx: &mut Context<Self>) {
+        self.table.release(&msg.name, &msg.token);
+    }
+}
+
+impl Handler<LockProbe> for LockService {
+    type Result = ();
+
+    fn handle(&mut self, msg: LockProbe, _ctx: &mut Context<Self>) {
+        let (nc, reply) = match (self.nc.clone(), msg.reply) {
+            (Some(nc), Some(reply)) => (nc, reply),
+            _ => return,
+        };
+        // Only the holder and other contenders answer, so a probe without answers means the lock
+        // is free
+        let now = crate::clock::monotonic(&self.host_id);
+        if let Some(answer) = self.table.answer(&msg.name, now) {
+            let payload = serde_json::to_vec(&answer).unwrap();
+            actix::spawn(async move {
+                let _ = nc.publish(&reply, payload).await;
+            });
+        }
+    }
+}
+
+impl Handler<LockTicket> for LockService {
+    type Result = ();
+
+    fn handle(&mut self, msg: LockTicket, _ctx: &mut Context<Self>) {
+        match msg.ticket {
+            Some(ref ticket) if crate::partitions::reachable(&self.host_id, Some(&ticket.host)) => {
+                self.table.contend(&msg.name, ticket)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Handler<Shutdown> for LockService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) {
+        self.table.held.clear();
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate_name, LockAnswer, LockTable, Ticket};
+    use std::time::Duration;
+
+    const NOW: Duration = Duration::from_secs(1000);
+
+    fn ticket(host: &str, issued_ms: u64) -> Ticket {
+        Ticket {
+            host: host.to_string(),
+            issued_ms,
+        }
+    }
+
+    #[test]
+    fn earliest_ticket_takes_the_lock() {
+        let mut a = LockTable::default();
+        assert!(a.begin("autoscaler", ticket("NA", 100), NOW));
+        assert!(!a.begin("autoscaler", ticket("NA", 101), NOW));
+        // A later ticket from another host, and this host's own ticket, don't beat it
+        a.contend("autoscaler", &ticket("NB", 200));
+        a.contend("autoscaler", &ticket("NA", 100));
+        let token = a
+            .finish("autoscaler", Duration::from_secs(30), false, NOW)
+            .unwrap();
+        assert!(a.holder("autoscaler", NOW).is_some());
+        assert!(!a.begin("autoscaler", ticket("NA", 300), NOW));
+
+        let mut b = LockTable::default();
+        assert!(b.begin("autoscaler", ticket("NB", 200), NOW));
+        b.contend("autoscaler", &ticket("NA", 100));
+        assert!(b
+            .finish("autoscaler", Duration::from_secs(30), false, NOW)
+            .is_none());
+        assert!(b.begin("autoscaler", ticket("NB", 300), NOW));
+        assert!(b
+            .finish("autoscaler", Duration::from_secs(30), true, NOW)
+            .is_none());
+
+        assert!(!a.renew("autoscaler", "wrong", Duration::from_secs(30), NOW));
+        assert!(a.renew("autoscaler", &token, Duration::from_secs(30), NOW));
+        // The renewed lease runs out 30 seconds from the renewal, and can't be renewed after that
+        let later = NOW + Duration::from_secs(30);
+        assert!(a
+            .holder("autoscaler", later - Duration::from_millis(1))
+            .is_some());
+        assert!(a.holder("autoscaler", later).is_none());
+        assert!(!a.renew("autoscaler", &token, Duration::from_secs(30), later));
+        a.release("autoscaler", &token);
+        assert!(a.holder("autoscaler", NOW).is_none());
+    }
+
+    #[test]
+    fn contenders_answer_probes_with_their_ticket() {
+        let mut a = LockTable::default();
+        let mut b = LockTable::default();
+        assert!(a.answer("reconciler", NOW).is_none());
+        assert!(a.begin("reconciler", ticket("NA", 100), NOW));
+        // B announced a later ticket that A missed, but A answers B's probe while contending
+        assert!(b.begin("reconciler", ticket("NB", 200), NOW));
+        match a.answer("reconciler", NOW) {
+            Some(LockAnswer::Pending { ticket: t }) => b.contend("reconciler", &t),
+            other => panic!("Unexpected answer {:?}", other),
+        }
+        assert!(b
+            .finish("reconciler", Duration::from_secs(30), false, NOW)
+            .is_none());
+        let token = a
+            .finish("reconciler", Duration::from_secs(30), false, NOW)
+            .unwrap();
+        assert!(matches!(
+            a.answer("reconciler", NOW),
+            Some(LockAnswer::Held { ticket: t, .. }) if t == ticket("NA", 100)
+        ));
+        assert!(a.confirm("reconciler", &token, &[]));
+    }
+
+    #[test]
+    fn later_holder_gives_the_lock_back() {
+        let mut a = LockTable::default();
+        let mut b = LockTable::default();
+        assert!(a.begin("reconciler", ticket("NA", 100), NOW));
+        assert!(b.begin("reconciler", ticket("NB", 200), NOW));
+        // Neither heard the other, so both took the lock
+        let ta = a
+            .finish("reconciler", Duration::from_secs(30), false, NOW)
+            .unwrap();
+        let tb = b
+            .finish("reconciler", Duration::from_secs(30), false, NOW)
+            .unwrap();
+        let from_a = vec![a.answer("reconciler", NOW).unwrap()];
+        let from_b = vec![b.answer("reconciler", NOW).unwrap()];
+        assert!(a.confirm("reconciler", &ta, &from_b));
+        assert!(!b.confirm("reconciler", &tb, &from_a));
+        assert!(a.holder("reconciler", NOW).is_some());
+        assert!(b.holder("reconciler", NOW).is_none());
+    }
+
+    #[test]
+    fn lock_names_are_subject_tokens() {
+        assert!(validate_name("leader.reconciler").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a..b").is_err());
+        assert!(validate_name("leader.*").is_err());
+        assert!(validate_name("two words").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// Operators can turn the logging of a single actor or capability provider up or down while the
+// host is running, either through the control interface or through the host API. A target
+// without a level of its own logs at the host's level (the `log` crate's maximum level).
+//
+// The levels apply to everything the host logs on a target's behalf: the messages a guest writes
+// with the logging host call (logged with the actor's public key as the log target, so they can
+// also be filtered by the host's logger), the messages actors send over the logging contract,
+// which are dropped before they reach the logging provider, and the host's own trace of the
+// target's invocations. Guests can ask for their level so that they can skip building messages
+// that would only be thrown away.
+
+use crate::Result;
+use log::{Level, LevelFilter};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Keyed by host ID and target public key
+static LEVELS: Lazy<RwLock<HashMap<(String, String), LevelFilter>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Parses a log level, where an empty string means that the target has no level of its own
+pub(crate) fn parse_level(level: &str) -> Result<Option<LevelFilter>> {
+    if level.trim().is_empty() {
+        return Ok(None);
+    }
+    LevelFilter::from_str(level.trim())
+        .map(Some)
+        .map_err(|_| format!("Unknown log level '{}'", level).into())
+}
+
+/// Sets (or, given no level, clears) the log level of the target on the host. Raising a target
+/// above the host's level raises the `log` crate's maximum level so that the target's messages
+/// still reach the host's logger
+pub(crate) fn set_level(host_id: &str, target: &str, level: Option<LevelFilter>) {
+    let key = (host_id.to_string(), target.to_string());
+    match level {
+        Some(level) => {
+            info!("Log level for {} set to {}", target, level);
+            if level > log::max_level() {
+                log::set_max_level(level);
+            }
+            LEVELS.write().insert(key, level);
+        }
+        None => {
+            info!("Log level for {} reset to the host's level", target);
+            LEVELS.write().remove(&key);
+        }
+    }
+}
+
+/// The level at which the target logs on the host
+pub(crate) fn level_for(host_id: &str, target: &str) -> LevelFilter {
+    LEVELS
+        .read()
+        .get(&(host_id.to_string(), target.to_string()))
+        .cloned()
+        .unwrap_or_else(log::max_level)
+}
+
+/// Whether a message at the given level should be logged for the target
+pub(crate) fn enabled(host_id: &str, target: &str, level: Level) -> bool {
+    level <= level_for(host_id, target)
+}
+
+/// Forgets the levels of every target on the host
+pub(crate) fn clear(host_id: &str) {
+    LEVELS.write().retain(|(h, _), _| h != host_id);
+}
+
+/// Converts the numeric level used by the logging contract (1 for errors through 5 for traces)
+pub(crate) fn from_contract_level(level: usize) -> Level {
+    match level {
+        0 | 1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clear, enabled, parse_level, set_level};
+    use log::{Level, LevelFilter};
+
+    #[test]
+    fn levels_are_per_target_and_host() {
+        assert_eq!(None, parse_level("").unwrap());
+        assert_eq!(Some(LevelFilter::Debug), parse_level("DEBUG").unwrap());
+        assert!(parse_level("chatty").is_err());
+
+        set_level("Nhost", "Mxxx", Some(LevelFilter::Trace));
+        set_level("Nhost", "Myyy", Some(LevelFilter::Off));
+        assert!(enabled("Nhost", "Mxxx", Level::Trace));
+        assert!(!enabled("Nhost", "Myyy", Level::Error));
+        // Raising a target raised the host's level, which the other targets still log at
+        assert!(enabled("Nother", "Myyy", Level::Trace));
+
+        set_level("Nhost", "Myyy", None);
+        assert_eq!(super::level_for("Nhost", "Myyy"), log::max_level());
+        clear("Nhost");
+        assert_eq!(super::level_for("Nhost", "Mxxx"), log::max_level());
+    }
+}
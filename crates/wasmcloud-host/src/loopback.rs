@@ -0,0 +1,106 @@
+// Sending an HTTP request to an actor normally means running the HTTP server provider, binding a
+// port, and making a real request. For tests (and for embedders that just want to poke an actor),
+// the host can instead build the invocation that the HTTP server provider would have sent and
+// deliver it straight to the actor, with no socket involved.
+
+use crate::messagebus::LinkDefinition;
+use crate::Result;
+
+/// The capability contract implemented by HTTP server providers
+pub(crate) const HTTP_SERVER_CONTRACT: &str = "wascc:http_server";
+// The origin of loopback requests to actors that don't have an HTTP server link
+pub(crate) const LOOPBACK_PROVIDER: &str = "loopback";
+
+const DEFAULT_LINK_NAME: &str = "default";
+
+/// The actor that should receive a loopback HTTP request, and the link it appears to arrive on
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LoopbackTarget {
+    pub actor: String,
+    pub link_name: String,
+    pub provider_id: String,
+}
+
+/// Resolves the target of a loopback request, which is either the public key of an actor or
+/// the name of an HTTP server link that exactly one actor has
+pub(crate) fn resolve_target(links: &[LinkDefinition], target: &str) -> Result<LoopbackTarget> {
+    let http_links = links
+        .iter()
+        .filter(|l| l.contract_id == HTTP_SERVER_CONTRACT);
+    if is_actor_key(target) {
+        let link = http_links
+            .filter(|l| l.actor_id == target)
+            .min_by_key(|l| l.link_name != DEFAULT_LINK_NAME);
+        return Ok(LoopbackTarget {
+            actor: target.to_string(),
+            link_name: link
+                .map(|l| l.link_name.to_string())
+                .unwrap_or_else(|| DEFAULT_LINK_NAME.to_string()),
+            provider_id: link
+                .map(|l| l.provider_id.to_string())
+                .unwrap_or_else(|| LOOPBACK_PROVIDER.to_string()),
+        });
+    }
+    let mut matches = http_links.filter(|l| l.link_name == target);
+    match (matches.next(), matches.next()) {
+        (Some(l), None) => Ok(LoopbackTarget {
+            actor: l.actor_id.to_string(),
+            link_name: l.link_name.to_string(),
+            provider_id: l.provider_id.to_string(),
+        }),
+        (Some(_), Some(_)) => Err(format!(
+            "More than one actor has an HTTP server link named '{}', use an actor's public key instead",
+            target
+        )
+        .into()),
+        (None, _) => Err(format!(
+            "'{}' is neither an actor public key nor the name of an HTTP server link",
+            target
+        )
+        .into()),
+    }
+}
+
+fn is_actor_key(target: &str) -> bool {
+    target.len() == 56 && target.starts_with('M')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve_target, HTTP_SERVER_CONTRACT, LOOPBACK_PROVIDER};
+    use crate::messagebus::LinkDefinition;
+    use std::collections::HashMap;
+
+    const ACTOR: &str = "MASCXFM4R6X63UD5MSCDZYCJNPBVSIU6RKMXUPXRKAOSBQ6UY3VT3NPZ";
+    const OTHER: &str = "MB2ZQB6ROOMAYBO4ZCTFYWN7YIVBWA3MTKZYAQKJMTIHE2ELLRW2E3ZW";
+
+    fn http_link(actor: &str, link_name: &str) -> LinkDefinition {
+        LinkDefinition {
+            actor_id: actor.to_string(),
+            provider_id: "VHTTP".to_string(),
+            contract_id: HTTP_SERVER_CONTRACT.to_string(),
+            link_name: link_name.to_string(),
+            values: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn resolves_actors_and_link_names() {
+        let links = vec![
+            http_link(ACTOR, "default"),
+            http_link(OTHER, "default"),
+            http_link(OTHER, "admin"),
+        ];
+        let t = resolve_target(&links, "admin").unwrap();
+        assert_eq!(OTHER, t.actor);
+        assert_eq!("VHTTP", t.provider_id);
+
+        let t = resolve_target(&links, ACTOR).unwrap();
+        assert_eq!("default", t.link_name);
+        assert!(resolve_target(&links, "default").is_err());
+        assert!(resolve_target(&links, "missing").is_err());
+
+        let t = resolve_target(&[], ACTOR).unwrap();
+        assert_eq!(LOOPBACK_PROVIDER, t.provider_id);
+    }
+}
@@ -1,8 +1,7 @@
 use crate::host_controller::{StartActor, StartProvider};
 use crate::messagebus::AdvertiseLink;
-use crate::oci::fetch_oci_bytes;
+use crate::oci::{fetch_oci_bytes, fetch_provider};
 use crate::NativeCapability;
-use provider_archive::ProviderArchive;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::{fs::File, io::Read, path::Path};
@@ -80,6 +79,7 @@ impl HostManifest {
 }
 
 pub(crate) async fn generate_actor_start_messages(
+    host_id: &str,
     manifest: &HostManifest,
     allow_latest: bool,
 ) -> Vec<StartActor> {
@@ -96,7 +96,7 @@ pub(crate) async fn generate_actor_start_messages(
             }
         } else {
             // load actor from OCI
-            if let Ok(a) = fetch_oci_bytes(&actor_ref, allow_latest)
+            if let Ok(a) = fetch_oci_bytes(host_id, &actor_ref, allow_latest)
                 .await
                 .and_then(|bytes| crate::Actor::from_slice(&bytes))
             {
@@ -111,6 +111,7 @@ pub(crate) async fn generate_actor_start_messages(
 }
 
 pub(crate) async fn generate_provider_start_messages(
+    host_id: &str,
     manifest: &HostManifest,
     allow_latest: bool,
 ) -> Vec<StartProvider> {
@@ -119,10 +120,7 @@ pub(crate) async fn generate_provider_start_messages(
         let p = Path::new(&cap.image_ref);
         if p.exists() {
             // read PAR from disk
-            if let Ok(prov) = file_bytes(&p)
-                .and_then(|bytes| ProviderArchive::try_load(&bytes))
-                .and_then(|par| NativeCapability::from_archive(&par, cap.link_name.clone()))
-            {
+            if let Ok(prov) = NativeCapability::from_archive_file(&p, cap.link_name.clone()) {
                 v.push(StartProvider {
                     provider: prov,
                     image_ref: None,
@@ -130,10 +128,8 @@ pub(crate) async fn generate_provider_start_messages(
             }
         } else {
             // read PAR from OCI
-            if let Ok(prov) = fetch_oci_bytes(&cap.image_ref, allow_latest)
-                .await
-                .and_then(|bytes| ProviderArchive::try_load(&bytes))
-                .and_then(|par| NativeCapability::from_archive(&par, cap.link_name.clone()))
+            if let Ok(prov) =
+                fetch_provider(host_id, &cap.image_ref, allow_latest, cap.link_name.clone()).await
             {
                 v.push(StartProvider {
                     provider: prov,
@@ -164,13 +160,6 @@ pub(crate) async fn generate_adv_link_messages(manifest: &HostManifest) -> Vec<A
         .collect()
 }
 
-fn file_bytes(path: &Path) -> crate::Result<Vec<u8>> {
-    let mut f = File::open(path)?;
-    let mut bytes = Vec::new();
-    f.read_to_end(&mut bytes)?;
-    Ok(bytes)
-}
-
 #[cfg(test)]
 mod test {
     use super::{Capability, LinkEntry};
@@ -0,0 +1,123 @@
+// Every invocation in a host shares the same actors and the same bus, so when the calls on one
+// contract slow down (a keyvalue store that takes seconds to answer, say), they pile up and tie
+// up the actors making them until nothing is left to answer calls on any other contract, such
+// as the HTTP requests for a health endpoint. A bulkhead bounds the number of invocations on a
+// contract that can be in flight at once, in either direction (an actor calling a provider of
+// the contract, or such a provider calling an actor). Past that bound, new invocations on the
+// contract fail straight away rather than waiting, which keeps the slowness of one contract from
+// spilling over into the others. Contracts without a bulkhead are unbounded.
+
+use super::MessageBus;
+use crate::{Invocation, InvocationResponse, WasccEntity};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub(crate) struct Bulkhead {
+    max_concurrent: usize,
+    in_flight: Arc<AtomicUsize>,
+    rejected: AtomicU64,
+}
+
+/// A slot in a bulkhead, which is given back when dropped
+pub(crate) struct BulkheadPermit {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for BulkheadPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Bulkhead {
+    pub fn new(max_concurrent: usize) -> Bulkhead {
+        Bulkhead {
+            max_concurrent,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes a slot in the bulkhead, if one is free
+    fn try_enter(&self) -> Option<BulkheadPermit> {
+        let mut current = self.in_flight.load(Ordering::SeqCst);
+        loop {
+            if current >= self.max_concurrent {
+                self.rejected.fetch_add(1, Ordering::SeqCst);
+                return None;
+            }
+            match self.in_flight.compare_exchange(
+                current,
+                current + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    return Some(BulkheadPermit {
+                        in_flight: self.in_flight.clone(),
+                    })
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+// The contract an invocation is made on, if it involves a capability provider
+fn contract_of(inv: &Invocation) -> Option<&str> {
+    match (&inv.origin, &inv.target) {
+        (WasccEntity::Capability { contract_id, .. }, _)
+        | (_, WasccEntity::Capability { contract_id, .. }) => Some(contract_id),
+        _ => None,
+    }
+}
+
+impl MessageBus {
+    /// Takes a slot in the bulkhead of the invocation's contract, answering the invocation with
+    /// an error if the bulkhead is full. Invocations on contracts without a bulkhead don't need
+    /// a slot
+    pub(crate) fn enter_bulkhead(
+        &self,
+        inv: &Invocation,
+    ) -> std::result::Result<Option<BulkheadPermit>, InvocationResponse> {
+        let (contract_id, bulkhead) =
+            match contract_of(inv).and_then(|c| self.bulkheads.get(c).map(|b| (c, b))) {
+                Some(b) => b,
+                None => return Ok(None),
+            };
+        match bulkhead.try_enter() {
+            Some(permit) => Ok(Some(permit)),
+            None => {
+                warn!(
+                    "Bulkhead for {} is full ({} in flight), rejecting invocation {}",
+                    contract_id, bulkhead.max_concurrent, inv.id
+                );
+                Err(InvocationResponse::error(
+                    inv,
+                    &format!("Too many invocations in flight on contract {}", contract_id),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bulkhead;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn bulkhead_bounds_in_flight_invocations() {
+        let bulkhead = Bulkhead::new(2);
+        let first = bulkhead.try_enter().unwrap();
+        let _second = bulkhead.try_enter().unwrap();
+        assert!(bulkhead.try_enter().is_none());
+        assert_eq!(1, bulkhead.rejected.load(Ordering::SeqCst));
+
+        // Finishing an invocation frees its slot
+        drop(first);
+        assert!(bulkhead.try_enter().is_some());
+        assert_eq!(1, bulkhead.in_flight.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,152 @@
+// Platform teams often want every actor an account signs to be wired to the same providers (the
+// organization's logging and metrics providers, say) without linking each actor by hand. A host
+// can be given capability bundles for an issuer: sets of links that every actor signed by that
+// issuer gets. When the host starts such an actor and advertises its claims, it also advertises
+// each of the bundle's links for which the actor holds the capability, unless the actor already
+// has a link under that contract and link name, so links set explicitly always win.
+
+use super::{AdvertiseLink, MessageBus};
+use crate::capability::link_cache::LinkKey;
+use actix::prelude::*;
+use std::collections::HashMap;
+use wascap::jwt::{Actor, Claims};
+
+/// A set of links given to every actor signed by an issuer. See
+/// `HostBuilder::with_issuer_bundle`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CapabilityBundle {
+    links: Vec<BundleLink>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BundleLink {
+    contract_id: String,
+    provider_id: String,
+    link_name: String,
+    values: HashMap<String, String>,
+}
+
+impl CapabilityBundle {
+    pub fn new() -> CapabilityBundle {
+        CapabilityBundle::default()
+    }
+
+    /// Adds a link to the given provider, under the `default` link name if none is given
+    pub fn link(
+        self,
+        contract_id: &str,
+        provider_id: &str,
+        link_name: Option<&str>,
+        values: HashMap<String, String>,
+    ) -> CapabilityBundle {
+        let mut links = self.links;
+        links.push(BundleLink {
+            contract_id: contract_id.to_string(),
+            provider_id: provider_id.to_string(),
+            link_name: link_name.unwrap_or("default").to_string(),
+            values,
+        });
+        CapabilityBundle { links }
+    }
+
+    /// Adds the links of another bundle to this one
+    pub(crate) fn merge(self, other: CapabilityBundle) -> CapabilityBundle {
+        let mut links = self.links;
+        links.extend(other.links);
+        CapabilityBundle { links }
+    }
+}
+
+impl MessageBus {
+    /// Advertises the links an actor gets from the bundles of its issuer
+    pub(crate) fn apply_issuer_bundle(&self, claims: &Claims<Actor>, ctx: &mut Context<Self>) {
+        let bundle = match self.issuer_bundles.get(&claims.issuer) {
+            Some(b) => b,
+            None => return,
+        };
+        for link in bundle_links(bundle, claims, |key| self.link_cache.get(key).is_some()) {
+            info!(
+                "Linking actor {} to {} ({}) from its issuer's bundle",
+                link.actor, link.provider_id, link.contract_id
+            );
+            ctx.notify(link);
+        }
+    }
+}
+
+// The links of the bundle that the actor holds the capability for and doesn't already have
+fn bundle_links(
+    bundle: &CapabilityBundle,
+    claims: &Claims<Actor>,
+    linked: impl Fn(&LinkKey) -> bool,
+) -> Vec<AdvertiseLink> {
+    let caps = claims
+        .metadata
+        .as_ref()
+        .and_then(|md| md.caps.clone())
+        .unwrap_or_default();
+    bundle
+        .links
+        .iter()
+        .filter(|l| caps.contains(&l.contract_id))
+        .filter(|l| {
+            !linked(&LinkKey {
+                actor: claims.subject.to_string(),
+                contract_id: l.contract_id.to_string(),
+                link_name: l.link_name.to_string(),
+            })
+        })
+        .map(|l| AdvertiseLink {
+            contract_id: l.contract_id.to_string(),
+            actor: claims.subject.to_string(),
+            link_name: l.link_name.to_string(),
+            provider_id: l.provider_id.to_string(),
+            values: l.values.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bundle_links, CapabilityBundle};
+    use std::collections::HashMap;
+    use wascap::jwt::{Actor, ClaimsBuilder};
+
+    #[test]
+    fn bundles_link_granted_capabilities_not_yet_linked() {
+        let mut claims = ClaimsBuilder::new()
+            .with_metadata(Actor::new(
+                "Mactor".to_string(),
+                Some(vec![
+                    "wascc:logging".to_string(),
+                    "wascc:keyvalue".to_string(),
+                ]),
+                None,
+                false,
+                None,
+                None,
+            ))
+            .build();
+        claims.subject = "Mactor".to_string();
+        let bundle = CapabilityBundle::new()
+            .link("wascc:logging", "Vlogging", None, HashMap::new())
+            .link("wasmcloud:metrics", "Vmetrics", None, HashMap::new())
+            .merge(CapabilityBundle::new().link(
+                "wascc:keyvalue",
+                "Vredis",
+                Some("cache"),
+                HashMap::new(),
+            ));
+
+        let links = bundle_links(&bundle, &claims, |_| false);
+        assert_eq!(2, links.len());
+        assert_eq!("Vlogging", links[0].provider_id);
+        assert_eq!("default", links[0].link_name);
+        assert_eq!("cache", links[1].link_name);
+
+        // Links the actor already has are left alone
+        let links = bundle_links(&bundle, &claims, |k| k.contract_id == "wascc:logging");
+        assert_eq!(1, links.len());
+        assert_eq!("Vredis", links[0].provider_id);
+    }
+}
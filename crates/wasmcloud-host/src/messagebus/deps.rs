@@ -0,0 +1,230 @@
+use super::MessageBus;
+use crate::dispatch::{Invocation, WasccEntity};
+use crate::topology::{
+    provider_node, LatticeTopology, NodeKind, TopologyEdge, TopologyHost, TopologyNode,
+};
+use crate::SYSTEM_ACTOR;
+use actix::prelude::*;
+use control_interface::{ActorCall, ActorDependencies, DependencyGraph};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+impl MessageBus {
+    /// Records a call made by an actor, whether to another actor or to a capability provider
+    pub(crate) fn record_call(&mut self, origin: &WasccEntity, target: &WasccEntity) {
+        match origin {
+            WasccEntity::Actor(actor) if actor != SYSTEM_ACTOR => {
+                *self
+                    .call_graph
+                    .entry(actor.to_string())
+                    .or_default()
+                    .entry(target.clone())
+                    .or_insert(0) += 1;
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn dependency_graph(&self) -> DependencyGraph {
+        let empty = HashMap::new();
+        let mut actors: Vec<_> = self
+            .actor_started
+            .keys()
+            .map(|actor| {
+                let calls = self.call_graph.get(actor).unwrap_or(&empty);
+                ActorDependencies {
+                    actor_id: actor.to_string(),
+                    calls: calls
+                        .iter()
+                        .map(|(target, count)| match target {
+                            WasccEntity::Actor(a) => ActorCall {
+                                target: a.to_string(),
+                                contract_id: None,
+                                link_name: None,
+                                calls: *count,
+                            },
+                            WasccEntity::Capability {
+                                id,
+                                contract_id,
+                                link_name,
+                            } => ActorCall {
+                                target: id.to_string(),
+                                contract_id: Some(contract_id.to_string()),
+                                link_name: Some(link_name.to_string()),
+                                calls: *count,
+                            },
+                        })
+                        .collect(),
+                    unused_capabilities: self.unused_capabilities(actor),
+                }
+            })
+            .collect();
+        actors.sort_by(|a, b| a.actor_id.cmp(&b.actor_id));
+        DependencyGraph {
+            host_id: self.key.as_ref().unwrap().public_key(),
+            actors,
+        }
+    }
+
+    /// The hosts, nodes and edges of the lattice topology, all taken from the bus at once. Every
+    /// node starts out unhealthy, to be set by its health check. Along with the topology comes
+    /// the subscriber to health check for each node
+    pub(crate) fn topology(
+        &self,
+    ) -> (
+        LatticeTopology,
+        Vec<(String, WasccEntity, Recipient<Invocation>)>,
+    ) {
+        let host_id = self.key.as_ref().unwrap().public_key();
+        let now = crate::clock::monotonic(&host_id);
+        let mut hosts = vec![TopologyHost {
+            host_id: host_id.to_string(),
+            local: true,
+            labels: crate::labels::labels_for(&host_id, None),
+        }];
+        hosts.extend(
+            crate::roster::members(&host_id)
+                .into_iter()
+                .filter(|m| m.host_id != host_id)
+                .map(|m| TopologyHost {
+                    host_id: m.host_id,
+                    local: false,
+                    labels: m.labels,
+                }),
+        );
+        let mut nodes = Vec::new();
+        let mut checks = Vec::new();
+        for (entity, recipient) in self.subscribers.iter() {
+            let node = match entity {
+                WasccEntity::Actor(a) if a == SYSTEM_ACTOR => continue,
+                WasccEntity::Actor(a) => TopologyNode {
+                    id: a.to_string(),
+                    kind: NodeKind::Actor,
+                    host_id: host_id.to_string(),
+                    name: self.claims_cache.get(a).map(|c| c.name()),
+                    contract_id: None,
+                    link_name: None,
+                    healthy: false,
+                    health_message: None,
+                },
+                WasccEntity::Capability {
+                    id,
+                    contract_id,
+                    link_name,
+                } => TopologyNode {
+                    id: provider_node(id, link_name),
+                    kind: NodeKind::Provider,
+                    host_id: host_id.to_string(),
+                    name: None,
+                    contract_id: Some(contract_id.to_string()),
+                    link_name: Some(link_name.to_string()),
+                    healthy: false,
+                    health_message: None,
+                },
+            };
+            checks.push((node.id.to_string(), entity.clone(), recipient.clone()));
+            nodes.push(node);
+        }
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut edges: Vec<_> = self
+            .link_cache
+            .all()
+            .into_iter()
+            .map(|(k, v)| {
+                let metrics = self.link_metrics.get(&k);
+                TopologyEdge {
+                    from: k.actor.to_string(),
+                    to: provider_node(&v.provider_id, &k.link_name),
+                    invocations: metrics.map_or(0, |m| m.invocations),
+                    errors: metrics.map_or(0, |m| m.errors),
+                    error_rate: metrics.map_or(0.0, |m| m.error_rate(now)),
+                    p99_latency_ms: metrics.map_or(0, |m| m.p99(now).as_millis() as u64),
+                    contract_id: k.contract_id,
+                    link_name: k.link_name,
+                }
+            })
+            .collect();
+        edges.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        let topology = LatticeTopology {
+            taken_at_ms: crate::clock::now_millis(&host_id),
+            hosts,
+            nodes,
+            edges,
+        };
+        (topology, checks)
+    }
+
+    fn unused_capabilities(&self, actor: &str) -> Vec<String> {
+        let claimed = self
+            .claims_cache
+            .get(actor)
+            .and_then(|c| c.metadata.as_ref())
+            .and_then(|md| md.caps.clone())
+            .unwrap_or_default();
+        let used: HashSet<&str> = self
+            .call_graph
+            .get(actor)
+            .map(|calls| {
+                calls
+                    .keys()
+                    .filter_map(|t| match t {
+                        WasccEntity::Capability { contract_id, .. } => Some(contract_id.as_str()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        unused(&claimed, &used)
+    }
+
+    /// Periodically warns about capabilities that actors have been granted in their claims but
+    /// have not used since they were started at least `grace` ago. Each capability is only
+    /// reported once per actor
+    pub(crate) fn warn_unused_capabilities(&self, ctx: &mut Context<Self>, grace: Duration) {
+        ctx.run_interval(grace, move |act, _ctx| {
+            let now = Instant::now();
+            let candidates: Vec<String> = act
+                .actor_started
+                .iter()
+                .filter(|(_, started)| now.duration_since(**started) >= grace)
+                .map(|(actor, _)| actor.to_string())
+                .collect();
+            for actor in candidates {
+                for cap in act.unused_capabilities(&actor) {
+                    if act.unused_warned.insert((actor.to_string(), cap.to_string())) {
+                        warn!(
+                            "Actor {} is granted capability {} in its claims but has not used it since it started",
+                            actor, cap
+                        );
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn unused(claimed: &[String], used: &HashSet<&str>) -> Vec<String> {
+    claimed
+        .iter()
+        .filter(|c| !used.contains(c.as_str()))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::unused;
+    use std::collections::HashSet;
+
+    #[test]
+    fn unused_capabilities_are_those_never_called() {
+        let claimed = vec![
+            "wascc:http_server".to_string(),
+            "wascc:keyvalue".to_string(),
+        ];
+        let mut used = HashSet::new();
+        assert_eq!(claimed, unused(&claimed, &used));
+        used.insert("wascc:http_server");
+        assert_eq!(vec!["wascc:keyvalue".to_string()], unused(&claimed, &used));
+    }
+}
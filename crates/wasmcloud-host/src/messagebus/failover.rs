@@ -0,0 +1,371 @@
+// A link can name failover providers (see `LINK_VALUE_FAILOVER`) that implement the same
+// contract as its primary provider, such as a second Redis provider running on another host.
+// Calls on the link go to the primary for as long as it can be reached. When a call can't be
+// delivered to it (e.g. the provider has stopped, or the host's lattice connection is down), the
+// call is retried on each failover provider in order, and the first one that answers takes over
+// the link, which is announced with a `LinkFailover` event. The primary is tried again
+// periodically, and calls fail back to it (with another event) once it answers.
+//
+// Only definite delivery failures cause a failover. A call that times out may still have run on
+// the provider, so it is only retried elsewhere if the link marks its operation as idempotent
+// (see `LINK_VALUE_FAILOVER_IDEMPOTENT`); otherwise the timeout is handed back to the actor. So
+// is an error returned by a provider that was reached.
+
+use super::rpc_client::{AttemptInvocation, RpcClient, RpcError};
+use super::MessageBus;
+use crate::capability::link_cache::LinkKey;
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::dispatch::{Invocation, InvocationResponse, WasccEntity};
+use crate::hlreg::HostLocalSystemService;
+use crate::links::is_idempotent;
+use crate::ControlEvent;
+use actix::prelude::*;
+use std::time::{Duration, Instant};
+use wascap::prelude::KeyPair;
+
+// While a link is failed over, calls skip its primary until this much time has passed
+const FAILBACK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The provider that has taken over a link from its primary, and when it did so
+pub(crate) struct FailedOver {
+    provider_id: String,
+    since: Instant,
+}
+
+// Why an attempt to call one of a link's providers failed
+#[derive(Debug, PartialEq)]
+enum AttemptError {
+    // The call never reached the provider, so it is safe to send elsewhere
+    Undelivered(String),
+    // The call may or may not have run on the provider
+    Uncertain(String),
+}
+
+impl From<RpcError> for AttemptError {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::Undelivered(_) => AttemptError::Undelivered(e.to_string()),
+            RpcError::TimedOut | RpcError::Unreadable => AttemptError::Uncertain(e.to_string()),
+        }
+    }
+}
+
+impl MessageBus {
+    /// If the invocation is a call on a link that has failover providers, returns the link and
+    /// the invocations to attempt, one per provider, in the order they should be tried
+    pub(crate) fn failover_candidates(
+        &self,
+        inv: &Invocation,
+    ) -> Option<(LinkKey, Vec<Invocation>)> {
+        let (actor, id, contract_id, link_name) = match (&inv.origin, &inv.target) {
+            (
+                WasccEntity::Actor(actor),
+                WasccEntity::Capability {
+                    id,
+                    contract_id,
+                    link_name,
+                },
+            ) => (actor, id, contract_id, link_name),
+            _ => return None,
+        };
+        let key = LinkKey {
+            actor: actor.to_string(),
+            contract_id: contract_id.to_string(),
+            link_name: link_name.to_string(),
+        };
+        let providers = self.link_cache.get(&key)?.providers();
+        // Calls addressed to a specific provider other than the primary are left alone
+        if providers.len() < 2 || providers[0] != *id {
+            return None;
+        }
+        let active = self
+            .link_failovers
+            .get(&key)
+            .filter(|f| f.since.elapsed() < FAILBACK_INTERVAL)
+            .map(|f| f.provider_id.as_str());
+        let hostkey = self.key.as_ref().unwrap();
+        let candidates = attempt_order(&providers, active)
+            .iter()
+            .map(|p| retarget(hostkey, inv, p))
+            .collect();
+        Some((key, candidates))
+    }
+
+    /// Delivers the invocation to the first of the candidates that can be reached, locally or
+    /// over the lattice, and records which provider is serving the link
+    pub(crate) fn route_with_failover(
+        &mut self,
+        link: LinkKey,
+        candidates: Vec<Invocation>,
+    ) -> ResponseActFuture<Self, InvocationResponse> {
+        let values = self.link_cache.get(&link).unwrap_or_default();
+        let primary = values.provider_id.to_string();
+        let idempotent = candidates
+            .first()
+            .map_or(false, |inv| is_idempotent(&values.values, &inv.operation));
+        let primary_attempted = candidates.first().map(provider_id) == Some(primary.as_str());
+        let attempts: Vec<_> = candidates
+            .into_iter()
+            .map(|inv| {
+                let local = self.subscribers.get(&inv.target).cloned();
+                (inv, local)
+            })
+            .collect();
+        let rpc = self.rpc_outbound.clone();
+        Box::pin(
+            attempt_in_order(attempts, rpc, idempotent)
+                .into_actor(self)
+                .map(move |(ir, provider), act, _ctx| {
+                    if let Some(provider) = provider {
+                        act.serving_provider(link, &primary, provider, primary_attempted);
+                    }
+                    ir
+                }),
+        )
+    }
+
+    fn serving_provider(
+        &mut self,
+        link: LinkKey,
+        primary: &str,
+        provider: String,
+        primary_attempted: bool,
+    ) {
+        let previous = self
+            .link_failovers
+            .get(&link)
+            .map(|f| f.provider_id.to_string())
+            .unwrap_or_else(|| primary.to_string());
+        if provider == primary {
+            self.link_failovers.remove(&link);
+        } else if provider != previous || primary_attempted {
+            // The primary just failed (again), so hold off on retrying it
+            self.link_failovers.insert(
+                link.clone(),
+                FailedOver {
+                    provider_id: provider.to_string(),
+                    since: Instant::now(),
+                },
+            );
+        }
+        if provider != previous {
+            warn!(
+                "Link {} ({}) of actor {} failed over from provider {} to {}",
+                link.contract_id, link.link_name, link.actor, previous, provider
+            );
+            let host_id = self.key.as_ref().unwrap().public_key();
+            ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent {
+                event: ControlEvent::LinkFailover {
+                    actor: link.actor,
+                    contract_id: link.contract_id,
+                    link_name: link.link_name,
+                    from_provider: previous,
+                    to_provider: provider,
+                },
+            });
+        }
+    }
+}
+
+// Tries each provider in turn until one answers, returning the response and the provider that
+// gave it. A provider that may have run the call ends the attempts, unless the call is
+// idempotent
+async fn attempt_in_order(
+    attempts: Vec<(Invocation, Option<Recipient<Invocation>>)>,
+    rpc: Option<Addr<RpcClient>>,
+    idempotent: bool,
+) -> (InvocationResponse, Option<String>) {
+    let first = attempts[0].0.clone();
+    let mut failures = vec![];
+    for (inv, local) in attempts {
+        let provider = provider_id(&inv).to_string();
+        let res = match (local, &rpc) {
+            // A local provider that has stopped never sees the call
+            (Some(t), _) => t.send(inv).await.map_err(|_| {
+                AttemptError::Undelivered("Provider is no longer running".to_string())
+            }),
+            (None, Some(rpc)) => match rpc.send(AttemptInvocation { invocation: inv }).await {
+                Ok(res) => res.map_err(AttemptError::from),
+                Err(_) => Err(AttemptError::Undelivered(
+                    "Lattice RPC client is unavailable".to_string(),
+                )),
+            },
+            (None, None) => Err(AttemptError::Undelivered(
+                "No local subscriber and no lattice RPC client".to_string(),
+            )),
+        };
+        match res {
+            Ok(ir) => return (ir, Some(provider)),
+            Err(AttemptError::Uncertain(e)) if !idempotent => {
+                warn!(
+                    "Provider {} may have run the call ({}), so it is not retried",
+                    provider, e
+                );
+                return (InvocationResponse::error(&first, &e), None);
+            }
+            Err(AttemptError::Undelivered(e)) | Err(AttemptError::Uncertain(e)) => {
+                warn!("Provider {} could not be reached: {}", provider, e);
+                failures.push(format!("{}: {}", provider, e));
+            }
+        }
+    }
+    let err = format!(
+        "None of the link's providers could be reached ({})",
+        failures.join("; ")
+    );
+    (InvocationResponse::error(&first, &err), None)
+}
+
+// The provider currently serving the link goes first, followed by the rest in preference order
+fn attempt_order(providers: &[String], active: Option<&str>) -> Vec<String> {
+    let mut order: Vec<String> = active
+        .filter(|a| providers.iter().any(|p| p == *a))
+        .map(|a| vec![a.to_string()])
+        .unwrap_or_default();
+    order.extend(
+        providers
+            .iter()
+            .filter(|p| Some(p.as_str()) != active)
+            .cloned(),
+    );
+    order
+}
+
+fn provider_id(inv: &Invocation) -> &str {
+    match inv.target {
+        WasccEntity::Capability { ref id, .. } => id,
+        WasccEntity::Actor(ref a) => a,
+    }
+}
+
+// The target is covered by the invocation's signature, so retargeted invocations are re-signed
+fn retarget(hostkey: &KeyPair, inv: &Invocation, provider: &str) -> Invocation {
+    let (contract_id, link_name) = match inv.target {
+        WasccEntity::Capability {
+            ref id,
+            ref contract_id,
+            ref link_name,
+        } if id != provider => (contract_id, link_name),
+        _ => return inv.clone(),
+    };
+    let retargeted = Invocation::new(
+        hostkey,
+        inv.origin.clone(),
+        WasccEntity::Capability {
+            id: provider.to_string(),
+            contract_id: contract_id.to_string(),
+            link_name: link_name.to_string(),
+        },
+        &inv.operation,
+        inv.msg.clone(),
+    );
+    Invocation {
+        idempotency_key: inv.idempotency_key.clone(),
+        baggage: inv.baggage.clone(),
+        ..retargeted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attempt_in_order, attempt_order};
+    use crate::dispatch::{Invocation, InvocationResponse, WasccEntity};
+    use actix::prelude::*;
+    use wascap::prelude::KeyPair;
+
+    struct StubProvider {
+        running: bool,
+    }
+
+    impl Actor for StubProvider {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            if !self.running {
+                ctx.stop();
+            }
+        }
+    }
+
+    impl Handler<Invocation> for StubProvider {
+        type Result = InvocationResponse;
+
+        fn handle(&mut self, msg: Invocation, _ctx: &mut Self::Context) -> InvocationResponse {
+            InvocationResponse::success(&msg, provider(&msg).into_bytes())
+        }
+    }
+
+    fn provider(inv: &Invocation) -> String {
+        super::provider_id(inv).to_string()
+    }
+
+    fn call(hostkey: &KeyPair, provider: &str) -> Invocation {
+        Invocation::new(
+            hostkey,
+            WasccEntity::Actor("Mactor".to_string()),
+            WasccEntity::Capability {
+                id: provider.to_string(),
+                contract_id: "wascc:keyvalue".to_string(),
+                link_name: "default".to_string(),
+            },
+            "Set",
+            vec![],
+        )
+    }
+
+    #[actix_rt::test]
+    async fn calls_fail_over_from_a_stopped_primary() {
+        let hostkey = KeyPair::new_server();
+        let primary = StubProvider { running: false }.start();
+        let secondary = StubProvider { running: true }.start();
+        // Let the primary stop
+        actix_rt::time::delay_for(std::time::Duration::from_millis(50)).await;
+
+        let attempts = vec![
+            (call(&hostkey, "Vprimary"), Some(primary.recipient())),
+            (call(&hostkey, "Vsecond"), Some(secondary.recipient())),
+        ];
+        let (ir, serving) = attempt_in_order(attempts, None, false).await;
+        assert_eq!(Some("Vsecond".to_string()), serving);
+        assert_eq!(b"Vsecond".to_vec(), ir.msg);
+
+        // With nowhere to deliver the call, every provider is tried and the failures reported
+        let attempts = vec![
+            (call(&hostkey, "Vprimary"), None),
+            (call(&hostkey, "Vsecond"), None),
+        ];
+        let (ir, serving) = attempt_in_order(attempts, None, false).await;
+        assert_eq!(None, serving);
+        assert!(ir.error.unwrap().contains("Vsecond"));
+    }
+
+    #[test]
+    fn only_definite_failures_are_safe_to_retry() {
+        use super::AttemptError;
+        use crate::messagebus::rpc_client::RpcError;
+        assert!(matches!(
+            AttemptError::from(RpcError::Undelivered("closed".to_string())),
+            AttemptError::Undelivered(_)
+        ));
+        assert!(matches!(
+            AttemptError::from(RpcError::TimedOut),
+            AttemptError::Uncertain(_)
+        ));
+    }
+
+    #[test]
+    fn serving_provider_is_attempted_first() {
+        let providers = vec![
+            "Vprimary".to_string(),
+            "Vsecond".to_string(),
+            "Vthird".to_string(),
+        ];
+        assert_eq!(providers, attempt_order(&providers, None));
+        assert_eq!(
+            vec!["Vthird", "Vprimary", "Vsecond"],
+            attempt_order(&providers, Some("Vthird"))
+        );
+        // A provider that is no longer part of the link is ignored
+        assert_eq!(providers, attempt_order(&providers, Some("Vgone")));
+    }
+}
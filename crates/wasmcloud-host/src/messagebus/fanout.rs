@@ -0,0 +1,313 @@
+// A regular invocation goes to exactly one instance of its target, picked by the lattice's queue
+// subscription. Some calls are instead meant for every instance: a cache invalidation, say, or a
+// configuration push. A fan-out invocation is delivered to every host running the target actor
+// (or, with a tag as the target, every host running any actor carrying that tag, as known from
+// the gossiped claims), and the responses are gathered according to a strategy:
+//
+// * `First` returns the first successful response
+// * `All` returns every response that arrives before the timeout
+// * `Quorum(n)` returns as soon as `n` successful responses have arrived, and fails otherwise
+//
+// Each host running an actor holds a plain (non-queue) subscription to the actor's fan-out
+// subject alongside its queue subscription. Without a lattice, the invocation is delivered to the
+// matching actors in this host. Ordered actors are never fan-out targets, since they only take
+// calls over their ordered subject.
+
+use super::MessageBus;
+use crate::generated::core::{deserialize, serialize};
+use crate::{Invocation, InvocationResponse, Result, WasccEntity, SYSTEM_ACTOR};
+use actix::prelude::*;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How the responses to a fan-out invocation are gathered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FanoutStrategy {
+    /// Return the first successful response
+    First,
+    /// Return every response received before the timeout
+    All,
+    /// Return once this many successful responses have been received
+    Quorum(usize),
+}
+
+/// The actors a fan-out invocation is delivered to
+#[derive(Debug, Clone, PartialEq)]
+pub enum FanoutTarget {
+    /// Every instance of the actor with this public key
+    Actor(String),
+    /// Every instance of every actor whose claims carry this tag
+    Tag(String),
+}
+
+/// The response of a single actor instance to a fan-out invocation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FanoutReply {
+    /// The host running the instance that responded
+    pub host_id: String,
+    pub actor: String,
+    pub msg: Vec<u8>,
+    pub error: Option<String>,
+}
+
+impl FanoutReply {
+    pub(crate) fn new(host_id: &str, actor: &str, ir: InvocationResponse) -> FanoutReply {
+        FanoutReply {
+            host_id: host_id.to_string(),
+            actor: actor.to_string(),
+            msg: ir.msg,
+            error: ir.error,
+        }
+    }
+}
+
+/// Delivers an invocation to every instance of the target, gathering the responses
+#[derive(Message)]
+#[rtype(result = "Result<Vec<FanoutReply>>")]
+pub(crate) struct FanoutInvocation {
+    pub target: FanoutTarget,
+    pub operation: String,
+    pub msg: Vec<u8>,
+    pub strategy: FanoutStrategy,
+    pub timeout: Duration,
+}
+
+pub(crate) fn fanout_subject(ns_prefix: &Option<String>, actor: &str) -> String {
+    format!(
+        "{}.fanout.{}",
+        super::rpc_subscription::subject_prefix(ns_prefix),
+        actor
+    )
+}
+
+// Gathers responses until the strategy is satisfied. The number of responses to expect is only
+// known when delivering within this host
+struct Aggregator {
+    strategy: FanoutStrategy,
+    expected: Option<usize>,
+    replies: Vec<FanoutReply>,
+}
+
+impl Aggregator {
+    fn new(strategy: FanoutStrategy, expected: Option<usize>) -> Aggregator {
+        Aggregator {
+            strategy,
+            expected,
+            replies: vec![],
+        }
+    }
+
+    fn successes(&self) -> usize {
+        self.replies.iter().filter(|r| r.error.is_none()).count()
+    }
+
+    /// Adds a response, returning true once no more are needed
+    fn push(&mut self, reply: FanoutReply) -> bool {
+        self.replies.push(reply);
+        let done = match self.strategy {
+            FanoutStrategy::First => self.successes() > 0,
+            FanoutStrategy::All => false,
+            FanoutStrategy::Quorum(n) => self.successes() >= n,
+        };
+        done || Some(self.replies.len()) == self.expected
+    }
+
+    fn finish(self) -> Result<Vec<FanoutReply>> {
+        let successes = self.successes();
+        match self.strategy {
+            FanoutStrategy::First => match self.replies.into_iter().find(|r| r.error.is_none()) {
+                Some(reply) => Ok(vec![reply]),
+                None => Err("No instance of the fan-out target responded successfully".into()),
+            },
+            FanoutStrategy::All => Ok(self.replies),
+            FanoutStrategy::Quorum(n) if successes >= n => Ok(self.replies),
+            FanoutStrategy::Quorum(n) => Err(format!(
+                "Fan-out quorum not reached: {} of {} successful responses",
+                successes, n
+            )
+            .into()),
+        }
+    }
+}
+
+impl MessageBus {
+    // The actors (local or elsewhere on the lattice) that a fan-out invocation is delivered to
+    fn fanout_actors(&self, target: &FanoutTarget) -> Vec<String> {
+        match target {
+            FanoutTarget::Actor(actor) => vec![actor.to_string()],
+            FanoutTarget::Tag(tag) => self
+                .claims_cache
+                .iter()
+                .filter(|(_, claims)| {
+                    claims
+                        .metadata
+                        .as_ref()
+                        .and_then(|md| md.tags.as_ref())
+                        .map_or(false, |tags| tags.contains(tag))
+                })
+                .map(|(actor, _)| actor.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Handler<FanoutInvocation> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<Vec<FanoutReply>>>;
+
+    fn handle(&mut self, msg: FanoutInvocation, _ctx: &mut Context<Self>) -> Self::Result {
+        let key = self.key.as_ref().unwrap();
+        let host_id = key.public_key();
+        let invocations: Vec<(String, Invocation)> = self
+            .fanout_actors(&msg.target)
+            .into_iter()
+            .filter(|a| {
+                !self
+                    .claims_cache
+                    .get(a)
+                    .map_or(false, super::ordered::is_ordered)
+            })
+            .map(|actor| {
+                let inv = Invocation::new(
+                    key,
+                    WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                    WasccEntity::Actor(actor.to_string()),
+                    &msg.operation,
+                    msg.msg.clone(),
+                );
+                (actor, inv)
+            })
+            .collect();
+        if invocations.is_empty() {
+            return Box::pin(
+                async { Err("No actors match the fan-out target".into()) }.into_actor(self),
+            );
+        }
+        trace!(
+            "Fanning out {} to {} actor(s)",
+            msg.operation,
+            invocations.len()
+        );
+        let strategy = msg.strategy;
+        let timeout = msg.timeout;
+        match self.nc.clone() {
+            Some(nc) => {
+                let ns = self.namespace.clone();
+                let host_id = host_id.to_string();
+                Box::pin(
+                    async move {
+                        let inbox = crate::inboxes::new_inbox(&host_id);
+                        let sub = nc.subscribe(&inbox).await?;
+                        for (actor, inv) in invocations {
+                            nc.publish_request(
+                                &fanout_subject(&ns, &actor),
+                                &inbox,
+                                &serialize(&inv)?,
+                            )
+                            .await?;
+                        }
+                        let mut agg = Aggregator::new(strategy, None);
+                        // The timeout is measured on the host's clock
+                        let deadline = crate::clock::monotonic(&host_id) + timeout;
+                        while let Some(remaining) =
+                            deadline.checked_sub(crate::clock::monotonic(&host_id))
+                        {
+                            match actix_rt::time::timeout(remaining, sub.next()).await {
+                                Ok(Some(m)) => match deserialize::<FanoutReply>(&m.data) {
+                                    Ok(reply) => {
+                                        if agg.push(reply) {
+                                            break;
+                                        }
+                                    }
+                                    Err(e) => trace!("Ignoring unreadable fan-out reply: {}", e),
+                                },
+                                _ => break,
+                            }
+                        }
+                        agg.finish()
+                    }
+                    .into_actor(self),
+                )
+            }
+            None => {
+                let mut pending: FuturesUnordered<_> = invocations
+                    .into_iter()
+                    .filter_map(|(actor, inv)| {
+                        self.subscribers
+                            .get(&WasccEntity::Actor(actor.to_string()))
+                            .map(|recipient| {
+                                let recipient = recipient.clone();
+                                async move {
+                                    let ir = match recipient.send(inv.clone()).await {
+                                        Ok(ir) => ir,
+                                        Err(_) => InvocationResponse::error(
+                                            &inv,
+                                            "Unresponsive target actor",
+                                        ),
+                                    };
+                                    (actor, ir)
+                                }
+                            })
+                    })
+                    .collect();
+                let mut agg = Aggregator::new(strategy, Some(pending.len()));
+                Box::pin(
+                    async move {
+                        if pending.is_empty() {
+                            return agg.finish();
+                        }
+                        let _ = actix_rt::time::timeout(timeout, async {
+                            while let Some((actor, ir)) = pending.next().await {
+                                if agg.push(FanoutReply::new(&host_id, &actor, ir)) {
+                                    break;
+                                }
+                            }
+                        })
+                        .await;
+                        agg.finish()
+                    }
+                    .into_actor(self),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Aggregator, FanoutReply, FanoutStrategy};
+
+    fn reply(host: &str, error: Option<&str>) -> FanoutReply {
+        FanoutReply {
+            host_id: host.to_string(),
+            actor: "Mxxx".to_string(),
+            msg: vec![],
+            error: error.map(|e| e.to_string()),
+        }
+    }
+
+    #[test]
+    fn strategies_gather_responses() {
+        let mut first = Aggregator::new(FanoutStrategy::First, None);
+        assert!(!first.push(reply("N1", Some("boom"))));
+        assert!(first.push(reply("N2", None)));
+        assert_eq!(vec![reply("N2", None)], first.finish().unwrap());
+
+        let mut quorum = Aggregator::new(FanoutStrategy::Quorum(2), None);
+        assert!(!quorum.push(reply("N1", None)));
+        assert!(!quorum.push(reply("N2", Some("boom"))));
+        assert!(quorum.push(reply("N3", None)));
+        assert_eq!(3, quorum.finish().unwrap().len());
+
+        let mut short = Aggregator::new(FanoutStrategy::Quorum(2), None);
+        short.push(reply("N1", None));
+        assert!(short.finish().is_err());
+
+        // All only stops early once every expected response is in
+        let mut all = Aggregator::new(FanoutStrategy::All, Some(2));
+        assert!(!all.push(reply("N1", Some("boom"))));
+        assert!(all.push(reply("N2", None)));
+        assert_eq!(2, all.finish().unwrap().len());
+    }
+}
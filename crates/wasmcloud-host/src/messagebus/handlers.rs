@@ -1,21 +1,51 @@
 use super::MessageBus;
-use crate::capability::{extras::EXTRAS_PUBLIC_KEY, link_cache::LinkKey};
+use crate::capability::{
+    discovery::DISCOVERY_PUBLIC_KEY, extras::EXTRAS_PUBLIC_KEY, grpc::GRPC_PUBLIC_KEY,
+    keyvalue::KEYVALUE_PUBLIC_KEY, link_cache::LinkKey, router::ROUTER_PUBLIC_KEY,
+};
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
 use crate::dispatch::{gen_config_invocation, Invocation, InvocationResponse, WasccEntity};
-use crate::hlreg::HostLocalSystemService;
-use crate::messagebus::rpc_client::RpcClient;
-use crate::messagebus::rpc_subscription::{CreateSubscription, RpcSubscription};
+use crate::generated::core::{deserialize, HealthResponse};
+use crate::hlreg::{HostLocalSystemService, Shutdown};
+use crate::limits::cap_response;
+use crate::messagebus::hb::{generate_ping, healthping_subscribers};
+use crate::messagebus::history::CacheChangeKind;
+use crate::messagebus::ordered::{is_ordered, ordered_subject, owner_subject};
+use crate::messagebus::rpc_client::{FlushClaims, OrderedInvocation, RpcClient};
+use crate::messagebus::rpc_subscription::{invoke_subject, CreateSubscription, RpcSubscription};
+use crate::messagebus::slow_consumer::SubscriptionStats;
 use crate::messagebus::{
-    AdvertiseClaims, AdvertiseLink, CanInvoke, ClaimsResponse, EnforceLocalActorLinks,
-    EnforceLocalLink, EnforceLocalProviderLinks, EstablishAllLinks, FindLinks, FindLinksResponse,
-    GetClaims, Initialize, LinkDefinition, LinksResponse, LookupLink, PutClaims, PutLink,
-    QueryActors, QueryAllLinks, QueryProviders, QueryResponse, Subscribe, Unsubscribe,
+    AdvertiseClaims, AdvertiseLink, BusDrained, CanInvoke, ClaimOrderedActor, ClaimsResponse,
+    DrainBus, EnforceLocalActorLinks, EnforceLocalLink, EnforceLocalProviderLinks,
+    EstablishAllLinks, FindLinks, FindLinksResponse, FindProvider, GetClaims, Initialize,
+    LinkDefinition, LinksResponse, LookupLink, ProbeProvider, PutClaims, PutLink, QueryActors,
+    QueryAllLinks, QueryDependencyGraph, QueryLinkStatistics, QueryNamespace, QueryProviders,
+    QueryQuiescedProviders, QueryResponse, QueryRpcClient, QuerySubscriptionStatistics,
+    QueryTopology, QuiesceProvider, RemoveLink, RestartCacheSync, RestartHeartbeat, Subscribe,
+    Unsubscribe,
 };
-use crate::{auth, Result};
+use crate::shutdown::FlushStatus;
+use crate::topology::LatticeTopology;
+use crate::watchdog::Ping;
+use crate::{auth, ControlEvent, Result, SYSTEM_ACTOR};
 use actix::prelude::*;
+use control_interface::{DependencyGraph, LinkStatistics, SubscriptionStatistics};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wascap::prelude::KeyPair;
+
+// How long to wait for the owner of an ordered actor to answer before assuming there is none
+const ORDERED_OWNER_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
 
 pub const OP_HEALTH_REQUEST: &str = "HealthRequest";
 pub const OP_BIND_ACTOR: &str = "BindActor";
+pub const OP_REMOVE_ACTOR: &str = "RemoveActor";
+/// Sent by the host to a provider to ask it to stop accepting new external work (e.g. close its
+/// listener) while finishing what is in flight. Links are left untouched
+pub const OP_QUIESCE: &str = "Quiesce";
+/// Sent by the host to a quiesced provider to ask it to start accepting new work again
+pub const OP_RESUME: &str = "Resume";
 
 impl Supervised for MessageBus {}
 
@@ -25,7 +55,6 @@ impl SystemService for MessageBus {
 
         // TODO: make this value configurable
         ctx.set_mailbox_capacity(1000);
-        self.hb(ctx);
     }
 }
 
@@ -65,7 +94,9 @@ impl Handler<EnforceLocalProviderLinks> for MessageBus {
 
     fn handle(&mut self, msg: EnforceLocalProviderLinks, ctx: &mut Context<Self>) -> Self::Result {
         for (key, values) in self.link_cache.all() {
-            if key.link_name == msg.link_name && values.provider_id == msg.provider_id {
+            if key.link_name == msg.link_name
+                && values.providers().iter().any(|p| *p == msg.provider_id)
+            {
                 ctx.notify(EnforceLocalLink {
                     actor: key.actor,
                     contract_id: key.contract_id,
@@ -84,6 +115,7 @@ impl Handler<EnforceLocalLink> for MessageBus {
     fn handle(&mut self, msg: EnforceLocalLink, _ctx: &mut Context<Self>) -> Self::Result {
         let claims = self.claims_cache.get(&msg.actor);
         if claims.is_none() {
+            self.wake_waiters(); // links to providers elsewhere are established once cached
             return Box::pin(async move {}.into_actor(self)); // do not send link invocation for actors we don't know about
         }
         let key = LinkKey {
@@ -96,31 +128,49 @@ impl Handler<EnforceLocalLink> for MessageBus {
             return Box::pin(async move {}.into_actor(self)); // do not invoke if we don't have the link in the link cache
         }
         let link = link.unwrap();
-        let target = WasccEntity::Capability {
-            id: link.provider_id.to_string(),
-            contract_id: msg.contract_id.to_string(),
-            link_name: msg.link_name.to_string(),
-        };
-        if let Some(t) = self.subscribers.get(&target) {
-            let t = t.clone();
-            let inv = gen_config_invocation(
-                self.key.as_ref().unwrap(),
-                &msg.actor,
-                &msg.contract_id,
-                &link.provider_id,
-                claims.unwrap().clone(),
-                msg.link_name.to_string(),
-                link.values,
-            );
-            Box::pin(
-                async move {
+        // Failover providers are bound too, so that they're ready to take over the link
+        let targets: Vec<_> = link
+            .providers()
+            .into_iter()
+            .filter_map(|provider_id| {
+                let target = WasccEntity::Capability {
+                    id: provider_id.to_string(),
+                    contract_id: msg.contract_id.to_string(),
+                    link_name: msg.link_name.to_string(),
+                };
+                self.subscribers
+                    .get(&target)
+                    .map(|t| (provider_id, t.clone()))
+            })
+            .collect();
+        let claims = claims.unwrap().clone();
+        let invs: Vec<_> = targets
+            .into_iter()
+            .map(|(provider_id, t)| {
+                let inv = gen_config_invocation(
+                    self.key.as_ref().unwrap(),
+                    &msg.actor,
+                    &msg.contract_id,
+                    &provider_id,
+                    claims.clone(),
+                    msg.link_name.to_string(),
+                    link.values.clone(),
+                );
+                (t, inv)
+            })
+            .collect();
+        Box::pin(
+            async move {
+                for (t, inv) in invs {
                     let _ = t.send(inv).await;
                 }
-                .into_actor(self),
-            )
-        } else {
-            Box::pin(async move {}.into_actor(self))
-        }
+            }
+            .into_actor(self)
+            .map(move |_, act, _ctx| {
+                act.bound_links.insert(key);
+                act.wake_waiters();
+            }),
+        )
     }
 }
 
@@ -142,6 +192,85 @@ impl Handler<EstablishAllLinks> for MessageBus {
     }
 }
 
+impl Handler<QueryNamespace> for MessageBus {
+    type Result = Option<String>;
+
+    fn handle(&mut self, _msg: QueryNamespace, _ctx: &mut Context<Self>) -> Self::Result {
+        self.namespace.clone()
+    }
+}
+
+impl Handler<QueryLinkStatistics> for MessageBus {
+    type Result = Vec<LinkStatistics>;
+
+    fn handle(&mut self, _msg: QueryLinkStatistics, _ctx: &mut Context<Self>) -> Self::Result {
+        let host_id = self.key.as_ref().unwrap().public_key();
+        let now = crate::clock::monotonic(&host_id);
+        self.link_metrics
+            .iter()
+            .map(|(k, m)| LinkStatistics {
+                actor_id: k.actor.to_string(),
+                contract_id: k.contract_id.to_string(),
+                link_name: k.link_name.to_string(),
+                invocations: m.invocations,
+                errors: m.errors,
+                error_rate: m.error_rate(now),
+                p99_latency_ms: m.p99(now).as_millis() as u64,
+                responses_too_large: Some(m.too_large),
+                labels: Some(crate::labels::labels_for(&host_id, Some(&k.actor))),
+            })
+            .collect()
+    }
+}
+
+impl Handler<QuerySubscriptionStatistics> for MessageBus {
+    type Result = Vec<SubscriptionStatistics>;
+
+    fn handle(
+        &mut self,
+        _msg: QuerySubscriptionStatistics,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        self.subscription_statistics()
+    }
+}
+
+impl Handler<QueryDependencyGraph> for MessageBus {
+    type Result = DependencyGraph;
+
+    fn handle(&mut self, _msg: QueryDependencyGraph, _ctx: &mut Context<Self>) -> Self::Result {
+        self.dependency_graph()
+    }
+}
+
+// The topology is taken from the bus in one step and its nodes are health checked afterward
+impl Handler<QueryTopology> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<LatticeTopology>>;
+
+    fn handle(&mut self, _msg: QueryTopology, _ctx: &mut Context<Self>) -> Self::Result {
+        let (mut topology, checks) = self.topology();
+        let seed = self.key.as_ref().unwrap().seed().unwrap();
+        Box::pin(
+            async move {
+                let mut states = HashMap::new();
+                for (node, entity, recipient) in checks {
+                    let state = healthping_subscribers(&[(entity, recipient)], seed.to_string())
+                        .await
+                        .into_iter()
+                        .next()
+                        .map(|(_, state)| state);
+                    if let Some(state) = state {
+                        states.insert(node, state);
+                    }
+                }
+                topology.set_health(&states);
+                Ok(topology)
+            }
+            .into_actor(self),
+        )
+    }
+}
+
 impl Handler<QueryActors> for MessageBus {
     type Result = QueryResponse;
 
@@ -165,6 +294,7 @@ impl Handler<PutClaims> for MessageBus {
 
     fn handle(&mut self, msg: PutClaims, ctx: &mut Context<Self>) {
         let subject = msg.claims.subject.to_string();
+        self.record_claims_set(msg.origin, &msg.claims);
         self.claims_cache
             .insert(msg.claims.subject.to_string(), msg.claims);
 
@@ -178,19 +308,228 @@ impl Handler<PutLink> for MessageBus {
 
     fn handle(&mut self, msg: PutLink, ctx: &mut Context<Self>) {
         trace!("Messagebus received link definition notification");
-        self.link_cache.add_link(
+        self.record_link_set(
+            msg.origin,
+            &msg.actor,
+            &msg.contract_id,
+            &msg.link_name,
+            &msg.provider_id,
+            &msg.values,
+        );
+        // Renewing a lease doesn't bind the link again
+        let changed = self.link_cache.add_link(
             &msg.actor,
             &msg.contract_id,
             &msg.link_name,
             &msg.provider_id,
             msg.values.clone(),
         );
+        if changed {
+            ctx.notify(EnforceLocalLink {
+                actor: msg.actor.to_string(),
+                contract_id: msg.contract_id.to_string(),
+                link_name: msg.link_name.to_string(),
+            });
+        }
+    }
+}
 
-        ctx.notify(EnforceLocalLink {
+// Remove a link definition. If the provider for the link is running in this host, it is told
+// to release the actor, and any pooled clients it holds for the link are evicted
+impl Handler<RemoveLink> for MessageBus {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: RemoveLink, _ctx: &mut Context<Self>) -> Self::Result {
+        let link = self
+            .link_cache
+            .remove_link(&msg.actor, &msg.contract_id, &msg.link_name);
+        let link = match link {
+            Some(l) => l,
+            None => {
+                trace!("Ignoring removal of unknown link");
+                return Box::pin(async move {}.into_actor(self));
+            }
+        };
+        info!(
+            "Removing link {} -> {} ({})",
+            msg.actor, link.provider_id, msg.link_name
+        );
+        let at = self.now();
+        self.cache_history.record(
+            at,
+            self.key.as_ref().map(|k| k.public_key()),
+            CacheChangeKind::LinkRemoved {
+                actor: msg.actor.to_string(),
+                contract_id: msg.contract_id.to_string(),
+                link_name: msg.link_name.to_string(),
+            },
+        );
+        let key = LinkKey {
             actor: msg.actor.to_string(),
             contract_id: msg.contract_id.to_string(),
             link_name: msg.link_name.to_string(),
-        });
+        };
+        self.link_metrics.remove(&key);
+        self.link_failovers.remove(&key);
+        self.bound_links.remove(&key);
+        let cfg = crate::generated::core::CapabilityConfiguration {
+            module: msg.actor.to_string(),
+            values: link.values.clone(),
+        };
+        let host_id = self.key.as_ref().unwrap().public_key();
+        let mut invs = vec![];
+        for provider_id in link.providers() {
+            crate::pool::evict_link(&host_id, &provider_id, &msg.actor, &msg.link_name);
+            let target = WasccEntity::Capability {
+                id: provider_id,
+                contract_id: msg.contract_id.to_string(),
+                link_name: msg.link_name.to_string(),
+            };
+            if let Some(t) = self.subscribers.get(&target).cloned() {
+                let inv = Invocation::new(
+                    self.key.as_ref().unwrap(),
+                    WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+                    target,
+                    OP_REMOVE_ACTOR,
+                    crate::generated::core::serialize(&cfg).unwrap(),
+                );
+                invs.push((t, inv));
+            }
+        }
+        Box::pin(
+            async move {
+                for (t, inv) in invs {
+                    let _ = t.send(inv).await;
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<ClaimOrderedActor> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    /// Asks the lattice whether any other host owns the ordered actor. The host that owns it
+    /// answers on the actor's owner subject, so a reply from anyone but this host means the
+    /// actor can't be started here
+    fn handle(&mut self, msg: ClaimOrderedActor, _ctx: &mut Context<Self>) -> Self::Result {
+        let nc = match self.nc.clone() {
+            Some(nc) => nc,
+            None => return Box::pin(async move { Ok(()) }.into_actor(self)),
+        };
+        let subject = owner_subject(&self.namespace, &msg.actor);
+        let host_id = self.key.as_ref().unwrap().public_key();
+        Box::pin(
+            async move {
+                let reply = actix_rt::time::timeout(
+                    ORDERED_OWNER_PROBE_TIMEOUT,
+                    crate::inboxes::request(&nc, &host_id, &subject, &[]),
+                )
+                .await;
+                match reply {
+                    Ok(Ok(m)) => {
+                        let owner = String::from_utf8_lossy(&m.data).to_string();
+                        if owner == host_id {
+                            Ok(())
+                        } else {
+                            Err(format!(
+                                "Ordered actor {} is already running on host {}",
+                                msg.actor, owner
+                            )
+                            .into())
+                        }
+                    }
+                    _ => Ok(()),
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<QuiesceProvider> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: QuiesceProvider, _ctx: &mut Context<Self>) -> Self::Result {
+        let (target, recipient) = match self.provider_subscriber(&msg.provider_id, &msg.link_name) {
+            Some(t) => t,
+            None => {
+                let err = format!(
+                    "Provider {} with link name {} is not running in this host",
+                    msg.provider_id, msg.link_name
+                );
+                return Box::pin(async move { Err(err.into()) }.into_actor(self));
+            }
+        };
+        let op = if msg.quiesce { OP_QUIESCE } else { OP_RESUME };
+        let (entity, quiesce) = (target.clone(), msg.quiesce);
+        let inv = Invocation::new(
+            self.key.as_ref().unwrap(),
+            WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+            target.clone(),
+            op,
+            vec![],
+        );
+        let host_id = self.key.as_ref().unwrap().public_key();
+        Box::pin(
+            async move {
+                let ir = recipient.send(inv).await?;
+                if let Some(e) = ir.error {
+                    return Err(format!("Provider rejected {} request: {}", op, e).into());
+                }
+                if let WasccEntity::Capability {
+                    id,
+                    contract_id,
+                    link_name,
+                } = target
+                {
+                    let event = if msg.quiesce {
+                        ControlEvent::ProviderQuiesced {
+                            contract_id,
+                            link_name,
+                            provider_id: id,
+                        }
+                    } else {
+                        ControlEvent::ProviderResumed {
+                            contract_id,
+                            link_name,
+                            provider_id: id,
+                        }
+                    };
+                    ControlInterface::from_hostlocal_registry(&host_id)
+                        .do_send(PublishEvent { event });
+                }
+                Ok(())
+            }
+            .into_actor(self)
+            .map(move |res, act, _ctx| {
+                if res.is_ok() {
+                    if quiesce {
+                        act.quiesced.insert(entity);
+                    } else {
+                        act.quiesced.remove(&entity);
+                    }
+                }
+                res
+            }),
+        )
+    }
+}
+
+impl Handler<QueryQuiescedProviders> for MessageBus {
+    type Result = Vec<(String, String)>;
+
+    fn handle(&mut self, _msg: QueryQuiescedProviders, _ctx: &mut Context<Self>) -> Self::Result {
+        self.quiesced
+            .iter()
+            .filter_map(|e| match e {
+                WasccEntity::Capability { id, link_name, .. } => {
+                    Some((id.to_string(), link_name.to_string()))
+                }
+                _ => None,
+            })
+            .collect()
     }
 }
 
@@ -273,8 +612,34 @@ impl Handler<Initialize> for MessageBus {
         self.authorizer = Some(msg.auth);
         self.nc = msg.nc;
         self.namespace = msg.namespace;
+        self.zone = msg.zone;
+        self.slos = msg.slos;
+        self.alert_webhooks = msg.alert_webhooks;
+        self.event_format = msg.event_format;
+        self.payload_offload = msg.payload_offload;
+        self.max_response_size = msg.max_response_size;
+        self.bulkheads = msg
+            .bulkheads
+            .into_iter()
+            .map(|(contract_id, max)| (contract_id, super::bulkhead::Bulkhead::new(max)))
+            .collect();
+        self.readiness_gates = msg.readiness_gates;
+        self.issuer_bundles = msg.issuer_bundles;
+        self.mirrors = msg.mirrors;
+        self.archive = msg.archive;
         let ns = self.namespace.clone();
+        let zone = self.zone.clone();
         let timeout = msg.rpc_timeout.clone();
+        let claims_compression = msg.claims_compression;
+        let offload = self.payload_offload.clone();
+        self.hb_interval = msg.hb_interval;
+        self.hb_handle = Some(self.hb(ctx, msg.hb_interval));
+        self.evaluate_slos(ctx);
+        self.watch_slow_consumers(ctx);
+        self.expire_leased_links(ctx);
+        if let Some(grace) = msg.unused_capability_grace {
+            self.warn_unused_capabilities(ctx, grace);
+        }
         info!("Messagebus initialized");
         if let Some(nc) = self.nc.clone() {
             let rpc_outbound = RpcClient::default().start();
@@ -283,17 +648,24 @@ impl Handler<Initialize> for MessageBus {
             let bus = ctx.address().clone();
             let host_id = self.key.as_ref().unwrap().public_key();
             info!("Messagebus initializing with lattice RPC support");
+            let key = self.key.as_ref().unwrap().seed().unwrap();
+            let init = super::rpc_client::Initialize {
+                host_id,
+                key: Arc::new(KeyPair::from_seed(&key).unwrap()),
+                nc,
+                ns_prefix: ns,
+                bus,
+                rpc_timeout: timeout,
+                claims_compression,
+                zone,
+                offload,
+                legacy_gossip: msg.legacy_gossip,
+                hb_interval: msg.hb_interval,
+            };
+            self.rpc_init = Some(init.clone());
             Box::pin(
                 async move {
-                    let _ = target
-                        .send(super::rpc_client::Initialize {
-                            host_id,
-                            nc,
-                            ns_prefix: ns,
-                            bus,
-                            rpc_timeout: timeout,
-                        })
-                        .await;
+                    let _ = target.send(init).await;
                 }
                 .into_actor(self),
             )
@@ -308,19 +680,29 @@ impl Handler<AdvertiseLink> for MessageBus {
 
     fn handle(&mut self, msg: AdvertiseLink, ctx: &mut Context<Self>) -> Self::Result {
         trace!("Advertisting link definition");
-        self.link_cache.add_link(
+        self.record_link_set(
+            self.key.as_ref().map(|k| k.public_key()),
+            &msg.actor,
+            &msg.contract_id,
+            &msg.link_name,
+            &msg.provider_id,
+            &msg.values,
+        );
+        // Renewing a lease doesn't bind the link again
+        let changed = self.link_cache.add_link(
             &msg.actor,
             &msg.contract_id,
             &msg.link_name,
             &msg.provider_id,
             msg.values.clone(),
         );
-
-        ctx.notify(EnforceLocalLink {
-            actor: msg.actor.to_string(),
-            contract_id: msg.contract_id.to_string(),
-            link_name: msg.link_name.to_string(),
-        });
+        if changed {
+            ctx.notify(EnforceLocalLink {
+                actor: msg.actor.to_string(),
+                contract_id: msg.contract_id.to_string(),
+                link_name: msg.link_name.to_string(),
+            });
+        }
 
         let advlink = msg.clone();
 
@@ -342,20 +724,22 @@ impl Handler<AdvertiseClaims> for MessageBus {
 
     fn handle(&mut self, msg: AdvertiseClaims, ctx: &mut Context<Self>) -> Self::Result {
         trace!("Advertising claims");
+        self.record_claims_set(self.key.as_ref().map(|k| k.public_key()), &msg.claims);
         self.claims_cache
             .insert(msg.claims.subject.to_string(), msg.claims.clone());
 
         ctx.notify(EnforceLocalActorLinks {
             actor: msg.claims.subject.to_string(),
         });
+        self.apply_issuer_bundle(&msg.claims, ctx);
 
         let rpc = self.rpc_outbound.clone();
         Box::pin(
             async move {
-                if let Some(rpc) = rpc {
-                    let _ = rpc.send(msg).await;
+                match rpc {
+                    Some(rpc) => rpc.send(msg).await?,
+                    None => Ok(()),
                 }
-                Ok(())
             }
             .into_actor(self),
         )
@@ -370,6 +754,164 @@ impl Handler<Invocation> for MessageBus {
     /// is not local, _and_ there is a lattice provider configured, then the bus will attempt
     /// to satisfy that call via RPC over lattice.
     fn handle(&mut self, msg: Invocation, _ctx: &mut Context<Self>) -> Self::Result {
+        let msg = crate::sampling::decide(&self.key.as_ref().unwrap().public_key(), msg);
+        if let Some(ir) = self.refuse_shadow_call(&msg) {
+            return Box::pin(async move { ir }.into_actor(self));
+        }
+        let link = match (&msg.origin, &msg.target) {
+            (
+                WasccEntity::Actor(actor),
+                WasccEntity::Capability {
+                    contract_id,
+                    link_name,
+                    ..
+                },
+            ) => Some(LinkKey {
+                actor: actor.to_string(),
+                contract_id: contract_id.to_string(),
+                link_name: link_name.to_string(),
+            }),
+            _ => None,
+        };
+        let actor = match msg.target {
+            WasccEntity::Actor(ref a) => Some(a.to_string()),
+            _ => None,
+        };
+        let permit = match self.enter_bulkhead(&msg) {
+            Ok(p) => p,
+            Err(ir) => return Box::pin(async move { ir }.into_actor(self)),
+        };
+        self.record_call(&msg.origin, &msg.target);
+        let copy = self.mirror_invocation(&msg);
+        let tracked = self.in_flight.track();
+        let target = msg.target.clone();
+        let fut: Self::Result = Box::pin(self.route_when_ready(msg).map(move |ir, act, _ctx| {
+            drop(tracked);
+            cap_response(act.max_response_size, &target, ir)
+        }));
+        let fut: Self::Result = match copy {
+            Some(copy) => Box::pin(fut.map(move |ir, act, ctx| {
+                act.send_to_shadow(copy, ir.clone(), ctx);
+                ir
+            })),
+            None => fut,
+        };
+        if link.is_none() && actor.is_none() && permit.is_none() {
+            return fut;
+        }
+        let started = Instant::now();
+        Box::pin(fut.map(move |ir, act, _ctx| {
+            // The bulkhead slot is held until the invocation has been answered
+            drop(permit);
+            let elapsed = started.elapsed();
+            let success = ir.error.is_none();
+            let oversized = ir.too_large;
+            let now = crate::clock::monotonic(&act.key.as_ref().unwrap().public_key());
+            if let Some(key) = link {
+                let metrics = act.link_metrics.entry(key).or_default();
+                metrics.record(now, elapsed, success);
+                if oversized {
+                    metrics.too_large += 1;
+                }
+            }
+            if let Some(actor) = actor {
+                let metrics = act.actor_metrics.entry(actor).or_default();
+                metrics.record(now, elapsed, success);
+                if oversized {
+                    metrics.too_large += 1;
+                }
+            }
+            ir
+        }))
+    }
+}
+
+impl Handler<ProbeProvider> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<HealthResponse>>;
+
+    fn handle(&mut self, msg: ProbeProvider, _ctx: &mut Context<Self>) -> Self::Result {
+        let (target, recipient) = match self.provider_subscriber(&msg.provider_id, &msg.link_name) {
+            Some(t) => t,
+            None => {
+                let err = format!(
+                    "Provider {} with link name {} is not running in this host",
+                    msg.provider_id, msg.link_name
+                );
+                return Box::pin(async move { Err(err.into()) }.into_actor(self));
+            }
+        };
+        let ping = generate_ping(&target, self.key.as_ref().unwrap());
+        Box::pin(
+            async move {
+                let ir = recipient.send(ping).await?;
+                if let Some(e) = ir.error {
+                    return Err(format!("Provider failed health check: {}", e).into());
+                }
+                deserialize(&ir.msg)
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl MessageBus {
+    // The archive record of an actor or provider instance being stopped: an actor's claims,
+    // links and metrics, or the links a provider serves and their metrics
+    fn archive_record(&self, entity: &WasccEntity) -> crate::archive::ArchivedEntity {
+        let links: HashMap<_, _> = self
+            .link_cache
+            .all()
+            .into_iter()
+            .filter(|(k, v)| match entity {
+                WasccEntity::Actor(a) => &k.actor == a,
+                WasccEntity::Capability {
+                    id,
+                    contract_id,
+                    link_name,
+                } => {
+                    &k.contract_id == contract_id
+                        && &k.link_name == link_name
+                        && v.providers().contains(id)
+                }
+            })
+            .collect();
+        let metrics = match entity {
+            WasccEntity::Actor(a) => self.actor_metrics.get(a).into_iter().collect(),
+            WasccEntity::Capability { .. } => links
+                .keys()
+                .filter_map(|k| self.link_metrics.get(k))
+                .collect(),
+        };
+        let host_id = self.key.as_ref().unwrap().public_key();
+        crate::archive::record(
+            &host_id,
+            entity,
+            self.claims_cache.get(&entity.key()).cloned(),
+            &links,
+            metrics,
+            crate::clock::monotonic(&host_id),
+            crate::clock::now_millis(&host_id),
+        )
+    }
+
+    // The entity and recipient of a provider instance running in this host
+    fn provider_subscriber(
+        &self,
+        provider_id: &str,
+        link_name: &str,
+    ) -> Option<(WasccEntity, Recipient<Invocation>)> {
+        self.subscribers.iter().find_map(|(e, r)| match e {
+            WasccEntity::Capability {
+                id, link_name: ln, ..
+            } if id == provider_id && ln == link_name => Some((e.clone(), r.clone())),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn route_invocation(
+        &mut self,
+        msg: Invocation,
+    ) -> ResponseActFuture<Self, InvocationResponse> {
         trace!(
             "{}: Handling invocation from {} to {}",
             self.key.as_ref().unwrap().public_key(),
@@ -388,6 +930,9 @@ impl Handler<Invocation> for MessageBus {
                 }.into_actor(self)
             );
         }
+        if let Some((link, candidates)) = self.failover_candidates(&msg) {
+            return self.route_with_failover(link, candidates);
+        }
         let subscribers = self.subscribers.clone();
         match subscribers.get(&msg.target) {
             Some(target) => {
@@ -423,9 +968,26 @@ impl Handler<Invocation> for MessageBus {
                 } else {
                     trace!("Deferring invocation to lattice (no local subscribers)");
                     let rpc = self.rpc_outbound.clone().unwrap();
+                    let ordered = match msg.target {
+                        WasccEntity::Actor(ref a) => self
+                            .claims_cache
+                            .get(a)
+                            .filter(|c| is_ordered(c))
+                            .map(|_| a.to_string()),
+                        _ => None,
+                    };
                     Box::pin(
                         async move {
-                            let ir = rpc.send(msg.clone()).await;
+                            let ir = match ordered {
+                                Some(actor) => {
+                                    rpc.send(OrderedInvocation {
+                                        actor,
+                                        invocation: msg.clone(),
+                                    })
+                                    .await
+                                }
+                                None => rpc.send(msg.clone()).await,
+                            };
                             match ir {
                                 Ok(ir) => ir,
                                 Err(e) => InvocationResponse::error(
@@ -442,6 +1004,23 @@ impl Handler<Invocation> for MessageBus {
     }
 }
 
+impl Handler<FindProvider> for MessageBus {
+    type Result = Option<String>;
+
+    fn handle(&mut self, msg: FindProvider, _ctx: &mut Self::Context) -> Self::Result {
+        self.subscribers.keys().find_map(|e| match e {
+            WasccEntity::Capability {
+                id,
+                contract_id,
+                link_name,
+            } if *contract_id == msg.contract_id && *link_name == msg.link_name => {
+                Some(id.to_string())
+            }
+            _ => None,
+        })
+    }
+}
+
 impl Handler<LookupLink> for MessageBus {
     type Result = Option<String>;
 
@@ -464,33 +1043,73 @@ impl Handler<Subscribe> for MessageBus {
         }
 
         trace!("Bus registered interest for {}", &msg.interest.url());
+        if let WasccEntity::Actor(ref actor) = msg.interest {
+            self.actor_started.insert(actor.to_string(), Instant::now());
+        }
 
+        // Ordered actors advertise their claims before subscribing, so the claims are known here
+        let owner = match msg.interest {
+            WasccEntity::Actor(ref a) if self.claims_cache.get(a).map_or(false, is_ordered) => {
+                Some(self.key.as_ref().unwrap().public_key())
+            }
+            _ => None,
+        };
         let nc = self.nc.clone();
         let ns = self.namespace.clone();
+        let zone = self.zone.clone();
+        let offload = self.payload_offload.clone();
+        let max_response_size = self.max_response_size;
+        let host_id = self.key.as_ref().unwrap().public_key();
         Box::pin(
             async move {
                 let interest = msg.interest.clone();
-                if interest.key() == EXTRAS_PUBLIC_KEY {
-                    return (interest, msg.subscriber); // extras are not available over lattice as all hosts have it
+                if interest.key() == EXTRAS_PUBLIC_KEY
+                    || interest.key() == DISCOVERY_PUBLIC_KEY
+                    || interest.key() == ROUTER_PUBLIC_KEY
+                    || interest.key() == GRPC_PUBLIC_KEY
+                    || interest.key() == KEYVALUE_PUBLIC_KEY
+                {
+                    return (interest, msg.subscriber, None); // built-ins are not available over lattice as they resolve against local config
                 }
-                let address = if let Some(ref nc) = nc {
+                if let Some(ref nc) = nc {
                     let addr = RpcSubscription::default().start();
+                    let stats = Arc::new(SubscriptionStats::default());
+                    let subject = match owner {
+                        Some(_) => ordered_subject(&ns, &interest.key()),
+                        None => invoke_subject(&ns, &interest),
+                    };
                     let _ = addr
                         .send(CreateSubscription {
                             entity: msg.interest.clone(),
                             target: msg.subscriber,
                             nc: Arc::new(nc.clone()),
                             namespace: ns,
+                            zone,
+                            host_id,
+                            owner,
+                            stats: stats.clone(),
+                            offload,
+                            max_response_size,
                         })
                         .await;
-                    addr.recipient() // RPC subscriber proxy
+                    // RPC subscriber proxy
+                    (
+                        interest,
+                        addr.clone().recipient(),
+                        Some((addr, subject, stats)),
+                    )
                 } else {
-                    msg.subscriber // Actual subscriber
-                };
-                (interest, address)
+                    (interest, msg.subscriber, None) // Actual subscriber
+                }
             }
             .into_actor(self)
-            .map(|(entity, res), act, _ctx| {
+            .map(|(entity, res, rpcsub), act, _ctx| {
+                if let Some((rpcsub, subject, stats)) = rpcsub {
+                    act.rpc_subscriptions.insert(entity.clone(), rpcsub);
+                    act.subscription_stats
+                        .insert(entity.clone(), (subject, stats));
+                    act.beat_zone();
+                }
                 act.subscribers.insert(entity, res);
             }),
         )
@@ -502,9 +1121,129 @@ impl Handler<Unsubscribe> for MessageBus {
 
     fn handle(&mut self, msg: Unsubscribe, _ctx: &mut Context<Self>) {
         trace!("Bus removing interest for {}", msg.interest.url());
+        if let Some(ref archive) = self.archive {
+            archive.keep(self.archive_record(&msg.interest));
+        }
         if let None = self.subscribers.remove(&msg.interest) {
             warn!("Attempted to remove a non-existent subscriber");
         }
+        if let WasccEntity::Actor(ref actor) = msg.interest {
+            self.actor_started.remove(actor);
+            self.call_graph.remove(actor);
+            self.unused_warned.retain(|(a, _)| a != actor);
+            // An actor that starts again is judged only on its new invocations
+            self.actor_metrics.remove(actor);
+            self.slo_breaches.remove(actor);
+        }
+        // Release the lattice subscription for this entity, if there is one
+        if let Some(rpcsub) = self.rpc_subscriptions.remove(&msg.interest) {
+            rpcsub.do_send(Shutdown);
+            self.beat_zone();
+        }
+        self.subscription_stats.remove(&msg.interest);
+        self.slow_consumers.remove(&msg.interest.key());
+        self.remove_push_queues(&msg.interest);
+        self.quiesced.remove(&msg.interest);
+    }
+}
+
+impl Handler<DrainBus> for MessageBus {
+    type Result = ResponseActFuture<Self, BusDrained>;
+
+    fn handle(&mut self, msg: DrainBus, _ctx: &mut Context<Self>) -> Self::Result {
+        let in_flight = self.in_flight.clone();
+        let at_start = in_flight.count();
+        let rpc = self.rpc_outbound.clone();
+        Box::pin(
+            async move {
+                let aborted = in_flight.settle(msg.deadline).await;
+                if aborted > 0 {
+                    warn!("Stopping with {} invocations still in flight", aborted);
+                }
+                let claims_flush = match rpc {
+                    Some(rpc) => rpc
+                        .send(FlushClaims)
+                        .timeout(msg.deadline)
+                        .await
+                        .unwrap_or_else(|e| FlushStatus::Failed(e.to_string())),
+                    None => FlushStatus::Nothing,
+                };
+                BusDrained {
+                    drained: at_start.saturating_sub(aborted),
+                    aborted,
+                    claims_flush,
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<Ping> for MessageBus {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Context<Self>) {}
+}
+
+impl Handler<RestartHeartbeat> for MessageBus {
+    type Result = ();
+
+    fn handle(&mut self, _msg: RestartHeartbeat, ctx: &mut Context<Self>) {
+        warn!("Restarting the heartbeat");
+        if let Some(handle) = self.hb_handle.take() {
+            ctx.cancel_future(handle);
+        }
+        self.hb_handle = Some(self.hb(ctx, self.hb_interval));
+    }
+}
+
+impl Handler<RestartCacheSync> for MessageBus {
+    type Result = ResponseActFuture<Self, Option<Addr<RpcClient>>>;
+
+    fn handle(&mut self, _msg: RestartCacheSync, _ctx: &mut Context<Self>) -> Self::Result {
+        let init = match self.rpc_init.clone() {
+            Some(init) => init,
+            None => return Box::pin(async { None }.into_actor(self)),
+        };
+        warn!("Restarting the lattice RPC client");
+        if let Some(rpc) = self.rpc_outbound.take() {
+            rpc.do_send(Shutdown);
+        }
+        let rpc = RpcClient::default().start();
+        self.rpc_outbound = Some(rpc.clone());
+        Box::pin(
+            async move {
+                let _ = rpc.send(init).await;
+                Some(rpc)
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<QueryRpcClient> for MessageBus {
+    type Result = Option<Addr<RpcClient>>;
+
+    fn handle(&mut self, _msg: QueryRpcClient, _ctx: &mut Context<Self>) -> Self::Result {
+        self.rpc_outbound.clone()
+    }
+}
+
+impl Handler<Shutdown> for MessageBus {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) {
+        info!("Message bus shutting down");
+        for (_entity, rpcsub) in self.rpc_subscriptions.drain() {
+            rpcsub.do_send(Shutdown);
+        }
+        self.subscription_stats.clear();
+        if let Some(rpc) = self.rpc_outbound.take() {
+            rpc.do_send(Shutdown);
+        }
+        self.subscribers.clear();
+        self.nc = None;
+        ctx.stop();
     }
 }
 
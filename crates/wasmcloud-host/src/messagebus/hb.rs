@@ -4,6 +4,7 @@ use crate::control_interface::events::RunState;
 use crate::generated::core::{deserialize, serialize, HealthRequest, HealthResponse};
 use crate::hlreg::HostLocalSystemService;
 use crate::messagebus::handlers::OP_HEALTH_REQUEST;
+use crate::messagebus::rpc_client::BeatZone;
 use crate::Result;
 use crate::{ControlEvent, Invocation, WasccEntity, SYSTEM_ACTOR};
 use actix::prelude::*;
@@ -12,14 +13,14 @@ use std::time::Duration;
 use wascap::prelude::KeyPair;
 
 const HEARTBEAT_INTERVAL_ENV_VAR: &str = "HEARTBEAT_INTERVAL_S";
-const DEFAULT_HEARTBEAT_INTERVAL: u16 = 30;
-const PING_TIMEOUT_MS: u64 = 200;
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const PING_TIMEOUT: Duration = Duration::from_millis(200);
 
 impl MessageBus {
-    pub(crate) fn hb(&self, ctx: &mut Context<Self>) {
+    pub(crate) fn hb(&self, ctx: &mut Context<Self>, interval: Duration) -> SpawnHandle {
         trace!("Emitting heartbeat");
-        let interval = hb_duration();
         ctx.run_interval(interval, |act, ctx| {
+            act.beat_zone();
             let claims = act.claims_cache.values().cloned().collect();
             let subs = act.subscribers.clone();
             let entities: Vec<(_, _)> = subs.into_iter().collect();
@@ -28,13 +29,34 @@ impl MessageBus {
 
             ctx.wait(
                 async move {
-                    let evt = generate_heartbeat_event(entities, claims, seed).await;
+                    let evt = generate_heartbeat_event(entities.clone(), claims, seed).await;
                     let cp = ControlInterface::from_hostlocal_registry(&host_id);
+                    if let ControlEvent::Heartbeat {
+                        entities: ref states,
+                        ..
+                    } = evt
+                    {
+                        for event in unhealthy_providers(&entities, states) {
+                            cp.do_send(PublishEvent { event });
+                        }
+                    }
                     cp.do_send(PublishEvent { event: evt });
+                    crate::watchdog::beat(&host_id, crate::watchdog::Subsystem::Heartbeat);
                 }
                 .into_actor(act),
             );
-        });
+        })
+    }
+}
+
+impl MessageBus {
+    // Tells the other hosts in this host's zone which entities they can reach here
+    pub(crate) fn beat_zone(&self) {
+        if let Some(ref rpc) = self.rpc_outbound {
+            rpc.do_send(BeatZone {
+                entities: self.rpc_subscriptions.keys().cloned().collect(),
+            });
+        }
     }
 }
 
@@ -49,7 +71,7 @@ async fn generate_heartbeat_event(
     }
 }
 
-async fn healthping_subscribers(
+pub(crate) async fn healthping_subscribers(
     subs: &[(WasccEntity, Recipient<Invocation>)],
     seed: String,
 ) -> HashMap<String, RunState> {
@@ -57,10 +79,7 @@ async fn healthping_subscribers(
     let mut hm = HashMap::new();
     for (subscriber, recipient) in subs {
         let ping = generate_ping(subscriber, &key);
-        let pong = recipient
-            .send(ping)
-            .timeout(Duration::from_millis(PING_TIMEOUT_MS))
-            .await;
+        let pong = recipient.send(ping).timeout(PING_TIMEOUT).await;
         match pong {
             Ok(ir) => {
                 let hr: Result<HealthResponse> = deserialize(&ir.msg);
@@ -96,7 +115,32 @@ async fn healthping_subscribers(
     hm
 }
 
-fn generate_ping(target: &WasccEntity, key: &KeyPair) -> Invocation {
+// A `ProviderUnhealthy` event for each provider instance that failed its health check
+fn unhealthy_providers(
+    subs: &[(WasccEntity, Recipient<Invocation>)],
+    states: &HashMap<String, RunState>,
+) -> Vec<ControlEvent> {
+    subs.iter()
+        .filter_map(|(entity, _)| match (entity, states.get(&entity.key())) {
+            (
+                WasccEntity::Capability {
+                    id,
+                    contract_id,
+                    link_name,
+                },
+                Some(RunState::Unhealthy(message)),
+            ) => Some(ControlEvent::ProviderUnhealthy {
+                contract_id: contract_id.to_string(),
+                link_name: link_name.to_string(),
+                provider_id: id.to_string(),
+                message: message.to_string(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn generate_ping(target: &WasccEntity, key: &KeyPair) -> Invocation {
     Invocation::new(
         key,
         WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
@@ -106,9 +150,12 @@ fn generate_ping(target: &WasccEntity, key: &KeyPair) -> Invocation {
     )
 }
 
-fn hb_duration() -> Duration {
-    match std::env::var(HEARTBEAT_INTERVAL_ENV_VAR) {
-        Ok(s) => Duration::from_secs(s.parse().unwrap_or(DEFAULT_HEARTBEAT_INTERVAL as u64)),
-        Err(_) => Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL as u64),
-    }
+/// The heartbeat interval used when one isn't supplied to the host builder. This can
+/// still be overridden (in seconds) via the `HEARTBEAT_INTERVAL_S` environment variable
+pub(crate) fn default_hb_duration() -> Duration {
+    std::env::var(HEARTBEAT_INTERVAL_ENV_VAR)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL)
 }
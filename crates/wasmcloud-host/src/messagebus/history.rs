@@ -0,0 +1,252 @@
+// The lattice cache (the link definitions and actor claims a host knows about) only holds the
+// current state, which doesn't help much when reviewing an incident that involved a binding
+// changing underneath an actor. Each host keeps a bounded history of the changes made to its
+// cache: what was set or removed, when, and by which host. Links and claims advertised on the
+// lattice name the host that issued them in the reply subject of their message (nothing replies
+// to advertisements, so hosts that don't know about this are unaffected). Advertisements that
+// don't change anything, such as a host's own advertisements echoed back to it, aren't recorded.
+
+use super::MessageBus;
+use actix::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
+
+const CACHE_HISTORY_CAPACITY: usize = 1024;
+
+/// A change made to the lattice cache of a host
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheChange {
+    pub at: SystemTime,
+    /// The public key of the host that issued the change, if known. Advertisements from hosts
+    /// that don't name themselves have no origin
+    pub origin: Option<String>,
+    pub kind: CacheChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheChangeKind {
+    LinkSet {
+        actor: String,
+        contract_id: String,
+        link_name: String,
+        provider_id: String,
+        values: HashMap<String, String>,
+    },
+    LinkRemoved {
+        actor: String,
+        contract_id: String,
+        link_name: String,
+    },
+    ClaimsSet {
+        actor: String,
+        /// The ID of the claims token
+        claims_id: String,
+    },
+}
+
+/// Selects the changes of interest in a cache history. Fields left empty match everything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheHistoryFilter {
+    pub actor: Option<String>,
+    pub contract_id: Option<String>,
+    pub link_name: Option<String>,
+}
+
+impl CacheHistoryFilter {
+    fn matches(&self, kind: &CacheChangeKind) -> bool {
+        let (actor, link) = match kind {
+            CacheChangeKind::LinkSet {
+                actor,
+                contract_id,
+                link_name,
+                ..
+            }
+            | CacheChangeKind::LinkRemoved {
+                actor,
+                contract_id,
+                link_name,
+            } => (actor, Some((contract_id, link_name))),
+            CacheChangeKind::ClaimsSet { actor, .. } => (actor, None),
+        };
+        // Claims never match a filter on a link's fields
+        let (contract_id, link_name) = match link {
+            Some((c, l)) => (Some(c), Some(l)),
+            None => (None, None),
+        };
+        self.actor.as_ref().map_or(true, |a| a == actor)
+            && self
+                .contract_id
+                .as_ref()
+                .map_or(true, |c| Some(c) == contract_id)
+            && self
+                .link_name
+                .as_ref()
+                .map_or(true, |l| Some(l) == link_name)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CacheHistory {
+    changes: VecDeque<CacheChange>,
+}
+
+impl CacheHistory {
+    pub fn record(&mut self, at: SystemTime, origin: Option<String>, kind: CacheChangeKind) {
+        if self.changes.len() == CACHE_HISTORY_CAPACITY {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(CacheChange { at, origin, kind });
+    }
+
+    /// The matching changes made at or after the given time, oldest first
+    pub fn query(&self, filter: &CacheHistoryFilter, since: SystemTime) -> Vec<CacheChange> {
+        self.changes
+            .iter()
+            .filter(|c| c.at >= since && filter.matches(&c.kind))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<CacheChange>")]
+pub(crate) struct QueryCacheHistory {
+    pub filter: CacheHistoryFilter,
+    pub since: SystemTime,
+}
+
+impl Handler<QueryCacheHistory> for MessageBus {
+    type Result = MessageResult<QueryCacheHistory>;
+
+    fn handle(&mut self, msg: QueryCacheHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.cache_history.query(&msg.filter, msg.since))
+    }
+}
+
+impl MessageBus {
+    // The current time by the host's clock
+    pub(crate) fn now(&self) -> SystemTime {
+        crate::clock::now(&self.key.as_ref().unwrap().public_key())
+    }
+
+    // Records a link being set, unless the cache already holds it as it is
+    pub(crate) fn record_link_set(
+        &mut self,
+        origin: Option<String>,
+        actor: &str,
+        contract_id: &str,
+        link_name: &str,
+        provider_id: &str,
+        values: &HashMap<String, String>,
+    ) {
+        let key = crate::capability::link_cache::LinkKey {
+            actor: actor.to_string(),
+            contract_id: contract_id.to_string(),
+            link_name: link_name.to_string(),
+        };
+        if let Some(existing) = self.link_cache.get(&key) {
+            if existing.provider_id == provider_id && &existing.values == values {
+                return;
+            }
+        }
+        let at = self.now();
+        self.cache_history.record(
+            at,
+            origin,
+            CacheChangeKind::LinkSet {
+                actor: key.actor,
+                contract_id: key.contract_id,
+                link_name: key.link_name,
+                provider_id: provider_id.to_string(),
+                values: values.clone(),
+            },
+        );
+    }
+
+    // Records an actor's claims being set, unless the cache already holds the same claims
+    pub(crate) fn record_claims_set(
+        &mut self,
+        origin: Option<String>,
+        claims: &wascap::jwt::Claims<wascap::jwt::Actor>,
+    ) {
+        if self
+            .claims_cache
+            .get(&claims.subject)
+            .map_or(false, |c| c.id == claims.id)
+        {
+            return;
+        }
+        let at = self.now();
+        self.cache_history.record(
+            at,
+            origin,
+            CacheChangeKind::ClaimsSet {
+                actor: claims.subject.to_string(),
+                claims_id: claims.id.to_string(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheChangeKind, CacheHistory, CacheHistoryFilter};
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    fn link_set(actor: &str, contract_id: &str) -> CacheChangeKind {
+        CacheChangeKind::LinkSet {
+            actor: actor.to_string(),
+            contract_id: contract_id.to_string(),
+            link_name: "default".to_string(),
+            provider_id: "Vxxx".to_string(),
+            values: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn history_is_filtered_and_bounded() {
+        let start = SystemTime::now();
+        let mut history = CacheHistory::default();
+        history.record(
+            start,
+            Some("N1".to_string()),
+            link_set("Ma", "wascc:keyvalue"),
+        );
+        history.record(start, None, link_set("Mb", "wascc:http_server"));
+        history.record(
+            start,
+            Some("N2".to_string()),
+            CacheChangeKind::ClaimsSet {
+                actor: "Ma".to_string(),
+                claims_id: "abc".to_string(),
+            },
+        );
+
+        let all = history.query(&CacheHistoryFilter::default(), start);
+        assert_eq!(3, all.len());
+        let ma = CacheHistoryFilter {
+            actor: Some("Ma".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(2, history.query(&ma, start).len());
+        let kv = CacheHistoryFilter {
+            contract_id: Some("wascc:keyvalue".to_string()),
+            ..Default::default()
+        };
+        let kv = history.query(&kv, start);
+        assert_eq!(1, kv.len());
+        assert_eq!(Some("N1".to_string()), kv[0].origin);
+        assert!(history
+            .query(
+                &CacheHistoryFilter::default(),
+                SystemTime::now() + Duration::from_secs(60)
+            )
+            .is_empty());
+
+        for _ in 0..super::CACHE_HISTORY_CAPACITY {
+            history.record(start, None, link_set("Mc", "wascc:logging"));
+        }
+        assert!(history.query(&ma, start).is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+// A link can be leased rather than permanent, for short-lived integrations such as preview
+// environments or temporary debug bindings. The lease's expiry travels with the link definition
+// as a link value, so every host in the lattice knows it and removes the link (unbinding it from
+// its providers) on its own once the lease runs out by the host's clock. Only the host running
+// the link's provider publishes the expiry as an event, so the lattice sees it once per provider
+// rather than once per host. A creator keeps its link by advertising it again with a later expiry
+// before then; a renewal only moves the expiry, and doesn't bind the link to its providers again.
+
+use super::{MessageBus, RemoveLink};
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::dispatch::WasccEntity;
+use crate::hlreg::HostLocalSystemService;
+use crate::links::lease_expiry;
+use crate::ControlEvent;
+use actix::prelude::*;
+use std::time::Duration;
+
+const LEASE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+impl MessageBus {
+    /// Periodically removes the links whose leases have run out, publishing an event for each
+    /// whose provider runs in this host
+    pub(crate) fn expire_leased_links(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(LEASE_SWEEP_INTERVAL, |act, ctx| {
+            let host_id = act.key.as_ref().unwrap().public_key();
+            let now = crate::clock::now_secs(&host_id);
+            for (key, link) in act.link_cache.all() {
+                match lease_expiry(&link.values) {
+                    Some(expires) if expires <= now => {}
+                    _ => continue,
+                }
+                info!(
+                    "Lease on link {} -> {} ({}) expired",
+                    key.actor, link.provider_id, key.link_name
+                );
+                ctx.notify(RemoveLink {
+                    actor: key.actor.to_string(),
+                    contract_id: key.contract_id.to_string(),
+                    link_name: key.link_name.to_string(),
+                });
+                let provider = WasccEntity::Capability {
+                    id: link.provider_id.to_string(),
+                    contract_id: key.contract_id.to_string(),
+                    link_name: key.link_name.to_string(),
+                };
+                if !act.subscribers.contains_key(&provider) {
+                    continue;
+                }
+                ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent {
+                    event: ControlEvent::LinkExpired {
+                        actor: key.actor,
+                        contract_id: key.contract_id,
+                        link_name: key.link_name,
+                        provider_id: link.provider_id,
+                    },
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,307 @@
+// Before a rewritten actor replaces the one in production, it helps to know that it answers the
+// same way on real traffic. A mirror duplicates a share of the invocations made to an actor and
+// sends the copies to a shadow actor once the actor has answered. The caller only ever sees the
+// actor's response; the shadow's response is compared with it and then discarded, and the host
+// counts how many responses matched and publishes an event for each that didn't.
+//
+// Copies carry the `BAGGAGE_MIRRORED` baggage item, and are never mirrored again. Baggage follows
+// every invocation made while handling a copy, so the shadow can still call other actors, but the
+// host refuses any call it (or an actor it calls) makes to a capability provider: those calls are
+// real, and a shadow writing to the same stores as production would write everything twice. A
+// shadow that needs a provider to answer the way the actor does sees an error instead, and the
+// refusals are counted in the mirror's stats so that its mismatches can be told apart.
+
+use super::MessageBus;
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::hlreg::HostLocalSystemService;
+use crate::messagebus::handlers::OP_HEALTH_REQUEST;
+use crate::{ControlEvent, Invocation, InvocationResponse, Result, WasccEntity};
+use actix::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The baggage item marking an invocation as a mirrored copy sent to a shadow actor
+pub const BAGGAGE_MIRRORED: &str = "wasmcloud-mirrored";
+
+// The resolution of mirroring percentages
+const PERCENT_BUCKETS: u64 = 10_000;
+
+/// Sends a share of an actor's invocations to a shadow actor as well. See
+/// `HostBuilder::with_mirror`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Mirror {
+    pub shadow: String,
+    pub percent: f64,
+    pub stats: MirrorStats,
+}
+
+/// How the responses of a shadow actor compared with those of the actor it mirrors
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MirrorStats {
+    /// The invocations copied to the shadow
+    pub mirrored: u64,
+    /// The copies the shadow answered the same way as the actor
+    pub matched: u64,
+    /// The copies the shadow answered differently, including those only one of them failed
+    pub mismatched: u64,
+    /// The calls to capability providers refused while the shadow handled copies
+    pub provider_calls_refused: u64,
+}
+
+impl Mirror {
+    pub fn new(shadow: &str, percent: f64) -> Mirror {
+        Mirror {
+            shadow: shadow.to_string(),
+            percent,
+            stats: MirrorStats::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if (0.0..=100.0).contains(&self.percent) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Mirroring percentage {} is not between 0 and 100",
+                self.percent
+            )
+            .into())
+        }
+    }
+
+    // Whether the invocation with the given ID is copied to the shadow
+    fn selects(&self, id: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        ((hasher.finish() % PERCENT_BUCKETS) as f64) < self.percent / 100.0 * PERCENT_BUCKETS as f64
+    }
+}
+
+/// Starts (or, without a shadow, stops) mirroring an actor's invocations
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct SetMirror {
+    pub actor: String,
+    pub mirror: Option<Mirror>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<MirrorStats>")]
+pub(crate) struct QueryMirrorStats {
+    pub actor: String,
+}
+
+impl Handler<SetMirror> for MessageBus {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetMirror, _ctx: &mut Context<Self>) {
+        match msg.mirror {
+            Some(mirror) => {
+                info!(
+                    "Mirroring {}% of invocations of {} to {}",
+                    mirror.percent, msg.actor, mirror.shadow
+                );
+                self.mirrors.insert(msg.actor, mirror);
+            }
+            None => {
+                self.mirrors.remove(&msg.actor);
+            }
+        }
+    }
+}
+
+impl Handler<QueryMirrorStats> for MessageBus {
+    type Result = Option<MirrorStats>;
+
+    fn handle(&mut self, msg: QueryMirrorStats, _ctx: &mut Context<Self>) -> Self::Result {
+        self.mirrors.get(&msg.actor).map(|m| m.stats.clone())
+    }
+}
+
+impl MessageBus {
+    /// The copy of the invocation to send to a shadow actor, if its target is mirrored and the
+    /// invocation is among the share selected
+    pub(crate) fn mirror_invocation(&mut self, inv: &Invocation) -> Option<Invocation> {
+        let actor = match inv.target {
+            WasccEntity::Actor(ref a) => a,
+            _ => return None,
+        };
+        if inv.operation == OP_HEALTH_REQUEST
+            || inv.baggage.contains_key(BAGGAGE_MIRRORED)
+            || inv.baggage.len() >= crate::MAX_BAGGAGE_ITEMS
+        {
+            return None;
+        }
+        let mirror = self.mirrors.get_mut(actor).filter(|m| m.selects(&inv.id))?;
+        mirror.stats.mirrored += 1;
+        let mut baggage = inv.baggage.clone();
+        baggage.insert(BAGGAGE_MIRRORED.to_string(), actor.to_string());
+        Some(
+            Invocation::new(
+                self.key.as_ref().unwrap(),
+                inv.origin.clone(),
+                WasccEntity::Actor(mirror.shadow.to_string()),
+                &inv.operation,
+                inv.msg.clone(),
+            )
+            .with_baggage(baggage),
+        )
+    }
+
+    /// The refusal of an invocation of a capability provider made while handling a mirrored
+    /// copy, or `None` if the invocation isn't one
+    pub(crate) fn refuse_shadow_call(&mut self, inv: &Invocation) -> Option<InvocationResponse> {
+        if let WasccEntity::Actor(_) = inv.target {
+            return None;
+        }
+        let actor = inv.baggage.get(BAGGAGE_MIRRORED)?;
+        if let Some(mirror) = self.mirrors.get_mut(actor) {
+            mirror.stats.provider_calls_refused += 1;
+        }
+        trace!(
+            "Refusing call from {} to {} made for a mirrored copy of an invocation of {}",
+            inv.origin_url(),
+            inv.target_url(),
+            actor
+        );
+        Some(InvocationResponse::error(
+            inv,
+            "Capability providers can't be invoked while handling a mirrored invocation",
+        ))
+    }
+
+    /// Sends the copy to the shadow actor, comparing its response with the actor's when it
+    /// arrives. The caller doesn't wait for the shadow
+    pub(crate) fn send_to_shadow(
+        &mut self,
+        copy: Invocation,
+        primary: InvocationResponse,
+        ctx: &mut Context<Self>,
+    ) {
+        let actor = copy
+            .baggage
+            .get(BAGGAGE_MIRRORED)
+            .cloned()
+            .unwrap_or_default();
+        let operation = copy.operation.to_string();
+        let shadow = copy.target.clone();
+        ctx.spawn(self.route_invocation(copy).map(move |ir, act, _ctx| {
+            let mirror = match act.mirrors.get_mut(&actor) {
+                Some(m) => m,
+                None => return,
+            };
+            if same_response(&primary, &ir) {
+                mirror.stats.matched += 1;
+                return;
+            }
+            mirror.stats.mismatched += 1;
+            warn!(
+                "Shadow {} answered {} differently from {} (error: {:?})",
+                mirror.shadow, operation, actor, ir.error
+            );
+            if let WasccEntity::Actor(shadow) = shadow {
+                let host_id = act.key.as_ref().unwrap().public_key();
+                ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent {
+                    event: ControlEvent::ActorMirrorMismatch {
+                        actor,
+                        shadow,
+                        operation,
+                    },
+                });
+            }
+        }));
+    }
+}
+
+// Whether the shadow answered the same way as the actor: both with the same payload, or both
+// with an error (the text of errors often holds details, such as IDs, that differ every time)
+fn same_response(primary: &InvocationResponse, shadow: &InvocationResponse) -> bool {
+    match (&primary.error, &shadow.error) {
+        (None, None) => primary.msg == shadow.msg,
+        (Some(_), Some(_)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{same_response, Mirror, BAGGAGE_MIRRORED};
+    use crate::messagebus::MessageBus;
+    use crate::{Invocation, InvocationResponse, WasccEntity};
+    use wascap::prelude::KeyPair;
+
+    #[test]
+    fn mirrors_select_a_share_and_compare_responses() {
+        let ids: Vec<String> = (0..1000).map(|i| format!("inv-{}", i)).collect();
+        let quarter = Mirror::new("Mshadow", 25.0);
+        let selected = ids.iter().filter(|id| quarter.selects(id)).count();
+        assert!(selected > 150 && selected < 350);
+        assert!(ids
+            .iter()
+            .all(|id| Mirror::new("Mshadow", 100.0).selects(id)));
+        assert!(Mirror::new("Mshadow", 101.0).validate().is_err());
+
+        let response = |msg: &[u8], error: Option<&str>| InvocationResponse {
+            msg: msg.to_vec(),
+            error: error.map(|e| e.to_string()),
+            invocation_id: "inv-1".to_string(),
+            too_large: false,
+        };
+        assert!(same_response(
+            &response(b"ok", None),
+            &response(b"ok", None)
+        ));
+        assert!(!same_response(
+            &response(b"ok", None),
+            &response(b"nope", None)
+        ));
+        assert!(same_response(
+            &response(b"", Some("timed out")),
+            &response(b"", Some("not found"))
+        ));
+        assert!(!same_response(
+            &response(b"ok", None),
+            &response(b"", Some("boom"))
+        ));
+    }
+
+    #[test]
+    fn shadows_cant_reach_providers() {
+        let mut bus = MessageBus::default();
+        bus.mirrors
+            .insert("Mactor".to_string(), Mirror::new("Mshadow", 100.0));
+        let kp = KeyPair::new_server();
+        let store = WasccEntity::Capability {
+            id: "Vstore".to_string(),
+            contract_id: "wascc:keyvalue".to_string(),
+            link_name: "default".to_string(),
+        };
+        let call = |target: WasccEntity, mirrored: bool| {
+            let inv = Invocation::new(
+                &kp,
+                WasccEntity::Actor("Mshadow".to_string()),
+                target,
+                "Set",
+                vec![],
+            );
+            if mirrored {
+                inv.with_baggage(
+                    vec![(BAGGAGE_MIRRORED.to_string(), "Mactor".to_string())]
+                        .into_iter()
+                        .collect(),
+                )
+            } else {
+                inv
+            }
+        };
+
+        let refused = bus.refuse_shadow_call(&call(store.clone(), true)).unwrap();
+        assert!(refused.error.is_some());
+        assert_eq!(1, bus.mirrors["Mactor"].stats.provider_calls_refused);
+        // The shadow can still call actors, and calls outside a copy are untouched
+        assert!(bus
+            .refuse_shadow_call(&call(WasccEntity::Actor("Mother".to_string()), true))
+            .is_none());
+        assert!(bus.refuse_shadow_call(&call(store, false)).is_none());
+    }
+}
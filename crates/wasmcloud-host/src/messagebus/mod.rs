@@ -1,21 +1,46 @@
+use crate::archive::Archive;
 use crate::auth::Authorizer;
-use crate::capability::link_cache::LinkCache;
+use crate::capability::link_cache::{LinkCache, LinkKey};
+use crate::compression::ClaimsCompression;
+use crate::control_interface::cloudevents::EventFormat;
+use crate::generated::core::HealthResponse;
+use crate::metrics::{ActorSlo, InvocationMetrics};
+use crate::offload::PayloadOffload;
+use crate::shutdown::{FlushStatus, Pending};
 use crate::Result;
 use crate::{Invocation, WasccEntity};
 use actix::dev::{MessageResponse, ResponseChannel};
 use actix::prelude::*;
-use std::collections::HashMap;
+use control_interface::{DependencyGraph, LinkStatistics, SubscriptionStatistics};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
 use wascap::prelude::{Claims, KeyPair};
 
 use crate::messagebus::rpc_client::RpcClient;
-pub use handlers::OP_BIND_ACTOR;
+use crate::messagebus::rpc_subscription::RpcSubscription;
+pub use handlers::{OP_BIND_ACTOR, OP_QUIESCE, OP_RESUME};
 use std::time::Duration;
 
+pub(crate) mod bulkhead;
+pub(crate) mod defaults;
+pub(crate) mod deps;
+pub(crate) mod failover;
+pub(crate) mod fanout;
 pub(crate) mod handlers;
-mod hb;
+pub(crate) mod hb;
+pub(crate) mod history;
+pub(crate) mod leases;
+pub(crate) mod mirror;
 pub(crate) mod nats_subscriber;
+pub(crate) mod ordered;
+pub(crate) mod push;
+pub(crate) mod readiness;
 pub(crate) mod rpc_client;
 pub(crate) mod rpc_subscription;
+pub(crate) mod slo;
+pub(crate) mod slow_consumer;
+pub(crate) mod upgrade;
 pub(crate) mod utils;
 
 pub(crate) use nats_subscriber::{NatsMessage, NatsSubscriber};
@@ -24,12 +49,43 @@ pub(crate) use nats_subscriber::{NatsMessage, NatsSubscriber};
 pub(crate) struct MessageBus {
     nc: Option<nats::asynk::Connection>,
     namespace: Option<String>,
+    zone: Option<String>,
     subscribers: HashMap<WasccEntity, Recipient<Invocation>>,
+    rpc_subscriptions: HashMap<WasccEntity, Addr<RpcSubscription>>,
+    subscription_stats: HashMap<WasccEntity, (String, Arc<slow_consumer::SubscriptionStats>)>,
+    slow_consumers: HashSet<String>,
     rpc_outbound: Option<Addr<RpcClient>>,
     link_cache: LinkCache,
     claims_cache: HashMap<String, Claims<wascap::jwt::Actor>>,
     key: Option<KeyPair>,
     authorizer: Option<Box<dyn Authorizer>>,
+    link_metrics: HashMap<LinkKey, InvocationMetrics>,
+    actor_metrics: HashMap<String, InvocationMetrics>,
+    slos: HashMap<String, ActorSlo>,
+    slo_breaches: HashSet<String>,
+    alert_webhooks: Vec<String>,
+    event_format: EventFormat,
+    call_graph: HashMap<String, HashMap<WasccEntity, u64>>,
+    actor_started: HashMap<String, Instant>,
+    unused_warned: HashSet<(String, String)>,
+    link_failovers: HashMap<LinkKey, failover::FailedOver>,
+    payload_offload: Option<PayloadOffload>,
+    bulkheads: HashMap<String, bulkhead::Bulkhead>,
+    cache_history: history::CacheHistory,
+    bound_links: HashSet<LinkKey>,
+    readiness_gates: HashMap<String, Duration>,
+    waiters: readiness::Waiters,
+    issuer_bundles: HashMap<String, defaults::CapabilityBundle>,
+    mirrors: HashMap<String, mirror::Mirror>,
+    push_queues: HashMap<push::PushKey, push::PushQueue>,
+    quiesced: HashSet<WasccEntity>,
+    archive: Option<Archive>,
+    in_flight: Pending,
+    max_response_size: Option<usize>,
+    hb_interval: Duration,
+    hb_handle: Option<SpawnHandle>,
+    // Kept so the lattice RPC client can be restarted by the watchdog
+    rpc_init: Option<rpc_client::Initialize>,
 }
 
 #[derive(Message)]
@@ -44,6 +100,86 @@ pub struct QueryProviders;
 #[rtype(result = "LinksResponse")]
 pub struct QueryAllLinks;
 
+#[derive(Message)]
+#[rtype(result = "Option<String>")]
+pub struct QueryNamespace;
+
+#[derive(Message)]
+#[rtype(result = "Vec<LinkStatistics>")]
+pub struct QueryLinkStatistics;
+
+#[derive(Message)]
+#[rtype(result = "Vec<SubscriptionStatistics>")]
+pub struct QuerySubscriptionStatistics;
+
+/// Ensures that no other host in the lattice is running the given ordered actor
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct ClaimOrderedActor {
+    pub actor: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "DependencyGraph")]
+pub struct QueryDependencyGraph;
+
+/// Waits for the invocations in flight on the bus to be answered, up to the deadline, then
+/// publishes the claims batched for the lattice. Sent as the host stops
+#[derive(Message)]
+#[rtype(result = "BusDrained")]
+pub(crate) struct DrainBus {
+    pub deadline: Duration,
+}
+
+pub(crate) struct BusDrained {
+    pub drained: usize,
+    pub aborted: usize,
+    pub claims_flush: FlushStatus,
+}
+
+/// Cancels the heartbeat and starts it again. Sent by the watchdog when the heartbeat stalls
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct RestartHeartbeat;
+
+/// Replaces the lattice RPC client, which keeps the claims and link caches in step with the
+/// lattice, with a new one. Sent by the watchdog when the client stalls
+#[derive(Message)]
+#[rtype(result = "Option<Addr<RpcClient>>")]
+pub(crate) struct RestartCacheSync;
+
+#[derive(Message)]
+#[rtype(result = "Option<Addr<RpcClient>>")]
+pub(crate) struct QueryRpcClient;
+
+/// Takes the topology of the lattice as this host sees it, health checking each of its nodes
+#[derive(Message)]
+#[rtype(result = "Result<crate::topology::LatticeTopology>")]
+pub struct QueryTopology;
+
+/// Asks a running provider instance to stop (or resume) accepting new external work, without
+/// touching any of its links
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub struct QuiesceProvider {
+    pub provider_id: String,
+    pub link_name: String,
+    pub quiesce: bool,
+}
+
+/// Lists the provider instances (ID and link name) that have been quiesced and not resumed
+#[derive(Message)]
+#[rtype(result = "Vec<(String, String)>")]
+pub struct QueryQuiescedProviders;
+
+/// Sends a health check to a single running provider instance
+#[derive(Message)]
+#[rtype(result = "Result<HealthResponse>")]
+pub struct ProbeProvider {
+    pub provider_id: String,
+    pub link_name: String,
+}
+
 pub struct LinksResponse {
     pub links: Vec<LinkDefinition>,
 }
@@ -92,6 +228,21 @@ pub struct Initialize {
     pub key: KeyPair,
     pub auth: Box<dyn Authorizer>,
     pub rpc_timeout: Duration,
+    pub hb_interval: Duration,
+    pub claims_compression: Box<dyn ClaimsCompression>,
+    pub legacy_gossip: bool,
+    pub zone: Option<String>,
+    pub slos: HashMap<String, ActorSlo>,
+    pub alert_webhooks: Vec<String>,
+    pub event_format: EventFormat,
+    pub unused_capability_grace: Option<Duration>,
+    pub payload_offload: Option<PayloadOffload>,
+    pub bulkheads: HashMap<String, usize>,
+    pub readiness_gates: HashMap<String, Duration>,
+    pub issuer_bundles: HashMap<String, defaults::CapabilityBundle>,
+    pub mirrors: HashMap<String, mirror::Mirror>,
+    pub archive: Option<Archive>,
+    pub max_response_size: Option<usize>,
 }
 
 #[derive(Message)]
@@ -111,6 +262,8 @@ pub struct Unsubscribe {
 #[rtype(result = "()")]
 pub struct PutClaims {
     pub claims: Claims<wascap::jwt::Actor>,
+    /// The host that advertised the claims, if it named itself
+    pub origin: Option<String>,
 }
 
 #[derive(Message)]
@@ -132,6 +285,14 @@ pub struct AdvertiseLink {
     pub values: HashMap<String, String>,
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RemoveLink {
+    pub contract_id: String,
+    pub actor: String,
+    pub link_name: String,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct PutLink {
@@ -140,6 +301,8 @@ pub struct PutLink {
     pub link_name: String,
     pub provider_id: String,
     pub values: HashMap<String, String>,
+    /// The host that advertised the link, if it named itself
+    pub origin: Option<String>,
 }
 
 #[derive(Message)]
@@ -148,6 +311,14 @@ pub struct AdvertiseClaims {
     pub claims: Claims<wascap::jwt::Actor>,
 }
 
+/// Looks up the ID of the provider running in this host for a contract and link name
+#[derive(Message)]
+#[rtype(result = "Option<String>")]
+pub struct FindProvider {
+    pub contract_id: String,
+    pub link_name: String,
+}
+
 #[derive(Message)]
 #[rtype(result = "FindLinksResponse")]
 pub struct FindLinks {
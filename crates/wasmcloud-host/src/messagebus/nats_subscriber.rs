@@ -1,3 +1,4 @@
+use crate::hlreg::Shutdown;
 use actix::prelude::*;
 use futures::StreamExt;
 
@@ -64,6 +65,14 @@ impl Handler<Initialize> for NatsSubscriber {
     }
 }
 
+impl Handler<Shutdown> for NatsSubscriber {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 impl Handler<NatsMessage> for NatsSubscriber {
     type Result = ResponseActFuture<Self, ()>;
 
@@ -0,0 +1,171 @@
+// Ordered delivery for actors that need it, such as event-sourced aggregates. An actor opts in
+// with the `wasmcloud:ordered` claims tag, and because claims are gossiped, every host in the
+// lattice knows to route calls to it over the ordered subject rather than the usual queue
+// subject. Only one host may run an ordered actor, and that host is the sole (non-queue)
+// subscriber of the ordered subject. Each caller stamps its calls with a per-target sequence
+// number, and the owning host executes them one at a time in sequence order.
+//
+// The trade-offs: an ordered actor can't be scaled out, calls to it are executed serially, a
+// caller that hasn't yet received the actor's claims will not find it, and two hosts racing to
+// start the same ordered actor may both succeed.
+
+use crate::generated::core::serialize;
+use crate::Invocation;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use wascap::jwt::{Actor, Claims};
+
+/// An actor claims tag that enables ordered (single consumer, FIFO) delivery of invocations to
+/// that actor across the lattice
+pub const TAG_ORDERED: &str = "wasmcloud:ordered";
+
+// An out-of-order invocation is held for at most this long waiting for the ones before it
+pub(crate) const ORDER_GAP_TIMEOUT: Duration = Duration::from_secs(2);
+// A sender with this many invocations held behind a gap has the gap skipped immediately
+const MAX_REORDER_BUFFER: usize = 64;
+
+pub(crate) fn is_ordered(claims: &Claims<Actor>) -> bool {
+    claims
+        .metadata
+        .as_ref()
+        .and_then(|md| md.tags.as_ref())
+        .map(|tags| tags.iter().any(|t| t == TAG_ORDERED))
+        .unwrap_or(false)
+}
+
+pub(crate) fn ordered_subject(ns_prefix: &Option<String>, actor: &str) -> String {
+    format!(
+        "{}.ordered.{}",
+        super::rpc_subscription::subject_prefix(ns_prefix),
+        actor
+    )
+}
+
+/// The subject on which the host that owns an ordered actor answers with its host ID
+pub(crate) fn owner_subject(ns_prefix: &Option<String>, actor: &str) -> String {
+    format!("{}.owner", ordered_subject(ns_prefix, actor))
+}
+
+/// An invocation sent over an ordered subject, stamped with the sending host and its sequence
+/// number for the target actor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct OrderedEnvelope {
+    pub sender: String,
+    pub seq: u64,
+    pub invocation: Invocation,
+}
+
+impl OrderedEnvelope {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serialize(self).unwrap()
+    }
+}
+
+struct SenderState<T> {
+    next: u64,
+    held: BTreeMap<u64, T>,
+    gap_since: Option<Instant>,
+}
+
+/// Puts items from each sender back into sequence order. Sequences from different senders are
+/// independent of each other
+pub(crate) struct Resequencer<T> {
+    senders: HashMap<String, SenderState<T>>,
+}
+
+impl<T> Default for Resequencer<T> {
+    fn default() -> Self {
+        Resequencer {
+            senders: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Resequencer<T> {
+    /// Accepts an item, returning every item that is now ready to be processed, in order
+    pub fn push(&mut self, sender: &str, seq: u64, item: T) -> Vec<T> {
+        // Host keys are generated at startup, so the first item seen from a sender starts its
+        // sequence, even if earlier ones were sent before this host was listening
+        let state = self
+            .senders
+            .entry(sender.to_string())
+            .or_insert_with(|| SenderState {
+                next: seq,
+                held: BTreeMap::new(),
+                gap_since: None,
+            });
+        if seq < state.next {
+            warn!(
+                "Discarding ordered invocation {} from {}, which arrived after a later one was processed",
+                seq, sender
+            );
+            return vec![];
+        }
+        state.held.insert(seq, item);
+        if state.held.len() > MAX_REORDER_BUFFER {
+            warn!(
+                "Skipping missing ordered invocation(s) from {} after buffering {} later ones",
+                sender, MAX_REORDER_BUFFER
+            );
+            state.next = *state.held.keys().next().unwrap();
+        }
+        drain_ready(state)
+    }
+
+    /// Skips gaps that have been outstanding for longer than the timeout, returning the items
+    /// that were held behind them
+    pub fn expire(&mut self, timeout: Duration) -> Vec<T> {
+        let mut ready = vec![];
+        for (sender, state) in self.senders.iter_mut() {
+            if let Some(since) = state.gap_since {
+                if since.elapsed() >= timeout {
+                    warn!(
+                        "Skipping missing ordered invocation(s) from {} after waiting {:?}",
+                        sender, timeout
+                    );
+                    state.next = *state.held.keys().next().unwrap();
+                    ready.extend(drain_ready(state));
+                }
+            }
+        }
+        ready
+    }
+}
+
+fn drain_ready<T>(state: &mut SenderState<T>) -> Vec<T> {
+    let mut ready = vec![];
+    while let Some(item) = state.held.remove(&state.next) {
+        ready.push(item);
+        state.next += 1;
+    }
+    state.gap_since = if state.held.is_empty() {
+        None
+    } else {
+        state.gap_since.or_else(|| Some(Instant::now()))
+    };
+    ready
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resequencer;
+    use std::time::Duration;
+
+    #[test]
+    fn items_are_released_in_sequence_order() {
+        let mut r = Resequencer::default();
+        assert_eq!(vec!["a0"], r.push("A", 0, "a0"));
+        assert!(r.push("A", 2, "a2").is_empty());
+        // Other senders aren't held up by a gap in A's sequence
+        assert_eq!(vec!["b7"], r.push("B", 7, "b7"));
+        assert_eq!(vec!["a1", "a2"], r.push("A", 1, "a1"));
+        // Stale sequence numbers are discarded
+        assert!(r.push("A", 1, "dup").is_empty());
+
+        assert!(r.push("A", 5, "a5").is_empty());
+        assert!(r.expire(Duration::from_secs(60)).is_empty());
+        assert_eq!(vec!["a5"], r.expire(Duration::from_secs(0)));
+        assert_eq!(vec!["a6"], r.push("A", 6, "a6"));
+    }
+}
@@ -0,0 +1,309 @@
+// Some providers deliver messages to actors that the actors never asked for in a call: a message
+// broker provider delivering a subscription, for instance. Dispatching each of these straight to
+// the actor blocks the provider until the actor has handled it, so such providers have tended to
+// spawn a thread per message, which loses their order and puts no bound on how many are waiting.
+//
+// Instead, a provider can subscribe to push to one of its linked actors. The host then keeps a
+// queue for the subscription: the provider's pushes are queued and answered straight away, and
+// the host delivers them to the actor as ordinary invocations, one at a time for an ordered
+// subscription or several at once otherwise. A subscription holds a bounded number of pushes.
+// Once it is full, new pushes are either refused, which tells the provider to slow down, or make
+// room by dropping the oldest push waiting, as the provider chose when it subscribed. Pushes that
+// the actor fails to handle are counted and logged, not retried.
+
+use super::MessageBus;
+use crate::capability::link_cache::LinkKey;
+use crate::{Invocation, Result, WasccEntity};
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// A provider's request to push messages to one of its linked actors
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct PushSubscribeRequest {
+    pub actor: String,
+    pub ordered: bool,
+    #[serde(rename = "maxPending")]
+    pub max_pending: u32,
+    #[serde(rename = "dropOldest")]
+    pub drop_oldest: bool,
+}
+
+/// Ends a provider's subscription to push to an actor
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct PushUnsubscribeRequest {
+    pub actor: String,
+}
+
+/// A message a provider pushes to an actor, delivered as an invocation of the given operation
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct PushRequest {
+    pub actor: String,
+    pub operation: String,
+    pub msg: Vec<u8>,
+}
+
+/// Whether a push was queued, and how many pushes are waiting for the actor
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct PushAck {
+    pub accepted: bool,
+    pub pending: u32,
+}
+
+// How many pushes a subscription holds if the provider doesn't say
+const DEFAULT_MAX_PENDING: usize = 1024;
+// How many pushes of an unordered subscription are delivered at once
+const UNORDERED_CONCURRENCY: usize = 16;
+
+// A subscription is identified by the provider instance pushing and the actor it pushes to
+pub(crate) type PushKey = (WasccEntity, String);
+
+/// The queue of pushes from a provider to one of its linked actors
+#[derive(Debug)]
+pub(crate) struct PushQueue {
+    ordered: bool,
+    max_pending: usize,
+    drop_oldest: bool,
+    pending: VecDeque<Invocation>,
+    in_flight: usize,
+    dropped: u64,
+    failed: u64,
+}
+
+impl PushQueue {
+    fn new(req: &PushSubscribeRequest) -> PushQueue {
+        PushQueue {
+            ordered: req.ordered,
+            max_pending: match req.max_pending {
+                0 => DEFAULT_MAX_PENDING,
+                n => n as usize,
+            },
+            drop_oldest: req.drop_oldest,
+            pending: VecDeque::new(),
+            in_flight: 0,
+            dropped: 0,
+            failed: 0,
+        }
+    }
+
+    /// Queues a push, returning false if the queue is full and refuses it
+    fn offer(&mut self, inv: Invocation) -> bool {
+        if self.pending.len() >= self.max_pending {
+            if !self.drop_oldest {
+                return false;
+            }
+            self.pending.pop_front();
+            self.dropped += 1;
+        }
+        self.pending.push_back(inv);
+        true
+    }
+
+    /// The next push to deliver, if there is one and the subscription can have another in flight
+    fn next(&mut self) -> Option<Invocation> {
+        let limit = if self.ordered {
+            1
+        } else {
+            UNORDERED_CONCURRENCY
+        };
+        if self.in_flight >= limit {
+            return None;
+        }
+        let inv = self.pending.pop_front()?;
+        self.in_flight += 1;
+        Some(inv)
+    }
+}
+
+/// Subscribes a provider instance to push to one of the actors linked to it
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub(crate) struct PushSubscribe {
+    pub provider: WasccEntity,
+    pub request: PushSubscribeRequest,
+}
+
+/// Removes a push subscription, discarding the pushes still waiting in it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct PushUnsubscribe {
+    pub provider: WasccEntity,
+    pub actor: String,
+}
+
+/// Queues an invocation from a provider to one of its linked actors
+#[derive(Message)]
+#[rtype(result = "Result<PushAck>")]
+pub(crate) struct Push {
+    pub invocation: Invocation,
+}
+
+impl Handler<PushSubscribe> for MessageBus {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: PushSubscribe, _ctx: &mut Context<Self>) -> Self::Result {
+        let (id, contract_id, link_name) = match msg.provider {
+            WasccEntity::Capability {
+                ref id,
+                ref contract_id,
+                ref link_name,
+            } => (id, contract_id, link_name),
+            WasccEntity::Actor(_) => return Err("Only providers can push to actors".into()),
+        };
+        let key = LinkKey {
+            actor: msg.request.actor.to_string(),
+            contract_id: contract_id.to_string(),
+            link_name: link_name.to_string(),
+        };
+        let linked = self
+            .link_cache
+            .get(&key)
+            .map_or(false, |l| l.providers().contains(id));
+        if !linked {
+            return Err(format!(
+                "Actor {} is not linked to provider {} ({})",
+                key.actor, id, link_name
+            )
+            .into());
+        }
+        info!(
+            "Provider {} ({}) subscribed to push to actor {}",
+            id, link_name, key.actor
+        );
+        self.push_queues.insert(
+            (msg.provider.clone(), key.actor),
+            PushQueue::new(&msg.request),
+        );
+        Ok(())
+    }
+}
+
+impl Handler<PushUnsubscribe> for MessageBus {
+    type Result = ();
+
+    fn handle(&mut self, msg: PushUnsubscribe, _ctx: &mut Context<Self>) {
+        self.push_queues.remove(&(msg.provider, msg.actor));
+    }
+}
+
+impl Handler<Push> for MessageBus {
+    type Result = Result<PushAck>;
+
+    fn handle(&mut self, msg: Push, ctx: &mut Context<Self>) -> Self::Result {
+        let inv = msg.invocation;
+        let key = (inv.origin.clone(), inv.target.key());
+        let queue = self.push_queues.get_mut(&key).ok_or_else(|| {
+            format!(
+                "Provider {} has no push subscription for actor {}",
+                inv.origin.key(),
+                inv.target.key()
+            )
+        })?;
+        let accepted = queue.offer(inv);
+        let pending = queue.pending.len() as u32;
+        if accepted {
+            self.deliver_pushes(key, ctx);
+        }
+        Ok(PushAck { accepted, pending })
+    }
+}
+
+impl MessageBus {
+    // Delivers as many of the subscription's pushes as it allows in flight. Each push goes
+    // through the bus like any other invocation, and its answer makes room for the next
+    fn deliver_pushes(&mut self, key: PushKey, ctx: &mut Context<Self>) {
+        let ready: Vec<Invocation> = match self.push_queues.get_mut(&key) {
+            Some(queue) => std::iter::from_fn(|| queue.next()).collect(),
+            None => return,
+        };
+        for inv in ready {
+            let key = key.clone();
+            let id = inv.id.to_string();
+            ctx.spawn(
+                ctx.address()
+                    .send(inv)
+                    .into_actor(self)
+                    .map(move |res, act, ctx| {
+                        let error = match res {
+                            Ok(ir) => ir.error,
+                            Err(e) => Some(e.to_string()),
+                        };
+                        if let Some(queue) = act.push_queues.get_mut(&key) {
+                            queue.in_flight = queue.in_flight.saturating_sub(1);
+                            if let Some(e) = error {
+                                queue.failed += 1;
+                                warn!(
+                                "Push {} from {} to actor {} failed ({} failed, {} dropped): {}",
+                                id,
+                                key.0.key(),
+                                key.1,
+                                queue.failed,
+                                queue.dropped,
+                                e
+                            );
+                            }
+                        }
+                        act.deliver_pushes(key, ctx);
+                    }),
+            );
+        }
+    }
+
+    /// Removes the push subscriptions of a provider instance that is going away
+    pub(crate) fn remove_push_queues(&mut self, provider: &WasccEntity) {
+        self.push_queues.retain(|(p, _), _| p != provider);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PushQueue, PushSubscribeRequest};
+    use crate::{Invocation, WasccEntity};
+    use wascap::prelude::KeyPair;
+
+    fn push(kp: &KeyPair, n: u8) -> Invocation {
+        Invocation::new(
+            kp,
+            WasccEntity::Capability {
+                id: "Vbroker".to_string(),
+                contract_id: "wascc:messaging".to_string(),
+                link_name: "default".to_string(),
+            },
+            WasccEntity::Actor("Mactor".to_string()),
+            "DeliverMessage",
+            vec![n],
+        )
+    }
+
+    #[test]
+    fn queues_bound_pushes_and_order_delivery() {
+        let kp = KeyPair::new_server();
+        let mut ordered = PushQueue::new(&PushSubscribeRequest {
+            actor: "Mactor".to_string(),
+            ordered: true,
+            max_pending: 2,
+            drop_oldest: false,
+        });
+        assert!(ordered.offer(push(&kp, 1)));
+        assert!(ordered.offer(push(&kp, 2)));
+        assert!(!ordered.offer(push(&kp, 3)));
+        // Only one push of an ordered subscription is in flight at a time
+        assert_eq!(vec![1], ordered.next().unwrap().msg);
+        assert!(ordered.next().is_none());
+        ordered.in_flight -= 1;
+        assert_eq!(vec![2], ordered.next().unwrap().msg);
+
+        let mut lossy = PushQueue::new(&PushSubscribeRequest {
+            actor: "Mactor".to_string(),
+            ordered: false,
+            max_pending: 2,
+            drop_oldest: true,
+        });
+        for n in 1..=3 {
+            assert!(lossy.offer(push(&kp, n)));
+        }
+        assert_eq!(1, lossy.dropped);
+        assert_eq!(vec![2], lossy.next().unwrap().msg);
+        assert_eq!(vec![3], lossy.next().unwrap().msg);
+    }
+}
@@ -0,0 +1,193 @@
+// Setting a link only puts it in the lattice cache; binding it to the providers that serve it
+// happens afterwards, so traffic sent right after `set_link` can reach an actor before the actor
+// can use its links. Rather than sleeping for a while and hoping, callers can wait for a link to
+// be established: in the cache, and bound to each of its providers that run in this host.
+//
+// A readiness gate does the same for traffic coming in to actors. With a gate on a contract (say
+// `wascc:http_server`), calls from that contract's providers to an actor are held until the
+// actor has an established link for every other capability its claims grant it, up to a maximum
+// wait after which they are delivered anyway.
+
+use super::MessageBus;
+use crate::capability::link_cache::LinkKey;
+use crate::{Invocation, InvocationResponse, WasccEntity};
+use actix::prelude::*;
+use futures::channel::oneshot;
+use std::collections::HashSet;
+use std::time::Duration;
+
+// Actors reach wascc:extras without a link
+const LINKLESS_CONTRACTS: [&str; 1] = ["wascc:extras"];
+
+/// Answers once the link has been established
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct AwaitLink {
+    pub key: LinkKey,
+}
+
+#[derive(Default)]
+pub(crate) struct Waiters {
+    links: Vec<(LinkKey, oneshot::Sender<()>)>,
+    // Invocations held by a gate, by actor and the contract of the provider that sent them
+    actors: Vec<(String, String, oneshot::Sender<()>)>,
+}
+
+impl MessageBus {
+    // Whether the link is in the cache and bound to each of its providers running in this host
+    fn link_established(&self, key: &LinkKey) -> bool {
+        match self.link_cache.get(key) {
+            Some(link) => {
+                self.bound_links.contains(key)
+                    || !link.providers().into_iter().any(|id| {
+                        self.subscribers.contains_key(&WasccEntity::Capability {
+                            id,
+                            contract_id: key.contract_id.to_string(),
+                            link_name: key.link_name.to_string(),
+                        })
+                    })
+            }
+            None => false,
+        }
+    }
+
+    // Whether the actor has an established link for each capability it is granted, other than
+    // the given contract
+    fn actor_ready(&self, actor: &str, except: &str) -> bool {
+        let caps = self
+            .claims_cache
+            .get(actor)
+            .and_then(|c| c.metadata.as_ref())
+            .and_then(|md| md.caps.clone())
+            .unwrap_or_default();
+        let linked: HashSet<String> = self
+            .link_cache
+            .all()
+            .into_iter()
+            .filter(|(k, _)| k.actor == actor && self.link_established(k))
+            .map(|(k, _)| k.contract_id)
+            .collect();
+        unlinked(&caps, except, &linked).is_empty()
+    }
+
+    // How long to hold an invocation from a gated contract's provider to an actor that isn't
+    // ready yet, if it is one
+    fn readiness_gate(&self, inv: &Invocation) -> Option<(String, String, Duration)> {
+        match (&inv.origin, &inv.target) {
+            (WasccEntity::Capability { contract_id, .. }, WasccEntity::Actor(actor)) => {
+                let max_wait = self.readiness_gates.get(contract_id)?;
+                if self.actor_ready(actor, contract_id) {
+                    None
+                } else {
+                    Some((actor.to_string(), contract_id.to_string(), *max_wait))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Routes an invocation, first holding it if a readiness gate applies to it
+    pub(crate) fn route_when_ready(
+        &mut self,
+        msg: Invocation,
+    ) -> ResponseActFuture<Self, InvocationResponse> {
+        let (actor, contract_id, max_wait) = match self.readiness_gate(&msg) {
+            Some(gate) => gate,
+            None => return self.route_invocation(msg),
+        };
+        trace!(
+            "Holding invocation from {} until actor {} is ready",
+            contract_id,
+            actor
+        );
+        let (tx, rx) = oneshot::channel();
+        self.waiters
+            .actors
+            .push((actor.to_string(), contract_id, tx));
+        Box::pin(
+            async move {
+                if actix_rt::time::timeout(max_wait, rx).await.is_err() {
+                    warn!(
+                        "Actor {} still lacks links after {:?}, delivering held invocation",
+                        actor, max_wait
+                    );
+                }
+            }
+            .into_actor(self)
+            .then(move |_, act, _ctx| act.route_invocation(msg)),
+        )
+    }
+
+    /// Releases whoever is waiting on links or actors that are now ready
+    pub(crate) fn wake_waiters(&mut self) {
+        let links = std::mem::take(&mut self.waiters.links);
+        for (key, tx) in links {
+            if self.link_established(&key) {
+                let _ = tx.send(());
+            } else if !tx.is_canceled() {
+                self.waiters.links.push((key, tx));
+            }
+        }
+        let actors = std::mem::take(&mut self.waiters.actors);
+        for (actor, contract_id, tx) in actors {
+            if self.actor_ready(&actor, &contract_id) {
+                let _ = tx.send(());
+            } else if !tx.is_canceled() {
+                self.waiters.actors.push((actor, contract_id, tx));
+            }
+        }
+    }
+}
+
+impl Handler<AwaitLink> for MessageBus {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: AwaitLink, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.link_established(&msg.key) {
+            return Box::pin(async {}.into_actor(self));
+        }
+        let (tx, rx) = oneshot::channel();
+        self.waiters.links.push((msg.key, tx));
+        Box::pin(
+            async move {
+                let _ = rx.await;
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+// The capabilities that need a link but have none, other than the given contract
+fn unlinked<'a>(caps: &'a [String], except: &str, linked: &HashSet<String>) -> Vec<&'a String> {
+    caps.iter()
+        .filter(|c| {
+            *c != except && !LINKLESS_CONTRACTS.contains(&c.as_str()) && !linked.contains(*c)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::unlinked;
+    use std::collections::HashSet;
+
+    #[test]
+    fn actors_wait_for_their_other_capabilities() {
+        let caps = vec![
+            "wascc:http_server".to_string(),
+            "wascc:keyvalue".to_string(),
+            "wascc:extras".to_string(),
+        ];
+        let mut linked = HashSet::new();
+        assert_eq!(
+            vec!["wascc:keyvalue"],
+            unlinked(&caps, "wascc:http_server", &linked)
+        );
+        linked.insert("wascc:keyvalue".to_string());
+        assert!(unlinked(&caps, "wascc:http_server", &linked).is_empty());
+        assert_eq!(
+            vec!["wascc:http_server"],
+            unlinked(&caps, "wascc:keyvalue", &linked)
+        );
+    }
+}
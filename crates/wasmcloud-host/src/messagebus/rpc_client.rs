@@ -1,19 +1,47 @@
+use crate::compression::{decompress_with, ClaimsCompression};
 use crate::generated::core::{deserialize, serialize};
-use crate::hlreg::HostLocalSystemService;
+use crate::hlreg::{HostLocalSystemService, Shutdown};
+use crate::hooks::bytes_digest;
 use crate::host_controller::HostController;
-use crate::messagebus::rpc_subscription::{claims_subject, invoke_subject, links_subject};
+use crate::messagebus::ordered::{ordered_subject, OrderedEnvelope};
+use crate::messagebus::rpc_subscription::{
+    claims_subject, invoke_subject, links_subject, zone_beats_subject, zoned_invoke_subject,
+};
 use crate::messagebus::{AdvertiseClaims, AdvertiseLink, MessageBus, PutClaims, PutLink};
+use crate::offload::{offload, release, PayloadOffload};
+use crate::shutdown::FlushStatus;
+use crate::watchdog::Ping;
 use crate::Result;
 use crate::{Invocation, InvocationResponse};
 use actix::prelude::*;
 use control_interface::LinkDefinition;
+use futures::channel::oneshot;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use wascap::jwt::{Actor, Claims};
+use wascap::prelude::KeyPair;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-#[derive(Message)]
+// Claims advertised within this window are gossiped together in a single batch
+const CLAIMS_BATCH_WINDOW: Duration = Duration::from_millis(50);
+// A batch is flushed immediately once it reaches this size
+const CLAIMS_BATCH_MAX: usize = 64;
+// Claims that have already been seen on the lattice are not re-broadcast until this much time has
+// passed, which gives hosts that joined in the meantime a chance to learn about them
+const CLAIMS_DIGEST_TTL: Duration = Duration::from_secs(300);
+// A host in our zone is taken to have left once it misses this many heartbeats in a row
+const ZONE_BEATS_MISSED: u32 = 3;
+// The version of the signed envelope in which claims and links are published
+const SIGNED_UPDATE_VERSION: u8 = 1;
+// The newest claims batch format this host understands. Batches in a newer format are discarded
+// rather than misread
+const CLAIMS_FORMAT_VERSION: u32 = 1;
+
+#[derive(Message, Clone)]
 #[rtype(result = "()")]
 pub(crate) struct Initialize {
     pub nc: nats::asynk::Connection,
@@ -21,8 +49,19 @@ pub(crate) struct Initialize {
     pub bus: Addr<MessageBus>,
     pub rpc_timeout: Duration,
     pub host_id: String,
+    pub key: Arc<KeyPair>,
+    pub claims_compression: Box<dyn ClaimsCompression>,
+    pub zone: Option<String>,
+    pub offload: Option<PayloadOffload>,
+    pub legacy_gossip: bool,
+    pub hb_interval: Duration,
 }
 
+/// Publishes the claims waiting to be batched right away, as the host stops
+#[derive(Message)]
+#[rtype(result = "FlushStatus")]
+pub(crate) struct FlushClaims;
+
 #[derive(Default)]
 pub(crate) struct RpcClient {
     nc: Option<nats::asynk::Connection>,
@@ -30,18 +69,349 @@ pub(crate) struct RpcClient {
     bus: Option<Addr<MessageBus>>,
     rpc_timeout: Duration,
     host_id: Option<String>,
+    key: Option<Arc<KeyPair>>,
+    claims_compression: Option<Box<dyn ClaimsCompression>>,
+    seen_claims: HashMap<String, Instant>,
+    pending_claims: Vec<Claims<Actor>>,
+    claims_waiters: Vec<oneshot::Sender<std::result::Result<(), String>>>,
+    legacy_gossip: bool,
+    zone: Option<String>,
+    zone_members: HashMap<String, (HashSet<String>, Instant)>,
+    zone_ttl: Duration,
+    ordered_seq: HashMap<String, u64>,
+    offload: Option<PayloadOffload>,
+}
+
+// The envelope in which a batch of claims is gossiped over the lattice. The payload is a
+// serialized list of claims, compressed with the named scheme. Batches from before the format
+// was versioned decode as version 0, which has the same layout as version 1
+#[derive(Serialize, Deserialize)]
+struct ClaimsBatch {
+    #[serde(default)]
+    version: u32,
+    encoding: String,
+    #[serde(with = "serde_bytes")]
+    payload: Vec<u8>,
+}
+
+/// An invocation of an actor that requires ordered delivery
+#[derive(Message)]
+#[rtype(result = "InvocationResponse")]
+pub(crate) struct OrderedInvocation {
+    pub actor: String,
+    pub invocation: Invocation,
+}
+
+/// Tells the hosts in this host's zone which of its entities they can reach in the zone. Sent
+/// with every heartbeat, and whenever the host's lattice subscriptions change
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct BeatZone {
+    pub entities: Vec<crate::WasccEntity>,
+}
+
+// The in-zone subjects a host in our zone has instances subscribed to
+#[derive(Serialize, Deserialize)]
+struct ZoneMembership {
+    host_id: String,
+    subjects: Vec<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ZoneMembershipInbound {
+    membership: ZoneMembership,
+}
+
+/// Why a lattice call got no response
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RpcError {
+    /// The request never left this host, so no instance of the target handled it
+    Undelivered(String),
+    /// No response came in time. The target may or may not have handled the request
+    TimedOut,
+    /// A response came back, but couldn't be read
+    Unreadable,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Undelivered(e) => write!(f, "RPC error: {}", e),
+            RpcError::TimedOut => write!(f, "RPC call timed out"),
+            RpcError::Unreadable => write!(f, "RPC - failed to deserialize invocation response"),
+        }
+    }
+}
+
+/// A lattice call whose failure is returned as an error rather than as an error response, so
+/// that the caller can decide whether to try another target
+#[derive(Message)]
+#[rtype(result = "std::result::Result<InvocationResponse, RpcError>")]
+pub(crate) struct AttemptInvocation {
+    pub invocation: Invocation,
+}
+
+// A cache update (a batch of claims or a link definition) signed with the key of the host that
+// published it. Receivers learn the sender from the signature rather than from the reply
+// subject, which any publisher can set to another host's ID
+#[derive(Serialize, Deserialize)]
+struct SignedUpdate {
+    version: u8,
+    signer: String,
+    #[serde(with = "serde_bytes")]
+    body: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+impl SignedUpdate {
+    fn seal(body: Vec<u8>, key: &KeyPair) -> Result<Vec<u8>> {
+        let signature = key.sign(&body)?;
+        serialize(&SignedUpdate {
+            version: SIGNED_UPDATE_VERSION,
+            signer: key.public_key(),
+            body,
+            signature,
+        })
+    }
+}
+
+// The body of a cache update along with who sent it. The sender is only authenticated when the
+// update was signed; older hosts publish unsigned updates, naming themselves in the reply subject
+#[derive(Debug, PartialEq)]
+struct Opened {
+    body: Vec<u8>,
+    origin: Option<String>,
+    verified: bool,
+}
+
+// Opens a cache update, returning None if it claims to be signed but the signature doesn't
+// hold
+fn open_update(data: &[u8], reply: Option<String>) -> Option<Opened> {
+    match deserialize::<SignedUpdate>(data) {
+        Ok(update) if update.version == SIGNED_UPDATE_VERSION => {
+            let valid = KeyPair::from_public_key(&update.signer)
+                .and_then(|kp| kp.verify(&update.body, &update.signature))
+                .is_ok();
+            if !valid {
+                warn!(
+                    "Discarding cache update with an invalid signature from {}",
+                    update.signer
+                );
+                return None;
+            }
+            Some(Opened {
+                body: update.body,
+                origin: Some(update.signer),
+                verified: true,
+            })
+        }
+        Ok(update) => {
+            warn!(
+                "Discarding cache update in unknown envelope version {}",
+                update.version
+            );
+            None
+        }
+        Err(_) => Some(Opened {
+            body: data.to_vec(),
+            origin: reply,
+            verified: false,
+        }),
+    }
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 struct ClaimsInbound {
-    claims: Option<wascap::jwt::Claims<wascap::jwt::Actor>>,
+    claims: Vec<Claims<Actor>>,
+    origin: Option<String>,
+    verified: bool,
+}
+
+impl RpcClient {
+    // The in-zone subject to send the call to, if this host has a zone and a host in the zone
+    // has recently said it runs an instance of the target
+    fn zone_subject(&self, target: &crate::WasccEntity) -> Option<String> {
+        let zone = self.zone.as_ref()?;
+        let subject = zoned_invoke_subject(&self.ns_prefix, zone, target);
+        let present = self
+            .zone_members
+            .values()
+            .any(|(subjects, seen)| seen.elapsed() < self.zone_ttl && subjects.contains(&subject));
+        if present {
+            Some(subject)
+        } else {
+            None
+        }
+    }
+
+    // Records the digest of the given claims, returning false if the same claims were already
+    // seen on the lattice recently
+    fn mark_seen(&mut self, claims: &Claims<Actor>) -> bool {
+        let digest = bytes_digest(&serialize(claims).unwrap_or_default());
+        let now = Instant::now();
+        match self.seen_claims.get(&digest) {
+            Some(seen) if now.duration_since(*seen) < CLAIMS_DIGEST_TTL => false,
+            _ => {
+                self.seen_claims.insert(digest, now);
+                true
+            }
+        }
+    }
+
+    fn flush_claims(&mut self, ctx: &mut Context<Self>) {
+        if let Some((publish, _)) = self.claims_batch() {
+            ctx.spawn(
+                async move {
+                    if let Err(e) = publish.await {
+                        error!("{}", e);
+                    }
+                }
+                .into_actor(self),
+            );
+        }
+    }
+
+    // Encodes claims for the lattice: a signed, compressed batch, or in the legacy format one
+    // unsigned message per set of claims, which hosts from before batching can read
+    fn encode_claims(&self, batch: &[Claims<Actor>]) -> Result<Vec<Vec<u8>>> {
+        if self.legacy_gossip {
+            return batch.iter().map(|c| serialize(c)).collect();
+        }
+        let compression = self.claims_compression.as_ref().unwrap();
+        let payload = compression.compress(&serialize(batch)?)?;
+        let bytes = serialize(&ClaimsBatch {
+            version: CLAIMS_FORMAT_VERSION,
+            encoding: compression.name().to_string(),
+            payload,
+        })?;
+        Ok(vec![SignedUpdate::seal(bytes, self.key.as_ref().unwrap())?])
+    }
+
+    // Takes the pending claims as an encoded batch, returning the publication of the batch and
+    // the number of claims in it. Whoever advertised the claims learns how the publication went
+    fn claims_batch(
+        &mut self,
+    ) -> Option<(
+        impl std::future::Future<Output = std::result::Result<(), String>>,
+        usize,
+    )> {
+        if self.pending_claims.is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        self.seen_claims
+            .retain(|_, seen| now.duration_since(*seen) < CLAIMS_DIGEST_TTL);
+
+        let batch: Vec<Claims<Actor>> = self.pending_claims.drain(..).collect();
+        let waiters: Vec<_> = self.claims_waiters.drain(..).collect();
+        trace!(
+            "Publishing batch of {} actor claims on lattice",
+            batch.len()
+        );
+        let messages = self
+            .encode_claims(&batch)
+            .map_err(|e| format!("Failed to encode claims batch: {}", e));
+        let nc = self.nc.clone().unwrap();
+        let subject = claims_subject(&self.ns_prefix);
+        // The reply subject still names this host, for hosts that predate signed updates
+        let origin = self.host_id.clone().unwrap();
+        let count = batch.len();
+        Some((
+            async move {
+                let mut res = Ok(());
+                match messages {
+                    Ok(messages) => {
+                        for bytes in messages {
+                            if let Err(e) = nc.publish_request(&subject, &origin, &bytes).await {
+                                res = Err(format!("Failed to publish claims notification: {}", e));
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => res = Err(e),
+                }
+                for waiter in waiters {
+                    let _ = waiter.send(res.clone());
+                }
+                res
+            },
+            count,
+        ))
+    }
+}
+
+// Offloads the invocation's payload if the invocation is too large for the lattice, returning
+// the bytes to send and the key of the offloaded payload
+async fn prepare(
+    offload_to: &Option<PayloadOffload>,
+    inv: Invocation,
+) -> std::result::Result<(Vec<u8>, Option<String>), String> {
+    let (inv, key) = offload(offload_to, inv).await.map_err(|e| e.to_string())?;
+    let bytes = serialize(&inv).map_err(|e| e.to_string())?;
+    Ok((bytes, key))
+}
+
+async fn rpc_request(
+    client: &nats::asynk::Connection,
+    host_id: &str,
+    subject: &str,
+    bytes: &[u8],
+    timeout: Duration,
+) -> std::result::Result<InvocationResponse, RpcError> {
+    let request = crate::inboxes::request(client, host_id, subject, bytes);
+    match actix_rt::time::timeout(timeout, request).await {
+        Ok(Ok(r)) => deserialize::<InvocationResponse>(&r.data).map_err(|_| RpcError::Unreadable),
+        Ok(Err(e)) => Err(RpcError::Undelivered(e.to_string())),
+        Err(_) => Err(RpcError::TimedOut),
+    }
+}
+
+// Decodes a claims gossip message, which is either a batch or, from older hosts, a single
+// set of claims
+fn decode_claims(data: &[u8], compression: &dyn ClaimsCompression) -> Vec<Claims<Actor>> {
+    if let Ok(batch) = deserialize::<ClaimsBatch>(data) {
+        if batch.version > CLAIMS_FORMAT_VERSION {
+            warn!(
+                "Discarding claims batch in newer format version {}",
+                batch.version
+            );
+            return vec![];
+        }
+        match decompress_with(compression, &batch.encoding, &batch.payload)
+            .and_then(|raw| deserialize::<Vec<Claims<Actor>>>(&raw))
+        {
+            Ok(claims) => claims,
+            Err(e) => {
+                warn!("Discarding undecodable claims batch: {}", e);
+                vec![]
+            }
+        }
+    } else {
+        deserialize::<Claims<Actor>>(data)
+            .map(|c| vec![c])
+            .unwrap_or_default()
+    }
+}
+
+// The sender the roster should judge an update by. Only a signature identifies a sender; an
+// unsigned update could have been sent by anyone
+fn sender(origin: &Option<String>, verified: bool) -> Option<&str> {
+    if verified {
+        origin.as_deref()
+    } else {
+        None
+    }
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 struct LinkInbound {
     link: Option<LinkDefinition>,
+    origin: Option<String>,
+    verified: bool,
 }
 
 impl Actor for RpcClient {
@@ -58,36 +428,62 @@ impl Handler<Initialize> for RpcClient {
         self.bus = Some(msg.bus);
         self.rpc_timeout = msg.rpc_timeout;
         self.host_id = Some(msg.host_id);
+        self.key = Some(msg.key);
+        self.claims_compression = Some(msg.claims_compression.clone());
+        self.zone = msg.zone;
+        self.offload = msg.offload;
+        self.legacy_gossip = msg.legacy_gossip;
+        self.zone_ttl = msg.hb_interval * ZONE_BEATS_MISSED;
 
         let nc = self.nc.clone().unwrap();
         let prefix = self.ns_prefix.clone();
+        let compression = msg.claims_compression;
+        let beats = self
+            .zone
+            .as_ref()
+            .map(|zone| zone_beats_subject(&prefix, zone));
         Box::pin(
             async move {
                 let claims_sub = nc.subscribe(&claims_subject(&prefix)).await;
                 let links_sub = nc.subscribe(&links_subject(&prefix)).await;
-                (claims_sub, links_sub)
+                let beats_sub = match beats {
+                    Some(beats) => nc.subscribe(&beats).await.ok(),
+                    None => None,
+                };
+                (claims_sub, links_sub, beats_sub)
             }
             .into_actor(self)
-            .map(|(claims, links), _act, ctx| {
+            .map(|(claims, links, beats), _act, ctx| {
+                // Learn which instances the other hosts in our zone run
+                if let Some(b) = beats {
+                    ctx.add_message_stream(b.filter_map(|m| {
+                        futures::future::ready(
+                            deserialize::<ZoneMembership>(&m.data)
+                                .ok()
+                                .map(|membership| ZoneMembershipInbound { membership }),
+                        )
+                    }));
+                }
                 // Set up subscriber for claims advertisements
                 if let Ok(c) = claims {
-                    ctx.add_message_stream(c.map(|m| {
-                        let claims =
-                            deserialize::<wascap::jwt::Claims<wascap::jwt::Actor>>(&m.data);
-                        match claims {
-                            Ok(c) => ClaimsInbound { claims: Some(c) },
-                            Err(_) => ClaimsInbound { claims: None },
-                        }
+                    ctx.add_message_stream(c.filter_map(move |m| {
+                        let update = open_update(&m.data, m.reply).map(|o| ClaimsInbound {
+                            claims: decode_claims(&o.body, compression.as_ref()),
+                            origin: o.origin,
+                            verified: o.verified,
+                        });
+                        futures::future::ready(update)
                     }));
                 }
                 // Set up subscriber for links advertisements
                 if let Ok(l) = links {
-                    ctx.add_message_stream(l.map(|m| {
-                        let link = deserialize::<LinkDefinition>(&m.data);
-                        match link {
-                            Ok(l) => LinkInbound { link: Some(l) },
-                            Err(_) => LinkInbound { link: None },
-                        }
+                    ctx.add_message_stream(l.filter_map(|m| {
+                        let update = open_update(&m.data, m.reply).map(|o| LinkInbound {
+                            link: deserialize::<LinkDefinition>(&o.body).ok(),
+                            origin: o.origin,
+                            verified: o.verified,
+                        });
+                        futures::future::ready(update)
                     }))
                 }
             }),
@@ -95,6 +491,98 @@ impl Handler<Initialize> for RpcClient {
     }
 }
 
+// Publishes the claims waiting to be batched, and flushes the connection so they are known to
+// have reached the lattice
+impl Handler<FlushClaims> for RpcClient {
+    type Result = ResponseActFuture<Self, FlushStatus>;
+
+    fn handle(&mut self, _msg: FlushClaims, _ctx: &mut Self::Context) -> Self::Result {
+        let batch = self.claims_batch();
+        let nc = self.nc.clone().unwrap();
+        Box::pin(
+            async move {
+                let claims = match batch {
+                    Some((publish, count)) => {
+                        if let Err(e) = publish.await {
+                            return FlushStatus::Failed(e);
+                        }
+                        count
+                    }
+                    None => 0,
+                };
+                match nc.flush().await {
+                    Ok(_) if claims == 0 => FlushStatus::Nothing,
+                    Ok(_) => FlushStatus::Flushed { claims },
+                    Err(e) => FlushStatus::Failed(e.to_string()),
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<BeatZone> for RpcClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: BeatZone, _ctx: &mut Self::Context) {
+        let zone = match self.zone {
+            Some(ref zone) => zone,
+            None => return,
+        };
+        let membership = ZoneMembership {
+            host_id: self.host_id.clone().unwrap(),
+            subjects: msg
+                .entities
+                .iter()
+                .map(|e| zoned_invoke_subject(&self.ns_prefix, zone, e))
+                .collect(),
+        };
+        let subject = zone_beats_subject(&self.ns_prefix, zone);
+        let nc = self.nc.clone().unwrap();
+        if let Ok(bytes) = serialize(&membership) {
+            actix::spawn(async move {
+                if let Err(e) = nc.publish(&subject, &bytes).await {
+                    warn!("Failed to publish zone membership: {}", e);
+                }
+            });
+        }
+    }
+}
+
+impl Handler<ZoneMembershipInbound> for RpcClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: ZoneMembershipInbound, _ctx: &mut Self::Context) {
+        if !crate::partitions::reachable(
+            self.host_id.as_ref().unwrap(),
+            Some(&msg.membership.host_id),
+        ) {
+            return;
+        }
+        let now = Instant::now();
+        let ttl = self.zone_ttl;
+        self.zone_members
+            .retain(|_, (_, seen)| now.duration_since(*seen) < ttl);
+        let subjects = msg.membership.subjects.into_iter().collect();
+        self.zone_members
+            .insert(msg.membership.host_id, (subjects, now));
+    }
+}
+
+impl Handler<Ping> for RpcClient {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+impl Handler<Shutdown> for RpcClient {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 // Perform an RPC call (subject request w/timeout) on the rpc bus
 impl Handler<Invocation> for RpcClient {
     type Result = ResponseActFuture<Self, InvocationResponse>;
@@ -102,28 +590,103 @@ impl Handler<Invocation> for RpcClient {
     fn handle(&mut self, msg: Invocation, _ctx: &mut Self::Context) -> Self::Result {
         trace!("Performing lattice RPC call to {}", msg.target.url());
         let client = self.nc.clone().unwrap();
+        let host_id = self.host_id.clone().unwrap();
         let subject = invoke_subject(&self.ns_prefix, &msg.target);
-        let bytes = serialize(&msg).unwrap();
+        let zone_subject = self.zone_subject(&msg.target);
         let timeout = self.rpc_timeout;
+        let offload = self.offload.clone();
 
         Box::pin(
             async move {
-                match actix_rt::time::timeout(timeout, client.request(&subject, &bytes)).await {
-                    Ok(r) => match r {
-                        Ok(r) => {
-                            let ir: Result<InvocationResponse> = deserialize(&r.data);
-                            match ir {
-                                Ok(ir) => ir,
-                                Err(_) => InvocationResponse::error(
-                                    &msg,
-                                    "RPC - failed to deserialize invocation response",
-                                ),
-                            }
+                let (bytes, offloaded) = match prepare(&offload, msg.clone()).await {
+                    Ok(p) => p,
+                    Err(e) => return InvocationResponse::error(&msg, &e),
+                };
+                // Prefer an instance in our own zone when the zone's heartbeats say there is
+                // one. The call is only sent on to other zones if it never left this host, so
+                // it can't run twice
+                let res = match zone_subject {
+                    Some(zs) => match rpc_request(&client, &host_id, &zs, &bytes, timeout).await {
+                        Err(RpcError::Undelivered(e)) => {
+                            trace!("In-zone call failed ({}), trying other zones", e);
+                            rpc_request(&client, &host_id, &subject, &bytes, timeout).await
                         }
-                        Err(e) => InvocationResponse::error(&msg, &format!("RPC error: {}", e)),
+                        res => res,
                     },
-                    Err(_) => InvocationResponse::error(&msg, "RPC call timed out"),
-                }
+                    None => rpc_request(&client, &host_id, &subject, &bytes, timeout).await,
+                };
+                release(&offload, offloaded);
+                res.unwrap_or_else(|e| InvocationResponse::error(&msg, &e.to_string()))
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+// Attempts skip zone preference, since the caller is already choosing between targets
+impl Handler<AttemptInvocation> for RpcClient {
+    type Result = ResponseActFuture<Self, std::result::Result<InvocationResponse, RpcError>>;
+
+    fn handle(&mut self, msg: AttemptInvocation, _ctx: &mut Self::Context) -> Self::Result {
+        trace!(
+            "Attempting lattice RPC call to {}",
+            msg.invocation.target.url()
+        );
+        let client = self.nc.clone().unwrap();
+        let host_id = self.host_id.clone().unwrap();
+        let subject = invoke_subject(&self.ns_prefix, &msg.invocation.target);
+        let timeout = self.rpc_timeout;
+        let offload = self.offload.clone();
+        Box::pin(
+            async move {
+                let (bytes, offloaded) = prepare(&offload, msg.invocation)
+                    .await
+                    .map_err(RpcError::Undelivered)?;
+                let res = rpc_request(&client, &host_id, &subject, &bytes, timeout).await;
+                release(&offload, offloaded);
+                res
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+// Ordered invocations skip zone preference, since there is only ever one instance to reach
+impl Handler<OrderedInvocation> for RpcClient {
+    type Result = ResponseActFuture<Self, InvocationResponse>;
+
+    fn handle(&mut self, msg: OrderedInvocation, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("Performing ordered lattice RPC call to {}", msg.actor);
+        // Sequence numbers are assigned in mailbox order, before any request is in flight
+        let seq = self.ordered_seq.entry(msg.actor.to_string()).or_insert(0);
+        let envelope = OrderedEnvelope {
+            sender: self.host_id.clone().unwrap(),
+            seq: *seq,
+            invocation: msg.invocation,
+        };
+        *seq += 1;
+        let client = self.nc.clone().unwrap();
+        let host_id = self.host_id.clone().unwrap();
+        let subject = ordered_subject(&self.ns_prefix, &msg.actor);
+        let timeout = self.rpc_timeout;
+        let offload_to = self.offload.clone();
+        Box::pin(
+            async move {
+                let inv = envelope.invocation.clone();
+                let (invocation, offloaded) = match offload(&offload_to, envelope.invocation).await
+                {
+                    Ok(o) => o,
+                    Err(e) => return InvocationResponse::error(&inv, &e.to_string()),
+                };
+                let envelope = OrderedEnvelope {
+                    invocation,
+                    ..envelope
+                };
+                let ir = rpc_request(&client, &host_id, &subject, &envelope.to_bytes(), timeout)
+                    .await
+                    .unwrap_or_else(|e| InvocationResponse::error(&inv, &e.to_string()));
+                release(&offload_to, offloaded);
+                ir
             }
             .into_actor(self),
         )
@@ -135,21 +698,33 @@ impl Handler<ClaimsInbound> for RpcClient {
 
     fn handle(&mut self, msg: ClaimsInbound, _ctx: &mut Self::Context) -> Self::Result {
         trace!("Received notification of actor claims added to lattice");
+        let host_id = self.host_id.as_ref().unwrap();
+        if !crate::partitions::reachable(host_id, msg.origin.as_deref())
+            || !crate::roster::admits(host_id, sender(&msg.origin, msg.verified))
+        {
+            return Box::pin(async {}.into_actor(self));
+        }
         let target = self.bus.clone().unwrap();
-        if msg.claims.is_some() {
-            Box::pin(
-                async move {
+        // Claims we've already seen (including our own, echoed back) don't need re-processing
+        let fresh: Vec<_> = msg
+            .claims
+            .into_iter()
+            .filter(|c| self.mark_seen(c))
+            .collect();
+        let origin = msg.origin;
+        Box::pin(
+            async move {
+                for claims in fresh {
                     let _ = target
                         .send(PutClaims {
-                            claims: msg.claims.as_ref().unwrap().clone(),
+                            claims,
+                            origin: origin.clone(),
                         })
                         .await;
                 }
-                .into_actor(self),
-            )
-        } else {
-            Box::pin(async move {}.into_actor(self))
-        }
+            }
+            .into_actor(self),
+        )
     }
 }
 
@@ -158,8 +733,15 @@ impl Handler<LinkInbound> for RpcClient {
 
     fn handle(&mut self, msg: LinkInbound, _ctx: &mut Self::Context) -> Self::Result {
         trace!("Received notification of link definition lattice-wide publication");
+        let host_id = self.host_id.as_ref().unwrap();
+        if !crate::partitions::reachable(host_id, msg.origin.as_deref())
+            || !crate::roster::admits(host_id, sender(&msg.origin, msg.verified))
+        {
+            return Box::pin(async {}.into_actor(self));
+        }
         let target = self.bus.clone().unwrap();
         let _hc = HostController::from_hostlocal_registry(self.host_id.as_ref().unwrap());
+        let origin = msg.origin;
         if let Some(link) = msg.link {
             Box::pin(
                 async move {
@@ -171,6 +753,7 @@ impl Handler<LinkInbound> for RpcClient {
                             provider_id: link.provider_id,
                             actor: link.actor_id,
                             values: link.values,
+                            origin,
                         })
                         .await;
                     //let _ = hc.send(CheckLink { linkdef: ld }).await;
@@ -199,10 +782,20 @@ impl Handler<AdvertiseLink> for RpcClient {
         };
         let nc = self.nc.clone().unwrap();
         let subject = links_subject(&self.ns_prefix);
-        let bytes = serialize(&ld).unwrap(); // we should never fail our own serialize
+        let key = self.key.as_ref().unwrap();
+        let bytes = serialize(&ld).and_then(|body| {
+            if self.legacy_gossip {
+                Ok(body)
+            } else {
+                SignedUpdate::seal(body, key)
+            }
+        });
+        // The reply subject still names this host, for hosts that predate signed updates
+        let origin = self.host_id.clone().unwrap();
         Box::pin(
             async move {
-                let r = nc.publish(&subject, &bytes).await;
+                let bytes = bytes.map_err(|e| format!("Failed to sign link definition: {}", e))?;
+                let r = nc.publish_request(&subject, &origin, &bytes).await;
                 let _ = nc.flush();
                 match r {
                     Ok(_) => Ok(()),
@@ -217,21 +810,162 @@ impl Handler<AdvertiseLink> for RpcClient {
 impl Handler<AdvertiseClaims> for RpcClient {
     type Result = ResponseActFuture<Self, Result<()>>;
 
-    fn handle(&mut self, msg: AdvertiseClaims, _ctx: &mut Self::Context) -> Self::Result {
-        trace!("Publishing actor claims on lattice");
-        let nc = self.nc.clone().unwrap();
-        let subject = claims_subject(&self.ns_prefix);
-        let bytes = serialize(&msg.claims).unwrap(); //should never fail
+    fn handle(&mut self, msg: AdvertiseClaims, ctx: &mut Self::Context) -> Self::Result {
+        if !self.mark_seen(&msg.claims) {
+            trace!("Suppressing re-broadcast of claims already seen on lattice");
+            return Box::pin(async move { Ok(()) }.into_actor(self));
+        }
+        trace!("Queueing actor claims for publication on lattice");
+        let (tx, rx) = oneshot::channel();
+        self.pending_claims.push(msg.claims);
+        self.claims_waiters.push(tx);
+        if self.pending_claims.len() >= CLAIMS_BATCH_MAX {
+            self.flush_claims(ctx);
+        } else if self.pending_claims.len() == 1 {
+            ctx.run_later(CLAIMS_BATCH_WINDOW, |act, ctx| act.flush_claims(ctx));
+        }
+        // Resolves once the batch the claims went out in has been published
         Box::pin(
             async move {
-                let r = nc.publish(&subject, &bytes).await;
-                let _ = nc.flush();
-                match r {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err("Failed to publish claims notification".into()),
+                match rx.await {
+                    Ok(res) => res.map_err(|e| e.into()),
+                    Err(_) => Err("Claims were dropped before being published".into()),
                 }
             }
             .into_actor(self),
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_claims, open_update, sender, ClaimsBatch, Opened, RpcClient, SignedUpdate,
+        CLAIMS_FORMAT_VERSION,
+    };
+    use crate::compression::DeflateCompression;
+    use crate::generated::core::{deserialize, serialize};
+    use crate::WasccEntity;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use wascap::jwt::{Actor, Claims, ClaimsBuilder};
+    use wascap::prelude::KeyPair;
+
+    fn claims(subject: &str) -> Claims<Actor> {
+        let mut claims = ClaimsBuilder::new()
+            .with_metadata(Actor::new(
+                subject.to_string(),
+                None,
+                None,
+                false,
+                None,
+                None,
+            ))
+            .build();
+        claims.subject = subject.to_string();
+        claims
+    }
+
+    fn client(legacy_gossip: bool) -> RpcClient {
+        RpcClient {
+            key: Some(Arc::new(KeyPair::new_server())),
+            claims_compression: Some(Box::new(DeflateCompression::default())),
+            legacy_gossip,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn claims_are_batched_and_deduplicated() {
+        let mut client = client(false);
+        assert!(client.mark_seen(&claims("Ma")));
+        assert!(!client.mark_seen(&claims("Ma")));
+        assert!(client.mark_seen(&claims("Mb")));
+
+        // A batch goes out as one signed message, and decodes back to every set of claims in it
+        let batch = vec![claims("Ma"), claims("Mb")];
+        let messages = client.encode_claims(&batch).unwrap();
+        assert_eq!(1, messages.len());
+        let opened = open_update(&messages[0], None).unwrap();
+        assert!(opened.verified);
+        let decoded = decode_claims(&opened.body, &DeflateCompression::default());
+        let subjects: Vec<_> = decoded.iter().map(|c| c.subject.as_str()).collect();
+        assert_eq!(vec!["Ma", "Mb"], subjects);
+
+        // A batch in a format this host doesn't know yet is discarded rather than misread
+        let mut newer: ClaimsBatch = deserialize(&opened.body).unwrap();
+        newer.version = CLAIMS_FORMAT_VERSION + 1;
+        let newer = serialize(&newer).unwrap();
+        assert!(decode_claims(&newer, &DeflateCompression::default()).is_empty());
+    }
+
+    #[test]
+    fn legacy_gossip_sends_claims_one_by_one() {
+        let client = client(true);
+        let messages = client.encode_claims(&[claims("Ma"), claims("Mb")]).unwrap();
+        assert_eq!(2, messages.len());
+        for (message, subject) in messages.iter().zip(&["Ma", "Mb"]) {
+            let opened = open_update(message, None).unwrap();
+            assert!(!opened.verified);
+            let decoded = decode_claims(&opened.body, &DeflateCompression::default());
+            assert_eq!(*subject, decoded[0].subject);
+        }
+    }
+
+    #[test]
+    fn updates_name_their_signer() {
+        let host = KeyPair::new_server();
+        let sealed = SignedUpdate::seal(b"link".to_vec(), &host).unwrap();
+
+        // The signer, not the reply subject, is the origin of a signed update
+        let opened = open_update(&sealed, Some("Nspoofed".to_string())).unwrap();
+        assert_eq!(
+            Opened {
+                body: b"link".to_vec(),
+                origin: Some(host.public_key()),
+                verified: true,
+            },
+            opened
+        );
+
+        // Claiming to be another host breaks the signature
+        let mut update: SignedUpdate = deserialize(&sealed).unwrap();
+        update.signer = KeyPair::new_server().public_key();
+        assert_eq!(None, open_update(&serialize(&update).unwrap(), None));
+
+        // An unsigned update from an older host is accepted, but its sender isn't trusted
+        let legacy = open_update(b"link", Some(host.public_key())).unwrap();
+        assert!(!legacy.verified);
+        assert_eq!(None, sender(&legacy.origin, legacy.verified));
+    }
+
+    #[test]
+    fn zone_membership_comes_from_heartbeats() {
+        let mut client = RpcClient {
+            zone: Some("east".to_string()),
+            zone_ttl: Duration::from_secs(90),
+            ..Default::default()
+        };
+        let target = WasccEntity::Actor("Mactor".to_string());
+        // Nothing in the zone has said it runs the target, so calls go to any zone
+        assert_eq!(None, client.zone_subject(&target));
+
+        let zs = "wasmbus.rpc.default.zone.east.Mactor".to_string();
+        let subjects: HashSet<String> = vec![zs.to_string()].into_iter().collect();
+        client
+            .zone_members
+            .insert("Nhost".to_string(), (subjects.clone(), Instant::now()));
+        assert_eq!(Some(zs), client.zone_subject(&target));
+
+        // A host that has stopped beating no longer draws calls into the zone
+        let quiet = Instant::now() - Duration::from_secs(91);
+        client
+            .zone_members
+            .insert("Nhost".to_string(), (subjects, quiet));
+        assert_eq!(None, client.zone_subject(&target));
+
+        let unzoned = RpcClient::default().zone_subject(&target);
+        assert_eq!(None, unzoned);
+    }
+}
@@ -1,4 +1,12 @@
 use crate::generated::core::{deserialize, serialize};
+use crate::hlreg::Shutdown;
+use crate::limits::cap_response;
+use crate::messagebus::fanout::{fanout_subject, FanoutReply};
+use crate::messagebus::ordered::{
+    ordered_subject, owner_subject, OrderedEnvelope, Resequencer, ORDER_GAP_TIMEOUT,
+};
+use crate::messagebus::slow_consumer::SubscriptionStats;
+use crate::offload::{restore, PayloadOffload};
 use crate::{Invocation, InvocationResponse, WasccEntity};
 use actix::prelude::*;
 use futures::StreamExt;
@@ -11,6 +19,21 @@ pub(crate) struct CreateSubscription {
     pub target: Recipient<Invocation>,
     pub nc: Arc<nats::asynk::Connection>,
     pub namespace: Option<String>,
+    pub zone: Option<String>,
+    pub host_id: String,
+    /// The ID of this host if the entity is an actor that requires ordered delivery, in which
+    /// case this host becomes the actor's sole consumer on the lattice
+    pub owner: Option<String>,
+    pub stats: Arc<SubscriptionStats>,
+    pub offload: Option<PayloadOffload>,
+    pub max_response_size: Option<usize>,
+}
+
+/// Hands the lattice subscription's invocations to a different subscriber from now on
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Retarget {
+    pub target: Recipient<Invocation>,
 }
 
 #[derive(Message)]
@@ -20,17 +43,51 @@ struct RpcInvocation {
     reply: Option<String>,
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+struct FanoutRpcInvocation {
+    invocation: Option<Invocation>,
+    reply: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct OrderedRpcInvocation {
+    envelope: Option<OrderedEnvelope>,
+    reply: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct OwnerProbe {
+    reply: Option<String>,
+}
+
 #[derive(Default)]
 pub(crate) struct RpcSubscription {
     target: Option<Recipient<Invocation>>,
     nc: Option<Arc<nats::asynk::Connection>>,
     ns_prefix: Option<String>,
+    owner: Option<String>,
+    resequencer: Resequencer<(Invocation, Option<String>)>,
+    stats: Arc<SubscriptionStats>,
+    offload: Option<PayloadOffload>,
+    host_id: String,
+    max_response_size: Option<usize>,
 }
 
 impl Actor for RpcSubscription {
     type Context = Context<Self>;
 }
 
+impl Handler<Retarget> for RpcSubscription {
+    type Result = ();
+
+    fn handle(&mut self, msg: Retarget, _ctx: &mut Self::Context) {
+        self.target = Some(msg.target);
+    }
+}
+
 impl Handler<CreateSubscription> for RpcSubscription {
     type Result = ResponseActFuture<Self, ()>;
 
@@ -39,13 +96,50 @@ impl Handler<CreateSubscription> for RpcSubscription {
         self.target = Some(msg.target);
         self.nc = Some(msg.nc.clone());
         self.ns_prefix = msg.namespace;
+        self.owner = msg.owner;
+        self.stats = msg.stats;
+        self.offload = msg.offload;
+        self.host_id = msg.host_id;
+        self.max_response_size = msg.max_response_size;
+        let fs = match msg.entity {
+            WasccEntity::Actor(ref actor) if self.owner.is_some() => {
+                return self.create_ordered_subscription(actor.to_string());
+            }
+            WasccEntity::Actor(ref actor) => Some(fanout_subject(&self.ns_prefix, actor)),
+            _ => None,
+        };
         let nc = msg.nc.clone();
         let s = invoke_subject(&self.ns_prefix, &msg.entity);
+        // Also join the subject for this host's zone so that callers in the same
+        // zone can reach this instance in preference to those in other zones
+        let zs = msg
+            .zone
+            .as_ref()
+            .map(|z| zoned_invoke_subject(&self.ns_prefix, z, &msg.entity));
 
         Box::pin(
-            async move { nc.queue_subscribe(&s, &s).await }
-                .into_actor(self)
-                .map(|sub, _act, ctx| {
+            async move {
+                let sub = nc.queue_subscribe(&s, &s).await;
+                let zsub = match zs {
+                    Some(zs) => Some(nc.queue_subscribe(&zs, &zs).await),
+                    None => None,
+                };
+                // Every instance of an actor hears its fan-out invocations
+                let fsub = match fs {
+                    Some(fs) => Some(nc.subscribe(&fs).await),
+                    None => None,
+                };
+                (sub, zsub, fsub)
+            }
+            .into_actor(self)
+            .map(|(sub, zsub, fsub), _act, ctx| {
+                if let Some(Ok(fsub)) = fsub {
+                    ctx.add_message_stream(fsub.map(|m| FanoutRpcInvocation {
+                        invocation: deserialize::<Invocation>(&m.data).ok(),
+                        reply: m.reply.clone(),
+                    }));
+                }
+                for sub in std::iter::once(sub).chain(zsub) {
                     if let Ok(sub) = sub {
                         ctx.add_message_stream(sub.map(|m| {
                             let i = deserialize::<Invocation>(&m.data);
@@ -61,24 +155,66 @@ impl Handler<CreateSubscription> for RpcSubscription {
                             }
                         }))
                     }
-                }),
+                }
+            }),
         )
     }
 }
 
+// Whether an inbound invocation came from a host that a simulated partition lets reach this one.
+// Invocations from hosts it doesn't are dropped without an answer, as the network would have
+fn partition_admits(host_id: &str, inv: &Option<Invocation>) -> bool {
+    inv.as_ref().map_or(true, |inv| {
+        crate::partitions::reachable(host_id, Some(&inv.host_id))
+    })
+}
+
 impl Handler<RpcInvocation> for RpcSubscription {
     type Result = ResponseActFuture<Self, ()>;
 
     fn handle(&mut self, msg: RpcInvocation, _ctx: &mut Self::Context) -> Self::Result {
+        if !partition_admits(&self.host_id, &msg.invocation) {
+            return Box::pin(async {}.into_actor(self));
+        }
         let target = self.target.clone().unwrap();
         let nc = self.nc.as_ref().unwrap().clone();
+        let stats = self.stats.clone();
+        let offload = self.offload.clone();
+        let max_response = self.max_response_size;
+        let refused = msg
+            .invocation
+            .as_ref()
+            .and_then(|inv| crate::roster::refuse(&self.host_id, inv));
         Box::pin(
             async move {
-                if let Some(inv) = msg.invocation {
+                if let Some(mut inv) = msg.invocation {
                     trace!("Handling inbound RPC call from {}", inv.origin.url());
-                    let res = target.send(inv).await; // TODO: convert this into a timeout
+                    if let Some(ir) = refused {
+                        if let Some(ref reply) = msg.reply {
+                            let _ = nc.publish(reply, &serialize(&ir).unwrap()).await;
+                        }
+                        return;
+                    }
+                    if !stats.received() {
+                        // Answer right away so the caller isn't left waiting for a timeout
+                        let ir = InvocationResponse::error(
+                            &inv,
+                            "Target is a slow consumer, invocation dropped",
+                        );
+                        if let Some(ref reply) = msg.reply {
+                            let _ = nc.publish(reply, &serialize(&ir).unwrap()).await;
+                        }
+                        return;
+                    }
+                    let target_entity = inv.target.clone();
+                    let res = match restore(&offload, &mut inv).await {
+                        Ok(_) => target.send(inv).await, // TODO: convert this into a timeout
+                        Err(e) => Ok(InvocationResponse::error(&inv, &e.to_string())),
+                    };
+                    stats.completed();
                     match res {
                         Ok(ir) => {
+                            let ir = cap_response(max_response, &target_entity, ir);
                             let _ = nc
                                 .publish(msg.reply.as_ref().unwrap(), &serialize(&ir).unwrap())
                                 .await;
@@ -94,6 +230,166 @@ impl Handler<RpcInvocation> for RpcSubscription {
     }
 }
 
+impl Handler<FanoutRpcInvocation> for RpcSubscription {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: FanoutRpcInvocation, _ctx: &mut Self::Context) -> Self::Result {
+        if !partition_admits(&self.host_id, &msg.invocation) {
+            return Box::pin(async {}.into_actor(self));
+        }
+        let target = self.target.clone().unwrap();
+        let nc = self.nc.clone().unwrap();
+        let offload = self.offload.clone();
+        let host_id = self.host_id.to_string();
+        let max_response = self.max_response_size;
+        let refused = msg
+            .invocation
+            .as_ref()
+            .and_then(|inv| crate::roster::refuse(&host_id, inv));
+        Box::pin(
+            async move {
+                if let (Some(mut inv), Some(reply)) = (msg.invocation, msg.reply) {
+                    trace!("Handling inbound fan-out call from {}", inv.origin.url());
+                    let ir = match refused {
+                        Some(ir) => ir,
+                        None => match restore(&offload, &mut inv).await {
+                            Ok(_) => match target.send(inv.clone()).await {
+                                Ok(ir) => cap_response(max_response, &inv.target, ir),
+                                Err(_) => {
+                                    InvocationResponse::error(&inv, "Unresponsive target actor")
+                                }
+                            },
+                            Err(e) => InvocationResponse::error(&inv, &e.to_string()),
+                        },
+                    };
+                    let fr = FanoutReply::new(&host_id, &inv.target.key(), ir);
+                    let _ = nc.publish(&reply, &serialize(&fr).unwrap()).await;
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl RpcSubscription {
+    // Ordered actors are only reachable over their ordered subject, on which this host is the
+    // only (non-queue) subscriber
+    fn create_ordered_subscription(&mut self, actor: String) -> ResponseActFuture<Self, ()> {
+        info!("Subscribing to ordered invocations for actor {}", actor);
+        let nc = self.nc.clone().unwrap();
+        let s = ordered_subject(&self.ns_prefix, &actor);
+        let os = owner_subject(&self.ns_prefix, &actor);
+        Box::pin(
+            async move { (nc.subscribe(&s).await, nc.subscribe(&os).await) }
+                .into_actor(self)
+                .map(|(sub, osub), _act, ctx| {
+                    if let Ok(sub) = sub {
+                        ctx.add_message_stream(sub.map(|m| OrderedRpcInvocation {
+                            envelope: deserialize::<OrderedEnvelope>(&m.data).ok(),
+                            reply: m.reply.clone(),
+                        }));
+                    }
+                    if let Ok(osub) = osub {
+                        ctx.add_message_stream(osub.map(|m| OwnerProbe {
+                            reply: m.reply.clone(),
+                        }));
+                    }
+                    ctx.run_interval(ORDER_GAP_TIMEOUT / 2, |act, ctx| {
+                        let ready = act.resequencer.expire(ORDER_GAP_TIMEOUT);
+                        act.execute_in_order(ready, ctx);
+                    });
+                }),
+        )
+    }
+
+    // Executes the invocations one after another, pausing this actor's mailbox until they've
+    // all completed so that nothing later can overtake them
+    fn execute_in_order(
+        &mut self,
+        ready: Vec<(Invocation, Option<String>)>,
+        ctx: &mut Context<Self>,
+    ) {
+        if ready.is_empty() {
+            return;
+        }
+        let target = self.target.clone().unwrap();
+        let nc = self.nc.clone().unwrap();
+        let offload = self.offload.clone();
+        let max_response = self.max_response_size;
+        ctx.wait(
+            async move {
+                for (mut inv, reply) in ready {
+                    let ir = match restore(&offload, &mut inv).await {
+                        Ok(_) => match target.send(inv.clone()).await {
+                            Ok(ir) => cap_response(max_response, &inv.target, ir),
+                            Err(_) => InvocationResponse::error(&inv, "Unresponsive target actor"),
+                        },
+                        Err(e) => InvocationResponse::error(&inv, &e.to_string()),
+                    };
+                    if let Some(reply) = reply {
+                        let _ = nc.publish(&reply, &serialize(&ir).unwrap()).await;
+                    }
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl Handler<OrderedRpcInvocation> for RpcSubscription {
+    type Result = ();
+
+    fn handle(&mut self, msg: OrderedRpcInvocation, ctx: &mut Self::Context) {
+        if let Some(env) = msg.envelope {
+            trace!(
+                "Received ordered RPC call {} from host {}",
+                env.seq,
+                env.sender
+            );
+            if let Some(ir) = crate::roster::refuse(&self.host_id, &env.invocation) {
+                let nc = self.nc.clone().unwrap();
+                if let Some(reply) = msg.reply {
+                    actix::spawn(async move {
+                        let _ = nc.publish(&reply, &serialize(&ir).unwrap()).await;
+                    });
+                }
+                return;
+            }
+            let ready = self
+                .resequencer
+                .push(&env.sender, env.seq, (env.invocation, msg.reply));
+            self.execute_in_order(ready, ctx);
+        }
+    }
+}
+
+impl Handler<OwnerProbe> for RpcSubscription {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: OwnerProbe, _ctx: &mut Self::Context) -> Self::Result {
+        let nc = self.nc.clone().unwrap();
+        let owner = self.owner.clone().unwrap_or_default();
+        Box::pin(
+            async move {
+                if let Some(reply) = msg.reply {
+                    let _ = nc.publish(&reply, owner.as_bytes()).await;
+                }
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+// Stopping the subscription actor drops the NATS subscription stream, which
+// removes this entity's interest from the lattice
+impl Handler<Shutdown> for RpcSubscription {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 // An RPC subscription will also act as a proxy for the underlying target when in local
 // dispatch mode, so it needs to be able to handle invocations as well as rpc invocations
 impl Handler<Invocation> for RpcSubscription {
@@ -129,6 +425,23 @@ pub(crate) fn invoke_subject(ns_prefix: &Option<String>, entity: &WasccEntity) -
     }
 }
 
+pub(crate) fn zoned_invoke_subject(
+    ns_prefix: &Option<String>,
+    zone: &str,
+    entity: &WasccEntity,
+) -> String {
+    let prefix = format!("{}.zone.{}", subject_prefix(ns_prefix), zone);
+    match entity {
+        WasccEntity::Actor(s) => format!("{}.{}", prefix, s),
+        WasccEntity::Capability { id, link_name, .. } => format!("{}.{}.{}", prefix, id, link_name),
+    }
+}
+
+/// The subject on which the hosts in a zone say which of their instances can be reached there
+pub(crate) fn zone_beats_subject(ns_prefix: &Option<String>, zone: &str) -> String {
+    format!("{}.zone.{}.beats", subject_prefix(ns_prefix), zone)
+}
+
 pub(crate) fn links_subject(ns_prefix: &Option<String>) -> String {
     let prefix = subject_prefix(ns_prefix);
     format!("{}.links", prefix)
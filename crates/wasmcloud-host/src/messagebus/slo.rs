@@ -0,0 +1,85 @@
+use super::MessageBus;
+use crate::control_interface::cloudevents::EventFormat;
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::hlreg::HostLocalSystemService;
+use crate::metrics::check_slo;
+use crate::ControlEvent;
+use actix::prelude::*;
+use std::time::Duration;
+
+const SLO_EVALUATION_INTERVAL: Duration = Duration::from_secs(10);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl MessageBus {
+    /// Periodically checks each actor's recent invocations against its objective, publishing
+    /// an event whenever an actor starts or stops breaching it. An actor that stops being
+    /// called recovers once its invocations age out of the metrics window
+    pub(crate) fn evaluate_slos(&self, ctx: &mut Context<Self>) {
+        if self.slos.is_empty() {
+            return;
+        }
+        ctx.run_interval(SLO_EVALUATION_INTERVAL, |act, _ctx| {
+            let host_id = act.key.as_ref().unwrap().public_key();
+            let now = crate::clock::monotonic(&host_id);
+            let mut events = vec![];
+            for (actor, slo) in act.slos.iter() {
+                let breach = act
+                    .actor_metrics
+                    .get(actor)
+                    .and_then(|m| check_slo(slo, m, now));
+                match (breach, act.slo_breaches.contains(actor)) {
+                    (Some(reason), false) => {
+                        warn!("Actor {} breached its objective: {}", actor, reason);
+                        act.slo_breaches.insert(actor.to_string());
+                        events.push(ControlEvent::ActorSloBreached {
+                            actor: actor.to_string(),
+                            reason,
+                        });
+                    }
+                    (None, true) => {
+                        info!("Actor {} recovered within its objective", actor);
+                        act.slo_breaches.remove(actor);
+                        events.push(ControlEvent::ActorSloRecovered {
+                            actor: actor.to_string(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            for event in events {
+                notify_webhooks(&act.alert_webhooks, act.event_format, &host_id, &event);
+                ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent { event });
+            }
+        });
+    }
+}
+
+pub(super) fn notify_webhooks(
+    webhooks: &[String],
+    format: EventFormat,
+    host_id: &str,
+    event: &ControlEvent,
+) {
+    if webhooks.is_empty() {
+        return;
+    }
+    let body = format.encode(&event.clone().into_published(host_id));
+    for url in webhooks {
+        let url = url.to_string();
+        let body = body.clone();
+        actix::spawn(async move {
+            let res = reqwest::Client::new()
+                .post(&url)
+                .header("Content-Type", format.content_type())
+                .timeout(WEBHOOK_TIMEOUT)
+                .body(body)
+                .send()
+                .await;
+            match res {
+                Ok(r) if r.status().is_success() => {}
+                Ok(r) => error!("Alert webhook {} returned {}", url, r.status()),
+                Err(e) => error!("Failed to deliver alert to webhook {}: {}", url, e),
+            }
+        });
+    }
+}
@@ -0,0 +1,158 @@
+// A lattice subscription hands each message it receives to the actor or capability provider
+// behind it and waits for the response. When messages arrive faster than the target can handle
+// them, they pile up in the host, invisibly, until callers start timing out. Each subscription
+// counts the messages it has received but not yet answered; a subscription with too many of
+// them is a slow consumer, and past a hard limit it sheds new messages (answering them with an
+// error rather than leaving the callers to time out) so that the host's memory stays bounded.
+
+use super::slo::notify_webhooks;
+use super::MessageBus;
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::hlreg::HostLocalSystemService;
+use crate::ControlEvent;
+use actix::prelude::*;
+use control_interface::SubscriptionStatistics;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The number of unanswered messages at which a lattice subscription is considered a slow
+/// consumer
+pub const SLOW_CONSUMER_PENDING: u64 = 256;
+/// The number of unanswered messages past which a lattice subscription drops new messages
+pub const MAX_PENDING_MESSAGES: u64 = 8192;
+
+const SLOW_CONSUMER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionStats {
+    delivered: AtomicU64,
+    pending: AtomicU64,
+    peak_pending: AtomicU64,
+    dropped: AtomicU64,
+    // The dropped count as of the last check, so that each check only looks at new drops
+    dropped_checked: AtomicU64,
+}
+
+impl SubscriptionStats {
+    /// Records the arrival of a message, returning false if the message must be dropped
+    /// because too many are already waiting for an answer
+    pub fn received(&self) -> bool {
+        if self.pending.load(Ordering::SeqCst) >= MAX_PENDING_MESSAGES {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+            return false;
+        }
+        let pending = self.pending.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak_pending.fetch_max(pending, Ordering::SeqCst);
+        true
+    }
+
+    /// Records that a received message has been answered
+    pub fn completed(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        self.delivered.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // A subscription is slow if its backlog is too deep or if it has dropped messages since
+    // the last check
+    fn check(&self) -> bool {
+        let dropped = self.dropped.load(Ordering::SeqCst);
+        let previous = self.dropped_checked.swap(dropped, Ordering::SeqCst);
+        self.pending.load(Ordering::SeqCst) >= SLOW_CONSUMER_PENDING || dropped > previous
+    }
+
+    pub fn statistics(&self, subject: &str, target: &str, slow: bool) -> SubscriptionStatistics {
+        SubscriptionStatistics {
+            subject: subject.to_string(),
+            target: target.to_string(),
+            delivered: self.delivered.load(Ordering::SeqCst),
+            pending: self.pending.load(Ordering::SeqCst),
+            peak_pending: self.peak_pending.load(Ordering::SeqCst),
+            dropped: self.dropped.load(Ordering::SeqCst),
+            slow,
+        }
+    }
+}
+
+impl MessageBus {
+    /// Periodically checks each lattice subscription for a growing backlog or dropped messages,
+    /// publishing an event when a subscription becomes a slow consumer
+    pub(crate) fn watch_slow_consumers(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(SLOW_CONSUMER_CHECK_INTERVAL, |act, _ctx| {
+            let host_id = act.key.as_ref().unwrap().public_key();
+            let mut events = vec![];
+            for (entity, (subject, stats)) in act.subscription_stats.iter() {
+                let slow = stats.check();
+                let target = entity.key();
+                match (slow, act.slow_consumers.contains(&target)) {
+                    (true, false) => {
+                        let s = stats.statistics(subject, &target, true);
+                        warn!(
+                            "Subscription {} for {} is a slow consumer: {} pending, {} dropped",
+                            subject, target, s.pending, s.dropped
+                        );
+                        act.slow_consumers.insert(target.to_string());
+                        events.push(ControlEvent::SlowConsumer {
+                            subject: subject.to_string(),
+                            target,
+                            pending: s.pending,
+                            dropped: s.dropped,
+                        });
+                    }
+                    (false, true) => {
+                        info!("Subscription {} for {} has caught up", subject, target);
+                        act.slow_consumers.remove(&target);
+                    }
+                    _ => {}
+                }
+            }
+            for event in events {
+                notify_webhooks(&act.alert_webhooks, act.event_format, &host_id, &event);
+                ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent { event });
+            }
+        });
+    }
+
+    pub(crate) fn subscription_statistics(&self) -> Vec<SubscriptionStatistics> {
+        self.subscription_stats
+            .iter()
+            .map(|(entity, (subject, stats))| {
+                let target = entity.key();
+                let slow = self.slow_consumers.contains(&target);
+                stats.statistics(subject, &target, slow)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SubscriptionStats, MAX_PENDING_MESSAGES, SLOW_CONSUMER_PENDING};
+
+    #[test]
+    fn backlogs_and_drops_are_slow() {
+        let stats = SubscriptionStats::default();
+        for _ in 0..SLOW_CONSUMER_PENDING {
+            assert!(stats.received());
+        }
+        assert!(stats.check());
+        stats.completed();
+        assert!(!stats.check());
+
+        for _ in stats.pending.load(super::Ordering::SeqCst)..MAX_PENDING_MESSAGES {
+            assert!(stats.received());
+        }
+        assert!(!stats.received());
+        for _ in 0..MAX_PENDING_MESSAGES {
+            stats.completed();
+        }
+        // The drop is reported by the next check only
+        assert!(stats.check());
+        assert!(!stats.check());
+
+        let s = stats.statistics("wasmbus.rpc.default.Mxxx", "Mxxx", false);
+        assert_eq!(0, s.pending);
+        assert_eq!(MAX_PENDING_MESSAGES, s.peak_pending);
+        assert_eq!(MAX_PENDING_MESSAGES + 1, s.delivered);
+        assert_eq!(1, s.dropped);
+    }
+}
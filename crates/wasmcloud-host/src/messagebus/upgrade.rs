@@ -0,0 +1,144 @@
+// Replacing a running provider with a new version of itself would normally mean stopping it,
+// which drops every link bound to it until the new version has started and the links have been
+// re-established. Instead, the new version is started alongside the old one without being
+// subscribed to the bus, is given every link definition bound to the old one, and must pass a
+// health check. Only then is the bus's subscription (and the lattice subscription behind it)
+// pointed at the new version, so invocations go to one version or the other, never to neither.
+
+use super::rpc_subscription::Retarget;
+use super::MessageBus;
+use crate::dispatch::gen_config_invocation;
+use crate::generated::core::{deserialize, HealthResponse};
+use crate::messagebus::hb::generate_ping;
+use crate::{Invocation, Result, WasccEntity};
+use actix::prelude::*;
+use std::time::Duration;
+
+const HEALTH_CHECK_ATTEMPTS: u32 = 10;
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Binds a provider that isn't yet subscribed to the bus to the links of the running provider it
+/// is about to replace, then waits for it to report itself healthy
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub(crate) struct WarmProvider {
+    pub entity: WasccEntity,
+    pub recipient: Recipient<Invocation>,
+}
+
+/// Points an existing subscription at a new subscriber, leaving any lattice subscription in place
+#[derive(Message)]
+#[rtype(result = "Result<()>")]
+pub(crate) struct RedirectSubscriber {
+    pub interest: WasccEntity,
+    pub subscriber: Recipient<Invocation>,
+}
+
+impl Handler<WarmProvider> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: WarmProvider, _ctx: &mut Context<Self>) -> Self::Result {
+        let (provider_id, contract_id, link_name) = match msg.entity {
+            WasccEntity::Capability {
+                ref id,
+                ref contract_id,
+                ref link_name,
+            } => (
+                id.to_string(),
+                contract_id.to_string(),
+                link_name.to_string(),
+            ),
+            _ => {
+                return Box::pin(
+                    async { Err("Only capability providers can be warmed".into()) }
+                        .into_actor(self),
+                )
+            }
+        };
+        let key = self.key.as_ref().unwrap();
+        let binds: Vec<_> = self
+            .link_cache
+            .all()
+            .into_iter()
+            .filter(|(k, v)| {
+                k.contract_id == contract_id
+                    && k.link_name == link_name
+                    && v.providers().iter().any(|p| *p == provider_id)
+            })
+            .filter_map(|(k, v)| {
+                self.claims_cache.get(&k.actor).map(|claims| {
+                    gen_config_invocation(
+                        key,
+                        &k.actor,
+                        &contract_id,
+                        &provider_id,
+                        claims.clone(),
+                        link_name.to_string(),
+                        v.values.clone(),
+                    )
+                })
+            })
+            .collect();
+        let ping = generate_ping(&msg.entity, key);
+        let recipient = msg.recipient;
+        Box::pin(
+            async move {
+                for inv in binds {
+                    let ir = recipient.send(inv).await?;
+                    if let Some(e) = ir.error {
+                        return Err(format!("New provider failed to bind a link: {}", e).into());
+                    }
+                }
+                for attempt in 1..=HEALTH_CHECK_ATTEMPTS {
+                    let ir = recipient.send(ping.clone()).await?;
+                    match ir.error {
+                        None => match deserialize::<HealthResponse>(&ir.msg) {
+                            Ok(hr) if hr.healthy => return Ok(()),
+                            Ok(hr) => trace!("New provider not healthy yet: {}", hr.message),
+                            Err(e) => trace!("New provider health check unreadable: {}", e),
+                        },
+                        Some(e) => trace!("New provider health check failed: {}", e),
+                    }
+                    if attempt < HEALTH_CHECK_ATTEMPTS {
+                        actix_rt::time::delay_for(HEALTH_CHECK_INTERVAL).await;
+                    }
+                }
+                Err("New provider did not become healthy".into())
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<RedirectSubscriber> for MessageBus {
+    type Result = ResponseActFuture<Self, Result<()>>;
+
+    fn handle(&mut self, msg: RedirectSubscriber, _ctx: &mut Context<Self>) -> Self::Result {
+        if !self.subscribers.contains_key(&msg.interest) {
+            let err = format!("{} is not subscribed to the bus", msg.interest.url());
+            return Box::pin(async move { Err(err.into()) }.into_actor(self));
+        }
+        trace!("Bus redirecting interest for {}", msg.interest.url());
+        match self.rpc_subscriptions.get(&msg.interest) {
+            // The bus delivers through the lattice subscription, which holds the real subscriber
+            Some(rpcsub) => {
+                let rpcsub = rpcsub.clone();
+                Box::pin(
+                    async move {
+                        rpcsub
+                            .send(Retarget {
+                                target: msg.subscriber,
+                            })
+                            .await?;
+                        Ok(())
+                    }
+                    .into_actor(self),
+                )
+            }
+            None => {
+                self.subscribers.insert(msg.interest, msg.subscriber);
+                Box::pin(async { Ok(()) }.into_actor(self))
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+// Percentiles and objectives are computed over the invocations of a recent span of time rather
+// than a number of recent invocations, so that an actor that stops being called ages out of a
+// breach instead of being judged on its last calls forever. Error rates are computed over the
+// same window as percentiles, so the two always describe the same invocations; only the call and
+// error counts cover the whole life of a stream. Times are readings of the host's monotonic clock.
+//
+// Per-link statistics can also be exported in the Prometheus text format, for scrapers and
+// dashboards that don't speak the control interface.
+
+use control_interface::LinkStatistics;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+// How long invocations count towards percentiles and objectives
+const METRICS_WINDOW: Duration = Duration::from_secs(300);
+// The most invocations kept in the window, however busy the stream, to bound memory
+const MAX_WINDOW_SAMPLES: usize = 4096;
+// Objectives aren't evaluated until there are enough recent invocations to be meaningful
+const SLO_MIN_SAMPLES: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Duration,
+    elapsed: Duration,
+    success: bool,
+}
+
+/// Call counts and a sliding window of latencies for a stream of invocations
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InvocationMetrics {
+    pub invocations: u64,
+    pub errors: u64,
+    /// Invocations whose response was failed for being larger than the host allows
+    pub too_large: u64,
+    latencies: VecDeque<Sample>,
+}
+
+/// A service level objective for an actor. When the actor's recent invocations exceed either
+/// threshold, the host publishes an `ActorSloBreached` event (and notifies any alert webhooks),
+/// followed by an `ActorSloRecovered` event once the actor is back within its objective
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActorSlo {
+    /// The maximum fraction of recent invocations that may fail, from 0.0 to 1.0
+    pub max_error_rate: Option<f64>,
+    /// The maximum 99th percentile latency of recent invocations
+    pub max_p99: Option<Duration>,
+}
+
+impl InvocationMetrics {
+    /// Records an invocation that completed at `now`
+    pub fn record(&mut self, now: Duration, elapsed: Duration, success: bool) {
+        self.invocations += 1;
+        if !success {
+            self.errors += 1;
+        }
+        while self.latencies.front().map_or(false, |s| !in_window(s, now))
+            || self.latencies.len() >= MAX_WINDOW_SAMPLES
+        {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(Sample {
+            at: now,
+            elapsed,
+            success,
+        });
+    }
+
+    // The invocations that completed within the window ending at `now`
+    fn recent(&self, now: Duration) -> impl Iterator<Item = &Sample> {
+        self.latencies.iter().filter(move |s| in_window(s, now))
+    }
+
+    /// The 99th percentile latency of the invocations within the window ending at `now`
+    pub fn p99(&self, now: Duration) -> Duration {
+        let mut sorted: Vec<_> = self.recent(now).map(|s| s.elapsed).collect();
+        if sorted.is_empty() {
+            return Duration::default();
+        }
+        sorted.sort();
+        let idx = ((sorted.len() as f64 * 0.99).ceil() as usize).max(1) - 1;
+        sorted[idx]
+    }
+
+    /// The fraction of the invocations within the window ending at `now` that failed, from 0.0
+    /// to 1.0
+    pub fn error_rate(&self, now: Duration) -> f64 {
+        let (total, failed) = self
+            .recent(now)
+            .fold((0, 0), |(t, f), s| (t + 1, f + (!s.success) as usize));
+        if total == 0 {
+            0.0
+        } else {
+            failed as f64 / total as f64
+        }
+    }
+}
+
+fn in_window(sample: &Sample, now: Duration) -> bool {
+    now.checked_sub(sample.at)
+        .map_or(true, |age| age <= METRICS_WINDOW)
+}
+
+/// Checks the invocations within the window ending at `now` against an objective, returning a
+/// description of the breach if either threshold has been exceeded
+pub(crate) fn check_slo(
+    slo: &ActorSlo,
+    metrics: &InvocationMetrics,
+    now: Duration,
+) -> Option<String> {
+    if metrics.recent(now).count() < SLO_MIN_SAMPLES {
+        return None;
+    }
+    let error_rate = metrics.error_rate(now);
+    if let Some(max) = slo.max_error_rate {
+        if error_rate > max {
+            return Some(format!(
+                "Error rate {:.3} exceeds objective of {:.3}",
+                error_rate, max
+            ));
+        }
+    }
+    let p99 = metrics.p99(now);
+    if let Some(max) = slo.max_p99 {
+        if p99 > max {
+            return Some(format!(
+                "p99 latency {}ms exceeds objective of {}ms",
+                p99.as_millis(),
+                max.as_millis()
+            ));
+        }
+    }
+    None
+}
+
+/// Renders link statistics in the Prometheus text exposition format
+pub(crate) fn link_metrics_text(host_id: &str, stats: &[LinkStatistics]) -> String {
+    let families: [(&str, &str, &str, fn(&LinkStatistics) -> String); 5] = [
+        (
+            "wasmcloud_link_invocations_total",
+            "counter",
+            "Invocations of providers over the link",
+            |s| s.invocations.to_string(),
+        ),
+        (
+            "wasmcloud_link_errors_total",
+            "counter",
+            "Failed invocations of providers over the link",
+            |s| s.errors.to_string(),
+        ),
+        (
+            "wasmcloud_link_responses_too_large_total",
+            "counter",
+            "Invocations failed for responses larger than the host allows",
+            |s| s.responses_too_large.unwrap_or_default().to_string(),
+        ),
+        (
+            "wasmcloud_link_error_rate",
+            "gauge",
+            "Fraction of recent invocations over the link that failed",
+            |s| s.error_rate.to_string(),
+        ),
+        (
+            "wasmcloud_link_p99_latency_seconds",
+            "gauge",
+            "99th percentile latency of recent invocations over the link",
+            |s| (s.p99_latency_ms as f64 / 1000.0).to_string(),
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in families.iter() {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for s in stats {
+            let _ = writeln!(
+                text,
+                "{}{{host=\"{}\",actor=\"{}\",contract=\"{}\",link_name=\"{}\"}} {}",
+                name,
+                escape_label(host_id),
+                escape_label(&s.actor_id),
+                escape_label(&s.contract_id),
+                escape_label(&s.link_name),
+                value(s)
+            );
+        }
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        check_slo, link_metrics_text, ActorSlo, InvocationMetrics, MAX_WINDOW_SAMPLES,
+        METRICS_WINDOW,
+    };
+    use control_interface::LinkStatistics;
+    use std::time::Duration;
+
+    const NOW: Duration = Duration::from_secs(1000);
+
+    #[test]
+    fn error_rate_and_p99() {
+        let mut m = InvocationMetrics::default();
+        assert_eq!(0.0, m.error_rate(NOW));
+        for i in 1..=100 {
+            m.record(NOW, Duration::from_millis(i), i % 10 != 0);
+        }
+        assert_eq!(0.1, m.error_rate(NOW));
+        assert_eq!(Duration::from_millis(99), m.p99(NOW));
+
+        // Only the invocations within the window count towards the percentile
+        let later = NOW + METRICS_WINDOW + Duration::from_secs(1);
+        assert_eq!(Duration::default(), m.p99(later));
+        m.record(later, Duration::from_millis(1), true);
+        assert_eq!(Duration::from_millis(1), m.p99(later));
+        // The error rate covers the same window as the percentile
+        assert_eq!(0.0, m.error_rate(later));
+        assert_eq!(101, m.invocations);
+        assert_eq!(10, m.errors);
+
+        // However busy the stream, the window holds a bounded number of invocations
+        for _ in 0..MAX_WINDOW_SAMPLES * 2 {
+            m.record(later, Duration::from_millis(1), true);
+        }
+        assert_eq!(MAX_WINDOW_SAMPLES, m.latencies.len());
+    }
+
+    #[test]
+    fn slo_breach_and_recovery() {
+        let slo = ActorSlo {
+            max_error_rate: Some(0.1),
+            max_p99: Some(Duration::from_millis(50)),
+        };
+        let mut m = InvocationMetrics::default();
+        for _ in 0..5 {
+            m.record(NOW, Duration::from_millis(1), false);
+        }
+        // Too few samples to judge
+        assert!(check_slo(&slo, &m, NOW).is_none());
+        for _ in 0..5 {
+            m.record(NOW, Duration::from_millis(1), true);
+        }
+        assert!(check_slo(&slo, &m, NOW).unwrap().starts_with("Error rate"));
+
+        let later = NOW + METRICS_WINDOW + Duration::from_secs(1);
+        for _ in 0..100 {
+            m.record(later, Duration::from_millis(100), true);
+        }
+        assert!(check_slo(&slo, &m, later).unwrap().starts_with("p99"));
+        for _ in 0..100 {
+            m.record(later, Duration::from_millis(1), true);
+        }
+        assert!(check_slo(&slo, &m, later).is_none());
+    }
+
+    #[test]
+    fn idle_actors_recover() {
+        let slo = ActorSlo {
+            max_error_rate: Some(0.1),
+            max_p99: None,
+        };
+        let mut m = InvocationMetrics::default();
+        for _ in 0..20 {
+            m.record(NOW, Duration::from_millis(1), false);
+        }
+        assert!(check_slo(&slo, &m, NOW).is_some());
+        // No further calls: once the failures leave the window the actor is no longer in breach
+        assert!(check_slo(&slo, &m, NOW + METRICS_WINDOW).is_some());
+        assert!(check_slo(&slo, &m, NOW + METRICS_WINDOW + Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn link_statistics_export() {
+        let stats = vec![LinkStatistics {
+            actor_id: "Mactor".to_string(),
+            contract_id: "wascc:keyvalue".to_string(),
+            link_name: "cache \"hot\"".to_string(),
+            invocations: 20,
+            errors: 2,
+            error_rate: 0.25,
+            p99_latency_ms: 1500,
+            responses_too_large: None,
+            labels: None,
+        }];
+        let text = link_metrics_text("Nhost", &stats);
+        let labels =
+            r#"{host="Nhost",actor="Mactor",contract="wascc:keyvalue",link_name="cache \"hot\""}"#;
+        assert!(text.contains("# TYPE wasmcloud_link_invocations_total counter\n"));
+        assert!(text.contains(&format!("wasmcloud_link_invocations_total{} 20\n", labels)));
+        assert!(text.contains(&format!("wasmcloud_link_errors_total{} 2\n", labels)));
+        assert!(text.contains(&format!(
+            "wasmcloud_link_responses_too_large_total{} 0\n",
+            labels
+        )));
+        assert!(text.contains(&format!("wasmcloud_link_error_rate{} 0.25\n", labels)));
+        assert!(text.contains(&format!(
+            "wasmcloud_link_p99_latency_seconds{} 1.5\n",
+            labels
+        )));
+    }
+}
@@ -1,61 +1,97 @@
+use crate::NativeCapability;
 use crate::Result;
-use provider_archive::ProviderArchive;
+use actix_web::web;
 use std::env::temp_dir;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 pub(crate) const OCI_VAR_USER: &str = "OCI_REGISTRY_USER";
 pub(crate) const OCI_VAR_PASSWORD: &str = "OCI_REGISTRY_PASSWORD";
 
-pub(crate) async fn fetch_oci_bytes(img: &str, allow_latest: bool) -> Result<Vec<u8>> {
+pub(crate) async fn fetch_oci_bytes(
+    host_id: &str,
+    img: &str,
+    allow_latest: bool,
+) -> Result<Vec<u8>> {
     if !allow_latest && img.ends_with(":latest") {
         return Err(
             "Fetching images tagged 'latest' is currently prohibited in this host. This option can be overridden".into());
     }
-    let cf = cached_file(img);
-    if !cf.exists() {
-        let cfg = oci_distribution::client::ClientConfig::default();
-        let mut c = oci_distribution::Client::new(cfg);
+    if let Some(bundle) = crate::bundle::active_bundle(host_id) {
+        return bundle.read(img);
+    }
+    let path = fetch_oci_path(host_id, img, allow_latest).await?;
+    let mut buf = vec![];
+    let mut f = std::fs::File::open(path)?;
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
+}
 
-        let img = oci_distribution::Reference::from_str(img)?;
-        let auth = if let Ok(u) = std::env::var(OCI_VAR_USER) {
-            if let Ok(p) = std::env::var(OCI_VAR_PASSWORD) {
-                oci_distribution::secrets::RegistryAuth::Basic(u, p)
-            } else {
-                oci_distribution::secrets::RegistryAuth::Anonymous
-            }
-        } else {
-            oci_distribution::secrets::RegistryAuth::Anonymous
-        };
-        let imgdata: Result<oci_distribution::client::ImageData> = c
-            .pull_image(&img, &auth)
-            .await
-            .map_err(|e| format!("{}", e).into());
+fn registry_auth() -> oci_distribution::secrets::RegistryAuth {
+    match (std::env::var(OCI_VAR_USER), std::env::var(OCI_VAR_PASSWORD)) {
+        (Ok(u), Ok(p)) => oci_distribution::secrets::RegistryAuth::Basic(u, p),
+        _ => oci_distribution::secrets::RegistryAuth::Anonymous,
+    }
+}
 
-        match imgdata {
-            Ok(imgdata) => {
-                let mut f = std::fs::File::create(cf)?;
-                f.write_all(&imgdata.content)?;
-                f.flush()?;
-                Ok(imgdata.content)
-            }
-            Err(e) => {
-                error!("Failed to fetch OCI bytes: {}", e);
-                Err("Failed to fetch OCI bytes".into())
-            }
+// Pulls the image's layers from the registry into the cache file one at a time, so that no more
+// than a single layer is ever held in memory
+async fn pull_to_cache(img: &str, cf: &Path) -> Result<()> {
+    let cfg = oci_distribution::client::ClientConfig::default();
+    let mut c = oci_distribution::Client::new(cfg);
+    let img = oci_distribution::Reference::from_str(img)?;
+    c.auth(
+        &img,
+        &registry_auth(),
+        &oci_distribution::client::RegistryOperation::Pull,
+    )
+    .await
+    .map_err(|e| format!("{}", e))?;
+    let (manifest, _digest) = c.pull_manifest(&img).await.map_err(|e| format!("{}", e))?;
+    // Written to the side and renamed into place, so that a concurrent fetch of the same image
+    // (e.g. a start command racing a preload) never reads a partial file
+    let tmp = cf.with_extension(format!("{}.part", uuid::Uuid::new_v4()));
+    let res: Result<()> = async {
+        std::fs::File::create(&tmp)?;
+        for layer in manifest.layers.iter() {
+            // The registry client needs a tokio 0.2 writer, so each layer is buffered and then
+            // appended to the file on a blocking thread
+            let mut buf = Vec::new();
+            c.pull_layer(&img, &layer.digest, &mut buf)
+                .await
+                .map_err(|e| format!("{}", e))?;
+            let path = tmp.clone();
+            web::block(move || {
+                let mut f = std::fs::OpenOptions::new().append(true).open(path)?;
+                f.write_all(&buf)?;
+                f.flush()
+            })
+            .await
+            .map_err(|e| format!("Failed to write image layer: {}", e))?;
         }
-    } else {
-        let mut buf = vec![];
-        let mut f = std::fs::File::open(cached_file(img))?;
-        f.read_to_end(&mut buf)?;
-        Ok(buf)
+        std::fs::rename(&tmp, cf)?;
+        Ok(())
+    }
+    .await;
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
     }
+    res
+}
+
+/// The directory in which OCI image bytes are cached after being downloaded
+pub(crate) fn oci_cache_dir() -> PathBuf {
+    temp_dir().join("wasmcloud_ocicache")
+}
+
+/// Removes an image from the cache, so that it is downloaded again the next time it's needed
+pub(crate) fn evict_cached(img: &str) {
+    let _ = std::fs::remove_file(cached_file(img));
 }
 
-fn cached_file(img: &str) -> PathBuf {
-    let path = temp_dir();
-    let path = path.join("wasmcloud_ocicache");
+pub(crate) fn cached_file(img: &str) -> PathBuf {
+    let path = oci_cache_dir();
     let _ = ::std::fs::create_dir_all(&path);
     // should produce a file like wascc_azurecr_io_kvcounter_v1.bin
     let img = img.replace(":", "_");
@@ -67,11 +103,39 @@ fn cached_file(img: &str) -> PathBuf {
     path
 }
 
-pub(crate) async fn fetch_provider_archive(
+/// Fetches an image into the local cache (or finds it in the offline bundle) and returns the
+/// path of the file holding it, so that large artifacts can be read from disk as needed. Images
+/// are written to the cache a layer at a time, and never held in memory as a whole
+pub(crate) async fn fetch_oci_path(
+    host_id: &str,
+    img: &str,
+    allow_latest: bool,
+) -> Result<PathBuf> {
+    if !allow_latest && img.ends_with(":latest") {
+        return Err(
+            "Fetching images tagged 'latest' is currently prohibited in this host. This option can be overridden".into());
+    }
+    if let Some(bundle) = crate::bundle::active_bundle(host_id) {
+        return bundle.path(img);
+    }
+    let cf = cached_file(img);
+    if !cf.exists() {
+        if let Err(e) = pull_to_cache(img, &cf).await {
+            error!("Failed to fetch OCI bytes: {}", e);
+            return Err("Failed to fetch OCI bytes".into());
+        }
+    }
+    Ok(cf)
+}
+
+/// Fetches a provider archive and extracts the plugin library for this host from it
+pub(crate) async fn fetch_provider(
+    host_id: &str,
     img: &str,
     allow_latest: bool,
-) -> Result<ProviderArchive> {
-    let bytes = fetch_oci_bytes(img, allow_latest).await?;
-    ProviderArchive::try_load(&bytes)
+    link_name: Option<String>,
+) -> Result<NativeCapability> {
+    let path = fetch_oci_path(host_id, img, allow_latest).await?;
+    NativeCapability::from_archive_file(&path, link_name)
         .map_err(|e| format!("Failed to load provider archive: {}", e).into())
 }
@@ -0,0 +1,288 @@
+// NATS limits the size of a message (1MB by default), so an invocation with a large payload can't
+// be sent over the lattice as-is. A host configured with a payload store puts the payload of any
+// invocation that would exceed the lattice's maximum message size into the store, and sends the
+// invocation with a claim check (the payload's key, size, and digest) in place of the payload.
+// The receiving host fetches the payload from the same store, verifies it against the claim
+// check, and puts it back into the invocation before it is authorized or delivered, so actors
+// and providers never see the difference. The invocation's signature covers the original
+// payload, which is verified once the payload has been restored.
+//
+// The sending host removes an offloaded payload from the store once it has the response. Store
+// operations block, so they are run on a thread pool rather than on the calling actor.
+
+use crate::dispatch::{Invocation, PayloadReference};
+use crate::generated::core::serialize;
+use crate::hooks::bytes_digest;
+use crate::Result;
+use actix_web::web;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The maximum message size of a NATS server with the default configuration
+pub const DEFAULT_LATTICE_MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// A store that holds the payloads of invocations too large to send over the lattice. Every
+/// host in the lattice that may receive such invocations needs to be configured with a store
+/// that reaches the same objects
+pub trait PayloadStore: ClonePayloadStore + Sync + Send {
+    fn put(&self, key: &str, payload: &[u8]) -> Result<()>;
+    fn get(&self, key: &str) -> Result<Vec<u8>>;
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+#[doc(hidden)]
+pub trait ClonePayloadStore {
+    fn clone_store(&self) -> Box<dyn PayloadStore>;
+}
+
+impl<T> ClonePayloadStore for T
+where
+    T: PayloadStore + Clone + 'static,
+{
+    fn clone_store(&self) -> Box<dyn PayloadStore> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn PayloadStore> {
+    fn clone(&self) -> Self {
+        self.clone_store()
+    }
+}
+
+/// Stores payloads as files in a directory, which must be shared by the hosts in the lattice
+/// (for example, a network file system mount)
+#[derive(Clone)]
+pub struct FilesystemPayloadStore {
+    root: PathBuf,
+}
+
+impl FilesystemPayloadStore {
+    pub fn new(root: impl AsRef<Path>) -> FilesystemPayloadStore {
+        FilesystemPayloadStore {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        valid_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+impl PayloadStore for FilesystemPayloadStore {
+    fn put(&self, key: &str, payload: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        fs::create_dir_all(&self.root)?;
+        // Write under a temporary name first so that readers never see a partial payload
+        let tmp = self.root.join(format!("{}.partial", key));
+        fs::write(&tmp, payload)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(key)?)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Ok(fs::remove_file(self.path(key)?)?)
+    }
+}
+
+/// Stores payloads as objects under a base URL with plain HTTP `PUT`, `GET`, and `DELETE`
+/// requests, which suits S3-compatible object stores (through a bucket or gateway that accepts
+/// the given headers as credentials) as well as simple HTTP blob services
+#[derive(Clone)]
+pub struct HttpPayloadStore {
+    base_url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpPayloadStore {
+    pub fn new(base_url: &str) -> HttpPayloadStore {
+        HttpPayloadStore {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers: vec![],
+        }
+    }
+
+    /// Adds a header (for example, an authorization header) to every request made to the store
+    pub fn with_header(self, name: &str, value: &str) -> HttpPayloadStore {
+        let mut headers = self.headers.clone();
+        headers.push((name.to_string(), value.to_string()));
+        HttpPayloadStore { headers, ..self }
+    }
+
+    fn request(&self, method: reqwest::Method, key: &str) -> reqwest::blocking::RequestBuilder {
+        let url = format!("{}/{}", self.base_url, key);
+        self.headers.iter().fold(
+            reqwest::blocking::Client::new().request(method, &url),
+            |req, (k, v)| req.header(k.as_str(), v.as_str()),
+        )
+    }
+}
+
+impl PayloadStore for HttpPayloadStore {
+    fn put(&self, key: &str, payload: &[u8]) -> Result<()> {
+        self.request(reqwest::Method::PUT, key)
+            .body(payload.to_vec())
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let res = self
+            .request(reqwest::Method::GET, key)
+            .send()?
+            .error_for_status()?;
+        Ok(res.bytes()?.to_vec())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.request(reqwest::Method::DELETE, key)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct PayloadOffload {
+    pub max_payload: usize,
+    pub store: Box<dyn PayloadStore>,
+}
+
+/// Offloads the invocation's payload if the invocation is too large to send over the lattice,
+/// returning the invocation to send in its place and the key of the offloaded payload
+pub(crate) async fn offload(
+    offload: &Option<PayloadOffload>,
+    inv: Invocation,
+) -> Result<(Invocation, Option<String>)> {
+    let offload = match offload {
+        Some(o) if serialize(&inv)?.len() > o.max_payload => o,
+        _ => return Ok((inv, None)),
+    };
+    let reference = PayloadReference {
+        key: format!("{}.payload", inv.id),
+        size: inv.msg.len() as u64,
+        digest: bytes_digest(&inv.msg),
+    };
+    trace!(
+        "Offloading {} byte payload of invocation {}",
+        reference.size,
+        inv.id
+    );
+    let store = offload.store.clone();
+    let key = reference.key.to_string();
+    let msg = inv.msg.clone();
+    web::block(move || store.put(&key, &msg))
+        .await
+        .map_err(|e| format!("Failed to offload invocation payload: {}", e))?;
+    let key = reference.key.to_string();
+    Ok((
+        Invocation {
+            msg: vec![],
+            payload_ref: Some(reference),
+            ..inv
+        },
+        Some(key),
+    ))
+}
+
+/// Puts an offloaded payload back into the invocation, verifying it against the claim check
+pub(crate) async fn restore(offload: &Option<PayloadOffload>, inv: &mut Invocation) -> Result<()> {
+    let reference = match inv.payload_ref {
+        Some(ref r) => r.clone(),
+        None => return Ok(()),
+    };
+    let store = match offload {
+        Some(o) => o.store.clone(),
+        None => {
+            return Err(
+                "Invocation payload was offloaded, but this host has no payload store".into(),
+            )
+        }
+    };
+    valid_key(&reference.key)?;
+    let key = reference.key.to_string();
+    let msg = web::block(move || store.get(&key))
+        .await
+        .map_err(|e| format!("Failed to fetch offloaded invocation payload: {}", e))?;
+    verify(&reference, &msg)?;
+    inv.msg = msg;
+    inv.payload_ref = None;
+    Ok(())
+}
+
+// Keys arrive from other hosts, so they must not be able to name anything in the store other
+// than an offloaded payload (such as a path outside of a filesystem store's directory)
+fn valid_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && !key.starts_with('.')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid payload key '{}'", key).into())
+    }
+}
+
+fn verify(reference: &PayloadReference, msg: &[u8]) -> Result<()> {
+    if msg.len() as u64 != reference.size || bytes_digest(msg) != reference.digest {
+        Err(format!(
+            "Offloaded payload {} does not match its claim check",
+            reference.key
+        )
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Removes an offloaded payload from the store in the background
+pub(crate) fn release(offload: &Option<PayloadOffload>, key: Option<String>) {
+    if let (Some(o), Some(key)) = (offload, key) {
+        let store = o.store.clone();
+        actix::spawn(async move {
+            if let Err(e) = web::block(move || store.delete(&key)).await {
+                warn!("Failed to remove offloaded invocation payload: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify, FilesystemPayloadStore, PayloadStore};
+    use crate::dispatch::PayloadReference;
+    use crate::hooks::bytes_digest;
+
+    #[test]
+    fn filesystem_store_round_trips_and_verifies() {
+        let root = std::env::temp_dir().join(format!("payloads-{}", std::process::id()));
+        let store = FilesystemPayloadStore::new(&root);
+        let payload = vec![7u8; 4096];
+        store.put("inv1.payload", &payload).unwrap();
+        let fetched = store.get("inv1.payload").unwrap();
+
+        let reference = PayloadReference {
+            key: "inv1.payload".to_string(),
+            size: payload.len() as u64,
+            digest: bytes_digest(&payload),
+        };
+        assert!(verify(&reference, &fetched).is_ok());
+        assert!(verify(&reference, &fetched[1..]).is_err());
+        let mut tampered = fetched.clone();
+        tampered[0] = 8;
+        assert!(verify(&reference, &tampered).is_err());
+
+        assert!(store.get("../inv1.payload").is_err());
+        store.delete("inv1.payload").unwrap();
+        assert!(store.get("inv1.payload").is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
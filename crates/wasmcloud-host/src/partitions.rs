@@ -0,0 +1,116 @@
+// Testing how a lattice copes with hosts that can't reach each other (cache reconciliation,
+// tombstoning, retries) shouldn't need real network chaos tooling. A partition splits hosts
+// running in this process into groups, and while it's in place each host drops the lattice
+// traffic it receives from hosts in the other groups: invocations, claims and link
+// advertisements, zone heartbeats, roster notices, and lock tickets and answers. Healing the
+// partition lets that traffic through again. Traffic is dropped by the receiver without an
+// answer, so callers see the same timeouts they would see if the network had failed. Hosts that
+// aren't in a partition are unaffected, as is the control interface.
+
+use crate::Result;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_PARTITION: AtomicU64 = AtomicU64::new(1);
+
+// The partition and group of each partitioned host, by host ID
+static GROUPS: Lazy<RwLock<HashMap<String, (u64, usize)>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// A simulated network partition between groups of hosts in this process. Hosts in different
+/// groups drop each other's lattice traffic until the partition is healed, either by calling
+/// `heal` or by dropping it
+/// ```ignore
+/// let partition = Partition::split(vec![vec![host_a.id()], vec![host_b.id(), host_c.id()]])?;
+/// // host A can't reach hosts B and C, and they can't reach it
+/// partition.heal();
+/// ```
+pub struct Partition {
+    id: u64,
+    hosts: Vec<String>,
+}
+
+impl Partition {
+    /// Splits the given hosts into groups that can't reach each other. Fails if a host is named
+    /// twice or is already in another partition
+    pub fn split(groups: Vec<Vec<String>>) -> Result<Partition> {
+        let id = NEXT_PARTITION.fetch_add(1, Ordering::SeqCst);
+        let mut members = HashMap::new();
+        for (group, hosts) in groups.into_iter().enumerate() {
+            for host in hosts {
+                if members.insert(host.to_string(), group).is_some() {
+                    return Err(format!("Host {} is in more than one group", host).into());
+                }
+            }
+        }
+        let mut all = GROUPS.write();
+        if let Some(host) = members.keys().find(|h| all.contains_key(*h)) {
+            return Err(format!("Host {} is already in a partition", host).into());
+        }
+        let hosts = members.keys().cloned().collect();
+        all.extend(members.into_iter().map(|(host, group)| (host, (id, group))));
+        Ok(Partition { id, hosts })
+    }
+
+    /// Ends the partition, letting its hosts reach each other again
+    pub fn heal(self) {}
+}
+
+impl Drop for Partition {
+    fn drop(&mut self) {
+        let mut all = GROUPS.write();
+        for host in self.hosts.iter() {
+            if all.get(host).map_or(false, |(id, _)| *id == self.id) {
+                all.remove(host);
+            }
+        }
+    }
+}
+
+/// Whether traffic from the peer host reaches the given host. Hosts with no peer information
+/// are always reachable
+pub(crate) fn reachable(host_id: &str, peer: Option<&str>) -> bool {
+    let peer = match peer {
+        Some(p) if p != host_id => p,
+        _ => return true,
+    };
+    let all = GROUPS.read();
+    match (all.get(host_id), all.get(peer)) {
+        (Some((a, group_a)), Some((b, group_b))) => a != b || group_a == group_b,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{reachable, Partition};
+
+    #[test]
+    fn partitions_split_and_heal() {
+        let p = Partition::split(vec![
+            vec!["PA1".to_string(), "PA2".to_string()],
+            vec!["PB1".to_string()],
+        ])
+        .unwrap();
+        assert!(reachable("PA1", Some("PA2")));
+        assert!(!reachable("PA1", Some("PB1")));
+        assert!(!reachable("PB1", Some("PA2")));
+        assert!(reachable("PB1", Some("PB1")));
+        assert!(reachable("PB1", None));
+        // Hosts outside the partition reach everyone
+        assert!(reachable("PC1", Some("PA1")));
+        assert!(reachable("PA1", Some("PC1")));
+
+        assert!(Partition::split(vec![vec!["PA1".to_string()]]).is_err());
+        assert!(Partition::split(vec![vec!["PC1".to_string()], vec!["PC1".to_string()]]).is_err());
+
+        p.heal();
+        assert!(reachable("PA1", Some("PB1")));
+        let p = Partition::split(vec![vec!["PA1".to_string()], vec!["PB1".to_string()]]).unwrap();
+        assert!(!reachable("PB1", Some("PA1")));
+        drop(p);
+        assert!(reachable("PB1", Some("PA1")));
+    }
+}
@@ -0,0 +1,224 @@
+// Works out the NATS subjects a host will use, so that its NATS user can be granted exactly
+// those rather than `>`. Subjects that depend on the actors and providers the host ends up
+// running are expressed with wildcards, since they change over the lifetime of the host.
+
+use crate::control_interface::extensions::LatticeExtension;
+use crate::messagebus::rpc_subscription::{claims_subject, links_subject, subject_prefix};
+use ::control_interface::broker::{self, commands, queries};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+const INBOX_SUBJECTS: &str = "_INBOX.>";
+
+/// A list of subjects allowed for one direction of NATS traffic
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SubjectPermissions {
+    pub allow: Vec<String>,
+}
+
+/// The NATS permissions required by a host. This serializes into the same shape as the
+/// `permissions` block of a NATS server user, and can also be used to issue a user with
+/// `LatticeCredentials::issue_user_with_permissions`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct NatsPermissions {
+    pub publish: SubjectPermissions,
+    pub subscribe: SubjectPermissions,
+}
+
+pub(crate) struct PermissionScope<'a> {
+    pub namespace: &'a str,
+    /// The host's ID, or `None` if it hasn't been started and so doesn't have one yet
+    pub host_id: Option<String>,
+    pub zone: Option<String>,
+    pub lattice_rpc: bool,
+    pub control: bool,
+    pub extensions: &'a [Box<dyn LatticeExtension>],
+}
+
+pub(crate) fn required_permissions(scope: &PermissionScope) -> NatsPermissions {
+    let ns = Some(scope.namespace.to_string());
+    let mut publish = BTreeSet::new();
+    let mut subscribe = BTreeSet::new();
+
+    if scope.lattice_rpc {
+        let prefix = subject_prefix(&ns);
+        // Actors are addressed by their key, providers by their key and link name
+        let mut subjects = vec![
+            format!("{}.*", prefix),
+            format!("{}.*.*", prefix),
+            // Hosts that own an ordered actor answer ownership probes for it
+            format!("{}.ordered.*.owner", prefix),
+            // Lattice locks are probed and contended for beneath their own subjects
+            format!("{}.locks.probe.>", prefix),
+            format!("{}.locks.ticket.>", prefix),
+            // Hosts with a roster welcome new members on a subject of the member's own
+            format!("{}.roster.welcome.*", prefix),
+            claims_subject(&ns),
+            links_subject(&ns),
+        ];
+        // A host only ever prefers targets in its own zone, and learns of them from the zone's
+        // heartbeats on a subject beneath it
+        if let Some(ref zone) = scope.zone {
+            subjects.push(format!("{}.zone.{}.*", prefix, zone));
+            subjects.push(format!("{}.zone.{}.*.*", prefix, zone));
+        }
+        for s in subjects {
+            publish.insert(s.to_string());
+            subscribe.insert(s);
+        }
+    }
+
+    if scope.control {
+        let host = scope.host_id.clone().unwrap_or_else(|| "*".to_string());
+        publish.insert(broker::control_event(&ns));
+        let subjects = vec![
+            commands::start_actor(&ns, &host),
+            commands::stop_actor(&ns, &host),
+            commands::start_provider(&ns, &host),
+            commands::stop_provider(&ns, &host),
+            commands::update_actor(&ns, &host),
+            commands::set_log_level(&ns, &host),
+            commands::prepare_upgrade(&ns, &host),
+            commands::set_trace_sampling(&ns),
+            queries::host_inventory(&ns, &host),
+            queries::host_inventory_delta(&ns, &host),
+            queries::host_config(&ns, &host),
+            queries::host_capabilities(&ns, &host),
+            queries::dependency_graph(&ns, &host),
+            queries::linkdefinitions(&ns),
+            queries::claims(&ns),
+            queries::hosts(&ns),
+            queries::provider_health(&ns, "*"),
+            queries::provider_config(&ns, "*"),
+            commands::quiesce_provider(&ns, "*"),
+            commands::resume_provider(&ns, "*"),
+            broker::provider_auction_subject(&ns),
+            broker::actor_auction_subject(&ns),
+        ];
+        subscribe.extend(subjects);
+        for ext in scope.extensions {
+            for s in ext.subjects() {
+                subscribe.insert(broker::extension_subject(&ns, ext.name(), &s));
+            }
+        }
+    }
+
+    if scope.lattice_rpc || scope.control {
+        // Replies to requests, in both directions
+        publish.insert(INBOX_SUBJECTS.to_string());
+        subscribe.insert(INBOX_SUBJECTS.to_string());
+    }
+
+    NatsPermissions {
+        publish: SubjectPermissions {
+            allow: publish.into_iter().collect(),
+        },
+        subscribe: SubjectPermissions {
+            allow: subscribe.into_iter().collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{required_permissions, PermissionScope};
+    use crate::control_interface::ctlactor::control_subjects;
+
+    // Whether a subject (which may itself contain wildcards) is matched by an allowed subject
+    fn allows(allowed: &str, subject: &str) -> bool {
+        let allowed: Vec<_> = allowed.split('.').collect();
+        let subject: Vec<_> = subject.split('.').collect();
+        for (i, a) in allowed.iter().enumerate() {
+            match (*a, subject.get(i)) {
+                (">", Some(_)) => return true,
+                (_, None) => return false,
+                ("*", Some(s)) if *s != ">" => {}
+                (a, Some(s)) if a == *s => {}
+                _ => return false,
+            }
+        }
+        allowed.len() == subject.len()
+    }
+
+    #[test]
+    fn permissions_cover_only_enabled_features() {
+        let mut scope = PermissionScope {
+            namespace: "prod",
+            host_id: Some("NHOST".to_string()),
+            zone: None,
+            lattice_rpc: false,
+            control: false,
+            extensions: &[],
+        };
+        let perms = required_permissions(&scope);
+        assert!(perms.publish.allow.is_empty());
+        assert!(perms.subscribe.allow.is_empty());
+
+        scope.lattice_rpc = true;
+        scope.zone = Some("east".to_string());
+        let perms = required_permissions(&scope);
+        assert!(perms
+            .subscribe
+            .allow
+            .contains(&"wasmbus.rpc.prod.zone.east.*".to_string()));
+        assert!(!perms
+            .subscribe
+            .allow
+            .iter()
+            .any(|s| s.starts_with("wasmbus.ctl")));
+
+        scope.control = true;
+        let perms = required_permissions(&scope);
+        assert!(perms
+            .subscribe
+            .allow
+            .contains(&"wasmbus.ctl.prod.cmd.NHOST.la".to_string()));
+        assert_eq!(
+            vec![
+                "_INBOX.>".to_string(),
+                "wasmbus.ctl.prod.events".to_string()
+            ],
+            perms
+                .publish
+                .allow
+                .iter()
+                .filter(|s| !s.starts_with("wasmbus.rpc"))
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        assert!(!perms.subscribe.allow.contains(&">".to_string()));
+    }
+
+    #[test]
+    fn permissions_cover_control_subscriptions() {
+        let prefix = Some("prod".to_string());
+        for host_id in vec![Some("NHOST".to_string()), None] {
+            let scope = PermissionScope {
+                namespace: "prod",
+                host_id: host_id.clone(),
+                zone: None,
+                lattice_rpc: false,
+                control: true,
+                extensions: &[],
+            };
+            let perms = required_permissions(&scope);
+            for subject in control_subjects(&prefix, "NHOST", &[]) {
+                assert!(
+                    perms.subscribe.allow.iter().any(|a| allows(a, &subject)),
+                    "{} is not allowed for host {:?}",
+                    subject,
+                    host_id
+                );
+            }
+        }
+        assert!(allows(
+            "wasmbus.ctl.prod.cmd.*.la",
+            "wasmbus.ctl.prod.cmd.NHOST.la"
+        ));
+        assert!(!allows(
+            "wasmbus.ctl.prod.cmd.*.la",
+            "wasmbus.ctl.prod.cmd.NHOST.sa"
+        ));
+        assert!(allows("_INBOX.>", "_INBOX.abc.def"));
+    }
+}
@@ -0,0 +1,198 @@
+// Host plugins let operators add extensions (authorizers, start hooks, billing sinks, lattice
+// extensions) to a host from `.so`/`.dylib` files, without recompiling the binary that embeds the
+// host. A plugin library is built like a native capability provider: it depends on this crate,
+// implements `HostPlugin`, and exports a constructor with the `declare_host_plugin!` macro.
+//
+// Like capability provider plugins, the plugin interface is a Rust trait rather than a C ABI, so a
+// plugin must be built with the same compiler and the same version of this crate as the host.
+// Both are checked when the plugin is loaded, through a function with a C ABI that returns plain C
+// data, so that the check itself is safe to call on a plugin built by any compiler. Nothing with
+// the Rust ABI is looked up until the check passes. Plugin libraries are never unloaded.
+
+use crate::auth::Authorizer;
+use crate::billing::BillingSink;
+use crate::control_interface::extensions::LatticeExtension;
+use crate::hooks::PreStartHook;
+use crate::Result;
+use libloading::{Library, Symbol};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::Path;
+
+/// The version of the plugin interface. It changes whenever `HostPlugin` changes incompatibly
+pub const HOST_PLUGIN_API_VERSION: u32 = 1;
+
+/// An extension to the host loaded from a dynamic library. Each method returns the extensions of
+/// a given kind that the plugin provides, and the defaults provide none
+pub trait HostPlugin: Send + Sync {
+    /// The name of the plugin, used in log messages
+    fn name(&self) -> &str;
+
+    /// An authorizer that replaces the host's authorizer. If more than one plugin provides one,
+    /// the last plugin loaded wins
+    fn authorizer(&self) -> Option<Box<dyn Authorizer>> {
+        None
+    }
+
+    fn prestart_hooks(&self) -> Vec<Box<dyn PreStartHook>> {
+        vec![]
+    }
+
+    fn billing_sinks(&self) -> Vec<Box<dyn BillingSink>> {
+        vec![]
+    }
+
+    fn lattice_extensions(&self) -> Vec<Box<dyn LatticeExtension>> {
+        vec![]
+    }
+}
+
+/// The identity of the build of this crate that a plugin was compiled against. The strings are
+/// NUL-terminated and static, so the struct can be read across a C ABI
+#[doc(hidden)]
+#[repr(C)]
+pub struct PluginBuildInfo {
+    pub api_version: u32,
+    pub host_version: *const c_char,
+    pub rustc_version: *const c_char,
+}
+
+#[doc(hidden)]
+pub fn plugin_build_info() -> PluginBuildInfo {
+    PluginBuildInfo {
+        api_version: HOST_PLUGIN_API_VERSION,
+        host_version: HOST_VERSION.as_ptr() as *const c_char,
+        rustc_version: RUSTC_VERSION.as_ptr() as *const c_char,
+    }
+}
+
+const HOST_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+// Recorded by the build, so that a host and a plugin built by different compilers can be told
+// apart. Trait objects don't have a stable layout across compilers
+const RUSTC_VERSION: &str = concat!(env!("RUSTC_VERSION"), "\0");
+
+// A build identity read out of a `PluginBuildInfo`
+#[derive(Debug, Clone, PartialEq)]
+struct BuildIdentity {
+    api_version: u32,
+    host_version: String,
+    rustc_version: String,
+}
+
+impl BuildIdentity {
+    // Safety: the strings must be NUL-terminated, or null
+    unsafe fn read(info: &PluginBuildInfo) -> BuildIdentity {
+        let string = |p: *const c_char| {
+            if p.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(p).to_string_lossy().to_string()
+            }
+        };
+        BuildIdentity {
+            api_version: info.api_version,
+            host_version: string(info.host_version),
+            rustc_version: string(info.rustc_version),
+        }
+    }
+
+    fn host() -> BuildIdentity {
+        unsafe { BuildIdentity::read(&plugin_build_info()) }
+    }
+}
+
+/// Exports a host plugin's constructor from a dynamic library. The constructor must take no
+/// arguments and return the plugin
+///
+/// ```ignore
+/// wasmcloud_host::declare_host_plugin!(AuditPlugin, AuditPlugin::default);
+/// ```
+#[macro_export]
+macro_rules! declare_host_plugin {
+    ($plugin_type:ty, $constructor:path) => {
+        #[no_mangle]
+        pub extern "C" fn __host_plugin_build_info() -> $crate::PluginBuildInfo {
+            $crate::plugin_build_info()
+        }
+
+        #[no_mangle]
+        pub extern "Rust" fn __host_plugin_create() -> *mut dyn $crate::HostPlugin {
+            let constructor: fn() -> $plugin_type = $constructor;
+            let boxed: Box<dyn $crate::HostPlugin> = Box::new(constructor());
+            Box::into_raw(boxed)
+        }
+    };
+}
+
+/// Loads a host plugin from a dynamic library, after checking that it was built against the
+/// same plugin interface, version of this crate, and compiler as the host
+pub(crate) fn load_plugin(path: &Path) -> Result<Box<dyn HostPlugin>> {
+    type BuildInfo = unsafe extern "C" fn() -> PluginBuildInfo;
+    type PluginCreate = unsafe extern "Rust" fn() -> *mut dyn HostPlugin;
+
+    let library = Library::new(path)?;
+    let plugin = unsafe {
+        let build_info: Symbol<BuildInfo> = library.get(b"__host_plugin_build_info")?;
+        check_build(&BuildIdentity::read(&build_info()), &BuildIdentity::host())
+            .map_err(|e| format!("Can't load host plugin {}: {}", path.display(), e))?;
+        let constructor: Symbol<PluginCreate> = library.get(b"__host_plugin_create")?;
+        Box::from_raw(constructor())
+    };
+    info!(
+        "Loaded host plugin {} from {}",
+        plugin.name(),
+        path.display()
+    );
+    // The plugin's code (including the vtables of everything it hands to the host) lives in the
+    // library, so it stays loaded for the life of the process
+    std::mem::forget(library);
+    Ok(plugin)
+}
+
+fn check_build(plugin: &BuildIdentity, host: &BuildIdentity) -> std::result::Result<(), String> {
+    if plugin.api_version != host.api_version {
+        Err(format!(
+            "it implements plugin API version {}, but this host requires version {}",
+            plugin.api_version, host.api_version
+        ))
+    } else if plugin.host_version != host.host_version {
+        Err(format!(
+            "it was built against wasmcloud-host {}, but this host is {}",
+            plugin.host_version, host.host_version
+        ))
+    } else if plugin.rustc_version != host.rustc_version {
+        Err(format!(
+            "it was built with rustc {}, but this host was built with {}",
+            plugin.rustc_version, host.rustc_version
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_build, BuildIdentity};
+
+    #[test]
+    fn mismatched_builds_are_rejected() {
+        let host = BuildIdentity::host();
+        assert_eq!(env!("CARGO_PKG_VERSION"), host.host_version);
+        assert!(!host.rustc_version.is_empty());
+        assert!(check_build(&BuildIdentity::host(), &host).is_ok());
+
+        let old = BuildIdentity {
+            host_version: "0.1.0".to_string(),
+            ..BuildIdentity::host()
+        };
+        assert!(check_build(&old, &host)
+            .unwrap_err()
+            .contains("wasmcloud-host 0.1.0"));
+        let other_compiler = BuildIdentity {
+            rustc_version: "rustc 1.0.0".to_string(),
+            ..BuildIdentity::host()
+        };
+        assert!(check_build(&other_compiler, &host).is_err());
+    }
+}
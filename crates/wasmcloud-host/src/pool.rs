@@ -0,0 +1,219 @@
+// Capability providers commonly hold a client (Redis connection, HTTP client, etc.) for each
+// actor that links to them. A `LinkPool` gives embedded providers a single place to keep those
+// clients, keyed by the actor and the link name. Because the host knows when links go away, it
+// evicts the affected entries itself, so providers no longer leak clients when links change.
+// Pools belong to a host as well as to a provider, so that removing a link in one host doesn't
+// evict the clients of the same provider running in another host in the process, and a host that
+// stops releases every client held in its pools.
+
+use crate::Result;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+
+// Every live pool, by the ID of the host and the public key of the provider that own it
+static POOLS: Lazy<Mutex<HashMap<(String, String), Vec<Weak<dyn Evict>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+trait Evict: Send + Sync {
+    fn evict(&self, actor: &str, link_name: &str);
+
+    fn evict_all(&self);
+}
+
+/// A point-in-time view of the activity of a link pool
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoolStats {
+    /// The number of clients currently held by the pool
+    pub active: usize,
+    /// The number of clients the pool has created
+    pub created: u64,
+    /// The number of times an existing client was handed out instead of creating a new one
+    pub reused: u64,
+    /// The number of clients removed from the pool, either by the provider or by the host
+    pub evicted: u64,
+}
+
+struct PoolInner<C> {
+    clients: RwLock<HashMap<(String, String), Arc<C>>>,
+    created: AtomicU64,
+    reused: AtomicU64,
+    evicted: AtomicU64,
+}
+
+impl<C: Send + Sync> Evict for PoolInner<C> {
+    fn evict(&self, actor: &str, link_name: &str) {
+        let key = (actor.to_string(), link_name.to_string());
+        if self.clients.write().remove(&key).is_some() {
+            self.evicted.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn evict_all(&self) {
+        let mut clients = self.clients.write();
+        self.evicted
+            .fetch_add(clients.len() as u64, Ordering::SeqCst);
+        clients.clear();
+    }
+}
+
+/// A pool of clients held by a capability provider, with one client per linked actor and link
+/// name. Clients are evicted automatically when the host removes the corresponding link, and all
+/// of them when the host stops. Any clones of a pool share the same clients
+#[derive(Clone)]
+pub struct LinkPool<C> {
+    inner: Arc<PoolInner<C>>,
+}
+
+impl<C: Send + Sync + 'static> LinkPool<C> {
+    /// Creates a new pool for the provider with the given public key, running in the host with
+    /// the given ID (see `Host::id`). The host uses these to find the pool when a link to the
+    /// provider is removed, or when the host stops
+    pub fn new(host_id: &str, provider_id: &str) -> LinkPool<C> {
+        let inner = Arc::new(PoolInner {
+            clients: RwLock::new(HashMap::new()),
+            created: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        });
+        let weak: Weak<dyn Evict> = Arc::downgrade(&(inner.clone() as Arc<dyn Evict>));
+        let mut pools = POOLS.lock();
+        let entry = pools
+            .entry((host_id.to_string(), provider_id.to_string()))
+            .or_insert_with(Vec::new);
+        entry.retain(|p| p.strong_count() > 0);
+        entry.push(weak);
+        LinkPool { inner }
+    }
+
+    /// Obtains the client for the given actor and link name, using the supplied function to
+    /// create one if the pool doesn't already hold it
+    pub fn get_or_create(
+        &self,
+        actor: &str,
+        link_name: &str,
+        create: impl FnOnce() -> Result<C>,
+    ) -> Result<Arc<C>> {
+        let key = (actor.to_string(), link_name.to_string());
+        if let Some(c) = self.inner.clients.read().get(&key) {
+            self.inner.reused.fetch_add(1, Ordering::SeqCst);
+            return Ok(c.clone());
+        }
+        let mut clients = self.inner.clients.write();
+        // Another caller may have created the client while we waited for the lock
+        if let Some(c) = clients.get(&key) {
+            self.inner.reused.fetch_add(1, Ordering::SeqCst);
+            return Ok(c.clone());
+        }
+        let c = Arc::new(create()?);
+        clients.insert(key, c.clone());
+        self.inner.created.fetch_add(1, Ordering::SeqCst);
+        Ok(c)
+    }
+
+    /// Obtains the client for the given actor and link name, if the pool holds one
+    pub fn get(&self, actor: &str, link_name: &str) -> Option<Arc<C>> {
+        self.inner
+            .clients
+            .read()
+            .get(&(actor.to_string(), link_name.to_string()))
+            .cloned()
+    }
+
+    /// Removes the client for the given actor and link name from the pool. Providers do not
+    /// need to call this for links removed by the host
+    pub fn remove(&self, actor: &str, link_name: &str) {
+        self.inner.evict(actor, link_name);
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            active: self.inner.clients.read().len(),
+            created: self.inner.created.load(Ordering::SeqCst),
+            reused: self.inner.reused.load(Ordering::SeqCst),
+            evicted: self.inner.evicted.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Called by the host when a link is removed, dropping the affected clients from every pool
+/// belonging to the provider in that host
+pub(crate) fn evict_link(host_id: &str, provider_id: &str, actor: &str, link_name: &str) {
+    let pools: Vec<Arc<dyn Evict>> = POOLS
+        .lock()
+        .get(&(host_id.to_string(), provider_id.to_string()))
+        .map(|v| v.iter().filter_map(|p| p.upgrade()).collect())
+        .unwrap_or_default();
+    for pool in pools {
+        pool.evict(actor, link_name);
+    }
+}
+
+/// Called by the host when it stops, dropping every client from its pools and forgetting them
+pub(crate) fn clear(host_id: &str) {
+    let pools: Vec<Arc<dyn Evict>> = {
+        let mut all = POOLS.lock();
+        let keys: Vec<_> = all.keys().filter(|(h, _)| h == host_id).cloned().collect();
+        keys.into_iter()
+            .filter_map(|k| all.remove(&k))
+            .flatten()
+            .filter_map(|p| p.upgrade())
+            .collect()
+    };
+    for pool in pools {
+        pool.evict_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clear, evict_link, LinkPool};
+
+    #[test]
+    fn clients_are_reused_and_evicted_on_link_removal() {
+        let pool: LinkPool<String> = LinkPool::new("Npooltest", "Vpooltest");
+        let a = pool
+            .get_or_create("Mxxx", "default", || Ok("client".to_string()))
+            .unwrap();
+        let b = pool
+            .get_or_create("Mxxx", "default", || Err("should not be called".into()))
+            .unwrap();
+        assert_eq!(a, b);
+        pool.get_or_create("Myyy", "default", || Ok("client".to_string()))
+            .unwrap();
+
+        evict_link("Npooltest", "Vpooltest", "Mxxx", "default");
+        assert!(pool.get("Mxxx", "default").is_none());
+        assert!(pool.get("Myyy", "default").is_some());
+
+        let stats = pool.stats();
+        assert_eq!(1, stats.active);
+        assert_eq!(2, stats.created);
+        assert_eq!(1, stats.reused);
+        assert_eq!(1, stats.evicted);
+    }
+
+    #[test]
+    fn pools_belong_to_their_host() {
+        let one: LinkPool<String> = LinkPool::new("Npoolone", "Vpoolshared");
+        let two: LinkPool<String> = LinkPool::new("Npooltwo", "Vpoolshared");
+        for pool in [&one, &two].iter() {
+            pool.get_or_create("Mxxx", "default", || Ok("client".to_string()))
+                .unwrap();
+        }
+
+        // Removing a link in one host leaves the other host's clients alone
+        evict_link("Npoolone", "Vpoolshared", "Mxxx", "default");
+        assert!(one.get("Mxxx", "default").is_none());
+        assert!(two.get("Mxxx", "default").is_some());
+
+        one.get_or_create("Mxxx", "default", || Ok("client".to_string()))
+            .unwrap();
+        clear("Npooltwo");
+        assert_eq!(0, two.stats().active);
+        assert_eq!(1, two.stats().evicted);
+        assert!(one.get("Mxxx", "default").is_some());
+    }
+}
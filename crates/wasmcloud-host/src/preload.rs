@@ -0,0 +1,105 @@
+// A host can be given a list of actor and provider references to fetch when it starts, without
+// starting them. Each artifact is downloaded into the OCI cache and verified (actor claims must
+// be valid, provider archives must be intact and contain a binary for this host's target), so that
+// a later start command for it is served from the cache, even if the registry is slow or
+// unreachable by then. Preloading runs in the background and reports its progress as events.
+
+use crate::actors::assert_validation_result;
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::hlreg::HostLocalSystemService;
+use crate::oci::{evict_cached, fetch_oci_bytes};
+use crate::{ControlEvent, Host, Result};
+use provider_archive::ProviderArchive;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// Fetches and verifies each of the references in turn, publishing an event as each one
+/// completes. A failure is reported and doesn't stop the remaining references
+pub(crate) async fn preload(host_id: String, refs: Vec<String>, allow_latest: bool) {
+    let cp = ControlInterface::from_hostlocal_registry(&host_id);
+    let total = refs.len();
+    let mut failed = 0;
+    for (i, image_ref) in refs.into_iter().enumerate() {
+        let event = match preload_artifact(&host_id, &image_ref, allow_latest).await {
+            Ok(()) => ControlEvent::ArtifactPreloaded {
+                image_ref,
+                completed: i + 1,
+                total,
+            },
+            Err(e) => {
+                error!("Failed to preload {}: {}", image_ref, e);
+                failed += 1;
+                ControlEvent::ArtifactPreloadFailed {
+                    image_ref,
+                    reason: e.to_string(),
+                    completed: i + 1,
+                    total,
+                }
+            }
+        };
+        cp.do_send(PublishEvent { event });
+    }
+    info!("Preloaded {} of {} artifacts", total - failed, total);
+}
+
+async fn preload_artifact(host_id: &str, image_ref: &str, allow_latest: bool) -> Result<()> {
+    let bytes = fetch_oci_bytes(host_id, image_ref, allow_latest).await?;
+    let verified = verify_artifact(&bytes);
+    if verified.is_err() {
+        evict_cached(image_ref);
+    }
+    verified
+}
+
+// Actors are WebAssembly modules, anything else must be a provider archive
+pub(crate) fn verify_artifact(bytes: &[u8]) -> Result<()> {
+    if bytes.starts_with(WASM_MAGIC) {
+        let actor = crate::Actor::from_slice(bytes)?;
+        let tv = wascap::jwt::validate_token::<wascap::jwt::Actor>(&actor.token.jwt)?;
+        assert_validation_result(&tv)
+    } else {
+        let par = ProviderArchive::try_load(bytes)
+            .map_err(|e| format!("Invalid provider archive: {}", e))?;
+        if par.target_bytes(&Host::native_target()).is_none() {
+            Err(format!(
+                "Provider archive has no binary for target {}",
+                Host::native_target()
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{preload_artifact, verify_artifact};
+    use crate::oci::cached_file;
+
+    #[test]
+    fn garbage_is_not_a_valid_artifact() {
+        assert!(verify_artifact(b"\0asm\x01\0\0\0").is_err());
+        assert!(verify_artifact(b"not an artifact").is_err());
+    }
+
+    // The echo actor stands in for a download: it is placed in the cache, where preloading finds
+    // it, verifies it, and leaves it for a later start
+    #[actix_rt::test]
+    async fn real_actors_are_verified_and_kept() {
+        let echo = std::fs::read("../../tests/modules/echo.wasm").unwrap();
+        let img = format!("registry.local/preload-echo-{}:0.1.0", std::process::id());
+        let cached = cached_file(&img);
+        std::fs::write(&cached, &echo).unwrap();
+        preload_artifact("Npreload", &img, false).await.unwrap();
+        assert_eq!(echo, std::fs::read(&cached).unwrap());
+
+        // A module whose signature no longer matches is evicted from the cache
+        let mut altered = echo.clone();
+        let last = altered.len() - 1;
+        altered[last] ^= 0xff;
+        std::fs::write(&cached, &altered).unwrap();
+        assert!(preload_artifact("Npreload", &img, false).await.is_err());
+        assert!(!cached.exists());
+    }
+}
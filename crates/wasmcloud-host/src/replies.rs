@@ -0,0 +1,322 @@
+// Request/reply over the messaging contract, with the host keeping track of who is owed a reply.
+// When a messaging provider delivers a message that has a reply subject, the host remembers the
+// subject, the link the message arrived on, and the deadline by which the requester needs an
+// answer. The actor can then reply with a host call that only carries the reply's body, either
+// while it is handling the message or, after deferring the reply, from a later invocation.
+//
+// Deadlines are milliseconds since the epoch on the host's clock, carried as an item of the
+// invocation's baggage so that they follow the request through every actor and provider it
+// reaches. Requests an actor makes while working against a deadline have their timeouts cut
+// down to the time that is left, and replies that would arrive after the requester has given up
+// are dropped by the host instead of being published. Deferred replies that are never sent are
+// forgotten once their deadline passes.
+
+use crate::baggage::{current_baggage, with_baggage};
+use crate::contracts::MESSAGING_CONTRACT;
+use crate::dispatch::{Invocation, WasccEntity};
+use crate::Result;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+use wascc_codec::messaging::{
+    BrokerMessage, DeliverMessage, PublishMessage, RequestMessage, OP_DELIVER_MESSAGE,
+};
+use wascc_codec::{deserialize, serialize};
+
+/// The body of a reply to a message an actor received
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct ReplyRequest {
+    #[serde(rename = "correlationId")]
+    pub correlation_id: String,
+    pub body: Vec<u8>,
+}
+
+/// Identifies a reply an actor has deferred, and the deadline by which it must be sent
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct DeferredReply {
+    #[serde(rename = "correlationId")]
+    pub correlation_id: String,
+    #[serde(rename = "deadlineMs")]
+    pub deadline_ms: u64,
+}
+
+/// The baggage item holding the deadline of the request an invocation is part of, in
+/// milliseconds since the epoch
+pub const BAGGAGE_DEADLINE: &str = "wasmcloud-deadline-ms";
+
+// How long an actor has to reply to a message when nothing upstream set a deadline
+const DEFAULT_REPLY_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PendingReply {
+    pub host_id: String,
+    pub actor: String,
+    pub link_name: String,
+    pub reply_to: String,
+    pub deadline_ms: u64,
+}
+
+thread_local! {
+    // The reply owed for the message the actor on this thread is handling, until it is sent or
+    // deferred
+    static CURRENT: RefCell<Option<PendingReply>> = RefCell::new(None);
+}
+
+static DEFERRED: Lazy<RwLock<HashMap<String, PendingReply>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The deadline of the invocation being handled on this thread, if there is one
+pub(crate) fn current_deadline() -> Option<u64> {
+    current_baggage()
+        .get(BAGGAGE_DEADLINE)
+        .and_then(|d| d.parse().ok())
+}
+
+/// Runs the function (the actor handling the invocation) with the invocation's baggage as the
+/// current baggage and, if the invocation is a message that expects a reply, with that reply
+/// owed. Messages that expect a reply get a deadline if they don't already have one
+pub(crate) fn handling<T>(host_id: &str, inv: &Invocation, f: impl FnOnce() -> T) -> T {
+    let pending = reply_expected(host_id, inv, crate::clock::now_millis(host_id));
+    let mut baggage = inv.baggage.clone();
+    if let Some(ref p) = pending {
+        baggage
+            .entry(BAGGAGE_DEADLINE.to_string())
+            .or_insert_with(|| p.deadline_ms.to_string());
+    }
+    let previous = CURRENT.with(|c| c.replace(pending));
+    let res = with_baggage(baggage, f);
+    if let Some(p) = CURRENT.with(|c| c.replace(previous)) {
+        // The actor may have published to the reply subject itself
+        trace!(
+            "Actor {} did not use the host to reply to {}",
+            p.actor,
+            p.reply_to
+        );
+    }
+    res
+}
+
+fn reply_expected(host_id: &str, inv: &Invocation, now_ms: u64) -> Option<PendingReply> {
+    if inv.operation != OP_DELIVER_MESSAGE {
+        return None;
+    }
+    let link_name = match inv.origin {
+        WasccEntity::Capability {
+            ref contract_id,
+            ref link_name,
+            ..
+        } if contract_id == MESSAGING_CONTRACT => link_name.to_string(),
+        _ => return None,
+    };
+    let delivered: DeliverMessage = deserialize(&inv.msg).ok()?;
+    if delivered.message.reply_to.is_empty() {
+        return None;
+    }
+    let deadline_ms = inv
+        .baggage
+        .get(BAGGAGE_DEADLINE)
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(now_ms + DEFAULT_REPLY_WINDOW.as_millis() as u64);
+    Some(PendingReply {
+        host_id: host_id.to_string(),
+        actor: inv.target.key(),
+        link_name,
+        reply_to: delivered.message.reply_to,
+        deadline_ms,
+    })
+}
+
+/// Defers the reply owed for the message being handled, returning the correlation ID with
+/// which the actor can send it from a later invocation
+pub(crate) fn defer(host_id: &str, actor: &str) -> Result<DeferredReply> {
+    let pending = take_current(host_id, actor)?;
+    let now = crate::clock::now_millis(host_id);
+    let mut lock = DEFERRED.write();
+    sweep(&mut lock, now);
+    let correlation_id = Uuid::new_v4().to_string();
+    let deadline_ms = pending.deadline_ms;
+    lock.insert(correlation_id.to_string(), pending);
+    Ok(DeferredReply {
+        correlation_id,
+        deadline_ms,
+    })
+}
+
+/// Claims the reply named by the request (the one owed for the message being handled if it has
+/// no correlation ID), returning the link to publish it on and the message to publish. Replies
+/// past their deadline are refused, since nobody is waiting for them anymore
+pub(crate) fn take_reply(
+    host_id: &str,
+    actor: &str,
+    req: ReplyRequest,
+) -> Result<(String, Vec<u8>)> {
+    let now = crate::clock::now_millis(host_id);
+    let pending = if req.correlation_id.is_empty() {
+        take_current(host_id, actor)?
+    } else {
+        let mut lock = DEFERRED.write();
+        sweep(&mut lock, now);
+        let owed = lock
+            .get(&req.correlation_id)
+            .map_or(false, |p| p.host_id == host_id && p.actor == actor);
+        if !owed {
+            return Err(format!(
+                "No reply is owed with correlation ID {}",
+                req.correlation_id
+            )
+            .into());
+        }
+        lock.remove(&req.correlation_id).unwrap()
+    };
+    if now > pending.deadline_ms {
+        warn!(
+            "Dropping reply from actor {} to {}: the deadline has passed",
+            actor, pending.reply_to
+        );
+        return Err("The deadline for this reply has passed".into());
+    }
+    let msg = PublishMessage {
+        message: BrokerMessage {
+            subject: pending.reply_to,
+            reply_to: String::new(),
+            body: req.body,
+        },
+    };
+    Ok((pending.link_name, serialize(msg)?))
+}
+
+fn take_current(host_id: &str, actor: &str) -> Result<PendingReply> {
+    CURRENT.with(|c| {
+        let mut current = c.borrow_mut();
+        let owed = current
+            .as_ref()
+            .map_or(false, |p| p.host_id == host_id && p.actor == actor);
+        if owed {
+            Ok(current.take().unwrap())
+        } else {
+            Err("The message being handled does not expect a reply".into())
+        }
+    })
+}
+
+// Forgets deferred replies whose requesters have stopped waiting
+fn sweep(deferred: &mut HashMap<String, PendingReply>, now_ms: u64) {
+    deferred.retain(|_, p| {
+        if now_ms > p.deadline_ms {
+            debug!(
+                "Actor {} never replied to {}, forgetting the reply",
+                p.actor, p.reply_to
+            );
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Cuts the timeout of a request made over the messaging contract down to the time left before
+/// the current deadline, refusing the request if the deadline has already passed
+pub(crate) fn clamp_request(host_id: &str, payload: &[u8]) -> Result<Vec<u8>> {
+    let deadline = match current_deadline() {
+        Some(d) => d,
+        None => return Ok(payload.to_vec()),
+    };
+    let remaining = deadline.saturating_sub(crate::clock::now_millis(host_id));
+    if remaining == 0 {
+        return Err("Deadline exceeded before the request was sent".into());
+    }
+    let mut req: RequestMessage = deserialize(payload)?;
+    if req.timeout_ms <= 0 || req.timeout_ms as u64 > remaining {
+        req.timeout_ms = remaining as i64;
+    }
+    Ok(serialize(req)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReplyRequest;
+    use super::{defer, handling, take_reply, BAGGAGE_DEADLINE, DEFERRED};
+    use crate::dispatch::{Invocation, WasccEntity};
+    use crate::ManualClock;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+    use wascc_codec::messaging::{BrokerMessage, DeliverMessage, OP_DELIVER_MESSAGE};
+    use wascc_codec::serialize;
+
+    fn delivery(reply_to: &str) -> Invocation {
+        let msg = DeliverMessage {
+            message: BrokerMessage {
+                subject: "orders".to_string(),
+                reply_to: reply_to.to_string(),
+                body: vec![],
+            },
+        };
+        Invocation::new(
+            &KeyPair::new_server(),
+            WasccEntity::Capability {
+                id: "Vnats".to_string(),
+                contract_id: "wascc:messaging".to_string(),
+                link_name: "default".to_string(),
+            },
+            WasccEntity::Actor("Mxxx".to_string()),
+            OP_DELIVER_MESSAGE,
+            serialize(msg).unwrap(),
+        )
+    }
+
+    fn reply(correlation_id: &str) -> ReplyRequest {
+        ReplyRequest {
+            correlation_id: correlation_id.to_string(),
+            body: b"ok".to_vec(),
+        }
+    }
+
+    #[test]
+    fn replies_are_owed_once_and_expire() {
+        let inv = delivery("_INBOX.1");
+        handling("Nhost", &inv, || {
+            assert!(crate::baggage::current_baggage().contains_key(BAGGAGE_DEADLINE));
+            assert!(take_reply("Nhost", "Myyy", reply("")).is_err());
+            let (link, _) = take_reply("Nhost", "Mxxx", reply("")).unwrap();
+            assert_eq!("default", link);
+            assert!(take_reply("Nhost", "Mxxx", reply("")).is_err());
+        });
+        // Nothing is owed outside of the invocation, or for messages without a reply subject
+        assert!(take_reply("Nhost", "Mxxx", reply("")).is_err());
+        handling("Nhost", &delivery(""), || {
+            assert!(defer("Nhost", "Mxxx").is_err());
+        });
+
+        let deferred = handling("Nhost", &inv, || defer("Nhost", "Mxxx").unwrap());
+        assert!(take_reply("Nhost", "Mxxx", reply(&deferred.correlation_id)).is_ok());
+        assert!(take_reply("Nhost", "Mxxx", reply(&deferred.correlation_id)).is_err());
+
+        let late = inv.with_baggage(
+            vec![(BAGGAGE_DEADLINE.to_string(), "1".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let deferred = handling("Nhost", &late, || defer("Nhost", "Mxxx").unwrap());
+        assert!(take_reply("Nhost", "Mxxx", reply(&deferred.correlation_id)).is_err());
+        assert!(!DEFERRED.read().contains_key(&deferred.correlation_id));
+    }
+
+    #[test]
+    fn deadlines_follow_the_host_clock() {
+        let clock = ManualClock::new();
+        crate::clock::register("Nreplies", Arc::new(clock.clone()));
+        let inv = delivery("_INBOX.2");
+        let first = handling("Nreplies", &inv, || defer("Nreplies", "Mxxx").unwrap());
+        let second = handling("Nreplies", &inv, || defer("Nreplies", "Mxxx").unwrap());
+        clock.advance(Duration::from_secs(29));
+        assert!(take_reply("Nreplies", "Mxxx", reply(&first.correlation_id)).is_ok());
+        clock.advance(Duration::from_secs(2));
+        assert!(take_reply("Nreplies", "Mxxx", reply(&second.correlation_id)).is_err());
+        crate::clock::unregister("Nreplies");
+    }
+}
@@ -0,0 +1,592 @@
+// By default a lattice has no membership list: any host that can publish on the lattice's
+// subjects is a member, and its invocations, claims, and links are trusted like any other's. A
+// host built with a roster instead keeps an explicit list of members. Each host in such a
+// lattice is given an admission key, and joins by publishing a notice signed with it that names
+// the host and carries its version and labels. Members that trust the key add the host to their
+// roster and welcome it with a notice of their own, so the new host learns about them too. Hosts
+// announce themselves again with every heartbeat and are dropped from the roster after missing a
+// few announcements in a row; a host that stops cleanly sends a signed leave notice instead.
+// Every notice carries the time it was issued and a sequence number, and members refuse notices
+// that are older than the roster's time to live or that don't come after the last notice they
+// accepted from the same host, so a captured notice can't be replayed to bring back a host that
+// has left.
+//
+// A host with a roster ignores invocations, claims, and links sent by hosts outside it, and
+// reports each such host once with an `UnauthorizedHost` event. Claims and links are signed with
+// the key of the host that published them, and it is that signer the roster checks. Hosts
+// without a roster accept every host, as before, so a lattice can move to a roster one host at a
+// time.
+
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::hlreg::{HostLocalSystemService, Shutdown};
+use crate::{ControlEvent, Invocation, InvocationResponse, Result};
+use actix::prelude::*;
+use data_encoding::HEXUPPER;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use wascap::prelude::KeyPair;
+
+// A member that misses this many announcements in a row is dropped from the roster
+const MISSED_ANNOUNCEMENTS: u32 = 3;
+
+const HOST_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+static ROSTERS: Lazy<RwLock<HashMap<String, Roster>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The key a host signs its roster notices with, and the keys whose notices it accepts. See
+/// `HostBuilder::with_roster`
+#[derive(Clone)]
+pub(crate) struct RosterConfig {
+    pub admission_seed: String,
+    pub trusted_issuers: HashSet<String>,
+}
+
+/// A host in a lattice's roster
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RosterMember {
+    pub host_id: String,
+    /// The public key of the admission key that signed the host's notices
+    pub issuer: String,
+    pub version: String,
+    pub labels: HashMap<String, String>,
+    /// When the host joined the lattice, in seconds since the epoch
+    pub joined_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum NoticeKind {
+    Join,
+    Announce,
+    Leave,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NoticeBody {
+    kind: NoticeKind,
+    member: RosterMember,
+    /// When the notice was issued, in seconds since the epoch
+    issued_at: u64,
+    /// Increases with every notice the host issues
+    seq: u64,
+}
+
+// A notice signed with the admission key of the host it is about. The body is kept serialized,
+// so the signature is checked against exactly the bytes that were signed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Notice {
+    body: String,
+    signature: String,
+}
+
+impl Notice {
+    fn sign(body: &NoticeBody, admission: &KeyPair) -> Result<Notice> {
+        let body = serde_json::to_string(body)?;
+        let signature = HEXUPPER.encode(&admission.sign(body.as_bytes())?);
+        Ok(Notice { body, signature })
+    }
+
+    // Checks that the notice was signed by the admission key it names, and that the key is one
+    // of the trusted issuers
+    fn check(&self, body: &NoticeBody, trusted: &HashSet<String>) -> Result<()> {
+        if !trusted.contains(&body.member.issuer) {
+            return Err(format!("Admission key {} is not trusted", body.member.issuer).into());
+        }
+        let sig = HEXUPPER.decode(self.signature.as_bytes())?;
+        KeyPair::from_public_key(&body.member.issuer)?
+            .verify(self.body.as_bytes(), &sig)
+            .map_err(|_| "Roster notice signature is invalid")?;
+        Ok(())
+    }
+}
+
+// The members a host knows of, with the (monotonic) time each was last heard from. The issue
+// time and sequence number of the last notice accepted from each host are kept after the host
+// leaves, so its old notices stay refused
+#[derive(Default)]
+struct Roster {
+    members: HashMap<String, (RosterMember, Duration)>,
+    reported: HashSet<String>,
+    last_notice: HashMap<String, (u64, u64)>,
+    ttl: Duration,
+}
+
+impl Roster {
+    // Records the notice as the latest from its host, unless it was issued outside the time to
+    // live (either way, to allow for clock skew) or doesn't follow the last one accepted
+    fn accept_notice(&mut self, body: &NoticeBody, now_secs: u64) -> Result<()> {
+        let ttl = self.ttl.as_secs().max(1);
+        if body.issued_at.saturating_add(ttl) < now_secs
+            || body.issued_at > now_secs.saturating_add(ttl)
+        {
+            return Err("Roster notice is stale".into());
+        }
+        let stamp = (body.issued_at, body.seq);
+        match self.last_notice.get(&body.member.host_id) {
+            Some(last) if stamp <= *last => Err("Roster notice was replayed".into()),
+            _ => {
+                self.last_notice
+                    .insert(body.member.host_id.to_string(), stamp);
+                Ok(())
+            }
+        }
+    }
+
+    // Adds a member or refreshes one already known, returning whether it is new
+    fn admit(&mut self, member: RosterMember, now: Duration) -> bool {
+        self.reported.remove(&member.host_id);
+        self.members
+            .insert(member.host_id.to_string(), (member, now))
+            .is_none()
+    }
+
+    fn remove(&mut self, host_id: &str) -> bool {
+        self.members.remove(host_id).is_some()
+    }
+
+    fn is_member(&self, host_id: &str, now: Duration) -> bool {
+        self.members
+            .get(host_id)
+            .map_or(false, |(_, seen)| !expired(*seen, now, self.ttl))
+    }
+
+    // Drops the members that haven't been heard from within the time to live
+    fn expire(&mut self, now: Duration) -> Vec<String> {
+        let ttl = self.ttl;
+        let gone: Vec<String> = self
+            .members
+            .iter()
+            .filter(|(_, (_, seen))| expired(*seen, now, ttl))
+            .map(|(id, _)| id.to_string())
+            .collect();
+        for id in gone.iter() {
+            self.members.remove(id);
+        }
+        gone
+    }
+}
+
+fn expired(seen: Duration, now: Duration, ttl: Duration) -> bool {
+    now.checked_sub(seen).map_or(false, |quiet| quiet >= ttl)
+}
+
+/// Whether the host accepts traffic from the given peer. A host with a roster ignores traffic
+/// from hosts outside it (and from senders it can't identify), reporting each host the first
+/// time. Hosts without a roster accept everything
+pub(crate) fn admits(host_id: &str, peer: Option<&str>) -> bool {
+    let now = crate::clock::monotonic(host_id);
+    let peer = {
+        let rosters = ROSTERS.read();
+        let roster = match rosters.get(host_id) {
+            Some(r) => r,
+            None => return true,
+        };
+        match peer {
+            Some(p) if p == host_id || roster.is_member(p, now) => return true,
+            Some(p) => p.to_string(),
+            None => return false,
+        }
+    };
+    report(host_id, &peer, "Host is not in the lattice roster");
+    false
+}
+
+// Reports a host that isn't allowed in the lattice, unless it has been reported already
+fn report(host_id: &str, peer: &str, reason: &str) {
+    let first = ROSTERS
+        .write()
+        .get_mut(host_id)
+        .map_or(false, |r| r.reported.insert(peer.to_string()));
+    if first {
+        warn!("Ignoring host {}: {}", peer, reason);
+        ControlInterface::from_hostlocal_registry(host_id).do_send(PublishEvent {
+            event: ControlEvent::UnauthorizedHost {
+                host_id: peer.to_string(),
+                reason: reason.to_string(),
+            },
+        });
+    }
+}
+
+/// The error response to an invocation sent by a host the roster doesn't admit, if it was
+pub(crate) fn refuse(host_id: &str, inv: &Invocation) -> Option<InvocationResponse> {
+    if admits(host_id, Some(&inv.host_id)) {
+        None
+    } else {
+        Some(InvocationResponse::error(
+            inv,
+            "Invocation refused: the sending host is not in the lattice roster",
+        ))
+    }
+}
+
+/// The members of the host's lattice roster, or none if the host has no roster
+pub(crate) fn members(host_id: &str) -> Vec<RosterMember> {
+    ROSTERS.read().get(host_id).map_or_else(Vec::new, |r| {
+        r.members.values().map(|(m, _)| m.clone()).collect()
+    })
+}
+
+pub(crate) fn clear(host_id: &str) {
+    ROSTERS.write().remove(host_id);
+}
+
+fn notices_subject(ns_prefix: &Option<String>) -> String {
+    format!(
+        "{}.roster.notices",
+        crate::messagebus::rpc_subscription::subject_prefix(ns_prefix)
+    )
+}
+
+fn welcome_subject(ns_prefix: &Option<String>, host_id: &str) -> String {
+    format!(
+        "{}.roster.welcome.{}",
+        crate::messagebus::rpc_subscription::subject_prefix(ns_prefix),
+        host_id
+    )
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Initialize {
+    pub nc: Option<nats::asynk::Connection>,
+    pub namespace: Option<String>,
+    pub host_id: String,
+    pub config: Option<RosterConfig>,
+    pub labels: HashMap<String, String>,
+    pub announce_interval: Duration,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct NoticeInbound {
+    notice: Option<Notice>,
+    reply: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct RosterService {
+    nc: Option<nats::asynk::Connection>,
+    namespace: Option<String>,
+    host_id: String,
+    admission: Option<KeyPair>,
+    trusted: HashSet<String>,
+    me: Option<RosterMember>,
+    seq: u64,
+}
+
+impl Actor for RosterService {
+    type Context = Context<Self>;
+}
+
+impl Supervised for RosterService {}
+
+impl SystemService for RosterService {}
+
+impl HostLocalSystemService for RosterService {}
+
+impl RosterService {
+    // Signs the next notice about this host
+    fn next_notice(&mut self, kind: NoticeKind) -> Option<Result<Notice>> {
+        self.seq += 1;
+        let (admission, me) = match (&self.admission, &self.me) {
+            (Some(a), Some(me)) => (a, me),
+            _ => return None,
+        };
+        let body = NoticeBody {
+            kind,
+            member: me.clone(),
+            issued_at: crate::clock::now_secs(&self.host_id),
+            seq: self.seq,
+        };
+        Some(Notice::sign(&body, admission))
+    }
+
+    // Signs a notice about this host and publishes it, asking for replies on the given subject
+    fn publish(&mut self, kind: NoticeKind, subject: String, reply: Option<String>) {
+        let nc = match self.nc.clone() {
+            Some(nc) => nc,
+            None => return,
+        };
+        let notice = match self.next_notice(kind) {
+            Some(n) => n,
+            None => return,
+        };
+        let payload = match notice.and_then(|n| Ok(serde_json::to_vec(&n)?)) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Failed to sign roster notice: {}", e);
+                return;
+            }
+        };
+        actix::spawn(async move {
+            let _ = match reply {
+                Some(reply) => nc.publish_request(&subject, &reply, payload).await,
+                None => nc.publish(&subject, payload).await,
+            };
+        });
+    }
+
+    // Refreshes this host's own entry, drops members that have gone quiet, and announces this
+    // host to the rest of the lattice
+    fn announce(&mut self) {
+        let now = crate::clock::monotonic(&self.host_id);
+        let expired = match ROSTERS.write().get_mut(&self.host_id) {
+            Some(roster) => {
+                if let Some(ref me) = self.me {
+                    roster.admit(me.clone(), now);
+                }
+                roster.expire(now)
+            }
+            None => return,
+        };
+        for host_id in expired {
+            info!("Host {} left the lattice roster without a notice", host_id);
+            self.publish_event(ControlEvent::LatticeMemberLeft { host_id });
+        }
+        self.publish(NoticeKind::Announce, notices_subject(&self.namespace), None);
+    }
+
+    fn publish_event(&self, event: ControlEvent) {
+        ControlInterface::from_hostlocal_registry(&self.host_id).do_send(PublishEvent { event });
+    }
+}
+
+impl Handler<Initialize> for RosterService {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: Initialize, _ctx: &mut Context<Self>) -> Self::Result {
+        self.nc = msg.nc;
+        self.namespace = msg.namespace;
+        self.host_id = msg.host_id;
+        let config = match msg.config {
+            Some(c) => c,
+            None => return Box::pin(async {}.into_actor(self)),
+        };
+        let admission = match KeyPair::from_seed(&config.admission_seed) {
+            Ok(kp) => kp,
+            Err(e) => {
+                error!("Invalid roster admission key: {}", e);
+                return Box::pin(async {}.into_actor(self));
+            }
+        };
+        let me = RosterMember {
+            host_id: self.host_id.to_string(),
+            issuer: admission.public_key(),
+            version: HOST_VERSION.to_string(),
+            labels: msg.labels,
+            joined_at: crate::clock::now_secs(&self.host_id),
+        };
+        self.trusted = config.trusted_issuers;
+        self.trusted.insert(admission.public_key());
+        self.admission = Some(admission);
+        let mut roster = Roster {
+            ttl: msg.announce_interval * MISSED_ANNOUNCEMENTS,
+            ..Default::default()
+        };
+        roster.admit(me.clone(), crate::clock::monotonic(&self.host_id));
+        ROSTERS.write().insert(self.host_id.to_string(), roster);
+        self.me = Some(me);
+
+        let nc = match self.nc.clone() {
+            Some(nc) => nc,
+            None => return Box::pin(async {}.into_actor(self)),
+        };
+        let notices = notices_subject(&self.namespace);
+        let welcome = welcome_subject(&self.namespace, &self.host_id);
+        let interval = msg.announce_interval;
+        Box::pin(
+            async move { (nc.subscribe(&notices).await, nc.subscribe(&welcome).await) }
+                .into_actor(self)
+                .map(move |(nsub, wsub), act, ctx| {
+                    for sub in vec![nsub, wsub].into_iter().flatten() {
+                        ctx.add_message_stream(sub.map(|m| NoticeInbound {
+                            notice: serde_json::from_slice(&m.data).ok(),
+                            reply: m.reply.clone(),
+                        }));
+                    }
+                    act.publish(NoticeKind::Join, notices, Some(welcome));
+                    ctx.run_interval(interval, |act, _ctx| act.announce());
+                }),
+        )
+    }
+}
+
+impl Handler<NoticeInbound> for RosterService {
+    type Result = ();
+
+    fn handle(&mut self, msg: NoticeInbound, _ctx: &mut Context<Self>) {
+        let notice = match msg.notice {
+            Some(n) => n,
+            None => return,
+        };
+        let body: NoticeBody = match serde_json::from_str(&notice.body) {
+            Ok(b) => b,
+            Err(e) => {
+                debug!("Ignoring unreadable roster notice: {}", e);
+                return;
+            }
+        };
+        let host_id = body.member.host_id.to_string();
+        if host_id == self.host_id || !crate::partitions::reachable(&self.host_id, Some(&host_id)) {
+            return;
+        }
+        if let Err(e) = notice.check(&body, &self.trusted) {
+            report(&self.host_id, &host_id, &e.to_string());
+            return;
+        }
+        let now = crate::clock::monotonic(&self.host_id);
+        let now_secs = crate::clock::now_secs(&self.host_id);
+        let mut rosters = ROSTERS.write();
+        let roster = match rosters.get_mut(&self.host_id) {
+            Some(r) => r,
+            None => return,
+        };
+        if let Err(e) = roster.accept_notice(&body, now_secs) {
+            drop(rosters);
+            debug!("Ignoring roster notice from {}: {}", host_id, e);
+            return;
+        }
+        match body.kind {
+            NoticeKind::Join | NoticeKind::Announce => {
+                let joined = roster.admit(body.member, now);
+                drop(rosters);
+                if joined {
+                    info!("Host {} joined the lattice roster", host_id);
+                    self.publish_event(ControlEvent::LatticeMemberJoined { host_id });
+                }
+                if let (NoticeKind::Join, Some(reply)) = (body.kind, msg.reply) {
+                    self.publish(NoticeKind::Announce, reply, None);
+                }
+            }
+            NoticeKind::Leave => {
+                let left = roster.remove(&host_id);
+                drop(rosters);
+                if left {
+                    info!("Host {} left the lattice roster", host_id);
+                    self.publish_event(ControlEvent::LatticeMemberLeft { host_id });
+                }
+            }
+        }
+    }
+}
+
+impl Handler<Shutdown> for RosterService {
+    type Result = ResponseActFuture<Self, ()>;
+
+    // Tells the other members that this host is leaving, so they drop it from their rosters
+    // straight away rather than once it misses its announcements
+    fn handle(&mut self, _msg: Shutdown, _ctx: &mut Context<Self>) -> Self::Result {
+        let notice = self.next_notice(NoticeKind::Leave).and_then(|n| n.ok());
+        let nc = self.nc.clone();
+        let subject = notices_subject(&self.namespace);
+        Box::pin(
+            async move {
+                if let (Some(nc), Some(notice)) = (nc, notice) {
+                    let _ = nc
+                        .publish(&subject, serde_json::to_vec(&notice).unwrap())
+                        .await;
+                }
+            }
+            .into_actor(self)
+            .map(|_, _act, ctx| ctx.stop()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Notice, NoticeBody, NoticeKind, Roster, RosterMember};
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+    use wascap::prelude::KeyPair;
+
+    fn member(host_id: &str, issuer: &KeyPair) -> RosterMember {
+        RosterMember {
+            host_id: host_id.to_string(),
+            issuer: issuer.public_key(),
+            version: "0.15.0".to_string(),
+            labels: HashMap::new(),
+            joined_at: 0,
+        }
+    }
+
+    fn notice(kind: NoticeKind, member: RosterMember, issued_at: u64, seq: u64) -> NoticeBody {
+        NoticeBody {
+            kind,
+            member,
+            issued_at,
+            seq,
+        }
+    }
+
+    #[test]
+    fn stale_and_replayed_notices_are_refused() {
+        let admission = KeyPair::new_account();
+        let mut roster = Roster {
+            ttl: Duration::from_secs(90),
+            ..Default::default()
+        };
+        let join = notice(NoticeKind::Join, member("Na", &admission), 1000, 1);
+        let leave = notice(NoticeKind::Leave, member("Na", &admission), 1010, 2);
+        assert!(roster.accept_notice(&join, 1005).is_ok());
+        assert!(roster.accept_notice(&leave, 1015).is_ok());
+
+        // Replaying the join after the host left is refused, even within the time to live
+        assert!(roster.accept_notice(&join, 1020).is_err());
+        // So is a notice issued long ago, or in the future
+        let old = notice(NoticeKind::Announce, member("Nb", &admission), 1000, 7);
+        assert!(roster.accept_notice(&old, 2000).is_err());
+        let early = notice(NoticeKind::Announce, member("Nb", &admission), 3000, 7);
+        assert!(roster.accept_notice(&early, 2000).is_err());
+
+        // A later notice is accepted even if the host has started counting again
+        let rejoin = notice(NoticeKind::Join, member("Na", &admission), 1050, 1);
+        assert!(roster.accept_notice(&rejoin, 1050).is_ok());
+    }
+
+    #[test]
+    fn only_trusted_notices_are_accepted() {
+        let admission = KeyPair::new_account();
+        let rogue = KeyPair::new_account();
+        let trusted: HashSet<String> = vec![admission.public_key()].into_iter().collect();
+
+        let body = notice(NoticeKind::Join, member("Nmember", &admission), 100, 1);
+        let notice = Notice::sign(&body, &admission).unwrap();
+        assert!(notice.check(&body, &trusted).is_ok());
+
+        let rogue_body = notice(NoticeKind::Join, member("Nrogue", &rogue), 100, 1);
+        let rogue_notice = Notice::sign(&rogue_body, &rogue).unwrap();
+        assert!(rogue_notice.check(&rogue_body, &trusted).is_err());
+
+        // A notice signed by another key than the one it names is refused, even if that key is
+        // trusted
+        let forged = Notice::sign(&body, &rogue).unwrap();
+        assert!(forged.check(&body, &trusted).is_err());
+    }
+
+    #[test]
+    fn quiet_members_expire() {
+        let admission = KeyPair::new_account();
+        let mut roster = Roster {
+            ttl: Duration::from_secs(90),
+            ..Default::default()
+        };
+        assert!(roster.admit(member("Na", &admission), Duration::from_secs(0)));
+        assert!(roster.admit(member("Nb", &admission), Duration::from_secs(60)));
+        assert!(!roster.admit(member("Na", &admission), Duration::from_secs(30)));
+        assert!(roster.is_member("Na", Duration::from_secs(100)));
+
+        assert_eq!(
+            vec!["Na".to_string()],
+            roster.expire(Duration::from_secs(120))
+        );
+        assert!(!roster.is_member("Na", Duration::from_secs(120)));
+        assert!(roster.is_member("Nb", Duration::from_secs(120)));
+        assert!(roster.remove("Nb"));
+        assert!(roster.members.is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+// Tracing every invocation (the host's trace of the invocations an actor or provider handles,
+// logged when the target's log level is `trace`) costs too much in a lattice handling thousands
+// of requests a second. Invocations are instead sampled where they enter the lattice: the first
+// host to route an invocation decides whether it is traced, using the rate set for its target
+// (an actor's public key or a provider's contract ID) or the default rate, and records the
+// decision as an item of the invocation's baggage. Every invocation made while handling it
+// carries the same baggage, so a request is either traced on every host it passes through or on
+// none of them. Failed invocations can be traced whatever the decision was.
+//
+// Sampling is set when the host is built and can be changed for the whole lattice at once with a
+// control interface command that every host answers.
+
+use crate::{Invocation, WasccEntity};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// The baggage item holding whether the request an invocation is part of is traced (`1`) or not
+/// (`0`)
+pub const BAGGAGE_SAMPLED: &str = "wasmcloud-sampled";
+
+// The resolution of sampling rates
+const RATE_BUCKETS: u64 = 10_000;
+
+static SAMPLING: Lazy<RwLock<HashMap<String, TraceSampling>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// How a host samples the invocations entering the lattice for tracing. Rates run from 0 (trace
+/// nothing) to 1 (trace everything)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceSampling {
+    /// The rate for targets without a rate of their own
+    pub default_rate: f64,
+    /// Rates by actor public key or contract ID
+    pub rates: HashMap<String, f64>,
+    /// Whether failed invocations are traced even if their request wasn't sampled
+    pub always_sample_errors: bool,
+}
+
+impl Default for TraceSampling {
+    fn default() -> Self {
+        TraceSampling {
+            default_rate: 1.0,
+            rates: HashMap::new(),
+            always_sample_errors: true,
+        }
+    }
+}
+
+impl TraceSampling {
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        let valid = |r: &f64| (0.0..=1.0).contains(r);
+        if !valid(&self.default_rate) {
+            return Err(
+                format!("Sampling rate {} is not between 0 and 1", self.default_rate).into(),
+            );
+        }
+        match self.rates.iter().find(|(_, r)| !valid(r)) {
+            Some((target, rate)) => Err(format!(
+                "Sampling rate {} for {} is not between 0 and 1",
+                rate, target
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // Whether a request with the given ID, entering the lattice for the given target, is traced
+    fn sample(&self, target: &str, id: &str) -> bool {
+        let rate = self.rates.get(target).unwrap_or(&self.default_rate);
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        ((hasher.finish() % RATE_BUCKETS) as f64) < rate * RATE_BUCKETS as f64
+    }
+}
+
+/// Sets how the host samples invocations
+pub(crate) fn set(host_id: &str, sampling: TraceSampling) {
+    info!(
+        "Trace sampling set to a default rate of {} with {} target rates",
+        sampling.default_rate,
+        sampling.rates.len()
+    );
+    SAMPLING.write().insert(host_id.to_string(), sampling);
+}
+
+pub(crate) fn clear(host_id: &str) {
+    SAMPLING.write().remove(host_id);
+}
+
+/// Records the sampling decision for the request in the invocation's baggage, if no host has
+/// made one yet
+pub(crate) fn decide(host_id: &str, mut inv: Invocation) -> Invocation {
+    if inv.baggage.contains_key(BAGGAGE_SAMPLED) || inv.baggage.len() >= crate::MAX_BAGGAGE_ITEMS {
+        return inv;
+    }
+    let target = match inv.target {
+        WasccEntity::Actor(ref a) => a.to_string(),
+        WasccEntity::Capability {
+            ref contract_id, ..
+        } => contract_id.to_string(),
+    };
+    let sampled = SAMPLING
+        .read()
+        .get(host_id)
+        .map_or(true, |s| s.sample(&target, &inv.id));
+    inv.baggage.insert(
+        BAGGAGE_SAMPLED.to_string(),
+        if sampled { "1" } else { "0" }.to_string(),
+    );
+    inv
+}
+
+/// Whether the request the invocation is part of is traced. Invocations that carry no decision
+/// are traced
+pub(crate) fn sampled(inv: &Invocation) -> bool {
+    inv.baggage.get(BAGGAGE_SAMPLED).map_or(true, |s| s != "0")
+}
+
+/// Whether the host traces failed invocations whatever their sampling decision
+pub(crate) fn errors_sampled(host_id: &str) -> bool {
+    SAMPLING
+        .read()
+        .get(host_id)
+        .map_or(true, |s| s.always_sample_errors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::TraceSampling;
+
+    #[test]
+    fn rates_apply_by_target() {
+        let sampling = TraceSampling {
+            default_rate: 0.0,
+            rates: vec![
+                ("Mhot".to_string(), 1.0),
+                ("wascc:http_server".to_string(), 0.5),
+            ]
+            .into_iter()
+            .collect(),
+            always_sample_errors: true,
+        };
+        assert!(sampling.validate().is_ok());
+        let ids: Vec<String> = (0..1000).map(|i| format!("inv-{}", i)).collect();
+        assert!(ids.iter().all(|id| sampling.sample("Mhot", id)));
+        assert!(!ids.iter().any(|id| sampling.sample("Mcold", id)));
+        let half = ids
+            .iter()
+            .filter(|id| sampling.sample("wascc:http_server", id))
+            .count();
+        assert!(half > 400 && half < 600);
+        // The decision for a request doesn't change
+        assert_eq!(
+            sampling.sample("wascc:http_server", "inv-1"),
+            sampling.sample("wascc:http_server", "inv-1")
+        );
+
+        let invalid = TraceSampling {
+            default_rate: 1.5,
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,198 @@
+// Upgrading the host itself across a fleet means replacing one running host process with
+// another without losing what the old one was running. When told to prepare for an upgrade
+// (through the host API or a control interface command), a host drains: it stops answering
+// auctions, refuses to start anything new, and quiesces its capability providers so they stop
+// taking on external work. It then writes an upgrade journal, holding a signed snapshot of the
+// lattice state it knows (see `LatticeState`) along with its version and namespace, and runs its
+// upgrade hooks, which signal whatever supervises the process to swap the binary and restart it.
+//
+// A host built with the same journal path finds the journal when it starts. It refuses to start
+// if the journal was written by a version of the host it can't take over from (a different
+// major version, or a different minor version before 1.0) or for another namespace, so that a
+// supervisor can roll back. Otherwise, once it has started, it restores the journal's state
+// (restarting the actors and providers the old host ran) and removes the journal.
+
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::hlreg::HostLocalSystemService;
+use crate::hooks::UpgradeHook;
+use crate::host_controller::{Drain, HostController, QueryHostInventory};
+use crate::lattice_state::{ConflictResolution, LatticeSnapshot};
+use crate::messagebus::{MessageBus, QuiesceProvider};
+use crate::{ControlEvent, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use wascap::prelude::KeyPair;
+
+// The format of the journal, changed whenever an older host couldn't write what a newer one reads
+const JOURNAL_FORMAT: u32 = 1;
+
+const HOST_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Where a host writes its upgrade journal, and the hooks that hand it over to its supervisor
+#[derive(Clone, Default)]
+pub(crate) struct UpgradeOptions {
+    pub journal: Option<PathBuf>,
+    pub hooks: Vec<Box<dyn UpgradeHook>>,
+}
+
+impl std::fmt::Debug for UpgradeOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpgradeOptions")
+            .field("journal", &self.journal)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+/// What a host leaves behind for the host that replaces it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct UpgradeJournal {
+    pub format: u32,
+    pub host_version: String,
+    pub host_id: String,
+    pub namespace: String,
+    pub snapshot: LatticeSnapshot,
+}
+
+/// Drains the host, writes its upgrade journal, and runs its upgrade hooks
+pub(crate) async fn prepare(kp: &KeyPair, namespace: &str, options: &UpgradeOptions) -> Result<()> {
+    let path = options
+        .journal
+        .as_ref()
+        .ok_or("Host has no upgrade journal configured")?;
+    let host_id = kp.public_key();
+    info!("Draining host {} for an upgrade", host_id);
+    let hc = HostController::from_hostlocal_registry(&host_id);
+    hc.send(Drain).await?;
+    let bus = MessageBus::from_hostlocal_registry(&host_id);
+    for p in hc.send(QueryHostInventory).await?.providers {
+        let quiesce = QuiesceProvider {
+            provider_id: p.id.to_string(),
+            link_name: p.link_name.to_string(),
+            quiesce: true,
+        };
+        if let Err(e) = bus.send(quiesce).await? {
+            debug!("Provider {} ({}) not quiesced: {}", p.id, p.link_name, e);
+        }
+    }
+
+    let journal = UpgradeJournal {
+        format: JOURNAL_FORMAT,
+        host_version: HOST_VERSION.to_string(),
+        host_id: host_id.to_string(),
+        namespace: namespace.to_string(),
+        snapshot: crate::lattice_state::export(&host_id, namespace, kp).await?,
+    };
+    // Written alongside and then moved into place, so a journal is never found half-written
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, serde_json::to_vec(&journal)?)?;
+    std::fs::rename(&partial, path)?;
+
+    ControlInterface::from_hostlocal_registry(&host_id).do_send(PublishEvent {
+        event: ControlEvent::HostUpgradeReady {
+            journal: path.display().to_string(),
+        },
+    });
+    for hook in options.hooks.iter() {
+        hook.on_upgrade_ready(&host_id, path)?;
+    }
+    Ok(())
+}
+
+/// Reads the journal left by the host this one replaces, if there is one, refusing journals this
+/// host can't take over from
+pub(crate) fn read_journal(path: &Path, namespace: &str) -> Result<Option<UpgradeJournal>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let journal: UpgradeJournal = serde_json::from_slice(&std::fs::read(path)?)?;
+    if journal.format != JOURNAL_FORMAT {
+        return Err(format!(
+            "Upgrade journal {} has format {}, this host reads format {}",
+            path.display(),
+            journal.format,
+            JOURNAL_FORMAT
+        )
+        .into());
+    }
+    if !compatible(&journal.host_version, HOST_VERSION) {
+        return Err(format!(
+            "Upgrade journal {} is from host version {}, which version {} can't take over from",
+            path.display(),
+            journal.host_version,
+            HOST_VERSION
+        )
+        .into());
+    }
+    if journal.namespace != namespace {
+        return Err(format!(
+            "Upgrade journal {} is for namespace '{}', not '{}'",
+            path.display(),
+            journal.namespace,
+            namespace
+        )
+        .into());
+    }
+    // The journal must have been signed by the host that wrote it
+    journal.snapshot.verify(&[journal.host_id.to_string()])?;
+    Ok(Some(journal))
+}
+
+/// Restores the state of the host this one replaces, then removes its journal
+pub(crate) async fn resume(
+    host_id: &str,
+    allow_latest: bool,
+    journal: &UpgradeJournal,
+    path: &Path,
+) -> Result<()> {
+    info!(
+        "Taking over from host {} (version {})",
+        journal.host_id, journal.host_version
+    );
+    let state = journal.snapshot.verify(&[journal.host_id.to_string()])?;
+    crate::lattice_state::import(
+        host_id,
+        &journal.namespace,
+        allow_latest,
+        &journal.snapshot.issuer,
+        state,
+        ConflictResolution::KeepExisting,
+    )
+    .await?;
+    std::fs::remove_file(path)?;
+    ControlInterface::from_hostlocal_registry(host_id).do_send(PublishEvent {
+        event: ControlEvent::HostUpgraded {
+            previous_host: journal.host_id.to_string(),
+            previous_version: journal.host_version.to_string(),
+        },
+    });
+    Ok(())
+}
+
+// Whether a host of the current version can take over from one of the previous version: the
+// major versions must match and, before 1.0, so must the minor versions
+fn compatible(previous: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Option<(u64, u64)> {
+        let mut parts = v.split('.');
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    };
+    match (parse(previous), parse(current)) {
+        (Some((0, prev_minor)), Some((0, minor))) => prev_minor == minor,
+        (Some((prev_major, _)), Some((major, _))) => prev_major == major,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::compatible;
+
+    #[test]
+    fn versions_must_be_compatible() {
+        assert!(compatible("0.15.0", "0.15.3"));
+        assert!(!compatible("0.14.2", "0.15.0"));
+        assert!(compatible("1.2.0", "1.4.1"));
+        assert!(!compatible("1.9.0", "2.0.0"));
+        assert!(!compatible("unknown", "1.0.0"));
+    }
+}
@@ -0,0 +1,187 @@
+// Moving an actor from one data store to another (say from a Redis key-value provider to another
+// key-value provider) used to mean changing the actor to call a new link name, and moving all of
+// its traffic at once. With traffic shaping, the actor keeps calling the link name it always has
+// while the host spreads those calls across other links of the same contract. The actor is
+// linked to both providers, each under a link name of its own, and a shaping rule sends a
+// percentage of the calls to each. The percentages can be changed while the host runs, so the new
+// provider can take a growing share of the traffic until it has all of it.
+//
+// A rule applies to some of a contract's operations, so reads and writes can be shaped apart
+// (e.g. reads split 90/10 while every write still goes to the old store). A rule can also give
+// the link names to fall back to, in order, when a call on the chosen link fails or the actor has
+// no link by that name. Rules are checked in the order they were given, and the first that
+// applies to a call decides where it goes. Calls no rule applies to are left alone.
+
+use crate::Result;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+static SHAPING: Lazy<RwLock<HashMap<String, Vec<ShapingRule>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Spreads the calls actors make on one link name of a contract across other link names of the
+/// same contract, by percentage
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapingRule {
+    pub contract_id: String,
+    /// The link name the actors call on
+    pub link_name: String,
+    /// The operations the rule applies to. An operation ending in `*` matches every operation
+    /// starting with the rest of it. A rule without operations applies to all of them
+    pub operations: Vec<String>,
+    /// The link names the calls are sent over, each with its percentage of the calls
+    pub weights: Vec<(String, u32)>,
+    /// The link names a call is retried on, in order, when it fails on the one chosen for it
+    pub failover: Vec<String>,
+}
+
+impl ShapingRule {
+    pub fn new(contract_id: &str, link_name: &str) -> ShapingRule {
+        ShapingRule {
+            contract_id: contract_id.to_string(),
+            link_name: link_name.to_string(),
+            operations: vec![],
+            weights: vec![],
+            failover: vec![],
+        }
+    }
+
+    /// Limits the rule to the given operations, such as `["Get", "Contains", "List*"]` for the
+    /// reads of a key-value store
+    pub fn operations(self, operations: &[&str]) -> ShapingRule {
+        let mut ops = self.operations.clone();
+        ops.extend(operations.iter().map(|o| o.to_string()));
+        ShapingRule {
+            operations: ops,
+            ..self
+        }
+    }
+
+    /// Sends the given percentage of the calls over the given link name
+    pub fn weight(self, link_name: &str, percent: u32) -> ShapingRule {
+        let mut weights = self.weights.clone();
+        weights.push((link_name.to_string(), percent));
+        ShapingRule { weights, ..self }
+    }
+
+    /// Adds a link name to retry calls on when they fail, after those added before it
+    pub fn failover(self, link_name: &str) -> ShapingRule {
+        let mut failover = self.failover.clone();
+        failover.push(link_name.to_string());
+        ShapingRule { failover, ..self }
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.weights.is_empty() {
+            return Err(format!(
+                "Traffic shaping rule for {} ({}) has no weights",
+                self.contract_id, self.link_name
+            )
+            .into());
+        }
+        let total: u32 = self.weights.iter().map(|(_, w)| w).sum();
+        if total != 100 {
+            return Err(format!(
+                "Traffic shaping weights for {} ({}) add up to {}, not 100",
+                self.contract_id, self.link_name, total
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn applies(&self, contract_id: &str, link_name: &str, operation: &str) -> bool {
+        self.contract_id == contract_id
+            && self.link_name == link_name
+            && (self.operations.is_empty()
+                || self.operations.iter().any(|o| match o.strip_suffix('*') {
+                    Some(prefix) => operation.starts_with(prefix),
+                    None => operation == o,
+                }))
+    }
+
+    // The link names to attempt a call on, in order, given a roll from 0 to 99: the link the roll
+    // lands on, followed by the failover links
+    fn attempts(&self, roll: u32) -> Vec<String> {
+        let mut acc = 0;
+        let chosen = self
+            .weights
+            .iter()
+            .find(|(_, w)| {
+                acc += w;
+                roll < acc
+            })
+            .or_else(|| self.weights.last())
+            .map(|(l, _)| l.to_string());
+        let mut attempts: Vec<String> = chosen.into_iter().collect();
+        for link in self.failover.iter() {
+            if !attempts.contains(link) {
+                attempts.push(link.to_string());
+            }
+        }
+        attempts
+    }
+}
+
+/// Replaces the host's traffic shaping rules
+pub(crate) fn set(host_id: &str, rules: Vec<ShapingRule>) {
+    if !rules.is_empty() {
+        info!("Traffic shaping set with {} rules", rules.len());
+    }
+    SHAPING.write().insert(host_id.to_string(), rules);
+}
+
+pub(crate) fn clear(host_id: &str) {
+    SHAPING.write().remove(host_id);
+}
+
+/// The link names to attempt an actor's call on, in order. Without a rule for the call, this is
+/// the link name the actor called on
+pub(crate) fn route(
+    host_id: &str,
+    contract_id: &str,
+    link_name: &str,
+    operation: &str,
+) -> Vec<String> {
+    use rand::Rng;
+    SHAPING
+        .read()
+        .get(host_id)
+        .and_then(|rules| {
+            rules
+                .iter()
+                .find(|r| r.applies(contract_id, link_name, operation))
+        })
+        .map(|r| r.attempts(rand::thread_rng().gen_range(0, 100)))
+        .unwrap_or_else(|| vec![link_name.to_string()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShapingRule;
+
+    #[test]
+    fn calls_are_split_by_weight_then_fail_over() {
+        let reads = ShapingRule::new("wascc:keyvalue", "default")
+            .operations(&["Get", "List*"])
+            .weight("redis", 90)
+            .weight("newkv", 10)
+            .failover("redis");
+        assert!(reads.validate().is_ok());
+        assert!(reads.applies("wascc:keyvalue", "default", "ListRange"));
+        assert!(!reads.applies("wascc:keyvalue", "default", "Set"));
+        assert!(!reads.applies("wascc:keyvalue", "other", "Get"));
+
+        assert_eq!(vec!["redis"], reads.attempts(0));
+        assert_eq!(vec!["redis"], reads.attempts(89));
+        assert_eq!(vec!["newkv", "redis"], reads.attempts(90));
+        assert_eq!(vec!["newkv", "redis"], reads.attempts(99));
+
+        let uneven = ShapingRule::new("wascc:keyvalue", "default").weight("redis", 50);
+        assert!(uneven.validate().is_err());
+        assert!(ShapingRule::new("wascc:keyvalue", "default")
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// Test suites and multi-tenant gateways often run several hosts in one process, and giving each
+// of them its own NATS connection multiplies the connections the NATS servers have to carry.
+// Hosts can instead share a connection, as long as each of them uses its own lattice namespace:
+// every subject a host subscribes to is scoped to its namespace, so the hosts never see each
+// other's traffic, and a host won't start on a shared connection whose namespace is already
+// taken by another host. The connection is closed when the last host using it stops.
+
+use crate::Result;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+// The hosts using each shared connection, by connection ID and then by namespace
+static USERS: Lazy<Mutex<HashMap<String, HashMap<String, String>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A NATS connection that can be shared by several hosts in the same process, each in its own
+/// lattice namespace. See `HostBuilder::with_shared_connection`
+#[derive(Clone)]
+pub struct SharedConnection {
+    id: String,
+    nc: nats::asynk::Connection,
+}
+
+impl SharedConnection {
+    pub fn new(nc: nats::asynk::Connection) -> SharedConnection {
+        SharedConnection {
+            id: uuid::Uuid::new_v4().to_string(),
+            nc,
+        }
+    }
+
+    pub fn connection(&self) -> &nats::asynk::Connection {
+        &self.nc
+    }
+
+    /// The number of running hosts using the connection
+    pub fn hosts(&self) -> usize {
+        USERS.lock().get(&self.id).map_or(0, |users| users.len())
+    }
+
+    pub(crate) fn attach(&self, namespace: &str, host_id: &str) -> Result<()> {
+        attach(&self.id, namespace, host_id)
+    }
+
+    /// Stops the host from using the connection, closing the connection if it was the last host
+    /// to use it
+    pub(crate) async fn detach(&self, host_id: &str) {
+        if detach(&self.id, host_id) {
+            if let Err(e) = self.nc.close().await {
+                warn!("Failed to close shared lattice connection: {}", e);
+            }
+        }
+    }
+
+    /// Like `detach`, for callers that can't wait: closing the connection, if it was the last
+    /// host to use it, is left to the actix system
+    pub(crate) fn release(&self, host_id: &str) {
+        if detach(&self.id, host_id) {
+            let nc = self.nc.clone();
+            actix::spawn(async move {
+                if let Err(e) = nc.close().await {
+                    warn!("Failed to close shared lattice connection: {}", e);
+                }
+            });
+        }
+    }
+}
+
+fn attach(conn_id: &str, namespace: &str, host_id: &str) -> Result<()> {
+    let mut users = USERS.lock();
+    let hosts = users.entry(conn_id.to_string()).or_default();
+    match hosts.get(namespace) {
+        Some(other) if other != host_id => Err(format!(
+            "Host {} is already using namespace '{}' on this shared connection",
+            other, namespace
+        )
+        .into()),
+        _ => {
+            hosts.insert(namespace.to_string(), host_id.to_string());
+            Ok(())
+        }
+    }
+}
+
+// Returns true if no hosts are left using the connection
+fn detach(conn_id: &str, host_id: &str) -> bool {
+    let mut users = USERS.lock();
+    let last = match users.get_mut(conn_id) {
+        Some(hosts) => {
+            hosts.retain(|_, h| h != host_id);
+            hosts.is_empty()
+        }
+        None => return false,
+    };
+    if last {
+        users.remove(conn_id);
+    }
+    last
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attach, detach};
+
+    #[test]
+    fn namespaces_are_exclusive_and_hosts_counted() {
+        assert!(attach("c1", "tenant-a", "N1").is_ok());
+        assert!(attach("c1", "tenant-b", "N2").is_ok());
+        assert!(attach("c1", "tenant-a", "N3").is_err());
+        assert!(attach("c2", "tenant-a", "N3").is_ok());
+
+        assert!(!detach("c1", "N1"));
+        assert!(attach("c1", "tenant-a", "N3").is_ok());
+        assert!(!detach("c1", "N2"));
+        assert!(detach("c1", "N3"));
+        assert!(!detach("c1", "N3"));
+        assert!(detach("c2", "N3"));
+    }
+}
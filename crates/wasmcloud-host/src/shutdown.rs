@@ -0,0 +1,134 @@
+// A host used to stop without saying how it went: invocations still being handled were dropped,
+// providers were left to clean up on their own time, and claims or events not yet sent to the
+// lattice could be lost without a trace. Stopping a host now reports what happened, so whatever
+// supervises it can log an imperfect shutdown and act on it.
+//
+// The host stops in phases, each given the same deadline (`HostBuilder::with_shutdown_deadline`).
+// It first leaves the lattice roster and waits for the invocations in flight on its bus to be
+// answered, counting those still unanswered at the deadline as aborted, then sends the actor
+// claims it has batched for the lattice. Its actors are stopped and each provider is asked to
+// clean up, and providers that haven't finished by the deadline are reported. Finally the host
+// waits for the events it is publishing and flushes them to the lattice; events that can't be
+// confirmed as sent are reported as unacknowledged.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How often a phase of the shutdown checks whether it is done
+const SETTLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A capability provider instance that was running when the host stopped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoppedProvider {
+    pub provider_id: String,
+    pub link_name: String,
+}
+
+/// What happened to the actor claims the host had batched for the lattice when it stopped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FlushStatus {
+    /// The host isn't connected to a lattice, or had nothing to send
+    Nothing,
+    /// The batched claims were published and the connection flushed
+    Flushed {
+        claims: usize,
+    },
+    Failed(String),
+}
+
+impl Default for FlushStatus {
+    fn default() -> Self {
+        FlushStatus::Nothing
+    }
+}
+
+/// What happened as the host stopped. See `Host::stop`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShutdownReport {
+    pub host_id: String,
+    pub actors_stopped: Vec<String>,
+    pub providers_stopped: Vec<StoppedProvider>,
+    /// Providers that hadn't finished cleaning up by the deadline
+    pub providers_timed_out: Vec<StoppedProvider>,
+    /// Invocations in flight when the host began to stop that were answered before the deadline
+    pub invocations_drained: usize,
+    /// Invocations still unanswered at the deadline, which were dropped
+    pub invocations_aborted: usize,
+    pub claims_flush: FlushStatus,
+    /// Events the host published that it couldn't confirm were sent to the lattice
+    pub unacked_events: usize,
+    pub duration_ms: u64,
+}
+
+impl ShutdownReport {
+    /// Whether the host stopped without losing any work
+    pub fn is_clean(&self) -> bool {
+        self.providers_timed_out.is_empty()
+            && self.invocations_aborted == 0
+            && self.unacked_events == 0
+            && !matches!(self.claims_flush, FlushStatus::Failed(_))
+    }
+}
+
+/// Counts the units of work (such as invocations) under way, so that a shutdown can wait for them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pending(Arc<AtomicUsize>);
+
+/// A unit of work under way, counted until dropped
+pub(crate) struct PendingGuard(Arc<AtomicUsize>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Pending {
+    pub fn track(&self) -> PendingGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        PendingGuard(self.0.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Waits until no work is under way or the deadline has passed, returning the amount of work
+    /// still under way
+    pub async fn settle(&self, deadline: Duration) -> usize {
+        let started = Instant::now();
+        while self.count() > 0 && started.elapsed() < deadline {
+            actix_rt::time::delay_for(SETTLE_INTERVAL).await;
+        }
+        self.count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlushStatus, Pending, ShutdownReport, StoppedProvider};
+
+    #[test]
+    fn pending_work_is_counted_until_dropped() {
+        let pending = Pending::default();
+        let first = pending.track();
+        let second = pending.clone().track();
+        assert_eq!(2, pending.count());
+        drop(first);
+        drop(second);
+        assert_eq!(0, pending.count());
+
+        let mut report = ShutdownReport::default();
+        assert!(report.is_clean());
+        report.providers_timed_out.push(StoppedProvider {
+            provider_id: "VPROV".to_string(),
+            link_name: "default".to_string(),
+        });
+        assert!(!report.is_clean());
+        report.providers_timed_out.clear();
+        report.claims_flush = FlushStatus::Failed("Connection closed".to_string());
+        assert!(!report.is_clean());
+    }
+}
@@ -0,0 +1,216 @@
+// When an actor fails while handling an invocation, the host can capture what it was asked to do
+// into a snapshot bundle on disk. A developer can then load the bundle and replay the same
+// operation and payload against the actor locally to reproduce the crash. Redactors get a chance
+// to scrub sensitive data from a snapshot before it is written.
+//
+// An actor that keeps failing would otherwise fill the disk, so only the most recent snapshots
+// are kept. Once a new snapshot takes the directory over its limit, the oldest are deleted.
+//
+// Snapshots do not include the actor's linear memory. The waPC engine providers (wasm3 and
+// wasmtime) only expose calls into a guest, not its memory, so the host has no way to read the
+// memory of a trapped instance. A replay starts from a freshly instantiated module instead.
+
+use crate::capability::native_host::provider_cache_dir;
+use crate::hooks::bytes_digest;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_EXTENSION: &str = "snapshot";
+
+/// The number of snapshots a host keeps unless configured otherwise
+pub const DEFAULT_MAX_SNAPSHOTS: usize = 100;
+
+/// The state of a failed actor invocation, as captured by the host
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExecutionSnapshot {
+    /// The public key of the actor that failed
+    pub actor: String,
+    /// The OCI image reference from which the actor was started, if any
+    pub image_ref: Option<String>,
+    /// The URL of the entity that sent the invocation
+    pub origin: String,
+    /// The operation being performed
+    pub operation: String,
+    /// The payload of the invocation
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+    /// The error reported by the WebAssembly engine
+    pub error: String,
+    /// The time of the failure, in milliseconds since the epoch
+    pub timestamp_ms: u64,
+}
+
+/// A snapshot redactor is given every snapshot before it is written to disk, and can modify
+/// it to remove secrets, personal data, or anything else that should not leave the host
+pub trait SnapshotRedactor: CloneSnapshotRedactor + Sync + Send {
+    fn redact(&self, snapshot: &mut ExecutionSnapshot);
+}
+
+#[doc(hidden)]
+pub trait CloneSnapshotRedactor {
+    fn clone_redactor(&self) -> Box<dyn SnapshotRedactor>;
+}
+
+impl<T> CloneSnapshotRedactor for T
+where
+    T: SnapshotRedactor + Clone + 'static,
+{
+    fn clone_redactor(&self) -> Box<dyn SnapshotRedactor> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn SnapshotRedactor> {
+    fn clone(&self) -> Self {
+        self.clone_redactor()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SnapshotConfig {
+    pub dir: PathBuf,
+    pub redactors: Vec<Box<dyn SnapshotRedactor>>,
+    pub max_snapshots: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            dir: provider_cache_dir().join("snapshots"),
+            redactors: vec![],
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Runs the redactors over the snapshot and writes it to the snapshot directory, returning
+    /// the path of the bundle and the hex-encoded SHA-256 digest of its contents
+    pub fn capture(&self, mut snapshot: ExecutionSnapshot) -> Result<(PathBuf, String)> {
+        for redactor in &self.redactors {
+            redactor.redact(&mut snapshot);
+        }
+        let bytes = crate::generated::core::serialize(&snapshot)?;
+        let digest = bytes_digest(&bytes);
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!(
+            "{}-{}.{}",
+            snapshot.actor,
+            &digest[..16],
+            SNAPSHOT_EXTENSION
+        ));
+        fs::write(&path, &bytes)?;
+        if let Err(e) = self.prune() {
+            warn!("Failed to remove old execution snapshots: {}", e);
+        }
+        Ok((path, digest))
+    }
+
+    // Deletes the oldest snapshots in the directory until no more than the maximum are left
+    fn prune(&self) -> Result<()> {
+        let mut snapshots = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == SNAPSHOT_EXTENSION) {
+                snapshots.push((fs::metadata(&path)?.modified()?, path));
+            }
+        }
+        if snapshots.len() <= self.max_snapshots {
+            return Ok(());
+        }
+        snapshots.sort();
+        let excess = snapshots.len() - self.max_snapshots;
+        for (_, path) in snapshots.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a snapshot bundle previously captured by a host. The operation and payload it contains
+/// can be replayed against the same actor with `Host::call_actor`
+pub fn load_snapshot(path: impl AsRef<Path>) -> Result<ExecutionSnapshot> {
+    let bytes = fs::read(path)?;
+    crate::generated::core::deserialize(&bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_snapshot, ExecutionSnapshot, SnapshotConfig, SnapshotRedactor};
+    use std::env::temp_dir;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct StripPayload;
+
+    impl SnapshotRedactor for StripPayload {
+        fn redact(&self, snapshot: &mut ExecutionSnapshot) {
+            snapshot.payload.clear();
+        }
+    }
+
+    #[test]
+    fn snapshots_are_redacted_and_round_trip() {
+        let config = SnapshotConfig {
+            dir: temp_dir().join("wasmcloud-snapshot-test"),
+            redactors: vec![Box::new(StripPayload)],
+            max_snapshots: 10,
+        };
+        let snapshot = ExecutionSnapshot {
+            actor: "Mxxx".to_string(),
+            image_ref: None,
+            origin: "wasmbus://system".to_string(),
+            operation: "HandleRequest".to_string(),
+            payload: b"secret".to_vec(),
+            error: "unreachable".to_string(),
+            timestamp_ms: 0,
+        };
+        let (path, digest) = config.capture(snapshot.clone()).unwrap();
+        assert_eq!(64, digest.len());
+
+        let loaded = load_snapshot(&path).unwrap();
+        assert!(loaded.payload.is_empty());
+        assert_eq!(
+            ExecutionSnapshot {
+                payload: vec![],
+                ..snapshot
+            },
+            loaded
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn only_the_newest_snapshots_are_kept() {
+        let dir = temp_dir().join("wasmcloud-snapshot-retention-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = SnapshotConfig {
+            dir: dir.clone(),
+            redactors: vec![],
+            max_snapshots: 2,
+        };
+        let paths: Vec<_> = (0..3)
+            .map(|i| {
+                // Give each snapshot a distinct modification time
+                std::thread::sleep(Duration::from_millis(20));
+                let snapshot = ExecutionSnapshot {
+                    actor: "Mxxx".to_string(),
+                    image_ref: None,
+                    origin: "wasmbus://system".to_string(),
+                    operation: "HandleRequest".to_string(),
+                    payload: vec![],
+                    error: "unreachable".to_string(),
+                    timestamp_ms: i,
+                };
+                config.capture(snapshot).unwrap().0
+            })
+            .collect();
+        assert!(!paths[0].exists());
+        assert!(paths[1].exists());
+        assert!(paths[2].exists());
+        assert_eq!(2, std::fs::read_dir(&dir).unwrap().count());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,99 @@
+// By default a host trusts the claims that come with a capability provider: the claims in a
+// provider archive are decoded without checking their signature or their validity period, and
+// providers embedded with `from_instance` carry whatever claims the embedding code built. A strict
+// host only admits artifacts whose claims it can verify itself. Actors and providers must come
+// with a signed claims token whose signature is valid, which is neither expired nor not yet
+// valid, and which matches the claims the artifact is started with. Embedded providers can meet
+// this by being created with `NativeCapability::from_instance_with_token`.
+
+use crate::capability::native::NativeCapability;
+use crate::Actor;
+use wascap::jwt::{validate_token, CapabilityProvider, Claims, TokenValidation};
+
+/// Verifies the actor's embedded claims token
+pub(crate) fn verify_actor(actor: &Actor) -> std::result::Result<(), String> {
+    let tv = validate_token::<wascap::jwt::Actor>(&actor.token.jwt)
+        .map_err(|e| format!("Actor claims are invalid: {}", e))?;
+    check_validation(&tv, "Actor")
+}
+
+/// Verifies that the provider was started with a signed claims token matching its claims
+pub(crate) fn verify_provider(provider: &NativeCapability) -> std::result::Result<(), String> {
+    let jwt = provider.jwt.as_ref().ok_or_else(|| {
+        "Capability provider has no signed claims token, which a strict host requires".to_string()
+    })?;
+    let tv = validate_token::<CapabilityProvider>(jwt)
+        .map_err(|e| format!("Capability provider claims are invalid: {}", e))?;
+    check_validation(&tv, "Capability provider")?;
+    let signed = Claims::<CapabilityProvider>::decode(jwt)
+        .map_err(|e| format!("Capability provider claims are invalid: {}", e))?;
+    if signed.subject != provider.claims.subject || signed.issuer != provider.claims.issuer {
+        Err("Capability provider claims don't match its signed claims token".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn check_validation(tv: &TokenValidation, kind: &str) -> std::result::Result<(), String> {
+    if !tv.signature_valid {
+        Err(format!("{} claims token has an invalid signature", kind))
+    } else if tv.expired {
+        Err(format!("{} claims expired {}", kind, tv.expires_human))
+    } else if tv.cannot_use_yet {
+        Err(format!(
+            "{} claims cannot be used until {}",
+            kind, tv.not_before_human
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify_provider;
+    use crate::capability::keyvalue::MemoryKeyValueProvider;
+    use crate::NativeCapability;
+    use std::collections::HashMap;
+    use wascap::jwt::{CapabilityProvider, Claims};
+    use wascap::prelude::KeyPair;
+
+    fn claims(issuer: &KeyPair) -> Claims<CapabilityProvider> {
+        Claims::<CapabilityProvider>::new(
+            "Test".to_string(),
+            issuer.public_key(),
+            KeyPair::new_service().public_key(),
+            "wascc:testing".to_string(),
+            "Testing".to_string(),
+            None,
+            None,
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn providers_need_signed_claims() {
+        let issuer = KeyPair::new_account();
+        let unsigned = NativeCapability::from_instance(
+            MemoryKeyValueProvider::new("Nstrict"),
+            None,
+            claims(&issuer),
+        )
+        .unwrap();
+        assert!(verify_provider(&unsigned).is_err());
+
+        let jwt = claims(&issuer).encode(&issuer).unwrap();
+        let signed = NativeCapability::from_instance_with_token(
+            MemoryKeyValueProvider::new("Nstrict"),
+            None,
+            &jwt,
+        )
+        .unwrap();
+        assert!(verify_provider(&signed).is_ok());
+
+        // Claims that differ from the signed token are refused
+        let mut forged = signed.clone();
+        forged.claims.subject = KeyPair::new_service().public_key();
+        assert!(verify_provider(&forged).is_err());
+    }
+}
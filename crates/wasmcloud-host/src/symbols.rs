@@ -0,0 +1,188 @@
+// When a guest traps, all an engine has to say about it is something like "unreachable executed",
+// along with (where the engine records them) the frames of the guest's stack by function index.
+// The indices are resolved here to function names from the module's name section, demangled, so
+// that the error in the invocation response and the event published for the trap carry a
+// readable backtrace. Actors are usually shipped with their name section stripped, so the names
+// can instead come from an unstripped build of the same module, uploaded to the host with
+// `Host::upload_actor_symbols`.
+
+use crate::Result;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use wasmparser::{Name, NameSectionReader, Parser, Payload};
+
+// Uploaded symbols, keyed by host ID and actor public key
+static UPLOADED: Lazy<RwLock<HashMap<(String, String), Symbols>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The names of a module's functions, by function index
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Symbols {
+    names: HashMap<u32, String>,
+}
+
+impl Symbols {
+    /// Reads the function names from a module's name section. A module without one has no names
+    pub fn from_module(bytes: &[u8]) -> Symbols {
+        let mut names = HashMap::new();
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload {
+                Ok(Payload::CustomSection {
+                    name: "name",
+                    data,
+                    data_offset,
+                    ..
+                }) => {
+                    if let Err(e) = read_function_names(data, data_offset, &mut names) {
+                        warn!("Ignoring malformed name section: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        Symbols { names }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    fn name(&self, index: u32) -> String {
+        match self.names.get(&index) {
+            Some(name) => format!("{:#}", rustc_demangle::demangle(name)),
+            None => format!("<wasm function {}>", index),
+        }
+    }
+
+    /// Rewrites an engine's trap message as the trap's description followed by a readable
+    /// backtrace. Messages without any frames are returned as they are
+    pub fn symbolicate(&self, error: &str) -> String {
+        let frames = frames(error);
+        if frames.is_empty() {
+            return error.to_string();
+        }
+        let mut out = error.lines().next().unwrap_or_default().to_string();
+        out.push_str("\nbacktrace:");
+        for (i, frame) in frames.iter().enumerate() {
+            let name = match frame {
+                Frame::Index(index) => self.name(*index),
+                Frame::Named(name) => format!("{:#}", rustc_demangle::demangle(name)),
+            };
+            out.push_str(&format!("\n  {}: {}", i, name));
+        }
+        out
+    }
+}
+
+fn read_function_names(
+    data: &[u8],
+    offset: usize,
+    names: &mut HashMap<u32, String>,
+) -> std::result::Result<(), wasmparser::BinaryReaderError> {
+    let mut reader = NameSectionReader::new(data, offset)?;
+    while !reader.eof() {
+        if let Name::Function(f) = reader.read()? {
+            let mut map = f.get_map()?;
+            for _ in 0..map.get_count() {
+                let naming = map.read()?;
+                names.insert(naming.index, naming.name.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Index(u32),
+    Named(String),
+}
+
+// Picks the frames out of the backtrace in a trap message, such as wasmtime's
+// `0: 0x1a2b - <unknown>!<wasm function 12>`. Frames the engine already named keep their names
+fn frames(error: &str) -> Vec<Frame> {
+    error
+        .lines()
+        .skip_while(|l| !l.contains("backtrace"))
+        .skip(1)
+        .filter_map(|line| {
+            let frame = line.splitn(2, " - ").nth(1)?.trim();
+            let func = frame.rsplit('!').next().unwrap_or(frame);
+            match func
+                .strip_prefix("<wasm function ")
+                .and_then(|f| f.strip_suffix('>'))
+            {
+                Some(index) => index.parse().ok().map(Frame::Index),
+                None => Some(Frame::Named(func.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// Replaces the symbols used for an actor's traps with those of an unstripped build of it
+pub(crate) fn upload(host_id: &str, actor: &str, debug_module: &[u8]) -> Result<()> {
+    let symbols = Symbols::from_module(debug_module);
+    if symbols.is_empty() {
+        return Err("Module has no function names to take symbols from".into());
+    }
+    UPLOADED
+        .write()
+        .insert((host_id.to_string(), actor.to_string()), symbols);
+    Ok(())
+}
+
+/// The symbols uploaded for an actor, if any
+pub(crate) fn uploaded(host_id: &str, actor: &str) -> Option<Symbols> {
+    UPLOADED
+        .read()
+        .get(&(host_id.to_string(), actor.to_string()))
+        .cloned()
+}
+
+/// Forgets the symbols uploaded to the host
+pub(crate) fn clear(host_id: &str) {
+    UPLOADED.write().retain(|(h, _), _| h != host_id);
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symbols;
+
+    // A module with a single, empty function, named in the module's name section
+    fn named_module(name: &str) -> Vec<u8> {
+        let mut names = vec![0x01, 0x00, name.len() as u8];
+        names.extend_from_slice(name.as_bytes());
+        let mut custom = vec![0x04];
+        custom.extend_from_slice(b"name");
+        custom.push(0x01);
+        custom.push(names.len() as u8);
+        custom.extend(names);
+
+        let mut module = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00, 0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, 0x00,
+        ];
+        module.push(custom.len() as u8);
+        module.extend(custom);
+        module
+    }
+
+    #[test]
+    fn traps_get_readable_backtraces() {
+        let symbols = Symbols::from_module(&named_module("_ZN5actor6handle17h0123456789abcdefE"));
+        let trap = "wasm trap: unreachable\nwasm backtrace:\n  0:   0x1a - <unknown>!<wasm function 0>\n  1:   0x2b - <unknown>!<wasm function 7>\n";
+        assert_eq!(
+            "wasm trap: unreachable\nbacktrace:\n  0: actor::handle\n  1: <wasm function 7>",
+            symbols.symbolicate(trap)
+        );
+
+        // Nothing to resolve without frames
+        assert_eq!(
+            "[trap] unreachable executed",
+            symbols.symbolicate("[trap] unreachable executed")
+        );
+        assert!(Symbols::from_module(&named_module("")[..24]).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+// A platform can designate one actor as the system actor of a host, so that the things a platform
+// does when a host comes and goes (registering it with an inventory system, warming caches, and
+// so on) can be written as an actor rather than as a script wrapped around the host. The host
+// starts the system actor itself, before it announces itself on the lattice, and invokes it:
+//
+// * with `HostStarted` once the host is up. An error from the actor fails the host's start
+// * with `HostStopping` when the host begins to stop, while everything is still running
+// * with `HandleLatticeEvent` for each of the events it was asked to receive, as the host
+//   publishes them
+//
+// Each invocation carries the event (a `PublishedEvent`) as JSON. The system actor is reserved:
+// it can't be stopped or updated on its own, and stops with the host.
+
+use crate::dispatch::{Invocation, InvocationResponse, WasccEntity};
+use crate::hlreg::HostLocalSystemService;
+use crate::messagebus::MessageBus;
+use crate::{PublishedEvent, Result, SYSTEM_ACTOR};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use wascap::prelude::KeyPair;
+
+/// The operation with which the system actor is invoked once the host has started
+pub const OP_HOST_STARTED: &str = "HostStarted";
+/// The operation with which the system actor is invoked when the host begins to stop
+pub const OP_HOST_STOPPING: &str = "HostStopping";
+/// The operation with which the system actor is invoked for each event it receives
+pub const OP_HANDLE_LATTICE_EVENT: &str = "HandleLatticeEvent";
+
+#[derive(Clone, Debug)]
+struct SystemActor {
+    actor: String,
+    events: Vec<String>,
+}
+
+// Keyed by host ID
+static SYSTEM_ACTORS: Lazy<RwLock<HashMap<String, SystemActor>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub(crate) fn register(host_id: &str, actor: &str, events: &[String]) {
+    SYSTEM_ACTORS.write().insert(
+        host_id.to_string(),
+        SystemActor {
+            actor: actor.to_string(),
+            events: events.to_vec(),
+        },
+    );
+}
+
+pub(crate) fn unregister(host_id: &str) {
+    SYSTEM_ACTORS.write().remove(host_id);
+}
+
+/// The public key of the host's system actor, if it has one
+pub(crate) fn system_actor(host_id: &str) -> Option<String> {
+    SYSTEM_ACTORS
+        .read()
+        .get(host_id)
+        .map(|s| s.actor.to_string())
+}
+
+/// Whether the actor is the host's system actor, which can only stop with the host
+pub(crate) fn is_reserved(host_id: &str, actor: &str) -> bool {
+    system_actor(host_id).map_or(false, |a| a == actor)
+}
+
+/// The host's system actor, if it has asked to receive the event
+pub(crate) fn subscriber(host_id: &str, event: &PublishedEvent) -> Option<String> {
+    let name = event.event.name();
+    SYSTEM_ACTORS
+        .read()
+        .get(host_id)
+        .filter(|s| s.events.iter().any(|e| e == &name))
+        .map(|s| s.actor.to_string())
+}
+
+/// Invokes the system actor with an event
+pub(crate) async fn notify(
+    kp: &KeyPair,
+    actor: &str,
+    operation: &str,
+    event: &PublishedEvent,
+) -> Result<()> {
+    let inv = Invocation::new(
+        kp,
+        WasccEntity::Actor(SYSTEM_ACTOR.to_string()),
+        WasccEntity::Actor(actor.to_string()),
+        operation,
+        serde_json::to_vec(event)?,
+    );
+    let bus = MessageBus::from_hostlocal_registry(&kp.public_key());
+    let ir: InvocationResponse = bus.send(inv).await?;
+    match ir.error {
+        Some(e) => Err(format!("System actor failed to handle {}: {}", operation, e).into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_reserved, register, subscriber, unregister};
+    use crate::ControlEvent;
+
+    #[test]
+    fn only_selected_events_reach_the_system_actor() {
+        register("Nhost", "Msys", &["ActorStarted".to_string()]);
+        assert!(is_reserved("Nhost", "Msys"));
+        assert!(!is_reserved("Nhost", "Mother"));
+        assert!(!is_reserved("Nother", "Msys"));
+
+        let started = ControlEvent::ActorStarted {
+            actor: "Mxxx".to_string(),
+            image_ref: None,
+        }
+        .into_published("Nhost");
+        let stopped = ControlEvent::ActorStopped {
+            actor: "Mxxx".to_string(),
+        }
+        .into_published("Nhost");
+        assert_eq!(Some("Msys".to_string()), subscriber("Nhost", &started));
+        assert_eq!(None, subscriber("Nhost", &stopped));
+
+        unregister("Nhost");
+        assert_eq!(None, subscriber("Nhost", &started));
+    }
+}
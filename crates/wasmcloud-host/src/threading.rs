@@ -0,0 +1,315 @@
+// Every actor has run on a thread of its own, which isolates it: a slow or busy actor only ever
+// holds up its own invocations. A thread per actor is a lot of threads, though, for a host running
+// hundreds of actors that are rarely invoked. Such actors can instead be placed in a pool, where
+// they share the pool's threads: an actor in a pool is pinned to whichever of the pool's threads
+// has the fewest actors when it starts. Or they can run as tasks, which hold no thread at all
+// between invocations. A latency-critical actor keeps a thread of its own while the rest of the
+// host's actors share a handful.
+//
+// The operator chooses an actor's model with `HostBuilder::with_thread_model`. Otherwise, the
+// actor's claims can ask for one with a `wasmcloud:threads=` tag (`dedicated`, `pool:<name>`, or
+// `task`), and an actor that asks for nothing gets a thread of its own, as before.
+//
+// An actor handling an invocation holds its thread until it is done, including while it waits on
+// the calls it makes, so the actors sharing a thread take turns. An actor calling another actor
+// pinned to the same thread would wait forever on an actor that can't run, so such calls are
+// refused straight away. Actors that call one another belong on different threads. A call that
+// comes back to the thread indirectly (through a provider) can't be told apart, and waits.
+//
+// Tasks run each invocation on the host's blocking thread pool, using an instance of the actor
+// kept on whichever of the pool's threads picks the invocation up. The pool is shared with the
+// rest of the host, so a task model actor waiting on a chain of calls as deep as the pool takes a
+// thread from everything else until the chain completes.
+
+use actix::Arbiter;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use wascap::jwt::{Actor, Claims};
+
+/// The prefix of the actor claims tag asking for a thread model, such as
+/// `wasmcloud:threads=pool:background`
+pub const TAG_PREFIX_THREADS: &str = "wasmcloud:threads=";
+
+// The number of threads of a pool whose size isn't configured
+const DEFAULT_POOL_THREADS: usize = 4;
+
+/// How an actor is given the thread it runs on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ThreadModel {
+    /// The actor has a thread of its own
+    Dedicated,
+    /// The actor shares the threads of the named pool with the other actors in it
+    Pool(String),
+    /// The actor's invocations run as tasks on the host's blocking thread pool
+    Task,
+}
+
+impl ThreadModel {
+    fn parse(value: &str) -> Option<ThreadModel> {
+        match value {
+            "dedicated" => Some(ThreadModel::Dedicated),
+            "task" => Some(ThreadModel::Task),
+            _ => value
+                .strip_prefix("pool:")
+                .filter(|name| !name.is_empty())
+                .map(|name| ThreadModel::Pool(name.to_string())),
+        }
+    }
+}
+
+/// The thread models the operator chose for actors and the sizes of their pools
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ThreadConfig {
+    pub models: HashMap<String, ThreadModel>,
+    pub pool_sizes: HashMap<String, usize>,
+}
+
+impl ThreadConfig {
+    pub fn validate(&self) -> crate::Result<()> {
+        match self.pool_sizes.iter().find(|(_, size)| **size == 0) {
+            Some((name, _)) => Err(format!("Thread pool '{}' has no threads", name).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+// The thread model of an actor: the operator's choice if there is one, then the one its claims
+// ask for, then a thread of its own
+fn choose(config: &ThreadConfig, claims: &Claims<Actor>) -> ThreadModel {
+    if let Some(model) = config.models.get(&claims.subject) {
+        return model.clone();
+    }
+    claims
+        .metadata
+        .as_ref()
+        .and_then(|md| md.tags.as_ref())
+        .and_then(|tags| {
+            tags.iter()
+                .filter_map(|t| t.strip_prefix(TAG_PREFIX_THREADS))
+                .find_map(ThreadModel::parse)
+        })
+        .unwrap_or(ThreadModel::Dedicated)
+}
+
+// The thread of a pool with the fewest actors, the first of them on a tie
+fn least_loaded(actors: &[usize]) -> usize {
+    (0..actors.len()).min_by_key(|i| actors[*i]).unwrap_or(0)
+}
+
+/// Where an actor was placed, so that its thread can be given back when it stops
+#[derive(Debug, Clone)]
+pub(crate) enum Placement {
+    Dedicated,
+    Pooled {
+        pool: String,
+        thread: usize,
+        arbiter: Arbiter,
+    },
+    Task,
+}
+
+struct Pool {
+    threads: Vec<Arbiter>,
+    actors: Vec<usize>,
+}
+
+/// The threads the host's actors run on
+#[derive(Default)]
+pub(crate) struct ThreadPools {
+    config: ThreadConfig,
+    pools: HashMap<String, Pool>,
+}
+
+impl ThreadPools {
+    pub fn new(config: ThreadConfig) -> ThreadPools {
+        ThreadPools {
+            config,
+            pools: HashMap::new(),
+        }
+    }
+
+    /// Picks the thread for an actor, starting its pool if need be. A dedicated actor starts
+    /// its own thread
+    pub fn place(&mut self, claims: &Claims<Actor>) -> Placement {
+        let name = match choose(&self.config, claims) {
+            ThreadModel::Dedicated => return Placement::Dedicated,
+            ThreadModel::Task => return Placement::Task,
+            ThreadModel::Pool(name) => name,
+        };
+        let size = self
+            .config
+            .pool_sizes
+            .get(&name)
+            .cloned()
+            .unwrap_or(DEFAULT_POOL_THREADS);
+        let pool = self.pools.entry(name.to_string()).or_insert_with(|| {
+            info!("Starting thread pool '{}' with {} threads", name, size);
+            Pool {
+                threads: (0..size).map(|_| Arbiter::new()).collect(),
+                actors: vec![0; size],
+            }
+        });
+        let thread = least_loaded(&pool.actors);
+        pool.actors[thread] += 1;
+        Placement::Pooled {
+            arbiter: pool.threads[thread].clone(),
+            pool: name,
+            thread,
+        }
+    }
+
+    /// Gives back the thread of an actor that has stopped. A dedicated actor's thread stops
+    /// with the actor
+    pub fn release(&mut self, placement: Placement) {
+        if let Placement::Pooled { pool, thread, .. } = placement {
+            if let Some(p) = self.pools.get_mut(&pool) {
+                p.actors[thread] = p.actors[thread].saturating_sub(1);
+            }
+        }
+    }
+
+    // The number of actors on each thread of a pool
+    #[cfg(test)]
+    fn load(&self, pool: &str) -> Vec<usize> {
+        self.pools
+            .get(pool)
+            .map(|p| p.actors.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stops the threads of every pool
+    pub fn stop(&mut self) {
+        for (_, pool) in self.pools.drain() {
+            for thread in pool.threads {
+                thread.stop();
+            }
+        }
+    }
+}
+
+thread_local! {
+    // The actors pinned to this thread, if it's the thread of a pool
+    static PINNED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Records that an actor is pinned to the current thread of a pool. Called on that thread
+pub(crate) fn pin(actor: &str) {
+    PINNED.with(|p| p.borrow_mut().insert(actor.to_string()));
+}
+
+/// Records that an actor is no longer pinned to the current thread. Called on that thread
+pub(crate) fn unpin(actor: &str) {
+    PINNED.with(|p| p.borrow_mut().remove(actor));
+}
+
+/// Whether an actor is pinned to the current thread, in which case a call to it from an actor
+/// running on this thread could never be answered
+pub(crate) fn pinned_here(actor: &str) -> bool {
+    PINNED.with(|p| p.borrow().contains(actor))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        choose, least_loaded, pin, pinned_here, unpin, Placement, ThreadConfig, ThreadModel,
+        ThreadPools,
+    };
+    use wascap::jwt::{Actor, ClaimsBuilder};
+
+    #[test]
+    fn operators_override_claims_hints() {
+        let mut claims = ClaimsBuilder::new()
+            .with_metadata(Actor::new(
+                "Mactor".to_string(),
+                None,
+                Some(vec!["wasmcloud:threads=pool:background".to_string()]),
+                false,
+                None,
+                None,
+            ))
+            .build();
+        claims.subject = "Mactor".to_string();
+        let mut config = ThreadConfig::default();
+        assert_eq!(
+            ThreadModel::Pool("background".to_string()),
+            choose(&config, &claims)
+        );
+        config
+            .models
+            .insert("Mactor".to_string(), ThreadModel::Dedicated);
+        assert_eq!(ThreadModel::Dedicated, choose(&config, &claims));
+
+        assert_eq!(None, ThreadModel::parse("pool:"));
+        assert_eq!(None, ThreadModel::parse("async"));
+        assert_eq!(Some(ThreadModel::Task), ThreadModel::parse("task"));
+        assert_eq!(1, least_loaded(&[2, 0, 0]));
+        config.pool_sizes.insert("background".to_string(), 0);
+        assert!(config.validate().is_err());
+    }
+
+    fn claims(subject: &str, tag: Option<&str>) -> wascap::jwt::Claims<Actor> {
+        let mut claims = ClaimsBuilder::new()
+            .with_metadata(Actor::new(
+                subject.to_string(),
+                None,
+                tag.map(|t| vec![t.to_string()]),
+                false,
+                None,
+                None,
+            ))
+            .build();
+        claims.subject = subject.to_string();
+        claims
+    }
+
+    #[actix_rt::test]
+    async fn actors_are_placed_and_released() {
+        let mut config = ThreadConfig::default();
+        config.pool_sizes.insert("background".to_string(), 2);
+        let mut pools = ThreadPools::new(config);
+        let pooled = Some("wasmcloud:threads=pool:background");
+
+        let a = pools.place(&claims("Ma", pooled));
+        let b = pools.place(&claims("Mb", pooled));
+        let c = pools.place(&claims("Mc", pooled));
+        assert_eq!(vec![2, 1], pools.load("background"));
+        match (&a, &b, &c) {
+            (
+                Placement::Pooled { thread: 0, .. },
+                Placement::Pooled { thread: 1, .. },
+                Placement::Pooled { thread: 0, .. },
+            ) => {}
+            other => panic!("Unexpected placements {:?}", other),
+        }
+        assert!(matches!(
+            pools.place(&claims("Md", None)),
+            Placement::Dedicated
+        ));
+        assert!(matches!(
+            pools.place(&claims("Me", Some("wasmcloud:threads=task"))),
+            Placement::Task
+        ));
+        assert_eq!(vec![2, 1], pools.load("background"));
+
+        pools.release(a);
+        pools.release(b);
+        assert_eq!(vec![1, 0], pools.load("background"));
+        // The next actor goes to the thread that was freed
+        let d = pools.place(&claims("Mf", pooled));
+        assert!(matches!(d, Placement::Pooled { thread: 1, .. }));
+        pools.release(c);
+        pools.release(d);
+        pools.release(Placement::Dedicated);
+        assert_eq!(vec![0, 0], pools.load("background"));
+        pools.stop();
+    }
+
+    #[test]
+    fn pinned_actors_are_per_thread() {
+        pin("Ma");
+        assert!(pinned_here("Ma"));
+        assert!(!std::thread::spawn(|| pinned_here("Ma")).join().unwrap());
+        unpin("Ma");
+        assert!(!pinned_here("Ma"));
+    }
+}
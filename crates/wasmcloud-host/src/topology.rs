@@ -0,0 +1,251 @@
+// Teams want diagrams of what is actually running, and the host already knows most of it: the
+// actors and provider instances subscribed to its bus, the links between them, the traffic
+// carried over each link, and the hosts of its lattice roster. Piecing a diagram together from
+// the separate inventory, link and statistics queries gives a picture that may never have
+// existed, as actors and links come and go between the queries. The topology is instead taken
+// by the message bus in a single step, so every node, edge and count in it belongs to the same
+// moment. Each node is then health checked; the checks happen just after the snapshot, but only
+// for the nodes in it.
+//
+// The topology renders as JSON or as a Graphviz DOT graph, with this host's actors and providers
+// grouped in a cluster. Links to providers running on other hosts point to nodes outside it.
+
+use crate::control_interface::events::RunState;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// A host of the lattice. Only the host taking the topology reports its actors and providers
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TopologyHost {
+    pub host_id: String,
+    /// Whether this is the host that took the topology
+    pub local: bool,
+    pub labels: HashMap<String, String>,
+}
+
+/// Whether a node is an actor or a capability provider instance
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Actor,
+    Provider,
+}
+
+/// An actor or capability provider instance running in the host
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TopologyNode {
+    /// The node's identity in the graph: the public key of an actor, or the public key and link
+    /// name of a provider instance
+    pub id: String,
+    pub kind: NodeKind,
+    pub host_id: String,
+    /// The name from an actor's claims
+    pub name: Option<String>,
+    pub contract_id: Option<String>,
+    pub link_name: Option<String>,
+    pub healthy: bool,
+    /// Why the node failed its health check
+    pub health_message: Option<String>,
+}
+
+/// A link from an actor to a provider, with the traffic the host has measured over it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TopologyEdge {
+    /// The id of the actor's node
+    pub from: String,
+    /// The id of the provider's node, which may be running on another host
+    pub to: String,
+    pub contract_id: String,
+    pub link_name: String,
+    pub invocations: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub p99_latency_ms: u64,
+}
+
+/// The hosts, actors, providers and links of the lattice as seen by one host at a single moment.
+/// See `Host::lattice_topology`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LatticeTopology {
+    /// The moment the topology was taken, in milliseconds since the epoch
+    pub taken_at_ms: u64,
+    pub hosts: Vec<TopologyHost>,
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<TopologyEdge>,
+}
+
+/// The id of a provider instance's node
+pub(crate) fn provider_node(provider_id: &str, link_name: &str) -> String {
+    format!("{}/{}", provider_id, link_name)
+}
+
+impl LatticeTopology {
+    /// Records the outcome of each node's health check. Nodes without one are unhealthy
+    pub(crate) fn set_health(&mut self, states: &HashMap<String, RunState>) {
+        for node in self.nodes.iter_mut() {
+            match states.get(&node.id) {
+                Some(RunState::Running) => {
+                    node.healthy = true;
+                    node.health_message = None;
+                }
+                Some(RunState::Unhealthy(m)) => {
+                    node.healthy = false;
+                    node.health_message = Some(m.to_string());
+                }
+                None => {
+                    node.healthy = false;
+                    node.health_message = Some("Not health checked".to_string());
+                }
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> crate::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Renders the topology as a Graphviz DOT graph. Unhealthy nodes are drawn in red, and each
+    /// edge is labeled with its contract, invocation count and p99 latency
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph lattice {\n  rankdir=LR;\n");
+        for (i, host) in self.hosts.iter().enumerate() {
+            if host.local {
+                let _ = writeln!(dot, "  subgraph cluster_{} {{", i);
+                let _ = writeln!(dot, "    label={};", quote(&host.host_id));
+                for node in self.nodes.iter().filter(|n| n.host_id == host.host_id) {
+                    let _ = writeln!(dot, "    {}", dot_node(node));
+                }
+                dot.push_str("  }\n");
+            } else {
+                let _ = writeln!(
+                    dot,
+                    "  {} [shape=box3d, label={}];",
+                    quote(&host.host_id),
+                    quote(&format!("host {}", host.host_id))
+                );
+            }
+        }
+        for edge in self.edges.iter() {
+            let mut label = format!(
+                "{} ({})\n{} calls, p99 {}ms",
+                edge.contract_id, edge.link_name, edge.invocations, edge.p99_latency_ms
+            );
+            if edge.errors > 0 {
+                let _ = write!(label, ", {} errors", edge.errors);
+            }
+            let _ = writeln!(
+                dot,
+                "  {} -> {} [label={}];",
+                quote(&edge.from),
+                quote(&edge.to),
+                quote(&label)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn dot_node(node: &TopologyNode) -> String {
+    let (shape, label) = match node.kind {
+        NodeKind::Actor => (
+            "ellipse",
+            node.name.clone().unwrap_or_else(|| node.id.to_string()),
+        ),
+        NodeKind::Provider => (
+            "box",
+            format!(
+                "{}\n{}",
+                node.contract_id.as_deref().unwrap_or_default(),
+                node.link_name.as_deref().unwrap_or_default()
+            ),
+        ),
+    };
+    let color = if node.healthy { "black" } else { "red" };
+    format!(
+        "{} [shape={}, color={}, label={}];",
+        quote(&node.id),
+        shape,
+        color,
+        quote(&label)
+    )
+}
+
+// A DOT quoted string
+fn quote(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        provider_node, LatticeTopology, NodeKind, TopologyEdge, TopologyHost, TopologyNode,
+    };
+    use crate::control_interface::events::RunState;
+    use std::collections::HashMap;
+
+    #[test]
+    fn dot_groups_local_nodes_and_marks_unhealthy_ones() {
+        let node = |id: &str, kind| TopologyNode {
+            id: id.to_string(),
+            kind,
+            host_id: "NLOCAL".to_string(),
+            name: None,
+            contract_id: None,
+            link_name: None,
+            healthy: false,
+            health_message: None,
+        };
+        let provider = provider_node("VPROV", "default");
+        let mut topology = LatticeTopology {
+            taken_at_ms: 1_000,
+            hosts: vec![
+                TopologyHost {
+                    host_id: "NLOCAL".to_string(),
+                    local: true,
+                    labels: HashMap::new(),
+                },
+                TopologyHost {
+                    host_id: "NPEER".to_string(),
+                    local: false,
+                    labels: HashMap::new(),
+                },
+            ],
+            nodes: vec![
+                node("Mactor", NodeKind::Actor),
+                node(&provider, NodeKind::Provider),
+            ],
+            edges: vec![TopologyEdge {
+                from: "Mactor".to_string(),
+                to: provider.to_string(),
+                contract_id: "wascc:\"kv\"".to_string(),
+                link_name: "default".to_string(),
+                invocations: 12,
+                errors: 0,
+                error_rate: 0.0,
+                p99_latency_ms: 3,
+            }],
+        };
+        let mut states = HashMap::new();
+        states.insert("Mactor".to_string(), RunState::Running);
+        topology.set_health(&states);
+        assert!(topology.nodes[0].healthy);
+        assert!(!topology.nodes[1].healthy);
+
+        let dot = topology.to_dot();
+        assert!(dot.contains("subgraph cluster_0 {\n    label=\"NLOCAL\";\n"));
+        assert!(dot.contains("\"Mactor\" [shape=ellipse, color=black"));
+        assert!(dot.contains("\"VPROV/default\" [shape=box, color=red"));
+        assert!(dot.contains("\"NPEER\" [shape=box3d"));
+        // Labels are escaped, with their line breaks kept
+        assert!(dot.contains(
+            "\"Mactor\" -> \"VPROV/default\" [label=\"wascc:\\\"kv\\\" (default)\\n12 calls"
+        ));
+    }
+}
@@ -0,0 +1,214 @@
+// Actors are validated against the WebAssembly proposals enabled in the host before they are
+// handed to the engine. This turns what would otherwise be an opaque instantiation failure (or,
+// worse, a silent acceptance) into an explicit error naming the proposal the actor depends on.
+
+use crate::Result;
+use wasmparser::{Validator, WasmFeatures as ValidatorFeatures};
+
+/// The optional WebAssembly proposals that actors in a host may use. An actor that requires a
+/// disabled proposal is rejected when it is started. Proposals that were already in common use
+/// are enabled by default, and can be turned off to minimize the instruction surface available
+/// to actors; newer proposals must be explicitly enabled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WasmFeatures {
+    /// 128-bit SIMD instructions
+    pub simd: bool,
+    /// Bulk memory and table operations, such as `memory.copy` and `memory.fill`
+    pub bulk_memory: bool,
+    /// Shared memories and atomic instructions
+    pub threads: bool,
+    /// `externref` values and multiple tables
+    pub reference_types: bool,
+    /// 64-bit linear memories, for actors that need more than 4 GB of address space
+    pub memory64: bool,
+    /// More than one linear memory per module
+    pub multi_memory: bool,
+}
+
+impl Default for WasmFeatures {
+    fn default() -> Self {
+        WasmFeatures {
+            simd: true,
+            bulk_memory: true,
+            threads: true,
+            reference_types: true,
+            memory64: false,
+            multi_memory: false,
+        }
+    }
+}
+
+struct Proposal {
+    name: &'static str,
+    enabled: fn(&WasmFeatures) -> bool,
+    set: fn(&mut ValidatorFeatures, bool),
+}
+
+const PROPOSALS: &[Proposal] = &[
+    Proposal {
+        name: "SIMD",
+        enabled: |f| f.simd,
+        set: |f, on| f.simd = on,
+    },
+    Proposal {
+        name: "bulk memory",
+        enabled: |f| f.bulk_memory,
+        set: |f, on| f.bulk_memory = on,
+    },
+    Proposal {
+        name: "threads",
+        enabled: |f| f.threads,
+        set: |f, on| f.threads = on,
+    },
+    Proposal {
+        name: "reference types",
+        enabled: |f| f.reference_types,
+        set: |f, on| f.reference_types = on,
+    },
+    Proposal {
+        name: "memory64",
+        enabled: |f| f.memory64,
+        set: |f, on| f.memory64 = on,
+    },
+    Proposal {
+        name: "multi-memory",
+        enabled: |f| f.multi_memory,
+        set: |f, on| f.multi_memory = on,
+    },
+];
+
+impl WasmFeatures {
+    fn enabled(&self) -> Vec<&'static str> {
+        PROPOSALS
+            .iter()
+            .filter(|p| (p.enabled)(self))
+            .map(|p| p.name)
+            .collect()
+    }
+}
+
+/// Proposals that can be enabled in the host but that the engine this host was compiled with
+/// can't run. wasm3 doesn't implement them, and the wasmtime provider builds its engine with the
+/// default configuration, which leaves them off, without a way for the host to change it. Until
+/// an engine provider can be configured, actors that use these proposals are rejected at start
+/// time whatever the host enables. Support for the other proposals is left for the engine to
+/// decide when the actor is instantiated
+const ENGINE_UNSUPPORTED: &[&str] = &["memory64", "multi-memory"];
+
+/// The proposals enabled in the host that the engine can't run
+pub(crate) fn unsupported_by_engine(features: &WasmFeatures) -> Vec<&'static str> {
+    features
+        .enabled()
+        .into_iter()
+        .filter(|n| ENGINE_UNSUPPORTED.contains(n))
+        .collect()
+}
+
+fn validator_features(enabled: &[&str]) -> ValidatorFeatures {
+    let mut f = ValidatorFeatures {
+        multi_value: true,
+        ..Default::default()
+    };
+    for p in PROPOSALS {
+        (p.set)(&mut f, enabled.contains(&p.name));
+    }
+    f
+}
+
+fn validates(bytes: &[u8], enabled: &[&str]) -> bool {
+    Validator::new()
+        .wasm_features(validator_features(enabled))
+        .validate_all(bytes)
+        .is_ok()
+}
+
+/// Ensures that an actor module only depends on proposals that are both enabled in the host and
+/// supported by the engine, returning an error naming the offending proposal if not
+pub(crate) fn check_actor_features(bytes: &[u8], features: &WasmFeatures) -> Result<()> {
+    let enabled = features.enabled();
+    if !validates(bytes, &enabled) {
+        // Find a disabled proposal that would let the actor validate
+        for name in PROPOSALS
+            .iter()
+            .map(|p| p.name)
+            .filter(|n| !enabled.contains(n))
+        {
+            let mut with = enabled.clone();
+            with.push(name);
+            if validates(bytes, &with) {
+                return Err(format!(
+                    "Actor requires the WebAssembly {} proposal, which is not enabled in this host",
+                    name
+                )
+                .into());
+            }
+        }
+        return Err("Actor module failed WebAssembly validation".into());
+    }
+    let engine = crate::Host::wasm_engine();
+    for name in enabled.iter().filter(|n| ENGINE_UNSUPPORTED.contains(*n)) {
+        let without: Vec<_> = enabled.iter().cloned().filter(|n| n != name).collect();
+        if !validates(bytes, &without) {
+            return Err(format!(
+                "Actor requires the WebAssembly {} proposal, which the {} engine does not support",
+                name, engine
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_actor_features, unsupported_by_engine, WasmFeatures};
+
+    // (module (memory i64 1))
+    const MEMORY64_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x05, 0x03, 0x01, 0x04, 0x01,
+    ];
+    // (module (memory 1) (memory 1))
+    const MULTI_MEMORY_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x05, 0x05, 0x02, 0x00, 0x01, 0x00, 0x01,
+    ];
+
+    // (module (memory 1) (func (memory.fill (i32.const 0) (i32.const 0) (i32.const 0))))
+    const BULK_MEMORY_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03,
+        0x02, 0x01, 0x00, 0x05, 0x03, 0x01, 0x00, 0x01, 0x0a, 0x0d, 0x01, 0x0b, 0x00, 0x41, 0x00,
+        0x41, 0x00, 0x41, 0x00, 0xfc, 0x0b, 0x00, 0x0b,
+    ];
+
+    #[test]
+    fn gated_proposals_are_named() {
+        let err = check_actor_features(MEMORY64_MODULE, &WasmFeatures::default()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("memory64 proposal, which is not enabled"));
+        let err = check_actor_features(MULTI_MEMORY_MODULE, &WasmFeatures::default()).unwrap_err();
+        assert!(err.to_string().contains("multi-memory proposal"));
+
+        // Enabled in the host, but the engine can't run it
+        let features = WasmFeatures {
+            memory64: true,
+            ..Default::default()
+        };
+        let err = check_actor_features(MEMORY64_MODULE, &features).unwrap_err();
+        assert!(err.to_string().contains("engine does not support"));
+        assert_eq!(unsupported_by_engine(&features), vec!["memory64"]);
+        assert!(unsupported_by_engine(&WasmFeatures::default()).is_empty());
+    }
+
+    #[test]
+    fn disabled_proposals_are_rejected() {
+        let features = WasmFeatures {
+            bulk_memory: false,
+            ..Default::default()
+        };
+        assert!(check_actor_features(BULK_MEMORY_MODULE, &WasmFeatures::default()).is_ok());
+        let err = check_actor_features(BULK_MEMORY_MODULE, &features).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("bulk memory proposal, which is not enabled"));
+    }
+}
@@ -0,0 +1,323 @@
+// A host can look healthy from the outside while one of its internal subsystems has stopped making
+// progress: a message bus whose mailbox no longer drains, a heartbeat that has stopped beating,
+// or a control interface that no longer publishes events. The watchdog keeps an eye on them from
+// a thread of its own, so that whatever holds up the host's own thread can't hold it up too.
+// It regularly asks the dispatcher (the message bus), the cache sync (the lattice RPC client
+// that keeps the claims and link caches in step with the lattice) and the event publisher (the
+// control interface) to answer, and watches the heartbeat tick. A subsystem that hasn't shown
+// progress for longer than the threshold is stalled.
+//
+// Each stall is published as a `SubsystemStalled` event and logged. The heartbeat and the cache
+// sync hold nothing that can't be rebuilt, so they are restarted in place. The dispatcher and the
+// event publisher can't be, as they hold the host's subscriptions and state; when the watchdog
+// is allowed to, it restarts the whole host instead (as does a restarted subsystem that stalls
+// again), draining it and handing it to its upgrade hooks as `Host::prepare_upgrade` would, so
+// whatever supervises the process can replace it.
+
+use crate::control_interface::ctlactor::{ControlInterface, PublishEvent};
+use crate::messagebus::rpc_client::RpcClient;
+use crate::messagebus::{MessageBus, RestartCacheSync, RestartHeartbeat};
+use crate::self_update::UpgradeOptions;
+use crate::ControlEvent;
+use actix::prelude::*;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use wascap::prelude::KeyPair;
+
+static BEATS: Lazy<RwLock<HashMap<String, HashMap<Subsystem, Duration>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// An internal subsystem of the host watched by the watchdog
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Subsystem {
+    Dispatcher,
+    CacheSync,
+    Heartbeat,
+    EventPublisher,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 4] = [
+        Subsystem::Dispatcher,
+        Subsystem::CacheSync,
+        Subsystem::Heartbeat,
+        Subsystem::EventPublisher,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Dispatcher => "dispatcher",
+            Subsystem::CacheSync => "cache-sync",
+            Subsystem::Heartbeat => "heartbeat",
+            Subsystem::EventPublisher => "event-publisher",
+        }
+    }
+}
+
+/// How the watchdog treats stalled subsystems. See `HostBuilder::with_watchdog`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchdogConfig {
+    /// How long a subsystem can go without showing progress before it is stalled. The heartbeat
+    /// is allowed its interval on top of this
+    pub threshold: Duration,
+    /// Whether to restart the host when a subsystem that can't be restarted on its own stalls
+    pub restart_host: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            threshold: Duration::from_secs(30),
+            restart_host: false,
+        }
+    }
+}
+
+/// Answered by each watched subsystem, showing that its mailbox is draining
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Ping;
+
+/// Records that the subsystem has shown progress
+pub(crate) fn beat(host_id: &str, subsystem: Subsystem) {
+    let now = crate::clock::monotonic(host_id);
+    BEATS
+        .write()
+        .entry(host_id.to_string())
+        .or_default()
+        .insert(subsystem, now);
+}
+
+pub(crate) fn clear(host_id: &str) {
+    BEATS.write().remove(host_id);
+}
+
+// How long the subsystem has been stalled, if it has been quiet for longer than it may be
+fn stalled(seen: Duration, now: Duration, allowed: Duration) -> Option<Duration> {
+    now.checked_sub(seen).filter(|quiet| *quiet > allowed)
+}
+
+pub(crate) struct Watchdog {
+    kp: KeyPair,
+    namespace: String,
+    config: WatchdogConfig,
+    hb_interval: Duration,
+    upgrade: UpgradeOptions,
+    bus: Addr<MessageBus>,
+    cp: Addr<ControlInterface>,
+    rpc: Option<Addr<RpcClient>>,
+    // The stalled subsystems already reported, with the moment each was restarted, if it was
+    stalled: HashMap<Subsystem, Option<Duration>>,
+    restarted: HashSet<Subsystem>,
+    restarting_host: bool,
+}
+
+impl Watchdog {
+    pub fn new(
+        kp: KeyPair,
+        namespace: &str,
+        config: WatchdogConfig,
+        hb_interval: Duration,
+        upgrade: UpgradeOptions,
+        rpc: Option<Addr<RpcClient>>,
+    ) -> Watchdog {
+        let host_id = kp.public_key();
+        for subsystem in Subsystem::ALL.iter() {
+            beat(&host_id, *subsystem);
+        }
+        Watchdog {
+            bus: crate::hlreg::HostLocalSystemService::from_hostlocal_registry(&host_id),
+            cp: crate::hlreg::HostLocalSystemService::from_hostlocal_registry(&host_id),
+            kp,
+            namespace: namespace.to_string(),
+            config,
+            hb_interval,
+            upgrade,
+            rpc,
+            stalled: HashMap::new(),
+            restarted: HashSet::new(),
+            restarting_host: false,
+        }
+    }
+
+    fn probe(&self, ctx: &mut Context<Self>) {
+        let host_id = self.kp.public_key();
+        let timeout = self.config.threshold;
+        let bus = self.bus.send(Ping).timeout(timeout);
+        let cp = self.cp.send(Ping).timeout(timeout);
+        let rpc = self.rpc.as_ref().map(|r| r.send(Ping).timeout(timeout));
+        ctx.spawn(
+            async move {
+                if bus.await.is_ok() {
+                    beat(&host_id, Subsystem::Dispatcher);
+                }
+                if cp.await.is_ok() {
+                    beat(&host_id, Subsystem::EventPublisher);
+                }
+                // A host without a lattice connection has no caches to sync
+                let synced = match rpc {
+                    Some(rpc) => rpc.await.is_ok(),
+                    None => true,
+                };
+                if synced {
+                    beat(&host_id, Subsystem::CacheSync);
+                }
+            }
+            .into_actor(self),
+        );
+    }
+
+    fn check(&mut self, ctx: &mut Context<Self>) {
+        let host_id = self.kp.public_key();
+        let now = crate::clock::monotonic(&host_id);
+        let beats = BEATS.read().get(&host_id).cloned().unwrap_or_default();
+        for subsystem in Subsystem::ALL.iter().cloned() {
+            let allowed = match subsystem {
+                Subsystem::Heartbeat => self.config.threshold + self.hb_interval,
+                _ => self.config.threshold,
+            };
+            let stalled_for = beats
+                .get(&subsystem)
+                .and_then(|seen| stalled(*seen, now, allowed));
+            match (stalled_for, self.stalled.get(&subsystem)) {
+                (Some(stalled_for), None) => self.on_stall(subsystem, stalled_for, ctx),
+                // A restarted subsystem gets the same allowance to show progress again
+                (Some(stalled_for), Some(Some(restarted_at)))
+                    if stalled(*restarted_at, now, allowed).is_some() =>
+                {
+                    self.on_stall(subsystem, stalled_for, ctx)
+                }
+                (None, _) if self.stalled.remove(&subsystem).is_some() => {
+                    info!("Host subsystem {} has recovered", subsystem.name());
+                    self.publish(ControlEvent::SubsystemRecovered {
+                        subsystem: subsystem.name().to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn on_stall(&mut self, subsystem: Subsystem, stalled_for: Duration, ctx: &mut Context<Self>) {
+        let now = crate::clock::monotonic(&self.kp.public_key());
+        // A subsystem that stalls again after being restarted isn't restarted again
+        let restart = !self.restarted.contains(&subsystem)
+            && match subsystem {
+                Subsystem::Heartbeat => {
+                    self.bus.do_send(RestartHeartbeat);
+                    true
+                }
+                Subsystem::CacheSync => {
+                    self.restart_cache_sync(ctx);
+                    true
+                }
+                Subsystem::Dispatcher | Subsystem::EventPublisher => false,
+            };
+        error!(
+            "Host subsystem {} has made no progress for {}ms{}",
+            subsystem.name(),
+            stalled_for.as_millis(),
+            if restart { ", restarting it" } else { "" }
+        );
+        self.stalled
+            .insert(subsystem, if restart { Some(now) } else { None });
+        self.publish(ControlEvent::SubsystemStalled {
+            subsystem: subsystem.name().to_string(),
+            stalled_for_ms: stalled_for.as_millis() as u64,
+            restarted: restart,
+        });
+        if restart {
+            self.restarted.insert(subsystem);
+        } else if self.config.restart_host {
+            self.restart_host(ctx);
+        }
+    }
+
+    fn restart_cache_sync(&mut self, ctx: &mut Context<Self>) {
+        let restarted = self
+            .bus
+            .send(RestartCacheSync)
+            .timeout(self.config.threshold);
+        ctx.spawn(
+            async move { restarted.await.ok().flatten() }
+                .into_actor(self)
+                .map(|rpc, act, _ctx| {
+                    if rpc.is_some() {
+                        act.rpc = rpc;
+                    }
+                }),
+        );
+    }
+
+    fn restart_host(&mut self, ctx: &mut Context<Self>) {
+        if self.restarting_host {
+            return;
+        }
+        self.restarting_host = true;
+        warn!(
+            "Restarting host {} after a subsystem stalled",
+            self.kp.public_key()
+        );
+        let kp = KeyPair::from_seed(&self.kp.seed().unwrap()).unwrap();
+        let namespace = self.namespace.to_string();
+        let upgrade = self.upgrade.clone();
+        let timeout = self.config.threshold;
+        ctx.spawn(
+            async move {
+                let prepare = crate::self_update::prepare(&kp, &namespace, &upgrade);
+                match actix_rt::time::timeout(timeout, prepare).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => error!("Failed to restart host: {}", e),
+                    Err(_) => error!("Timed out preparing the host for a restart"),
+                }
+            }
+            .into_actor(self),
+        );
+    }
+
+    // Events go straight to the control interface's mailbox, which doesn't need the registry
+    fn publish(&self, event: ControlEvent) {
+        self.cp.do_send(PublishEvent { event });
+    }
+}
+
+impl Actor for Watchdog {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("Watchdog started");
+        // Subsystems are probed often enough that a healthy one never looks stalled
+        let interval = self.config.threshold / 4;
+        ctx.run_interval(interval, |act, ctx| {
+            act.probe(ctx);
+            act.check(ctx);
+        });
+    }
+}
+
+impl Handler<crate::hlreg::Shutdown> for Watchdog {
+    type Result = ();
+
+    fn handle(&mut self, _msg: crate::hlreg::Shutdown, ctx: &mut Context<Self>) {
+        clear(&self.kp.public_key());
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::stalled;
+    use std::time::Duration;
+
+    #[test]
+    fn subsystems_stall_once_quiet_past_their_allowance() {
+        let secs = Duration::from_secs;
+        assert_eq!(None, stalled(secs(10), secs(30), secs(30)));
+        assert_eq!(Some(secs(31)), stalled(secs(10), secs(41), secs(30)));
+        // A beat taken after the check started isn't a stall
+        assert_eq!(None, stalled(secs(42), secs(41), secs(30)));
+    }
+}
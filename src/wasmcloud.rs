@@ -3,6 +3,8 @@ use wasmcloud_host::{HostBuilder, Result};
 #[macro_use]
 extern crate log;
 
+const PLUGINS_VAR: &str = "WASMCLOUD_HOST_PLUGINS";
+
 #[actix_rt::main]
 async fn main() -> Result<()> {
     let _ = env_logger::Builder::from_env(env_logger::Env::default().filter_or(
@@ -16,11 +18,18 @@ async fn main() -> Result<()> {
     let nc_rpc = nats::asynk::connect("0.0.0.0:4222").await?;
     let nc_control = nats::asynk::connect("0.0.0.0:4222").await?;
 
-    let host = HostBuilder::new()
+    let mut builder = HostBuilder::new()
         .with_rpc_client(nc_rpc)
         .with_control_client(nc_control)
-        .enable_live_updates()
-        .build();
+        .enable_live_updates();
+    // Host plugins to load, as a list of library paths separated like PATH
+    if let Some(paths) = std::env::var_os(PLUGINS_VAR) {
+        for path in std::env::split_paths(&paths) {
+            info!("Loading host plugin {}", path.display());
+            builder = builder.with_plugin(&path)?;
+        }
+    }
+    let host = builder.build();
     match host.start().await {
         Ok(_) => {
             actix_rt::signal::ctrl_c().await.unwrap();
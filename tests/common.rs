@@ -108,6 +108,13 @@ pub async fn gen_kvcounter_host(
         webvalues,
     )
     .await?;
+    h.await_link(
+        &kvcounter_key,
+        "wascc:http_server",
+        "default",
+        Duration::from_secs(2),
+    )
+    .await?;
 
     Ok(h)
 }
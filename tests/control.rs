@@ -4,6 +4,7 @@ use crate::{
     },
     generated::http::{deserialize, serialize},
 };
+use ::control_interface::tokens::{decode_token, mint_token, TokenScope};
 use ::control_interface::Client;
 use actix_rt::time::delay_for;
 use std::collections::HashMap;
@@ -12,7 +13,7 @@ use std::time::Duration;
 
 use wascap::prelude::KeyPair;
 use wasmcloud_host::Result;
-use wasmcloud_host::{Actor, HostBuilder};
+use wasmcloud_host::{Actor, Host, HostBuilder};
 
 pub(crate) async fn basics() -> Result<()> {
     let nc = nats::asynk::connect("0.0.0.0:4222").await?;
@@ -21,6 +22,7 @@ pub(crate) async fn basics() -> Result<()> {
         .with_control_client(nc)
         .oci_allow_latest()
         .with_label("testing", "test-one")
+        .with_max_actors(10)
         .build();
 
     h.start().await?;
@@ -117,6 +119,8 @@ pub(crate) async fn basics() -> Result<()> {
     );
 
     let hosts = ctl_client.get_hosts(Duration::from_millis(500)).await?;
+    assert!(hosts.is_complete());
+    let hosts = hosts.results;
     assert_eq!(hosts.len(), 1);
     assert_eq!(hosts[0].id, hid);
 
@@ -132,6 +136,30 @@ pub(crate) async fn basics() -> Result<()> {
         .find(|p| p.image_ref == Some(HTTPSRV_OCI.to_string()) && p.id == http_ack.provider_id)
         .is_some());
 
+    let lattice_inv = ctl_client
+        .get_lattice_inventory(Duration::from_millis(500))
+        .await?;
+    assert!(lattice_inv.is_complete());
+    assert_eq!(1, lattice_inv.results.len());
+
+    // a host that doesn't exist shows up as a timeout rather than failing the whole query
+    let partial = ctl_client
+        .get_host_inventories(&[hid.to_string(), "NOTAHOST".to_string()])
+        .await;
+    assert_eq!(1, partial.results.len());
+    assert_eq!(1, partial.failures.len());
+    assert_eq!(partial.failures[0].host_id, Some("NOTAHOST".to_string()));
+
+    let cfg = ctl_client.get_host_config(&hid).await?;
+    assert_eq!(cfg.host_id, hid);
+    assert_eq!(cfg.namespace, "controlbasics");
+    assert!(cfg.oci_allow_latest);
+    assert!(!cfg.lattice_rpc_enabled);
+    assert_eq!(cfg.labels["testing"], "test-one");
+    assert_eq!(cfg.max_actors, Some(10));
+    assert_eq!(cfg.max_providers, None);
+    assert_eq!(cfg.max_guest_memory, None);
+
     delay_for(Duration::from_secs(1)).await;
     h.stop().await;
     delay_for(Duration::from_secs(1)).await;
@@ -215,13 +243,15 @@ pub(crate) async fn auctions() -> Result<()> {
     // auction with no requirements
     let kvack = ctl_client
         .perform_actor_auction(KVCOUNTER_OCI, HashMap::new(), Duration::from_secs(1))
-        .await?;
+        .await?
+        .results;
     assert_eq!(2, kvack.len());
 
     // auction the KV counter with a constraint
     let kvack = ctl_client
         .perform_actor_auction(KVCOUNTER_OCI, kvrequirements(), Duration::from_secs(1))
-        .await?;
+        .await?
+        .results;
     assert_eq!(1, kvack.len());
     assert_eq!(kvack[0].host_id, hid);
 
@@ -231,7 +261,8 @@ pub(crate) async fn auctions() -> Result<()> {
 
     let kvack = ctl_client
         .perform_actor_auction(KVCOUNTER_OCI, kvrequirements(), Duration::from_millis(500))
-        .await?;
+        .await?
+        .results;
     // Should be no viable candidates now
     assert_eq!(0, kvack.len());
 
@@ -243,7 +274,8 @@ pub(crate) async fn auctions() -> Result<()> {
             webrequirements(),
             Duration::from_millis(200),
         )
-        .await?;
+        .await?
+        .results;
     assert_eq!(1, httpack.len());
     assert_eq!(httpack[0].host_id, hid2);
 
@@ -261,7 +293,8 @@ pub(crate) async fn auctions() -> Result<()> {
             webrequirements(),
             Duration::from_millis(200),
         )
-        .await?;
+        .await?
+        .results;
     assert_eq!(0, httpack.len());
     h.stop().await;
     h2.stop().await;
@@ -294,3 +327,47 @@ fn embed_revision(source: &[u8], kp: &KeyPair, rev: i32, subject: &str, issuer:
     );
     wascap::wasm::embed_claims(source, &claims, kp).unwrap()
 }
+
+async fn token_host(namespace: &str, issuer: &KeyPair) -> Result<Host> {
+    let nc = nats::asynk::connect("0.0.0.0:4222").await?;
+    let h = HostBuilder::new()
+        .with_namespace(namespace)
+        .with_control_client(nc)
+        .with_rpc_timeout(Duration::from_secs(1))
+        .with_api_token_issuer(&issuer.public_key())
+        .build();
+    h.start().await?;
+    Ok(h)
+}
+
+// A token revoked with the key that issued it is refused by every host that trusts the key,
+// including hosts started after the revocation
+pub(crate) async fn token_revocation() -> Result<()> {
+    let cluster = KeyPair::new_account();
+    let ns = Some("tokenrevocation".to_string());
+    let a = token_host("tokenrevocation", &cluster).await?;
+
+    let revoked = mint_token(&cluster, "ci", &ns, TokenScope::ReadOnly, None)?;
+    let kept = mint_token(&cluster, "dashboard", &ns, TokenScope::ReadOnly, None)?;
+    let nc = nats::asynk::connect("0.0.0.0:4222").await?;
+    let client = Client::new(nc.clone(), ns.clone(), Duration::from_secs(2)).with_token(&revoked);
+    let other = Client::new(nc, ns.clone(), Duration::from_secs(2)).with_token(&kept);
+    assert!(client.get_host_inventory(&a.id()).await.is_ok());
+
+    client
+        .revoke_api_token(&cluster, &decode_token(&revoked)?.jti)
+        .await?;
+    delay_for(Duration::from_millis(500)).await;
+    assert!(client.get_host_inventory(&a.id()).await.is_err());
+    assert!(other.get_host_inventory(&a.id()).await.is_ok());
+
+    // A host started later collects the revocation from the host already running
+    let b = token_host("tokenrevocation", &cluster).await?;
+    delay_for(Duration::from_millis(1500)).await;
+    assert!(client.get_host_inventory(&b.id()).await.is_err());
+    assert!(other.get_host_inventory(&b.id()).await.is_ok());
+
+    a.stop().await;
+    b.stop().await;
+    Ok(())
+}
@@ -2,6 +2,7 @@ mod common;
 mod control;
 mod generated;
 mod no_lattice;
+mod soak;
 mod with_lattice;
 
 use std::env::temp_dir;
@@ -21,6 +22,16 @@ async fn start_and_execute_echo() -> Result<()> {
     no_lattice::start_and_execute_echo().await
 }
 
+#[actix_rt::test]
+async fn loopback_echo() -> Result<()> {
+    no_lattice::loopback_echo().await
+}
+
+#[actix_rt::test]
+async fn scoped_echo() -> Result<()> {
+    no_lattice::scoped_echo().await
+}
+
 #[actix_rt::test]
 async fn kvcounter_basic() -> Result<()> {
     no_lattice::kvcounter_basic().await
@@ -36,6 +47,41 @@ async fn kvcounter_start_stop() -> Result<()> {
     no_lattice::kvcounter_start_stop().await
 }
 
+#[actix_rt::test]
+async fn kvcounter_upgrade_provider() -> Result<()> {
+    no_lattice::kvcounter_upgrade_provider().await
+}
+
+#[actix_rt::test]
+async fn kvcounter_unhealthy_upgrade() -> Result<()> {
+    no_lattice::kvcounter_unhealthy_upgrade().await
+}
+
+#[actix_rt::test]
+async fn prestart_hook_refuses_update() -> Result<()> {
+    no_lattice::prestart_hook_refuses_update().await
+}
+
+#[actix_rt::test]
+async fn concurrent_starts_respect_limits() -> Result<()> {
+    no_lattice::concurrent_starts_respect_limits().await
+}
+
+#[actix_rt::test]
+async fn leased_link_expires() -> Result<()> {
+    no_lattice::leased_link_expires().await
+}
+
+#[actix_rt::test]
+async fn quiesced_router_refuses_requests() -> Result<()> {
+    no_lattice::quiesced_router_refuses_requests().await
+}
+
+#[actix_rt::test]
+async fn keyvalue_client_without_actors() -> Result<()> {
+    no_lattice::keyvalue_client_without_actors().await
+}
+
 #[actix_rt::test]
 async fn distributed_echo() -> Result<()> {
     with_lattice::distributed_echo().await
@@ -46,6 +92,21 @@ async fn link_on_third_host() -> Result<()> {
     with_lattice::link_on_third_host().await
 }
 
+#[actix_rt::test]
+async fn lattice_locks() -> Result<()> {
+    with_lattice::lattice_locks().await
+}
+
+#[actix_rt::test]
+async fn shared_connection_isolates_namespaces() -> Result<()> {
+    with_lattice::shared_connection_isolates_namespaces().await
+}
+
+#[actix_rt::test]
+async fn partitioned_hosts() -> Result<()> {
+    with_lattice::partitioned_hosts().await
+}
+
 //#[actix_rt::test]
 //async fn scaled_kvcounter() -> Result<()> {
 //    with_lattice::scaled_kvcounter().await
@@ -65,3 +126,20 @@ async fn control_auctions() -> Result<()> {
 async fn control_calltest() -> Result<()> {
     control::calltest().await
 }
+
+#[actix_rt::test]
+async fn control_token_revocation() -> Result<()> {
+    control::token_revocation().await
+}
+
+#[actix_rt::test]
+#[ignore]
+async fn soak_start_stop() -> Result<()> {
+    soak::start_stop_cycle().await
+}
+
+#[actix_rt::test]
+#[ignore]
+async fn soak_start_stop_lattice() -> Result<()> {
+    soak::start_stop_cycle_lattice().await
+}
@@ -2,9 +2,14 @@ use crate::common::{await_actor_count, await_provider_count, gen_kvcounter_host,
 use crate::generated::http::{deserialize, serialize, Request, Response};
 use actix_rt::time::delay_for;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use wasmcloud_host::Result;
-use wasmcloud_host::{Actor, HostBuilder, NativeCapability};
+use wasmcloud_host::{
+    Actor, ActorAdmission, CapabilityBuilder, HostBuilder, HttpRequest, ManualClock,
+    NativeCapability, NativeProvider, PreStartHook, ROUTER_PUBLIC_KEY,
+};
 
 pub async fn start_and_execute_echo() -> Result<()> {
     let h = HostBuilder::new().build();
@@ -34,6 +39,77 @@ pub async fn start_and_execute_echo() -> Result<()> {
     Ok(())
 }
 
+// A loopback request reaches the echo actor as if an HTTP server provider had sent it
+pub async fn loopback_echo() -> Result<()> {
+    let h = HostBuilder::new().build();
+    h.start().await?;
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    h.start_actor(echo).await?;
+    await_actor_count(&h, 1, Duration::from_millis(50), 3).await?;
+
+    let request = HttpRequest {
+        method: "POST".to_string(),
+        path: "/loopback".to_string(),
+        query_string: "test=kthxbye".to_string(),
+        header: Default::default(),
+        body: b"no socket involved".to_vec(),
+    };
+    let resp = h.http_request(&actor_id, request).await?;
+    assert_eq!(resp.status_code, 200);
+    let v: serde_json::Value = serde_json::from_slice(&resp.body)?;
+    assert_eq!("test=kthxbye", v["query_string"].as_str().unwrap());
+    assert_eq!("/loopback", v["path"].as_str().unwrap());
+    assert_eq!("POST", v["method"].as_str().unwrap());
+
+    // Without an actor key, the target has to be the name of an HTTP server link
+    assert!(h
+        .http_request("nosuchlink", HttpRequest::default())
+        .await
+        .is_err());
+    h.stop().await;
+    Ok(())
+}
+
+pub async fn scoped_echo() -> Result<()> {
+    let h = HostBuilder::new().build();
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    let request = Request {
+        method: "GET".to_string(),
+        path: "/foo/bar".to_string(),
+        query_string: "test=scoped".to_string(),
+        header: Default::default(),
+        body: vec![],
+    };
+    let buf = serialize(&request)?;
+
+    let status = h
+        .run_scoped(|h| async move {
+            h.start_actor(echo).await?;
+            await_actor_count(h, 1, Duration::from_millis(50), 3).await?;
+            let res = h.call_actor(&actor_id, "HandleRequest", &buf).await?;
+            let resp: Response = deserialize(&res)?;
+            Ok(resp.status_code)
+        })
+        .await?;
+    assert_eq!(status, 200);
+
+    // Everything the host started went away with the scope
+    assert!(h
+        .call_actor(&actor_id, "HandleRequest", &serialize(&request)?)
+        .await
+        .is_err());
+
+    // ...but the actix system is still available to other hosts
+    let h2 = HostBuilder::new().build();
+    h2.start().await?;
+    assert_eq!(0, h2.get_actors().await?.len());
+    h2.stop().await;
+
+    Ok(())
+}
+
 pub async fn kvcounter_basic() -> Result<()> {
     use redis::Commands;
 
@@ -170,3 +246,289 @@ pub async fn kvcounter_link_first() -> Result<()> {
 
     Ok(())
 }
+
+pub async fn kvcounter_upgrade_provider() -> Result<()> {
+    use redis::Commands;
+    let h = gen_kvcounter_host(9996, None, None).await?;
+
+    let key = uuid::Uuid::new_v4().to_string();
+    let rkey = format!(":{}", key); // the kv wasm logic does a replace on '/' with ':'
+    let url = format!("http://localhost:9996/{}", key);
+
+    let resp = reqwest::get(&url).await?;
+    assert!(resp.status().is_success());
+
+    let arc = par_from_file("./tests/modules/libwascc_redis.par.gz")?;
+    let redis_id = arc.claims().unwrap().subject;
+    let redis = NativeCapability::from_archive(&arc, None)?;
+    h.update_native_capability(&redis_id, redis).await?;
+    // The new version replaced the old one rather than running beside it
+    await_provider_count(&h, 3, Duration::from_millis(50), 3).await?;
+    assert_eq!(3, h.get_providers().await?.len());
+
+    // The actor's link carried over to the new version
+    let resp2 = reqwest::get(&url).await?;
+    assert!(resp2.status().is_success());
+    assert_eq!(resp2.text().await?, "{\"counter\":2}");
+
+    let client = redis::Client::open("redis://127.0.0.1/")?;
+    let mut con = client.get_connection()?;
+    let _: () = con.del(&rkey)?;
+    h.stop().await;
+
+    Ok(())
+}
+
+// A key-value provider that never reports itself healthy
+struct Unhealthy;
+
+impl NativeProvider for Unhealthy {
+    fn health(&self) -> std::result::Result<(), String> {
+        Err("not ready".to_string())
+    }
+}
+
+pub async fn kvcounter_unhealthy_upgrade() -> Result<()> {
+    use redis::Commands;
+    let h = gen_kvcounter_host(9995, None, None).await?;
+
+    let key = uuid::Uuid::new_v4().to_string();
+    let rkey = format!(":{}", key); // the kv wasm logic does a replace on '/' with ':'
+    let url = format!("http://localhost:9995/{}", key);
+
+    let resp = reqwest::get(&url).await?;
+    assert!(resp.status().is_success());
+
+    let arc = par_from_file("./tests/modules/libwascc_redis.par.gz")?;
+    let claims = arc.claims().unwrap();
+    let redis_id = claims.subject.to_string();
+    let unhealthy =
+        CapabilityBuilder::new("wascc:keyvalue", Unhealthy).into_native(None, claims)?;
+    let err = h
+        .update_native_capability(&redis_id, unhealthy)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("did not become healthy"));
+
+    // The old version is still serving the actor
+    let resp2 = reqwest::get(&url).await?;
+    assert!(resp2.status().is_success());
+    assert_eq!(resp2.text().await?, "{\"counter\":2}");
+
+    let client = redis::Client::open("redis://127.0.0.1/")?;
+    let mut con = client.get_connection()?;
+    let _: () = con.del(&rkey)?;
+    h.stop().await;
+
+    Ok(())
+}
+
+// Admits actors started directly, but refuses any that come from a registry
+#[derive(Clone)]
+struct NoRegistryActors;
+
+impl PreStartHook for NoRegistryActors {
+    fn before_actor_start(&self, admission: &ActorAdmission) -> std::result::Result<(), String> {
+        match admission.image_ref {
+            Some(image_ref) => Err(format!("{} is not allowed", image_ref)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub async fn prestart_hook_refuses_update() -> Result<()> {
+    let h = HostBuilder::new()
+        .enable_live_updates()
+        .with_prestart_hook(NoRegistryActors)
+        .build();
+    h.start().await?;
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    h.start_actor(echo).await?;
+    await_actor_count(&h, 1, Duration::from_millis(50), 3).await?;
+
+    let update = Actor::from_file("./tests/modules/echo.wasm")?;
+    let err = h
+        .update_actor("wasmcloud.azurecr.io/echo:0.2.0", update)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Actor update rejected"));
+
+    // The running version carries on
+    let request = Request {
+        method: "GET".to_string(),
+        path: "/".to_string(),
+        query_string: "test=update".to_string(),
+        header: Default::default(),
+        body: vec![],
+    };
+    let res = h
+        .call_actor(&actor_id, "HandleRequest", &serialize(&request)?)
+        .await?;
+    let resp: Response = deserialize(&res)?;
+    assert_eq!(resp.status_code, 200);
+    h.stop().await;
+    Ok(())
+}
+
+// Starts that are in flight at the same time are held to the host's limits together, and the
+// same actor can't be started twice by racing starts
+pub async fn concurrent_starts_respect_limits() -> Result<()> {
+    let h = HostBuilder::new().with_max_actors(1).build();
+    h.start().await?;
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let kvcounter = Actor::from_file("./tests/modules/kvcounter.wasm")?;
+    let (a, b) = futures::join!(h.start_actor(echo), h.start_actor(kvcounter));
+    assert!(a.is_ok() != b.is_ok());
+    assert_eq!(1, h.get_actors().await?.len());
+    h.stop().await;
+
+    let h = HostBuilder::new().build();
+    h.start().await?;
+    let (a, b) = futures::join!(
+        h.start_actor(Actor::from_file("./tests/modules/echo.wasm")?),
+        h.start_actor(Actor::from_file("./tests/modules/echo.wasm")?)
+    );
+    assert!(a.is_ok() != b.is_ok());
+    assert_eq!(1, h.get_actors().await?.len());
+    h.stop().await;
+    Ok(())
+}
+
+// A key-value provider that counts the times actors are bound to it and released
+#[derive(Clone, Default)]
+struct BindCounter {
+    binds: Arc<AtomicUsize>,
+    unbinds: Arc<AtomicUsize>,
+}
+
+impl NativeProvider for BindCounter {
+    fn bind(&self, _actor: &str, _values: &HashMap<String, String>) -> Result<()> {
+        self.binds.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn unbind(&self, _actor: &str) {
+        self.unbinds.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Renewing a leased link only moves its expiry, and the link is removed once the lease runs out
+// by the host's clock
+pub async fn leased_link_expires() -> Result<()> {
+    let clock = ManualClock::new();
+    let h = HostBuilder::new().with_clock(clock.clone()).build();
+    h.start().await?;
+    let arc = par_from_file("./tests/modules/libwascc_redis.par.gz")?;
+    let claims = arc.claims().unwrap();
+    let provider_id = claims.subject.to_string();
+    let counter = BindCounter::default();
+    let provider =
+        CapabilityBuilder::new("wascc:keyvalue", counter.clone()).into_native(None, claims)?;
+    h.start_native_capability(provider).await?;
+    let kvcounter = Actor::from_file("./tests/modules/kvcounter.wasm")?;
+    let actor_id = kvcounter.public_key();
+    h.start_actor(kvcounter).await?;
+    await_actor_count(&h, 1, Duration::from_millis(50), 3).await?;
+
+    h.set_link(
+        &actor_id,
+        "wascc:keyvalue",
+        None,
+        provider_id,
+        HashMap::new(),
+    )
+    .await?;
+    delay_for(Duration::from_millis(200)).await;
+    assert_eq!(1, counter.binds.load(Ordering::SeqCst));
+
+    let ttl = Duration::from_secs(30);
+    h.renew_link(&actor_id, "wascc:keyvalue", "default", ttl)
+        .await?;
+    h.renew_link(&actor_id, "wascc:keyvalue", "default", ttl)
+        .await?;
+    delay_for(Duration::from_millis(200)).await;
+    assert_eq!(1, counter.binds.load(Ordering::SeqCst));
+
+    // The lease sweep runs every second
+    clock.advance(Duration::from_secs(29));
+    delay_for(Duration::from_millis(1500)).await;
+    assert_eq!(0, counter.unbinds.load(Ordering::SeqCst));
+    clock.advance(Duration::from_secs(1));
+    delay_for(Duration::from_millis(1500)).await;
+    assert_eq!(1, counter.unbinds.load(Ordering::SeqCst));
+    assert!(h
+        .renew_link(&actor_id, "wascc:keyvalue", "default", ttl)
+        .await
+        .is_err());
+    h.stop().await;
+    Ok(())
+}
+
+// A quiesced router turns new requests away, keeps its routes, and serves them again once
+// resumed. The quiesced state shows up on the host until then
+pub async fn quiesced_router_refuses_requests() -> Result<()> {
+    let h = HostBuilder::new().with_http_router(9994).build();
+    h.start().await?;
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    h.start_actor(echo).await?;
+    await_actor_count(&h, 1, Duration::from_millis(50), 3).await?;
+    h.set_link(
+        &actor_id,
+        "wascc:http_server",
+        None,
+        ROUTER_PUBLIC_KEY.to_string(),
+        HashMap::new(),
+    )
+    .await?;
+    delay_for(Duration::from_millis(200)).await;
+
+    let url = "http://localhost:9994/quiesce";
+    assert!(reqwest::get(url).await?.status().is_success());
+
+    h.quiesce_provider(ROUTER_PUBLIC_KEY, "default").await?;
+    assert_eq!(503, reqwest::get(url).await?.status().as_u16());
+    assert_eq!(
+        vec![(ROUTER_PUBLIC_KEY.to_string(), "default".to_string())],
+        h.get_quiesced_providers().await?
+    );
+
+    h.resume_provider(ROUTER_PUBLIC_KEY, "default").await?;
+    assert!(reqwest::get(url).await?.status().is_success());
+    assert!(h.get_quiesced_providers().await?.is_empty());
+
+    // Quiescing a provider that isn't running is an error
+    assert!(h
+        .quiesce_provider(ROUTER_PUBLIC_KEY, "other")
+        .await
+        .is_err());
+    h.stop().await;
+    Ok(())
+}
+
+// The host's own key-value client reaches the built-in provider without any actor running
+pub async fn keyvalue_client_without_actors() -> Result<()> {
+    let h = HostBuilder::new().with_memory_keyvalue().build();
+    h.start().await?;
+    await_provider_count(&h, 2, Duration::from_millis(50), 20).await?; // plus wascc:extras
+
+    let kv = h.keyvalue("default");
+    kv.set("greeting", "hello").await?;
+    assert_eq!(Some("hello".to_string()), kv.get("greeting").await?);
+    assert_eq!(
+        (false, Some("hello".to_string())),
+        kv.compare_and_swap("greeting", None, "hi", None).await?
+    );
+    assert_eq!((true, 5), kv.increment("counter", 5, None, Some(10)).await?);
+    assert_eq!(
+        (false, 5),
+        kv.increment("counter", 6, None, Some(10)).await?
+    );
+    kv.del("greeting").await?;
+    assert_eq!(None, kv.get("greeting").await?);
+
+    assert!(h.keyvalue("other").get("greeting").await.is_err());
+    h.stop().await;
+    Ok(())
+}
@@ -0,0 +1,226 @@
+// Soak testing repeatedly cycles actors, providers, and links through a single host while driving
+// traffic, and then verifies that host resources (including the links the host knows of) return to
+// the baseline taken after a warm-up cycle. The lattice variant also checks that the host's
+// lattice subscriptions are released, and needs a NATS server on the default port. These tests
+// take a while, so they are ignored by default. Run them with `cargo test soak -- --ignored`,
+// optionally setting `SOAK_ITERATIONS`.
+
+use crate::common::{await_actor_count, await_provider_count, par_from_file};
+use crate::generated::http::{deserialize, serialize, Request, Response};
+use actix_rt::time::delay_for;
+use log::info;
+use std::collections::HashMap;
+use std::time::Duration;
+use wasmcloud_host::{Actor, Host, HostBuilder, NativeCapability, Result};
+
+const SOAK_ITERATIONS_ENV_VAR: &str = "SOAK_ITERATIONS";
+const DEFAULT_SOAK_ITERATIONS: u32 = 25;
+const CALLS_PER_ITERATION: u32 = 20;
+const WEB_PORT: u32 = 9901;
+const LATTICE_WEB_PORT: u32 = 9902;
+
+// Memory allocators rarely hand pages back to the OS, so allow some growth in resident size
+const RSS_TOLERANCE_PERCENT: u64 = 25;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ResourceSnapshot {
+    open_fds: Option<usize>,
+    rss_kb: Option<u64>,
+    actors: usize,
+    providers: usize,
+    links: usize,
+    subscriptions: usize,
+}
+
+pub(crate) async fn start_stop_cycle() -> Result<()> {
+    let h = HostBuilder::new().build();
+    soak(h, WEB_PORT).await
+}
+
+pub(crate) async fn start_stop_cycle_lattice() -> Result<()> {
+    let nc = nats::asynk::connect("0.0.0.0:4222").await?;
+    let h = HostBuilder::new()
+        .with_namespace("soak")
+        .with_rpc_client(nc)
+        .build();
+    soak(h, LATTICE_WEB_PORT).await
+}
+
+async fn soak(h: Host, web_port: u32) -> Result<()> {
+    let iterations = soak_iterations();
+    h.start().await?;
+
+    // The first cycle warms up caches, thread pools, and lazily initialized statics
+    soak_cycle(&h, web_port).await?;
+    let baseline = snapshot(&h).await?;
+    info!("Soak baseline: {:?}", baseline);
+
+    for i in 0..iterations {
+        soak_cycle(&h, web_port).await?;
+        if i % 5 == 0 {
+            info!("Soak iteration {}: {:?}", i, snapshot(&h).await?);
+        }
+    }
+
+    let after = snapshot(&h).await?;
+    info!("Soak final: {:?}", after);
+    h.stop().await;
+
+    assert_eq!(
+        baseline.actors, after.actors,
+        "Leaked actors: {:?} before, {:?} after",
+        baseline, after
+    );
+    assert_eq!(
+        baseline.providers, after.providers,
+        "Leaked providers: {:?} before, {:?} after",
+        baseline, after
+    );
+    assert_eq!(
+        baseline.links, after.links,
+        "Leaked links: {:?} before, {:?} after",
+        baseline, after
+    );
+    assert_eq!(
+        baseline.subscriptions, after.subscriptions,
+        "Leaked lattice subscriptions: {:?} before, {:?} after",
+        baseline, after
+    );
+    if let (Some(before), Some(after)) = (baseline.open_fds, after.open_fds) {
+        assert!(
+            after <= before,
+            "Leaked file descriptors: {} before, {} after",
+            before,
+            after
+        );
+    }
+    if let (Some(before), Some(after)) = (baseline.rss_kb, after.rss_kb) {
+        let limit = before + (before * RSS_TOLERANCE_PERCENT / 100);
+        assert!(
+            after <= limit,
+            "Resident memory grew from {}KB to {}KB",
+            before,
+            after
+        );
+    }
+
+    Ok(())
+}
+
+// A single soak cycle starts an actor and a provider, links them, drives traffic through
+// both the host API and the provider, and then removes the link and stops everything it started.
+async fn soak_cycle(h: &Host, web_port: u32) -> Result<()> {
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    h.start_actor(echo).await?;
+    await_actor_count(h, 1, Duration::from_millis(50), 20).await?;
+
+    let arc = par_from_file("./tests/modules/libwascc_httpsrv.par.gz")?;
+    let provider_id = arc.claims().unwrap().subject.to_string();
+    let websrv = NativeCapability::from_instance(
+        wascc_httpsrv::HttpServerProvider::new(),
+        None,
+        arc.claims().unwrap(),
+    )?;
+    h.start_native_capability(websrv).await?;
+    await_provider_count(h, 2, Duration::from_millis(50), 20).await?;
+
+    let mut webvalues: HashMap<String, String> = HashMap::new();
+    webvalues.insert("PORT".to_string(), format!("{}", web_port));
+    h.set_link(
+        &actor_id,
+        "wascc:http_server",
+        None,
+        provider_id.to_string(),
+        webvalues,
+    )
+    .await?;
+    h.await_link(
+        &actor_id,
+        "wascc:http_server",
+        "default",
+        Duration::from_secs(2),
+    )
+    .await?;
+
+    let url = format!("http://localhost:{}/soak", web_port);
+    let req = serialize(&Request {
+        method: "GET".to_string(),
+        path: "/soak".to_string(),
+        query_string: "".to_string(),
+        header: HashMap::new(),
+        body: b"soak".to_vec(),
+    })?;
+    for _ in 0..CALLS_PER_ITERATION {
+        let res = h.call_actor(&actor_id, "HandleRequest", &req).await?;
+        let resp: Response = deserialize(&res)?;
+        assert_eq!(resp.status_code, 200);
+
+        let resp = reqwest::get(&url).await?;
+        assert!(resp.status().is_success());
+    }
+
+    h.remove_link(&actor_id, "wascc:http_server", "default")
+        .await?;
+    h.stop_actor(&actor_id).await?;
+    h.stop_provider(&provider_id, "wascc:http_server", None)
+        .await?;
+    await_actor_count_exact(h, 0).await?;
+    delay_for(Duration::from_millis(100)).await; // give the web server time to release the port
+
+    Ok(())
+}
+
+async fn await_actor_count_exact(h: &Host, count: usize) -> Result<()> {
+    for _ in 0..20 {
+        if h.get_actors().await?.len() == count {
+            return Ok(());
+        }
+        delay_for(Duration::from_millis(50)).await;
+    }
+    Err(format!("Actor count did not return to {}", count).into())
+}
+
+async fn snapshot(h: &Host) -> Result<ResourceSnapshot> {
+    Ok(ResourceSnapshot {
+        open_fds: open_fds(),
+        rss_kb: rss_kb(),
+        actors: h.get_actors().await?.len(),
+        providers: h.get_providers().await?.len(),
+        links: h.get_links().await?.len(),
+        // Each lattice subscription reports its statistics
+        subscriptions: h.get_subscription_statistics().await?.len(),
+    })
+}
+
+fn soak_iterations() -> u32 {
+    std::env::var(SOAK_ITERATIONS_ENV_VAR)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_SOAK_ITERATIONS)
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd").ok().map(|d| d.count())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_fds() -> Option<usize> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find(|l| l.starts_with("VmRSS:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|v| v.parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rss_kb() -> Option<u64> {
+    None
+}
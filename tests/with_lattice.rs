@@ -3,7 +3,7 @@ use actix_rt::time::delay_for;
 use provider_archive::ProviderArchive;
 use std::collections::HashMap;
 use std::time::Duration;
-use wasmcloud_host::{Actor, HostBuilder, NativeCapability};
+use wasmcloud_host::{Actor, HostBuilder, NativeCapability, Partition, SharedConnection};
 use wasmcloud_host::{Host, Result};
 
 // Start two hosts, A and B. Host A contains an actor
@@ -129,7 +129,14 @@ pub(crate) async fn link_on_third_host() -> Result<()> {
         )
         .await?;
 
-    delay_for(Duration::from_millis(100)).await; // let the HTTP server spin up
+    host_c
+        .await_link(
+            &actor_id,
+            "wascc:http_server",
+            "default",
+            Duration::from_secs(2),
+        )
+        .await?;
 
     let url = format!("http://localhost:{}/foo/bar", web_port);
     let resp = reqwest::get(&url).await?;
@@ -143,6 +150,52 @@ pub(crate) async fn link_on_third_host() -> Result<()> {
     Ok(())
 }
 
+// Start two hosts in the same namespace and have both try to take the same lock at once. Exactly
+// one of them gets it, and once it's released, the other host wins the election for it
+pub(crate) async fn lattice_locks() -> Result<()> {
+    let nc = nats::asynk::connect("0.0.0.0:4222").await?;
+    let host_a = HostBuilder::new()
+        .with_rpc_client(nc)
+        .with_namespace("latticelocks")
+        .build();
+    host_a.start().await.unwrap();
+    let nc2 = nats::asynk::connect("0.0.0.0:4222").await?;
+    let host_b = HostBuilder::new()
+        .with_rpc_client(nc2)
+        .with_namespace("latticelocks")
+        .build();
+    host_b.start().await.unwrap();
+
+    let ttl = Duration::from_secs(10);
+    let (a, b) = futures::join!(
+        host_a.acquire_lock("leader.reconciler", ttl),
+        host_b.acquire_lock("leader.reconciler", ttl)
+    );
+    let (a, b) = (a?, b?);
+    assert!(a.is_some() != b.is_some());
+    let (holder, other) = if a.is_some() {
+        (&host_a, &host_b)
+    } else {
+        (&host_b, &host_a)
+    };
+    assert!(other
+        .acquire_lock("leader.reconciler", ttl)
+        .await?
+        .is_none());
+
+    a.or(b).unwrap().release().await;
+    let lock = other.elect_leader("reconciler", ttl).await?;
+    assert!(lock.is_held());
+    assert!(holder
+        .acquire_lock("leader.reconciler", ttl)
+        .await?
+        .is_none());
+
+    host_a.stop().await;
+    host_b.stop().await;
+    Ok(())
+}
+
 // Run the kvcounter scenario, but with 1 instance of a HTTP provider, 2 instances
 // of redis provider,  and 3 instances of the actor in a 5-host lattice.
 // We can't do 2 instances of the HTTP provider because it would try and bind the same HTTP port twice
@@ -255,3 +308,117 @@ async fn scaledkv_host(actor: Option<Actor>, par: Option<Vec<ProviderArchive>>)
 
     Ok(h)
 }
+
+// Hosts A and B share one connection, each in its own namespace, and host C joins A's namespace
+// over a connection of its own. C can reach the actor running on A, but B can't, and a host
+// can't take a namespace that's already in use on the shared connection
+pub(crate) async fn shared_connection_isolates_namespaces() -> Result<()> {
+    let shared = SharedConnection::new(nats::asynk::connect("0.0.0.0:4222").await?);
+    let host_a = HostBuilder::new()
+        .with_shared_connection(&shared)
+        .with_namespace("sharedconn-a")
+        .build();
+    host_a.start().await?;
+    let host_b = HostBuilder::new()
+        .with_shared_connection(&shared)
+        .with_namespace("sharedconn-b")
+        .build();
+    host_b.start().await?;
+    assert_eq!(shared.hosts(), 2);
+
+    let nc = nats::asynk::connect("0.0.0.0:4222").await?;
+    let host_c = HostBuilder::new()
+        .with_rpc_client(nc)
+        .with_namespace("sharedconn-a")
+        .build();
+    host_c.start().await?;
+
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    host_a.start_actor(echo).await?;
+    await_actor_count(&host_a, 1, Duration::from_millis(50), 3).await?;
+    delay_for(Duration::from_secs(1)).await;
+
+    let req = crate::generated::http::Request {
+        header: HashMap::new(),
+        method: "GET".to_string(),
+        path: "/shared".to_string(),
+        query_string: "".to_string(),
+        body: vec![],
+    };
+    let buf = crate::generated::http::serialize(&req)?;
+    let res = host_c.call_actor(&actor_id, "HandleRequest", &buf).await?;
+    let resp: crate::generated::http::Response = crate::generated::http::deserialize(&res)?;
+    assert_eq!(resp.status_code, 200);
+    assert!(host_b
+        .call_actor(&actor_id, "HandleRequest", &buf)
+        .await
+        .is_err());
+
+    // A host that can't start leaves the connection to the hosts already using it
+    let host_d = HostBuilder::new()
+        .with_shared_connection(&shared)
+        .with_namespace("sharedconn-a")
+        .build();
+    assert!(host_d.start().await.is_err());
+    assert_eq!(shared.hosts(), 2);
+    assert!(host_d.id().is_empty());
+
+    host_a.stop().await;
+    assert_eq!(shared.hosts(), 1);
+    host_b.stop().await;
+    assert_eq!(shared.hosts(), 0);
+    host_c.stop().await;
+    Ok(())
+}
+
+// Host A runs an actor that host B calls over the lattice. While a partition separates the two
+// hosts, B's calls go unanswered, and once it heals they go through again
+pub(crate) async fn partitioned_hosts() -> Result<()> {
+    const NS: &str = "partitionedhosts";
+
+    let nc = nats::asynk::connect("0.0.0.0:4222").await?;
+    let host_a = HostBuilder::new()
+        .with_rpc_client(nc)
+        .with_namespace(NS)
+        .build();
+    host_a.start().await?;
+    let nc2 = nats::asynk::connect("0.0.0.0:4222").await?;
+    let host_b = HostBuilder::new()
+        .with_rpc_client(nc2)
+        .with_namespace(NS)
+        .with_rpc_timeout(Duration::from_millis(500))
+        .build();
+    host_b.start().await?;
+
+    let echo = Actor::from_file("./tests/modules/echo.wasm")?;
+    let actor_id = echo.public_key();
+    host_a.start_actor(echo).await?;
+    await_actor_count(&host_a, 1, Duration::from_millis(50), 3).await?;
+    delay_for(Duration::from_secs(1)).await;
+
+    let req = crate::generated::http::Request {
+        header: HashMap::new(),
+        method: "GET".to_string(),
+        path: "/partitioned".to_string(),
+        query_string: "".to_string(),
+        body: vec![],
+    };
+    let buf = crate::generated::http::serialize(&req)?;
+    host_b.call_actor(&actor_id, "HandleRequest", &buf).await?;
+
+    let partition = Partition::split(vec![vec![host_a.id()], vec![host_b.id()]])?;
+    assert!(host_b
+        .call_actor(&actor_id, "HandleRequest", &buf)
+        .await
+        .is_err());
+
+    partition.heal();
+    let res = host_b.call_actor(&actor_id, "HandleRequest", &buf).await?;
+    let resp: crate::generated::http::Response = crate::generated::http::deserialize(&res)?;
+    assert_eq!(resp.status_code, 200);
+
+    host_a.stop().await;
+    host_b.stop().await;
+    Ok(())
+}